@@ -1,7 +1,11 @@
 use crate::common::test_key::TestKey;
 use crate::common::test_value::TestValue;
-use dharma::options::DharmaOpts;
+use dharmadb::dharma::Dharma;
+use dharmadb::options::DharmaOpts;
+use dharmadb::storage::sorted_string_table_writer::write_sstable;
+use dharmadb::storage::write_ahead_log::WriteAheadLog;
 use std::fs::{create_dir, remove_dir_all, remove_file};
+use std::path::PathBuf;
 
 pub mod test_key;
 pub mod test_value;
@@ -39,3 +43,48 @@ pub fn cleanup_paths(options: &DharmaOpts) {
     create_dir(&sstable_dir);
     remove_file(&wal_path);
 }
+
+/// Create a database holding `count` values in memory. The memtable is large
+/// enough that the values are only written to a table when flushed explicitly.
+///
+/// # Arguments
+///   - _options_ - The database config.
+///   - _count_ - The number of values to insert.
+pub fn create_memtable(options: &DharmaOpts, count: u32) -> Dharma<TestKey, TestValue> {
+    cleanup_paths(options);
+    let mut options = options.clone();
+    options.memtable_size_in_bytes = usize::MAX;
+    let mut db = Dharma::create(options).unwrap();
+    for (key, value) in get_test_data(count) {
+        db.put(key, value).unwrap();
+    }
+    db
+}
+
+/// Write `tables` SSTables that each hold the same `count` keys so that every
+/// table overlaps all others.
+///
+/// # Arguments
+///   - _options_ - The database config.
+///   - _tables_ - The number of tables to write.
+///   - _count_ - The number of values in each table.
+pub fn write_overlapping_tables(options: &DharmaOpts, tables: usize, count: u32) -> Vec<PathBuf> {
+    cleanup_paths(options);
+    let data = get_test_data(count);
+    (0..tables)
+        .map(|idx| write_sstable(options, &data, idx).unwrap().unwrap())
+        .collect()
+}
+
+/// Write a log holding `count` values.
+///
+/// # Arguments
+///   - _options_ - The database config.
+///   - _count_ - The number of values to append to the log.
+pub fn write_log(options: &DharmaOpts, count: u32) {
+    cleanup_paths(options);
+    let wal = WriteAheadLog::create(options.clone()).unwrap();
+    for (key, value) in get_test_data(count) {
+        wal.append(key, value).unwrap();
+    }
+}
@@ -5,8 +5,10 @@ use criterion::{black_box, criterion_group, criterion_main, Criterion};
 use crate::common::test_key::TestKey;
 use crate::common::test_value::TestValue;
 use crate::common::{cleanup_paths, get_test_data};
-use dharma::dharma::Dharma;
-use dharma::options::DharmaOpts;
+use dharmadb::dharma::Dharma;
+use dharmadb::options::DharmaOpts;
+use dharmadb::storage::backend::InMemoryBackend;
+use std::sync::Arc;
 
 fn dharma_db_benchmark(c: &mut Criterion) {
     let options = DharmaOpts::default();
@@ -33,5 +35,33 @@ fn dharma_db_benchmark(c: &mut Criterion) {
     });
 }
 
-criterion_group!(benches, dharma_db_benchmark);
+// Sequential keys are appended to the memtable and skip looking up the versions of
+// the key, random keys are compared against them to show the benefit.
+fn sequential_put_benchmark(c: &mut Criterion) {
+    let mut options = DharmaOpts::default();
+    options.backend = Arc::new(InMemoryBackend::new());
+    let value = TestValue::from("Test data string reprsentative of small to medium payloads.");
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options.clone()).unwrap();
+    let mut next_key = 0u32;
+    c.bench_function("benchmark sequential put operation", |b| {
+        b.iter(|| {
+            next_key = next_key.wrapping_add(1);
+            return db.put(TestKey::from(next_key), value.clone()).unwrap();
+        })
+    });
+    drop(db);
+    options.backend = Arc::new(InMemoryBackend::new());
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options).unwrap();
+    let mut seed = 0u32;
+    c.bench_function("benchmark random put operation", |b| {
+        b.iter(|| {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            return db
+                .put(TestKey::from(black_box(seed)), value.clone())
+                .unwrap();
+        })
+    });
+}
+
+criterion_group!(benches, dharma_db_benchmark, sequential_put_benchmark);
 criterion_main!(benches);
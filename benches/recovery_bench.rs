@@ -0,0 +1,32 @@
+mod common;
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+
+use crate::common::test_key::TestKey;
+use crate::common::test_value::TestValue;
+use crate::common::write_log;
+use dharmadb::options::DharmaOpts;
+use dharmadb::storage::write_ahead_log::WriteAheadLog;
+
+const LOG_ENTRIES: u32 = 20000;
+
+fn recovery_benchmark(c: &mut Criterion) {
+    for readahead_blocks in &[1, 16] {
+        let mut options = DharmaOpts::default();
+        options.wal_recovery_readahead_blocks = *readahead_blocks;
+        let name = format!(
+            "benchmark recovery with {} readahead blocks",
+            readahead_blocks
+        );
+        c.bench_function(&name, |b| {
+            b.iter_batched(
+                || write_log(&options, LOG_ENTRIES),
+                |_| WriteAheadLog::recover::<TestKey, TestValue>(options.clone()).unwrap(),
+                BatchSize::PerIteration,
+            )
+        });
+    }
+}
+
+criterion_group!(benches, recovery_benchmark);
+criterion_main!(benches);
@@ -0,0 +1,66 @@
+mod common;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::common::{cleanup_paths, get_test_data};
+use dharmadb::options::DharmaOpts;
+use dharmadb::storage::sorted_string_table_reader::SSTableReader;
+use dharmadb::storage::sorted_string_table_writer::write_sstable;
+
+// Counts allocations so that the allocations made per scanned block can be checked.
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::SeqCst);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+// Walk the table without decoding records so only block loads allocate.
+fn scan(options: &DharmaOpts, path: &std::path::PathBuf) -> usize {
+    let mut reader = SSTableReader::from(path, options.block_size_in_bytes).unwrap();
+    let records = reader.offsets().count();
+    assert!(reader.blocks_read() > 1);
+    records
+}
+
+fn sstable_scan_benchmark(c: &mut Criterion) {
+    let mut options = DharmaOpts::default();
+    options.block_size_in_bytes = 4096;
+    cleanup_paths(&options);
+    let path = write_sstable(&options, &get_test_data(20000), 0)
+        .unwrap()
+        .unwrap();
+
+    let before = ALLOCATIONS.load(Ordering::SeqCst);
+    scan(&options, &path);
+    let allocations = ALLOCATIONS.load(Ordering::SeqCst) - before;
+    let reader = SSTableReader::from(&path, options.block_size_in_bytes).unwrap();
+    let blocks = reader.size / options.block_size_in_bytes;
+    // block buffers are reused so scanning doesn't allocate for every block loaded
+    assert!(
+        allocations < blocks,
+        "{} allocations to scan {} blocks",
+        allocations,
+        blocks
+    );
+
+    c.bench_function("benchmark sstable scan", |b| {
+        b.iter(|| scan(&options, &path))
+    });
+}
+
+criterion_group!(benches, sstable_scan_benchmark);
+criterion_main!(benches);
@@ -0,0 +1,103 @@
+mod common;
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+
+use crate::common::test_key::TestKey;
+use crate::common::test_value::TestValue;
+use crate::common::{create_memtable, write_overlapping_tables};
+use dharmadb::options::DharmaOpts;
+use dharmadb::storage::block::Value;
+use dharmadb::storage::compaction::basic::{BasicCompaction, BasicCompactionOpts};
+use dharmadb::storage::sorted_string_table_reader::SSTableReader;
+
+const TABLE_ENTRIES: u32 = 20000;
+const MEMTABLE_ENTRIES: u32 = 20000;
+const COMPACTED_TABLES: usize = 4;
+
+// Read and decode every record in the table.
+fn scan_records(options: &DharmaOpts, path: &std::path::PathBuf) -> usize {
+    let mut reader = SSTableReader::from(path, options.block_size_in_bytes).unwrap();
+    let mut records = 0;
+    while reader.has_next() {
+        let _record: Value<TestKey, TestValue> = reader.read().to_record().unwrap();
+        records += 1;
+        reader.next();
+    }
+    records
+}
+
+fn full_scan_benchmark(c: &mut Criterion) {
+    let options = DharmaOpts::default();
+    let paths = write_overlapping_tables(&options, 1, TABLE_ENTRIES);
+    c.bench_function("benchmark full range scan", |b| {
+        b.iter(|| scan_records(&options, &paths[0]))
+    });
+}
+
+fn flush_benchmark(c: &mut Criterion) {
+    let options = DharmaOpts::default();
+    c.bench_function("benchmark flush of large memtable", |b| {
+        b.iter_batched(
+            || create_memtable(&options, MEMTABLE_ENTRIES),
+            |mut db| db.flush().unwrap(),
+            BatchSize::PerIteration,
+        )
+    });
+}
+
+// Compare against `flush_benchmark` to measure the effect of resizing tables to
+// their final size before writing them.
+fn preallocated_flush_benchmark(c: &mut Criterion) {
+    let mut options = DharmaOpts::default();
+    options.preallocate_tables = true;
+    c.bench_function(
+        "benchmark flush of large memtable to preallocated tables",
+        |b| {
+            b.iter_batched(
+                || create_memtable(&options, MEMTABLE_ENTRIES),
+                |mut db| db.flush().unwrap(),
+                BatchSize::PerIteration,
+            )
+        },
+    );
+}
+
+fn compaction_benchmark(c: &mut Criterion) {
+    let options = DharmaOpts::default();
+    let mut compaction_opts = BasicCompactionOpts::from(options.clone());
+    compaction_opts.threshold = COMPACTED_TABLES as u8;
+    let compaction = BasicCompaction::new(compaction_opts);
+    c.bench_function("benchmark compaction of overlapping tables", |b| {
+        b.iter_batched(
+            || write_overlapping_tables(&options, COMPACTED_TABLES, TABLE_ENTRIES),
+            |_| compaction.compact::<TestKey, TestValue>().unwrap(),
+            BatchSize::PerIteration,
+        )
+    });
+}
+
+// Merge the overlapping tables without writing the output so that the cost of
+// ordering records across tables isn't hidden by writing the compacted table.
+fn merge_benchmark(c: &mut Criterion) {
+    let options = DharmaOpts::default();
+    let paths = write_overlapping_tables(&options, COMPACTED_TABLES, TABLE_ENTRIES);
+    let compaction = BasicCompaction::new(BasicCompactionOpts::from(options.clone()));
+    c.bench_function("benchmark merge of overlapping tables", |b| {
+        b.iter(|| {
+            compaction
+                .merge_latest_iter::<TestKey, TestValue>(&paths)
+                .unwrap()
+                .count()
+        })
+    });
+}
+
+criterion_group!(
+    benches,
+    full_scan_benchmark,
+    flush_benchmark,
+    preallocated_flush_benchmark,
+    compaction_benchmark,
+    merge_benchmark
+);
+criterion_main!(benches);
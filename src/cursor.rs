@@ -0,0 +1,22 @@
+use serde::{Deserialize, Serialize};
+
+/// Position to resume paging through the database from, returned by `Dharma::range_page`.
+/// The cursor holds the last key of the page it was returned with so the next page
+/// starts after that key even if keys were written or deleted in between. Cursors
+/// can be serialized to hand them to clients between requests.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Cursor<K> {
+    last_key: K,
+}
+
+impl<K> Cursor<K> {
+    /// Create a cursor resuming after the supplied key.
+    pub fn new(last_key: K) -> Cursor<K> {
+        Cursor { last_key }
+    }
+
+    /// Get the key after which the next page starts.
+    pub fn last_key(&self) -> &K {
+        &self.last_key
+    }
+}
@@ -1,10 +1,27 @@
 use core::mem::size_of;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime};
 
 use subway::skiplist::SkipList;
 
+use crate::cursor::Cursor;
+use crate::lazy_value::LazyValue;
+use crate::memtable::Memtable;
 use crate::result::{Errors, Result};
-use crate::options::DharmaOpts;
-use crate::persistence::Persistence;
+use crate::options::{DharmaOpts, PutOptions};
+use crate::persistence::{Activity, Persistence};
+use crate::platform;
+use crate::storage::block::{RangeTombstone, Value};
+use crate::stats::{
+    FlushEstimate, GetStats, GetTrace, IntegrityReport, SnapshotDescriptor, Stats, TableInfo,
+};
+use crate::storage::block_cache::BlockCacheStats;
+use crate::storage::compaction::basic::DeduplicationReport;
+use crate::storage::compression::Compression;
+use crate::storage::metadata::Metadata;
+use crate::storage::value_cache::{ValueCache, ValueCacheStats};
+use crate::storage::write_ahead_log::WalOperation;
 use crate::traits::{ResourceKey, ResourceValue};
 
 /// Represents the database interface using which data can be persisted and retrieved.
@@ -15,17 +32,57 @@ use crate::traits::{ResourceKey, ResourceValue};
 ///  * _get_ - Used to retrieve a value associated with a key.
 ///  * _put_ - Associate the supplied key with a value.
 ///  * _delete_ - Delete the value associated with a key.
-pub struct Dharma<K: ResourceKey, V: ResourceValue> {
+///
+/// The latest values are held in memory by a `Memtable` until they are flushed,
+/// a `SkipList` is used unless a different memtable is specified.
+pub struct Dharma<K: ResourceKey, V: ResourceValue, M: Memtable<K, V> = SkipList<K, V>> {
     options: DharmaOpts,
 
-    memory: SkipList<K, V>,
+    memory: M,
+
+    // sequence number of the newest version of each in-memory key, whose value is
+    // held by the memtable, along with the older retained versions tagged with
+    // their sequence number ordered from newest to oldest
+    versions: SkipList<K, (u64, Vec<(u64, V)>)>,
+
+    // largest key written to memory since the memtable was reset, keys written
+    // after it are appended and can't have versions in memory yet
+    largest_key: Option<K>,
+
+    // range deletions that have not been flushed to disk
+    range_tombstones: Vec<RangeTombstone<K>>,
 
     persistence: Persistence<K>,
 
+    // application metadata stored alongside the data
+    metadata: Metadata,
+
+    // latest values of recently read persisted keys
+    value_cache: ValueCache<K, V>,
+
+    // every live key when `keep_key_set` is enabled, not set until it is built
+    key_set: Option<BTreeSet<K>>,
+
+    // deadlines of keys written with a TTL along with the sequence number of
+    // the write, see `PutOptions::ttl`
+    expirations: BTreeMap<K, (Instant, u64)>,
+
+    // earliest deadline in `expirations` when it was last scanned
+    next_expiration: Option<Instant>,
+
     size: usize,
+
+    // time the oldest value held in memory was written
+    oldest_write: Option<Instant>,
+
+    // sequence number of the latest write
+    sequence: u64,
+
+    // whether the database was closed and must not be flushed again when dropped
+    closed: bool,
 }
 
-impl<'a, K, V> Dharma<K, V>
+impl<K, V> Dharma<K, V>
 where
     K: ResourceKey,
     V: ResourceValue,
@@ -36,13 +93,99 @@ where
     /// # Arguments
     /// * _options_ - The configuration properties used to initialize the database.
     pub fn create(options: DharmaOpts) -> Result<Dharma<K, V>> {
-        let persistence_result = Persistence::create::<V>(options.clone());
-        return persistence_result.map(move |persistence| Dharma {
-            memory: SkipList::new(),
+        Dharma::create_with_memtable(options)
+    }
+
+    /// Create a new instance of the database and run the supplied callback once the
+    /// database is usable. This lets applications check their own invariants on
+    /// startup, such as the presence of metadata keys. An error returned by the
+    /// callback aborts the open and is returned instead of the database.
+    /// The callback is supplied here rather than in `DharmaOpts` since the options
+    /// are shared across databases with different key and value types.
+    ///
+    /// # Arguments
+    /// * _options_ - The configuration properties used to initialize the database.
+    /// * _on_open_ - Callback invoked with the opened database.
+    ///
+    /// # Returns
+    /// Result that resolves:
+    ///  - _Ok_ - The database if it was opened and the callback succeeded.
+    ///  - _Err_ - Error that occurred while opening the database or returned by the callback.
+    pub fn create_with<F>(options: DharmaOpts, on_open: F) -> Result<Dharma<K, V>>
+    where
+        F: FnOnce(&mut Dharma<K, V>) -> Result<()>,
+    {
+        let mut db = Dharma::create(options)?;
+        on_open(&mut db)?;
+        Ok(db)
+    }
+}
+
+impl<K, V, M> Dharma<K, V, M>
+where
+    K: ResourceKey,
+    V: ResourceValue,
+    M: Memtable<K, V>,
+{
+    /// Create a new instance of the database that holds in-memory values in the
+    /// supplied type of memtable. See `create`.
+    ///
+    /// # Arguments
+    /// * _options_ - The configuration properties used to initialize the database.
+    pub fn create_with_memtable(options: DharmaOpts) -> Result<Dharma<K, V, M>> {
+        // a log set aside by an interrupted recovery still has to be replayed
+        let opened = if Persistence::<K>::is_recovering(&options) {
+            Err(Errors::DB_PATH_DIRTY)
+        } else {
+            Self::open(options.clone())
+        };
+        match opened {
+            Err(Errors::DB_PATH_DIRTY) if options.auto_recover => {
+                options.log_warn(&format!(
+                    "Write Ahead Log found at {}, recovering it",
+                    options.path
+                ));
+                Self::recover_into(options)
+            }
+            result => result,
+        }
+    }
+
+    // the log is set aside while its operations are replayed into a database with
+    // a new log and only deleted once the replayed operations are durable
+    fn recover_into(options: DharmaOpts) -> Result<Dharma<K, V, M>> {
+        let operations = Persistence::<K>::begin_recovery::<K, V>(options.clone())?;
+        let mut db = Self::open(options.clone())?;
+        db.replay_operations(operations)?;
+        db.persistence.sync_log()?;
+        Persistence::<K>::finish_recovery(options)?;
+        Ok(db)
+    }
+
+    fn open(options: DharmaOpts) -> Result<Dharma<K, V, M>> {
+        let persistence = Persistence::create::<V>(options)?;
+        // options may have been adjusted to match the persisted data
+        let options = persistence.options().clone();
+        let metadata = Metadata::read(&options)?;
+        let mut db = Dharma {
+            memory: M::new(),
+            versions: SkipList::new(),
+            largest_key: None,
+            range_tombstones: Vec::new(),
+            value_cache: ValueCache::new(options.value_cache_entries),
+            key_set: None,
+            expirations: BTreeMap::new(),
+            next_expiration: None,
             size: 0,
+            oldest_write: None,
+            sequence: persistence.last_sequence(),
             persistence,
+            metadata,
             options,
-        });
+            closed: false,
+        };
+        db.load_key_set()?;
+        Ok(db)
     }
 
     /// Get the value associated with the supplied key.
@@ -55,17 +198,446 @@ where
     ///  - _Ok_ - Optional that may contain value if found.
     ///  - _Err_ - Error specifying why read couldn't be completed.
     pub fn get(&mut self, key: &K) -> Result<Option<V>> {
-        let maybe_in_memory = self.memory.get(key);
-        if maybe_in_memory.is_some() {
-            let retrieved_value = maybe_in_memory.unwrap();
-            // check value is not delete marker
-            let marker: V = V::nil();
-            if marker == retrieved_value {
-                return Ok(None);
+        // range deletions are resolved by sequence number so read the latest version
+        self.get_version(key, u64::MAX)
+    }
+
+    /// Check whether a live value is associated with the supplied key. When
+    /// `keep_key_set` is enabled the answer comes from the in-memory key set without
+    /// reading any table, otherwise the key is read like `get`.
+    ///
+    /// # Arguments
+    /// * _key_ - The key to check.
+    ///
+    /// # Returns
+    /// Result that resolves:
+    ///  - _Ok_ - Whether the key has a live value.
+    ///  - _Err_ - Error specifying why the key couldn't be checked.
+    pub fn contains_key(&mut self, key: &K) -> Result<bool> {
+        if !self.options.keep_key_set {
+            return Ok(self.get(key)?.is_some());
+        }
+        if self.key_set.is_none() {
+            self.load_key_set()?;
+        }
+        let is_live = self
+            .key_set
+            .as_ref()
+            .map_or(false, |keys| keys.contains(key));
+        Ok(is_live && !self.is_expired(key, u64::MAX))
+    }
+
+    /// Get the value associated with the supplied key treating a missing key as an error.
+    /// Unlike `get` which resolves to `Ok(None)` for keys that don't exist.
+    ///
+    /// # Arguments
+    /// * _key_ - The key whose value is to fetched.
+    ///
+    /// # Returns
+    /// Result that resolves:
+    ///  - _Ok_ - The value associated with the key.
+    ///  - _Err_ - `DB_NO_SUCH_KEY` if the key doesn't exist or error specifying why read
+    ///    couldn't be completed.
+    pub fn get_strict(&mut self, key: &K) -> Result<V> {
+        self.get(key)?.ok_or(Errors::DB_NO_SUCH_KEY)
+    }
+
+    /// Get the value associated with the supplied key as of the supplied sequence number.
+    /// Every write is assigned the next sequence number, see `last_sequence`.
+    /// Only the number of versions configured by `versions_to_keep` are retained
+    /// for each key so older versions may no longer be available.
+    ///
+    /// # Arguments
+    /// * _key_ - The key whose value is to fetched.
+    /// * _at_seq_ - The sequence number as of which to read the value.
+    ///
+    /// # Returns
+    /// Result that resolves:
+    ///  - _Ok_ - Optional that may contain the value written at or before the sequence number.
+    ///  - _Err_ - Error specifying why read couldn't be completed.
+    pub fn get_version(&mut self, key: &K, at_seq: u64) -> Result<Option<V>> {
+        let maybe_versions = self.get_versions(key);
+        if let Some(versions) = maybe_versions {
+            let maybe_version = versions.into_iter().find(|(seq, _)| *seq <= at_seq);
+            if let Some((seq, value)) = maybe_version {
+                if value == V::nil()
+                    || self.is_range_deleted(key, seq, at_seq)
+                    || self.is_expired(key, seq)
+                {
+                    return Ok(None);
+                }
+                return Ok(Some(value));
+            }
+        }
+        // only the latest value of a key is cached
+        let is_latest = at_seq == u64::MAX;
+        if is_latest && self.value_cache.is_enabled() {
+            if let Some(value) = self.value_cache.get(key) {
+                if self.is_expired(key, u64::MAX) {
+                    return Ok(None);
+                }
+                return Ok(Some(value));
+            }
+        }
+        let maybe_record = self.persistence.get_record::<V>(key, at_seq)?;
+        if let Some(record) = maybe_record {
+            if !record.is_tombstone()
+                && !self.is_range_deleted(key, record.seq, at_seq)
+                && !self.is_expired(key, record.seq)
+            {
+                if is_latest {
+                    self.value_cache.insert(key.clone(), record.value.clone());
+                }
+                return Ok(Some(record.value));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Get the value associated with the supplied key like `get` along with a trace
+    /// of where the key was looked for. Use it to diagnose which tables a read
+    /// probes and which it skips.
+    ///
+    /// # Arguments
+    /// * _key_ - The key whose value is to fetched.
+    ///
+    /// # Returns
+    /// Result that resolves:
+    ///  - _Ok_ - Trace of the read holding the value if found.
+    ///  - _Err_ - Error specifying why read couldn't be completed.
+    pub fn get_debug(&mut self, key: &K) -> Result<GetTrace<V>> {
+        let mut trace = GetTrace {
+            memtable_hit: false,
+            value_cache_hit: false,
+            tables_probed: Vec::new(),
+            tables_skipped: Vec::new(),
+            value: None,
+        };
+        let maybe_latest = self
+            .get_versions(key)
+            .and_then(|versions| versions.into_iter().next());
+        if let Some((seq, value)) = maybe_latest {
+            trace.memtable_hit = true;
+            if value != V::nil()
+                && !self.is_range_deleted(key, seq, u64::MAX)
+                && !self.is_expired(key, seq)
+            {
+                trace.value = Some(value);
+            }
+            return Ok(trace);
+        }
+        if self.value_cache.is_enabled() {
+            if let Some(value) = self.value_cache.get(key) {
+                trace.value_cache_hit = true;
+                if !self.is_expired(key, u64::MAX) {
+                    trace.value = Some(value);
+                }
+                return Ok(trace);
+            }
+        }
+        let maybe_record =
+            self.persistence
+                .get_record_probing::<V>(key, u64::MAX, &mut trace.tables_probed)?;
+        // the table of the nearest sampled key is read even if its range excludes the key
+        trace.tables_skipped = self
+            .persistence
+            .tables_excluding(key)
+            .into_iter()
+            .filter(|path| !trace.tables_probed.contains(path))
+            .collect();
+        if let Some(record) = maybe_record {
+            if !record.is_tombstone()
+                && !self.is_range_deleted(key, record.seq, u64::MAX)
+                && !self.is_expired(key, record.seq)
+            {
+                self.value_cache.insert(key.clone(), record.value.clone());
+                trace.value = Some(record.value);
+            }
+        }
+        Ok(trace)
+    }
+
+    /// Get the value associated with the supplied key like `get` along with the number
+    /// of tables and blocks read to find it. Reads probing many tables are a sign
+    /// that compaction is lagging, see `get_debug` for the tables that were probed.
+    ///
+    /// # Arguments
+    /// * _key_ - The key whose value is to fetched.
+    ///
+    /// # Returns
+    /// Result that resolves:
+    ///  - _Ok_ - Optional that may contain the value along with the read amplification.
+    ///  - _Err_ - Error specifying why read couldn't be completed.
+    pub fn get_with_stats(&mut self, key: &K) -> Result<(Option<V>, GetStats)> {
+        let blocks_read = self.persistence.blocks_read();
+        let trace = self.get_debug(key)?;
+        let stats = GetStats {
+            tables_probed: trace.tables_probed.len(),
+            blocks_read: self.persistence.blocks_read() - blocks_read,
+        };
+        Ok((trace.value, stats))
+    }
+
+    /// Store a small amount of application metadata, like a schema version, under the
+    /// supplied name. Metadata is persisted immediately in a file of its own so it
+    /// doesn't appear in reads or scans of the keys.
+    ///
+    /// # Arguments
+    /// * _key_ - The name of the metadata.
+    /// * _value_ - The metadata to store replacing any existing value.
+    ///
+    /// # Returns
+    /// Result that resolves:
+    ///  - _Ok_ - () if the metadata was persisted.
+    ///  - _Err_ - `METADATA_WRITE_FAILED` if the metadata couldn't be written.
+    pub fn set_metadata(&mut self, key: &str, value: &[u8]) -> Result<()> {
+        if self.persistence.is_sealed() {
+            return Err(Errors::DB_SEALED);
+        }
+        let mut metadata = self.metadata.clone();
+        metadata.set(key, value);
+        metadata.write(&self.options)?;
+        self.metadata = metadata;
+        Ok(())
+    }
+
+    /// Get the application metadata stored under the supplied name. See `set_metadata`.
+    pub fn get_metadata(&self, key: &str) -> Option<Vec<u8>> {
+        self.metadata.get(key)
+    }
+
+    /// Get the sequence number assigned to the latest write.
+    pub fn last_sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    /// Count the live keys exactly by scanning the values in memory merged with every
+    /// SSTable. Each key is counted once and deleted keys aren't counted. This reads
+    /// every key so it takes O(n) time, use `estimate_count_in_range` for a cheap
+    /// approximation.
+    ///
+    /// # Returns
+    /// Result that resolves:
+    ///  - _Ok_ - The number of live keys.
+    ///  - _Err_ - Error that occurred while scanning the keys.
+    pub fn count(&mut self) -> Result<usize> {
+        let mut count = 0;
+        for entry in self.iter()? {
+            entry?;
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Estimate the number of keys within the range `[start, end)` without scanning
+    /// the data. The estimate is derived from the sparse index sampling points for
+    /// persisted keys and is therefore approximate. Keys that are still in memory are
+    /// counted exactly and may overlap with keys that are already persisted.
+    ///
+    /// # Arguments
+    /// * _start_ - The inclusive lower bound of the range.
+    /// * _end_ - The exclusive upper bound of the range.
+    ///
+    /// # Returns
+    /// Result that resolves:
+    ///  - _Ok_ - The approximate number of keys in the range.
+    ///  - _Err_ - Error specifying why the estimate couldn't be computed.
+    pub fn estimate_count_in_range(&mut self, start: &K, end: &K) -> Result<usize> {
+        let persisted_estimate = self.persistence.estimate_count_in_range(start, end)?;
+        let marker: V = V::nil();
+        let in_memory_count = self
+            .memory
+            .range(start, end)
+            .iter()
+            .filter(|(_, value)| *value != marker)
+            .count();
+        Ok(persisted_estimate + in_memory_count)
+    }
+
+    /// Build an approximate histogram of the key distribution without scanning the data.
+    /// Persisted keys are estimated from the sparse index sampling points while keys
+    /// that are still in memory are counted exactly. Buckets are chosen so that they
+    /// hold roughly the same number of keys, so densely populated parts of the key
+    /// space are covered by more buckets with boundaries closer together.
+    /// Keys present in several tables or removed by range deletions may be counted.
+    ///
+    /// # Arguments
+    /// * _buckets_ - The maximum number of buckets in the histogram.
+    ///
+    /// # Returns
+    /// Result that resolves:
+    ///  - _Ok_ - The inclusive lower bound of each bucket in order along with the
+    ///    approximate number of keys from that bound up to the next bucket.
+    ///  - _Err_ - Error specifying why the histogram couldn't be built.
+    pub fn key_histogram(&mut self, buckets: usize) -> Result<Vec<(K, usize)>> {
+        let mut samples = self.persistence.key_samples();
+        let marker: V = V::nil();
+        for (key, value) in self.memory.collect() {
+            if value != marker {
+                samples.push((key, 1));
+            }
+        }
+        samples.sort_by(|a, b| a.0.cmp(&b.0));
+        let total: usize = samples.iter().map(|(_, count)| count).sum();
+        let mut histogram: Vec<(K, usize)> = Vec::new();
+        if buckets == 0 || total == 0 {
+            return Ok(histogram);
+        }
+        let mut counted = 0;
+        for (key, count) in samples {
+            // a new bucket starts once the previous buckets hold their share of keys
+            let bucket = std::cmp::min(counted * buckets / total, buckets - 1);
+            if histogram.len() <= bucket {
+                histogram.push((key, 0));
+            }
+            if let Some((_, bucket_count)) = histogram.last_mut() {
+                *bucket_count += count;
             }
-            return Ok(Some(retrieved_value));
+            counted += count;
         }
-        self.persistence.get::<V>(key)
+        Ok(histogram)
+    }
+
+    /// Get the smallest key that is associated with a value either in memory or on disk.
+    ///
+    /// # Returns
+    /// Result that resolves:
+    ///  - _Ok_ - Optional that contains the smallest live key unless the database is empty.
+    ///  - _Err_ - Error specifying why the key couldn't be found.
+    pub fn first_key(&mut self) -> Result<Option<K>> {
+        let in_memory = self.memory.collect();
+        let candidate = in_memory
+            .first()
+            .map(|(key, _)| key.clone())
+            .into_iter()
+            .chain(self.persistence.key_range().map(|(first, _)| first))
+            .min();
+        self.find_live_key(candidate, false)
+    }
+
+    /// Get the largest key that is associated with a value either in memory or on disk.
+    ///
+    /// # Returns
+    /// Result that resolves:
+    ///  - _Ok_ - Optional that contains the largest live key unless the database is empty.
+    ///  - _Err_ - Error specifying why the key couldn't be found.
+    pub fn last_key(&mut self) -> Result<Option<K>> {
+        let in_memory = self.memory.collect();
+        let candidate = in_memory
+            .last()
+            .map(|(key, _)| key.clone())
+            .into_iter()
+            .chain(self.persistence.key_range().map(|(_, last)| last))
+            .max();
+        self.find_live_key(candidate, true)
+    }
+
+    /// Get the keys in the range `[start, end)` along with their latest values either
+    /// in memory or on disk. Deleted keys are skipped. The whole range is materialized
+    /// so memory use grows with the number of keys in it, prefer `scan_persisted`
+    /// when reading large parts of the database.
+    ///
+    /// # Arguments
+    /// * _start_ - The inclusive lower bound of the range.
+    /// * _end_ - The exclusive upper bound of the range.
+    ///
+    /// # Returns
+    /// Result that resolves:
+    ///  - _Ok_ - The live keys in the range along with their values sorted by key.
+    ///  - _Err_ - Error specifying why the range couldn't be read.
+    pub fn range_collect(&mut self, start: &K, end: &K) -> Result<Vec<(K, V)>> {
+        let mut output = Vec::new();
+        for entry in self.merge_range(Some(start), Some(end))? {
+            let (key, _, value) = entry?;
+            output.push((key, value.get()?));
+        }
+        Ok(output)
+    }
+
+    /// Get the keys in the range `[start, end)` along with their latest values without
+    /// deserializing the values read from disk. Values are only deserialized when
+    /// `LazyValue::get` is called so scans that filter by key skip the cost for
+    /// values they never read. Deleted keys are skipped. The value cache is bypassed
+    /// and the whole range is materialized like with `range_collect`.
+    ///
+    /// # Arguments
+    /// * _start_ - The inclusive lower bound of the range.
+    /// * _end_ - The exclusive upper bound of the range.
+    ///
+    /// # Returns
+    /// Result that resolves:
+    ///  - _Ok_ - The live keys in the range along with their lazy values sorted by key.
+    ///  - _Err_ - Error specifying why the range couldn't be read.
+    pub fn range_lazy(&mut self, start: &K, end: &K) -> Result<Vec<(K, LazyValue<V>)>> {
+        self.merge_range(Some(start), Some(end))?
+            .map(|entry| entry.map(|(key, _, value)| (key, value)))
+            .collect()
+    }
+
+    /// Iterate over every key that is associated with a value either in memory or on
+    /// disk along with its latest value. Deleted keys are skipped. Keys are read from
+    /// the SSTables as the iterator advances so the database isn't materialized.
+    ///
+    /// # Returns
+    /// Result that resolves:
+    ///  - _Ok_ - Iterator over the live keys and values sorted by key.
+    ///    The iterator ends after yielding the first error.
+    ///  - _Err_ - Error that occurred while opening the SSTables.
+    pub fn iter(&mut self) -> Result<impl Iterator<Item = Result<(K, V)>> + '_> {
+        // the merged value is the newest version of the key so it isn't read again
+        let mut failed = false;
+        Ok(self.merge_range(None, None)?.map_while(move |entry| {
+            if failed {
+                return None;
+            }
+            let result = entry.and_then(|(key, _, value)| Ok((key, value.get()?)));
+            failed = result.is_err();
+            Some(result)
+        }))
+    }
+
+    /// Get a page of up to `limit` live keys along with their latest values sorted by
+    /// key and a cursor to get the next page with. Pages resume after the last key of
+    /// the previous page so keys written or deleted between pages never cause keys
+    /// to be returned twice.
+    ///
+    /// # Arguments
+    /// * _cursor_ - The cursor returned with the previous page or `None` to get the first page.
+    /// * _limit_ - The maximum number of keys in the page.
+    ///
+    /// # Returns
+    /// Result that resolves:
+    ///  - _Ok_ - The keys and values of the page along with the cursor to resume from
+    ///    which is not set once all keys were returned.
+    ///  - _Err_ - Error specifying why the page couldn't be read.
+    pub fn range_page(
+        &mut self,
+        cursor: Option<Cursor<K>>,
+        limit: usize,
+    ) -> Result<(Vec<(K, V)>, Option<Cursor<K>>)> {
+        if limit == 0 {
+            return Ok((Vec::new(), cursor));
+        }
+        let mut page = Vec::with_capacity(limit);
+        let mut has_more = false;
+        // the tables are read from the last key of the previous page on
+        let last_key = cursor.map(|cursor| cursor.last_key().clone());
+        for entry in self.merge_range(last_key.as_ref(), None)? {
+            let (key, _, value) = entry?;
+            if Some(&key) == last_key.as_ref() {
+                continue;
+            }
+            if page.len() == limit {
+                has_more = true;
+                break;
+            }
+            page.push((key, value.get()?));
+        }
+        let next_cursor = match page.last() {
+            Some((last_key, _)) if has_more => Some(Cursor::new(last_key.clone())),
+            _ => None,
+        };
+        Ok((page, next_cursor))
     }
 
     /// Associate the supplied value with the key.
@@ -79,21 +651,203 @@ where
     ///  - _Ok_ - () when operation succeeded.
     ///  - _Err_ - Error specifying why operation failed.
     pub fn put(&mut self, key: K, value: V) -> Result<()> {
+        self.put_with_options(key, value, PutOptions::default())
+    }
+
+    /// Associate the supplied value with the key controlling how the write is made.
+    /// See `PutOptions`.
+    ///
+    /// # Arguments
+    /// * _key_ - The key used to associate the value with.
+    /// * _value_ - Value to be associated with the key.
+    /// * _opts_ - Options of the write.
+    ///
+    /// # Returns
+    /// Result that resolves:
+    ///  - _Ok_ - () when operation succeeded.
+    ///  - _Err_ - Error specifying why operation failed.
+    pub fn put_with_options(&mut self, key: K, value: V, opts: PutOptions) -> Result<()> {
+        self.expire_keys()?;
+        let seq = self.sequence + 1;
+        // reject writes up front that would fail to flush
+        self.check_can_flush(size_of::<K>() + size_of::<V>())?;
+        let deleted = if value == V::nil() {
+            Some(key.clone())
+        } else {
+            None
+        };
+        let expiration = opts
+            .ttl
+            .and_then(|ttl| Some((key.clone(), platform::instant()? + ttl)));
+        self.insert_with_options(key, value, seq, opts.skip_wal)?;
+        // only deletions that were applied are audited
+        if let Some(key) = deleted {
+            self.persistence.audit_deletions(&[key])?;
+        }
+        if opts.sync && !opts.skip_wal {
+            self.persistence.sync_log()?;
+        }
+        if let Some((key, deadline)) = expiration {
+            self.expirations.insert(key, (deadline, seq));
+            self.next_expiration = Some(
+                self.next_expiration
+                    .map_or(deadline, |next| std::cmp::min(next, deadline)),
+            );
+        }
+        // threshold exceeded so try flushing memtable to disk
+        if self.should_flush() {
+            return self.flush();
+        }
+        Ok(())
+    }
+
+    fn put_versioned(&mut self, key: K, value: V, seq: u64) -> Result<()> {
+        // reject writes up front that would fail to flush
+        self.check_can_flush(size_of::<K>() + size_of::<V>())?;
+        self.insert_versioned(key, value, seq)?;
+        // threshold exceeded so try flushing memtable to disk
+        if self.should_flush() {
+            return self.flush();
+        }
+        Ok(())
+    }
+
+    /// Associate the supplied value with the key without ever flushing the in-memory
+    /// values to disk. Writes that would fill the memtable up to the configured
+    /// threshold are rejected so the caller decides when `flush` is run and flush
+    /// errors are never returned by writes.
+    ///
+    /// # Arguments
+    /// * _key_ - The key to be associated with the value.
+    /// * _value_ - The value to be stored.
+    ///
+    /// # Returns
+    /// Result that resolves:
+    ///  - _Ok_ - () when operation succeeded.
+    ///  - _Err_ - `DB_MEMTABLE_FULL` if the memtable has to be flushed before the
+    ///    value can be written or error specifying why operation failed.
+    pub fn put_no_flush(&mut self, key: K, value: V) -> Result<()> {
+        let write_size = size_of::<K>() + size_of::<V>();
+        if self.size + write_size >= self.options.memtable_size_in_bytes {
+            return Err(Errors::DB_MEMTABLE_FULL);
+        }
+        self.expire_keys()?;
+        let deleted = if value == V::nil() {
+            Some(key.clone())
+        } else {
+            None
+        };
+        let seq = self.sequence + 1;
+        self.insert_versioned(key, value, seq)?;
+        match deleted {
+            Some(key) => self.persistence.audit_deletions(&[key]),
+            None => Ok(()),
+        }
+    }
+
+    /// Log the version of the key to the Write Ahead Log and add it to the memtable.
+    fn insert_versioned(&mut self, key: K, value: V, seq: u64) -> Result<()> {
+        self.insert_with_options(key, value, seq, false)
+    }
+
+    /// Add the version of the key to the memtable logging it to the Write Ahead Log
+    /// unless `skip_wal` is set. Any TTL of the key is dropped.
+    fn insert_with_options(&mut self, key: K, value: V, seq: u64, skip_wal: bool) -> Result<()> {
         // try inserting into WAL else fail the operation
         // might need to acquire lock over memory before mutating memory
-        let wal_insert_result = self.persistence.insert(key.clone(), value.clone());
+        let wal_insert_result = if skip_wal {
+            self.persistence.check_writable()
+        } else {
+            self.persistence
+                .insert_versioned(key.clone(), value.clone(), seq)
+        };
+        // writes to a sealed database are rejected before they reach the log
+        if let Err(Errors::DB_SEALED) = wal_insert_result {
+            return Err(Errors::DB_SEALED);
+        }
         if wal_insert_result.is_ok() {
-            self.memory.insert(key.clone(), value.clone());
-            self.size += size_of::<K>() + size_of::<V>();
-            // threshold exceeded so try flushing memtable to disk
-            if self.size >= self.options.memtable_size_in_bytes {
-                return self.flush();
+            self.sequence = std::cmp::max(self.sequence, seq);
+            self.value_cache.invalidate(&key);
+            self.expirations.remove(&key);
+            if let Some(keys) = &mut self.key_set {
+                if value == V::nil() {
+                    keys.remove(&key);
+                } else {
+                    keys.insert(key.clone());
+                }
             }
+            self.add_version(key.clone(), value.clone(), seq);
+            self.size += size_of::<K>() + size_of::<V>();
+            self.oldest_write = self.oldest_write.or_else(platform::instant);
             return Ok(());
         }
         Err(Errors::WAL_WRITE_FAILED)
     }
 
+    /// Delete the values associated with all keys in the range `[start, end)`.
+    /// The deletion is recorded as a single range tombstone instead of a delete
+    /// marker per key. Values written after the range deletion are not affected.
+    ///
+    /// # Arguments
+    ///  - _start_ - The inclusive lower bound of the range.
+    ///  - _end_ - The exclusive upper bound of the range.
+    ///
+    /// # Returns
+    /// Result that resolves:
+    ///  - _Ok_ - () if operation succeeded.
+    ///  - _Err_ - Error that occured deleting the range.
+    pub fn delete_range(&mut self, start: &K, end: &K) -> Result<()> {
+        if start >= end {
+            return Ok(());
+        }
+        self.expire_keys()?;
+        // the keys are collected before the range deletion hides them
+        let keys: Vec<K> = if self.options.retain_deletion_audit {
+            self.range_collect(start, end)?
+                .into_iter()
+                .map(|(key, _)| key)
+                .collect()
+        } else {
+            Vec::new()
+        };
+        let tombstone = RangeTombstone::new(start.clone(), end.clone(), self.sequence + 1);
+        self.apply_range_tombstone(tombstone)?;
+        self.persistence.audit_deletions(&keys)?;
+        if self.should_flush() {
+            return self.flush();
+        }
+        Ok(())
+    }
+
+    fn delete_range_versioned(&mut self, tombstone: RangeTombstone<K>) -> Result<()> {
+        self.apply_range_tombstone(tombstone)?;
+        if self.should_flush() {
+            return self.flush();
+        }
+        Ok(())
+    }
+
+    /// Log the range tombstone to the Write Ahead Log and add it to memory.
+    fn apply_range_tombstone(&mut self, tombstone: RangeTombstone<K>) -> Result<()> {
+        self.check_can_flush(size_of::<RangeTombstone<K>>())?;
+        match self.persistence.insert_range_tombstone(&tombstone) {
+            Ok(()) => {}
+            Err(Errors::DB_SEALED) => return Err(Errors::DB_SEALED),
+            Err(_) => return Err(Errors::WAL_WRITE_FAILED),
+        }
+        self.sequence = std::cmp::max(self.sequence, tombstone.seq);
+        self.value_cache
+            .invalidate_where(|key| tombstone.start <= *key && *key < tombstone.end);
+        if let Some(keys) = &mut self.key_set {
+            let mut deleted = keys.split_off(&tombstone.start);
+            keys.append(&mut deleted.split_off(&tombstone.end));
+        }
+        self.range_tombstones.push(tombstone);
+        self.size += size_of::<RangeTombstone<K>>();
+        self.oldest_write = self.oldest_write.or_else(platform::instant);
+        Ok(())
+    }
+
     /// Delete the value associated with the key.
     /// Delete operation uses the `nil` method that is implemented
     /// by the `ResourceValue`.
@@ -122,12 +876,120 @@ where
     ///  - _Ok_ - The initialized database instance on successful recovery.
     ///  - _Err_ - The error that occured while resolving database.
     pub fn recover<T: ResourceKey, U: ResourceValue>(options: DharmaOpts) -> Result<Dharma<T, U>> {
-        let data = Persistence::<T>::recover(options.clone())?;
-        let mut db = Dharma::create(options.clone())?;
-        for (key, value) in data {
-            db.put(key, value);
+        Dharma::<T, U>::recover_into(options)
+    }
+
+    /// Repair the database at the configured path when its manifest is lost or
+    /// corrupt. The SSTables are scanned to check that they are readable and a fresh
+    /// manifest is written after which the database can be opened with `create`.
+    ///
+    /// # Arguments
+    ///  - _options_ -  The database config
+    ///
+    /// # Returns
+    /// Result that resolves
+    ///  - _Ok_ - The database was repaired.
+    ///  - _Err_ - The error that occured while repairing the database.
+    pub fn repair(options: DharmaOpts) -> Result<()> {
+        Persistence::<K>::repair::<V>(options)
+    }
+
+    /// Replay the records of the current Write Ahead Log into the in-memory store
+    /// without flushing them or deleting the log. Records already in memory are
+    /// deduplicated and the newest version of each key wins.
+    ///
+    /// # Returns
+    /// Result that specifies:
+    ///  - _Ok_ - Records were replayed into memory.
+    ///  - _Err_ - Failed to read the Write Ahead Log.
+    pub fn absorb_wal(&mut self) -> Result<()> {
+        let (records, tombstones) = self.persistence.read_wal_entries::<V>()?;
+        // replayed writes and range deletions may supersede cached values
+        self.value_cache.clear();
+        self.key_set = None;
+        for record in records {
+            self.absorb_version(record.key, record.value, record.seq);
+        }
+        for tombstone in tombstones {
+            if !self.range_tombstones.contains(&tombstone) {
+                self.sequence = std::cmp::max(self.sequence, tombstone.seq);
+                self.range_tombstones.push(tombstone);
+                self.size += size_of::<RangeTombstone<K>>();
+                self.oldest_write = self.oldest_write.or_else(platform::instant);
+            }
+        }
+        Ok(())
+    }
+
+    /// Check that the Write Ahead Log mirrors the memtable. The log is read into a
+    /// temporary structure keeping the newest version of each key which must hold
+    /// exactly the keys and values currently in the memtable. A mismatch indicates
+    /// a bug in how writes are logged or applied to memory.
+    ///
+    /// # Returns
+    /// Result that specifies:
+    ///  - _Ok_ - Whether the Write Ahead Log and the memtable hold the same keys.
+    ///  - _Err_ - Failed to read the Write Ahead Log.
+    pub fn validate_wal_matches_memtable(&mut self) -> Result<bool> {
+        let (records, _) = self.persistence.read_wal_entries::<V>()?;
+        let mut logged: SkipList<K, (u64, V)> = SkipList::new();
+        for record in records {
+            match logged.get(&record.key) {
+                Some((seq, _)) if seq > record.seq => continue,
+                Some(_) => logged.delete(&record.key),
+                None => {}
+            }
+            logged.insert(record.key, (record.seq, record.value));
+        }
+        let logged = logged
+            .collect()
+            .into_iter()
+            .map(|(key, (_, value))| (key, value))
+            .collect::<Vec<(K, V)>>();
+        let matches = logged == self.memory.collect();
+        if !matches {
+            self.options
+                .log_warn("Write Ahead Log does not match the memtable");
+        }
+        Ok(matches)
+    }
+
+    /// Copy every live key and value of the database at `other_path` into this database.
+    /// The keys of the other database are streamed so it is never materialized.
+    /// A key present in both databases keeps the version with the larger sequence
+    /// number. The incoming value wins when either version has no sequence number,
+    /// like values of tables written before sequence numbers were recorded.
+    /// Each database numbers its own writes so the sequence numbers only order
+    /// versions that share a history, e.g. when one database started as a copy of
+    /// the other. For unrelated databases the rule merely prefers the key that was
+    /// written later relative to its own database, resolve such conflicts before
+    /// merging when that isn't wanted.
+    /// The other database is opened with this database's options and must not be open.
+    ///
+    /// # Arguments
+    /// * _other_path_ - The path at which the other database is persisted.
+    ///
+    /// # Returns
+    /// Result that specifies:
+    ///  - _Ok_ - All live values of the other database were merged.
+    ///  - _Err_ - Failed to read the other database or to write the merged values.
+    pub fn merge_from(&mut self, other_path: &Path) -> Result<()> {
+        let mut options = self.options.clone();
+        options.path = other_path.to_string_lossy().into_owned();
+        let mut other: Dharma<K, V> = Dharma::create(options)?;
+        for entry in other.merge_range(None, None)? {
+            let (key, incoming_seq, value) = entry?;
+            let incoming_wins = match self.latest_sequence(&key)? {
+                Some(existing_seq) if incoming_seq > 0 && existing_seq > 0 => {
+                    incoming_seq > existing_seq
+                }
+                _ => true,
+            };
+            if incoming_wins {
+                self.put(key, value.get()?)?;
+            }
         }
-        return Ok(db);
+        Ok(())
     }
 
     /// Flush the in-memory values to disk. This method is automatically called
@@ -138,12 +1000,458 @@ where
     ///  - _Ok_ - Values were flushed to disk successfully.
     ///  - _Err_ - Failed to flush values to disk.
     pub fn flush(&mut self) -> Result<()> {
-        let flush_memory_result = self.persistence.flush(&self.memory.collect());
+        self.expire_keys()?;
+        let values = Self::to_values(self.collect_versions());
+        let last_compaction = self.persistence.last_compaction();
+        let flush_memory_result = self
+            .persistence
+            .flush_values_with_tombstones(&values, &self.range_tombstones);
+        // flushed keys are only held by the tables once the memory is reset
         if flush_memory_result.is_ok() {
             self.reset_memory();
-            return Ok(());
         }
-        return flush_memory_result;
+        self.invalidate_if_compacted(last_compaction);
+        flush_memory_result
+    }
+
+    /// Estimate the layout of the SSTable `flush` would write from the in-memory values
+    /// without writing anything. Useful to tune `block_size_in_bytes` before flushing.
+    ///
+    /// # Returns
+    /// Result that resolves:
+    ///  - _Ok_ - The number of blocks, their size, their padding and the number of
+    ///    records split across blocks.
+    ///  - _Err_ - `RECORD_SERIALIZATION_FAILED` if a value couldn't be serialized.
+    pub fn estimate_flush(&self) -> Result<FlushEstimate> {
+        let values = Self::to_values(self.collect_versions());
+        self.persistence
+            .estimate_flush(&values, &self.range_tombstones)
+    }
+
+    /// Flush the in-memory values and merge all SSTables into a single table holding
+    /// only the latest value of each live key, regardless of the compaction threshold.
+    /// Deletes and range deletions are applied and dropped and the Write Ahead Log is
+    /// empty afterwards which leaves a clean snapshot to copy or back up.
+    ///
+    /// # Returns
+    /// Result that specifies:
+    ///  - _Ok_ - The database was consolidated into a single table.
+    ///  - _Err_ - Failed to flush the values or merge the tables.
+    pub fn consolidate(&mut self) -> Result<()> {
+        self.flush()?;
+        self.persistence.consolidate::<V>()?;
+        self.value_cache.clear();
+        Ok(())
+    }
+
+    /// Flush the in-memory values to disk if the oldest of them was written at least
+    /// `flush_interval` ago. Writes already check this, applications whose writes
+    /// pause can call this periodically so that values don't stay in memory.
+    ///
+    /// # Returns
+    /// Result that specifies:
+    ///  - _Ok_ - Whether the in-memory values were flushed.
+    ///  - _Err_ - Failed to flush values to disk.
+    pub fn flush_if_due(&mut self) -> Result<bool> {
+        if !self.is_flush_due() {
+            return Ok(false);
+        }
+        self.flush()?;
+        Ok(true)
+    }
+
+    /// Flush the in-memory values, remove the Write Ahead Log and turn the database
+    /// into a handle that can only read the now durable tables. Unlike opening a
+    /// second handle on the same path this can't race with writes of this handle.
+    ///
+    /// # Returns
+    /// Result that resolves:
+    ///  - _Ok_ - The read-only handle.
+    ///  - _Err_ - Failed to flush values to disk or remove the Write Ahead Log.
+    pub fn into_read_only(mut self) -> Result<ReadOnlyDharma<K, V, M>> {
+        self.flush()?;
+        self.persistence.remove_log()?;
+        Ok(ReadOnlyDharma { db: self })
+    }
+
+    /// Flush the in-memory values, remove the Write Ahead Log and mark the database
+    /// as sealed in its manifest. Any later `create` of the database returns a
+    /// handle that can read the tables but whose writes fail with `DB_SEALED`,
+    /// which guards a finished database, e.g. after `consolidate`, against
+    /// accidental writes.
+    ///
+    /// # Returns
+    /// Result that resolves:
+    ///  - _Ok_ - The database was sealed.
+    ///  - _Err_ - Failed to flush values to disk, remove the Write Ahead Log or
+    ///    write the manifest.
+    pub fn seal(mut self) -> Result<()> {
+        self.flush()?;
+        self.persistence.remove_log()?;
+        let seal_result = self.persistence.seal();
+        self.closed = true;
+        seal_result
+    }
+
+    /// Check whether the database was sealed by `seal`, writes to a sealed
+    /// database fail with `DB_SEALED`.
+    pub fn is_sealed(&self) -> bool {
+        self.persistence.is_sealed()
+    }
+
+    /// Flush the in-memory values to disk and close the database. Unlike dropping
+    /// the database, errors that occur while flushing are reported. The database
+    /// is not flushed again when it is dropped even if this flush failed, values
+    /// that were not flushed are kept in the Write Ahead Log for `recover`.
+    ///
+    /// # Returns
+    /// Result that specifies:
+    ///  - _Ok_ - Values were flushed to disk successfully.
+    ///  - _Err_ - Failed to flush values to disk.
+    pub fn close(mut self) -> Result<()> {
+        let flush_result = self.flush();
+        if flush_result.is_err() {
+            self.persistence.retain_log();
+        }
+        self.closed = true;
+        flush_result
+    }
+
+    /// Flush only the in-memory values with keys in the range `[start, end)` to disk.
+    /// Values outside the range and range deletions are retained in memory and the
+    /// Write Ahead Log is replaced by one holding only them, so that they remain
+    /// recoverable at every point of the flush.
+    ///
+    /// # Arguments
+    /// * _start_ - The inclusive lower bound of the range.
+    /// * _end_ - The exclusive upper bound of the range.
+    ///
+    /// # Returns
+    /// Result that specifies:
+    ///  - _Ok_ - Values in range were flushed to disk successfully.
+    ///  - _Err_ - Failed to flush values to disk.
+    pub fn flush_range(&mut self, start: &K, end: &K) -> Result<()> {
+        let (in_range, outside_range): (Vec<_>, Vec<_>) = self
+            .collect_versions()
+            .into_iter()
+            .partition(|(key, _)| key >= start && key < end);
+        if in_range.is_empty() {
+            return Ok(());
+        }
+        // values kept in memory replace the log in the same step that drops the
+        // flushed values from it
+        let mut retained = Vec::new();
+        for (key, versions) in outside_range {
+            for (seq, value) in versions.into_iter().rev() {
+                retained.push(Value::with_seq(key.clone(), value, seq));
+            }
+        }
+        let keys: Vec<K> = in_range.iter().map(|(key, _)| key.clone()).collect();
+        let last_compaction = self.persistence.last_compaction();
+        let flush_result = self.persistence.flush_values_retaining(
+            &Self::to_values(in_range),
+            &Vec::new(),
+            &retained,
+            &self.range_tombstones,
+        );
+        // values stay in memory until they were flushed so a failed flush loses none
+        if flush_result.is_ok() {
+            self.remove_from_memory(&keys);
+        }
+        self.invalidate_if_compacted(last_compaction);
+        flush_result
+    }
+
+    /// Permanently discard all values with keys outside the range `[start, end]`.
+    /// Unlike `delete_range` the data is removed from disk immediately by flushing
+    /// the in-memory values and rewriting the SSTables with only the keys in range.
+    ///
+    /// # Arguments
+    /// * _start_ - The inclusive lower bound of the range.
+    /// * _end_ - The inclusive upper bound of the range.
+    ///
+    /// # Returns
+    /// Result that specifies:
+    ///  - _Ok_ - Values outside the range were discarded.
+    ///  - _Err_ - Failed to rewrite the values on disk.
+    pub fn retain_range(&mut self, start: &K, end: &K) -> Result<()> {
+        self.flush()?;
+        self.value_cache.clear();
+        self.key_set = None;
+        self.persistence.retain_range::<V>(start, end)
+    }
+
+    /// Delete the tables merged by compaction that have been kept in the trash for
+    /// at least `older_than`. See `DharmaOpts::compaction_retain_inputs`.
+    ///
+    /// # Arguments
+    /// * _older_than_ - The minimum time tables have to spend in the trash.
+    ///
+    /// # Returns
+    /// Result that resolves:
+    ///  - _Ok_ - The number of tables that were deleted.
+    ///  - _Err_ - Failed to delete tables from the trash.
+    pub fn purge_trash(&mut self, older_than: Duration) -> Result<usize> {
+        self.persistence.purge_trash(older_than)
+    }
+
+    /// Delete temporary tables left behind by compactions that were interrupted
+    /// before their output replaced the merged tables. This also happens every
+    /// time the database is opened.
+    ///
+    /// # Returns
+    /// Result that resolves:
+    ///  - _Ok_ - The number of files that were deleted.
+    ///  - _Err_ - Failed to delete the temporary files.
+    pub fn gc_temp_files(&mut self) -> Result<usize> {
+        self.persistence.gc_temp_files()
+    }
+
+    /// Load the blocks holding persisted keys within the range `[start, end)` into
+    /// the block cache so that subsequent reads over the range are served from memory.
+    ///
+    /// # Arguments
+    /// * _start_ - The inclusive lower bound of the range.
+    /// * _end_ - The exclusive upper bound of the range.
+    ///
+    /// # Returns
+    /// Result that resolves:
+    ///  - _Ok_ - The number of blocks loaded into the cache.
+    ///  - _Err_ - Error specifying why blocks couldn't be loaded.
+    pub fn warm_range(&mut self, start: &K, end: &K) -> Result<usize> {
+        self.persistence.warm_range(start, end)
+    }
+
+    /// Check whether in-memory values are currently being flushed to disk.
+    pub fn is_flushing(&self) -> bool {
+        self.persistence.activity().is_flushing()
+    }
+
+    /// Check whether SSTables are currently being compacted.
+    pub fn is_compacting(&self) -> bool {
+        self.persistence.activity().is_compacting()
+    }
+
+    /// Stop compacting SSTables after flushes, during bulk loads for instance.
+    /// Flushed tables accumulate until `resume_compaction` is called, flushes fail
+    /// with `DB_TOO_MANY_TABLES` once `max_sstables` is reached.
+    pub fn pause_compaction(&mut self) {
+        self.persistence.pause_compaction();
+    }
+
+    /// Resume compacting SSTables after flushes and compact the tables that
+    /// accumulated while compaction was paused.
+    ///
+    /// # Returns
+    /// Result that resolves:
+    ///  - _Ok_ - If compaction was resumed.
+    ///  - _Err_ - Failed to compact the accumulated tables.
+    pub fn resume_compaction(&mut self) -> Result<()> {
+        let last_compaction = self.persistence.last_compaction();
+        let resume_result = self.persistence.resume_compaction::<V>();
+        self.invalidate_if_compacted(last_compaction);
+        resume_result
+    }
+
+    /// Check whether compaction was paused by `pause_compaction`.
+    pub fn is_compaction_paused(&self) -> bool {
+        self.persistence.is_compaction_paused()
+    }
+
+    /// Get the space taken by identical values found by the last compaction, see
+    /// `DeduplicationReport`. Not set until tables are compacted with
+    /// `DharmaOpts::compaction_report_duplicates` set.
+    pub fn last_deduplication_report(&self) -> Option<DeduplicationReport> {
+        self.persistence.last_deduplication_report()
+    }
+
+    /// Get the deleted keys when `DharmaOpts::retain_deletion_audit` is set. Each key
+    /// is listed with the time it was deleted and remains listed after compaction
+    /// drops its delete marker. Keys removed by `delete_range` are listed if they
+    /// were live when the range was deleted, values are never retained.
+    ///
+    /// # Returns
+    /// Result that resolves:
+    ///  - _Ok_ - The deleted keys in the order they were audited, empty if no
+    ///    deletion was audited yet.
+    ///  - _Err_ - `DELETION_AUDIT_READ_FAILED` if the audit table couldn't be read.
+    pub fn deletion_log(&mut self) -> Result<Vec<(K, SystemTime)>> {
+        self.persistence.deletion_log()
+    }
+
+    /// Get the flags describing the operations the database is performing.
+    /// Unlike the database, the returned flags can be shared with other threads
+    /// to observe flushes and compactions while they run.
+    pub fn activity(&self) -> Activity {
+        self.persistence.activity()
+    }
+
+    /// Rebuild the sparse index from the SSTables sampling one out of every `new_rate`
+    /// keys and use the rate for tables flushed from now on. A lower rate speeds up
+    /// lookups at the cost of memory. See `DharmaOpts::sparse_index_sampling_rate`.
+    ///
+    /// # Arguments
+    /// * _new_rate_ - The sampling rate of the rebuilt index.
+    ///
+    /// # Returns
+    /// Result that resolves:
+    ///  - _Ok_ - () if the index was rebuilt.
+    ///  - _Err_ - `DB_INVALID_SAMPLING_RATE` if the rate is 0 or error specifying
+    ///    why the tables couldn't be read.
+    pub fn resample_index(&mut self, new_rate: u32) -> Result<()> {
+        self.persistence.resample_index::<V>(new_rate)?;
+        self.options.sparse_index_sampling_rate = new_rate;
+        Ok(())
+    }
+
+    /// Flush the in-memory values and rewrite all SSTables with blocks of `new_size`
+    /// bytes. Tables are rewritten one at a time and stay readable throughout. The
+    /// database is opened with blocks of `new_size` bytes from now on regardless
+    /// of the configured `block_size_in_bytes`, see `effective_options`.
+    ///
+    /// # Arguments
+    /// * _new_size_ - The block size in bytes of the rewritten tables.
+    ///
+    /// # Returns
+    /// Result that resolves:
+    ///  - _Ok_ - () if all tables were rewritten.
+    ///  - _Err_ - `DB_INVALID_BLOCK_SIZE` if the block size can't hold a record or
+    ///    error specifying why the tables couldn't be rewritten.
+    pub fn rewrite_with_block_size(&mut self, new_size: usize) -> Result<()> {
+        self.flush()?;
+        self.persistence.rewrite_with_block_size::<V>(new_size)?;
+        self.options.block_size_in_bytes = new_size;
+        Ok(())
+    }
+
+    /// Get the hit and miss counters of the block cache.
+    pub fn block_cache_stats(&self) -> BlockCacheStats {
+        self.persistence.block_cache_stats()
+    }
+
+    /// Get the hit and miss counters of the value cache.
+    /// See `DharmaOpts::value_cache_entries`.
+    pub fn value_cache_stats(&self) -> ValueCacheStats {
+        self.value_cache.stats()
+    }
+
+    /// Collect a report on the state of the database covering the memtable,
+    /// files on disk, recent flushes and compactions and the block cache.
+    ///
+    /// # Returns
+    /// Result that contains the statistics or an error if the size of
+    /// files on disk could not be read.
+    pub fn stats(&self) -> Result<Stats> {
+        let table_sizes = self.persistence.table_sizes()?;
+        let disk_size_in_bytes =
+            table_sizes.iter().sum::<u64>() + self.persistence.value_log_size()?;
+        Ok(Stats {
+            memtable_entries: self.memory.len(),
+            memtable_size_in_bytes: self.size,
+            sstables: table_sizes.len(),
+            disk_size_in_bytes,
+            wal_size_in_bytes: self.persistence.wal_size()?,
+            last_flush: self.persistence.last_flush(),
+            last_compaction: self.persistence.last_compaction(),
+            index_entries: self.persistence.index_entries(),
+            block_cache_hit_ratio: self.block_cache_stats().hit_ratio(),
+        })
+    }
+
+    /// Flush the in-memory values and describe the SSTables holding every write up to
+    /// the latest sequence number. The descriptor is serializable so that agents copying
+    /// the tables to another machine can check that the copy is complete and consistent.
+    /// Tables may be replaced by later flushes and compactions so they have to be copied
+    /// before the database is written to again.
+    ///
+    /// # Returns
+    /// Result that resolves:
+    ///  - _Ok_ - The descriptor listing the path, size and checksum of each table.
+    ///  - _Err_ - Error that occurred while flushing the values or reading the tables.
+    pub fn snapshot_descriptor(&mut self) -> Result<SnapshotDescriptor> {
+        self.flush()?;
+        self.persistence.snapshot_descriptor()
+    }
+
+    /// Scan the keys and values that were flushed to disk ignoring the values that are
+    /// still held in memory. Only the latest persisted value of each key is returned
+    /// and keys deleted on disk are skipped. Range deletions that were not flushed
+    /// are ignored as well.
+    ///
+    /// # Returns
+    /// Result that resolves:
+    ///  - _Ok_ - Iterator over the persisted keys and values sorted by key.
+    ///    The iterator ends after yielding the first error.
+    ///  - _Err_ - Error that occurred while opening the SSTables.
+    pub fn scan_persisted(&mut self) -> Result<impl Iterator<Item = Result<(K, V)>>> {
+        self.persistence.scan_persisted::<V>()
+    }
+
+    /// Read every SSTable to check that it can be decoded and report the keys that
+    /// are stored in more than one table along with the sequence number of each copy.
+    /// Duplicates are expected until compaction merges the tables holding them.
+    ///
+    /// # Returns
+    /// Result that resolves:
+    ///  - _Ok_ - The report describing the tables.
+    ///  - _Err_ - Error that occurred while reading a table.
+    pub fn verify_integrity(&self) -> Result<IntegrityReport<K>> {
+        self.persistence.verify_integrity::<V>()
+    }
+
+    /// Estimate how much the SSTables would shrink if their blocks were compressed
+    /// with the codec, without changing anything on disk. Values held in memory
+    /// are not sampled.
+    ///
+    /// # Arguments
+    /// * _codec_ - The codec to compress the sampled blocks with.
+    /// * _sample_blocks_ - The maximum number of blocks to compress.
+    ///
+    /// # Returns
+    /// Result that resolves:
+    ///  - _Ok_ - The size of the compressed blocks relative to their size, a ratio
+    ///    close to 1 means compression isn't worth the CPU.
+    ///  - _Err_ - Error that occurred while reading a table.
+    pub fn estimate_compression(
+        &mut self,
+        codec: Compression,
+        sample_blocks: usize,
+    ) -> Result<f64> {
+        self.persistence.estimate_compression(codec, sample_blocks)
+    }
+
+    /// Describe each SSTable in order along with the range of keys it covers, the
+    /// number of records in it and its size. Tables with overlapping ranges are
+    /// merged by the next compaction.
+    ///
+    /// # Returns
+    /// Result that contains the description of each table or an error if a
+    /// table could not be read.
+    pub fn table_layout(&self) -> Result<Vec<TableInfo<K>>> {
+        self.persistence.table_layout()
+    }
+
+    /// Count the deletions persisted to SSTables that compaction has not reclaimed yet.
+    /// Both delete markers and range deletions are counted, a high count signals space
+    /// that compaction would reclaim. A delete marker is dropped once compaction merges
+    /// it with the older values of its key. Deletions that were not flushed yet are not
+    /// counted.
+    ///
+    /// # Returns
+    /// Result that resolves:
+    ///  - _Ok_ - The number of deletions stored in the SSTables.
+    ///  - _Err_ - Error that occurred while reading a table.
+    pub fn pending_tombstone_count(&mut self) -> Result<usize> {
+        self.persistence.tombstone_count::<V>()
+    }
+
+    /// Get the options in use by the database. These may differ from the options
+    /// the database was opened with since the block size is always taken from the
+    /// persisted tables and some options change at runtime, like the sampling rate
+    /// set by `resample_index`. The index may further coarsen its sampling rate,
+    /// see `DharmaOpts::max_index_entries`.
+    pub fn effective_options(&self) -> &DharmaOpts {
+        &self.options
     }
 
     /// Gets the size in bytes of data stored in-memory currently.
@@ -154,21 +1462,498 @@ where
         self.size
     }
 
+    /// Remove the in-memory values of the keys and the size they take up.
+    fn remove_from_memory(&mut self, keys: &[K]) {
+        for key in keys {
+            self.memory.delete(key);
+            self.versions.delete(key);
+        }
+        self.size -= keys.len() * (size_of::<K>() + size_of::<V>());
+    }
+
+    /// Get the sequence number of the newest version of the key in memory or on disk,
+    /// including delete markers.
+    fn latest_sequence(&mut self, key: &K) -> Result<Option<u64>> {
+        if let Some((seq, _)) = self.versions.get(key) {
+            return Ok(Some(seq));
+        }
+        let record = self.persistence.get_record::<V>(key, u64::MAX)?;
+        Ok(record.map(|record| record.seq))
+    }
+
+    // The boundary key recorded in memory or in the index is returned when it is
+    // still live. Otherwise keys are merged from the smallest key on until a live
+    // key is found, or in reverse from the first key of each table backwards.
+    fn find_live_key(&mut self, candidate: Option<K>, reverse: bool) -> Result<Option<K>> {
+        let candidate = match candidate {
+            Some(key) => key,
+            None => return Ok(None),
+        };
+        if self.get(&candidate)?.is_some() {
+            return Ok(Some(candidate));
+        }
+        if !reverse {
+            return match self.merge_range(None, None)?.next() {
+                Some(entry) => Ok(Some(entry?.0)),
+                None => Ok(None),
+            };
+        }
+        let mut starts: Vec<K> = self
+            .persistence
+            .table_ranges()
+            .into_iter()
+            .map(|(first, _)| first)
+            .collect();
+        starts.sort();
+        starts.dedup();
+        // each window ends where the previously merged one starts so every key is
+        // merged at most once, the last window holds the keys below every table
+        let mut end: Option<K> = None;
+        loop {
+            let start = starts.pop();
+            let mut last = None;
+            for entry in self.merge_range(start.as_ref(), end.as_ref())? {
+                last = Some(entry?.0);
+            }
+            if last.is_some() || start.is_none() {
+                return Ok(last);
+            }
+            end = start;
+        }
+    }
+
+    /// Check whether the in-memory values have to be flushed after a write.
+    fn should_flush(&self) -> bool {
+        self.size >= self.options.memtable_size_in_bytes || self.is_flush_due()
+    }
+
+    /// Check whether the oldest in-memory value was written at least `flush_interval` ago.
+    fn is_flush_due(&self) -> bool {
+        match (self.options.flush_interval, self.oldest_write) {
+            (Some(interval), Some(oldest_write)) => oldest_write.elapsed() >= interval,
+            _ => false,
+        }
+    }
+
+    /// Check whether a write of the supplied size can be accepted when it causes
+    /// the in-memory values to be flushed.
+    fn check_can_flush(&self, write_size: usize) -> Result<()> {
+        if self.size + write_size >= self.options.memtable_size_in_bytes || self.is_flush_due() {
+            return self.persistence.can_flush();
+        }
+        Ok(())
+    }
+
+    /// Merge the live keys in memory with the latest persisted version of each key in
+    /// the range `[start, end)` without materializing the range, see
+    /// `Persistence::scan_range`. Values in memory are newer than any value on disk.
+    ///
+    /// # Arguments
+    /// * _start_ - The inclusive lower bound of the range, unbounded when not set.
+    /// * _end_ - The exclusive upper bound of the range, unbounded when not set.
+    ///
+    /// # Returns
+    /// Result that resolves:
+    ///  - _Ok_ - Iterator over the live keys in the range along with the sequence
+    ///    number of their latest version and their lazy values sorted by key.
+    ///    The iterator ends after yielding the first error.
+    ///  - _Err_ - Error that occurred while opening the SSTables.
+    fn merge_range(
+        &mut self,
+        start: Option<&K>,
+        end: Option<&K>,
+    ) -> Result<impl Iterator<Item = Result<(K, u64, LazyValue<V>)>> + '_> {
+        let in_memory = match (start, end) {
+            (Some(start), Some(end)) => self.memory.range(start, end),
+            _ => self
+                .memory
+                .collect()
+                .into_iter()
+                .filter(|(key, _)| {
+                    start.map_or(true, |start| key >= start) && end.map_or(true, |end| key < end)
+                })
+                .collect(),
+        };
+        let mut in_memory = in_memory.into_iter().peekable();
+        let mut persisted = self.persistence.scan_range::<V>(start, end)?.peekable();
+        let mut failed = false;
+        Ok(std::iter::from_fn(move || loop {
+            if failed {
+                return None;
+            }
+            let persisted_key = match persisted.peek() {
+                Some(Ok((key, _, _))) => Some(key.clone()),
+                Some(Err(_)) => {
+                    failed = true;
+                    return persisted.next();
+                }
+                None => None,
+            };
+            let from_memory = match (in_memory.peek(), &persisted_key) {
+                (Some((memory_key, _)), Some(persisted_key)) => memory_key <= persisted_key,
+                (Some(_), None) => true,
+                (None, Some(_)) => false,
+                (None, None) => return None,
+            };
+            if from_memory {
+                let (key, value) = in_memory.next()?;
+                // the value in memory replaces the persisted value of the key
+                if persisted_key.as_ref() == Some(&key) {
+                    persisted.next();
+                }
+                let seq = self
+                    .versions
+                    .get(&key)
+                    .map_or(self.sequence, |(seq, _)| seq);
+                if value == V::nil()
+                    || self.is_range_deleted(&key, seq, u64::MAX)
+                    || self.is_expired(&key, seq)
+                {
+                    continue;
+                }
+                return Some(Ok((key, seq, LazyValue::decoded(value))));
+            }
+            let (key, seq, value) = match persisted.next()? {
+                Ok(entry) => entry,
+                Err(error) => return Some(Err(error)),
+            };
+            // range deletions that were not flushed yet still apply
+            if self.is_range_deleted(&key, seq, u64::MAX) || self.is_expired(&key, seq) {
+                continue;
+            }
+            return Some(Ok((key, seq, value)));
+        }))
+    }
+
+    /// Check whether the version of the key with the supplied sequence number is
+    /// deleted by a range deletion when reading as of `at_seq`.
+    fn is_range_deleted(&self, key: &K, seq: u64, at_seq: u64) -> bool {
+        let deleted_in_memory = self
+            .range_tombstones
+            .iter()
+            .any(|tombstone| tombstone.deletes(key, seq, at_seq));
+        deleted_in_memory || self.persistence.is_range_deleted(key, seq, at_seq)
+    }
+
+    /// Clear the value cache when SSTables were compacted since `last_compaction`.
+    fn invalidate_if_compacted(&mut self, last_compaction: Option<SystemTime>) {
+        if self.persistence.last_compaction() != last_compaction {
+            self.value_cache.clear();
+        }
+        self.remove_filtered_keys();
+    }
+
+    /// Remove the keys whose values the compaction filter dropped from the key set.
+    /// Compacted tables hold every live persisted key of the key ranges they merged,
+    /// so only keys in those ranges missing from the compacted tables are removed.
+    /// Keys with versions in memory are newer than any table and remain.
+    fn remove_filtered_keys(&mut self) {
+        let compactions = self.persistence.take_compacted_keys();
+        let mut keys = match self.key_set.take() {
+            Some(keys) => keys,
+            None => return,
+        };
+        for compaction in compactions {
+            for (first, last) in &compaction.merged_ranges {
+                let filtered: Vec<K> = keys
+                    .range(first..=last)
+                    .filter(|key| !compaction.live_keys.contains(*key))
+                    .cloned()
+                    .collect();
+                for key in filtered {
+                    if self.versions.get(&key).is_none() {
+                        keys.remove(&key);
+                    }
+                }
+            }
+        }
+        self.key_set = Some(keys);
+    }
+
+    /// Build the set of live keys by merging the keys of the memtable and the tables
+    /// when `keep_key_set` is enabled. Values are not deserialized.
+    fn load_key_set(&mut self) -> Result<()> {
+        if !self.options.keep_key_set {
+            return Ok(());
+        }
+        let mut keys = BTreeSet::new();
+        for entry in self.merge_range(None, None)? {
+            keys.insert(entry?.0);
+        }
+        self.key_set = Some(keys);
+        Ok(())
+    }
+
+    /// Record a version of the key retaining at most `versions_to_keep` versions.
+    /// Sequential writes, common for time series, append keys larger than every key
+    /// in memory. Such keys have no versions yet so the lookup of their versions is
+    /// skipped, saving a skiplist search for every appended write.
+    fn add_version(&mut self, key: K, value: V, seq: u64) {
+        let is_append = self.is_append(&key);
+        let mut versions = if is_append {
+            Vec::new()
+        } else {
+            self.get_versions(&key).unwrap_or_default()
+        };
+        let is_new_key = versions.is_empty();
+        if is_append {
+            self.largest_key = Some(key.clone());
+        }
+        versions.insert(0, (seq, value));
+        versions.truncate(std::cmp::max(self.options.versions_to_keep, 1));
+        self.set_versions(key, versions, !is_new_key);
+    }
+
+    /// Record a version of the key that is already logged to the Write Ahead Log.
+    /// The version is ordered among the retained versions by sequence number
+    /// and replaces a retained version with the same sequence number.
+    fn absorb_version(&mut self, key: K, value: V, seq: u64) {
+        if self.is_append(&key) {
+            self.largest_key = Some(key.clone());
+        }
+        let mut versions = self.get_versions(&key).unwrap_or_default();
+        let is_new_key = versions.is_empty();
+        match versions
+            .iter()
+            .position(|(version_seq, _)| *version_seq <= seq)
+        {
+            Some(idx) if versions[idx].0 == seq => versions[idx] = (seq, value),
+            Some(idx) => versions.insert(idx, (seq, value)),
+            None => versions.push((seq, value)),
+        }
+        versions.truncate(std::cmp::max(self.options.versions_to_keep, 1));
+        if is_new_key {
+            self.size += size_of::<K>() + size_of::<V>();
+            self.oldest_write = self.oldest_write.or_else(platform::instant);
+        }
+        self.set_versions(key, versions, !is_new_key);
+        self.sequence = std::cmp::max(self.sequence, seq);
+    }
+
+    /// Get the retained versions of the in-memory key ordered from newest to oldest.
+    /// The value of the newest version is read from the memtable.
+    fn get_versions(&mut self, key: &K) -> Option<Vec<(u64, V)>> {
+        let (seq, older) = self.versions.get(key)?;
+        let latest = self.memory.get(key)?;
+        let mut versions = Vec::with_capacity(older.len() + 1);
+        versions.push((seq, latest));
+        versions.extend(older);
+        Some(versions)
+    }
+
+    /// Get the retained versions of every in-memory key sorted by key,
+    /// see `get_versions`.
+    fn collect_versions(&self) -> Vec<(K, Vec<(u64, V)>)> {
+        self.versions
+            .collect()
+            .into_iter()
+            .zip(self.memory.collect())
+            .map(|((key, (seq, older)), (_, latest))| {
+                let mut versions = Vec::with_capacity(older.len() + 1);
+                versions.push((seq, latest));
+                versions.extend(older);
+                (key, versions)
+            })
+            .collect()
+    }
+
+    /// Store the retained versions of the in-memory key ordered from newest to
+    /// oldest. Only the value of the newest version is stored in the memtable so
+    /// values are held in memory once. Existing versions of the key are replaced.
+    fn set_versions(&mut self, key: K, mut versions: Vec<(u64, V)>, replace: bool) {
+        if replace {
+            self.memory.delete(&key);
+            self.versions.delete(&key);
+        }
+        let (seq, latest) = versions.remove(0);
+        self.memory.insert(key.clone(), latest);
+        self.versions.insert(key, (seq, versions));
+    }
+
+    /// Apply the operations recovered from the Write Ahead Log. Operations are
+    /// replayed in the order they were logged, deletes are replayed as delete
+    /// markers so keys deleted before the crash stay deleted.
+    fn replay_operations(&mut self, operations: Vec<WalOperation<K, V>>) -> Result<()> {
+        self.options.log_info(&format!(
+            "Recovered {} operations from the Write Ahead Log",
+            operations.len()
+        ));
+        for operation in operations {
+            match operation {
+                WalOperation::Put(record) => {
+                    self.put_versioned(record.key, record.value, record.seq)?
+                }
+                WalOperation::Delete { key, seq } => self.put_versioned(key, V::nil(), seq)?,
+                WalOperation::DeleteRange(tombstone) => self.delete_range_versioned(tombstone)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Check whether the key is larger than every key written to memory.
+    /// Keys removed from memory by `flush_range` may still bound the check.
+    fn is_append(&self, key: &K) -> bool {
+        self.largest_key
+            .as_ref()
+            .map_or(true, |largest| key > largest)
+    }
+
+    /// Convert the in-memory versions of keys sorted by key to values that can be
+    /// persisted, newer versions of a key are placed first.
+    fn to_values(versions: Vec<(K, Vec<(u64, V)>)>) -> Vec<Value<K, V>> {
+        let mut values = Vec::new();
+        for (key, key_versions) in versions {
+            for (seq, value) in key_versions {
+                values.push(Value::with_seq(key.clone(), value, seq));
+            }
+        }
+        values
+    }
+
+    /// Check whether the TTL of the key elapsed for the version of the key written
+    /// with the supplied sequence number. Reads use it to hide expired keys until
+    /// `expire_keys` writes their delete markers.
+    fn is_expired(&self, key: &K, seq: u64) -> bool {
+        match self.expirations.get(key) {
+            Some((deadline, expiring_seq)) => {
+                seq >= *expiring_seq && platform::instant().map_or(false, |now| *deadline <= now)
+            }
+            None => false,
+        }
+    }
+
+    /// Delete the keys whose TTL elapsed. Writes call this first so the delete
+    /// markers are only logged when the database accepts writes, reads never write
+    /// and hide expired keys with `is_expired` instead.
+    fn expire_keys(&mut self) -> Result<()> {
+        let now = match platform::instant() {
+            Some(now) => now,
+            None => return Ok(()),
+        };
+        match self.next_expiration {
+            Some(next) if next <= now => {}
+            _ => return Ok(()),
+        }
+        if self.persistence.check_writable().is_err() {
+            return Ok(());
+        }
+        let expired: Vec<K> = self
+            .expirations
+            .iter()
+            .filter(|(_, (deadline, _))| *deadline <= now)
+            .map(|(key, _)| key.clone())
+            .collect();
+        let mut deleted = Vec::with_capacity(expired.len());
+        let mut insert_result = Ok(());
+        for key in expired {
+            let seq = self.sequence + 1;
+            insert_result = self.insert_versioned(key.clone(), V::nil(), seq);
+            if insert_result.is_err() {
+                break;
+            }
+            deleted.push(key);
+        }
+        // keys deleted before a failed insert are audited as well
+        self.persistence.audit_deletions(&deleted)?;
+        insert_result?;
+        self.next_expiration = self
+            .expirations
+            .values()
+            .map(|(deadline, _)| *deadline)
+            .min();
+        Ok(())
+    }
+
     /// Create a new in-memory store to process further operations.
     /// This operation is required after the current in-memory data is flushed to disk.
     fn reset_memory(&mut self) {
-        self.memory = SkipList::new();
+        self.memory = M::new();
+        self.versions = SkipList::new();
+        self.largest_key = None;
+        self.range_tombstones = Vec::new();
         self.size = 0;
+        self.oldest_write = None;
+    }
+}
+
+/// Read-only handle over the tables of a database returned by `Dharma::into_read_only`.
+/// All values were flushed before the handle was created and it has no Write Ahead
+/// Log so it offers no way to write.
+pub struct ReadOnlyDharma<K: ResourceKey, V: ResourceValue, M: Memtable<K, V> = SkipList<K, V>> {
+    // memory of the database stays empty as nothing can be written through this handle
+    db: Dharma<K, V, M>,
+}
+
+impl<K, V, M> ReadOnlyDharma<K, V, M>
+where
+    K: ResourceKey,
+    V: ResourceValue,
+    M: Memtable<K, V>,
+{
+    /// Get the value associated with the supplied key. See `Dharma::get`.
+    pub fn get(&mut self, key: &K) -> Result<Option<V>> {
+        self.db.get(key)
+    }
+
+    /// Get the value associated with the supplied key treating a missing key as an
+    /// error. See `Dharma::get_strict`.
+    pub fn get_strict(&mut self, key: &K) -> Result<V> {
+        self.db.get_strict(key)
+    }
+
+    /// Get the value associated with the supplied key as of the supplied sequence
+    /// number. See `Dharma::get_version`.
+    pub fn get_version(&mut self, key: &K, at_seq: u64) -> Result<Option<V>> {
+        self.db.get_version(key, at_seq)
+    }
+
+    /// Get the live keys in the range `[start, end)` along with their values.
+    /// See `Dharma::range_collect`.
+    pub fn range_collect(&mut self, start: &K, end: &K) -> Result<Vec<(K, V)>> {
+        self.db.range_collect(start, end)
+    }
+
+    /// Get a page of live keys along with their values and the cursor to get the
+    /// next page with. See `Dharma::range_page`.
+    pub fn range_page(
+        &mut self,
+        cursor: Option<Cursor<K>>,
+        limit: usize,
+    ) -> Result<(Vec<(K, V)>, Option<Cursor<K>>)> {
+        self.db.range_page(cursor, limit)
+    }
+
+    /// Iterate over every live key along with its value. See `Dharma::iter`.
+    pub fn iter(&mut self) -> Result<impl Iterator<Item = Result<(K, V)>> + '_> {
+        self.db.iter()
+    }
+
+    /// Get the sequence number assigned to the latest write.
+    pub fn last_sequence(&self) -> u64 {
+        self.db.last_sequence()
+    }
+
+    /// Describe each SSTable in order. See `Dharma::table_layout`.
+    pub fn table_layout(&self) -> Result<Vec<TableInfo<K>>> {
+        self.db.table_layout()
     }
 }
 
 /// Cleanup database state before shutdown.
-impl<K, V> Drop for Dharma<K, V>
+impl<K, V, M> Drop for Dharma<K, V, M>
 where
     K: ResourceKey,
     V: ResourceValue,
+    M: Memtable<K, V>,
 {
     fn drop(&mut self) {
-        self.flush();
+        if self.closed {
+            return;
+        }
+        let flushed = self.options.flush_on_drop && self.flush().is_ok();
+        // keep values that were not flushed recoverable from the Write Ahead Log
+        if !flushed && (!self.versions.is_empty() || !self.range_tombstones.is_empty()) {
+            self.persistence.retain_log();
+        }
     }
 }
@@ -0,0 +1,70 @@
+use crate::result::{Errors, Result};
+use crate::storage::encoding::Encoding;
+use crate::traits::ResourceValue;
+
+/// Value yielded by `Dharma::range_lazy` that is only deserialized when it is read.
+/// Values read from SSTables hold their serialized bytes so callers filtering by key
+/// don't pay for deserializing values they never look at. Values held in memory and
+/// values whose bytes can't be split from the rest of their record, like values
+/// stored inline with the varint encoding, are already deserialized.
+#[derive(Clone, Debug)]
+pub struct LazyValue<V> {
+    state: LazyValueState<V>,
+}
+
+#[derive(Clone, Debug)]
+enum LazyValueState<V> {
+    Decoded(V),
+    Encoded { data: Vec<u8>, encoding: Encoding },
+}
+
+impl<V> LazyValue<V>
+where
+    V: ResourceValue,
+{
+    /// Create a lazy value holding an already deserialized value.
+    pub fn decoded(value: V) -> LazyValue<V> {
+        LazyValue {
+            state: LazyValueState::Decoded(value),
+        }
+    }
+
+    /// Create a lazy value from the bytes of a value serialized with the encoding.
+    pub fn encoded(data: Vec<u8>, encoding: Encoding) -> LazyValue<V> {
+        LazyValue {
+            state: LazyValueState::Encoded { data, encoding },
+        }
+    }
+
+    /// Check whether the value has been deserialized.
+    pub fn is_decoded(&self) -> bool {
+        matches!(self.state, LazyValueState::Decoded(_))
+    }
+
+    /// Get the value deserializing it from its bytes if needed.
+    /// The value is deserialized again on every call.
+    ///
+    /// # Returns
+    /// Result that resolves:
+    ///  - _Ok_ - The value.
+    ///  - _Err_ - `RECORD_DESERIALIZATION_FAILED` if the bytes couldn't be deserialized.
+    pub fn get(&self) -> Result<V> {
+        match &self.state {
+            LazyValueState::Decoded(value) => Ok(value.clone()),
+            LazyValueState::Encoded { data, encoding } => encoding
+                .deserialize(data.as_slice())
+                .map_err(|_| Errors::RECORD_DESERIALIZATION_FAILED),
+        }
+    }
+
+    /// Check whether the value is the delete marker without deserializing it.
+    /// Serialized values are compared with the serialized delete marker.
+    pub fn is_nil(&self) -> bool {
+        match &self.state {
+            LazyValueState::Decoded(value) => value == &V::nil(),
+            LazyValueState::Encoded { data, encoding } => encoding
+                .serialize(&V::nil())
+                .map_or(false, |nil| &nil == data),
+        }
+    }
+}
@@ -1,8 +1,14 @@
+pub mod cursor;
 pub mod dharma;
+pub mod lazy_value;
+pub mod logger;
+pub mod memtable;
 pub mod options;
 pub mod result;
+pub mod stats;
 pub mod traits;
 
 pub mod persistence;
+mod platform;
 mod sparse_index;
 pub mod storage;
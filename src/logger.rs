@@ -0,0 +1,14 @@
+/// Sink receiving the log messages of a single database, see `DharmaOpts::logger`.
+/// Implement this trait to scope logs to a database instance or to route them to a
+/// structured sink without installing a global logger.
+pub trait DbLogger: Send + Sync {
+    /// Log a lifecycle event, like a flush, a compaction or a recovery.
+    fn info(&self, message: &str);
+
+    /// Log a condition the database recovered from, like a corrupt manifest that
+    /// was repaired or a rejected write.
+    fn warn(&self, message: &str);
+
+    /// Log a failure, like a table that couldn't be written or read.
+    fn error(&self, message: &str);
+}
@@ -0,0 +1,82 @@
+use crate::traits::{ResourceKey, ResourceValue};
+use subway::skiplist::SkipList;
+
+/// Sorted in-memory store holding the latest values written to the database
+/// before they are flushed to disk. Implement this trait to back the database
+/// with a structure other than the default `SkipList`.
+pub trait Memtable<K, V> {
+    /// Create an empty memtable.
+    fn new() -> Self;
+
+    /// Associate the value with the key replacing any existing value.
+    fn insert(&mut self, key: K, value: V);
+
+    /// Get the value associated with the key.
+    fn get(&mut self, key: &K) -> Option<V>;
+
+    /// Remove the key and its value.
+    fn delete(&mut self, key: &K);
+
+    /// Get the keys in the range `[start, end)` along with their values in sorted order.
+    fn range(&self, start: &K, end: &K) -> Vec<(K, V)>;
+
+    /// Get all keys along with their values in sorted order.
+    fn collect(&self) -> Vec<(K, V)>;
+
+    /// Get the number of keys in the memtable.
+    fn len(&self) -> usize;
+
+    /// Check whether the memtable holds no keys.
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<K, V> Memtable<K, V> for SkipList<K, V>
+where
+    K: ResourceKey,
+    V: ResourceValue,
+{
+    fn new() -> Self {
+        SkipList::new()
+    }
+
+    // the skiplist inserts equal keys after each other instead of replacing them
+    fn insert(&mut self, key: K, value: V) {
+        if SkipList::get(self, &key).is_some() {
+            SkipList::delete(self, &key);
+        }
+        SkipList::insert(self, key, value);
+    }
+
+    fn get(&mut self, key: &K) -> Option<V> {
+        SkipList::get(self, key)
+    }
+
+    fn delete(&mut self, key: &K) {
+        SkipList::delete(self, key);
+    }
+
+    // The skiplist can only be traversed as a whole so the range is cut out of the
+    // sorted entries in place instead of filtering them into another vector.
+    fn range(&self, start: &K, end: &K) -> Vec<(K, V)> {
+        let mut entries = SkipList::collect(self);
+        let range_end = entries.partition_point(|(key, _)| key < end);
+        entries.truncate(range_end);
+        let range_start = entries.partition_point(|(key, _)| key < start);
+        entries.drain(..range_start);
+        entries
+    }
+
+    fn collect(&self) -> Vec<(K, V)> {
+        SkipList::collect(self)
+    }
+
+    fn len(&self) -> usize {
+        SkipList::len(self)
+    }
+
+    fn is_empty(&self) -> bool {
+        SkipList::is_empty(self)
+    }
+}
@@ -1,3 +1,18 @@
+#[cfg(feature = "std")]
+use crate::storage::backend::FsBackend;
+#[cfg(not(feature = "std"))]
+use crate::storage::backend::InMemoryBackend;
+use crate::logger::DbLogger;
+use crate::storage::backend::StorageBackend;
+use crate::storage::codec::RegisteredRecordCodec;
+use crate::storage::compaction::RegisteredCompactionFilter;
+use crate::storage::encoding::Encoding;
+use crate::storage::transform::ValueTransform;
+use crate::storage::write_ahead_log::WalFormat;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
 /// Configuration properties used to initialize a database instance.
 #[derive(Clone)]
 pub struct DharmaOpts {
@@ -16,6 +31,214 @@ pub struct DharmaOpts {
     /// Sparse Index Sampling frequency. On out of all n values
     /// is stored in this spares Index
     pub sparse_index_sampling_rate: u32,
+    /// Minimum serialized size of a value in bytes for the record to be
+    /// stored compressed. Compression is disabled when not set.
+    pub value_compression_min_bytes: Option<usize>,
+    /// Maximum number of blocks held in the block cache.
+    pub block_cache_size_in_blocks: usize,
+    /// Maximum number of decoded values held in the value cache. Repeated reads
+    /// of a cached key skip reading blocks and deserializing records.
+    /// The value cache is disabled when set to 0.
+    pub value_cache_entries: usize,
+    /// Flag specifying whether writes to the Write Ahead Log are synced to disk.
+    /// Concurrent writers that arrive while a sync is in flight are batched
+    /// together and synced once.
+    pub group_commit: bool,
+    /// Number of bytes compaction reads or writes before yielding to other threads.
+    /// This smooths out IO at the cost of longer compactions. Compaction
+    /// runs without yielding when not set.
+    pub compaction_io_budget_bytes_per_op: Option<usize>,
+    /// Number of versions retained for each key. Older versions can be read
+    /// by sequence number using `Dharma::get_version`. Only the latest
+    /// version is retained by default.
+    pub versions_to_keep: usize,
+    /// Number of blocks of the Write Ahead Log read at once during recovery.
+    pub wal_recovery_readahead_blocks: usize,
+    /// Flag specifying whether a flushed SSTable is read back and checked against
+    /// the flushed values. This catches bugs in the read and write path early
+    /// at the cost of slower flushes.
+    pub verify_after_flush: bool,
+    /// Minimum serialized size of a value in bytes for it to be stored in the
+    /// value log instead of the SSTable. Tables then only hold the key and a
+    /// pointer to the value which keeps them small and lets compaction rewrite
+    /// keys without copying values. Values are stored inline when not set.
+    pub value_separation_min_bytes: Option<usize>,
+    /// Flag specifying whether keys are stored as the suffix they don't share with
+    /// the previous key in the same block. This shrinks tables whose keys share
+    /// long prefixes at the cost of reconstructing keys while reading.
+    pub prefix_compress_keys: bool,
+    /// Number of writes to the Write Ahead Log after which the log is rewritten to
+    /// only hold the retained versions of each key. This reduces recovery work for
+    /// keys that are written repeatedly at the cost of periodically rewriting the log.
+    /// The log is never compacted when not set.
+    pub wal_compaction_interval_in_writes: Option<usize>,
+    /// Maximum number of SSTables. Flushes that would create more tables fail with
+    /// `DB_TOO_MANY_TABLES` so that reads don't silently slow down when compaction
+    /// can't keep up. The number of tables is not limited when not set.
+    pub max_sstables: Option<usize>,
+    /// Maximum size in bytes of each SSTable written by a flush. Larger flushes are
+    /// split on block boundaries into several tables holding disjoint key ranges.
+    /// Each flush writes a single table when not set.
+    pub max_flush_table_size_in_bytes: Option<usize>,
+    /// Number of key partitions the values of a flush are split into. Each partition
+    /// is written to its own SSTable holding a disjoint range of keys with about as
+    /// many values as the other partitions, so a read only scans the table of the
+    /// partition holding its key. Partitions are further split by
+    /// `max_flush_table_size_in_bytes` when set.
+    pub flush_partitions: usize,
+    /// Maximum number of tables of a flush written at once, each on its own thread.
+    /// Values are packed into blocks before any table is written so only writing the
+    /// tables to storage is done in parallel. Tables are written one at a time when
+    /// set to 1. See `flush_partitions`.
+    pub flush_parallelism: usize,
+    /// The bincode configuration used to serialize records. SSTables record the
+    /// encoding they were written with so that they are read back correctly after
+    /// the encoding is changed. The Write Ahead Log is always read with this encoding.
+    pub encoding: Encoding,
+    /// Flag specifying whether in-memory values are flushed to disk when the
+    /// database is dropped. Values that are not flushed remain in the Write Ahead
+    /// Log and can be restored with `Dharma::recover`.
+    /// Databases closed with `close` are never flushed again when dropped.
+    pub flush_on_drop: bool,
+    /// Flag specifying whether the directory holding the SSTables is synced after
+    /// tables are renamed by compaction. Flushed tables and their directory are
+    /// always synced before the Write Ahead Log is reset so that a crash never
+    /// loses flushed values.
+    pub sync_tables: bool,
+    /// Flag specifying whether the directory holding the Write Ahead Log is synced
+    /// after the log is deleted by a flush. Without it a crash right after a flush may
+    /// bring back the deleted log next to the flushed tables, applying its records
+    /// a second time when the database is recovered.
+    pub sync_wal_on_reset: bool,
+    /// Flag specifying whether the tables merged by compaction are moved to the
+    /// `tables/trash` directory instead of being deleted. This keeps the inputs of
+    /// recent compactions around for debugging and rollback until they are
+    /// reclaimed with `Dharma::purge_trash`.
+    pub compaction_retain_inputs: bool,
+    /// Flag specifying whether compaction only merges tables with overlapping key
+    /// ranges. Tables are grouped into clusters of overlapping key ranges and tables
+    /// disjoint from every other table are left untouched, saving the IO of rewriting
+    /// them. All tables are merged into one when not set.
+    pub compaction_merge_overlapping_only: bool,
+    /// Maximum number of compactions run at once. Clusters of tables with disjoint
+    /// key ranges are compacted in parallel, each on its own thread. Only applies
+    /// when `compaction_merge_overlapping_only` is set.
+    pub max_concurrent_compactions: usize,
+    /// Maximum number of records written to each table produced by a compaction.
+    /// A compaction whose output exceeds it is split into several tables with
+    /// disjoint key ranges, the versions of a key are never split across tables.
+    /// Each compaction writes a single table when not set.
+    pub compaction_max_records_per_table: Option<usize>,
+    /// Flag specifying whether compaction compares the serialized values it merges to
+    /// report superseded versions identical to their successor and runs of adjacent keys
+    /// sharing a value. See `Dharma::last_deduplication_report`.
+    pub compaction_report_duplicates: bool,
+    /// Flag specifying whether deleted keys are recorded in an audit table at `path`
+    /// that is kept when compaction drops their delete markers from the SSTables.
+    /// Only the key and the time it was deleted are kept, never the value. Each
+    /// deletion syncs the table and `delete_range` reads the keys in the range.
+    /// See `Dharma::deletion_log`.
+    pub retain_deletion_audit: bool,
+    /// Total size in bytes of the SSTables after which compaction is run. When set
+    /// this replaces the default trigger on the number of tables, which suits tables
+    /// of highly variable size. Compaction is triggered by the number of tables when not set.
+    pub compaction_threshold_bytes: Option<u64>,
+    /// Flag specifying whether records that can't be deserialized are logged and
+    /// skipped by reads and table scans instead of failing them with
+    /// `RECORD_DESERIALIZATION_FAILED`. Values in corrupt records are lost.
+    pub skip_corrupt_records: bool,
+    /// Filter consulted by compaction for every merged value. Values the filter
+    /// rejects are dropped from the compacted table. Compaction keeps all values
+    /// when not set. See `CompactionFilter`.
+    pub compaction_filter: Option<RegisteredCompactionFilter>,
+    /// Flag specifying whether SSTable files written from values collected up front are
+    /// resized to their final size before blocks are written to them. This lets the file
+    /// system allocate the table contiguously instead of growing it with every block.
+    /// Tables are written by appending blocks when the file can't be resized.
+    pub preallocate_tables: bool,
+    /// Name of the directory within `path` holding the SSTables.
+    pub table_directory: String,
+    /// Extension of SSTable files. Files with other extensions in the table
+    /// directory are not treated as tables.
+    pub table_extension: String,
+    /// Minimum serialized size of a value in bytes for its record to be stored
+    /// compressed in the Write Ahead Log. Compressing large compressible values
+    /// shrinks the log and the data synced with every write. Records in the log
+    /// are compressed like records in SSTables when not set.
+    pub wal_compression_min_bytes: Option<usize>,
+    /// How records are laid out in the Write Ahead Log, see `WalFormat`. The log is
+    /// always read with this format so it can only be changed once the log was
+    /// recovered or flushed.
+    pub wal_format: WalFormat,
+    /// Maximum time values are held in memory before writes flush them to disk even
+    /// if the memtable is below `memtable_size_in_bytes`. This bounds how long values
+    /// of low throughput workloads are only stored in the Write Ahead Log.
+    /// Applications that go idle can call `Dharma::flush_if_due` periodically.
+    /// Values are only flushed by size when not set.
+    pub flush_interval: Option<Duration>,
+    /// Maximum number of keys sampled by the sparse index. When indexing a table
+    /// would exceed it the sampling stride is doubled and every other sampled key
+    /// is dropped, bounding the memory of the index at the cost of scanning more
+    /// blocks per read. The index always holds at least one key.
+    /// The index grows with the tables when not set.
+    pub max_index_entries: Option<usize>,
+    /// Maximum number of threads probing SSTables in parallel when a read has to
+    /// check several tables whose range of keys covers the key. The value from the
+    /// newest table holding the key is returned and older tables that weren't
+    /// probed yet are skipped once it is found. Tables are probed one at a time
+    /// when set to 1.
+    pub get_parallelism: usize,
+    /// Flag specifying whether the directory of the database and its table directory
+    /// are created when opening a database that doesn't exist yet. When not set,
+    /// opening a path that doesn't exist fails with `DB_NOT_FOUND` so a mistyped
+    /// path doesn't silently create an empty database.
+    pub create_if_missing: bool,
+    /// Flag specifying whether SSTables are checked to be sorted by key while they are
+    /// indexed, when the database is opened and after flushes and compactions. Reads
+    /// stop scanning a table at the first larger key so keys of an unsorted table
+    /// would silently be missed. Indexing fails with `UNSORTED_TABLE` when set.
+    pub validate_tables_on_open: bool,
+    /// Flag specifying whether SSTables are read with direct IO, bypassing the page
+    /// cache of the operating system so that large scans don't evict the pages of
+    /// other files. Reads are aligned as direct IO requires. Direct IO is supported
+    /// by the `FsBackend` on Linux, tables are read as usual elsewhere.
+    pub direct_io: bool,
+    /// Flag specifying whether the set of live keys is kept in memory so that
+    /// `Dharma::contains_key` answers without reading any table. The set holds a copy
+    /// of every live key, costing roughly the in-memory size of each key plus the
+    /// overhead of a tree node per key. It is built by scanning all tables when the
+    /// database is opened and kept up to date by writes afterwards. Keys dropped by
+    /// the `compaction_filter` are looked up in the compacted tables only.
+    pub keep_key_set: bool,
+    /// Flag specifying whether opening a database with a Write Ahead Log left behind
+    /// by a crash replays the log like `Dharma::recover` does. Opening such a
+    /// database fails with `DB_PATH_DIRTY` when not set.
+    pub auto_recover: bool,
+    /// Flag specifying whether every block of the SSTables written starts with a
+    /// header recording the number of records in the block and the key of the first
+    /// value starting in it, see `BlockHeader`. Seeking to a key binary searches the
+    /// blocks by their first key instead of walking every record before it and
+    /// validating a table checks the record count. Headers cost the size of a record
+    /// header plus the first key per block. Tables written either way can be read.
+    pub block_headers: bool,
+    /// Sink receiving the messages logged by this database, like flushes, compactions,
+    /// recoveries and failures. Messages are logged with the `log` facade when not set.
+    pub logger: Option<Arc<dyn DbLogger>>,
+    /// Transform applied to the records holding values when they are written to
+    /// SSTables, the value log and the Write Ahead Log in the `Blocks` format, and
+    /// reversed when they are read, like encryption at rest. Keys of prefix compressed
+    /// tables can't be told apart from transformed values so keys aren't prefix
+    /// compressed while a transform is set. Data written with a transform can only be
+    /// read back with the same transform.
+    pub value_transform: Option<Arc<dyn ValueTransform>>,
+    /// Codec serializing the records of the SSTables written by flushes and
+    /// compactions, see `RecordCodec`. The codec is recorded in the footer of each
+    /// table and tables written with the `BincodeCodec` can still be read after it
+    /// is set. The Write Ahead Log is always serialized with `bincode`.
+    /// Records are serialized with the `BincodeCodec` when not set.
+    pub record_codec: Option<RegisteredRecordCodec>,
+    /// The storage backend used to read and write files.
+    pub backend: Arc<dyn StorageBackend>,
 }
 
 impl DharmaOpts {
@@ -28,6 +251,7 @@ impl DharmaOpts {
     /// | :------- | :------------ |
     /// | path     | /var/lib/dharma |
     /// | bootstrap | true         |
+    /// | backend  | FsBackend, InMemoryBackend without the `std` feature |
     ///
     pub fn default() -> DharmaOpts {
         DharmaOpts {
@@ -41,8 +265,132 @@ impl DharmaOpts {
             // overall 32MB per SSTable
             blocks_per_sstable: 32 * 32,
             sparse_index_sampling_rate: 100,
+            value_compression_min_bytes: None,
+            // 128 blocks (each block 32k in size) result in 4MB of cached data
+            block_cache_size_in_blocks: 128,
+            value_cache_entries: 0,
+            group_commit: false,
+            compaction_io_budget_bytes_per_op: None,
+            versions_to_keep: 1,
+            // 16 blocks (each block 32k in size) are read per call during recovery
+            wal_recovery_readahead_blocks: 16,
+            verify_after_flush: false,
+            value_separation_min_bytes: None,
+            prefix_compress_keys: false,
+            wal_compaction_interval_in_writes: None,
+            max_sstables: None,
+            max_flush_table_size_in_bytes: None,
+            flush_partitions: 1,
+            flush_parallelism: 1,
+            encoding: Encoding::Fixint,
+            flush_on_drop: true,
+            sync_tables: true,
+            sync_wal_on_reset: true,
+            compaction_retain_inputs: false,
+            compaction_merge_overlapping_only: false,
+            max_concurrent_compactions: 1,
+            compaction_max_records_per_table: None,
+            compaction_report_duplicates: false,
+            retain_deletion_audit: false,
+            compaction_threshold_bytes: None,
+            skip_corrupt_records: false,
+            compaction_filter: None,
+            preallocate_tables: false,
+            table_directory: String::from("tables"),
+            table_extension: String::from("db"),
+            wal_compression_min_bytes: None,
+            wal_format: WalFormat::Blocks,
+            flush_interval: None,
+            max_index_entries: None,
+            get_parallelism: 1,
+            create_if_missing: true,
+            validate_tables_on_open: false,
+            direct_io: false,
+            keep_key_set: false,
+            auto_recover: false,
+            block_headers: false,
+            logger: None,
+            value_transform: None,
+            record_codec: None,
+            backend: default_backend(),
+        }
+    }
+
+    /// Get the directory holding the SSTables of the database.
+    pub fn tables_path(&self) -> PathBuf {
+        self.tables_path_in(&self.path)
+    }
+
+    /// Get the directory holding the SSTables of the database stored at the supplied path.
+    pub fn tables_path_in(&self, base_path: &str) -> PathBuf {
+        PathBuf::from(format!("{}/{}", base_path, self.table_directory))
+    }
+
+    /// Get the path of the SSTable with the supplied number. Tables are named by
+    /// their number so that they are read in the order they were written.
+    pub fn table_path(&self, table_number: usize) -> PathBuf {
+        self.tables_path()
+            .join(format!("{}.{}", table_number, self.table_extension))
+    }
+
+    /// Log a lifecycle event to the `logger` of the database.
+    pub fn log_info(&self, message: &str) {
+        match &self.logger {
+            Some(logger) => logger.info(message),
+            None => log::info!("{}", message),
         }
     }
+
+    /// Log a condition the database recovered from to the `logger` of the database.
+    pub fn log_warn(&self, message: &str) {
+        match &self.logger {
+            Some(logger) => logger.warn(message),
+            None => log::warn!("{}", message),
+        }
+    }
+
+    /// Log a failure to the `logger` of the database.
+    pub fn log_error(&self, message: &str) {
+        match &self.logger {
+            Some(logger) => logger.error(message),
+            None => log::error!("{}", message),
+        }
+    }
+
+    /// Check whether the file at the path is named like an SSTable.
+    pub fn is_table_path(&self, path: &Path) -> bool {
+        path.extension().map_or(false, |extension| {
+            extension == self.table_extension.as_str()
+        })
+    }
+}
+
+/// Options of a single write made with `Dharma::put_with_options`. The defaults
+/// match a plain `put`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PutOptions {
+    /// Flag specifying whether the Write Ahead Log is synced to disk before the
+    /// write returns so that it survives a crash.
+    pub sync: bool,
+    /// Time after which the key expires and is deleted. Reads hide expired keys and
+    /// their delete markers are written with the next write. Deadlines are held in
+    /// memory so keys whose delete marker wasn't written when the database is closed
+    /// never expire.
+    pub ttl: Option<Duration>,
+    /// Flag specifying whether the write skips the Write Ahead Log. Such writes are
+    /// faster but are lost if the database crashes before they are flushed.
+    pub skip_wal: bool,
+}
+
+#[cfg(feature = "std")]
+fn default_backend() -> Arc<dyn StorageBackend> {
+    Arc::new(FsBackend::new())
+}
+
+// databases are kept in memory when file system access is not available
+#[cfg(not(feature = "std"))]
+fn default_backend() -> Arc<dyn StorageBackend> {
+    Arc::new(InMemoryBackend::new())
 }
 
 #[cfg(test)]
@@ -58,5 +406,59 @@ mod tests {
         assert_eq!(options.block_size_in_bytes, 32768);
         assert_eq!(options.blocks_per_sstable, 32 * 32);
         assert_eq!(options.sparse_index_sampling_rate, 100);
+        assert_eq!(options.value_compression_min_bytes, None);
+        assert_eq!(options.block_cache_size_in_blocks, 128);
+        assert_eq!(options.value_cache_entries, 0);
+        assert!(!options.group_commit);
+        assert_eq!(options.compaction_io_budget_bytes_per_op, None);
+        assert_eq!(options.versions_to_keep, 1);
+        assert_eq!(options.wal_recovery_readahead_blocks, 16);
+        assert!(!options.verify_after_flush);
+        assert_eq!(options.value_separation_min_bytes, None);
+        assert!(!options.prefix_compress_keys);
+        assert_eq!(options.wal_compaction_interval_in_writes, None);
+        assert_eq!(options.max_sstables, None);
+        assert_eq!(options.max_flush_table_size_in_bytes, None);
+        assert_eq!(options.flush_partitions, 1);
+        assert_eq!(options.flush_parallelism, 1);
+        assert_eq!(options.encoding, Encoding::Fixint);
+        assert!(options.flush_on_drop);
+        assert!(options.sync_tables);
+        assert!(options.sync_wal_on_reset);
+        assert!(!options.compaction_retain_inputs);
+        assert!(!options.compaction_merge_overlapping_only);
+        assert_eq!(options.max_concurrent_compactions, 1);
+        assert_eq!(options.compaction_max_records_per_table, None);
+        assert!(!options.compaction_report_duplicates);
+        assert!(!options.retain_deletion_audit);
+        assert_eq!(options.compaction_threshold_bytes, None);
+        assert!(!options.skip_corrupt_records);
+        assert!(options.compaction_filter.is_none());
+        assert!(!options.preallocate_tables);
+        assert_eq!(options.table_directory, String::from("tables"));
+        assert_eq!(options.table_extension, String::from("db"));
+        assert_eq!(options.wal_compression_min_bytes, None);
+        assert_eq!(options.wal_format, WalFormat::Blocks);
+        assert_eq!(options.flush_interval, None);
+        assert_eq!(options.max_index_entries, None);
+        assert_eq!(options.get_parallelism, 1);
+        assert!(options.create_if_missing);
+        assert!(!options.validate_tables_on_open);
+        assert!(!options.direct_io);
+        assert!(!options.keep_key_set);
+        assert!(!options.auto_recover);
+        assert!(!options.block_headers);
+        assert!(options.logger.is_none());
+        assert!(options.value_transform.is_none());
+        assert!(options.record_codec.is_none());
+        assert_eq!(options.table_path(3), PathBuf::from("/tmp/tables/3.db"));
+    }
+
+    #[test]
+    fn test_default_put_options() {
+        let options = PutOptions::default();
+        assert!(!options.sync);
+        assert_eq!(options.ttl, None);
+        assert!(!options.skip_wal);
     }
 }
@@ -1,23 +1,122 @@
+use crate::lazy_value::LazyValue;
 use crate::result::{Errors, Result};
 use crate::options::DharmaOpts;
+use crate::platform;
 use crate::sparse_index::{SparseIndex, TableAddress};
-use crate::storage::block::Value;
-use crate::storage::compaction::basic::{BasicCompaction, BasicCompactionOpts};
+use crate::stats::{
+    DuplicateKey, FlushEstimate, IntegrityReport, SnapshotDescriptor, TableInfo, TableSnapshot,
+};
+use crate::storage::backend::{move_file, DirectIoBackend};
+use crate::storage::block::{create_blocks_with_tombstones, RangeTombstone, Record, RecordType, Value};
+use crate::storage::block_cache::{BlockCache, BlockCacheStats};
+use crate::storage::checksum::checksum_from_footer;
+use crate::storage::compaction::basic::{BasicCompaction, BasicCompactionOpts, DeduplicationReport};
+use crate::storage::compression::Compression;
+use crate::storage::deletion_audit::{append_deletions, read_deletions, truncate_torn_deletions};
+use crate::storage::encoding::Encoding;
+use crate::storage::manifest::{Manifest, FORMAT_VERSION, MIN_TABLE_FORMAT_VERSION};
 use crate::storage::sorted_string_table_reader::{SSTableReader, SSTableValue};
-use crate::storage::sorted_string_table_writer::write_sstable;
-use crate::storage::write_ahead_log::WriteAheadLog;
+use crate::storage::sorted_string_table_writer::{
+    pack_table, split_values_by_table_size, split_values_into_partitions, write_packed_table,
+    write_table_at_path, PackedTable,
+};
+use crate::storage::value_log::ValueLog;
+use crate::storage::write_ahead_log::{WalOperation, WriteAheadLog};
 use crate::traits::{ResourceKey, ResourceValue};
-use std::cmp::Ordering;
-use std::fs::{copy, remove_file};
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BTreeMap, BTreeSet, BinaryHeap};
+use std::ops::Range;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering as AtomicOrdering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Flags describing the operations the persistence layer is currently performing.
+/// Clones share the same flags so they can be observed from other threads.
+#[derive(Clone)]
+pub struct Activity {
+    flushing: Arc<AtomicBool>,
+    compacting: Arc<AtomicBool>,
+}
+
+impl Activity {
+    fn new() -> Activity {
+        Activity {
+            flushing: Arc::new(AtomicBool::new(false)),
+            compacting: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Check whether in-memory values are being flushed to disk.
+    pub fn is_flushing(&self) -> bool {
+        self.flushing.load(AtomicOrdering::SeqCst)
+    }
+
+    /// Check whether SSTables are being compacted.
+    pub fn is_compacting(&self) -> bool {
+        self.compacting.load(AtomicOrdering::SeqCst)
+    }
+}
+
+/// Live keys of the tables written by a compaction along with the key ranges of the
+/// tables it merged. Merged key ranges overlap no table that wasn't merged, so a
+/// persisted key within them is live only if the compacted tables hold it.
+/// See `Persistence::take_compacted_keys`.
+pub struct CompactedKeys<K> {
+    pub merged_ranges: Vec<(K, K)>,
+    pub live_keys: BTreeSet<K>,
+}
+
+// Sets an activity flag for as long as the guard is alive.
+struct ActivityGuard {
+    flag: Arc<AtomicBool>,
+}
+
+impl ActivityGuard {
+    fn start(flag: &Arc<AtomicBool>) -> ActivityGuard {
+        flag.store(true, AtomicOrdering::SeqCst);
+        ActivityGuard { flag: flag.clone() }
+    }
+}
+
+impl Drop for ActivityGuard {
+    fn drop(&mut self) {
+        self.flag.store(false, AtomicOrdering::SeqCst);
+    }
+}
 
 /// Encapsulates all functionality that involves reading
 /// and writing to File System.
 pub struct Persistence<K: ResourceKey> {
     options: DharmaOpts,
     index: SparseIndex<K>,
-    log: WriteAheadLog,
+    // not set when only some tables are mounted, see `create_from_tables`
+    log: Option<WriteAheadLog>,
     compaction: BasicCompaction,
+    block_cache: Arc<Mutex<BlockCache>>,
+    // largest sequence number of persisted values
+    last_sequence: u64,
+    activity: Activity,
+    // number of writes to the Write Ahead Log since it was last compacted
+    writes_since_wal_compaction: usize,
+    // time of the last successful flush and compaction
+    last_flush: Option<SystemTime>,
+    last_compaction: Option<SystemTime>,
+    // whether the Write Ahead Log is kept when the persistence layer is dropped
+    retain_log: bool,
+    // tables reads are served from when only a subset of the tables was opened,
+    // see `create_from_tables`
+    mounted_tables: Option<Vec<PathBuf>>,
+    // whether flushes skip compaction, see `pause_compaction`
+    compaction_paused: bool,
+    // number of blocks loaded while looking up keys in tables
+    blocks_read: AtomicUsize,
+    // whether the manifest marks the database as sealed, see `seal`
+    sealed: bool,
+    // keys written by the compactions since they were last taken, only kept while
+    // the compaction filter can drop live keys from the key set
+    compacted_keys: Vec<CompactedKeys<K>>,
 }
 
 impl<K> Persistence<K>
@@ -33,29 +132,153 @@ where
     /// A result that resolves:
     ///  - _Ok_ - The created persistence instance.
     ///  - _Err_ - Error encountered while creating persistence layer.
-    pub fn create<V: ResourceValue>(options: DharmaOpts) -> Result<Persistence<K>> {
-        // try to create write ahead log
-        let log_result = WriteAheadLog::create(options.clone());
-        if log_result.is_ok() {
-            // read all SSTables and create the sparse index
-            let sstable_paths = SSTableReader::get_valid_table_paths(&options.path)?;
-            // read through each SSTable and create the sparse index on startup
-            let mut index = SparseIndex::new();
-            for path in sstable_paths {
-                let load_result =
-                    Persistence::populate_index_from_path::<V>(&options, &path, &mut index);
-                if load_result.is_err() {
-                    return Err(Errors::DB_INDEX_INITIALIZATION_FAILED);
-                }
-            }
-            return Ok(Persistence {
-                log: log_result.unwrap(),
-                options: options.clone(),
-                index,
-                compaction: BasicCompaction::new(BasicCompactionOpts::from(options.clone())),
-            });
+    pub fn create<V: ResourceValue>(mut options: DharmaOpts) -> Result<Persistence<K>> {
+        // a filter registered for other types would never be consulted by compaction
+        if let Some(filter) = &options.compaction_filter {
+            if filter.get::<K, V>().is_none() {
+                return Err(Errors::COMPACTION_FILTER_MISMATCH);
+            }
+        }
+        // a corrupt manifest is rebuilt from the tables
+        let manifest = match Manifest::read(&options) {
+            Err(Errors::MANIFEST_CORRUPT) => {
+                options.log_warn(&format!(
+                    "Manifest at {} is corrupt, repairing it",
+                    options.path
+                ));
+                Persistence::<K>::repair::<V>(options.clone())?;
+                Manifest::read(&options)?
+            }
+            manifest => manifest?,
+        };
+        let sealed = manifest.as_ref().map_or(false, |manifest| manifest.sealed);
+        // refuse to open data written in a different format
+        let manifest_sequence = match manifest {
+            Some(manifest) => {
+                manifest.check_format_version()?;
+                // tables can only be read with the block size they were written with
+                let block_size = manifest.block_size(&options);
+                if block_size != options.block_size_in_bytes {
+                    options.log_warn(&format!(
+                        "Configured block size {} differs from the block size {} of the tables, using {}",
+                        options.block_size_in_bytes,
+                        block_size,
+                        block_size
+                    ));
+                    options.block_size_in_bytes = block_size;
+                }
+                manifest.last_sequence
+            }
+            None => {
+                if !options.create_if_missing && !options.backend.exists(Path::new(&options.path)) {
+                    return Err(Errors::DB_NOT_FOUND);
+                }
+                options
+                    .backend
+                    .create_dir_all(&options.tables_path())
+                    .map_err(|_| Errors::DB_PATH_CREATION_FAILED)?;
+                Manifest::with_options(&options).write(&options)?;
+                0
+            }
+        };
+        // outputs of compactions interrupted by a crash were never swapped in
+        Persistence::<K>::remove_temp_files(&options)?;
+        // deletions are audited after the records a crash left partially written
+        if options.retain_deletion_audit {
+            truncate_torn_deletions(&options)?;
+        }
+        // read all SSTables and create the sparse index
+        let sstable_paths = SSTableReader::get_table_paths(&options, &options.path)?;
+        let mut persistence =
+            Persistence::open_tables::<V>(options, manifest_sequence, sstable_paths, None)?;
+        persistence.sealed = sealed;
+        Ok(persistence)
+    }
+
+    /// Create the persistence layer serving reads only from the supplied tables of the
+    /// database rather than every table in its directory. This lets tools examine a
+    /// subset of the tables, like the tables of a corrupt database that can still be
+    /// read. The database is read only, writes fail with `DB_PARTIALLY_MOUNTED`, and
+    /// no Write Ahead Log is created or removed.
+    ///
+    /// # Arguments
+    ///  - _options_ - The Dharma options configuration.
+    ///  - _tables_ - The paths of the tables to serve reads from, ordered oldest first
+    ///    like the tables in the directory of the database.
+    ///
+    /// # Returns
+    /// A result that resolves:
+    ///  - _Ok_ - The created persistence instance.
+    ///  - _Err_ - Error encountered while reading the manifest or the tables.
+    pub fn create_from_tables<V: ResourceValue>(
+        mut options: DharmaOpts,
+        tables: Vec<PathBuf>,
+    ) -> Result<Persistence<K>> {
+        // the database isn't modified so a missing or corrupt manifest isn't repaired
+        let manifest_sequence = match Manifest::read(&options)? {
+            Some(manifest) => {
+                manifest.check_format_version()?;
+                options.block_size_in_bytes = manifest.block_size(&options);
+                manifest.last_sequence
+            }
+            None => return Err(Errors::DB_NOT_FOUND),
+        };
+        let mounted_tables = Some(tables.clone());
+        Persistence::open_tables::<V>(options, manifest_sequence, tables, mounted_tables)
+    }
+
+    /// Open the Write Ahead Log and build the sparse index from the tables.
+    fn open_tables<V: ResourceValue>(
+        mut options: DharmaOpts,
+        manifest_sequence: u64,
+        sstable_paths: Vec<PathBuf>,
+        mounted_tables: Option<Vec<PathBuf>>,
+    ) -> Result<Persistence<K>> {
+        if options.direct_io {
+            options.backend = Arc::new(DirectIoBackend::new(
+                options.backend.clone(),
+                options.tables_path(),
+                options.table_extension.clone(),
+            ));
+        }
+        // try to create write ahead log unless the database is only read from
+        let log = match mounted_tables {
+            Some(_) => None,
+            None => Some(WriteAheadLog::create(options.clone())?),
+        };
+        // read through each SSTable and create the sparse index on startup
+        let mut index = SparseIndex::new();
+        let mut last_sequence = manifest_sequence;
+        for path in sstable_paths {
+            let load_result =
+                Persistence::populate_index_from_path::<V>(&options, &path, &mut index);
+            let table_sequence = match load_result {
+                Ok(table_sequence) => table_sequence,
+                Err(err @ Errors::UNSORTED_TABLE { .. }) => return Err(err),
+                Err(_) => return Err(Errors::DB_INDEX_INITIALIZATION_FAILED),
+            };
+            last_sequence = std::cmp::max(last_sequence, table_sequence);
         }
-        Err(log_result.err().unwrap())
+        Ok(Persistence {
+            log,
+            options: options.clone(),
+            index,
+            compaction: BasicCompaction::new(BasicCompactionOpts::from(options.clone())),
+            block_cache: Arc::new(Mutex::new(BlockCache::new(
+                options.block_cache_size_in_blocks,
+            ))),
+            last_sequence,
+            activity: Activity::new(),
+            writes_since_wal_compaction: 0,
+            last_flush: None,
+            last_compaction: None,
+            retain_log: false,
+            mounted_tables,
+            compaction_paused: false,
+            blocks_read: AtomicUsize::new(0),
+            sealed: false,
+            compacted_keys: Vec::new(),
+        })
     }
 
     /// Get the value associated with the specified key.
@@ -68,175 +291,2475 @@ where
     ///  - _Ok_ - Optional that may contain the result value.
     ///  - _Err_ - Error that occurred while reading the value.
     pub fn get<V: ResourceValue>(&mut self, key: &K) -> Result<Option<V>> {
+        self.get_version(key, u64::MAX)
+    }
+
+    /// Get the latest value associated with the specified key that was written
+    /// at or before the supplied sequence number.
+    ///
+    /// # Arguments
+    ///  - _key_ - The key whose value to query.
+    ///  - _at_seq_ - The sequence number as of which to read the value.
+    ///
+    /// # returns
+    /// Result that resolves:
+    ///  - _Ok_ - Optional that may contain the result value.
+    ///  - _Err_ - Error that occurred while reading the value.
+    pub fn get_version<V: ResourceValue>(&mut self, key: &K, at_seq: u64) -> Result<Option<V>> {
+        let found = self.get_record::<V>(key, at_seq)?;
+        if let Some(record) = found {
+            if !record.is_tombstone() && !self.is_range_deleted(key, record.seq, at_seq) {
+                return Ok(Some(record.value));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Get the newest persisted version of the key written at or before the supplied
+    /// sequence number. Unlike `get_version` delete markers are returned and range
+    /// tombstones are not applied.
+    ///
+    /// # Arguments
+    ///  - _key_ - The key whose version to query.
+    ///  - _at_seq_ - The sequence number as of which to read the version.
+    ///
+    /// # returns
+    /// Result that resolves:
+    ///  - _Ok_ - Optional that may contain the version.
+    ///  - _Err_ - Error that occurred while reading the version.
+    pub fn get_record<V: ResourceValue>(
+        &mut self,
+        key: &K,
+        at_seq: u64,
+    ) -> Result<Option<Value<K, V>>> {
+        self.get_record_probing(key, at_seq, &mut Vec::new())
+    }
+
+    /// Get the newest persisted version of the key like `get_record` while recording
+    /// the tables that were read.
+    ///
+    /// # Arguments
+    ///  - _key_ - The key whose version to query.
+    ///  - _at_seq_ - The sequence number as of which to read the version.
+    ///  - _probed_ - Receives the path to each table read in the order they were read.
+    ///
+    /// # returns
+    /// Result that resolves:
+    ///  - _Ok_ - Optional that may contain the version.
+    ///  - _Err_ - Error that occurred while reading the version.
+    pub fn get_record_probing<V: ResourceValue>(
+        &mut self,
+        key: &K,
+        at_seq: u64,
+        probed: &mut Vec<PathBuf>,
+    ) -> Result<Option<Value<K, V>>> {
         // read SSTables and return the value is present
         let maybe_address = self.index.get_nearest_address(key);
-        if maybe_address.is_some() {
-            let address = maybe_address.unwrap();
-            let mut reader = SSTableReader::from(&address.path, self.options.block_size_in_bytes)?;
-            // try to find the value in the sstable
-            let seek_result = reader.seek_closest(address.offset);
-            // if seek offset is invalid then return error
-            // this should never happen as long as SSTables and Sparse Index are in sync
-            let mut prev = None;
-            if seek_result.is_ok() {
-                while reader.has_next() {
-                    let sstable_value = reader.read();
-                    let record = bincode::deserialize::<Value<K, V>>(&sstable_value.data).unwrap();
-                    match record.key.cmp(key) {
-                        Ordering::Less => {
-                            prev = Some(record);
-                            reader.next();
-                        }
-                        Ordering::Equal => {
-                            prev = Some(record);
-                            reader.next();
+        if platform::threads(self.options.get_parallelism) > 1 {
+            let paths = self.index.get_tables_containing(key);
+            if paths.len() > 1 {
+                let candidates = paths
+                    .into_iter()
+                    .map(|path| match &maybe_address {
+                        Some(address) if address.path == path => (path, address.offset),
+                        _ => (path, 0),
+                    })
+                    .collect();
+                return self.get_record_in_parallel::<V>(candidates, key, at_seq, probed);
+            }
+        }
+        let mut found = None;
+        if let Some(address) = &maybe_address {
+            probed.push(address.path.clone());
+            found = self.find_in_table::<V>(&address.path, address.offset, key, at_seq)?;
+        }
+        if found.is_none() {
+            // the nearest sampled key may belong to a table that doesn't hold the key
+            // so scan the other tables whose range covers the key from their start
+            let searched_path = maybe_address.map(|address| address.path);
+            for path in self.index.get_tables_containing(key) {
+                if Some(&path) == searched_path.as_ref() {
+                    continue;
+                }
+                probed.push(path.clone());
+                found = self.find_in_table::<V>(&path, 0, key, at_seq)?;
+                if found.is_some() {
+                    break;
+                }
+            }
+        }
+        Ok(found)
+    }
+
+    /// Probe the candidate tables on up to `get_parallelism` threads and get the
+    /// version of the key from the newest candidate holding it. Candidates are
+    /// handed out newest first and candidates older than a table already known to
+    /// hold the key are skipped.
+    ///
+    /// # Arguments
+    ///  - _candidates_ - The tables to probe along with the offset to start scanning
+    ///    them from, most recently written first.
+    ///  - _key_ - The key whose version to query.
+    ///  - _at_seq_ - The sequence number as of which to read the version.
+    ///  - _probed_ - Receives the path to each table that was read.
+    ///
+    /// # returns
+    /// Result that resolves:
+    ///  - _Ok_ - Optional that may contain the version.
+    ///  - _Err_ - Error that occurred while reading a table newer than the one
+    ///    holding the key.
+    fn get_record_in_parallel<V: ResourceValue>(
+        &self,
+        candidates: Vec<(PathBuf, usize)>,
+        key: &K,
+        at_seq: u64,
+        probed: &mut Vec<PathBuf>,
+    ) -> Result<Option<Value<K, V>>> {
+        // keys aren't required to be shared across threads so workers decode their own copy
+        let encoding = Encoding::default();
+        let key_bytes = encoding
+            .serialize(key)
+            .map_err(|_| Errors::RECORD_SERIALIZATION_FAILED)?;
+        let next_candidate = AtomicUsize::new(0);
+        let newest_found = AtomicUsize::new(usize::MAX);
+        let outcomes: Mutex<Vec<(usize, Result<()>)>> = Mutex::new(Vec::new());
+        let workers = std::cmp::min(
+            platform::threads(self.options.get_parallelism),
+            candidates.len(),
+        );
+        let options = &self.options;
+        let block_cache = &self.block_cache;
+        let blocks_read = &self.blocks_read;
+        thread::scope(|scope| {
+            for _ in 0..workers {
+                scope.spawn(|| {
+                    let worker_key: K = match encoding.deserialize(&key_bytes) {
+                        Ok(worker_key) => worker_key,
+                        Err(_) => {
+                            let error = Err(Errors::RECORD_DESERIALIZATION_FAILED);
+                            outcomes.lock().unwrap().push((0, error));
+                            return;
                         }
-                        Ordering::Greater => {
-                            break;
+                    };
+                    loop {
+                        let index = next_candidate.fetch_add(1, AtomicOrdering::SeqCst);
+                        if index >= candidates.len()
+                            || index > newest_found.load(AtomicOrdering::SeqCst)
+                        {
+                            return;
                         }
+                        let (path, offset) = &candidates[index];
+                        let result = Persistence::find_in_table_with::<V>(
+                            options,
+                            block_cache,
+                            blocks_read,
+                            path,
+                            *offset,
+                            &worker_key,
+                            at_seq,
+                        );
+                        let outcome = match result {
+                            Ok(Some(_)) => {
+                                newest_found.fetch_min(index, AtomicOrdering::SeqCst);
+                                Ok(())
+                            }
+                            Ok(None) => Ok(()),
+                            Err(err) => Err(err),
+                        };
+                        outcomes.lock().unwrap().push((index, outcome));
                     }
-                }
-                if prev.is_some() {
-                    let record = prev.unwrap();
-                    if record.key.cmp(key) == Ordering::Equal && record.value != V::nil() {
-                        return Ok(Some(record.value));
-                    }
-                }
+                });
+            }
+        });
+        let newest_found = newest_found.into_inner();
+        let mut outcomes = outcomes.into_inner().unwrap();
+        outcomes.sort_by_key(|(index, _)| *index);
+        for (index, outcome) in outcomes {
+            if index > newest_found {
+                break;
             }
+            probed.push(candidates[index].0.clone());
+            outcome?;
+        }
+        match candidates.get(newest_found) {
+            // the blocks read by the worker are cached so reading the version again is
+            // cheap, the blocks were already counted by the worker
+            Some((path, offset)) => Persistence::find_in_table_with::<V>(
+                &self.options,
+                &self.block_cache,
+                &AtomicUsize::new(0),
+                path,
+                *offset,
+                key,
+                at_seq,
+            ),
+            None => Ok(None),
         }
-        Ok(None)
     }
 
-    /// Associate the supplied value with the key. This operation writes the
-    /// record to the Write Ahead Log so that it can be recovered in case of failure.
+    /// Get the tables that hold a version of the key. Only tables whose range of keys
+    /// covers the key are read, their number is the number of tables a read of the
+    /// key may have to probe which helps debugging read amplification.
     ///
     /// # Arguments
-    ///  - _key_ - The key.
-    ///  - _value_ - The value to save associated with the key.
+    ///  - _key_ - The key to look up.
+    ///
+    /// # returns
+    /// Result that resolves:
+    ///  - _Ok_ - The paths to the tables holding the key, most recently written first.
+    ///  - _Err_ - Error that occurred while reading the tables.
+    pub fn tables_containing<V: ResourceValue>(&mut self, key: &K) -> Result<Vec<PathBuf>> {
+        let mut paths = Vec::new();
+        for path in self.index.get_tables_containing(key) {
+            if self.find_in_table::<V>(&path, 0, key, u64::MAX)?.is_some() {
+                paths.push(path);
+            }
+        }
+        Ok(paths)
+    }
+
+    /// Get the number of blocks loaded while looking up keys in tables since the
+    /// persistence layer was created, including blocks served from the block cache.
+    pub fn blocks_read(&self) -> usize {
+        self.blocks_read.load(AtomicOrdering::SeqCst)
+    }
+
+    /// Get the tables that a read of the key skips without reading them because
+    /// their range of keys excludes the key, most recently written first.
+    pub fn tables_excluding(&self, key: &K) -> Vec<PathBuf> {
+        self.index.get_tables_excluding(key)
+    }
+
+    /// Check whether the version of the key with the supplied sequence number is
+    /// deleted by a persisted range tombstone when reading as of `at_seq`.
+    pub fn is_range_deleted(&self, key: &K, seq: u64, at_seq: u64) -> bool {
+        self.index.is_range_deleted(key, seq, at_seq)
+    }
+
+    /// Check whether a flush would create more SSTables than `max_sstables` allows.
     ///
     /// # Returns
-    /// A result that resolves:
-    ///  - _Ok_ - If value was successfully saved.
-    ///  - _Err_ - Error that occurred while saving value.
-    pub fn insert<V: ResourceValue>(&mut self, key: K, value: V) -> Result<()> {
-        let log_write_result = self.log.append(key.clone(), value.clone());
-        if log_write_result.is_ok() {
+    /// Result that resolves:
+    ///  - _Ok_ - If a flush may create a new table.
+    ///  - _Err_ - `DB_TOO_MANY_TABLES` if the table limit is reached or error that
+    ///    occurred while listing the tables.
+    pub fn can_flush(&self) -> Result<()> {
+        if self.options.max_sstables.is_none() {
             return Ok(());
         }
-        Err(Errors::DB_WRITE_FAILED)
+        let paths = SSTableReader::get_table_paths(&self.options, &self.options.path)?;
+        self.check_table_limit(paths.len())
     }
 
-    /// Flush the list of key value pairs to disk. This method assumes that list is already
-    /// sorted by key and writes the list to disk as an SSTable.
+    /// Get the flags describing the operations currently being performed.
+    pub fn activity(&self) -> Activity {
+        self.activity.clone()
+    }
+
+    /// Get the options in use, which may differ from the options the
+    /// persistence layer was created with, see `Dharma::effective_options`.
+    pub fn options(&self) -> &DharmaOpts {
+        &self.options
+    }
+
+    /// Get the largest sequence number of the values written so far.
+    pub fn last_sequence(&self) -> u64 {
+        self.last_sequence
+    }
+
+    /// Estimate the number of persisted keys within the range `[start, end)` using
+    /// the sampling points stored in the sparse index.
+    /// Each sampled key stands in for the `sampling_stride` keys that follow it
+    /// in its SSTable. The partially covered intervals at either end of the range offset
+    /// each other on average so the estimate is the number of sampled keys in range
+    /// multiplied by the sampling stride.
     ///
     /// # Arguments
-    ///  - values - List of Key-Value pairs that need to be written to disk.
+    ///  - _start_ - The inclusive lower bound of the range.
+    ///  - _end_ - The exclusive upper bound of the range.
     ///
     /// # Returns
-    /// Result that signifies:
-    ///  - _Ok_ - If values were flushed to disk successfully.
-    ///  - _Err_ - Error that occurred while saving value.
-    pub fn flush<V: ResourceValue>(&mut self, values: &Vec<(K, V)>) -> Result<()> {
-        if values.len() == 0 {
-            return Ok(());
+    /// Result that resolves:
+    ///  - _Ok_ - The approximate number of keys in the range.
+    ///  - _Err_ - Error that occurred while computing the estimate.
+    pub fn estimate_count_in_range(&self, start: &K, end: &K) -> Result<usize> {
+        if start >= end {
+            return Ok(0);
         }
-        // get the existing SSTable paths
-        let paths = SSTableReader::get_valid_table_paths(&self.options.path)?;
-        let flush_result = write_sstable(&self.options, values, paths.len());
-        if flush_result.is_ok() {
-            let new_sstable_path = flush_result.unwrap();
-            // reset Write Ahead Log
-            self.log = self.log.reset()?;
-            // compact sstables
-            let compaction_result = self.compaction.compact::<K, V>();
-            if compaction_result.is_ok() {
-                let maybe_compacted_path = compaction_result.unwrap();
-                if maybe_compacted_path.is_some() {
-                    let compacted_path = maybe_compacted_path.unwrap();
-                    // remove old sstables and replace with compacted table
-                    let swap_result = self.swap_sstables_with_compacted_table(&compacted_path)?;
-                    // update sparse index with new table
-                    self.index.reset();
-                    return Persistence::populate_index_from_path::<V>(
-                        &self.options,
-                        &PathBuf::from(swap_result),
-                        &mut self.index,
-                    );
+        let sampled_count = self.index.count_in_range(start, end);
+        Ok(sampled_count * self.sampling_stride())
+    }
+
+    /// Get the keys sampled in the sparse index in sorted order along with the
+    /// approximate number of persisted keys each sample stands in for.
+    /// See `estimate_count_in_range`.
+    pub fn key_samples(&self) -> Vec<(K, usize)> {
+        let sampling_rate = self.sampling_stride();
+        self.index
+            .sampled_keys()
+            .into_iter()
+            .map(|key| (key, sampling_rate))
+            .collect()
+    }
+
+    /// Load the blocks holding keys within the range `[start, end)` into the block cache.
+    /// The blocks to load are determined using the sampled keys in the sparse index.
+    ///
+    /// # Arguments
+    ///  - _start_ - The inclusive lower bound of the range.
+    ///  - _end_ - The exclusive upper bound of the range.
+    ///
+    /// # Returns
+    /// Result that resolves:
+    ///  - _Ok_ - The number of blocks loaded into the cache.
+    ///  - _Err_ - Error that occurred while reading blocks.
+    pub fn warm_range(&mut self, start: &K, end: &K) -> Result<usize> {
+        // byte offsets spanning the range within each table
+        let mut spans: BTreeMap<PathBuf, (usize, usize)> = BTreeMap::new();
+        for address in self.index.get_addresses_in_range(start, end) {
+            let span = spans
+                .entry(address.path.clone())
+                .or_insert((address.offset, address.offset));
+            span.0 = std::cmp::min(span.0, address.offset);
+            span.1 = std::cmp::max(span.1, address.offset);
+        }
+        let mut warmed = 0;
+        for (path, (start_offset, end_offset)) in spans {
+            let mut reader = SSTableReader::with_options(&self.options, &path)?;
+            reader.set_block_cache(self.block_cache.clone());
+            warmed += reader.warm(start_offset, end_offset);
+        }
+        Ok(warmed)
+    }
+
+    /// Get the hit and miss counters of the block cache.
+    pub fn block_cache_stats(&self) -> BlockCacheStats {
+        self.block_cache.lock().unwrap().stats()
+    }
+
+    /// Get the size in bytes of each SSTable in order.
+    pub fn table_sizes(&self) -> Result<Vec<u64>> {
+        let backend = self.options.backend.as_ref();
+        let paths = self.table_paths()?;
+        paths
+            .iter()
+            .map(|path| {
+                backend
+                    .open(path)
+                    .and_then(|file| file.size())
+                    .map_err(|_| Errors::DB_STATS_FAILED)
+            })
+            .collect()
+    }
+
+    /// Estimate how well the blocks of the SSTables compress with the codec by
+    /// compressing a sample of blocks spread evenly across the tables. Blocks are
+    /// read directly from storage so the block cache is left untouched.
+    ///
+    /// # Arguments
+    ///  - _codec_ - The codec to compress the sampled blocks with.
+    ///  - _sample_blocks_ - The maximum number of blocks to compress.
+    ///
+    /// # Returns
+    /// Result that resolves:
+    ///  - _Ok_ - The size of the compressed blocks relative to their size, 1 when
+    ///    there are no blocks to sample.
+    ///  - _Err_ - `SSTABLE_READ_FAILED` if a sampled block couldn't be read.
+    pub fn estimate_compression(&self, codec: Compression, sample_blocks: usize) -> Result<f64> {
+        let backend = self.options.backend.as_ref();
+        let block_size = self.options.block_size_in_bytes;
+        let mut blocks = Vec::new();
+        for path in self.table_paths()? {
+            let size = backend
+                .open(&path)
+                .and_then(|file| file.size())
+                .map_err(|_| Errors::SSTABLE_READ_FAILED)?;
+            for block in 0..size as usize / block_size {
+                blocks.push((path.clone(), block));
+            }
+        }
+        if blocks.is_empty() || sample_blocks == 0 {
+            return Ok(1.0);
+        }
+        let stride = std::cmp::max(blocks.len() / sample_blocks, 1);
+        let mut buffer = vec![0u8; block_size];
+        let mut original_size = 0;
+        let mut compressed_size = 0;
+        for (path, block) in blocks.iter().step_by(stride).take(sample_blocks) {
+            let read = backend
+                .open(path)
+                .and_then(|mut file| file.read_at(&mut buffer, (block * block_size) as u64))
+                .map_err(|_| Errors::SSTABLE_READ_FAILED)?;
+            original_size += read;
+            compressed_size += codec.compress(&buffer[..read]).len();
+        }
+        if original_size == 0 {
+            return Ok(1.0);
+        }
+        Ok(compressed_size as f64 / original_size as f64)
+    }
+
+    /// Estimate the layout of the SSTable flushing the values along with the range
+    /// tombstones would write by packing them into blocks without writing anything.
+    /// Values are estimated as stored inline and in a single table, see
+    /// `flush_values_with_tombstones`.
+    ///
+    /// # Arguments
+    ///  - _values_ - List of values sorted by key.
+    ///  - _tombstones_ - List of range tombstones stored after the values.
+    ///
+    /// # Returns
+    /// Result that resolves:
+    ///  - _Ok_ - The number of blocks, their size, their padding and the number of
+    ///    split records.
+    ///  - _Err_ - `RECORD_SERIALIZATION_FAILED` if a value couldn't be serialized.
+    pub fn estimate_flush<V: ResourceValue>(
+        &self,
+        values: &[Value<K, V>],
+        tombstones: &[RangeTombstone<K>],
+    ) -> Result<FlushEstimate> {
+        let mut blocks = Vec::new();
+        create_blocks_with_tombstones(&self.options, values, tombstones, &mut blocks)
+            .map_err(|_| Errors::RECORD_SERIALIZATION_FAILED)?;
+        let mut estimate = FlushEstimate {
+            blocks: blocks.len(),
+            total_bytes: blocks.len() * self.options.block_size_in_bytes,
+            ..FlushEstimate::default()
+        };
+        let mut record_bytes = 0;
+        for record in blocks.iter().flat_map(|block| block.records.iter()) {
+            match record.record_type {
+                RecordType::PADDING => continue,
+                RecordType::START => estimate.split_records += 1,
+                _ => {}
+            }
+            record_bytes += Record::RECORD_BASE_SIZE_IN_BYTES + record.data_size_in_bytes as usize;
+        }
+        estimate.padding_bytes = estimate.total_bytes - record_bytes;
+        Ok(estimate)
+    }
+
+    /// Describe each SSTable in order along with its size and recorded checksum.
+    /// Values that were not flushed yet are not covered by the descriptor.
+    pub fn snapshot_descriptor(&self) -> Result<SnapshotDescriptor> {
+        let backend = self.options.backend.as_ref();
+        let mut tables = Vec::new();
+        for path in self.table_paths()? {
+            let mut file = backend.open(&path).map_err(|_| Errors::DB_STATS_FAILED)?;
+            let size = file.size().map_err(|_| Errors::DB_STATS_FAILED)?;
+            tables.push(TableSnapshot {
+                checksum: checksum_from_footer(file.as_mut(), size as usize),
+                size_in_bytes: size,
+                path,
+            });
+        }
+        Ok(SnapshotDescriptor {
+            tables,
+            last_sequence: self.last_sequence,
+        })
+    }
+
+    /// Get the number of keys sampled by the sparse index.
+    pub fn index_entries(&self) -> usize {
+        self.index.len()
+    }
+
+    /// Get the number of keys each key sampled by the sparse index stands in for.
+    /// This is the sampling rate unless the index was coarsened to stay within
+    /// `max_index_entries`.
+    pub fn sampling_stride(&self) -> usize {
+        self.options.sparse_index_sampling_rate as usize * self.index.coarsening() as usize
+    }
+
+    /// Rebuild the sparse index from the SSTables sampling keys at the supplied rate.
+    /// The rate is used for tables written from now on as well. The existing index
+    /// is kept if the tables can't be read.
+    ///
+    /// # Arguments
+    ///  - _sampling_rate_ - One out of every `sampling_rate` keys is sampled.
+    ///
+    /// # Returns
+    /// Result that resolves:
+    ///  - _Ok_ - If the index was rebuilt.
+    ///  - _Err_ - Error that occurred while reading the tables.
+    pub fn resample_index<V: ResourceValue>(&mut self, sampling_rate: u32) -> Result<()> {
+        if sampling_rate == 0 {
+            return Err(Errors::DB_INVALID_SAMPLING_RATE);
+        }
+        let mut options = self.options.clone();
+        options.sparse_index_sampling_rate = sampling_rate;
+        let sstable_paths = self.table_paths()?;
+        let mut index = SparseIndex::new();
+        for path in sstable_paths {
+            Persistence::populate_index_from_path::<V>(&options, &path, &mut index)?;
+        }
+        self.index = index;
+        self.options = options;
+        Ok(())
+    }
+
+    /// Merge the values of all SSTables ignoring values that were not flushed yet.
+    /// Only the latest value of each key is returned, deleted keys are skipped.
+    ///
+    /// # Returns
+    /// Result that resolves:
+    ///  - _Ok_ - Iterator over the persisted keys and values sorted by key.
+    ///    The iterator ends after yielding the first error.
+    ///  - _Err_ - Error that occurred while opening the tables.
+    pub fn scan_persisted<V: ResourceValue>(&self) -> Result<impl Iterator<Item = Result<(K, V)>>> {
+        let paths = self.table_paths()?;
+        let merged = self
+            .compaction
+            .merge_latest_iter::<K, V>(&paths)
+            .map_err(|_| Errors::SSTABLE_READ_FAILED)?;
+        Ok(merged.filter_map(|result| match result {
+            Ok((_, value)) if value == V::nil() => None,
+            Ok(entry) => Some(Ok(entry)),
+            Err(_) => Some(Err(Errors::SSTABLE_READ_FAILED)),
+        }))
+    }
+
+    /// Merge the values of all SSTables like `scan_persisted` along with the path of
+    /// the table each value was read from. The newest table holding a key provides
+    /// its value. Useful to build compaction policies and to debug read amplification.
+    ///
+    /// # Returns
+    /// Result that resolves:
+    ///  - _Ok_ - Iterator over the persisted keys and values along with the path of
+    ///    their table sorted by key. The iterator ends after yielding the first error.
+    ///  - _Err_ - Error that occurred while opening the tables.
+    pub fn global_scan_with_source<V: ResourceValue>(
+        &mut self,
+    ) -> Result<impl Iterator<Item = Result<(K, V, PathBuf)>>> {
+        let paths = self.table_paths()?;
+        let merged = self
+            .compaction
+            .merge_latest_iter_with_source::<K, V>(&paths)
+            .map_err(|_| Errors::SSTABLE_READ_FAILED)?;
+        Ok(merged.filter_map(|result| match result {
+            Ok((_, value, _)) if value == V::nil() => None,
+            Ok(entry) => Some(Ok(entry)),
+            Err(_) => Some(Err(Errors::SSTABLE_READ_FAILED)),
+        }))
+    }
+
+    /// Get the latest persisted value of each key in the range `[start, end)` without
+    /// deserializing the values, see `LazyValue`. Keys deleted on disk are skipped.
+    /// Values whose sequence number can't be read without deserializing them are
+    /// returned deserialized. Use `scan_range` to read the range without
+    /// materializing it.
+    ///
+    /// # Arguments
+    ///  - _start_ - The inclusive lower bound of the range.
+    ///  - _end_ - The exclusive upper bound of the range.
+    ///
+    /// # Returns
+    /// Result that resolves:
+    ///  - _Ok_ - The persisted keys in the range along with their sequence numbers
+    ///    and values sorted by key.
+    ///  - _Err_ - Error that occurred while reading the tables.
+    pub fn range_lazy<V: ResourceValue>(
+        &self,
+        start: &K,
+        end: &K,
+    ) -> Result<Vec<(K, u64, LazyValue<V>)>> {
+        self.scan_range(Some(start), Some(end))?.collect()
+    }
+
+    /// Merge the latest persisted version of each key in the range `[start, end)`
+    /// across the SSTables without materializing the range. Each table is moved to
+    /// `start` from the offset of the nearest key the sparse index sampled from it
+    /// and tables whose keys all fall outside of the range are not read. Keys deleted
+    /// on disk are skipped and values are not deserialized, see `LazyValue`.
+    ///
+    /// # Arguments
+    ///  - _start_ - The inclusive lower bound of the range, unbounded when not set.
+    ///  - _end_ - The exclusive upper bound of the range, unbounded when not set.
+    ///
+    /// # Returns
+    /// Result that resolves:
+    ///  - _Ok_ - Iterator over the persisted keys in the range along with their
+    ///    sequence numbers and values sorted by key. The iterator ends after
+    ///    yielding the first error.
+    ///  - _Err_ - Error that occurred while opening the tables.
+    pub fn scan_range<V: ResourceValue>(
+        &self,
+        start: Option<&K>,
+        end: Option<&K>,
+    ) -> Result<PersistedRange<K, V>> {
+        let paths = self.table_paths()?;
+        let offsets = match start {
+            Some(start) => self.index.get_nearest_table_offsets(start),
+            None => BTreeMap::new(),
+        };
+        let mut tables = Vec::with_capacity(paths.len());
+        for path in &paths {
+            if let Some((first, last)) = self.index.get_table_range(path) {
+                let below_start = start.map_or(false, |start| &last < start);
+                let above_end = end.map_or(false, |end| &first >= end);
+                if below_start || above_end {
+                    continue;
                 }
             }
-            let index_update_result = Persistence::populate_index_from_path::<V>(
-                &self.options,
-                &new_sstable_path,
-                &mut self.index,
-            );
-            if index_update_result.is_err() {
-                return Err(Errors::DB_INDEX_UPDATE_FAILED);
+            let mut reader = SSTableReader::with_options(&self.options, path)?;
+            if let Some(start) = start {
+                match offsets.get(path) {
+                    Some(offset) => reader.seek_to_key_from(start, *offset)?,
+                    None => reader.seek_to_key(start)?,
+                };
             }
-            return Ok(());
+            tables.push(reader);
         }
-        Err(Errors::SSTABLE_CREATION_FAILED)
+        PersistedRange::new(tables, end.cloned(), self.index.get_range_tombstones())
     }
 
-    /// Attempt to recover data from existing WAL. This operation does not ensure
-    /// database recovery and could lead to data loss. WAL is deleted after
-    /// this operation.
-    pub fn recover<T: ResourceKey, U: ResourceValue>(options: DharmaOpts) -> Result<Vec<(T, U)>> {
-        return WriteAheadLog::recover(options);
+    /// Get the smallest and largest key stored in the SSTables. Either key may
+    /// have been deleted since it was written.
+    pub fn key_range(&self) -> Option<(K, K)> {
+        self.index.key_range()
     }
 
-    pub fn delete(&mut self, key: &K) -> Result<()> {
-        // add delete marker to Write Ahead Log
-        unimplemented!()
+    /// Get the smallest and largest key stored in each SSTable. Either key may
+    /// have been deleted since it was written.
+    pub fn table_ranges(&self) -> Vec<(K, K)> {
+        self.index.table_ranges()
     }
 
-    fn populate_index_from_path<V: ResourceValue>(
-        options: &DharmaOpts,
-        path: &PathBuf,
-        index: &mut SparseIndex<K>,
-    ) -> Result<()> {
-        let mut counter = 0;
-        let maybe_reader = SSTableReader::from(path, options.block_size_in_bytes);
-        if maybe_reader.is_ok() {
-            let mut reader = maybe_reader.unwrap();
+    /// Read every SSTable and report the keys stored in more than one table.
+    /// Only tables whose key range overlaps the range of another table can share
+    /// keys so the keys of the remaining tables are not collected.
+    pub fn verify_integrity<V: ResourceValue>(&self) -> Result<IntegrityReport<K>> {
+        let paths = self.table_paths()?;
+        let ranges: Vec<Option<(K, K)>> = paths
+            .iter()
+            .map(|path| self.index.get_table_range(path))
+            .collect();
+        let mut occurrences: BTreeMap<K, Vec<(PathBuf, u64)>> = BTreeMap::new();
+        for (position, path) in paths.iter().enumerate() {
+            let overlaps = ranges[position].as_ref().map_or(false, |(first, last)| {
+                ranges.iter().enumerate().any(|(other, range)| {
+                    other != position
+                        && range
+                            .as_ref()
+                            .map_or(false, |(start, end)| start <= last && first <= end)
+                })
+            });
+            let mut reader = SSTableReader::with_options(&self.options, path)?;
             while reader.has_next() {
-                if counter % options.sparse_index_sampling_rate == 0 {
-                    let sstable_value: SSTableValue = reader.read();
-                    let record: Value<K, V> =
-                        bincode::deserialize(sstable_value.data.as_slice()).unwrap();
-                    let key = record.key;
-                    println!("saving key in index {}", key);
-                    let offset = sstable_value.offset;
-                    let address = TableAddress::new(path, offset);
-                    index.update(key.clone(), address);
-                }
-                counter += 1;
+                let sstable_value = reader.read();
                 reader.next();
+                if !overlaps || sstable_value.range_tombstone {
+                    continue;
+                }
+                let key: K = sstable_value.to_key()?;
+                let seq = match sstable_value.to_seq::<K>()? {
+                    Some(seq) => seq,
+                    None => sstable_value.to_record::<K, V>()?.seq,
+                };
+                let tables = occurrences.entry(key).or_insert_with(Vec::new);
+                // versions of a key are stored newest first within a table
+                if tables.last().map_or(true, |(table, _)| table != path) {
+                    tables.push((path.clone(), seq));
+                }
+            }
+            if reader.read_failed() {
+                return Err(Errors::SSTABLE_READ_FAILED);
             }
-            return Ok(());
         }
-        Err(Errors::DB_INDEX_UPDATE_FAILED)
+        let duplicate_keys = occurrences
+            .into_iter()
+            .filter(|(_, tables)| tables.len() > 1)
+            .map(|(key, tables)| DuplicateKey { key, tables })
+            .collect();
+        Ok(IntegrityReport {
+            tables_checked: paths.len(),
+            duplicate_keys,
+        })
     }
 
-    fn swap_sstables_with_compacted_table(&mut self, compacted_path: &PathBuf) -> Result<String> {
-        let sstable_paths = SSTableReader::get_valid_table_paths(&self.options.path)?;
-        for table_path in sstable_paths {
-            remove_file(table_path);
+    /// Describe each SSTable in order along with the range of keys it covers. Key
+    /// ranges come from the index and record counts from the summary in the footer
+    /// of each table, only tables written before the summary was recorded are read.
+    pub fn table_layout(&self) -> Result<Vec<TableInfo<K>>> {
+        let paths = self.table_paths()?;
+        let backend = self.options.backend.as_ref();
+        let mut output = Vec::new();
+        for path in paths {
+            let size_in_bytes = backend
+                .open(&path)
+                .and_then(|file| file.size())
+                .map_err(|_| Errors::SSTABLE_READ_FAILED)?;
+            let footer = SSTableReader::read_footer_with_backend(backend, &path)?;
+            let records = match footer.records {
+                Some(records) => records,
+                None => SSTableReader::with_options(&self.options, &path)?
+                    .offsets()
+                    .collect::<Result<Vec<(usize, usize)>>>()?
+                    .len(),
+            };
+            output.push(TableInfo {
+                key_range: self.index.get_table_range(&path),
+                records,
+                size_in_bytes,
+                path,
+            });
         }
-        let new_sstable_path = format!("{}/tables/0.db", self.options.path);
-        // copy compacted table
-        return copy(compacted_path, Path::new(&new_sstable_path))
-            .and_then(|_| remove_file(compacted_path))
-            .map(|_| new_sstable_path)
-            .map_err(|_| Errors::COMPACTION_CLEANUP_FAILED);
+        Ok(output)
     }
-}
 
-// Cleanup Persistence layer state before shutdown.
-impl<K> Drop for Persistence<K>
-where
-    K: ResourceKey,
-{
-    fn drop(&mut self) {
-        self.log.cleanup();
+    /// Count the delete markers and range tombstones stored in the SSTables.
+    /// Records that can't be decoded are skipped if `skip_corrupt_records` is set.
+    pub fn tombstone_count<V: ResourceValue>(&self) -> Result<usize> {
+        let paths = self.table_paths()?;
+        let mut count = 0;
+        for path in paths {
+            let mut reader = SSTableReader::with_options(&self.options, &path)?;
+            while reader.has_next() {
+                let sstable_value = reader.read();
+                if sstable_value.range_tombstone {
+                    count += 1;
+                } else if let Some(record) =
+                    Persistence::<K>::decode_record::<V>(&self.options, &path, &sstable_value)?
+                {
+                    if record.is_tombstone() {
+                        count += 1;
+                    }
+                }
+                reader.next();
+            }
+            if reader.read_failed() {
+                return Err(Errors::SSTABLE_READ_FAILED);
+            }
+        }
+        Ok(count)
+    }
+
+    /// Get the size in bytes of the value log, zero if no values were separated.
+    pub fn value_log_size(&self) -> Result<u64> {
+        let path = ValueLog::path(&self.options);
+        if !self.options.backend.exists(&path) {
+            return Ok(0);
+        }
+        self.options
+            .backend
+            .open(&path)
+            .and_then(|file| file.size())
+            .map_err(|_| Errors::DB_STATS_FAILED)
+    }
+
+    /// Get the size in bytes of the Write Ahead Log.
+    pub fn wal_size(&self) -> Result<u64> {
+        match &self.log {
+            Some(log) => log.size(),
+            None => Ok(0),
+        }
+    }
+
+    /// Sync the records written to the Write Ahead Log to disk.
+    pub fn sync_log(&self) -> Result<()> {
+        match &self.log {
+            Some(log) => log.sync(),
+            None => Ok(()),
+        }
+    }
+
+    /// Keep the Write Ahead Log when the persistence layer is dropped so that
+    /// values that were not flushed can be recovered with `Dharma::recover`.
+    pub fn retain_log(&mut self) {
+        self.retain_log = true;
+    }
+
+    /// Remove the Write Ahead Log. Only call this once all values logged to it
+    /// were flushed, values are no longer logged afterwards.
+    ///
+    /// # Returns
+    /// Result that signifies:
+    ///  - _Ok_ - If the log was removed.
+    ///  - _Err_ - `WAL_CLEANUP_FAILED` if the log couldn't be removed.
+    pub fn remove_log(&mut self) -> Result<()> {
+        if let Some(log) = self.log.as_mut() {
+            log.cleanup()?;
+        }
+        // the log no longer exists so it isn't removed again on drop
+        self.retain_log = true;
+        Ok(())
+    }
+
+    /// Mark the database as sealed in the manifest. Writes are rejected with
+    /// `DB_SEALED` from then on, including writes of handles opened later.
+    /// Only call this once all values were flushed and the log was removed.
+    ///
+    /// # Returns
+    /// Result that signifies:
+    ///  - _Ok_ - If the database was sealed.
+    ///  - _Err_ - Error that occurred while writing the manifest.
+    pub fn seal(&mut self) -> Result<()> {
+        self.check_writable()?;
+        // the marker is written first so a manifest rebuilt by `repair` is sealed too
+        Manifest::write_sealed_marker(&self.options)?;
+        self.sealed = true;
+        if let Err(err) = self.write_manifest() {
+            self.sealed = false;
+            self.options
+                .backend
+                .remove(&Manifest::sealed_marker_path(&self.options))
+                .map_err(|_| Errors::MANIFEST_WRITE_FAILED)?;
+            return Err(err);
+        }
+        self.options.log_info("Sealed database");
+        Ok(())
+    }
+
+    /// Check whether the database was sealed, see `seal`.
+    pub fn is_sealed(&self) -> bool {
+        self.sealed
+    }
+
+    /// Get the time at which values were last flushed to disk.
+    pub fn last_flush(&self) -> Option<SystemTime> {
+        self.last_flush
+    }
+
+    /// Get the time at which SSTables were last compacted.
+    pub fn last_compaction(&self) -> Option<SystemTime> {
+        self.last_compaction
+    }
+
+    /// Take the live keys of the tables written by the compactions since this was
+    /// last called, oldest compaction first. Keys are only kept when both
+    /// `keep_key_set` and `compaction_filter` are set since compactions can't drop
+    /// live keys otherwise.
+    pub fn take_compacted_keys(&mut self) -> Vec<CompactedKeys<K>> {
+        std::mem::take(&mut self.compacted_keys)
+    }
+
+    /// Get the space taken by identical values found by the last compaction.
+    /// Not set unless `compaction_report_duplicates` is set.
+    pub fn last_deduplication_report(&self) -> Option<DeduplicationReport> {
+        self.compaction.last_deduplication_report()
+    }
+
+    /// Read the deleted keys along with the time they were deleted, see
+    /// `DharmaOpts::retain_deletion_audit`.
+    ///
+    /// # Returns
+    /// Result that resolves:
+    ///  - _Ok_ - The deleted keys in the order they were audited.
+    ///  - _Err_ - `DELETION_AUDIT_READ_FAILED` if the audit table couldn't be read.
+    pub fn deletion_log(&self) -> Result<Vec<(K, SystemTime)>> {
+        read_deletions::<K>(&self.options)
+    }
+
+    /// Record the deletion of the keys in the audit table when
+    /// `DharmaOpts::retain_deletion_audit` is set. Deletions are audited once
+    /// they were applied so only deletions that took effect are listed.
+    ///
+    /// # Arguments
+    ///  - _keys_ - The deleted keys.
+    ///
+    /// # Returns
+    /// Result that resolves:
+    ///  - _Ok_ - If the deletions were audited or auditing is disabled.
+    ///  - _Err_ - `DB_SEALED` if the database can't be written to or
+    ///    `DELETION_AUDIT_WRITE_FAILED` if the deletions couldn't be audited.
+    pub fn audit_deletions(&self, keys: &[K]) -> Result<()> {
+        if !self.options.retain_deletion_audit {
+            return Ok(());
+        }
+        self.check_writable()?;
+        append_deletions(&self.options, keys, platform::now())
+    }
+
+    /// Walk through all records in the SSTable at the supplied path invoking the
+    /// visitor with the key, value and offset of each record in order.
+    /// This can be used to build custom indexes or statistics in a single pass.
+    ///
+    /// # Arguments
+    ///  - _path_ - The path of the SSTable to scan.
+    ///  - _visitor_ - Callback invoked with the key, value and offset of each record.
+    ///
+    /// # Returns
+    /// Result that resolves:
+    ///  - _Ok_ - If all records in the table were visited.
+    ///  - _Err_ - Error that occurred while reading a record.
+    pub fn scan_table<V: ResourceValue, F: FnMut(&K, &V, usize)>(
+        &self,
+        path: &PathBuf,
+        mut visitor: F,
+    ) -> Result<()> {
+        let mut reader = SSTableReader::with_options(&self.options, path)?;
+        while reader.has_next() {
+            let sstable_value = reader.read();
+            // range tombstones are not values so they aren't visited
+            if !sstable_value.range_tombstone {
+                if let Some(record) =
+                    Persistence::<K>::decode_record::<V>(&self.options, path, &sstable_value)?
+                {
+                    visitor(&record.key, &record.value, sstable_value.offset);
+                }
+            }
+            reader.next();
+        }
+        Ok(())
+    }
+
+    /// Associate the supplied value with the key. This operation writes the
+    /// record to the Write Ahead Log so that it can be recovered in case of failure.
+    ///
+    /// # Arguments
+    ///  - _key_ - The key.
+    ///  - _value_ - The value to save associated with the key.
+    ///
+    /// # Returns
+    /// A result that resolves:
+    ///  - _Ok_ - If value was successfully saved.
+    ///  - _Err_ - Error that occurred while saving value.
+    pub fn insert<V: ResourceValue>(&mut self, key: K, value: V) -> Result<()> {
+        self.insert_versioned(key, value, 0)
+    }
+
+    /// Associate the supplied value with the key tagging it with the sequence
+    /// number of the write. See `insert`.
+    ///
+    /// # Arguments
+    ///  - _key_ - The key.
+    ///  - _value_ - The value to save associated with the key.
+    ///  - _seq_ - The sequence number of the write.
+    ///
+    /// # Returns
+    /// A result that resolves:
+    ///  - _Ok_ - If value was successfully saved.
+    ///  - _Err_ - Error that occurred while saving value.
+    pub fn insert_versioned<V: ResourceValue>(&mut self, key: K, value: V, seq: u64) -> Result<()> {
+        self.check_writable()?;
+        let log = self.log_mut()?;
+        let log_write_result = log.append_versioned(key, value, seq);
+        if log_write_result.is_ok() {
+            self.last_sequence = std::cmp::max(self.last_sequence, seq);
+            if let Some(interval) = self.options.wal_compaction_interval_in_writes {
+                self.writes_since_wal_compaction += 1;
+                if self.writes_since_wal_compaction >= interval {
+                    self.writes_since_wal_compaction = 0;
+                    self.log_mut()?.compact::<K, V>()?;
+                }
+            }
+            return Ok(());
+        }
+        Err(Errors::DB_WRITE_FAILED)
+    }
+
+    /// Record the range tombstone in the Write Ahead Log so that the range
+    /// deletion can be recovered in case of failure.
+    ///
+    /// # Arguments
+    ///  - _tombstone_ - The range tombstone.
+    ///
+    /// # Returns
+    /// A result that resolves:
+    ///  - _Ok_ - If the tombstone was successfully saved.
+    ///  - _Err_ - Error that occurred while saving the tombstone.
+    pub fn insert_range_tombstone(&mut self, tombstone: &RangeTombstone<K>) -> Result<()> {
+        self.check_writable()?;
+        if self.log_mut()?.append_range_tombstone(tombstone).is_ok() {
+            self.last_sequence = std::cmp::max(self.last_sequence, tombstone.seq);
+            return Ok(());
+        }
+        Err(Errors::DB_WRITE_FAILED)
+    }
+
+    /// Flush the list of key value pairs to disk. This method assumes that list is already
+    /// sorted by key and writes the list to disk as an SSTable.
+    ///
+    /// # Arguments
+    ///  - values - List of Key-Value pairs that need to be written to disk.
+    ///
+    /// # Returns
+    /// Result that signifies:
+    ///  - _Ok_ - If values were flushed to disk successfully.
+    ///  - _Err_ - Error that occurred while saving value.
+    pub fn flush<V: ResourceValue>(&mut self, values: &Vec<(K, V)>) -> Result<()> {
+        let values = values
+            .iter()
+            .map(|(key, value)| Value::new(key.clone(), value.clone()))
+            .collect();
+        self.flush_values(&values)
+    }
+
+    /// Flush the list of values to disk. This method assumes that the list is sorted
+    /// by key and that versions of the same key are sorted by descending sequence number.
+    ///
+    /// # Arguments
+    ///  - values - List of values that need to be written to disk.
+    ///
+    /// # Returns
+    /// Result that signifies:
+    ///  - _Ok_ - If values were flushed to disk successfully.
+    ///  - _Err_ - Error that occurred while saving value.
+    pub fn flush_values<V: ResourceValue>(&mut self, values: &Vec<Value<K, V>>) -> Result<()> {
+        self.flush_values_with_tombstones(values, &Vec::new())
+    }
+
+    /// Flush the list of values along with the range tombstones to disk.
+    /// See `flush_values`.
+    ///
+    /// # Arguments
+    ///  - values - List of values that need to be written to disk.
+    ///  - tombstones - List of range tombstones that need to be written to disk.
+    ///
+    /// # Returns
+    /// Result that signifies:
+    ///  - _Ok_ - If values and tombstones were flushed to disk successfully.
+    ///  - _Err_ - Error that occurred while saving value.
+    pub fn flush_values_with_tombstones<V: ResourceValue>(
+        &mut self,
+        values: &Vec<Value<K, V>>,
+        tombstones: &Vec<RangeTombstone<K>>,
+    ) -> Result<()> {
+        self.flush_values_retaining(values, tombstones, &Vec::new(), &Vec::new())
+    }
+
+    /// Flush the list of values along with the range tombstones to disk while keeping
+    /// values and range tombstones that are still only held in memory in the Write
+    /// Ahead Log. The log is replaced by one holding only the retained entries instead
+    /// of being reset, so the retained entries are logged at every point of the flush.
+    /// See `flush_values`.
+    ///
+    /// # Arguments
+    ///  - values - List of values that need to be written to disk.
+    ///  - tombstones - List of range tombstones that need to be written to disk.
+    ///  - retained_values - Values to keep in the log in the order they are replayed.
+    ///  - retained_tombstones - Range tombstones to keep in the log.
+    ///
+    /// # Returns
+    /// Result that signifies:
+    ///  - _Ok_ - If values and tombstones were flushed to disk successfully.
+    ///  - _Err_ - Error that occurred while saving value.
+    pub fn flush_values_retaining<V: ResourceValue>(
+        &mut self,
+        values: &Vec<Value<K, V>>,
+        tombstones: &Vec<RangeTombstone<K>>,
+        retained_values: &Vec<Value<K, V>>,
+        retained_tombstones: &Vec<RangeTombstone<K>>,
+    ) -> Result<()> {
+        self.check_writable()?;
+        if values.len() == 0 && tombstones.len() == 0 {
+            return Ok(());
+        }
+        let _flushing = ActivityGuard::start(&self.activity.flushing);
+        // get the existing SSTable paths
+        let paths = SSTableReader::get_table_paths(&self.options, &self.options.path)?;
+        // values are partitioned by key and large partitions are split further into
+        // several tables, the tables of a flush hold disjoint key ranges
+        let partitions = split_values_into_partitions(values, self.options.flush_partitions);
+        let ranges: Vec<Range<usize>> = match self.options.max_flush_table_size_in_bytes {
+            Some(max_table_size) => {
+                let mut ranges = Vec::new();
+                for partition in partitions {
+                    let partition_values = &values[partition.clone()];
+                    let table_ranges = split_values_by_table_size(
+                        &self.options,
+                        partition_values,
+                        max_table_size,
+                    )?;
+                    for range in table_ranges {
+                        ranges.push(range.start + partition.start..range.end + partition.start);
+                    }
+                }
+                ranges
+            }
+            None => partitions,
+        };
+        self.check_table_limit(paths.len() + ranges.len() - 1)?;
+        let no_tombstones = Vec::new();
+        // values are packed one table at a time, only writing the tables is parallel
+        let mut tables = Vec::new();
+        for (table_counter, range) in ranges.iter().enumerate() {
+            // range tombstones are written to the last table
+            let table_tombstones = if table_counter + 1 == ranges.len() {
+                tombstones
+            } else {
+                &no_tombstones
+            };
+            let table_values = &values[range.clone()];
+            // no table is written when there is nothing to flush
+            if table_values.is_empty() && table_tombstones.is_empty() {
+                continue;
+            }
+            let table_number = paths.len() + table_counter;
+            let packed = pack_table(&self.options, table_values, table_tombstones);
+            match packed {
+                Ok(packed) => tables.push((table_number, range.clone(), table_tombstones, packed)),
+                Err(_) => {
+                    self.options
+                        .log_error(&format!("Failed to flush values to table {}", table_number));
+                    return Err(Errors::SSTABLE_CREATION_FAILED);
+                }
+            }
+        }
+        let tables_path = self.options.tables_path();
+        if !tables.is_empty() && !self.options.backend.exists(&tables_path) {
+            self.options
+                .backend
+                .create_dir_all(&tables_path)
+                .map_err(|_| Errors::SSTABLE_CREATION_FAILED)?;
+        }
+        let packed_tables: Vec<(PathBuf, &PackedTable)> = tables
+            .iter()
+            .map(|(table_number, _, _, packed)| (self.options.table_path(*table_number), packed))
+            .collect();
+        let write_results = Persistence::<K>::write_packed_tables(&self.options, &packed_tables);
+        let mut new_sstable_paths = Vec::new();
+        for ((table_number, range, table_tombstones, _), write_result) in
+            tables.iter().zip(write_results)
+        {
+            if write_result.is_err() {
+                self.options
+                    .log_error(&format!("Failed to flush values to table {}", table_number));
+                return Err(Errors::SSTABLE_CREATION_FAILED);
+            }
+            let new_sstable_path = self.options.table_path(*table_number);
+            let table_values = &values[range.clone()];
+            if self.options.verify_after_flush {
+                self.verify_table::<V>(&new_sstable_path, table_values, table_tombstones)?;
+            }
+            // table paths are reused so drop stale blocks of a previous table at this path
+            self.block_cache.lock().unwrap().invalidate(&new_sstable_path);
+            new_sstable_paths.push(new_sstable_path);
+        }
+        if new_sstable_paths.is_empty() {
+            return Ok(());
+        }
+        // the tables and their directory entries must be durable before the values
+        // are dropped from the log, otherwise a crash in between loses them
+        self.sync_table_dir()?;
+        // the sequence numbers of the flushed writes are dropped from the log
+        self.write_manifest()?;
+        // reset Write Ahead Log
+        let log = self.log_mut()?;
+        if retained_values.is_empty() && retained_tombstones.is_empty() {
+            *log = log.reset()?;
+        } else {
+            log.rewrite(retained_values, retained_tombstones)?;
+        }
+        self.writes_since_wal_compaction = 0;
+        self.last_flush = Some(platform::now());
+        self.options.log_info(&format!(
+            "Flushed {} values to {} tables",
+            values.len(),
+            new_sstable_paths.len()
+        ));
+        // the key ranges of the flushed tables are known from their values, compaction
+        // looks them up when the tables are merged
+        for (new_sstable_path, (_, range, _, _)) in new_sstable_paths.iter().zip(&tables) {
+            let table_values = &values[range.clone()];
+            if let (Some(first), Some(last)) = (table_values.first(), table_values.last()) {
+                self.index.update_table_range(
+                    new_sstable_path,
+                    first.key.clone(),
+                    last.key.clone(),
+                );
+            }
+        }
+        // compact sstables unless compaction is paused, the merged tables are left in
+        // place when compaction fails so only the flushed tables are indexed then
+        if !self.compaction_paused && self.compact_tables::<V>(&new_sstable_paths)? {
+            return Ok(());
+        }
+        for new_sstable_path in &new_sstable_paths {
+            let index_update_result = Persistence::populate_index_from_path::<V>(
+                &self.options,
+                new_sstable_path,
+                &mut self.index,
+            );
+            if index_update_result.is_err() {
+                return Err(Errors::DB_INDEX_UPDATE_FAILED);
+            }
+        }
+        Ok(())
+    }
+
+    /// Write the packed tables to their paths on up to `flush_parallelism` threads.
+    ///
+    /// # Returns
+    /// The result of writing each table in the order of the tables.
+    fn write_packed_tables(
+        options: &DharmaOpts,
+        tables: &[(PathBuf, &PackedTable)],
+    ) -> Vec<Result<()>> {
+        let workers = std::cmp::min(platform::threads(options.flush_parallelism), tables.len());
+        if workers <= 1 {
+            return tables
+                .iter()
+                .map(|(path, table)| write_packed_table(options, table, path))
+                .collect();
+        }
+        let next_table = AtomicUsize::new(0);
+        let outcomes: Mutex<Vec<(usize, Result<()>)>> = Mutex::new(Vec::new());
+        thread::scope(|scope| {
+            for _ in 0..workers {
+                scope.spawn(|| loop {
+                    let index = next_table.fetch_add(1, AtomicOrdering::SeqCst);
+                    if index >= tables.len() {
+                        return;
+                    }
+                    let (path, table) = &tables[index];
+                    let outcome = write_packed_table(options, table, path);
+                    outcomes.lock().unwrap().push((index, outcome));
+                });
+            }
+        });
+        let mut outcomes = outcomes.into_inner().unwrap();
+        outcomes.sort_by_key(|(index, _)| *index);
+        outcomes.into_iter().map(|(_, outcome)| outcome).collect()
+    }
+
+    /// Stop compacting tables after flushes until `resume_compaction` is called.
+    /// Flushed tables accumulate in the meantime, flushes still fail with
+    /// `DB_TOO_MANY_TABLES` once `max_sstables` is reached.
+    pub fn pause_compaction(&mut self) {
+        self.compaction_paused = true;
+    }
+
+    /// Resume compacting tables after flushes and compact the tables that
+    /// accumulated while compaction was paused.
+    ///
+    /// # Returns
+    /// Result that resolves:
+    ///  - _Ok_ - If compaction was resumed.
+    ///  - _Err_ - Error that occurred while indexing the compacted tables.
+    pub fn resume_compaction<V: ResourceValue>(&mut self) -> Result<()> {
+        self.check_writable()?;
+        self.compaction_paused = false;
+        self.compact_tables::<V>(&Vec::new())?;
+        Ok(())
+    }
+
+    /// Check whether compaction was paused by `pause_compaction`.
+    pub fn is_compaction_paused(&self) -> bool {
+        self.compaction_paused
+    }
+
+    /// Rewrite the SSTables keeping only the values with keys in the range `[start, end]`
+    /// and the range tombstones overlapping it. Everything else is discarded from disk.
+    /// Tables left empty are removed and the remaining tables are renumbered.
+    ///
+    /// # Arguments
+    ///  - _start_ - The inclusive lower bound of the range.
+    ///  - _end_ - The inclusive upper bound of the range.
+    ///
+    /// # Returns
+    /// Result that signifies:
+    ///  - _Ok_ - If the tables were rewritten.
+    ///  - _Err_ - Error that occurred while rewriting the tables.
+    pub fn retain_range<V: ResourceValue>(&mut self, start: &K, end: &K) -> Result<()> {
+        self.check_writable()?;
+        let backend = self.options.backend.as_ref();
+        let sstable_paths = SSTableReader::get_table_paths(&self.options, &self.options.path)?;
+        let rewrite_path = self.options.tables_path().join("retain.tmp");
+        let mut retained_paths = Vec::new();
+        for path in sstable_paths {
+            let mut reader = SSTableReader::with_options(&self.options, &path)?;
+            let mut values: Vec<Value<K, V>> = Vec::new();
+            let mut tombstones = Vec::new();
+            while reader.has_next() {
+                let sstable_value = reader.read();
+                if sstable_value.range_tombstone {
+                    let tombstone = sstable_value.to_range_tombstone::<K>()?;
+                    if &tombstone.start <= end && &tombstone.end > start {
+                        tombstones.push(tombstone);
+                    }
+                } else {
+                    let record = sstable_value.to_record::<K, V>()?;
+                    if &record.key >= start && &record.key <= end {
+                        values.push(record);
+                    }
+                }
+                reader.next();
+            }
+            if reader.read_failed() {
+                return Err(Errors::SSTABLE_READ_FAILED);
+            }
+            if values.is_empty() && tombstones.is_empty() {
+                backend
+                    .remove(&path)
+                    .map_err(|_| Errors::COMPACTION_CLEANUP_FAILED)?;
+                continue;
+            }
+            write_table_at_path(&self.options, &values, &tombstones, &rewrite_path)?;
+            backend
+                .rename(&rewrite_path, &path)
+                .map_err(|_| Errors::COMPACTION_CLEANUP_FAILED)?;
+            retained_paths.push(path);
+        }
+        self.block_cache.lock().unwrap().clear();
+        let moved_paths = self.renumber_tables(&retained_paths)?;
+        self.index = SparseIndex::new();
+        for (_, table_path) in moved_paths {
+            Persistence::populate_index_from_path::<V>(
+                &self.options,
+                &table_path,
+                &mut self.index,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Merge all SSTables into a single table holding only the latest value of each
+    /// live key regardless of the compaction threshold. Deleted keys, values deleted
+    /// by range tombstones and the tombstones themselves are dropped. The merged
+    /// table replaces the first table before the remaining tables are removed.
+    ///
+    /// # Returns
+    /// Result that signifies:
+    ///  - _Ok_ - If the tables were merged.
+    ///  - _Err_ - Error that occurred while reading or replacing the tables.
+    pub fn consolidate<V: ResourceValue>(&mut self) -> Result<()> {
+        self.check_writable()?;
+        let _compacting = ActivityGuard::start(&self.activity.compacting);
+        let backend = self.options.backend.as_ref();
+        let sstable_paths = SSTableReader::get_table_paths(&self.options, &self.options.path)?;
+        if sstable_paths.is_empty() {
+            return Ok(());
+        }
+        // values are streamed from the tables to the consolidated table
+        let consolidated_path = self.options.tables_path().join("consolidate.tmp");
+        let consolidated = self
+            .compaction
+            .consolidate::<K, V>(&sstable_paths, &consolidated_path)
+            .map_err(|_| Errors::SSTABLE_READ_FAILED)?;
+        let mut retained_paths = Vec::new();
+        if consolidated {
+            move_file(backend, &consolidated_path, &sstable_paths[0])
+                .map_err(|_| Errors::COMPACTION_CLEANUP_FAILED)?;
+            retained_paths.push(sstable_paths[0].clone());
+        }
+        for path in &sstable_paths[retained_paths.len()..] {
+            backend
+                .remove(path)
+                .map_err(|_| Errors::COMPACTION_CLEANUP_FAILED)?;
+        }
+        if self.options.sync_tables {
+            self.sync_table_dir()?;
+        }
+        self.block_cache.lock().unwrap().clear();
+        self.index = SparseIndex::new();
+        for path in &retained_paths {
+            Persistence::populate_index_from_path::<V>(&self.options, path, &mut self.index)?;
+        }
+        self.last_compaction = Some(platform::now());
+        Ok(())
+    }
+
+    /// Rewrite the SSTables and the Write Ahead Log with blocks of the supplied size.
+    /// Tables are read with the block size recorded in their footer, or the block size
+    /// recorded in the manifest for tables written before the footer recorded it. Each
+    /// table is written next to the table and renamed over it so every table is
+    /// complete at all times, and the log is replaced in the same way. Tables and the
+    /// log record their block size so a rewrite interrupted part way leaves a database
+    /// that can be read. The manifest records the new block size once all tables are
+    /// rewritten.
+    ///
+    /// # Arguments
+    ///  - _block_size_ - The size in bytes of the blocks of the rewritten tables.
+    ///
+    /// # Returns
+    /// Result that signifies:
+    ///  - _Ok_ - If the tables and the log were rewritten.
+    ///  - _Err_ - Error that occurred while rewriting the tables or the log.
+    pub fn rewrite_with_block_size<V: ResourceValue>(&mut self, block_size: usize) -> Result<()> {
+        self.check_writable()?;
+        if block_size <= Record::RECORD_BASE_SIZE_IN_BYTES {
+            return Err(Errors::DB_INVALID_BLOCK_SIZE);
+        }
+        let stored_block_size = match Manifest::read(&self.options)? {
+            Some(manifest) => manifest.block_size(&self.options),
+            None => self.options.block_size_in_bytes,
+        };
+        let mut options = self.options.clone();
+        options.block_size_in_bytes = block_size;
+        let backend = self.options.backend.as_ref();
+        let sstable_paths = SSTableReader::get_table_paths(&self.options, &self.options.path)?;
+        let rewrite_path = self.options.tables_path().join("rewrite.tmp");
+        for path in &sstable_paths {
+            let mut reader = SSTableReader::with_value_log(
+                backend,
+                path,
+                stored_block_size,
+                &ValueLog::path(&self.options),
+            )?;
+            reader.set_value_transform(self.options.value_transform.clone());
+            reader.set_record_codec(self.options.record_codec.clone());
+            let mut values: Vec<Value<K, V>> = Vec::new();
+            let mut tombstones = Vec::new();
+            while reader.has_next() {
+                let sstable_value = reader.read();
+                if sstable_value.range_tombstone {
+                    tombstones.push(sstable_value.to_range_tombstone::<K>()?);
+                } else {
+                    values.push(sstable_value.to_record::<K, V>()?);
+                }
+                reader.next();
+            }
+            if reader.read_failed() {
+                return Err(Errors::SSTABLE_READ_FAILED);
+            }
+            write_table_at_path(&options, &values, &tombstones, &rewrite_path)?;
+            move_file(backend, &rewrite_path, path)
+                .map_err(|_| Errors::COMPACTION_CLEANUP_FAILED)?;
+        }
+        if self.options.sync_tables {
+            self.sync_table_dir()?;
+        }
+        // unflushed entries are logged again in blocks of the new size
+        let (values, tombstones) = self.read_wal_entries::<V>()?;
+        self.log = Some(WriteAheadLog::replace(
+            options.clone(),
+            &values,
+            &tombstones,
+        )?);
+        self.block_cache.lock().unwrap().clear();
+        self.index = SparseIndex::new();
+        for path in &sstable_paths {
+            Persistence::populate_index_from_path::<V>(&options, path, &mut self.index)?;
+        }
+        self.compaction = BasicCompaction::new(BasicCompactionOpts::from(options.clone()));
+        self.options = options;
+        self.write_manifest()
+    }
+
+    /// Rebuild the manifest of the database at the configured path from the SSTables
+    /// in the `tables` directory. The footer of every table is read first so that
+    /// tables in an unsupported format are rejected without being scanned. Every table
+    /// is then scanned to check that it holds the records counted in its footer
+    /// before a fresh manifest replaces a lost or corrupt one.
+    /// A database sealed by `seal` stays sealed. The Write Ahead Log is left untouched.
+    ///
+    /// # Arguments
+    ///  - _options_ - The database config.
+    ///
+    /// # Returns
+    /// Result that resolves:
+    ///  - _Ok_ - If all tables could be read and the manifest was written.
+    ///  - _Err_ - Error that occurred while reading the tables or writing the manifest.
+    pub fn repair<V: ResourceValue>(options: DharmaOpts) -> Result<()> {
+        let sstable_paths = SSTableReader::get_table_paths(&options, &options.path)?;
+        let mut last_sequence = 0;
+        for path in sstable_paths {
+            let footer = SSTableReader::read_footer_with_backend(options.backend.as_ref(), &path)
+                .map_err(|_| Errors::DB_REPAIR_FAILED)?;
+            if footer.format_version < MIN_TABLE_FORMAT_VERSION
+                || footer.format_version > FORMAT_VERSION
+            {
+                return Err(Errors::DB_REPAIR_FAILED);
+            }
+            let mut reader = SSTableReader::with_options(&options, &path)
+                .map_err(|_| Errors::DB_REPAIR_FAILED)?;
+            let mut records = 0;
+            let mut max_sequence = 0;
+            let mut key_range: Option<(K, K)> = None;
+            while reader.has_next() {
+                let sstable_value = reader.read();
+                if sstable_value.range_tombstone {
+                    let tombstone = sstable_value
+                        .to_range_tombstone::<K>()
+                        .map_err(|_| Errors::DB_REPAIR_FAILED)?;
+                    max_sequence = std::cmp::max(max_sequence, tombstone.seq);
+                } else {
+                    let record = sstable_value
+                        .to_record::<K, V>()
+                        .map_err(|_| Errors::DB_REPAIR_FAILED)?;
+                    max_sequence = std::cmp::max(max_sequence, record.seq);
+                    key_range = match key_range {
+                        None => Some((record.key.clone(), record.key)),
+                        Some((first, _)) => Some((first, record.key)),
+                    };
+                }
+                records += 1;
+                reader.next();
+            }
+            // tables written before the summary was recorded have no record count
+            let truncated = footer.records.map_or(false, |expected| expected != records);
+            if reader.read_failed() || truncated {
+                return Err(Errors::DB_REPAIR_FAILED);
+            }
+            last_sequence = std::cmp::max(last_sequence, max_sequence);
+            match key_range {
+                Some((first, last)) => options.log_info(&format!(
+                    "Repaired table {} with {} records from {} to {} up to sequence {}",
+                    path.display(),
+                    records,
+                    first,
+                    last,
+                    max_sequence
+                )),
+                None => options.log_info(&format!(
+                    "Repaired table {} with {} records",
+                    path.display(),
+                    records
+                )),
+            }
+        }
+        // the seal is kept from a readable manifest or else from the marker of `seal`
+        let sealed = match Manifest::read(&options) {
+            Ok(Some(manifest)) => manifest.sealed,
+            _ => false,
+        } || Manifest::is_marked_sealed(&options);
+        Manifest {
+            last_sequence,
+            sealed,
+            ..Manifest::with_options(&options)
+        }
+        .write(&options)
+    }
+
+    /// Attempt to recover data from existing WAL. This operation does not ensure
+    /// database recovery and could lead to data loss. WAL is deleted after
+    /// this operation.
+    pub fn recover<T: ResourceKey, U: ResourceValue>(options: DharmaOpts) -> Result<Vec<(T, U)>> {
+        return WriteAheadLog::recover(options);
+    }
+
+    /// Attempt to recover the values along with their sequence numbers from the
+    /// existing WAL. See `recover`.
+    pub fn recover_values<T: ResourceKey, U: ResourceValue>(
+        options: DharmaOpts,
+    ) -> Result<Vec<Value<T, U>>> {
+        return WriteAheadLog::recover_values(options);
+    }
+
+    /// Attempt to recover the values and range tombstones from the existing WAL.
+    /// See `recover`.
+    pub fn recover_entries<T: ResourceKey, U: ResourceValue>(
+        options: DharmaOpts,
+    ) -> Result<(Vec<Value<T, U>>, Vec<RangeTombstone<T>>)> {
+        return WriteAheadLog::recover_entries(options);
+    }
+
+    /// Attempt to recover the operations from the existing WAL in the order they
+    /// were written. See `recover`.
+    pub fn recover_operations<T: ResourceKey, U: ResourceValue>(
+        options: DharmaOpts,
+    ) -> Result<Vec<WalOperation<T, U>>> {
+        return WriteAheadLog::recover_operations(options);
+    }
+
+    /// Set the existing WAL aside and read its operations, see
+    /// `WriteAheadLog::begin_recovery`.
+    pub fn begin_recovery<T: ResourceKey, U: ResourceValue>(
+        options: DharmaOpts,
+    ) -> Result<Vec<WalOperation<T, U>>> {
+        return WriteAheadLog::begin_recovery(options);
+    }
+
+    /// Check whether a WAL set aside by `begin_recovery` still has to be recovered.
+    pub fn is_recovering(options: &DharmaOpts) -> bool {
+        WriteAheadLog::is_recovering(options)
+    }
+
+    /// Delete the WAL set aside by `begin_recovery` once the recovered operations
+    /// are durable.
+    pub fn finish_recovery(options: DharmaOpts) -> Result<()> {
+        return WriteAheadLog::finish_recovery(options);
+    }
+
+    /// Read the values along with their sequence numbers from the current WAL
+    /// without deleting it.
+    pub fn read_wal_values<V: ResourceValue>(&self) -> Result<Vec<Value<K, V>>> {
+        return WriteAheadLog::read_values(self.options.clone());
+    }
+
+    /// Read the values and range tombstones from the current WAL without deleting it.
+    pub fn read_wal_entries<V: ResourceValue>(
+        &self,
+    ) -> Result<(Vec<Value<K, V>>, Vec<RangeTombstone<K>>)> {
+        return WriteAheadLog::read_entries(self.options.clone());
+    }
+
+    pub fn delete(&mut self, key: &K) -> Result<()> {
+        // add delete marker to Write Ahead Log
+        unimplemented!()
+    }
+
+    /// Get the paths of the tables reads are served from, the mounted tables if only a
+    /// subset of the tables was opened and otherwise every table of the database.
+    fn table_paths(&self) -> Result<Vec<PathBuf>> {
+        match &self.mounted_tables {
+            Some(tables) => Ok(tables.clone()),
+            None => SSTableReader::get_table_paths(&self.options, &self.options.path),
+        }
+    }
+
+    /// Check whether the database accepts writes. Sealed databases never do and
+    /// tables written or merged while only a subset of the tables is mounted would
+    /// be numbered and compacted without regard for the tables that were left out.
+    ///
+    /// # Returns
+    /// Result that signifies:
+    ///  - _Ok_ - If writes are accepted.
+    ///  - _Err_ - `DB_SEALED` or `DB_PARTIALLY_MOUNTED` if writes are rejected.
+    pub fn check_writable(&self) -> Result<()> {
+        if self.sealed {
+            self.options.log_warn("Rejecting write to sealed database");
+            return Err(Errors::DB_SEALED);
+        }
+        if self.mounted_tables.is_some() {
+            self.options
+                .log_warn("Rejecting write to partially mounted database");
+            return Err(Errors::DB_PARTIALLY_MOUNTED);
+        }
+        Ok(())
+    }
+
+    fn check_table_limit(&self, table_count: usize) -> Result<()> {
+        match self.options.max_sstables {
+            Some(max_sstables) if table_count >= max_sstables => {
+                self.options
+                    .log_warn(&format!("Rejecting flush with {} SSTables", table_count));
+                Err(Errors::DB_TOO_MANY_TABLES)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Read back the SSTable at the path and check that it holds as many records as
+    /// the flushed values and tombstones and that the keys sampled for the index match.
+    fn verify_table<V: ResourceValue>(
+        &self,
+        path: &PathBuf,
+        values: &[Value<K, V>],
+        tombstones: &[RangeTombstone<K>],
+    ) -> Result<()> {
+        let mut reader = SSTableReader::with_options(&self.options, path)?;
+        let sampling_rate = self.options.sparse_index_sampling_rate as usize;
+        let mut count = 0;
+        while reader.has_next() {
+            // range tombstones are stored after the values
+            if count < values.len() && count % sampling_rate == 0 {
+                let record = reader
+                    .read()
+                    .to_record::<K, V>()
+                    .map_err(|_| Errors::FLUSH_VERIFICATION_FAILED)?;
+                let matches = values
+                    .get(count)
+                    .map_or(false, |value| value.key == record.key);
+                if !matches {
+                    return Err(Errors::FLUSH_VERIFICATION_FAILED);
+                }
+            }
+            count += 1;
+            reader.next();
+        }
+        if count != values.len() + tombstones.len() {
+            return Err(Errors::FLUSH_VERIFICATION_FAILED);
+        }
+        Ok(())
+    }
+
+    /// Find the newest version of the key written at or before the sequence number
+    /// by scanning the table at the path starting at the supplied offset.
+    fn find_in_table<V: ResourceValue>(
+        &self,
+        path: &PathBuf,
+        offset: usize,
+        key: &K,
+        at_seq: u64,
+    ) -> Result<Option<Value<K, V>>> {
+        Persistence::find_in_table_with::<V>(
+            &self.options,
+            &self.block_cache,
+            &self.blocks_read,
+            path,
+            offset,
+            key,
+            at_seq,
+        )
+    }
+
+    /// Find the newest version of the key like `find_in_table` without borrowing the
+    /// persistence layer so that tables can be scanned from other threads.
+    fn find_in_table_with<V: ResourceValue>(
+        options: &DharmaOpts,
+        block_cache: &Arc<Mutex<BlockCache>>,
+        blocks_read: &AtomicUsize,
+        path: &PathBuf,
+        offset: usize,
+        key: &K,
+        at_seq: u64,
+    ) -> Result<Option<Value<K, V>>> {
+        let mut reader = SSTableReader::with_options(&options, path)?;
+        reader.set_block_cache(block_cache.clone());
+        // try to find the value in the sstable
+        let seek_result = reader.seek_closest(offset);
+        // if seek offset is invalid then return error
+        // this should never happen as long as SSTables and Sparse Index are in sync
+        let mut found: Option<Value<K, V>> = None;
+        if seek_result.is_ok() {
+            while reader.has_next() {
+                let sstable_value = reader.read();
+                // range tombstones are stored after all values of the table
+                if sstable_value.range_tombstone {
+                    break;
+                }
+                let record =
+                    match Persistence::<K>::decode_record::<V>(options, path, &sstable_value)? {
+                        Some(record) => record,
+                        None => {
+                            reader.next();
+                            continue;
+                        }
+                    };
+                match record.key.cmp(key) {
+                    Ordering::Less => {}
+                    Ordering::Equal => {
+                        // the newest version within the sequence wins and
+                        // later values win among values with the same sequence
+                        let newer = found.as_ref().map_or(true, |v| record.seq >= v.seq);
+                        if record.seq <= at_seq && newer {
+                            found = Some(record);
+                        }
+                    }
+                    Ordering::Greater => {
+                        break;
+                    }
+                }
+                reader.next();
+            }
+        }
+        blocks_read.fetch_add(reader.blocks_read(), AtomicOrdering::SeqCst);
+        Ok(found)
+    }
+
+    /// Deserialize a record read from the table at the path. The location of corrupt
+    /// records is logged and they are skipped when `skip_corrupt_records` is set.
+    ///
+    /// # Returns
+    /// Result that resolves:
+    ///  - _Ok_ - Optional that contains the record unless it was skipped.
+    ///  - _Err_ - `RECORD_DESERIALIZATION_FAILED` if the record is corrupt or
+    ///    `SSTABLE_READ_FAILED` if a block of the record couldn't be read.
+    fn decode_record<V: ResourceValue>(
+        options: &DharmaOpts,
+        path: &PathBuf,
+        sstable_value: &SSTableValue,
+    ) -> Result<Option<Value<K, V>>> {
+        match sstable_value.to_record::<K, V>() {
+            Ok(record) => Ok(Some(record)),
+            // a block that failed to load fails the read instead of skipping the record
+            Err(Errors::SSTABLE_READ_FAILED) => Err(Errors::SSTABLE_READ_FAILED),
+            Err(err) => {
+                options.log_error(&format!(
+                    "Corrupt record in SSTable {} at offset {}",
+                    path.display(),
+                    sstable_value.offset
+                ));
+                if options.skip_corrupt_records {
+                    return Ok(None);
+                }
+                Err(err)
+            }
+        }
+    }
+
+    /// Add sampled keys and range tombstones of the SSTable at the path to the index.
+    /// The first key of the table is sampled unless the index is coarsened to stay
+    /// within `max_index_entries` and a sample never lands on an older version of a key
+    /// so that lookups always start at the newest version.
+    ///
+    /// # Returns
+    /// The largest sequence number of the values and tombstones in the table or
+    /// `UNSORTED_TABLE` if `validate_tables_on_open` is set and the table isn't sorted.
+    fn populate_index_from_path<V: ResourceValue>(
+        options: &DharmaOpts,
+        path: &PathBuf,
+        index: &mut SparseIndex<K>,
+    ) -> Result<u64> {
+        let mut counter = 0;
+        let mut last_sequence = 0;
+        let mut sample_pending = false;
+        let mut first_key: Option<K> = None;
+        let mut previous_key: Option<K> = None;
+        let maybe_reader = SSTableReader::with_options(&options, path);
+        if maybe_reader.is_ok() {
+            let mut reader = maybe_reader.unwrap();
+            while reader.has_next() {
+                let sstable_value: SSTableValue = reader.read();
+                if sstable_value.range_tombstone {
+                    let tombstone = sstable_value.to_range_tombstone::<K>()?;
+                    last_sequence = std::cmp::max(last_sequence, tombstone.seq);
+                    index.add_range_tombstone(path, tombstone);
+                    reader.next();
+                    continue;
+                }
+                // only the key and sequence number are needed so values aren't decoded
+                let record_key: K = sstable_value.to_key()?;
+                let seq = match sstable_value.to_seq::<K>()? {
+                    Some(seq) => seq,
+                    None => sstable_value.to_record::<K, V>()?.seq,
+                };
+                last_sequence = std::cmp::max(last_sequence, seq);
+                let sampling_rate = options
+                    .sparse_index_sampling_rate
+                    .saturating_mul(index.coarsening());
+                sample_pending = sample_pending || counter % sampling_rate == 0;
+                if options.validate_tables_on_open
+                    && previous_key
+                        .as_ref()
+                        .map_or(false, |previous| previous > &record_key)
+                {
+                    return Err(Errors::UNSORTED_TABLE { path: path.clone() });
+                }
+                let is_new_key = previous_key.as_ref() != Some(&record_key);
+                if sample_pending && is_new_key {
+                    let key = record_key.clone();
+                    let offset = sstable_value.offset;
+                    let address = TableAddress::new(path, offset);
+                    index.update(key, address);
+                    sample_pending = false;
+                    if let Some(max_entries) = options.max_index_entries {
+                        // coarsen the stride until the index fits within the cap
+                        while index.len() > max_entries.max(1) {
+                            index.coarsen();
+                        }
+                    }
+                }
+                if first_key.is_none() {
+                    first_key = Some(record_key.clone());
+                }
+                previous_key = Some(record_key);
+                counter += 1;
+                reader.next();
+            }
+            if let (Some(first), Some(last)) = (first_key, previous_key) {
+                index.update_table_range(path, first, last);
+            }
+            return Ok(last_sequence);
+        }
+        Err(Errors::DB_INDEX_UPDATE_FAILED)
+    }
+
+    /// Compact the tables if the compaction threshold is reached and update the index.
+    ///
+    /// # Arguments
+    ///  - _flushed_paths_ - The paths of the tables that were just flushed and are
+    ///    not indexed yet.
+    ///
+    /// # Returns
+    /// Result that resolves:
+    ///  - _Ok_ - Whether tables were compacted, the flushed tables are indexed if so.
+    ///  - _Err_ - Error that occurred while swapping or indexing the compacted tables.
+    ///    The index is rebuilt from the tables on disk when the swap failed.
+    fn compact_tables<V: ResourceValue>(&mut self, flushed_paths: &Vec<PathBuf>) -> Result<bool> {
+        let _compacting = ActivityGuard::start(&self.activity.compacting);
+        // clusters of tables with disjoint key ranges are compacted concurrently
+        let compaction_result = self.compaction.compact_concurrently::<K, V>();
+        if compaction_result.is_err() {
+            self.options
+                .log_error("Failed to compact tables, the tables are left in place");
+        }
+        if let Ok(compacted_tables) = compaction_result {
+            if !compacted_tables.is_empty() {
+                // remove merged sstables and replace them with compacted tables
+                let swap_result = self.swap_sstables_with_compacted_tables(&compacted_tables);
+                let (swapped_tables, moved_paths) = match swap_result {
+                    Ok(swapped) => swapped,
+                    Err(err) => {
+                        self.options
+                            .log_error("Failed to swap compacted tables, rebuilding the index");
+                        self.block_cache.lock().unwrap().clear();
+                        self.resample_index::<V>(self.options.sparse_index_sampling_rate)?;
+                        return Err(err);
+                    }
+                };
+                if !swapped_tables.is_empty() {
+                    self.last_compaction = Some(platform::now());
+                    let (compacted, merged) = swapped_tables.iter().fold(
+                        (0, 0),
+                        |(compacted, merged), (compacted_paths, merged_paths)| {
+                            (
+                                compacted + compacted_paths.len(),
+                                merged + merged_paths.len(),
+                            )
+                        },
+                    );
+                    self.options.log_info(&format!(
+                        "Compacted {} tables into {} tables",
+                        merged, compacted
+                    ));
+                    self.block_cache.lock().unwrap().clear();
+                    let merged_ranges = swapped_tables
+                        .iter()
+                        .flat_map(|(_, merged_paths)| merged_paths)
+                        .filter_map(|merged_path| self.index.get_table_range(merged_path))
+                        .collect();
+                    let compacted_paths = self.update_index_after_compaction::<V>(
+                        &swapped_tables,
+                        &moved_paths,
+                        flushed_paths,
+                    )?;
+                    self.record_compacted_keys::<V>(merged_ranges, &compacted_paths)?;
+                    return Ok(true);
+                }
+            }
+        }
+        Ok(false)
+    }
+
+    /// Update the index after the merged tables were replaced by the compacted tables.
+    /// Entries of the merged tables are removed, entries of the other tables follow
+    /// them to their new paths and the compacted tables are indexed along with the
+    /// flushed table if it wasn't merged. Tables that were not merged aren't read.
+    ///
+    /// # Returns
+    /// Result that resolves:
+    ///  - _Ok_ - The paths of the compacted tables after the swap.
+    ///  - _Err_ - Error that occurred while indexing the compacted tables.
+    fn update_index_after_compaction<V: ResourceValue>(
+        &mut self,
+        swapped_tables: &Vec<(Vec<PathBuf>, Vec<PathBuf>)>,
+        moved_paths: &Vec<(PathBuf, PathBuf)>,
+        flushed_paths: &Vec<PathBuf>,
+    ) -> Result<Vec<PathBuf>> {
+        for (_, merged_paths) in swapped_tables {
+            for merged_path in merged_paths {
+                self.index.remove_table(merged_path);
+            }
+        }
+        let mut compacted_paths = Vec::new();
+        let mut unindexed_paths = Vec::new();
+        // tables move to lower numbers in order so a table is never moved onto an indexed path
+        for (from, to) in moved_paths {
+            // the first table compacted from each cluster was moved over the first
+            // table it merged, the others were moved from their temporary paths
+            let compacted = swapped_tables
+                .iter()
+                .any(|(compacted_paths, merged_paths)| {
+                    merged_paths.first() == Some(from) || compacted_paths[1..].contains(from)
+                });
+            if compacted {
+                compacted_paths.push(to.clone());
+                unindexed_paths.push(to.clone());
+            } else if flushed_paths.contains(from) {
+                // only the key range of a flushed table was indexed
+                self.index.remove_table(from);
+                unindexed_paths.push(to.clone());
+            } else if from != to {
+                self.index.rename_table(from, to);
+            }
+        }
+        for table_path in unindexed_paths {
+            Persistence::populate_index_from_path::<V>(
+                &self.options,
+                &table_path,
+                &mut self.index,
+            )?;
+        }
+        Ok(compacted_paths)
+    }
+
+    /// Record the live keys of the compacted tables along with the key ranges of the
+    /// tables they merged when the key set could be missing keys the compaction
+    /// filter dropped, see `take_compacted_keys`.
+    ///
+    /// # Arguments
+    ///  - _merged_ranges_ - The key ranges of the merged tables.
+    ///  - _compacted_paths_ - The paths of the compacted tables.
+    ///
+    /// # Returns
+    /// Result that resolves:
+    ///  - _Ok_ - If the keys were recorded or don't need to be.
+    ///  - _Err_ - `SSTABLE_READ_FAILED` if the compacted tables couldn't be read.
+    fn record_compacted_keys<V: ResourceValue>(
+        &mut self,
+        merged_ranges: Vec<(K, K)>,
+        compacted_paths: &Vec<PathBuf>,
+    ) -> Result<()> {
+        if !self.options.keep_key_set || self.options.compaction_filter.is_none() {
+            return Ok(());
+        }
+        let merged = self
+            .compaction
+            .merge_latest_iter::<K, V>(compacted_paths)
+            .map_err(|_| Errors::SSTABLE_READ_FAILED)?;
+        let mut live_keys = BTreeSet::new();
+        for entry in merged {
+            let (key, value) = entry.map_err(|_| Errors::SSTABLE_READ_FAILED)?;
+            if value != V::nil() {
+                live_keys.insert(key);
+            }
+        }
+        self.compacted_keys.push(CompactedKeys {
+            merged_ranges,
+            live_keys,
+        });
+        Ok(())
+    }
+
+    /// Replace the merged tables of each compaction with the compacted tables and
+    /// renumber the tables so that they remain in order. See `replace_merged_tables`.
+    /// The tables of a compaction that can't be swapped are left intact and its
+    /// compacted tables are removed while the other compactions are still applied.
+    /// When a cluster was compacted into several tables the first one takes the place
+    /// of the first merged table and the others are numbered after all other tables.
+    /// Clusters hold key ranges that no other table overlaps so their position
+    /// doesn't affect which value of a key is read.
+    ///
+    /// # Returns
+    /// Result that resolves:
+    ///  - _Ok_ - The paths of the tables compacted and merged by each applied compaction
+    ///    along with the path of each table before and after the swap in order. The first
+    ///    compacted table is listed under the path of the first table it merged. Empty if
+    ///    no compaction could be applied.
+    ///  - _Err_ - Error if the tables of an applied compaction couldn't be removed or
+    ///    renumbered, the tables on disk no longer match the index then.
+    fn swap_sstables_with_compacted_tables(
+        &mut self,
+        compacted_tables: &Vec<(Vec<PathBuf>, Vec<PathBuf>)>,
+    ) -> Result<(Vec<(Vec<PathBuf>, Vec<PathBuf>)>, Vec<(PathBuf, PathBuf)>)> {
+        let sstable_paths = SSTableReader::get_table_paths(&self.options, &self.options.path)?;
+        let mut swapped_tables = Vec::new();
+        for (compacted_paths, merged_paths) in compacted_tables {
+            let replaced = match compacted_paths.first() {
+                Some(compacted_path) => self.replace_merged_tables(compacted_path, merged_paths),
+                None => Err(Errors::COMPACTION_CLEANUP_FAILED),
+            };
+            if replaced.is_ok() {
+                // the other merged tables would shadow newer values once they are renumbered
+                self.remove_merged_tables(merged_paths)?;
+                swapped_tables.push((compacted_paths.clone(), merged_paths.clone()));
+                continue;
+            }
+            self.options
+                .log_error("Failed to replace merged tables with the compacted table");
+            for compacted_path in compacted_paths {
+                if self.options.backend.remove(compacted_path).is_err() {
+                    self.options
+                        .log_warn("Failed to remove the compacted table");
+                }
+            }
+        }
+        if swapped_tables.is_empty() {
+            return Ok((swapped_tables, Vec::new()));
+        }
+        let ordered_paths: Vec<PathBuf> = sstable_paths
+            .into_iter()
+            .filter(|table_path| {
+                !swapped_tables
+                    .iter()
+                    .any(|(_, merged_paths)| merged_paths[1..].contains(table_path))
+            })
+            .chain(
+                swapped_tables
+                    .iter()
+                    .flat_map(|(compacted_paths, _)| compacted_paths[1..].to_vec()),
+            )
+            .collect();
+        let moved_paths = self.renumber_tables(&ordered_paths)?;
+        Ok((swapped_tables, moved_paths))
+    }
+
+    /// Replace the first merged table with the compacted table. The compacted table is
+    /// renamed over the first merged table which atomically replaces it, the other
+    /// merged tables are removed after that, see `remove_merged_tables`. A compacted table
+    /// written to another file system is first copied next to the tables, see `move_file`.
+    /// The merged tables are left intact if the compacted table can't be moved into place.
+    fn replace_merged_tables(
+        &self,
+        compacted_path: &PathBuf,
+        merged_paths: &Vec<PathBuf>,
+    ) -> Result<()> {
+        let backend = self.options.backend.as_ref();
+        let replaced_path = merged_paths
+            .first()
+            .ok_or(Errors::COMPACTION_CLEANUP_FAILED)?;
+        // the replaced table is overwritten so it has to be retained before the swap
+        let retained_path = if self.options.compaction_retain_inputs {
+            Some(self.move_to_trash(replaced_path)?)
+        } else {
+            None
+        };
+        if move_file(backend, compacted_path, replaced_path).is_err() {
+            // put the retained table back so that the merged tables remain intact
+            if let Some(retained_path) = retained_path {
+                if backend.rename(&retained_path, replaced_path).is_err() {
+                    self.options
+                        .log_error("Failed to restore the merged table from the trash");
+                }
+            }
+            return Err(Errors::COMPACTION_CLEANUP_FAILED);
+        }
+        Ok(())
+    }
+
+    /// Remove the merged tables following the first one after it was replaced by the
+    /// compacted table, or move them to the trash when `compaction_retain_inputs` is set.
+    /// The swap is synced first when `sync_tables` is set so that a crash never finds
+    /// the tables removed without the compacted table in their place.
+    ///
+    /// # Returns
+    /// Result that resolves:
+    ///  - _Ok_ - If all merged tables were removed.
+    ///  - _Err_ - `SSTABLE_SYNC_FAILED` if the swap couldn't be synced or
+    ///    `COMPACTION_CLEANUP_FAILED` if a merged table couldn't be removed.
+    fn remove_merged_tables(&self, merged_paths: &Vec<PathBuf>) -> Result<()> {
+        if self.options.sync_tables {
+            self.sync_table_dir()?;
+        }
+        for table_path in merged_paths.iter().skip(1) {
+            if self.options.compaction_retain_inputs {
+                self.move_to_trash(table_path)?;
+            } else {
+                self.options
+                    .backend
+                    .remove(table_path)
+                    .map_err(|_| Errors::COMPACTION_CLEANUP_FAILED)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Move the table at the path to the trash directory. Tables in the trash are
+    /// named by the time at which they were moved followed by their previous name.
+    ///
+    /// # Returns
+    /// The path of the table in the trash.
+    fn move_to_trash(&self, table_path: &PathBuf) -> Result<PathBuf> {
+        let backend = self.options.backend.as_ref();
+        let trash_path = self.trash_path();
+        if !backend.exists(&trash_path) {
+            backend
+                .create_dir_all(&trash_path)
+                .map_err(|_| Errors::COMPACTION_CLEANUP_FAILED)?;
+        }
+        let moved_at = platform::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(|_| Errors::COMPACTION_CLEANUP_FAILED)?
+            .as_millis();
+        let file_name = table_path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or(Errors::COMPACTION_CLEANUP_FAILED)?;
+        let retained_path = trash_path.join(format!("{}-{}", moved_at, file_name));
+        backend
+            .rename(table_path, &retained_path)
+            .map_err(|_| Errors::COMPACTION_CLEANUP_FAILED)?;
+        Ok(retained_path)
+    }
+
+    fn trash_path(&self) -> PathBuf {
+        self.options.tables_path().join("trash")
+    }
+
+    /// Delete the tables that were moved to the trash by compaction at least
+    /// `older_than` ago. See `DharmaOpts::compaction_retain_inputs`.
+    ///
+    /// # Arguments
+    ///  - _older_than_ - The minimum time tables have to spend in the trash.
+    ///
+    /// # Returns
+    /// Result that resolves:
+    ///  - _Ok_ - The number of tables that were deleted.
+    ///  - _Err_ - Error that occurred while deleting tables.
+    pub fn purge_trash(&mut self, older_than: Duration) -> Result<usize> {
+        let backend = self.options.backend.as_ref();
+        let trash_path = self.trash_path();
+        let table_paths = match backend.list_dir(&trash_path) {
+            Ok(table_paths) => table_paths,
+            // nothing was moved to the trash yet
+            Err(_) if !backend.exists(&trash_path) => return Ok(0),
+            Err(_) => return Err(Errors::COMPACTION_CLEANUP_FAILED),
+        };
+        let now = platform::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(|_| Errors::COMPACTION_CLEANUP_FAILED)?
+            .as_millis();
+        let mut purged = 0;
+        for table_path in table_paths {
+            let moved_at = table_path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.split('-').next())
+                .and_then(|moved_at| moved_at.parse::<u128>().ok());
+            // files that weren't moved to the trash by compaction are left alone
+            let expired = moved_at.map_or(false, |moved_at| {
+                now.saturating_sub(moved_at) >= older_than.as_millis()
+            });
+            if expired {
+                backend
+                    .remove(&table_path)
+                    .map_err(|_| Errors::COMPACTION_CLEANUP_FAILED)?;
+                purged += 1;
+            }
+        }
+        Ok(purged)
+    }
+
+    /// Delete the temporary tables written by compactions and range rewrites that
+    /// were interrupted before their output replaced the tables they merge.
+    ///
+    /// # Returns
+    /// Result that resolves:
+    ///  - _Ok_ - The number of files that were deleted.
+    ///  - _Err_ - Error that occurred while deleting files.
+    pub fn gc_temp_files(&self) -> Result<usize> {
+        Persistence::<K>::remove_temp_files(&self.options)
+    }
+
+    fn remove_temp_files(options: &DharmaOpts) -> Result<usize> {
+        let backend = options.backend.as_ref();
+        let tables_path = options.tables_path();
+        let paths = match backend.list_dir(&tables_path) {
+            Ok(paths) => paths,
+            // nothing was flushed yet
+            Err(_) if !backend.exists(&tables_path) => return Ok(0),
+            Err(_) => return Err(Errors::COMPACTION_CLEANUP_FAILED),
+        };
+        let mut removed = 0;
+        for path in paths {
+            // temporary tables are named `*.tmp` or `*.tmp.<job>` for concurrent compactions
+            // suffixed with `.part<n>` when a compaction is split into several tables
+            let is_temp = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .map_or(false, |name| {
+                    name.ends_with(".tmp") || name.contains(".tmp.")
+                });
+            if is_temp {
+                options.log_warn(&format!("Removing orphaned temporary table {:?}", path));
+                backend
+                    .remove(&path)
+                    .map_err(|_| Errors::COMPACTION_CLEANUP_FAILED)?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Record the sequence number of the latest write in the manifest.
+    fn write_manifest(&self) -> Result<()> {
+        Manifest {
+            last_sequence: self.last_sequence,
+            sealed: self.sealed,
+            ..Manifest::with_options(&self.options)
+        }
+        .write(&self.options)
+    }
+
+    /// Get the Write Ahead Log the writes are logged to.
+    ///
+    /// # Returns
+    /// Result that resolves:
+    ///  - _Ok_ - The Write Ahead Log of the database.
+    ///  - _Err_ - `DB_PARTIALLY_MOUNTED` if the database was opened without a log.
+    fn log_mut(&mut self) -> Result<&mut WriteAheadLog> {
+        self.log.as_mut().ok_or(Errors::DB_PARTIALLY_MOUNTED)
+    }
+
+    /// Sync the directory holding the SSTables so that tables created or
+    /// renamed in it survive a crash.
+    fn sync_table_dir(&self) -> Result<()> {
+        let tables_path = self.options.tables_path();
+        self.options
+            .backend
+            .sync_dir(&tables_path)
+            .map_err(|_| Errors::SSTABLE_SYNC_FAILED)
+    }
+
+    /// Rename the tables so that they are numbered from zero in the supplied order.
+    ///
+    /// # Returns
+    /// The path of each table before and after it was renamed in order.
+    fn renumber_tables(&self, ordered_paths: &Vec<PathBuf>) -> Result<Vec<(PathBuf, PathBuf)>> {
+        let backend = self.options.backend.as_ref();
+        // tables only move to lower numbers so a rename never replaces a table still in use
+        let mut output = Vec::new();
+        for (table_number, table_path) in ordered_paths.iter().enumerate() {
+            let new_sstable_path = self.options.table_path(table_number);
+            if table_path != &new_sstable_path {
+                backend
+                    .rename(table_path, &new_sstable_path)
+                    .map_err(|_| Errors::COMPACTION_CLEANUP_FAILED)?;
+            }
+            output.push((table_path.clone(), new_sstable_path));
+        }
+        if self.options.sync_tables {
+            self.sync_table_dir()?;
+        }
+        Ok(output)
+    }
+}
+
+// Cleanup Persistence layer state before shutdown.
+impl<K> Drop for Persistence<K>
+where
+    K: ResourceKey,
+{
+    fn drop(&mut self) {
+        if !self.retain_log {
+            if let Some(log) = self.log.as_mut() {
+                if log.cleanup().is_err() {
+                    self.options
+                        .log_error("Failed to remove the Write Ahead Log on shutdown");
+                }
+            }
+        }
+    }
+}
+
+/// Merges the latest persisted version of each key across SSTables in key order,
+/// see `Persistence::scan_range`.
+pub struct PersistedRange<K, V> {
+    // readers of the merged tables ordered from oldest to newest
+    tables: Vec<SSTableReader>,
+    // next version read from each table that was not merged yet
+    heads: Vec<Option<(K, u64, LazyValue<V>)>>,
+    // key of the head of each table along with the index of the table
+    heap: BinaryHeap<Reverse<(K, usize)>>,
+    // exclusive upper bound of the merged keys
+    end: Option<K>,
+    // range tombstones of the persisted tables
+    tombstones: Vec<RangeTombstone<K>>,
+    // whether reading a table failed, the iterator ends after the error
+    failed: bool,
+}
+
+impl<K, V> PersistedRange<K, V>
+where
+    K: ResourceKey,
+    V: ResourceValue,
+{
+    fn new(
+        tables: Vec<SSTableReader>,
+        end: Option<K>,
+        tombstones: Vec<RangeTombstone<K>>,
+    ) -> Result<PersistedRange<K, V>> {
+        let mut range = PersistedRange {
+            heads: (0..tables.len()).map(|_| None).collect(),
+            tables,
+            heap: BinaryHeap::new(),
+            end,
+            tombstones,
+            failed: false,
+        };
+        for idx in 0..range.tables.len() {
+            let head = range.read_version(idx)?;
+            range.set_head(idx, head);
+        }
+        Ok(range)
+    }
+
+    /// Get the number of blocks loaded from the merged tables so far.
+    pub fn blocks_read(&self) -> usize {
+        self.tables.iter().map(SSTableReader::blocks_read).sum()
+    }
+
+    /// Read the version the table is at and advance past it. Range tombstones are
+    /// stored after the values of a table so reading stops at the first one.
+    fn read_version(&mut self, idx: usize) -> Result<Option<(K, u64, LazyValue<V>)>> {
+        let reader = &mut self.tables[idx];
+        if !reader.has_next() {
+            if reader.read_failed() {
+                return Err(Errors::SSTABLE_READ_FAILED);
+            }
+            return Ok(None);
+        }
+        let sstable_value = reader.read();
+        if reader.read_failed() {
+            return Err(Errors::SSTABLE_READ_FAILED);
+        }
+        if sstable_value.range_tombstone {
+            return Ok(None);
+        }
+        let key = sstable_value.to_key::<K>()?;
+        if self.end.as_ref().map_or(false, |end| &key >= end) {
+            return Ok(None);
+        }
+        let raw = match sstable_value.to_seq::<K>()? {
+            Some(seq) => sstable_value.to_raw::<K>().ok().map(|raw| (seq, raw.1)),
+            None => None,
+        };
+        let (seq, value) = match raw {
+            Some((seq, data)) => (
+                seq,
+                LazyValue::encoded(data, sstable_value.value_encoding()),
+            ),
+            None => {
+                let record = sstable_value.to_record::<K, V>()?;
+                (record.seq, LazyValue::decoded(record.value))
+            }
+        };
+        reader.next();
+        Ok(Some((key, seq, value)))
+    }
+
+    fn set_head(&mut self, idx: usize, head: Option<(K, u64, LazyValue<V>)>) {
+        if let Some((key, _, _)) = &head {
+            self.heap.push(Reverse((key.clone(), idx)));
+        }
+        self.heads[idx] = head;
+    }
+
+    /// Get the latest version of the next key that wasn't deleted.
+    fn next_version(&mut self) -> Result<Option<(K, u64, LazyValue<V>)>> {
+        while let Some(Reverse((key, idx))) = self.heap.pop() {
+            let mut sources = vec![idx];
+            while let Some(Reverse((next_key, next_idx))) = self.heap.peek() {
+                if next_key != &key {
+                    break;
+                }
+                sources.push(*next_idx);
+                self.heap.pop();
+            }
+            // newer tables win on equal sequence numbers like later values within a table
+            let mut latest: Option<(u64, usize, LazyValue<V>)> = None;
+            for idx in sources {
+                let mut head = self.heads[idx].take();
+                // versions of a key are stored next to each other within a table
+                while let Some((head_key, seq, value)) = head {
+                    if head_key != key {
+                        head = Some((head_key, seq, value));
+                        break;
+                    }
+                    let newer = latest.as_ref().map_or(true, |(latest_seq, latest_idx, _)| {
+                        seq > *latest_seq || (seq == *latest_seq && idx >= *latest_idx)
+                    });
+                    if newer {
+                        latest = Some((seq, idx, value));
+                    }
+                    head = self.read_version(idx)?;
+                }
+                self.set_head(idx, head);
+            }
+            if let Some((seq, _, value)) = latest {
+                let deleted = self
+                    .tombstones
+                    .iter()
+                    .any(|tombstone| tombstone.deletes(&key, seq, u64::MAX));
+                if !value.is_nil() && !deleted {
+                    return Ok(Some((key, seq, value)));
+                }
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl<K, V> Iterator for PersistedRange<K, V>
+where
+    K: ResourceKey,
+    V: ResourceValue,
+{
+    type Item = Result<(K, u64, LazyValue<V>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        match self.next_version() {
+            Ok(entry) => entry.map(Ok),
+            Err(error) => {
+                self.failed = true;
+                Some(Err(error))
+            }
+        }
     }
 }
@@ -0,0 +1,45 @@
+//! Clock and thread facilities of the platform. Without the `std` feature the
+//! database doesn't rely on them so that it runs on targets that provide
+//! neither, like `wasm32-unknown-unknown`.
+#[cfg(not(feature = "std"))]
+use std::sync::atomic::{AtomicU64, Ordering};
+#[cfg(not(feature = "std"))]
+use std::time::{Duration, UNIX_EPOCH};
+use std::time::{Instant, SystemTime};
+
+/// Get the current time of the system clock.
+#[cfg(feature = "std")]
+pub fn now() -> SystemTime {
+    SystemTime::now()
+}
+
+/// Get the current time of a logical clock that advances by a millisecond each
+/// time it is read, so that timestamps taken without a system clock stay ordered
+/// and distinct.
+#[cfg(not(feature = "std"))]
+pub fn now() -> SystemTime {
+    static READS: AtomicU64 = AtomicU64::new(0);
+    UNIX_EPOCH + Duration::from_millis(READS.fetch_add(1, Ordering::SeqCst) + 1)
+}
+
+/// Get the current instant of the monotonic clock.
+#[cfg(feature = "std")]
+pub fn instant() -> Option<Instant> {
+    Some(Instant::now())
+}
+
+// without a monotonic clock TTLs and flush intervals never elapse
+#[cfg(not(feature = "std"))]
+pub fn instant() -> Option<Instant> {
+    None
+}
+
+/// Get the number of threads to spread work across for the configured number.
+/// Work runs on the calling thread without the `std` feature.
+pub fn threads(configured: usize) -> usize {
+    if cfg!(feature = "std") {
+        configured
+    } else {
+        1
+    }
+}
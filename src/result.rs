@@ -1,6 +1,7 @@
 //! Errors, type aliases, and functions related to working with `Result`.
 
 use std::fmt::{Debug, Display, Formatter};
+use std::path::PathBuf;
 
 /// Result
 pub type Result<T> = std::result::Result<T, Errors>;
@@ -24,6 +25,35 @@ pub enum Errors {
     RECORD_SERIALIZATION_FAILED,
     RECORD_DESERIALIZATION_FAILED,
     COMPACTION_CLEANUP_FAILED,
+    MANIFEST_READ_FAILED,
+    MANIFEST_WRITE_FAILED,
+    MANIFEST_CORRUPT,
+    UNSUPPORTED_FORMAT_VERSION { found: u32, expected: u32 },
+    FLUSH_VERIFICATION_FAILED,
+    VALUE_LOG_WRITE_FAILED,
+    DB_REPAIR_FAILED,
+    DB_TOO_MANY_TABLES,
+    DB_STATS_FAILED,
+    SSTABLE_SYNC_FAILED,
+    DB_MEMTABLE_FULL,
+    DB_INVALID_SAMPLING_RATE,
+    DB_INVALID_BLOCK_SIZE,
+    BLOCK_WRITE_FAILED,
+    WAL_INVALID_READ_OFFSET,
+    WAL_FORMAT_MISMATCH,
+    DB_NOT_FOUND,
+    DB_PATH_CREATION_FAILED,
+    METADATA_READ_FAILED,
+    METADATA_WRITE_FAILED,
+    METADATA_CORRUPT,
+    UNSORTED_TABLE { path: PathBuf },
+    SSTABLE_STREAM_FAILED,
+    SSTABLE_BLOCK_SIZE_MISMATCH,
+    DB_PARTIALLY_MOUNTED,
+    DB_SEALED,
+    DELETION_AUDIT_WRITE_FAILED,
+    DELETION_AUDIT_READ_FAILED,
+    COMPACTION_FILTER_MISMATCH,
 }
 
 impl Errors {
@@ -54,18 +84,77 @@ impl Errors {
             Errors::RECORD_SERIALIZATION_FAILED => "Failed to serialize record.",
             Errors::RECORD_DESERIALIZATION_FAILED => "Failed to deserialize record.",
             Errors::COMPACTION_CLEANUP_FAILED => "Compaction cleanup failed.",
+            Errors::MANIFEST_READ_FAILED => "Failed to read the database manifest.",
+            Errors::FLUSH_VERIFICATION_FAILED => {
+                "Flushed SSTable does not match the values that were flushed."
+            }
+            Errors::VALUE_LOG_WRITE_FAILED => "Failed to write values to the value log.",
+            Errors::DB_TOO_MANY_TABLES => {
+                "Too many SSTables, writes that flush are rejected until compaction catches up."
+            }
+            Errors::DB_STATS_FAILED => "Failed to collect database statistics.",
+            Errors::SSTABLE_SYNC_FAILED => "Failed to sync the SSTable directory to disk.",
+            Errors::DB_INVALID_SAMPLING_RATE => "Sparse index sampling rate must be at least 1.",
+            Errors::DB_INVALID_BLOCK_SIZE => "Block size must be larger than a record header.",
+            Errors::BLOCK_WRITE_FAILED => "Failed to write block to storage.",
+            Errors::WAL_INVALID_READ_OFFSET => "Invalid read offset supplied to Write Ahead Log.",
+            Errors::WAL_FORMAT_MISMATCH => {
+                "Write Ahead Log was written in a different format than the configured one."
+            }
+            Errors::DB_NOT_FOUND => "No database found at the supplied path.",
+            Errors::DB_PATH_CREATION_FAILED => "Failed to create the database directory.",
+            Errors::DB_MEMTABLE_FULL => {
+                "The memtable is full, flush the database before writing more values."
+            }
+            Errors::DB_REPAIR_FAILED => {
+                "Failed to repair the database, an SSTable could not be read completely."
+            }
+            Errors::MANIFEST_WRITE_FAILED => "Failed to write the database manifest.",
+            Errors::MANIFEST_CORRUPT => "The database manifest is corrupt.",
+            Errors::METADATA_READ_FAILED => "Failed to read the database metadata.",
+            Errors::METADATA_WRITE_FAILED => "Failed to write the database metadata.",
+            Errors::METADATA_CORRUPT => "The database metadata is corrupt.",
+            Errors::UNSORTED_TABLE { .. } => "SSTable records are not sorted by key.",
+            Errors::SSTABLE_STREAM_FAILED => "Failed to stream SSTable records.",
+            Errors::DB_PARTIALLY_MOUNTED => {
+                "Database opened with a subset of its tables can't be written to."
+            }
+            Errors::DB_SEALED => "Database was sealed and can't be written to.",
+            Errors::DELETION_AUDIT_WRITE_FAILED => "Failed to write to the deletion audit table.",
+            Errors::DELETION_AUDIT_READ_FAILED => "Failed to read the deletion audit table.",
+            Errors::COMPACTION_FILTER_MISMATCH => {
+                "Compaction filter was registered for other key or value types than the database."
+            }
+            Errors::SSTABLE_BLOCK_SIZE_MISMATCH => {
+                "SSTable was written with a different block size than the one it was read with."
+            }
+            Errors::UNSUPPORTED_FORMAT_VERSION { .. } => {
+                "Data at supplied path was written in an unsupported format version."
+            }
         }
     }
 }
 
 impl Display for Errors {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.value())
+        match self {
+            Errors::UNSUPPORTED_FORMAT_VERSION { found, expected } => write!(
+                f,
+                "{} Found version {}, expected version {}.",
+                self.value(),
+                found,
+                expected
+            ),
+            Errors::UNSORTED_TABLE { path } => {
+                write!(f, "{} Table at {}.", self.value(), path.display())
+            }
+            _ => write!(f, "{}", self.value()),
+        }
     }
 }
 
 impl Debug for Errors {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.value())
+        write!(f, "{}", self)
     }
 }
@@ -1,4 +1,6 @@
+use crate::storage::block::RangeTombstone;
 use crate::traits::ResourceKey;
+use std::collections::BTreeMap;
 use std::fmt::Display;
 use std::path::PathBuf;
 use subway::skiplist::SkipList;
@@ -24,6 +26,12 @@ impl TableAddress {
 
 pub struct SparseIndex<K> {
     data: SkipList<K, TableAddress>,
+    // smallest and largest key of each indexed table in the order tables were indexed
+    table_ranges: Vec<(PathBuf, K, K)>,
+    // range tombstones stored in the indexed tables along with the table they are stored in
+    range_tombstones: Vec<(PathBuf, RangeTombstone<K>)>,
+    // factor the sampling rate is multiplied by after the index was coarsened
+    coarsening: u32,
 }
 
 impl<K> SparseIndex<K>
@@ -33,6 +41,9 @@ where
     pub fn new() -> SparseIndex<K> {
         SparseIndex {
             data: SkipList::new(),
+            table_ranges: Vec::new(),
+            range_tombstones: Vec::new(),
+            coarsening: 1,
         }
     }
 
@@ -42,6 +53,7 @@ where
     /// * _key_ - The key associated with the value.
     /// * - address_ - The TableAddress specifying where the is stored.
     pub fn update(&mut self, key: K, address: TableAddress) {
+        // the skiplist inserts equal keys after each other instead of replacing them
         if self.data.get(&key).is_some() {
             self.data.delete(&key);
         }
@@ -61,8 +73,264 @@ where
         return maybe_nearest_key.and_then(|nearest_key| self.data.get(&nearest_key));
     }
 
-    /// Reset the sparse index, Equivalent to creating a new index and using it.
-    pub fn reset(&mut self) {
-        self.data = SkipList::new();
+    /// Returns the offset of the largest key less than or equal to the target key
+    /// sampled from each table, so that every table can be scanned from the key.
+    /// Tables without such a sampled key are not included.
+    ///
+    /// # Arguments
+    /// * _key_ - The target key to compare against.
+    pub fn get_nearest_table_offsets(&self, key: &K) -> BTreeMap<PathBuf, usize> {
+        let mut offsets = BTreeMap::new();
+        for (sampled_key, address) in self.data.collect() {
+            if &sampled_key > key {
+                break;
+            }
+            offsets.insert(address.path, address.offset);
+        }
+        offsets
+    }
+
+    /// Count the number of sampled keys that fall within the range `[start, end)`.
+    ///
+    /// # Arguments
+    /// * _start_ - The inclusive lower bound of the range.
+    /// * _end_ - The exclusive upper bound of the range.
+    pub fn count_in_range(&self, start: &K, end: &K) -> usize {
+        self.data
+            .collect()
+            .iter()
+            .filter(|(key, _)| key >= start && key < end)
+            .count()
+    }
+
+    /// Get the number of sampled keys.
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Get the factor the sampling rate is multiplied by. Each coarsening of the
+    /// index doubles it.
+    pub fn coarsening(&self) -> u32 {
+        self.coarsening
+    }
+
+    /// Halve the number of sampled keys by dropping every other key and double
+    /// the coarsening of the index. Reads of keys whose sampled key was dropped
+    /// scan from the preceding sampled key instead.
+    pub fn coarsen(&mut self) {
+        for (key, _) in self.data.collect().into_iter().skip(1).step_by(2) {
+            self.data.delete(&key);
+        }
+        self.coarsening = self.coarsening.saturating_mul(2);
+    }
+
+    /// Get the sampled keys in sorted order.
+    pub fn sampled_keys(&self) -> Vec<K> {
+        self.data
+            .collect()
+            .into_iter()
+            .map(|(key, _)| key)
+            .collect()
+    }
+
+    /// Get the addresses of the sampled keys covering the range `[start, end)`.
+    /// This includes the address of the largest key less than `start` and the
+    /// address of the smallest key greater than or equal to `end` so that the
+    /// addresses span all keys in the range.
+    ///
+    /// # Arguments
+    /// * _start_ - The inclusive lower bound of the range.
+    /// * _end_ - The exclusive upper bound of the range.
+    pub fn get_addresses_in_range(&self, start: &K, end: &K) -> Vec<TableAddress> {
+        let mut output = Vec::new();
+        let mut previous = None;
+        for (key, address) in self.data.collect() {
+            if &key < start {
+                previous = Some(address);
+                continue;
+            }
+            if let Some(previous_address) = previous.take() {
+                output.push(previous_address);
+            }
+            output.push(address);
+            if &key >= end {
+                break;
+            }
+        }
+        if let Some(previous_address) = previous {
+            output.push(previous_address);
+        }
+        output
+    }
+
+    /// Record the range of keys stored in the table at the supplied path.
+    ///
+    /// # Arguments
+    /// * _path_ - The path to the SSTable.
+    /// * _first_ - The smallest key in the table.
+    /// * _last_ - The largest key in the table.
+    pub fn update_table_range(&mut self, path: &PathBuf, first: K, last: K) {
+        self.table_ranges
+            .retain(|(table_path, _, _)| table_path != path);
+        self.table_ranges.push((path.clone(), first, last));
+    }
+
+    /// Get the smallest and largest key stored in the table at the supplied path.
+    ///
+    /// # Arguments
+    /// * _path_ - The path to the SSTable.
+    pub fn get_table_range(&self, path: &PathBuf) -> Option<(K, K)> {
+        self.table_ranges
+            .iter()
+            .find(|(table_path, _, _)| table_path == path)
+            .map(|(_, first, last)| (first.clone(), last.clone()))
+    }
+
+    /// Get the smallest and largest key stored in each indexed table.
+    pub fn table_ranges(&self) -> Vec<(K, K)> {
+        self.table_ranges
+            .iter()
+            .map(|(_, first, last)| (first.clone(), last.clone()))
+            .collect()
+    }
+
+    /// Get the smallest and largest key stored across all indexed tables.
+    pub fn key_range(&self) -> Option<(K, K)> {
+        let first = self.table_ranges.iter().map(|(_, first, _)| first).min()?;
+        let last = self.table_ranges.iter().map(|(_, _, last)| last).max()?;
+        Some((first.clone(), last.clone()))
+    }
+
+    /// Get the paths to the tables whose range of keys contains the target key,
+    /// most recently indexed tables first.
+    ///
+    /// # Arguments
+    /// * _key_ - The target key.
+    pub fn get_tables_containing(&self, key: &K) -> Vec<PathBuf> {
+        self.table_ranges
+            .iter()
+            .rev()
+            .filter(|(_, first, last)| first <= key && key <= last)
+            .map(|(path, _, _)| path.clone())
+            .collect()
+    }
+
+    /// Get the paths to the tables whose range of keys excludes the target key,
+    /// most recently indexed tables first.
+    ///
+    /// # Arguments
+    /// * _key_ - The target key.
+    pub fn get_tables_excluding(&self, key: &K) -> Vec<PathBuf> {
+        self.table_ranges
+            .iter()
+            .rev()
+            .filter(|(_, first, last)| key < first || last < key)
+            .map(|(path, _, _)| path.clone())
+            .collect()
+    }
+
+    /// Add a range tombstone stored in an indexed table.
+    ///
+    /// # Arguments
+    /// * _path_ - The path to the SSTable holding the tombstone.
+    /// * _tombstone_ - The range tombstone.
+    pub fn add_range_tombstone(&mut self, path: &PathBuf, tombstone: RangeTombstone<K>) {
+        self.range_tombstones.push((path.clone(), tombstone));
+    }
+
+    /// Get the range tombstones stored in the indexed tables.
+    pub fn get_range_tombstones(&self) -> Vec<RangeTombstone<K>> {
+        self.range_tombstones
+            .iter()
+            .map(|(_, tombstone)| tombstone.clone())
+            .collect()
+    }
+
+    /// Check whether the version of the key with the supplied sequence number is
+    /// deleted by an indexed range tombstone when reading as of `at_seq`.
+    ///
+    /// # Arguments
+    /// * _key_ - The key of the version.
+    /// * _seq_ - The sequence number of the version.
+    /// * _at_seq_ - The sequence number as of which the key is read.
+    pub fn is_range_deleted(&self, key: &K, seq: u64, at_seq: u64) -> bool {
+        self.range_tombstones
+            .iter()
+            .any(|(_, tombstone)| tombstone.deletes(key, seq, at_seq))
+    }
+
+    /// Remove all entries of the table at the supplied path from the index.
+    ///
+    /// # Arguments
+    /// * _path_ - The path to the SSTable.
+    pub fn remove_table(&mut self, path: &PathBuf) {
+        for (key, address) in self.data.collect() {
+            if &address.path == path {
+                self.data.delete(&key);
+            }
+        }
+        self.table_ranges
+            .retain(|(table_path, _, _)| table_path != path);
+        self.range_tombstones
+            .retain(|(table_path, _)| table_path != path);
+    }
+
+    /// Point the entries of the table at `from` to the table at `to` after the
+    /// table was moved. No table may be indexed at `to`.
+    ///
+    /// # Arguments
+    /// * _from_ - The previous path to the SSTable.
+    /// * _to_ - The new path to the SSTable.
+    pub fn rename_table(&mut self, from: &PathBuf, to: &PathBuf) {
+        for (key, address) in self.data.collect() {
+            if &address.path == from {
+                self.data.delete(&key);
+                self.data.insert(key, TableAddress::new(to, address.offset));
+            }
+        }
+        for (table_path, _, _) in self.table_ranges.iter_mut() {
+            if table_path == from {
+                *table_path = to.clone();
+            }
+        }
+        for (table_path, _) in self.range_tombstones.iter_mut() {
+            if table_path == from {
+                *table_path = to.clone();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+    struct IndexKey(u32);
+
+    impl Display for IndexKey {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "{}", self.0)
+        }
+    }
+
+    impl ResourceKey for IndexKey {}
+
+    #[test]
+    fn test_update_replaces_address_of_indexed_key() {
+        let older = PathBuf::from("tables/0.db");
+        let newer = PathBuf::from("tables/1.db");
+        let mut index = SparseIndex::new();
+        index.update(IndexKey(1), TableAddress::new(&older, 0));
+        index.update(IndexKey(1), TableAddress::new(&newer, 64));
+        assert_eq!(index.len(), 1);
+        assert_eq!(index.count_in_range(&IndexKey(0), &IndexKey(2)), 1);
+        let address = index.get_nearest_address(&IndexKey(1)).unwrap();
+        assert_eq!(address.path, newer);
+        assert_eq!(address.offset, 64);
+        // removing the older table leaves the address of the newer one
+        index.remove_table(&older);
+        assert_eq!(index.get_nearest_address(&IndexKey(1)).unwrap().path, newer);
     }
 }
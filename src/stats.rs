@@ -0,0 +1,129 @@
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+/// Snapshot of the state of a database returned by `Dharma::stats`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Stats {
+    /// Number of keys held in the memtable.
+    pub memtable_entries: usize,
+    /// Size in bytes of the data held in memory. See `Dharma::in_memory_size`.
+    pub memtable_size_in_bytes: usize,
+    /// Number of SSTables on disk.
+    pub sstables: usize,
+    /// Combined size in bytes of the SSTables and the value log.
+    pub disk_size_in_bytes: u64,
+    /// Size in bytes of the Write Ahead Log.
+    pub wal_size_in_bytes: u64,
+    /// Time at which values were last flushed to disk by this instance.
+    pub last_flush: Option<SystemTime>,
+    /// Time at which SSTables were last compacted by this instance.
+    pub last_compaction: Option<SystemTime>,
+    /// Number of keys sampled by the sparse index.
+    pub index_entries: usize,
+    /// Fraction of block reads served from the block cache. Not set until
+    /// a block has been read.
+    pub block_cache_hit_ratio: Option<f64>,
+}
+
+/// Description of an SSTable returned by `Dharma::table_layout`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TableInfo<K> {
+    /// Path to the SSTable.
+    pub path: PathBuf,
+    /// Smallest and largest key of the values in the table. Not set for
+    /// tables that only hold range tombstones.
+    pub key_range: Option<(K, K)>,
+    /// Number of records in the table including range tombstones.
+    pub records: usize,
+    /// Size of the table in bytes.
+    pub size_in_bytes: u64,
+}
+
+/// Description of an SSTable listed by a `SnapshotDescriptor`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TableSnapshot {
+    /// Path to the SSTable.
+    pub path: PathBuf,
+    /// Size of the table in bytes.
+    pub size_in_bytes: u64,
+    /// Checksum recorded in the footer of the table, see `compute_table_checksum`.
+    /// Not set for tables written before checksums were recorded.
+    pub checksum: Option<u64>,
+}
+
+/// Serializable description of the SSTables of a database returned by
+/// `Dharma::snapshot_descriptor`. A copy of the tables is complete and consistent
+/// when it holds every listed table with the listed size and checksum.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SnapshotDescriptor {
+    /// Each SSTable in order.
+    pub tables: Vec<TableSnapshot>,
+    /// Sequence number of the latest write held by the tables.
+    pub last_sequence: u64,
+}
+
+/// Key stored in more than one SSTable, see `IntegrityReport`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DuplicateKey<K> {
+    /// The duplicated key.
+    pub key: K,
+    /// Path to each table holding the key in order along with the sequence
+    /// number of the newest version of the key in that table.
+    pub tables: Vec<(PathBuf, u64)>,
+}
+
+/// Result of checking the SSTables returned by `Dharma::verify_integrity`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct IntegrityReport<K> {
+    /// Number of SSTables that were read.
+    pub tables_checked: usize,
+    /// Keys present in more than one table. Keys are expected to be duplicated
+    /// until overlapping tables are compacted, many duplicates are a sign that
+    /// compaction is lagging and reads of those keys inspect several tables.
+    pub duplicate_keys: Vec<DuplicateKey<K>>,
+}
+
+/// Layout of the SSTable a flush of the in-memory values would write, returned by
+/// `Dharma::estimate_flush`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FlushEstimate {
+    /// Number of blocks the values and range tombstones are packed into.
+    pub blocks: usize,
+    /// Size in bytes of the blocks.
+    pub total_bytes: usize,
+    /// Bytes of the blocks that hold no record, including the padding records
+    /// filling the end of each block.
+    pub padding_bytes: usize,
+    /// Number of records that don't fit in a block and are split across blocks.
+    pub split_records: usize,
+}
+
+/// Read amplification of a read returned by `Dharma::get_with_stats`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct GetStats {
+    /// Number of tables that were read. Reads served from memory or from the value
+    /// cache read no tables.
+    pub tables_probed: usize,
+    /// Number of blocks loaded from the tables that were read, including blocks
+    /// served from the block cache.
+    pub blocks_read: usize,
+}
+
+/// Trace of a read returned by `Dharma::get_debug` describing where the key was
+/// looked for.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GetTrace<V> {
+    /// Whether the key was found in memory. Tables aren't read when it was.
+    pub memtable_hit: bool,
+    /// Whether the value was served from the value cache.
+    pub value_cache_hit: bool,
+    /// Path to each table that was read in the order they were read.
+    pub tables_probed: Vec<PathBuf>,
+    /// Path to each table that was skipped without being read because its range
+    /// of keys excludes the key. Tables have no bloom filters, their key range
+    /// is the only filter applied before reading them.
+    pub tables_skipped: Vec<PathBuf>,
+    /// The value the read resolved to.
+    pub value: Option<V>,
+}
@@ -0,0 +1,608 @@
+use std::collections::BTreeMap;
+#[cfg(feature = "std")]
+use std::fs::{create_dir_all, read_dir, remove_file, rename, File, OpenOptions};
+use std::io::{Error, ErrorKind, Result};
+#[cfg(feature = "std")]
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// A handle to a file managed by a `StorageBackend`.
+/// Writes are appended to the end of the file unless they are explicitly
+/// performed at an offset with `write_at` while reads can be performed at
+/// arbitrary offsets.
+pub trait StorageFile: Send {
+    /// Read bytes starting at the supplied offset into the buffer.
+    /// Reading past the end of the file leaves the remainder of the buffer untouched.
+    ///
+    /// # Returns
+    /// The number of bytes read into the buffer.
+    fn read_at(&mut self, buffer: &mut [u8], offset: u64) -> Result<usize>;
+
+    /// Append the supplied bytes to the end of the file. Either all bytes are
+    /// written or an error is returned.
+    fn write(&mut self, data: &[u8]) -> Result<()>;
+
+    /// Get the current size of the file in bytes.
+    fn size(&self) -> Result<u64>;
+
+    /// Flush written data to durable storage.
+    fn sync(&mut self) -> Result<()>;
+
+    /// Resize the file to the supplied size. Bytes added to the file are zeroed.
+    /// Files that don't support resizing return an error of kind `Unsupported`.
+    fn set_len(&mut self, _size: u64) -> Result<()> {
+        Err(Error::from(ErrorKind::Unsupported))
+    }
+
+    /// Write the supplied bytes at the offset overwriting any existing bytes.
+    /// The file is extended if the bytes end past the end of the file.
+    /// Files that don't support positioned writes return an error of kind `Unsupported`.
+    fn write_at(&mut self, _data: &[u8], _offset: u64) -> Result<()> {
+        Err(Error::from(ErrorKind::Unsupported))
+    }
+}
+
+/// Overwrite the bytes of the buffer at the offset extending it as needed.
+fn write_to_buffer(buffer: &mut Vec<u8>, data: &[u8], offset: u64) {
+    let start = offset as usize;
+    let end = start + data.len();
+    if buffer.len() < end {
+        buffer.resize(end, 0);
+    }
+    buffer[start..end].copy_from_slice(data);
+}
+
+/// A byte buffer can be used as a file to stage writes in memory.
+impl StorageFile for Vec<u8> {
+    fn read_at(&mut self, buffer: &mut [u8], offset: u64) -> Result<usize> {
+        let start = offset as usize;
+        if start >= self.len() {
+            return Ok(0);
+        }
+        let end = std::cmp::min(self.len(), start + buffer.len());
+        buffer[..end - start].copy_from_slice(&self[start..end]);
+        Ok(end - start)
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<()> {
+        self.extend_from_slice(data);
+        Ok(())
+    }
+
+    fn size(&self) -> Result<u64> {
+        Ok(self.len() as u64)
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn set_len(&mut self, size: u64) -> Result<()> {
+        self.resize(size as usize, 0);
+        Ok(())
+    }
+
+    fn write_at(&mut self, data: &[u8], offset: u64) -> Result<()> {
+        write_to_buffer(self, data, offset);
+        Ok(())
+    }
+}
+
+/// Abstraction over the file operations performed by the database.
+/// The default `FsBackend` persists data to the local file system while
+/// `InMemoryBackend` keeps all data in memory which is useful for tests.
+pub trait StorageBackend: Send + Sync {
+    /// Create a new empty file at the path, truncating any existing file.
+    fn create(&self, path: &Path) -> Result<Box<dyn StorageFile>>;
+
+    /// Open an existing file at the path.
+    fn open(&self, path: &Path) -> Result<Box<dyn StorageFile>>;
+
+    /// Open an existing file at the path for reading with direct IO, bypassing the
+    /// page cache of the operating system. Backends that don't support direct IO
+    /// open the file as usual. See `DharmaOpts::direct_io`.
+    fn open_direct(&self, path: &Path) -> Result<Box<dyn StorageFile>> {
+        self.open(path)
+    }
+
+    /// Check whether a file or directory exists at the path.
+    fn exists(&self, path: &Path) -> bool;
+
+    /// Remove the file at the path.
+    fn remove(&self, path: &Path) -> Result<()>;
+
+    /// List the files within the directory at the path.
+    fn list_dir(&self, path: &Path) -> Result<Vec<PathBuf>>;
+
+    /// Move the file at `from` to `to`, replacing any existing file at `to`.
+    fn rename(&self, from: &Path, to: &Path) -> Result<()>;
+
+    /// Create the directory at the path along with any missing parents.
+    fn create_dir_all(&self, path: &Path) -> Result<()>;
+
+    /// Flush the entries of the directory at the path to durable storage so that
+    /// files created, renamed or removed within it are found after a crash.
+    fn sync_dir(&self, path: &Path) -> Result<()>;
+}
+
+/// Move the file at `from` to `to` replacing any existing file at `to`. Renaming
+/// fails when the paths are on different file systems in which case the file is
+/// copied next to `to` under a temporary name, synced and then renamed into place
+/// so that a partially copied file is never found at `to`.
+pub fn move_file(backend: &dyn StorageBackend, from: &Path, to: &Path) -> Result<()> {
+    match backend.rename(from, to) {
+        Err(error) if error.kind() == ErrorKind::CrossesDevices => {
+            let staging_path = to.with_extension("tmp");
+            let moved = copy_file(backend, from, &staging_path)
+                .and_then(|_| backend.rename(&staging_path, to));
+            if moved.is_err() {
+                let _ = backend.remove(&staging_path);
+                return moved;
+            }
+            backend.remove(from)
+        }
+        result => result,
+    }
+}
+
+/// Copy the file at `from` to a new file at `to` and sync the copy.
+fn copy_file(backend: &dyn StorageBackend, from: &Path, to: &Path) -> Result<()> {
+    let mut source = backend.open(from)?;
+    let mut destination = backend.create(to)?;
+    let size = source.size()?;
+    let mut buffer = vec![0u8; 64 * 1024];
+    let mut offset = 0;
+    while offset < size {
+        let read = source.read_at(&mut buffer, offset)?;
+        if read == 0 {
+            return Err(Error::from(ErrorKind::UnexpectedEof));
+        }
+        destination.write(&buffer[..read])?;
+        offset += read as u64;
+    }
+    destination.sync()
+}
+
+/// Backend opening the SSTables of a database with `StorageBackend::open_direct` so
+/// that reads of the tables bypass the page cache, see `DharmaOpts::direct_io`.
+/// All other files are handled by the wrapped backend as is.
+pub struct DirectIoBackend {
+    inner: Arc<dyn StorageBackend>,
+    tables_path: PathBuf,
+    table_extension: String,
+}
+
+impl DirectIoBackend {
+    /// Wrap the backend opening the tables within the directory at `tables_path`
+    /// that have the supplied extension with direct IO.
+    pub fn new(
+        inner: Arc<dyn StorageBackend>,
+        tables_path: PathBuf,
+        table_extension: String,
+    ) -> DirectIoBackend {
+        DirectIoBackend {
+            inner,
+            tables_path,
+            table_extension,
+        }
+    }
+
+    fn is_table(&self, path: &Path) -> bool {
+        path.parent() == Some(self.tables_path.as_path())
+            && path.extension().map_or(false, |extension| {
+                extension == self.table_extension.as_str()
+            })
+    }
+}
+
+impl StorageBackend for DirectIoBackend {
+    fn create(&self, path: &Path) -> Result<Box<dyn StorageFile>> {
+        self.inner.create(path)
+    }
+
+    fn open(&self, path: &Path) -> Result<Box<dyn StorageFile>> {
+        if self.is_table(path) {
+            return self.inner.open_direct(path);
+        }
+        self.inner.open(path)
+    }
+
+    fn open_direct(&self, path: &Path) -> Result<Box<dyn StorageFile>> {
+        self.inner.open_direct(path)
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        self.inner.exists(path)
+    }
+
+    fn remove(&self, path: &Path) -> Result<()> {
+        self.inner.remove(path)
+    }
+
+    fn list_dir(&self, path: &Path) -> Result<Vec<PathBuf>> {
+        self.inner.list_dir(path)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        self.inner.rename(from, to)
+    }
+
+    fn create_dir_all(&self, path: &Path) -> Result<()> {
+        self.inner.create_dir_all(path)
+    }
+
+    fn sync_dir(&self, path: &Path) -> Result<()> {
+        self.inner.sync_dir(path)
+    }
+}
+
+/// Storage backend that persists data to the local file system using `std::fs`.
+/// Only available with the `std` feature.
+#[cfg(feature = "std")]
+pub struct FsBackend;
+
+#[cfg(feature = "std")]
+impl FsBackend {
+    pub fn new() -> FsBackend {
+        FsBackend
+    }
+}
+
+#[cfg(feature = "std")]
+struct FsFile {
+    file: File,
+}
+
+#[cfg(feature = "std")]
+impl StorageFile for FsFile {
+    fn read_at(&mut self, buffer: &mut [u8], offset: u64) -> Result<usize> {
+        self.file.seek(SeekFrom::Start(offset))?;
+        let mut total_read = 0;
+        while total_read < buffer.len() {
+            let read = self.file.read(&mut buffer[total_read..])?;
+            if read == 0 {
+                break;
+            }
+            total_read += read;
+        }
+        Ok(total_read)
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<()> {
+        self.file.seek(SeekFrom::End(0))?;
+        self.file.write_all(data)
+    }
+
+    fn size(&self) -> Result<u64> {
+        self.file.metadata().map(|metadata| metadata.len())
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        self.file.sync_data()
+    }
+
+    fn set_len(&mut self, size: u64) -> Result<()> {
+        self.file.set_len(size)
+    }
+
+    fn write_at(&mut self, data: &[u8], offset: u64) -> Result<()> {
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.file.write_all(data)
+    }
+}
+
+/// Value of the `O_DIRECT` open flag on the platforms direct IO is supported on.
+#[cfg(all(
+    feature = "std",
+    target_os = "linux",
+    any(target_arch = "x86", target_arch = "x86_64")
+))]
+const O_DIRECT: i32 = 0o40000;
+#[cfg(all(
+    feature = "std",
+    target_os = "linux",
+    any(target_arch = "arm", target_arch = "aarch64")
+))]
+const O_DIRECT: i32 = 0o200000;
+
+/// Alignment of the offset, size and memory of reads performed with direct IO.
+/// It covers the logical block size of common devices.
+#[cfg(all(
+    feature = "std",
+    target_os = "linux",
+    any(
+        target_arch = "x86",
+        target_arch = "x86_64",
+        target_arch = "arm",
+        target_arch = "aarch64"
+    )
+))]
+const DIRECT_IO_ALIGNMENT: usize = 4096;
+
+/// File opened with `O_DIRECT` that can only be read. Reads are widened to aligned
+/// offsets and sizes and performed into an aligned buffer which the requested bytes
+/// are copied from.
+#[cfg(all(
+    feature = "std",
+    target_os = "linux",
+    any(
+        target_arch = "x86",
+        target_arch = "x86_64",
+        target_arch = "arm",
+        target_arch = "aarch64"
+    )
+))]
+struct DirectFsFile {
+    file: File,
+}
+
+#[cfg(all(
+    feature = "std",
+    target_os = "linux",
+    any(
+        target_arch = "x86",
+        target_arch = "x86_64",
+        target_arch = "arm",
+        target_arch = "aarch64"
+    )
+))]
+impl StorageFile for DirectFsFile {
+    fn read_at(&mut self, buffer: &mut [u8], offset: u64) -> Result<usize> {
+        use std::os::unix::fs::FileExt;
+        if buffer.is_empty() {
+            return Ok(0);
+        }
+        let alignment = DIRECT_IO_ALIGNMENT as u64;
+        let start = offset / alignment * alignment;
+        let end = (offset + buffer.len() as u64 + alignment - 1) / alignment * alignment;
+        let size = (end - start) as usize;
+        // over allocate so that the buffer can start at an aligned address
+        let mut allocation = vec![0u8; size + DIRECT_IO_ALIGNMENT];
+        let padding = allocation.as_ptr().align_offset(DIRECT_IO_ALIGNMENT);
+        let aligned = &mut allocation[padding..padding + size];
+        let mut total_read = 0;
+        while total_read < size {
+            let read = self
+                .file
+                .read_at(&mut aligned[total_read..], start + total_read as u64)?;
+            total_read += read;
+            // a short read ends at the end of the file which is not aligned
+            if read == 0 || read % DIRECT_IO_ALIGNMENT != 0 {
+                break;
+            }
+        }
+        let skipped = (offset - start) as usize;
+        let available = total_read.saturating_sub(skipped);
+        let copied = std::cmp::min(buffer.len(), available);
+        buffer[..copied].copy_from_slice(&aligned[skipped..skipped + copied]);
+        Ok(copied)
+    }
+
+    fn write(&mut self, _data: &[u8]) -> Result<()> {
+        Err(Error::from(ErrorKind::Unsupported))
+    }
+
+    fn size(&self) -> Result<u64> {
+        self.file.metadata().map(|metadata| metadata.len())
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(feature = "std")]
+impl StorageBackend for FsBackend {
+    fn create(&self, path: &Path) -> Result<Box<dyn StorageFile>> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        Ok(Box::new(FsFile { file }))
+    }
+
+    fn open(&self, path: &Path) -> Result<Box<dyn StorageFile>> {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        Ok(Box::new(FsFile { file }))
+    }
+
+    #[cfg(all(
+        target_os = "linux",
+        any(
+            target_arch = "x86",
+            target_arch = "x86_64",
+            target_arch = "arm",
+            target_arch = "aarch64"
+        )
+    ))]
+    fn open_direct(&self, path: &Path) -> Result<Box<dyn StorageFile>> {
+        use std::os::unix::fs::OpenOptionsExt;
+        match OpenOptions::new()
+            .read(true)
+            .custom_flags(O_DIRECT)
+            .open(path)
+        {
+            Ok(file) => Ok(Box::new(DirectFsFile { file })),
+            // file systems like tmpfs don't support direct IO
+            Err(error) if error.kind() == ErrorKind::InvalidInput => {
+                log::warn!(
+                    "Direct IO is not supported for {}, opening it without",
+                    path.display()
+                );
+                self.open(path)
+            }
+            Err(error) => Err(error),
+        }
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        path.exists()
+    }
+
+    fn remove(&self, path: &Path) -> Result<()> {
+        remove_file(path)
+    }
+
+    fn list_dir(&self, path: &Path) -> Result<Vec<PathBuf>> {
+        let mut output = Vec::new();
+        for entry in read_dir(path)? {
+            output.push(entry?.path());
+        }
+        Ok(output)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        rename(from, to)
+    }
+
+    fn create_dir_all(&self, path: &Path) -> Result<()> {
+        create_dir_all(path)
+    }
+
+    fn sync_dir(&self, path: &Path) -> Result<()> {
+        File::open(path)?.sync_all()
+    }
+}
+
+type InMemoryFiles = Arc<Mutex<BTreeMap<PathBuf, Vec<u8>>>>;
+
+/// Storage backend that keeps all files in memory. Data is lost once the
+/// backend is dropped. Directories are implicit and always exist.
+#[derive(Clone)]
+pub struct InMemoryBackend {
+    files: InMemoryFiles,
+}
+
+impl InMemoryBackend {
+    pub fn new() -> InMemoryBackend {
+        InMemoryBackend {
+            files: Arc::new(Mutex::new(BTreeMap::new())),
+        }
+    }
+}
+
+struct InMemoryFile {
+    files: InMemoryFiles,
+    path: PathBuf,
+}
+
+fn not_found(path: &Path) -> Error {
+    Error::new(
+        ErrorKind::NotFound,
+        format!("No in-memory file at {}", path.display()),
+    )
+}
+
+impl StorageFile for InMemoryFile {
+    fn read_at(&mut self, buffer: &mut [u8], offset: u64) -> Result<usize> {
+        let files = self.files.lock().unwrap();
+        let data = files.get(&self.path).ok_or_else(|| not_found(&self.path))?;
+        let start = offset as usize;
+        if start >= data.len() {
+            return Ok(0);
+        }
+        let end = std::cmp::min(data.len(), start + buffer.len());
+        buffer[..end - start].copy_from_slice(&data[start..end]);
+        Ok(end - start)
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<()> {
+        let mut files = self.files.lock().unwrap();
+        let file = files
+            .get_mut(&self.path)
+            .ok_or_else(|| not_found(&self.path))?;
+        file.extend_from_slice(data);
+        Ok(())
+    }
+
+    fn size(&self) -> Result<u64> {
+        let files = self.files.lock().unwrap();
+        let data = files.get(&self.path).ok_or_else(|| not_found(&self.path))?;
+        Ok(data.len() as u64)
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn set_len(&mut self, size: u64) -> Result<()> {
+        let mut files = self.files.lock().unwrap();
+        let file = files
+            .get_mut(&self.path)
+            .ok_or_else(|| not_found(&self.path))?;
+        file.resize(size as usize, 0);
+        Ok(())
+    }
+
+    fn write_at(&mut self, data: &[u8], offset: u64) -> Result<()> {
+        let mut files = self.files.lock().unwrap();
+        let file = files
+            .get_mut(&self.path)
+            .ok_or_else(|| not_found(&self.path))?;
+        write_to_buffer(file, data, offset);
+        Ok(())
+    }
+}
+
+impl StorageBackend for InMemoryBackend {
+    fn create(&self, path: &Path) -> Result<Box<dyn StorageFile>> {
+        self.files
+            .lock()
+            .unwrap()
+            .insert(path.to_path_buf(), Vec::new());
+        Ok(Box::new(InMemoryFile {
+            files: self.files.clone(),
+            path: path.to_path_buf(),
+        }))
+    }
+
+    fn open(&self, path: &Path) -> Result<Box<dyn StorageFile>> {
+        if !self.files.lock().unwrap().contains_key(path) {
+            return Err(not_found(path));
+        }
+        Ok(Box::new(InMemoryFile {
+            files: self.files.clone(),
+            path: path.to_path_buf(),
+        }))
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        self.files.lock().unwrap().contains_key(path)
+    }
+
+    fn remove(&self, path: &Path) -> Result<()> {
+        self.files
+            .lock()
+            .unwrap()
+            .remove(path)
+            .map(|_| ())
+            .ok_or_else(|| not_found(path))
+    }
+
+    fn list_dir(&self, path: &Path) -> Result<Vec<PathBuf>> {
+        let files = self.files.lock().unwrap();
+        Ok(files
+            .keys()
+            .filter(|file_path| file_path.parent() == Some(path))
+            .cloned()
+            .collect())
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        let mut files = self.files.lock().unwrap();
+        let data = files.remove(from).ok_or_else(|| not_found(from))?;
+        files.insert(to.to_path_buf(), data);
+        Ok(())
+    }
+
+    fn create_dir_all(&self, _path: &Path) -> Result<()> {
+        Ok(())
+    }
+
+    fn sync_dir(&self, _path: &Path) -> Result<()> {
+        Ok(())
+    }
+}
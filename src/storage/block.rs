@@ -1,15 +1,58 @@
-use crate::result::Result;
+use crate::result::{Errors, Result};
 use crate::options::DharmaOpts;
+use crate::storage::backend::StorageFile;
+use crate::storage::codec::{BincodeCodec, RecordCodec, BINCODE_CODEC_ID};
+use crate::storage::value_log::ValuePointer;
 use crate::traits::{ResourceKey, ResourceValue};
+use lz4_flex::{compress_prepend_size, decompress_size_prepended};
 use serde::{Deserialize, Serialize};
 use std::cmp::Ordering;
-use std::fs::File;
-use std::io::Write;
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct Value<K, V> {
     pub key: K,
     pub value: V,
+    /// Sequence number of the write that produced this value.
+    pub seq: u64,
+    /// Location of the value in the value log if it is stored separately
+    /// from its key. See `value_log.rs`.
+    #[serde(skip)]
+    pub pointer: Option<ValuePointer>,
+}
+
+/// Marks all keys within the range `[start, end)` written before the tombstone
+/// as deleted. Range tombstones are stored as records of their own after the
+/// values of a table.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct RangeTombstone<K> {
+    pub start: K,
+    pub end: K,
+    /// Sequence number of the range deletion.
+    pub seq: u64,
+}
+
+impl<K> RangeTombstone<K>
+where
+    K: ResourceKey,
+{
+    pub fn new(start: K, end: K, seq: u64) -> RangeTombstone<K> {
+        RangeTombstone { start, end, seq }
+    }
+
+    /// Check whether the tombstone deletes the version of the key with the supplied
+    /// sequence number when reading as of `at_seq`.
+    pub fn deletes(&self, key: &K, seq: u64, at_seq: u64) -> bool {
+        &self.start <= key && key < &self.end && seq < self.seq && self.seq <= at_seq
+    }
+}
+
+/// Data of a record whose value is stored in the value log.
+/// The pointer is serialized first so it can be read without knowing the key type.
+#[derive(Serialize, Deserialize)]
+pub struct SeparatedValue<K> {
+    pub pointer: ValuePointer,
+    pub key: K,
+    pub seq: u64,
 }
 
 impl<K, V> PartialEq for Value<K, V>
@@ -28,37 +71,164 @@ where
     V: ResourceValue,
 {
     pub fn new(key: K, value: V) -> Value<K, V> {
-        Value { key, value }
+        Value::with_seq(key, value, 0)
+    }
+
+    pub fn with_seq(key: K, value: V, seq: u64) -> Value<K, V> {
+        Value {
+            key,
+            value,
+            seq,
+            pointer: None,
+        }
+    }
+
+    /// Get the key of the value.
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    /// Get the value associated with the key.
+    pub fn value(&self) -> &V {
+        &self.value
+    }
+
+    /// Split the value into its key and the value associated with the key.
+    pub fn into_tuple(self) -> (K, V) {
+        (self.key, self.value)
+    }
+
+    /// Check whether the value marks the key as deleted. Deleted keys are
+    /// associated with the nil value, see `Nil`.
+    pub fn is_tombstone(&self) -> bool {
+        self.value == V::nil()
     }
 }
 
-#[derive(Copy, Clone)]
+/// The type of a record stored in the lower bits of the record type byte, see
+/// `Record::RECORD_TYPE_MASK`. Values of the type bits that are not listed here
+/// are reserved for new record types and are read as `UNKNOWN`. Readers fail on
+/// unknown records instead of guessing how to skip them, so adding a record type
+/// requires a new `FORMAT_VERSION`.
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum RecordType {
     PADDING = 0,
     COMPLETE = 1,
     START = 2,
     MIDDLE = 3,
     END = 4,
-    UNKNOWN = 5,
+    // first record of blocks of tables written with block headers, see `BlockHeader`
+    HEADER = 5,
+    // never written, stands for every reserved value of the type bits
+    UNKNOWN = 15,
 }
 
-/// Map a unsigned byte to a Record Type.
+/// Map a unsigned byte to a Record Type. The flags of the record are ignored.
 pub fn to_record_type(val: u8) -> RecordType {
-    return match val {
+    return match val & Record::RECORD_TYPE_MASK {
         0 => RecordType::PADDING,
         1 => RecordType::COMPLETE,
         2 => RecordType::START,
         3 => RecordType::MIDDLE,
         4 => RecordType::END,
+        5 => RecordType::HEADER,
         _ => RecordType::UNKNOWN,
     };
 }
 
+/// Check whether the record type byte has the compression flag set.
+pub fn is_compressed(val: u8) -> bool {
+    val & Record::RECORD_COMPRESSED_FLAG != 0
+}
+
+/// Check whether the record type byte has the separation flag set.
+pub fn is_separated(val: u8) -> bool {
+    val & Record::RECORD_SEPARATED_FLAG != 0
+}
+
+/// Check whether the record type byte has the key prefix compression flag set.
+pub fn is_prefix_compressed(val: u8) -> bool {
+    val & Record::RECORD_PREFIX_FLAG != 0
+}
+
+/// Check whether the record type byte has the range tombstone flag set.
+pub fn is_range_tombstone(val: u8) -> bool {
+    val & Record::RECORD_RANGE_TOMBSTONE_FLAG != 0
+}
+
+/// Encode serialized data starting with a key of the supplied length by replacing
+/// the prefix the key shares with the previous key by the length of the prefix.
+/// The data is encoded as
+///
+/// | shared prefix length (2 bytes) | suffix length (2 bytes) | key suffix | remaining data |
+pub fn encode_key_prefix(data: &[u8], key_size: usize, previous_key: &[u8]) -> Vec<u8> {
+    let key = &data[..key_size];
+    let shared = key
+        .iter()
+        .zip(previous_key)
+        .take(u16::MAX as usize)
+        .take_while(|(byte, previous_byte)| byte == previous_byte)
+        .count();
+    let suffix = &data[shared..key_size];
+    let mut encoded = Vec::with_capacity(4 + data.len() - shared);
+    encoded.extend_from_slice(&(shared as u16).to_be_bytes());
+    encoded.extend_from_slice(&(suffix.len() as u16).to_be_bytes());
+    encoded.extend_from_slice(suffix);
+    encoded.extend_from_slice(&data[key_size..]);
+    encoded
+}
+
+/// Get the key of data encoded with `encode_key_prefix`.
+///
+/// # Returns
+/// Optional that contains the key unless the data is malformed.
+pub fn decode_key_prefix(data: &[u8], previous_key: &[u8]) -> Option<Vec<u8>> {
+    if data.len() < 4 {
+        return None;
+    }
+    let shared = u16::from_be_bytes([data[0], data[1]]) as usize;
+    let suffix_size = u16::from_be_bytes([data[2], data[3]]) as usize;
+    if shared > previous_key.len() || 4 + suffix_size > data.len() {
+        return None;
+    }
+    let mut key = Vec::with_capacity(shared + suffix_size);
+    key.extend_from_slice(&previous_key[..shared]);
+    key.extend_from_slice(&data[4..4 + suffix_size]);
+    Some(key)
+}
+
+/// Restore data encoded with `encode_key_prefix` to the data it was encoded from.
+///
+/// # Returns
+/// Optional that contains the restored data unless the data is malformed.
+pub fn decode_prefix(data: &[u8], previous_key: &[u8]) -> Option<Vec<u8>> {
+    let mut restored = decode_key_prefix(data, previous_key)?;
+    let suffix_size = u16::from_be_bytes([data[2], data[3]]) as usize;
+    restored.extend_from_slice(&data[4 + suffix_size..]);
+    Some(restored)
+}
+
+/// Decompress the data of a record that was stored compressed.
+/// The data is returned as is if it cannot be decompressed.
+pub fn decompress(data: Vec<u8>) -> Vec<u8> {
+    decompress_size_prepended(&data).unwrap_or(data)
+}
+
 /// A Record represents the key, value and some metadata persisted to disk.
 /// Records are written to disk as
 ///
 /// | type (1 byte )| size (2 bytes) | data - array of u8 of length size |
 ///
+/// The lower four bits of the type byte hold the `RecordType` and the upper four
+/// bits hold flags describing the data.
+/// The most significant bit of the type byte is set when the data of the record
+/// is compressed. The second most significant bit is set when the data is a
+/// `SeparatedValue` pointing to the value in the value log. The third most
+/// significant bit is set when the key is encoded relative to the key of the
+/// previous prefix compressed record starting in the same block, see
+/// `encode_key_prefix`. The fourth most significant bit is set when the data is a
+/// `RangeTombstone`. Records split across blocks carry the flags on every chunk.
+///
 /// The maximum size of a record is specified in `option.block_size_in_bytes`.
 /// The maximum size of a record is limited to 32KB since that is the maximum
 /// addressable memory with 2 bytes.
@@ -69,6 +239,14 @@ pub struct Record {
     pub data_size_in_bytes: u16,
     // can hold up to 32 kilobytes of data
     pub data: Vec<u8>,
+    // whether the data is compressed
+    pub compressed: bool,
+    // whether the data points to a value in the value log
+    pub separated: bool,
+    // whether the key is stored as a suffix of the previous key in the block
+    pub prefix_compressed: bool,
+    // whether the data is a range tombstone
+    pub range_tombstone: bool,
 }
 
 impl Record {
@@ -76,6 +254,40 @@ impl Record {
     /// record type and size.
     pub const RECORD_BASE_SIZE_IN_BYTES: usize = 3;
 
+    /// Bit of the record type byte that marks the record data as compressed.
+    pub const RECORD_COMPRESSED_FLAG: u8 = 0x80;
+
+    /// Bit of the record type byte that marks the record data as a `SeparatedValue`.
+    pub const RECORD_SEPARATED_FLAG: u8 = 0x40;
+
+    /// Bit of the record type byte that marks the key of the record as prefix compressed.
+    pub const RECORD_PREFIX_FLAG: u8 = 0x20;
+
+    /// Bit of the record type byte that marks the record data as a `RangeTombstone`.
+    pub const RECORD_RANGE_TOMBSTONE_FLAG: u8 = 0x10;
+
+    /// Bits of the record type byte that hold the `RecordType`, leaving room for
+    /// 16 record types. All other bits are flags.
+    pub const RECORD_TYPE_MASK: u8 = 0x0F;
+
+    /// Get the record type byte holding the type and the flags of the record.
+    pub fn type_byte(&self) -> u8 {
+        let mut type_byte = self.record_type as u8;
+        if self.compressed {
+            type_byte |= Record::RECORD_COMPRESSED_FLAG;
+        }
+        if self.separated {
+            type_byte |= Record::RECORD_SEPARATED_FLAG;
+        }
+        if self.prefix_compressed {
+            type_byte |= Record::RECORD_PREFIX_FLAG;
+        }
+        if self.range_tombstone {
+            type_byte |= Record::RECORD_RANGE_TOMBSTONE_FLAG;
+        }
+        type_byte
+    }
+
     /// Create a record that will be used to pad leftover space
     /// within a block. Padding records don't contain any data.
     pub fn with_padding(size: u16) -> Record {
@@ -83,8 +295,75 @@ impl Record {
             record_type: RecordType::PADDING,
             data_size_in_bytes: size,
             data: Vec::new(),
+            compressed: false,
+            separated: false,
+            prefix_compressed: false,
+            range_tombstone: false,
+        }
+    }
+}
+
+/// Header stored as the first record of every block of tables written with
+/// `DharmaOpts::block_headers`, letting readers learn about a block without
+/// walking its records. The header is stored as a `HEADER` record holding
+///
+/// | record count (2 bytes) | first key |
+///
+/// Headers are only read from tables of at least `BLOCK_HEADER_FORMAT_VERSION`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BlockHeader {
+    /// Number of records stored in the block, counting each chunk of a record split
+    /// across blocks and leaving out the header and padding.
+    pub records: usize,
+    /// Serialized key of the first value starting in the block. Empty if no value
+    /// starts in the block or its key doesn't fit in the block next to the header.
+    pub first_key: Vec<u8>,
+}
+
+impl BlockHeader {
+    /// Size in bytes of the header record of a block without the first key.
+    pub const BASE_SIZE_IN_BYTES: usize = Record::RECORD_BASE_SIZE_IN_BYTES + 2;
+
+    /// Get the record storing the header.
+    pub fn to_record(&self) -> Record {
+        let mut data = Vec::with_capacity(2 + self.first_key.len());
+        data.extend_from_slice(&(self.records as u16).to_be_bytes());
+        data.extend_from_slice(&self.first_key);
+        Record {
+            record_type: RecordType::HEADER,
+            data_size_in_bytes: data.len() as u16,
+            data,
+            compressed: false,
+            separated: false,
+            prefix_compressed: false,
+            range_tombstone: false,
         }
     }
+
+    /// Read the header at the start of the block.
+    ///
+    /// # Returns
+    /// Optional that contains the header along with the size in bytes of its record,
+    /// unless the block doesn't start with a header or the header is malformed.
+    pub fn read(block: &[u8]) -> Option<(BlockHeader, usize)> {
+        if block.len() < BlockHeader::BASE_SIZE_IN_BYTES {
+            return None;
+        }
+        if !matches!(to_record_type(block[0]), RecordType::HEADER) {
+            return None;
+        }
+        let size = u16::from_be_bytes([block[1], block[2]]) as usize;
+        let end = Record::RECORD_BASE_SIZE_IN_BYTES + size;
+        if size < 2 || end > block.len() {
+            return None;
+        }
+        let data = &block[Record::RECORD_BASE_SIZE_IN_BYTES..end];
+        let header = BlockHeader {
+            records: u16::from_be_bytes([data[0], data[1]]) as usize,
+            first_key: data[2..].to_vec(),
+        };
+        Some((header, end))
+    }
 }
 
 /// A Block is the smallest unit of memory that is read from disk.
@@ -108,131 +387,456 @@ impl Block {
     }
 }
 
+/// Pack the values serialized with the codec of the database config into blocks.
+///
+/// # Returns
+/// Result that resolves:
+///  - _Ok_ - () when all values were packed.
+///  - _Err_ - `RECORD_SERIALIZATION_FAILED` or the error returned by the codec if a
+///    value couldn't be serialized.
 pub fn create_blocks<K: ResourceKey, V: ResourceValue>(
     options: &DharmaOpts,
     values: &Vec<Value<K, V>>,
     block_vec: &mut Vec<Block>,
-) {
-    let mut current_block = Block::new();
-    let mut available_memory_in_bytes = options.block_size_in_bytes;
-    let mut i = 0;
-    while i < values.len() {
-        let val = &values[i];
-        // TODO: add logging and handle encoding error
-        let encoded = bincode::serialize(val).unwrap();
+) -> Result<()> {
+    create_blocks_with_tombstones(options, values, &Vec::new(), block_vec)
+}
+
+/// Pack the values followed by the range tombstones into blocks. See `create_blocks`.
+pub fn create_blocks_with_tombstones<K: ResourceKey, V: ResourceValue>(
+    options: &DharmaOpts,
+    values: &[Value<K, V>],
+    tombstones: &[RangeTombstone<K>],
+    block_vec: &mut Vec<Block>,
+) -> Result<()> {
+    let mut builder = BlockBuilder::new(options);
+    for val in values {
+        builder.add(options, val, block_vec)?;
+    }
+    for tombstone in tombstones {
+        builder.add_range_tombstone(options, tombstone, block_vec)?;
+    }
+    builder.finish(block_vec);
+    Ok(())
+}
+
+/// Pack the values serialized with the supplied codec followed by the range
+/// tombstones into blocks. See `create_blocks`.
+///
+/// # Returns
+/// Result that resolves:
+///  - _Ok_ - () when all values were packed.
+///  - _Err_ - Error returned by the codec while serializing a value.
+pub fn create_blocks_with_codec<K: ResourceKey, V: ResourceValue, C: RecordCodec>(
+    options: &DharmaOpts,
+    codec: &C,
+    values: &[Value<K, V>],
+    tombstones: &[RangeTombstone<K>],
+    block_vec: &mut Vec<Block>,
+) -> Result<()> {
+    let mut builder = BlockBuilder::new(options);
+    for val in values {
+        builder.add_with_codec(options, codec, val, block_vec)?;
+    }
+    for tombstone in tombstones {
+        builder.add_range_tombstone(options, tombstone, block_vec)?;
+    }
+    builder.finish(block_vec);
+    Ok(())
+}
+
+/// Flags of the records a value is split into.
+#[derive(Clone, Copy, Default)]
+struct RecordFlags {
+    compressed: bool,
+    separated: bool,
+    prefix_compressed: bool,
+    range_tombstone: bool,
+}
+
+/// Packs values into blocks one value at a time. Blocks are only emitted once
+/// no more data fits in them so streaming values through the builder results
+/// in the same blocks as packing all values at once with `create_blocks`.
+pub struct BlockBuilder {
+    current_block: Block,
+    available_memory_in_bytes: usize,
+    // key of the last prefix compressed record starting in the current block
+    previous_key: Vec<u8>,
+    // whether blocks start with a `BlockHeader`
+    block_headers: bool,
+    // key of the first value starting in the current block once space was reserved
+    // for it in the header
+    first_key: Option<Vec<u8>>,
+}
+
+impl BlockBuilder {
+    pub fn new(options: &DharmaOpts) -> BlockBuilder {
+        BlockBuilder {
+            current_block: Block::new(),
+            available_memory_in_bytes: BlockBuilder::block_capacity(options),
+            previous_key: Vec::new(),
+            block_headers: options.block_headers,
+            first_key: None,
+        }
+    }
+
+    // space left for records in an empty block
+    fn block_capacity(options: &DharmaOpts) -> usize {
+        match options.block_headers {
+            true => options.block_size_in_bytes - BlockHeader::BASE_SIZE_IN_BYTES,
+            false => options.block_size_in_bytes,
+        }
+    }
+
+    /// Add the value serialized with the codec of the database config to the blocks
+    /// being built. Blocks that are filled by the value are appended to the block list.
+    ///
+    /// # Returns
+    /// Result that resolves:
+    ///  - _Ok_ - () when the value was added.
+    ///  - _Err_ - `RECORD_SERIALIZATION_FAILED` or the error returned by the codec if
+    ///    the value couldn't be serialized.
+    pub fn add<K: ResourceKey, V: ResourceValue>(
+        &mut self,
+        options: &DharmaOpts,
+        val: &Value<K, V>,
+        block_vec: &mut Vec<Block>,
+    ) -> Result<()> {
+        match &options.record_codec {
+            Some(codec) => {
+                self.add_encoded_value(options, codec.id(), val, || codec.encode(val), block_vec)
+            }
+            None => self.add_with_codec(
+                options,
+                &BincodeCodec::new(options.encoding),
+                val,
+                block_vec,
+            ),
+        }
+    }
+
+    /// Add the value serialized with the supplied codec to the blocks being built.
+    /// Values stored in the value log are replaced by a pointer that is always
+    /// serialized with `bincode`.
+    pub fn add_with_codec<K: ResourceKey, V: ResourceValue, C: RecordCodec>(
+        &mut self,
+        options: &DharmaOpts,
+        codec: &C,
+        val: &Value<K, V>,
+        block_vec: &mut Vec<Block>,
+    ) -> Result<()> {
+        self.add_encoded_value(options, codec.id(), val, || codec.encode(val), block_vec)
+    }
+
+    // add the value serialized by `encode` with the codec of the supplied identifier
+    fn add_encoded_value<K, V, F>(
+        &mut self,
+        options: &DharmaOpts,
+        codec_id: u8,
+        val: &Value<K, V>,
+        encode: F,
+        block_vec: &mut Vec<Block>,
+    ) -> Result<()>
+    where
+        K: ResourceKey,
+        V: ResourceValue,
+        F: FnOnce() -> Result<Vec<u8>>,
+    {
+        let separated = val.pointer.is_some();
+        let mut encoded = match val.pointer {
+            // values stored in the value log are replaced by a pointer
+            Some(pointer) => options
+                .encoding
+                .serialize(&SeparatedValue {
+                    pointer,
+                    key: &val.key,
+                    seq: val.seq,
+                })
+                .map_err(|_| Errors::RECORD_SERIALIZATION_FAILED)?,
+            None => encode()?,
+        };
+        // values in the value log are transformed on their own
+        if let (false, Some(transform)) = (separated, &options.value_transform) {
+            encoded = transform.on_write(encoded)?;
+        }
+        // only compress records whose value is large enough to benefit from it
+        let mut compressed = false;
+        if let (false, Some(min_bytes)) = (separated, options.value_compression_min_bytes) {
+            let value_size = options
+                .encoding
+                .serialized_size(&val.value)
+                .map_err(|_| Errors::RECORD_SERIALIZATION_FAILED)?
+                as usize;
+            if value_size >= min_bytes {
+                encoded = compress_prepend_size(&encoded);
+                compressed = true;
+            }
+        }
+        // keys are encoded relative to the previous key in the block the record
+        // starts in so leftover space too small for a record is skipped first
+        if self.available_memory_in_bytes <= Record::RECORD_BASE_SIZE_IN_BYTES {
+            self.emit_block(options, block_vec);
+        }
+        if self.block_headers && self.first_key.is_none() {
+            self.reserve_first_key(options, &val.key, block_vec)?;
+        }
+        // only keys serialized with bincode can be told apart from the value
+        let prefix_compressed = options.prefix_compress_keys
+            && codec_id == BINCODE_CODEC_ID
+            && !separated
+            && !compressed
+            && options.value_transform.is_none();
+        if prefix_compressed {
+            let key_size = options
+                .encoding
+                .serialized_size(&val.key)
+                .map_err(|_| Errors::RECORD_SERIALIZATION_FAILED)?
+                as usize;
+            let key = encoded[..key_size].to_vec();
+            encoded = encode_key_prefix(&encoded, key_size, &self.previous_key);
+            self.previous_key = key;
+        }
+        let flags = RecordFlags {
+            compressed,
+            separated,
+            prefix_compressed,
+            range_tombstone: false,
+        };
+        self.add_encoded(options, encoded, flags, block_vec);
+        Ok(())
+    }
+
+    /// Add the range tombstone to the blocks being built. Blocks that are filled
+    /// by the tombstone are appended to the block list.
+    ///
+    /// # Returns
+    /// Result that resolves:
+    ///  - _Ok_ - () when the tombstone was added.
+    ///  - _Err_ - `RECORD_SERIALIZATION_FAILED` if the tombstone couldn't be serialized.
+    pub fn add_range_tombstone<K: ResourceKey>(
+        &mut self,
+        options: &DharmaOpts,
+        tombstone: &RangeTombstone<K>,
+        block_vec: &mut Vec<Block>,
+    ) -> Result<()> {
+        let encoded = options
+            .encoding
+            .serialize(tombstone)
+            .map_err(|_| Errors::RECORD_SERIALIZATION_FAILED)?;
+        let flags = RecordFlags {
+            range_tombstone: true,
+            ..RecordFlags::default()
+        };
+        self.add_encoded(options, encoded, flags, block_vec);
+        Ok(())
+    }
+
+    fn add_encoded(
+        &mut self,
+        options: &DharmaOpts,
+        encoded: Vec<u8>,
+        flags: RecordFlags,
+        block_vec: &mut Vec<Block>,
+    ) {
         // encoded is an array of 8 bit integers (u8)
         // each value in the array takes a byte of memory
         // therefore size of array in bytes is the size of this record in bytes
         let record_size = encoded.len();
         // each record needs at has a base size to hold
         let required_record_size = Record::RECORD_BASE_SIZE_IN_BYTES + record_size;
-        match available_memory_in_bytes.cmp(&required_record_size) {
-            // record will be broken into chunks
-            Ordering::Less => {
-                // decoder should skip reading memory in block
-                // if leftover data is less than Record::RECORD_BASE_SIZE_IN_BYTES
-                let mut record_offset = 0;
-                if available_memory_in_bytes > Record::RECORD_BASE_SIZE_IN_BYTES {
+        loop {
+            match self.available_memory_in_bytes.cmp(&required_record_size) {
+                // record will be broken into chunks
+                Ordering::Less => {
+                    // leftover space too small to hold a record header is filled with
+                    // zeroes when the block is written to disk, the record is then
+                    // placed in a fresh block
+                    if self.available_memory_in_bytes <= Record::RECORD_BASE_SIZE_IN_BYTES {
+                        self.emit_block(options, block_vec);
+                        // records that fit in the fresh block are stored whole
+                        if required_record_size <= self.available_memory_in_bytes {
+                            continue;
+                        }
+                    }
+                    // records larger than a block start their split right away instead
+                    // of being retried in the fresh block
+                    let mut record_offset = 0;
                     // flag specifying whether we are processing the first chunk of record
                     let mut is_first_chunk = true;
                     // records are broken into chunks
                     // in each iteration of this loop we process one chunk
-                    while available_memory_in_bytes > Record::RECORD_BASE_SIZE_IN_BYTES {
-                        available_memory_in_bytes -= Record::RECORD_BASE_SIZE_IN_BYTES;
+                    while self.available_memory_in_bytes > Record::RECORD_BASE_SIZE_IN_BYTES {
+                        self.available_memory_in_bytes -= Record::RECORD_BASE_SIZE_IN_BYTES;
                         let mut record_type = RecordType::START;
                         if !is_first_chunk {
                             record_type = RecordType::MIDDLE;
                         }
-                        let mut record_offset_end = record_offset + available_memory_in_bytes;
+                        let mut record_offset_end = record_offset + self.available_memory_in_bytes;
                         if record_offset_end >= record_size {
                             record_offset_end = record_size;
                             record_type = RecordType::END;
                         }
-                        let mut data_chunk: Vec<u8> = Vec::new();
-                        for i in record_offset..record_offset_end {
-                            data_chunk.push(encoded[i]);
-                        }
+                        let data_chunk: Vec<u8> =
+                            encoded[record_offset..record_offset_end].to_vec();
                         let processed_memory_in_bytes = record_offset_end - record_offset;
                         record_offset = record_offset_end;
                         let record = Record {
                             record_type,
                             data_size_in_bytes: data_chunk.len() as u16,
                             data: data_chunk,
+                            compressed: flags.compressed,
+                            separated: flags.separated,
+                            prefix_compressed: flags.prefix_compressed,
+                            range_tombstone: flags.range_tombstone,
                         };
-                        current_block.add(record);
+                        self.current_block.add(record);
                         // depending on record type determine whether new block has to be created
                         match record_type {
                             RecordType::END => {
                                 // we may not have exhausted all the space in the block
-                                available_memory_in_bytes -= processed_memory_in_bytes;
-                                // if we have exhausted all space then create a new block
-                                if available_memory_in_bytes == 0 {
-                                    block_vec.push(current_block);
-                                    current_block = Block::new();
-                                    available_memory_in_bytes = options.block_size_in_bytes;
+                                self.available_memory_in_bytes -= processed_memory_in_bytes;
+                                // if we have exhausted all space then create a new block,
+                                // the full block is written without a padding record
+                                if self.available_memory_in_bytes == 0 {
+                                    self.emit_block(options, block_vec);
                                 }
-                                // break since we have finished processing this value
-                                i += 1;
-                                break;
+                                // return since we have finished processing this value
+                                return;
                             }
                             // for start and middle blocks all space has been exhausted
                             _ => {
-                                block_vec.push(current_block);
-                                current_block = Block::new();
-                                available_memory_in_bytes = options.block_size_in_bytes;
+                                self.emit_block(options, block_vec);
                                 is_first_chunk = false;
                             }
                         }
                     }
-                } else {
-                    // bytes to pad is available space minus 1 byte to store record type(PADDING)
-                    let bytes_to_pad = available_memory_in_bytes - 1;
-                    let padding = Record::with_padding(bytes_to_pad as u16);
-                    current_block.add(padding);
-                    // create new block
-                    block_vec.push(current_block);
-                    current_block = Block::new();
-                    available_memory_in_bytes = options.block_size_in_bytes;
+                }
+                Ordering::Equal => {
+                    let record = Record {
+                        record_type: RecordType::COMPLETE,
+                        data_size_in_bytes: record_size as u16,
+                        data: encoded,
+                        compressed: flags.compressed,
+                        separated: flags.separated,
+                        prefix_compressed: flags.prefix_compressed,
+                        range_tombstone: flags.range_tombstone,
+                    };
+                    self.current_block.add(record);
+                    self.emit_block(options, block_vec);
+                    return;
+                }
+                Ordering::Greater => {
+                    let record = Record {
+                        record_type: RecordType::COMPLETE,
+                        data_size_in_bytes: record_size as u16,
+                        data: encoded,
+                        compressed: flags.compressed,
+                        separated: flags.separated,
+                        prefix_compressed: flags.prefix_compressed,
+                        range_tombstone: flags.range_tombstone,
+                    };
+                    self.current_block.add(record);
+                    self.available_memory_in_bytes -= required_record_size;
+                    return;
                 }
             }
-            Ordering::Equal => {
-                let record = Record {
-                    record_type: RecordType::COMPLETE,
-                    data_size_in_bytes: record_size as u16,
-                    data: encoded,
-                };
-                current_block.add(record);
-                block_vec.push(current_block);
-                current_block = Block::new();
-                available_memory_in_bytes = options.block_size_in_bytes;
-                i += 1;
-            }
-            Ordering::Greater => {
-                let record = Record {
-                    record_type: RecordType::COMPLETE,
-                    data_size_in_bytes: record_size as u16,
-                    data: encoded,
-                };
-                current_block.add(record);
-                available_memory_in_bytes -= required_record_size;
-                i += 1;
+        }
+    }
+
+    /// Reserve space in the header of the current block for the key of the first
+    /// value starting in it. The block is emitted first if the key and the start of
+    /// the value don't fit in it, keys that don't fit in an empty block are left out.
+    ///
+    /// # Returns
+    /// Result that resolves:
+    ///  - _Ok_ - If space for the key was reserved or the key was left out.
+    ///  - _Err_ - `RECORD_SERIALIZATION_FAILED` if the key couldn't be serialized.
+    fn reserve_first_key<K: ResourceKey>(
+        &mut self,
+        options: &DharmaOpts,
+        key: &K,
+        block_vec: &mut Vec<Block>,
+    ) -> Result<()> {
+        let key = options
+            .encoding
+            .serialize(key)
+            .map_err(|_| Errors::RECORD_SERIALIZATION_FAILED)?;
+        let required = key.len() + Record::RECORD_BASE_SIZE_IN_BYTES;
+        if self.available_memory_in_bytes <= required {
+            if self.current_block.records.is_empty()
+                || BlockBuilder::block_capacity(options) <= required
+            {
+                self.first_key = Some(Vec::new());
+                return Ok(());
             }
+            self.emit_block(options, block_vec);
         }
+        self.available_memory_in_bytes -= key.len();
+        self.first_key = Some(key);
+        Ok(())
     }
-    // blocks are added to the block list if they have no space left in them
-    // and a new block with no records committed is created
-    // if the current block has records in it then it represents a block
-    // that is not full and hasn't been added to the block list
-    if current_block.records.len() > 0 {
-        block_vec.push(current_block);
+
+    /// Check whether the padding of the partially filled block has room for a
+    /// footer of the supplied size. A builder without records always has room
+    /// since the footer is then written to a block of its own.
+    pub fn has_room_for_footer(&self, footer_size_in_bytes: usize) -> bool {
+        self.available_memory_in_bytes >= Record::RECORD_BASE_SIZE_IN_BYTES + footer_size_in_bytes
+    }
+
+    /// Append the partially filled block, if any, to the block list.
+    pub fn finish(mut self, block_vec: &mut Vec<Block>) {
+        // blocks are added to the block list if they have no space left in them
+        // and a new block with no records committed is created
+        // if the current block has records in it then it represents a block
+        // that is not full and hasn't been added to the block list
+        if self.current_block.records.len() > 0 {
+            block_vec.push(self.take_block());
+        }
+    }
+
+    fn emit_block(&mut self, options: &DharmaOpts, block_vec: &mut Vec<Block>) {
+        block_vec.push(self.take_block());
+        self.available_memory_in_bytes = BlockBuilder::block_capacity(options);
+        self.previous_key.clear();
+    }
+
+    // Take the current block placing its header in front of its records.
+    fn take_block(&mut self) -> Block {
+        let mut block = std::mem::replace(&mut self.current_block, Block::new());
+        let first_key = self.first_key.take();
+        if self.block_headers {
+            let header = BlockHeader {
+                records: block.records.len(),
+                first_key: first_key.unwrap_or_default(),
+            };
+            block.records.insert(0, header.to_record());
+        }
+        block
     }
 }
 
 pub fn write_block_to_disk(
     options: &DharmaOpts,
-    file_handle: &mut File,
+    file_handle: &mut dyn StorageFile,
     block: &Block,
 ) -> Result<()> {
+    write_block_with_footer(options, file_handle, block, &[]).map(|_| ())
+}
+
+/// Write the block to disk storing the footer at the end of the padding that fills
+/// up the block. Readers skip padding so the footer doesn't affect the records.
+///
+/// # Returns
+/// A `Result` which is
+/// - `Ok`: - Flag specifying whether the padding had room for the footer.
+/// - `Err`: - Error that occurred while writing the block.
+pub fn write_block_with_footer(
+    options: &DharmaOpts,
+    file_handle: &mut dyn StorageFile,
+    block: &Block,
+    footer: &[u8],
+) -> Result<bool> {
+    let mut footer_written = footer.is_empty();
     let mut written_size_in_bytes = 0;
     for record in &block.records {
         match record.record_type {
@@ -246,19 +850,18 @@ pub fn write_block_to_disk(
                 for i in 0..record.data_size_in_bytes {
                     padding_bytes.push(0u8);
                 }
-                file_handle.write(&final_bytes);
-                file_handle.write(padding_bytes.as_slice());
+                write_all(file_handle, &final_bytes)?;
+                write_all(file_handle, padding_bytes.as_slice())?;
                 written_size_in_bytes += final_bytes.len() + padding_bytes.len();
             }
             _ => {
-                let record_type = record.record_type as u8;
-                let type_bytes: [u8; 1] = record_type.to_be_bytes();
+                let type_bytes: [u8; 1] = record.type_byte().to_be_bytes();
                 let size_bytes: [u8; 2] = record.data_size_in_bytes.to_be_bytes();
                 let data_bytes: &[u8] = &record.data;
-                file_handle.write(&type_bytes);
-                file_handle.write(&size_bytes);
+                write_all(file_handle, &type_bytes)?;
+                write_all(file_handle, &size_bytes)?;
                 written_size_in_bytes += 3;
-                file_handle.write(data_bytes);
+                write_all(file_handle, data_bytes)?;
                 written_size_in_bytes += data_bytes.len();
             }
         }
@@ -275,18 +878,32 @@ pub fn write_block_to_disk(
             for _ in 0..available_space_in_bytes {
                 padding.push(0u8);
             }
+            if !footer_written && footer.len() <= padding.len() {
+                let footer_offset = padding.len() - footer.len();
+                padding[footer_offset..].copy_from_slice(footer);
+                footer_written = true;
+            }
             // TODO: merge these file system writes into a single call and benchmark performance
-            file_handle.write(&type_bytes);
-            file_handle.write(&size_bytes);
-            file_handle.write(padding.as_slice());
+            write_all(file_handle, &type_bytes)?;
+            write_all(file_handle, &size_bytes)?;
+            write_all(file_handle, padding.as_slice())?;
         } else {
             let mut padding: Vec<u8> = Vec::with_capacity(available_space_in_bytes as usize);
             for _ in 0..available_space_in_bytes {
                 padding.push(0u8);
             }
-            file_handle.write(padding.as_slice());
+            write_all(file_handle, padding.as_slice())?;
             available_space_in_bytes = 0;
         }
     }
-    Ok(())
+    Ok(footer_written)
+}
+
+/// Write all of the bytes to the file. A block that is only partially written
+/// would leave a malformed table behind so any failure is reported.
+fn write_all(file_handle: &mut dyn StorageFile, data: &[u8]) -> Result<()> {
+    file_handle.write(data).map_err(|error| {
+        log::error!("Failed to write block to storage: {}", error);
+        Errors::BLOCK_WRITE_FAILED
+    })
 }
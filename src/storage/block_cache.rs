@@ -0,0 +1,130 @@
+use std::collections::{HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+
+/// Counters describing the effectiveness of the block cache.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BlockCacheStats {
+    /// Number of block reads served from the cache.
+    pub hits: u64,
+    /// Number of block reads that had to go to storage.
+    pub misses: u64,
+    /// Number of blocks currently held in the cache.
+    pub blocks: usize,
+}
+
+impl BlockCacheStats {
+    /// Get the fraction of block reads served from the cache.
+    ///
+    /// # Returns
+    /// Optional that contains the ratio unless no blocks were read.
+    pub fn hit_ratio(&self) -> Option<f64> {
+        let reads = self.hits + self.misses;
+        if reads == 0 {
+            return None;
+        }
+        Some(self.hits as f64 / reads as f64)
+    }
+}
+
+/// Cache of blocks read from SSTables keyed by table path and block offset.
+/// When the cache is full the least recently used block is evicted.
+pub struct BlockCache {
+    capacity: usize,
+    blocks: HashMap<(PathBuf, usize), Vec<u8>>,
+    // keys ordered from least to most recently used
+    usage: VecDeque<(PathBuf, usize)>,
+    hits: u64,
+    misses: u64,
+}
+
+impl BlockCache {
+    /// Create a block cache that holds at most `capacity` blocks.
+    /// A cache with zero capacity never stores blocks.
+    pub fn new(capacity: usize) -> BlockCache {
+        BlockCache {
+            capacity,
+            blocks: HashMap::new(),
+            usage: VecDeque::new(),
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    /// Get the block of the table at the specified offset if it is cached.
+    pub fn get(&mut self, path: &Path, offset: usize) -> Option<Vec<u8>> {
+        let key = (path.to_path_buf(), offset);
+        let maybe_block = self.blocks.get(&key).cloned();
+        if maybe_block.is_some() {
+            self.hits += 1;
+            self.touch(key);
+        } else {
+            self.misses += 1;
+        }
+        maybe_block
+    }
+
+    /// Copy the block of the table at the specified offset into the buffer if it is
+    /// cached. This avoids allocating a copy of the block.
+    ///
+    /// # Returns
+    /// Flag specifying whether the block was cached.
+    pub fn read_into(&mut self, path: &Path, offset: usize, buffer: &mut [u8]) -> bool {
+        let key = (path.to_path_buf(), offset);
+        let found = match self.blocks.get(&key) {
+            Some(block) if block.len() == buffer.len() => {
+                buffer.copy_from_slice(block);
+                true
+            }
+            _ => false,
+        };
+        if found {
+            self.hits += 1;
+            self.touch(key);
+        } else {
+            self.misses += 1;
+        }
+        found
+    }
+
+    /// Add the block of the table at the specified offset to the cache.
+    pub fn insert(&mut self, path: &Path, offset: usize, block: Vec<u8>) {
+        if self.capacity == 0 {
+            return;
+        }
+        let key = (path.to_path_buf(), offset);
+        if self.blocks.insert(key.clone(), block).is_none() {
+            while self.blocks.len() > self.capacity {
+                if let Some(evicted) = self.usage.pop_front() {
+                    self.blocks.remove(&evicted);
+                }
+            }
+        }
+        self.touch(key);
+    }
+
+    /// Remove all cached blocks belonging to the table at the supplied path.
+    pub fn invalidate(&mut self, path: &Path) {
+        self.blocks.retain(|(block_path, _), _| block_path != path);
+        self.usage.retain(|(block_path, _)| block_path != path);
+    }
+
+    /// Remove all cached blocks.
+    pub fn clear(&mut self) {
+        self.blocks.clear();
+        self.usage.clear();
+    }
+
+    /// Get the hit and miss counters of the cache.
+    pub fn stats(&self) -> BlockCacheStats {
+        BlockCacheStats {
+            hits: self.hits,
+            misses: self.misses,
+            blocks: self.blocks.len(),
+        }
+    }
+
+    fn touch(&mut self, key: (PathBuf, usize)) {
+        self.usage.retain(|used| used != &key);
+        self.usage.push_back(key);
+    }
+}
@@ -0,0 +1,111 @@
+use crate::result::{Errors, Result};
+use crate::storage::backend::StorageFile;
+use crate::storage::encoding::TABLE_FOOTER_SIZE_IN_BYTES;
+use crate::storage::manifest::{table_version_from_footer, TABLE_VERSION_FOOTER_SIZE_IN_BYTES};
+use xxhash_rust::xxh64::Xxh64;
+
+/// Marks the part of the SSTable footer recording the checksum of the table.
+/// It precedes the part of the footer recording the format version.
+const CHECKSUM_FOOTER_MAGIC: [u8; 4] = *b"DSUM";
+
+/// Size in bytes of the part of the footer recording the checksum. It is made up
+/// of `CHECKSUM_FOOTER_MAGIC` followed by the little endian checksum.
+pub const CHECKSUM_FOOTER_SIZE_IN_BYTES: usize = 12;
+
+/// Number of bytes hashed at once while verifying a table.
+const CHECKSUM_READ_SIZE_IN_BYTES: usize = 64 * 1024;
+
+/// Create the hasher used to compute the checksum of an SSTable.
+pub fn new_table_hasher() -> Xxh64 {
+    Xxh64::new(0)
+}
+
+/// Get the part of the footer recording the checksum. Tables are written with a
+/// checksum of zero which is replaced by `seal_table_checksum` once all bytes
+/// of the table are known.
+pub fn to_checksum_footer(checksum: u64) -> Vec<u8> {
+    let mut footer = CHECKSUM_FOOTER_MAGIC.to_vec();
+    footer.extend_from_slice(&checksum.to_le_bytes());
+    footer
+}
+
+/// Store the checksum of the table in its footer. The checksum is the xxHash of
+/// every byte of the table with the bytes of the checksum itself set to zero.
+///
+/// # Arguments
+///  - _hasher_ - The hasher that consumed all bytes written before the tail.
+///  - _tail_ - The last bytes of the table ending with the footer.
+pub fn seal_table_checksum(mut hasher: Xxh64, tail: &mut [u8]) {
+    hasher.update(tail);
+    let checksum_end = tail.len() - TABLE_FOOTER_SIZE_IN_BYTES - TABLE_VERSION_FOOTER_SIZE_IN_BYTES;
+    let checksum_start = checksum_end - std::mem::size_of::<u64>();
+    tail[checksum_start..checksum_end].copy_from_slice(&hasher.digest().to_le_bytes());
+}
+
+/// Get the size in bytes of the footer that follows the part recording the checksum.
+fn trailing_footer_size(file: &mut dyn StorageFile, size: usize) -> usize {
+    match table_version_from_footer(file, size) {
+        Some(_) => TABLE_VERSION_FOOTER_SIZE_IN_BYTES + TABLE_FOOTER_SIZE_IN_BYTES,
+        None => TABLE_FOOTER_SIZE_IN_BYTES,
+    }
+}
+
+/// Read the checksum recorded in the footer of the SSTable.
+///
+/// # Arguments
+///  - _file_ - The SSTable.
+///  - _size_ - The size of the SSTable in bytes.
+///
+/// # Returns
+/// Optional that contains the checksum unless the table has no checksum footer.
+pub fn checksum_from_footer(file: &mut dyn StorageFile, size: usize) -> Option<u64> {
+    let offset =
+        size.checked_sub(CHECKSUM_FOOTER_SIZE_IN_BYTES + trailing_footer_size(file, size))?;
+    let mut footer = [0u8; CHECKSUM_FOOTER_SIZE_IN_BYTES];
+    match file.read_at(&mut footer, offset as u64) {
+        Ok(read) if read == CHECKSUM_FOOTER_SIZE_IN_BYTES => {}
+        _ => return None,
+    }
+    if footer[..CHECKSUM_FOOTER_MAGIC.len()] != CHECKSUM_FOOTER_MAGIC {
+        return None;
+    }
+    let mut checksum = [0u8; 8];
+    checksum.copy_from_slice(&footer[CHECKSUM_FOOTER_MAGIC.len()..]);
+    Some(u64::from_le_bytes(checksum))
+}
+
+/// Compute the checksum of the SSTable, see `seal_table_checksum`.
+///
+/// # Arguments
+///  - _file_ - The SSTable.
+///  - _size_ - The size of the SSTable in bytes.
+///
+/// # Returns
+/// Result that resolves:
+///  - _Ok_ - The checksum of the table.
+///  - _Err_ - Error if the table has no checksum footer or could not be read.
+pub fn compute_table_checksum(file: &mut dyn StorageFile, size: usize) -> Result<u64> {
+    checksum_from_footer(file, size).ok_or(Errors::SSTABLE_READ_FAILED)?;
+    let checksum_end = size - trailing_footer_size(file, size);
+    let checksum_start = checksum_end - std::mem::size_of::<u64>();
+    let mut hasher = new_table_hasher();
+    let mut buffer = vec![0u8; CHECKSUM_READ_SIZE_IN_BYTES];
+    let mut offset = 0;
+    while offset < size {
+        let length = std::cmp::min(buffer.len(), size - offset);
+        let chunk = &mut buffer[..length];
+        let read = file
+            .read_at(chunk, offset as u64)
+            .map_err(|_| Errors::SSTABLE_READ_FAILED)?;
+        if read != length {
+            return Err(Errors::SSTABLE_READ_FAILED);
+        }
+        // the checksum was computed while its own bytes were zero
+        for position in checksum_start.max(offset)..checksum_end.min(offset + length) {
+            chunk[position - offset] = 0;
+        }
+        hasher.update(chunk);
+        offset += length;
+    }
+    Ok(hasher.digest())
+}
@@ -0,0 +1,261 @@
+use crate::options::DharmaOpts;
+use crate::result::{Errors, Result};
+use crate::storage::backend::StorageFile;
+use crate::storage::checksum::{checksum_from_footer, CHECKSUM_FOOTER_SIZE_IN_BYTES};
+use crate::storage::block::Value;
+use crate::storage::encoding::{Encoding, TABLE_FOOTER_SIZE_IN_BYTES};
+use crate::storage::manifest::{table_version_from_footer, TABLE_VERSION_FOOTER_SIZE_IN_BYTES};
+use crate::traits::{ResourceKey, ResourceValue};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::any::Any;
+use std::sync::Arc;
+
+/// Identifier of the `BincodeCodec` recorded in the footer of SSTables.
+pub const BINCODE_CODEC_ID: u8 = 0;
+
+/// Marks the part of the footer recording the codec the records of an SSTable
+/// were serialized with. It precedes the footer recording the checksum of the table
+/// and is only written for codecs other than the `BincodeCodec`.
+const CODEC_FOOTER_MAGIC: [u8; 4] = *b"DCOD";
+
+/// Size in bytes of the part of the footer recording the codec. It is made up of
+/// `CODEC_FOOTER_MAGIC` followed by the identifier of the codec.
+pub const CODEC_FOOTER_SIZE_IN_BYTES: usize = 5;
+
+/// Serializes the records stored in SSTables. Implement this trait to store
+/// records with an existing serialization of the key and value types.
+/// Keys of records serialized with a codec other than the `BincodeCodec` are
+/// never prefix compressed.
+pub trait RecordCodec {
+    /// Get the identifier recorded in the footer of tables written with this codec.
+    /// Tables are only read back by a codec with the same identifier.
+    fn id(&self) -> u8;
+
+    /// Serialize the record.
+    ///
+    /// # Arguments
+    /// * _value_ - The record to serialize.
+    ///
+    /// # Returns
+    /// Result that resolves:
+    ///  - _Ok_ - The serialized record.
+    ///  - _Err_ - Error specifying why the record couldn't be serialized.
+    fn encode<K: Serialize, V: Serialize>(&self, value: &Value<K, V>) -> Result<Vec<u8>>;
+
+    /// Deserialize a record serialized by `encode`.
+    ///
+    /// # Arguments
+    /// * _data_ - The serialized record.
+    ///
+    /// # Returns
+    /// Result that resolves:
+    ///  - _Ok_ - The deserialized record.
+    ///  - _Err_ - Error specifying why the record couldn't be deserialized.
+    fn decode<K: DeserializeOwned, V: DeserializeOwned>(&self, data: &[u8]) -> Result<Value<K, V>>;
+}
+
+/// The default codec serializing records with `bincode` in the supplied encoding.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct BincodeCodec {
+    encoding: Encoding,
+}
+
+impl BincodeCodec {
+    pub fn new(encoding: Encoding) -> BincodeCodec {
+        BincodeCodec { encoding }
+    }
+}
+
+impl RecordCodec for BincodeCodec {
+    fn id(&self) -> u8 {
+        BINCODE_CODEC_ID
+    }
+
+    fn encode<K: Serialize, V: Serialize>(&self, value: &Value<K, V>) -> Result<Vec<u8>> {
+        self.encoding
+            .serialize(value)
+            .map_err(|_| Errors::RECORD_SERIALIZATION_FAILED)
+    }
+
+    fn decode<K: DeserializeOwned, V: DeserializeOwned>(&self, data: &[u8]) -> Result<Value<K, V>> {
+        self.encoding
+            .deserialize(data)
+            .map_err(|_| Errors::RECORD_DESERIALIZATION_FAILED)
+    }
+}
+
+/// A record codec registered independently of the key and value types so that it
+/// can be stored in `DharmaOpts`. See `DharmaOpts::record_codec`.
+#[derive(Clone)]
+pub struct RegisteredRecordCodec {
+    id: u8,
+    // holds an `Arc<dyn TypedRecordCodec<K, V>>`
+    codec: Arc<dyn Any + Send + Sync>,
+    // holds a `KeyDecoder<K>` so that keys are read without the value type
+    key_decoder: Arc<dyn Any + Send + Sync>,
+}
+
+// reads the key of a record by deserializing the whole record
+type KeyDecoder<K> = Arc<dyn Fn(&[u8]) -> Result<K> + Send + Sync>;
+
+// Serializes the records of a single key and value type. Unlike `RecordCodec` it
+// can be used as a trait object.
+trait TypedRecordCodec<K, V>: Send + Sync {
+    fn encode(&self, value: &Value<K, V>) -> Result<Vec<u8>>;
+
+    fn decode(&self, data: &[u8]) -> Result<Value<K, V>>;
+}
+
+impl<K, V, C> TypedRecordCodec<K, V> for C
+where
+    K: ResourceKey,
+    V: ResourceValue,
+    C: RecordCodec + Send + Sync,
+{
+    fn encode(&self, value: &Value<K, V>) -> Result<Vec<u8>> {
+        RecordCodec::encode(self, value)
+    }
+
+    fn decode(&self, data: &[u8]) -> Result<Value<K, V>> {
+        RecordCodec::decode(self, data)
+    }
+}
+
+impl RegisteredRecordCodec {
+    /// Register the codec for values of type `V` associated with keys of type `K`.
+    pub fn new<K, V, C>(codec: C) -> RegisteredRecordCodec
+    where
+        K: ResourceKey,
+        V: ResourceValue,
+        C: RecordCodec + Send + Sync + 'static,
+    {
+        let id = codec.id();
+        let codec: Arc<dyn TypedRecordCodec<K, V>> = Arc::new(codec);
+        let record_codec = codec.clone();
+        let key_decoder: KeyDecoder<K> =
+            Arc::new(move |data| record_codec.decode(data).map(|record| record.key));
+        RegisteredRecordCodec {
+            id,
+            codec: Arc::new(codec),
+            key_decoder: Arc::new(key_decoder),
+        }
+    }
+
+    /// Get the identifier recorded in the footer of tables written with the codec.
+    pub fn id(&self) -> u8 {
+        self.id
+    }
+
+    /// Serialize the record with the codec.
+    ///
+    /// # Arguments
+    /// * _value_ - The record to serialize.
+    ///
+    /// # Returns
+    /// Result that resolves:
+    ///  - _Ok_ - The serialized record.
+    ///  - _Err_ - `RECORD_SERIALIZATION_FAILED` if the codec was registered for
+    ///    other key or value types, otherwise the error returned by the codec.
+    pub fn encode<K: ResourceKey, V: ResourceValue>(&self, value: &Value<K, V>) -> Result<Vec<u8>> {
+        self.codec
+            .downcast_ref::<Arc<dyn TypedRecordCodec<K, V>>>()
+            .ok_or(Errors::RECORD_SERIALIZATION_FAILED)?
+            .encode(value)
+    }
+
+    /// Deserialize a record serialized by `encode`.
+    ///
+    /// # Arguments
+    /// * _data_ - The serialized record.
+    ///
+    /// # Returns
+    /// Result that resolves:
+    ///  - _Ok_ - The deserialized record.
+    ///  - _Err_ - `RECORD_DESERIALIZATION_FAILED` if the codec was registered for
+    ///    other key or value types, otherwise the error returned by the codec.
+    pub fn decode<K: ResourceKey, V: ResourceValue>(&self, data: &[u8]) -> Result<Value<K, V>> {
+        self.codec
+            .downcast_ref::<Arc<dyn TypedRecordCodec<K, V>>>()
+            .ok_or(Errors::RECORD_DESERIALIZATION_FAILED)?
+            .decode(data)
+    }
+
+    /// Deserialize the key of a record serialized by `encode`. Keys can't be told
+    /// apart from the value by other codecs than the `BincodeCodec` so the whole
+    /// record is deserialized.
+    ///
+    /// # Arguments
+    /// * _data_ - The serialized record.
+    ///
+    /// # Returns
+    /// Result that resolves:
+    ///  - _Ok_ - The key of the record.
+    ///  - _Err_ - `RECORD_DESERIALIZATION_FAILED` if the codec was registered for
+    ///    other key types, otherwise the error returned by the codec.
+    pub fn decode_key<K: ResourceKey>(&self, data: &[u8]) -> Result<K> {
+        let key_decoder = self
+            .key_decoder
+            .downcast_ref::<KeyDecoder<K>>()
+            .ok_or(Errors::RECORD_DESERIALIZATION_FAILED)?;
+        key_decoder(data)
+    }
+}
+
+/// Get the identifier of the codec records are serialized with by the database,
+/// which is the `BincodeCodec` unless `DharmaOpts::record_codec` is set.
+pub fn record_codec_id(options: &DharmaOpts) -> u8 {
+    options
+        .record_codec
+        .as_ref()
+        .map_or(BINCODE_CODEC_ID, RegisteredRecordCodec::id)
+}
+
+/// Get the part of the footer recording the codec with the supplied identifier.
+/// Nothing is recorded for the `BincodeCodec` so that tables written with it
+/// are unchanged.
+pub fn to_codec_footer(codec_id: u8) -> Vec<u8> {
+    if codec_id == BINCODE_CODEC_ID {
+        return Vec::new();
+    }
+    let mut footer = CODEC_FOOTER_MAGIC.to_vec();
+    footer.push(codec_id);
+    footer
+}
+
+/// Read the identifier of the codec the records of the SSTable were serialized with.
+///
+/// # Arguments
+///  - _file_ - The SSTable.
+///  - _size_ - The size of the SSTable in bytes.
+///
+/// # Returns
+/// The identifier of the codec, tables without a codec footer were written
+/// with the `BincodeCodec`.
+pub fn codec_from_footer(file: &mut dyn StorageFile, size: usize) -> u8 {
+    let version_footer_size = match table_version_from_footer(file, size) {
+        Some(_) => TABLE_VERSION_FOOTER_SIZE_IN_BYTES,
+        None => 0,
+    };
+    let checksum_footer_size = match checksum_from_footer(file, size) {
+        Some(_) => CHECKSUM_FOOTER_SIZE_IN_BYTES,
+        None => 0,
+    };
+    let footer_size = CODEC_FOOTER_SIZE_IN_BYTES
+        + checksum_footer_size
+        + version_footer_size
+        + TABLE_FOOTER_SIZE_IN_BYTES;
+    let offset = match size.checked_sub(footer_size) {
+        Some(offset) => offset,
+        None => return BINCODE_CODEC_ID,
+    };
+    let mut footer = [0u8; CODEC_FOOTER_SIZE_IN_BYTES];
+    match file.read_at(&mut footer, offset as u64) {
+        Ok(read) if read == CODEC_FOOTER_SIZE_IN_BYTES => {}
+        _ => return BINCODE_CODEC_ID,
+    }
+    if footer[..CODEC_FOOTER_MAGIC.len()] != CODEC_FOOTER_MAGIC {
+        return BINCODE_CODEC_ID;
+    }
+    footer[CODEC_FOOTER_MAGIC.len()]
+}
@@ -6,6 +6,8 @@ use std::fmt::{Debug, Display, Formatter};
 pub enum CompactionErrors {
     INVALID_COMPACTION_INPUT_PATH,
     INVALID_COMPACTION_OUTPUT_PATH,
+    INVALID_COMPACTION_INPUT_RECORD,
+    COMPACTION_FILTER_MISMATCH,
 }
 
 impl CompactionErrors {
@@ -17,6 +19,12 @@ impl CompactionErrors {
             CompactionErrors::INVALID_COMPACTION_OUTPUT_PATH => {
                 "Could not write SSTables to the supplied path"
             }
+            CompactionErrors::INVALID_COMPACTION_INPUT_RECORD => {
+                "Could not read a record from an SSTable being compacted"
+            }
+            CompactionErrors::COMPACTION_FILTER_MISMATCH => {
+                "Compaction filter was registered for other key or value types"
+            }
         }
     }
 }
@@ -1,17 +1,21 @@
 use crate::options::DharmaOpts;
+use crate::platform;
 use crate::result::Errors;
-use crate::storage::block::Value;
+use crate::storage::block::{RangeTombstone, Value};
 use crate::storage::compaction::basic::errors::{CompactionError, CompactionErrors};
-use crate::storage::compaction::CompactionStrategy;
+use crate::storage::compaction::{CompactionFilter, CompactionStrategy};
+use crate::storage::encoding::Encoding;
 use crate::storage::sorted_string_table_reader::SSTableReader;
-use crate::storage::sorted_string_table_writer::{write_sstable, write_sstable_at_path};
+use crate::storage::sorted_string_table_writer::SSTableWriter;
+use crate::storage::value_log::ValueLog;
 use crate::traits::{ResourceKey, ResourceValue};
+use std::cell::{Cell, RefCell};
 use std::cmp::{Ordering, Reverse};
-use std::collections::{BinaryHeap, HashMap};
-use std::fs::{create_dir_all, File};
-use std::io::Write;
+use std::collections::{BinaryHeap, VecDeque};
 use std::panic::resume_unwind;
-use std::path::{Path, PathBuf};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::thread;
 
 pub mod errors;
 
@@ -27,6 +31,27 @@ pub struct BasicCompactionOpts {
     /// Number of SSTables at input path after which compaction is run to
     /// merge the SSTables into a single table.
     pub threshold: u8,
+    /// Total size in bytes of the SSTables at input path after which compaction
+    /// is run. When set this replaces the table count `threshold` so that the
+    /// trigger reflects the amount of data instead of the number of tables.
+    pub threshold_bytes: Option<u64>,
+    /// Number of bytes compaction reads or writes before yielding to other threads.
+    /// Compaction never yields when not set.
+    pub io_budget_bytes_per_op: Option<usize>,
+    /// Number of versions of each key retained in the compacted table.
+    pub versions_to_keep: usize,
+    /// Flag specifying whether only tables with overlapping key ranges are merged.
+    /// All tables are merged into a single cluster when not set.
+    pub merge_overlapping_only: bool,
+    /// Maximum number of compactions of disjoint key ranges run at once
+    /// by `compact_concurrently`.
+    pub max_concurrent_jobs: usize,
+    /// Maximum number of records written to each table by `compact_concurrently`.
+    /// Each cluster is compacted into a single table when not set.
+    pub max_records_per_output_table: Option<usize>,
+    /// Flag specifying whether compaction reports the space taken by identical values.
+    /// See `DeduplicationReport`.
+    pub report_duplicates: bool,
 }
 
 impl BasicCompactionOpts {
@@ -34,9 +59,162 @@ impl BasicCompactionOpts {
         BasicCompactionOpts {
             db_options: options.clone(),
             input_path: options.path.clone(),
-            output_path: format!("{}/compaction/compaction.db", options.path.clone()),
+            // the compacted table is written next to the tables it replaces so that
+            // it can be renamed into place atomically
+            output_path: options
+                .tables_path()
+                .join("compaction.tmp")
+                .to_string_lossy()
+                .into_owned(),
             block_size: options.block_size_in_bytes,
             threshold: 4,
+            threshold_bytes: options.compaction_threshold_bytes,
+            io_budget_bytes_per_op: options.compaction_io_budget_bytes_per_op,
+            versions_to_keep: options.versions_to_keep,
+            merge_overlapping_only: options.compaction_merge_overlapping_only,
+            max_concurrent_jobs: options.max_concurrent_compactions,
+            max_records_per_output_table: options.compaction_max_records_per_table,
+            report_duplicates: options.compaction_report_duplicates,
+        }
+    }
+
+    /// Open a reader over the table at the path with the block size of the compaction
+    /// and the storage backend, value transform and record codec of the database.
+    fn open_table(&self, path: &PathBuf) -> Result<SSTableReader, Errors> {
+        let backend = self.db_options.backend.as_ref();
+        let value_log_path = ValueLog::path(&self.db_options);
+        let mut reader =
+            SSTableReader::with_value_log(backend, path, self.block_size, &value_log_path)?;
+        reader.set_value_transform(self.db_options.value_transform.clone());
+        reader.set_record_codec(self.db_options.record_codec.clone());
+        Ok(reader)
+    }
+}
+
+/// Space taken by identical values found while compacting tables, collected when
+/// `BasicCompactionOpts::report_duplicates` is set.
+/// Superseded versions are dropped by compaction regardless, versions identical
+/// to the version superseding them are reported so that repeated writes of the
+/// same value can be told apart from updates. Runs of keys sharing a value are
+/// only reported, they are candidates for a dictionary encoding of values.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct DeduplicationReport {
+    /// Number of dropped versions whose value is identical byte for byte to
+    /// the version of the same key superseding them.
+    pub duplicate_versions: usize,
+    /// Size in bytes of the serialized records of the duplicate versions.
+    pub bytes_reclaimed: u64,
+    /// Number of runs of adjacent keys whose latest values are identical.
+    pub identical_value_runs: usize,
+    /// Number of keys in the runs of identical values.
+    pub keys_in_identical_runs: usize,
+    /// Size in bytes of the serialized values repeated by the keys following
+    /// the first key of each run.
+    pub repeated_value_bytes: u64,
+}
+
+impl DeduplicationReport {
+    fn add(&mut self, other: &DeduplicationReport) {
+        self.duplicate_versions += other.duplicate_versions;
+        self.bytes_reclaimed += other.bytes_reclaimed;
+        self.identical_value_runs += other.identical_value_runs;
+        self.keys_in_identical_runs += other.keys_in_identical_runs;
+        self.repeated_value_bytes += other.repeated_value_bytes;
+    }
+}
+
+/// Compares the serialized values merged by a compaction to fill a `DeduplicationReport`.
+struct Deduplication {
+    encoding: Encoding,
+    report: DeduplicationReport,
+    // serialized latest value of the last key and the length of its run
+    last_value: Option<(Vec<u8>, usize)>,
+}
+
+impl Deduplication {
+    fn new(encoding: Encoding) -> Deduplication {
+        Deduplication {
+            encoding,
+            report: DeduplicationReport::default(),
+            last_value: None,
+        }
+    }
+
+    /// Count the superseded versions identical to the version superseding them.
+    /// Versions are ordered from newest to oldest, the first `retained` are kept.
+    fn count_superseded<K: ResourceKey, V: ResourceValue>(
+        &mut self,
+        versions: &[(usize, Value<K, V>)],
+        retained: usize,
+    ) {
+        for pair in versions.windows(2).skip(retained.saturating_sub(1)) {
+            let newer = self.encoding.serialize(&pair[0].1.value);
+            let older = self.encoding.serialize(&pair[1].1.value);
+            if let (Ok(newer), Ok(older)) = (newer, older) {
+                if newer == older {
+                    self.report.duplicate_versions += 1;
+                    self.report.bytes_reclaimed +=
+                        self.encoding.serialized_size(&pair[1].1).unwrap_or(0);
+                }
+            }
+        }
+    }
+
+    /// Extend or end the run of identical values with the latest value of the next key.
+    /// Delete markers end runs.
+    fn count_latest<K: ResourceKey, V: ResourceValue>(&mut self, value: &Value<K, V>) {
+        let serialized = match value.is_tombstone() {
+            true => None,
+            false => self.encoding.serialize(&value.value).ok(),
+        };
+        let serialized = match serialized {
+            Some(serialized) => serialized,
+            None => {
+                self.last_value = None;
+                return;
+            }
+        };
+        if let Some((last, run)) = self.last_value.as_mut() {
+            if *last == serialized {
+                *run += 1;
+                if *run == 2 {
+                    self.report.identical_value_runs += 1;
+                    self.report.keys_in_identical_runs += 1;
+                }
+                self.report.keys_in_identical_runs += 1;
+                self.report.repeated_value_bytes += serialized.len() as u64;
+                return;
+            }
+        }
+        self.last_value = Some((serialized, 1));
+    }
+}
+
+/// Tracks the bytes read and written by a compaction and yields to other
+/// threads each time the budget is used up.
+struct IoBudget {
+    budget: Option<usize>,
+    used: usize,
+    steps: u64,
+}
+
+impl IoBudget {
+    fn new(budget: Option<usize>) -> IoBudget {
+        IoBudget {
+            budget,
+            used: 0,
+            steps: 1,
+        }
+    }
+
+    fn consume(&mut self, bytes: usize) {
+        if let Some(budget) = self.budget {
+            self.used += bytes;
+            while self.used >= budget.max(1) {
+                self.used -= budget.max(1);
+                self.steps += 1;
+                thread::yield_now();
+            }
         }
     }
 }
@@ -62,11 +240,12 @@ where
     V: ResourceValue,
 {
     fn cmp(&self, other: &Self) -> Ordering {
-        // two records are equal if their keys are equal
-        if self.value == other.value {
-            return self.idx.cmp(&other.idx);
-        }
-        self.value.key.cmp(&other.value.key)
+        // records with equal keys are ordered by the table they came from, keys are
+        // compared once since comparing them dominates the cost of heap operations
+        self.value
+            .key
+            .cmp(&other.value.key)
+            .then_with(|| self.idx.cmp(&other.idx))
     }
 }
 
@@ -93,10 +272,7 @@ where
     V: ResourceValue,
 {
     fn eq(&self, other: &Self) -> bool {
-        if self.idx != other.idx {
-            return false;
-        }
-        return self.value.key == other.value.key;
+        self.idx == other.idx && self.value.key == other.value.key
     }
 }
 
@@ -106,11 +282,41 @@ where
 /// compacts them into a single table that is written to the output path.
 pub struct BasicCompaction {
     options: BasicCompactionOpts,
+    // number of steps the last compaction was split into
+    last_steps: Cell<u64>,
+    // paths of the tables merged by the last compaction
+    last_merged: RefCell<Vec<PathBuf>>,
+    // identical values found by the last compaction
+    last_deduplication: Cell<Option<DeduplicationReport>>,
 }
 
 impl BasicCompaction {
     pub fn new(options: BasicCompactionOpts) -> BasicCompaction {
-        BasicCompaction { options }
+        BasicCompaction {
+            options,
+            last_steps: Cell::new(0),
+            last_merged: RefCell::new(Vec::new()),
+            last_deduplication: Cell::new(None),
+        }
+    }
+
+    /// Get the number of steps the last compaction was split into based on
+    /// the configured IO budget. A compaction without a budget runs in a single step.
+    pub fn last_compaction_steps(&self) -> u64 {
+        self.last_steps.get()
+    }
+
+    /// Get the paths of the tables that were merged by the last compaction.
+    /// These tables are replaced by the compacted table while all other tables
+    /// are left untouched.
+    pub fn last_merged_tables(&self) -> Vec<PathBuf> {
+        self.last_merged.borrow().clone()
+    }
+
+    /// Get the space taken by identical values found by the last compaction.
+    /// Not set unless `report_duplicates` is set.
+    pub fn last_deduplication_report(&self) -> Option<DeduplicationReport> {
+        self.last_deduplication.get()
     }
 }
 
@@ -122,100 +328,712 @@ impl BasicCompaction {
     pub fn compact<K: ResourceKey, V: ResourceValue>(
         &self,
     ) -> Result<Option<PathBuf>, CompactionError> {
-        let mut count: u64 = 0;
-        let input_path = &self.options.input_path;
-        // list all SSTables in the directory in sorted order
-        let sstable_paths_result = SSTableReader::get_valid_table_paths(input_path);
-        if sstable_paths_result.is_ok() {
-            let paths = sstable_paths_result.unwrap();
-            if paths.len() < self.options.threshold as usize {
-                return Ok(None);
+        let paths = match self.select_tables::<K, V>()? {
+            Some(paths) => paths,
+            None => return Ok(None),
+        };
+        let output_path = PathBuf::from(&self.options.output_path);
+        // a single table is written regardless of `max_records_per_output_table`
+        let (steps, _, deduplication) =
+            write_compacted_tables::<K, V>(&self.options, &paths, &output_path, None)?;
+        self.last_steps.set(steps);
+        self.last_deduplication.set(deduplication);
+        self.last_merged.replace(paths);
+        Ok(Some(output_path))
+    }
+
+    /// Compact up to `max_concurrent_jobs` clusters of overlapping tables at once.
+    /// Tables in different clusters hold disjoint key ranges and every table belongs
+    /// to a single cluster so no two compactions read the same table. Each cluster
+    /// is compacted on its own thread into a separate table, the largest clusters
+    /// are compacted first. Either all compactions succeed or no table is written.
+    /// A cluster is compacted into several tables with disjoint key ranges when
+    /// `max_records_per_output_table` is set.
+    ///
+    /// # Returns
+    /// Result that resolves:
+    ///  - _Ok_ - The paths of the tables compacted from each cluster in key order
+    ///    along with the paths of the tables they replace in their original order.
+    ///    Empty if no tables need to be compacted.
+    ///  - _Err_ - Error that occurred during any of the compactions.
+    pub fn compact_concurrently<K: ResourceKey, V: ResourceValue>(
+        &self,
+    ) -> Result<Vec<(Vec<PathBuf>, Vec<PathBuf>)>, CompactionError> {
+        let clusters = match self.select_clusters::<K, V>()? {
+            Some(clusters) => clusters,
+            None => return Ok(Vec::new()),
+        };
+        let jobs: Vec<(PathBuf, Vec<PathBuf>)> = clusters
+            .into_iter()
+            .take(std::cmp::max(self.options.max_concurrent_jobs, 1))
+            .enumerate()
+            .map(|(job, paths)| (self.job_output_path(job), paths))
+            .collect();
+        let options = &self.options;
+        let max_records = options.max_records_per_output_table;
+        type JobResult = Result<(u64, Vec<PathBuf>, Option<DeduplicationReport>), CompactionError>;
+        let results: Vec<JobResult> = if platform::threads(jobs.len()) == 1 {
+            jobs.iter()
+                .map(|(output_path, paths)| {
+                    write_compacted_tables::<K, V>(options, paths, output_path, max_records)
+                })
+                .collect()
+        } else {
+            thread::scope(|scope| {
+                let handles: Vec<_> = jobs
+                    .iter()
+                    .map(|(output_path, paths)| {
+                        scope.spawn(move || {
+                            write_compacted_tables::<K, V>(options, paths, output_path, max_records)
+                        })
+                    })
+                    .collect();
+                handles
+                    .into_iter()
+                    .map(|handle| handle.join().unwrap_or_else(|err| resume_unwind(err)))
+                    .collect()
+            })
+        };
+        let mut steps = 0;
+        let mut deduplication: Option<DeduplicationReport> = None;
+        let mut compacted = Vec::with_capacity(jobs.len());
+        let mut failure = None;
+        for (result, (_, paths)) in results.into_iter().zip(jobs) {
+            match result {
+                Ok((job_steps, output_paths, job_deduplication)) => {
+                    steps += job_steps;
+                    if let Some(job_deduplication) = job_deduplication {
+                        deduplication
+                            .get_or_insert_with(DeduplicationReport::default)
+                            .add(&job_deduplication);
+                    }
+                    compacted.push((output_paths, paths));
+                }
+                // a failed job removes the tables it wrote itself
+                Err(err) => {
+                    failure.get_or_insert(err);
+                }
+            }
+        }
+        if let Some(err) = failure {
+            // tables of the successful compactions are discarded as well
+            let backend = options.db_options.backend.as_ref();
+            for output_path in compacted.iter().flat_map(|(outputs, _)| outputs) {
+                if backend.exists(output_path) {
+                    let _ = backend.remove(output_path);
+                }
             }
-            let mut sstables: Vec<SSTableReader> = paths
+            return Err(err);
+        }
+        self.last_steps.set(steps);
+        self.last_deduplication.set(deduplication);
+        self.last_merged.replace(
+            compacted
                 .iter()
-                .map(|path| SSTableReader::from(path, self.options.block_size))
-                .map(Result::unwrap)
-                .collect();
-            // create new SSTable at output path
-            let output_path = Path::new(&self.options.output_path);
-            if output_path.parent().is_some() && !output_path.parent().unwrap().exists() {
-                create_dir_all(output_path.parent().unwrap());
+                .flat_map(|(_, paths)| paths.clone())
+                .collect(),
+        );
+        Ok(compacted)
+    }
+
+    /// Merge the tables that would be compacted next into a single stream of values
+    /// sorted by key without writing them to disk. Values of the same key are
+    /// resolved as they would be by `compact` and values deleted by range tombstones
+    /// are dropped. The stream is empty when no tables need to be compacted.
+    ///
+    /// # Returns
+    /// Result that resolves:
+    ///  - _Ok_ - Iterator over the merged keys and values. The iterator ends
+    ///    after yielding the first error.
+    ///  - _Err_ - Error that occurred while opening the tables.
+    pub fn merge_iter<K: ResourceKey, V: ResourceValue>(
+        &self,
+    ) -> Result<impl Iterator<Item = Result<(K, V), CompactionError>>, CompactionError> {
+        let paths = self.select_tables::<K, V>()?.unwrap_or_default();
+        let merged = merge_tables::<K, V>(&self.options, &paths, self.options.versions_to_keep)?;
+        Ok(merged.map(|value| value.map(Value::into_tuple)))
+    }
+
+    /// Merge the tables at the paths into a single stream holding the latest value
+    /// of each key sorted by key. Tables later in the list take precedence and values
+    /// deleted by range tombstones are dropped. Delete markers are kept since they
+    /// may still hide values in tables that are not merged.
+    ///
+    /// # Arguments
+    /// * _paths_ - The paths of the tables to merge ordered from oldest to newest.
+    ///
+    /// # Returns
+    /// Result that resolves:
+    ///  - _Ok_ - Iterator over the merged keys and values. The iterator ends
+    ///    after yielding the first error.
+    ///  - _Err_ - Error that occurred while opening the tables.
+    pub fn merge_latest_iter<K: ResourceKey, V: ResourceValue>(
+        &self,
+        paths: &Vec<PathBuf>,
+    ) -> Result<impl Iterator<Item = Result<(K, V), CompactionError>>, CompactionError> {
+        let merged = merge_tables::<K, V>(&self.options, paths, 1)?;
+        Ok(merged.map(|value| value.map(Value::into_tuple)))
+    }
+
+    /// Merge the tables at the paths like `merge_latest_iter` and tag each value
+    /// with the path of the table it was read from.
+    ///
+    /// # Arguments
+    /// * _paths_ - The paths of the tables to merge ordered from oldest to newest.
+    ///
+    /// # Returns
+    /// Result that resolves:
+    ///  - _Ok_ - Iterator over the merged keys and values along with the path of
+    ///    their table. The iterator ends after yielding the first error.
+    ///  - _Err_ - Error that occurred while opening the tables.
+    pub fn merge_latest_iter_with_source<K: ResourceKey, V: ResourceValue>(
+        &self,
+        paths: &Vec<PathBuf>,
+    ) -> Result<impl Iterator<Item = Result<(K, V, PathBuf), CompactionError>>, CompactionError>
+    {
+        let mut merged = merge_tables::<K, V>(&self.options, paths, 1)?;
+        let paths = paths.clone();
+        Ok(
+            std::iter::from_fn(move || merged.next_with_source()).map(move |entry| {
+                entry.map(|(idx, value)| {
+                    let (key, value) = value.into_tuple();
+                    (key, value, paths[idx].clone())
+                })
+            }),
+        )
+    }
+
+    /// Merge the tables at the paths into a single table at the output path holding
+    /// only the latest live value of each key. Values are streamed from the tables
+    /// to the output like with `compact` but delete markers are dropped as well, so
+    /// the tables must hold every value of the database. The output table is removed
+    /// if the merge fails.
+    ///
+    /// # Arguments
+    /// * _paths_ - The paths of the tables to merge ordered from oldest to newest.
+    /// * _output_path_ - The path to write the merged table to.
+    ///
+    /// # Returns
+    /// Result that resolves:
+    ///  - _Ok_ - Whether any live value was found, no table is written otherwise.
+    ///  - _Err_ - Error that occurred while merging or writing the tables.
+    pub fn consolidate<K: ResourceKey, V: ResourceValue>(
+        &self,
+        paths: &Vec<PathBuf>,
+        output_path: &PathBuf,
+    ) -> Result<bool, CompactionError> {
+        let merged = merge_tables::<K, V>(&self.options, paths, 1)?;
+        let mut live = merged.filter(|value| !matches!(value, Ok(value) if value.is_tombstone()));
+        let first = match live.next() {
+            Some(value) => value?,
+            None => return Ok(false),
+        };
+        let invalid_output =
+            |_| CompactionError::with(CompactionErrors::INVALID_COMPACTION_OUTPUT_PATH);
+        let mut writer =
+            SSTableWriter::create(&self.options.db_options, output_path).map_err(invalid_output)?;
+        let result = std::iter::once(Ok(first))
+            .chain(live)
+            .try_for_each(|value| writer.append(&value?).map_err(invalid_output))
+            .and_then(|_| writer.finish().map_err(invalid_output));
+        if result.is_err() {
+            let backend = self.options.db_options.backend.as_ref();
+            if backend.exists(output_path) {
+                let _ = backend.remove(output_path);
+            }
+        }
+        result.map(|_| true)
+    }
+
+    /// Get the paths of the tables to compact or `None` if the number of tables
+    /// is below the threshold or only overlapping tables are merged and none overlap.
+    fn select_tables<K: ResourceKey, V: ResourceValue>(
+        &self,
+    ) -> Result<Option<Vec<PathBuf>>, CompactionError> {
+        let clusters = self.select_clusters::<K, V>()?;
+        Ok(clusters.and_then(|clusters| clusters.into_iter().next()))
+    }
+
+    /// Get the paths of the tables in each cluster of overlapping tables largest
+    /// cluster first or `None` if the number or size of tables is below the
+    /// threshold or no tables overlap. All tables form a single cluster unless
+    /// only overlapping tables are merged.
+    fn select_clusters<K: ResourceKey, V: ResourceValue>(
+        &self,
+    ) -> Result<Option<Vec<Vec<PathBuf>>>, CompactionError> {
+        // list all SSTables in the directory in sorted order
+        let paths =
+            SSTableReader::get_table_paths(&self.options.db_options, &self.options.input_path)
+                .map_err(|_| {
+                    CompactionError::with(CompactionErrors::INVALID_COMPACTION_INPUT_PATH)
+                })?;
+        if !self.is_threshold_reached(&paths)? {
+            return Ok(None);
+        }
+        if !self.options.merge_overlapping_only {
+            return Ok(Some(vec![paths]));
+        }
+        // only tables whose key ranges overlap are merged
+        let clusters: Vec<Vec<PathBuf>> = self
+            .find_overlapping_tables::<K, V>(&paths)
+            .into_iter()
+            .filter(|cluster| cluster.len() >= 2)
+            .collect();
+        if clusters.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(clusters))
+    }
+
+    /// Check whether the tables at the paths reach the configured threshold on
+    /// their total size in bytes if set or on their number otherwise.
+    fn is_threshold_reached(&self, paths: &Vec<PathBuf>) -> Result<bool, CompactionError> {
+        let threshold_bytes = match self.options.threshold_bytes {
+            Some(threshold_bytes) => threshold_bytes,
+            None => return Ok(paths.len() >= self.options.threshold as usize),
+        };
+        let backend = self.options.db_options.backend.as_ref();
+        let mut total_bytes = 0;
+        for path in paths {
+            total_bytes += backend
+                .open(path)
+                .and_then(|file| file.size())
+                .map_err(|_| {
+                    CompactionError::with(CompactionErrors::INVALID_COMPACTION_INPUT_PATH)
+                })?;
+        }
+        Ok(total_bytes >= threshold_bytes)
+    }
+
+    /// Get the path at which the compacted table of the job is written.
+    /// The first job writes to the configured output path.
+    fn job_output_path(&self, job: usize) -> PathBuf {
+        if job == 0 {
+            return PathBuf::from(&self.options.output_path);
+        }
+        PathBuf::from(format!("{}.{}", self.options.output_path, job))
+    }
+    /// Group the tables into clusters of tables with overlapping key ranges and
+    /// get the paths of the tables in each cluster in their original order.
+    /// Tables with ranges that overlap a table in the cluster belong to the cluster.
+    /// Larger clusters are placed first, clusters of the same size are ordered by key.
+    fn find_overlapping_tables<K: ResourceKey, V: ResourceValue>(
+        &self,
+        paths: &Vec<PathBuf>,
+    ) -> Vec<Vec<PathBuf>> {
+        let mut ranges: Vec<(K, K, usize)> = Vec::new();
+        for (idx, path) in paths.iter().enumerate() {
+            let reader = self.options.open_table(path);
+            if let Some((first, last)) = reader.ok().and_then(|r| table_range::<K, V>(r)) {
+                ranges.push((first, last, idx));
+            }
+        }
+        ranges.sort_by(|a, b| a.0.cmp(&b.0));
+        let mut clusters: Vec<Vec<usize>> = Vec::new();
+        let mut cluster: Vec<usize> = Vec::new();
+        let mut cluster_end: Option<K> = None;
+        for (first, last, idx) in ranges {
+            let overlaps = cluster_end.as_ref().map_or(false, |end| &first <= end);
+            if !overlaps && !cluster.is_empty() {
+                clusters.push(cluster);
+                cluster = Vec::new();
+                cluster_end = None;
             }
-            let output_sstable_result = File::create(output_path);
-            if output_sstable_result.is_err() {
-                return Err(CompactionError::with(
-                    CompactionErrors::INVALID_COMPACTION_OUTPUT_PATH,
+            cluster.push(idx);
+            if cluster_end.as_ref().map_or(true, |end| &last > end) {
+                cluster_end = Some(last);
+            }
+        }
+        if !cluster.is_empty() {
+            clusters.push(cluster);
+        }
+        clusters.sort_by(|a, b| b.len().cmp(&a.len()));
+        clusters
+            .into_iter()
+            .map(|mut cluster| {
+                cluster.sort();
+                cluster.into_iter().map(|idx| paths[idx].clone()).collect()
+            })
+            .collect()
+    }
+}
+
+/// Open the tables at the paths and merge their values.
+fn merge_tables<K: ResourceKey, V: ResourceValue>(
+    options: &BasicCompactionOpts,
+    paths: &Vec<PathBuf>,
+    versions_to_keep: usize,
+) -> Result<MergeIterator<K, V>, CompactionError> {
+    let invalid_input = |_| CompactionError::with(CompactionErrors::INVALID_COMPACTION_INPUT_PATH);
+    let mut io_budget = IoBudget::new(options.io_budget_bytes_per_op);
+    // range tombstones are stored after the values of a table so they are
+    // collected up front to drop deleted values as the tables are merged
+    let mut tombstones: Vec<RangeTombstone<K>> = Vec::new();
+    for path in paths {
+        let reader = options.open_table(path).map_err(invalid_input)?;
+        read_range_tombstones(reader, &mut tombstones, &mut io_budget)?;
+    }
+    let mut sstables = Vec::with_capacity(paths.len());
+    let mut heap = BinaryHeap::new();
+    for (idx, path) in paths.iter().enumerate() {
+        let mut reader = options.open_table(path).map_err(invalid_input)?;
+        if let Some(record) = read_next_value(&mut reader, &mut io_budget)? {
+            heap.push(Reverse(CompactionHeapNode::new(record, idx)));
+        }
+        sstables.push(reader);
+    }
+    Ok(MergeIterator {
+        versions_to_keep,
+        sstables,
+        heap,
+        versions: Vec::new(),
+        pending: VecDeque::new(),
+        tombstones,
+        filter: None,
+        deduplication: None,
+        io_budget,
+        failed: false,
+    })
+}
+
+/// Merge the tables at the paths and write the merged values to tables starting
+/// with the one at the output path. Once a table holds `max_records` records the
+/// values of the next key are written to a new table at the output path suffixed
+/// with `.part<n>`, so the versions of a key always end up in the same table.
+/// The written tables are removed if the compaction fails.
+///
+/// # Returns
+/// Result that resolves:
+///  - _Ok_ - The number of steps the compaction was split into along with the
+///    paths of the written tables in key order and the identical values found
+///    if `report_duplicates` is set.
+///  - _Err_ - Error that occurred while merging or writing the tables.
+fn write_compacted_tables<K: ResourceKey, V: ResourceValue>(
+    options: &BasicCompactionOpts,
+    paths: &Vec<PathBuf>,
+    output_path: &PathBuf,
+    max_records: Option<usize>,
+) -> Result<(u64, Vec<PathBuf>, Option<DeduplicationReport>), CompactionError> {
+    let mut merged = merge_tables::<K, V>(options, paths, options.versions_to_keep)?;
+    if let Some(filter) = &options.db_options.compaction_filter {
+        let filter = filter
+            .get::<K, V>()
+            .ok_or_else(|| CompactionError::with(CompactionErrors::COMPACTION_FILTER_MISMATCH))?;
+        merged.filter = Some(filter);
+    }
+    if options.report_duplicates {
+        merged.deduplication = Some(Deduplication::new(options.db_options.encoding));
+    }
+    // create new SSTable at output path
+    let backend = options.db_options.backend.as_ref();
+    if let Some(parent) = output_path.parent() {
+        if !backend.exists(parent) {
+            backend.create_dir_all(parent).map_err(|_| {
+                CompactionError::with(CompactionErrors::INVALID_COMPACTION_OUTPUT_PATH)
+            })?;
+        }
+    }
+    let mut output_paths = vec![output_path.clone()];
+    let result = write_merged_values(options, &mut merged, &mut output_paths, max_records);
+    if let Err(err) = result {
+        for path in &output_paths {
+            if backend.exists(path) && backend.remove(path).is_err() {
+                options.db_options.log_warn(&format!(
+                    "Failed to remove the partially compacted table {}",
+                    path.display()
                 ));
             }
-            let size = sstables.len();
-            let mut output_sstable_handle = output_sstable_result.unwrap();
-            let mut valid: Vec<bool> = Vec::with_capacity(size);
-            for i in 0..size {
-                valid.push(true);
+        }
+        return Err(err);
+    }
+    let deduplication = merged
+        .deduplication
+        .as_ref()
+        .map(|deduplication| deduplication.report);
+    Ok((merged.io_budget.steps, output_paths, deduplication))
+}
+
+/// Write the merged values to the table at the last output path, adding the path
+/// of each new table to the output paths when the current one is full.
+fn write_merged_values<K: ResourceKey, V: ResourceValue>(
+    options: &BasicCompactionOpts,
+    merged: &mut MergeIterator<K, V>,
+    output_paths: &mut Vec<PathBuf>,
+    max_records: Option<usize>,
+) -> Result<(), CompactionError> {
+    let invalid_output =
+        |_| CompactionError::with(CompactionErrors::INVALID_COMPACTION_OUTPUT_PATH);
+    let first_path = output_paths[0].to_string_lossy().into_owned();
+    let mut writer =
+        SSTableWriter::create(&options.db_options, &output_paths[0]).map_err(invalid_output)?;
+    let mut records = 0;
+    let mut last_key: Option<K> = None;
+    // the budget is shared with the merge so the loop doesn't borrow the iterator
+    while let Some(value) = merged.next() {
+        let value = value?;
+        let new_key = last_key.as_ref() != Some(&value.key);
+        if new_key && max_records.map_or(false, |max| records >= max.max(1)) {
+            // the full table is finished before the next one is started so
+            // every table ends on a block boundary
+            finish_table(options, writer, &mut merged.io_budget)?;
+            let next_path = PathBuf::from(format!("{}.part{}", first_path, output_paths.len()));
+            output_paths.push(next_path);
+            writer = SSTableWriter::create(&options.db_options, output_paths.last().unwrap())
+                .map_err(invalid_output)?;
+            records = 0;
+        }
+        let blocks_written = writer.blocks_written();
+        writer.append(&value).map_err(invalid_output)?;
+        let written_blocks = writer.blocks_written() - blocks_written;
+        merged
+            .io_budget
+            .consume(written_blocks * options.db_options.block_size_in_bytes);
+        records += 1;
+        if new_key {
+            last_key = Some(value.key);
+        }
+    }
+    finish_table(options, writer, &mut merged.io_budget)
+}
+
+/// Finish the table written by the writer, charging the blocks written to finish
+/// it to the budget.
+fn finish_table(
+    options: &BasicCompactionOpts,
+    writer: SSTableWriter,
+    io_budget: &mut IoBudget,
+) -> Result<(), CompactionError> {
+    let blocks_written = writer.blocks_written();
+    let blocks = writer
+        .finish()
+        .map_err(|_| CompactionError::with(CompactionErrors::INVALID_COMPACTION_OUTPUT_PATH))?;
+    io_budget.consume((blocks - blocks_written) * options.db_options.block_size_in_bytes);
+    Ok(())
+}
+
+/// Move the newest `versions_to_keep` versions of a key into the result.
+/// Versions are ordered by sequence number with values from newer tables
+/// taking precedence. When only the latest version is retained an
+/// overwritten key whose latest value is a delete marker is dropped.
+/// Dropped versions identical to the version superseding them are counted
+/// by the deduplication when set.
+fn retain_versions<K: ResourceKey, V: ResourceValue>(
+    versions_to_keep: usize,
+    versions: &mut Vec<(usize, Value<K, V>)>,
+    result: &mut Vec<(usize, Value<K, V>)>,
+    deduplication: Option<&mut Deduplication>,
+) {
+    if versions.is_empty() {
+        return;
+    }
+    // values read later win among values with the same sequence number
+    versions.reverse();
+    versions.sort_by(|a, b| b.1.seq.cmp(&a.1.seq).then(b.0.cmp(&a.0)));
+    let overwritten = versions.len() > 1;
+    if let Some(deduplication) = deduplication {
+        deduplication.count_superseded(versions, std::cmp::max(versions_to_keep, 1));
+    }
+    let mut retained: Vec<(usize, Value<K, V>)> = versions
+        .drain(..)
+        .take(std::cmp::max(versions_to_keep, 1))
+        .collect();
+    if versions_to_keep <= 1 && overwritten && retained[0].1.is_tombstone() {
+        return;
+    }
+    result.append(&mut retained);
+}
+
+/// Get the smallest and largest key stored in the table.
+/// Range tombstones stored after the values widen the range by their bounds.
+fn table_range<K: ResourceKey, V: ResourceValue>(mut reader: SSTableReader) -> Option<(K, K)> {
+    let mut range: Option<(K, K)> = None;
+    while reader.has_next() {
+        let sstable_value = reader.read();
+        let (first, last) = if sstable_value.range_tombstone {
+            let tombstone = sstable_value.to_range_tombstone::<K>().ok()?;
+            (tombstone.start, tombstone.end)
+        } else {
+            let record: Value<K, V> = sstable_value.to_record().ok()?;
+            (record.key.clone(), record.key)
+        };
+        range = match range {
+            None => Some((first, last)),
+            Some((range_first, range_last)) => Some((
+                std::cmp::min(range_first, first),
+                std::cmp::max(range_last, last),
+            )),
+        };
+        reader.next();
+    }
+    range
+}
+
+/// Collect the range tombstones stored in the table.
+fn read_range_tombstones<K: ResourceKey>(
+    mut reader: SSTableReader,
+    tombstones: &mut Vec<RangeTombstone<K>>,
+    io_budget: &mut IoBudget,
+) -> Result<(), CompactionError> {
+    while reader.has_next() {
+        let sstable_value = reader.read();
+        io_budget.consume(sstable_value.data.len());
+        if sstable_value.range_tombstone {
+            let tombstone = sstable_value.to_range_tombstone().map_err(|_| {
+                CompactionError::with(CompactionErrors::INVALID_COMPACTION_INPUT_RECORD)
+            })?;
+            tombstones.push(tombstone);
+        }
+        reader.next();
+    }
+    Ok(())
+}
+
+/// Read the next value from the table advancing the reader past it.
+/// Range tombstones are skipped, see `read_range_tombstones`.
+///
+/// # Returns
+/// Result that resolves:
+///  - _Ok_ - Optional that contains the next value unless the table has no more values.
+///  - _Err_ - Error if the value could not be read.
+fn read_next_value<K: ResourceKey, V: ResourceValue>(
+    reader: &mut SSTableReader,
+    io_budget: &mut IoBudget,
+) -> Result<Option<Value<K, V>>, CompactionError> {
+    while reader.has_next() {
+        let sstable_value = reader.read();
+        io_budget.consume(sstable_value.data.len());
+        if sstable_value.range_tombstone {
+            reader.next();
+            continue;
+        }
+        let record = sstable_value.to_record().map_err(|_| {
+            CompactionError::with(CompactionErrors::INVALID_COMPACTION_INPUT_RECORD)
+        })?;
+        reader.next();
+        return Ok(Some(record));
+    }
+    if reader.read_failed() {
+        return Err(CompactionError::with(
+            CompactionErrors::INVALID_COMPACTION_INPUT_RECORD,
+        ));
+    }
+    Ok(None)
+}
+
+/// Merges the values of several tables into a single stream sorted by key.
+/// The retained versions of a key are buffered until the next key is reached.
+struct MergeIterator<K, V> {
+    // number of versions of each key retained, see `BasicCompactionOpts`
+    versions_to_keep: usize,
+    sstables: Vec<SSTableReader>,
+    // smallest unmerged value of each table
+    heap: BinaryHeap<Reverse<CompactionHeapNode<K, V>>>,
+    // versions of the key currently being merged along with the table they came from
+    versions: Vec<(usize, Value<K, V>)>,
+    // retained versions of the last merged key that have not been yielded yet
+    // along with the table they came from
+    pending: VecDeque<(usize, Value<K, V>)>,
+    // range tombstones of the merged tables
+    tombstones: Vec<RangeTombstone<K>>,
+    // filter dropping values as they are merged, see `DharmaOpts::compaction_filter`
+    filter: Option<Arc<dyn CompactionFilter<K, V>>>,
+    // counts identical values as they are merged, see `DeduplicationReport`
+    deduplication: Option<Deduplication>,
+    io_budget: IoBudget,
+    // whether reading a table failed, the stream ends after the error
+    failed: bool,
+}
+
+impl<K, V> MergeIterator<K, V>
+where
+    K: ResourceKey,
+    V: ResourceValue,
+{
+    /// Move the retained versions of the key being merged to the pending values.
+    fn retain_pending(&mut self) {
+        let mut retained = Vec::new();
+        retain_versions(
+            self.versions_to_keep,
+            &mut self.versions,
+            &mut retained,
+            self.deduplication.as_mut(),
+        );
+        let tombstones = &self.tombstones;
+        let filter = self.filter.as_ref();
+        // values deleted by a range tombstone are dropped along with the tombstones,
+        // tables that are not merged hold no keys within the ranges of merged tables
+        // so the tombstones can't delete values outside of the merge
+        self.pending
+            .extend(retained.into_iter().filter(|(_, value)| {
+                let filtered = filter.map_or(false, |filter| {
+                    !value.is_tombstone() && !filter.should_keep(&value.key, &value.value)
+                });
+                !filtered
+                    && !tombstones
+                        .iter()
+                        .any(|tombstone| tombstone.deletes(&value.key, value.seq, u64::MAX))
+            }));
+        // values are only retained once every pending value was yielded
+        if let (Some(deduplication), Some((_, latest))) =
+            (self.deduplication.as_mut(), self.pending.front())
+        {
+            deduplication.count_latest(latest);
+        }
+    }
+
+    /// Get the next merged value along with the index of the table it came from.
+    fn next_with_source(&mut self) -> Option<Result<(usize, Value<K, V>), CompactionError>> {
+        loop {
+            if let Some(entry) = self.pending.pop_front() {
+                return Some(Ok(entry));
+            }
+            if self.failed || (self.heap.is_empty() && self.versions.is_empty()) {
+                return None;
             }
-            let mut result = Vec::new();
-            let mut minimums: HashMap<usize, Value<K, V>> = HashMap::new();
-            // create heap to store values
-            let mut heap = BinaryHeap::new();
-            let mut prev_min: Option<Value<K, V>> = None;
-            for i in 0..size {
-                let sstable_value = sstables[i].read();
-                let record_result = sstable_value.to_record();
-                if record_result.is_ok() {
-                    let record: Value<K, V> = record_result.unwrap();
-                    heap.push(Reverse(CompactionHeapNode::new(record.clone(), i)));
-                    minimums.insert(i, record.clone());
-                    sstables[i].next();
+            let minimum_node = match self.heap.pop() {
+                Some(Reverse(node)) => node,
+                None => {
+                    self.retain_pending();
+                    continue;
                 }
+            };
+            let same = self
+                .versions
+                .last()
+                .map_or(false, |(_, previous)| previous.eq(&minimum_node.value));
+            if !same {
+                self.retain_pending();
             }
-            while !heap.is_empty() {
-                let minimum_node = heap.pop().unwrap().0;
-                let value = minimum_node.value.clone();
-                if prev_min.is_some() {
-                    let same = prev_min.as_ref().unwrap().eq(&value);
-                    if same {
-                        // pop previously pushed value and push in updated value
-                        result.pop();
-                        // push new value only if it is not a delete marker
-                        if value.value.clone() != V::nil() {
-                            result.push((value.key.clone(), value.value.clone()));
-                        }
-                    } else {
-                        result.push((value.key.clone(), value.value.clone()));
-                    }
-                    prev_min = Some(value.clone());
-                } else {
-                    prev_min = Some(value.clone());
-                    result.push((value.key.clone(), value.value.clone()));
+            let idx = minimum_node.idx;
+            self.versions.push((idx, minimum_node.value));
+            // advance the sstable pointer housing the minimum value
+            match read_next_value(&mut self.sstables[idx], &mut self.io_budget) {
+                Ok(Some(new_record)) => {
+                    self.heap
+                        .push(Reverse(CompactionHeapNode::new(new_record, idx)));
                 }
-                // advance the sstable pointer housing the minimum value
-                if sstables[minimum_node.idx].has_next() {
-                    let new_sstable_value = sstables[minimum_node.idx].read();
-                    let new_record_result = new_sstable_value.to_record();
-                    if new_record_result.is_ok() {
-                        let new_record: Value<K, V> = new_record_result.unwrap();
-                        minimums.insert(minimum_node.idx, new_record.clone());
-                        heap.push(Reverse(CompactionHeapNode::new(
-                            new_record.clone(),
-                            minimum_node.idx,
-                        )));
-                        sstables[minimum_node.idx].next();
-                    }
+                Ok(None) => {}
+                Err(err) => {
+                    self.failed = true;
+                    return Some(Err(err));
                 }
             }
-            write_sstable_at_path(
-                &self.options.db_options,
-                &result,
-                &PathBuf::from(&self.options.output_path),
-            );
-            return Ok(Some(PathBuf::from(&self.options.output_path)));
         }
-        Err(CompactionError::with(
-            CompactionErrors::INVALID_COMPACTION_INPUT_PATH,
-        ))
     }
 }
 
+impl<K, V> Iterator for MergeIterator<K, V>
+where
+    K: ResourceKey,
+    V: ResourceValue,
+{
+    type Item = Result<Value<K, V>, CompactionError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_with_source()
+            .map(|entry| entry.map(|(_, value)| value))
+    }
+}
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -227,9 +1045,16 @@ mod tests {
         assert_eq!(compaction_opts.input_path, dharma_opts.path);
         assert_eq!(
             compaction_opts.output_path,
-            format!("{}/compaction/compaction.db", dharma_opts.path)
+            format!("{}/tables/compaction.tmp", dharma_opts.path)
         );
         assert_eq!(compaction_opts.block_size, dharma_opts.block_size_in_bytes);
         assert_eq!(compaction_opts.threshold, 4);
+        assert_eq!(compaction_opts.threshold_bytes, None);
+        assert_eq!(compaction_opts.io_budget_bytes_per_op, None);
+        assert_eq!(compaction_opts.versions_to_keep, 1);
+        assert!(!compaction_opts.merge_overlapping_only);
+        assert_eq!(compaction_opts.max_concurrent_jobs, 1);
+        assert_eq!(compaction_opts.max_records_per_output_table, None);
+        assert!(!compaction_opts.report_duplicates);
     }
 }
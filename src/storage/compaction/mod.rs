@@ -1,3 +1,6 @@
+use std::any::Any;
+use std::sync::Arc;
+
 pub mod basic;
 
 /// Specifies the compaction strategy used to compact SSTables.
@@ -5,3 +8,45 @@ pub enum CompactionStrategy {
     /// Represents Basic compaction Strategy. See `BasicCompaction` for more details.
     BASIC,
 }
+
+/// Decides which values are kept when tables are compacted. Implement this trait
+/// to purge values matching an arbitrary predicate, for example values that expired,
+/// during the regular compaction pass. Register the filter with
+/// `DharmaOpts::compaction_filter`.
+/// Values read before compaction runs are unaffected and delete markers are never
+/// filtered. When several versions of a key are retained, dropping the newest version
+/// makes the next retained version the latest value of the key.
+pub trait CompactionFilter<K, V>: Send + Sync {
+    /// Check whether compaction keeps the value associated with the key.
+    fn should_keep(&self, key: &K, value: &V) -> bool;
+}
+
+/// A compaction filter registered independently of the key and value types so that
+/// it can be stored in `DharmaOpts`.
+#[derive(Clone)]
+pub struct RegisteredCompactionFilter {
+    // holds an `Arc<dyn CompactionFilter<K, V>>`
+    filter: Arc<dyn Any + Send + Sync>,
+}
+
+impl RegisteredCompactionFilter {
+    /// Register the filter for values of type `V` associated with keys of type `K`.
+    pub fn new<K, V, F>(filter: F) -> RegisteredCompactionFilter
+    where
+        K: 'static,
+        V: 'static,
+        F: CompactionFilter<K, V> + 'static,
+    {
+        let filter: Arc<dyn CompactionFilter<K, V>> = Arc::new(filter);
+        RegisteredCompactionFilter {
+            filter: Arc::new(filter),
+        }
+    }
+
+    /// Get the filter if it was registered for keys of type `K` and values of type `V`.
+    pub fn get<K: 'static, V: 'static>(&self) -> Option<Arc<dyn CompactionFilter<K, V>>> {
+        self.filter
+            .downcast_ref::<Arc<dyn CompactionFilter<K, V>>>()
+            .cloned()
+    }
+}
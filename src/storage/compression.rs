@@ -0,0 +1,15 @@
+/// Codecs that blocks of SSTables can be compressed with.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Compression {
+    /// The LZ4 block format, as used for values above `value_compression_min_bytes`.
+    Lz4,
+}
+
+impl Compression {
+    /// Compress the data with this codec.
+    pub fn compress(&self, data: &[u8]) -> Vec<u8> {
+        match self {
+            Compression::Lz4 => lz4_flex::compress(data),
+        }
+    }
+}
@@ -0,0 +1,122 @@
+use crate::options::DharmaOpts;
+use crate::result::{Errors, Result};
+use crate::storage::encoding::Encoding;
+use crate::storage::sorted_string_table_reader::SSTableReader;
+use crate::traits::{ResourceKey, ResourceValue};
+use serde::Serialize;
+use std::fmt::Display;
+use std::path::PathBuf;
+
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// How keys and values are rendered by debug dumps. Values that have no meaningful
+/// `Display` representation, like binary blobs, can be rendered from their
+/// serialized bytes instead.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DebugFormat {
+    /// Render keys and values with their `Display` implementation.
+    Display,
+    /// Render the serialized bytes of keys and values as lowercase hex.
+    Hex,
+    /// Render the serialized bytes of keys and values as padded standard base64.
+    Base64,
+}
+
+impl DebugFormat {
+    /// Render the key or value in this format.
+    ///
+    /// # Arguments
+    ///  - _value_ - The key or value to render.
+    ///  - _encoding_ - The encoding the bytes of the key or value are serialized with.
+    ///
+    /// # Returns
+    /// Result that resolves:
+    ///  - _Ok_ - The rendered key or value.
+    ///  - _Err_ - `RECORD_SERIALIZATION_FAILED` if the bytes to render couldn't be serialized.
+    pub fn render<T: Display + Serialize>(&self, value: &T, encoding: Encoding) -> Result<String> {
+        if let DebugFormat::Display = self {
+            return Ok(value.to_string());
+        }
+        let bytes = encoding
+            .serialize(value)
+            .map_err(|_| Errors::RECORD_SERIALIZATION_FAILED)?;
+        Ok(match self {
+            DebugFormat::Hex => to_hex(&bytes),
+            _ => to_base64(&bytes),
+        })
+    }
+}
+
+/// Render every record of the SSTable at the path, one line per record in the order
+/// they are stored. Values are rendered as `<offset>: <key> => <value> @<seq>` and
+/// range tombstones as `<offset>: [<start>, <end>) deleted @<seq>`.
+///
+/// # Arguments
+///  - _options_ - The database config.
+///  - _path_ - The path to the SSTable.
+///  - _format_ - How keys and values are rendered.
+///
+/// # Returns
+/// Result that resolves:
+///  - _Ok_ - The rendered records.
+///  - _Err_ - Error that occurred while reading the table.
+pub fn dump_table<K: ResourceKey, V: ResourceValue>(
+    options: &DharmaOpts,
+    path: &PathBuf,
+    format: DebugFormat,
+) -> Result<Vec<String>> {
+    let mut reader = SSTableReader::with_options(options, path)?;
+    let mut lines = Vec::new();
+    while reader.has_next() {
+        let sstable_value = reader.read();
+        let encoding = sstable_value.encoding;
+        let line = if sstable_value.range_tombstone {
+            let tombstone = sstable_value.to_range_tombstone::<K>()?;
+            format!(
+                "{}: [{}, {}) deleted @{}",
+                sstable_value.offset,
+                format.render(&tombstone.start, encoding)?,
+                format.render(&tombstone.end, encoding)?,
+                tombstone.seq
+            )
+        } else {
+            let record = sstable_value.to_record::<K, V>()?;
+            format!(
+                "{}: {} => {} @{}",
+                sstable_value.offset,
+                format.render(&record.key, encoding)?,
+                format.render(&record.value, encoding)?,
+                record.seq
+            )
+        };
+        lines.push(line);
+        reader.next();
+    }
+    if reader.read_failed() {
+        return Err(Errors::SSTABLE_READ_FAILED);
+    }
+    Ok(lines)
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn to_base64(bytes: &[u8]) -> String {
+    let mut output = String::with_capacity((bytes.len() + 2) / 3 * 4);
+    for chunk in bytes.chunks(3) {
+        let mut group = [0u8; 3];
+        group[..chunk.len()].copy_from_slice(chunk);
+        let bits = (group[0] as u32) << 16 | (group[1] as u32) << 8 | group[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                let index = (bits >> (18 - 6 * i)) & 0x3F;
+                output.push(BASE64_ALPHABET[index as usize] as char);
+            } else {
+                output.push('=');
+            }
+        }
+    }
+    output
+}
@@ -0,0 +1,199 @@
+use crate::options::DharmaOpts;
+use crate::result::{Errors, Result};
+use crate::traits::ResourceKey;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use xxhash_rust::xxh64::xxh64;
+
+const DELETION_AUDIT_NAME: &str = "DELETIONS";
+
+/// Size in bytes of the header of each deletion record. Records are made up of
+///
+/// | record size (4) | checksum (8) | deleted at (8) | key |
+///
+/// where the record size covers the whole record, the checksum is the xxHash of
+/// the deletion time and the key and the deletion time is stored in nanoseconds
+/// since the unix epoch. Integers are stored little endian.
+const DELETION_RECORD_HEADER_SIZE_IN_BYTES: usize = 20;
+
+/// Get the path of the table auditing deleted keys for the supplied database config.
+pub fn deletion_audit_path(options: &DharmaOpts) -> PathBuf {
+    PathBuf::from(format!("{0}/{1}", options.path, DELETION_AUDIT_NAME))
+}
+
+/// Append a deletion record for each key to the audit table at the database path.
+/// The table only ever grows, it is created when the first records are appended.
+/// Records left partially written by a failed append are truncated again.
+///
+/// # Arguments
+///  - _options_ - The database config.
+///  - _keys_ - The deleted keys.
+///  - _deleted_at_ - The time the keys were deleted.
+///
+/// # Returns
+/// Result that resolves:
+///  - _Ok_ - If the records were written and synced successfully.
+///  - _Err_ - `RECORD_SERIALIZATION_FAILED` if a key couldn't be serialized or
+///    `DELETION_AUDIT_WRITE_FAILED` if the records couldn't be written.
+pub fn append_deletions<K: ResourceKey>(
+    options: &DharmaOpts,
+    keys: &[K],
+    deleted_at: SystemTime,
+) -> Result<()> {
+    if keys.is_empty() {
+        return Ok(());
+    }
+    let nanos = deleted_at
+        .duration_since(UNIX_EPOCH)
+        .map(|since_epoch| since_epoch.as_nanos() as u64)
+        .unwrap_or(0);
+    let mut data = Vec::new();
+    for key in keys {
+        let serialized_key = options
+            .encoding
+            .serialize(key)
+            .map_err(|_| Errors::RECORD_SERIALIZATION_FAILED)?;
+        let size = DELETION_RECORD_HEADER_SIZE_IN_BYTES + serialized_key.len();
+        let mut payload = nanos.to_le_bytes().to_vec();
+        payload.extend_from_slice(&serialized_key);
+        data.extend_from_slice(&(size as u32).to_le_bytes());
+        data.extend_from_slice(&xxh64(&payload, 0).to_le_bytes());
+        data.extend_from_slice(&payload);
+    }
+    let path = deletion_audit_path(options);
+    let backend = options.backend.as_ref();
+    let created = !backend.exists(&path);
+    let mut file = if created {
+        backend.create(&path)
+    } else {
+        backend.open(&path)
+    }
+    .map_err(|_| Errors::DELETION_AUDIT_WRITE_FAILED)?;
+    let size = file
+        .size()
+        .map_err(|_| Errors::DELETION_AUDIT_WRITE_FAILED)?;
+    let write_result = file.write(&data).and_then(|_| file.sync());
+    if write_result.is_err() {
+        // later records are never appended after the torn ones
+        file.set_len(size)
+            .and_then(|_| file.sync())
+            .map_err(|_| Errors::DELETION_AUDIT_WRITE_FAILED)?;
+        return Err(Errors::DELETION_AUDIT_WRITE_FAILED);
+    }
+    // a new table is only durable once its directory entry is
+    if created {
+        backend
+            .sync_dir(&PathBuf::from(&options.path))
+            .map_err(|_| Errors::DELETION_AUDIT_WRITE_FAILED)?;
+    }
+    Ok(())
+}
+
+/// Truncate the audit table at the database path to its last whole record so that
+/// records appended later follow it. Records are left partially written when the
+/// database crashes while appending them.
+///
+/// # Arguments
+///  - _options_ - The database config.
+///
+/// # Returns
+/// Result that resolves:
+///  - _Ok_ - If the table holds whole records only or doesn't exist.
+///  - _Err_ - `DELETION_AUDIT_WRITE_FAILED` if the table couldn't be truncated.
+pub fn truncate_torn_deletions(options: &DharmaOpts) -> Result<()> {
+    let path = deletion_audit_path(options);
+    if !options.backend.exists(&path) {
+        return Ok(());
+    }
+    let mut file = options
+        .backend
+        .open(&path)
+        .map_err(|_| Errors::DELETION_AUDIT_WRITE_FAILED)?;
+    let size = file
+        .size()
+        .map_err(|_| Errors::DELETION_AUDIT_WRITE_FAILED)?;
+    let mut data = vec![0u8; size as usize];
+    file.read_at(&mut data, 0)
+        .map_err(|_| Errors::DELETION_AUDIT_WRITE_FAILED)?;
+    let records_end = decode_records(&data).len();
+    if records_end < data.len() {
+        options.log_warn(&format!(
+            "Truncating {} bytes of partially written deletion records",
+            data.len() - records_end
+        ));
+        file.set_len(records_end as u64)
+            .and_then(|_| file.sync())
+            .map_err(|_| Errors::DELETION_AUDIT_WRITE_FAILED)?;
+    }
+    Ok(())
+}
+
+/// Read the deletion records from the audit table at the database path in the
+/// order they were appended. Records following one left partially written by a
+/// crash while appending or that don't match their checksum are ignored.
+///
+/// # Arguments
+///  - _options_ - The database config.
+///
+/// # Returns
+/// Result that resolves:
+///  - _Ok_ - The deleted keys with the time they were deleted, empty if no
+///    deletion was recorded yet.
+///  - _Err_ - `DELETION_AUDIT_READ_FAILED` if the table couldn't be read or a key
+///    couldn't be deserialized.
+pub fn read_deletions<K: ResourceKey>(options: &DharmaOpts) -> Result<Vec<(K, SystemTime)>> {
+    let path = deletion_audit_path(options);
+    if !options.backend.exists(&path) {
+        return Ok(Vec::new());
+    }
+    let mut file = options
+        .backend
+        .open(&path)
+        .map_err(|_| Errors::DELETION_AUDIT_READ_FAILED)?;
+    let size = file
+        .size()
+        .map_err(|_| Errors::DELETION_AUDIT_READ_FAILED)?;
+    let mut data = vec![0u8; size as usize];
+    file.read_at(&mut data, 0)
+        .map_err(|_| Errors::DELETION_AUDIT_READ_FAILED)?;
+    let mut deletions = Vec::new();
+    let mut offset = 0;
+    let records_end = decode_records(&data).len();
+    while offset < records_end {
+        let record_size = record_size_at(&data, offset);
+        let mut nanos = [0u8; 8];
+        nanos.copy_from_slice(&data[offset + 12..offset + DELETION_RECORD_HEADER_SIZE_IN_BYTES]);
+        let key = options
+            .encoding
+            .deserialize(&data[offset + DELETION_RECORD_HEADER_SIZE_IN_BYTES..offset + record_size])
+            .map_err(|_| Errors::DELETION_AUDIT_READ_FAILED)?;
+        let deleted_at = UNIX_EPOCH + Duration::from_nanos(u64::from_le_bytes(nanos));
+        deletions.push((key, deleted_at));
+        offset += record_size;
+    }
+    Ok(deletions)
+}
+
+/// Get the part of the data made up of whole records matching their checksum.
+fn decode_records(data: &[u8]) -> &[u8] {
+    let mut offset = 0;
+    while offset + DELETION_RECORD_HEADER_SIZE_IN_BYTES <= data.len() {
+        let record_size = record_size_at(data, offset);
+        if record_size < DELETION_RECORD_HEADER_SIZE_IN_BYTES || offset + record_size > data.len() {
+            break;
+        }
+        let mut checksum = [0u8; 8];
+        checksum.copy_from_slice(&data[offset + 4..offset + 12]);
+        if xxh64(&data[offset + 12..offset + record_size], 0) != u64::from_le_bytes(checksum) {
+            break;
+        }
+        offset += record_size;
+    }
+    &data[..offset]
+}
+
+fn record_size_at(data: &[u8], offset: usize) -> usize {
+    let mut record_size = [0u8; 4];
+    record_size.copy_from_slice(&data[offset..offset + 4]);
+    u32::from_le_bytes(record_size) as usize
+}
@@ -0,0 +1,112 @@
+use crate::storage::backend::StorageFile;
+use bincode::Options;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+/// Marks the footer holding the encoding of the records in an SSTable.
+const TABLE_FOOTER_MAGIC: [u8; 4] = *b"DENC";
+
+/// Size in bytes of the footer at the end of an SSTable. The footer is made up
+/// of `TABLE_FOOTER_MAGIC` followed by a byte specifying the `Encoding`.
+pub const TABLE_FOOTER_SIZE_IN_BYTES: usize = 5;
+
+/// The bincode configuration used to serialize keys, values and range tombstones.
+/// Integers are stored little endian with either encoding.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Encoding {
+    /// Integers are stored with a fixed width. This matches `bincode::serialize`.
+    Fixint,
+    /// Integers are stored with as few bytes as their value requires. This
+    /// shrinks integer heavy keys and values at the cost of slower encoding.
+    Varint,
+}
+
+impl Encoding {
+    /// Serialize the value with this encoding.
+    pub fn serialize<T: ?Sized + Serialize>(&self, value: &T) -> bincode::Result<Vec<u8>> {
+        match self {
+            Encoding::Fixint => fixint().serialize(value),
+            Encoding::Varint => varint().serialize(value),
+        }
+    }
+
+    /// Deserialize a value from the start of the data with this encoding.
+    /// Bytes following the value are ignored.
+    pub fn deserialize<T: DeserializeOwned>(&self, data: &[u8]) -> bincode::Result<T> {
+        match self {
+            Encoding::Fixint => fixint().deserialize(data),
+            Encoding::Varint => varint().deserialize(data),
+        }
+    }
+
+    /// Get the size in bytes of the value serialized with this encoding.
+    pub fn serialized_size<T: ?Sized + Serialize>(&self, value: &T) -> bincode::Result<u64> {
+        match self {
+            Encoding::Fixint => fixint().serialized_size(value),
+            Encoding::Varint => varint().serialized_size(value),
+        }
+    }
+
+    /// Get the identifier recording this encoding in footers and streamed records.
+    pub fn id(&self) -> u8 {
+        match self {
+            Encoding::Fixint => 0,
+            Encoding::Varint => 1,
+        }
+    }
+
+    /// Get the encoding recorded with the identifier, see `id`.
+    pub fn from_id(id: u8) -> Option<Encoding> {
+        match id {
+            0 => Some(Encoding::Fixint),
+            1 => Some(Encoding::Varint),
+            _ => None,
+        }
+    }
+
+    /// Get the footer recording this encoding at the end of an SSTable.
+    pub fn to_footer(&self) -> Vec<u8> {
+        let mut footer = TABLE_FOOTER_MAGIC.to_vec();
+        footer.push(self.id());
+        footer
+    }
+
+    /// Read the encoding of the SSTable from its footer.
+    ///
+    /// # Arguments
+    ///  - _file_ - The SSTable.
+    ///  - _size_ - The size of the SSTable in bytes.
+    ///
+    /// # Returns
+    /// Optional that contains the encoding unless the table has no valid footer.
+    pub fn from_footer(file: &mut dyn StorageFile, size: usize) -> Option<Encoding> {
+        let offset = size.checked_sub(TABLE_FOOTER_SIZE_IN_BYTES)?;
+        let mut footer = [0u8; TABLE_FOOTER_SIZE_IN_BYTES];
+        match file.read_at(&mut footer, offset as u64) {
+            Ok(read) if read == TABLE_FOOTER_SIZE_IN_BYTES => {}
+            _ => return None,
+        }
+        if footer[..TABLE_FOOTER_MAGIC.len()] != TABLE_FOOTER_MAGIC {
+            return None;
+        }
+        Encoding::from_id(footer[TABLE_FOOTER_MAGIC.len()])
+    }
+}
+
+impl Default for Encoding {
+    fn default() -> Encoding {
+        Encoding::Fixint
+    }
+}
+
+fn fixint() -> impl Options {
+    bincode::options()
+        .with_fixint_encoding()
+        .allow_trailing_bytes()
+}
+
+fn varint() -> impl Options {
+    bincode::options()
+        .with_varint_encoding()
+        .allow_trailing_bytes()
+}
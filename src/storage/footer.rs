@@ -0,0 +1,227 @@
+use crate::storage::backend::StorageFile;
+use crate::storage::block::{RangeTombstone, Value};
+use crate::storage::checksum::{checksum_from_footer, CHECKSUM_FOOTER_SIZE_IN_BYTES};
+use crate::storage::codec::{codec_from_footer, BINCODE_CODEC_ID, CODEC_FOOTER_SIZE_IN_BYTES};
+use crate::storage::encoding::{Encoding, TABLE_FOOTER_SIZE_IN_BYTES};
+use crate::storage::manifest::{
+    table_version_from_footer, MIN_TABLE_FORMAT_VERSION, TABLE_VERSION_FOOTER_SIZE_IN_BYTES,
+};
+use crate::traits::ResourceKey;
+
+/// Marks the part of the footer summarizing the records of an SSTable. It precedes
+/// the footer recording the codec, or the checksum for tables of the `BincodeCodec`.
+const SUMMARY_FOOTER_MAGIC: [u8; 4] = *b"DSMY";
+
+/// Size in bytes of the part of the footer summarizing the records without the keys.
+/// It is made up of
+///
+/// | min key | max key | min key size (1) | max key size (1) | records (4) | block size (4) | `SUMMARY_FOOTER_MAGIC` |
+///
+/// with integers stored little endian.
+const SUMMARY_FOOTER_BASE_SIZE_IN_BYTES: usize = 14;
+
+/// Maximum size in bytes of each key recorded in the footer. The key range is
+/// left out of the footer when either key is larger.
+const MAX_SUMMARY_KEY_SIZE_IN_BYTES: usize = u8::MAX as usize;
+
+/// Number of bytes read from the end of an SSTable by `read_footer`. Footers
+/// are never larger so they are read at once.
+pub const FOOTER_READ_SIZE_IN_BYTES: usize = 1024;
+
+/// Summary of the records of an SSTable recorded in its footer so that tools can
+/// learn about a table without reading its blocks. See `TableFooter`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TableSummary {
+    /// Number of values and range tombstones stored in the table.
+    pub records: usize,
+    /// Smallest and largest key stored in the table serialized with the encoding
+    /// of the table. Range tombstones widen the range by their bounds.
+    pub key_range: Option<(Vec<u8>, Vec<u8>)>,
+}
+
+impl TableSummary {
+    /// Summarize the values sorted by key followed by the range tombstones.
+    pub fn of<K: ResourceKey, V>(
+        encoding: Encoding,
+        values: &[Value<K, V>],
+        tombstones: &[RangeTombstone<K>],
+    ) -> TableSummary {
+        let mut bounds = values
+            .first()
+            .zip(values.last())
+            .map(|(first, last)| (&first.key, &last.key));
+        for tombstone in tombstones {
+            bounds = Some(match bounds {
+                Some((min, max)) => (min.min(&tombstone.start), max.max(&tombstone.end)),
+                None => (&tombstone.start, &tombstone.end),
+            });
+        }
+        let key_range = bounds.and_then(|(min, max)| {
+            Some((encoding.serialize(min).ok()?, encoding.serialize(max).ok()?))
+        });
+        TableSummary {
+            records: values.len() + tombstones.len(),
+            key_range,
+        }
+    }
+
+    /// Add a value whose key is not smaller than the keys added before.
+    pub fn add<K: ResourceKey, V>(&mut self, encoding: Encoding, value: &Value<K, V>) {
+        self.records += 1;
+        let key = match encoding.serialize(&value.key) {
+            Ok(key) => key,
+            Err(_) => return,
+        };
+        match self.key_range.as_mut() {
+            Some((_, max)) => *max = key,
+            None => self.key_range = Some((key.clone(), key)),
+        }
+    }
+}
+
+/// Metadata recorded in the footer of an SSTable, read by `SSTableReader::read_footer`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TableFooter {
+    /// Size in bytes of the blocks of the table. Not set for tables written
+    /// before the summary was recorded.
+    pub block_size: Option<usize>,
+    /// Number of values and range tombstones stored in the table. Not set for
+    /// tables written before the summary was recorded.
+    pub records: Option<usize>,
+    /// Smallest and largest key stored in the table serialized with `encoding`.
+    /// Not set for empty tables, tables whose keys are too large to be recorded
+    /// and tables written before the summary was recorded.
+    pub key_range: Option<(Vec<u8>, Vec<u8>)>,
+    /// Checksum of the table, see `seal_table_checksum`.
+    pub checksum: Option<u64>,
+    /// Format version the table was written in.
+    pub format_version: u32,
+    /// Encoding the records of the table were serialized with.
+    pub encoding: Encoding,
+    /// Identifier of the codec the records of the table were serialized with.
+    pub codec_id: u8,
+}
+
+impl TableFooter {
+    /// Read the footer of the SSTable.
+    ///
+    /// # Arguments
+    ///  - _file_ - The SSTable or the bytes at its end holding the footer.
+    ///  - _size_ - The size of the file in bytes.
+    ///
+    /// # Returns
+    /// The footer, parts missing from the footer of older tables are left unset
+    /// or set to the value they had before they were recorded.
+    pub fn read(file: &mut dyn StorageFile, size: usize) -> TableFooter {
+        let summary = summary_from_footer(file, size);
+        TableFooter {
+            block_size: summary.as_ref().map(|(block_size, _)| *block_size),
+            records: summary.as_ref().map(|(_, summary)| summary.records),
+            key_range: summary.and_then(|(_, summary)| summary.key_range),
+            checksum: checksum_from_footer(file, size),
+            // tables written before the format version was recorded are in the first format
+            format_version: table_version_from_footer(file, size)
+                .unwrap_or(MIN_TABLE_FORMAT_VERSION),
+            // tables written before the encoding was configurable have no footer
+            encoding: Encoding::from_footer(file, size).unwrap_or_default(),
+            codec_id: codec_from_footer(file, size),
+        }
+    }
+
+    /// Deserialize the smallest and largest key stored in the table.
+    ///
+    /// # Returns
+    /// Optional that contains the keys unless the footer holds no key range or
+    /// the keys couldn't be deserialized.
+    pub fn decode_key_range<K: ResourceKey>(&self) -> Option<(K, K)> {
+        let (min, max) = self.key_range.as_ref()?;
+        Some((
+            self.encoding.deserialize(min).ok()?,
+            self.encoding.deserialize(max).ok()?,
+        ))
+    }
+}
+
+/// Get the part of the footer summarizing the records of a table with the
+/// supplied block size. The key range is left out when `with_keys` isn't set
+/// or a key is larger than `MAX_SUMMARY_KEY_SIZE_IN_BYTES`.
+pub fn to_summary_footer(block_size: usize, summary: &TableSummary, with_keys: bool) -> Vec<u8> {
+    let empty = Vec::new();
+    let (min, max) = match &summary.key_range {
+        Some((min, max))
+            if with_keys
+                && min.len() <= MAX_SUMMARY_KEY_SIZE_IN_BYTES
+                && max.len() <= MAX_SUMMARY_KEY_SIZE_IN_BYTES =>
+        {
+            (min, max)
+        }
+        _ => (&empty, &empty),
+    };
+    let mut footer = Vec::with_capacity(SUMMARY_FOOTER_BASE_SIZE_IN_BYTES + min.len() + max.len());
+    footer.extend_from_slice(min);
+    footer.extend_from_slice(max);
+    footer.push(min.len() as u8);
+    footer.push(max.len() as u8);
+    footer.extend_from_slice(&(summary.records as u32).to_le_bytes());
+    footer.extend_from_slice(&(block_size as u32).to_le_bytes());
+    footer.extend_from_slice(&SUMMARY_FOOTER_MAGIC);
+    footer
+}
+
+/// Read the size of the blocks the SSTable was written with from its footer.
+///
+/// # Returns
+/// Optional that contains the block size unless the table has no summary.
+pub fn block_size_from_footer(file: &mut dyn StorageFile, size: usize) -> Option<usize> {
+    summary_from_footer(file, size).map(|(block_size, _)| block_size)
+}
+
+/// Read the block size and the summary recorded in the footer of the SSTable.
+///
+/// # Returns
+/// Optional that contains the block size and summary unless the table has no summary.
+fn summary_from_footer(file: &mut dyn StorageFile, size: usize) -> Option<(usize, TableSummary)> {
+    // the summary is only written in front of the other parts of the footer
+    Encoding::from_footer(file, size)?;
+    let mut trailing_size = TABLE_FOOTER_SIZE_IN_BYTES;
+    if table_version_from_footer(file, size).is_some() {
+        trailing_size += TABLE_VERSION_FOOTER_SIZE_IN_BYTES;
+    }
+    if checksum_from_footer(file, size).is_some() {
+        trailing_size += CHECKSUM_FOOTER_SIZE_IN_BYTES;
+    }
+    if codec_from_footer(file, size) != BINCODE_CODEC_ID {
+        trailing_size += CODEC_FOOTER_SIZE_IN_BYTES;
+    }
+    let end = size.checked_sub(trailing_size)?;
+    let offset = end.checked_sub(SUMMARY_FOOTER_BASE_SIZE_IN_BYTES)?;
+    let mut footer = [0u8; SUMMARY_FOOTER_BASE_SIZE_IN_BYTES];
+    match file.read_at(&mut footer, offset as u64) {
+        Ok(read) if read == SUMMARY_FOOTER_BASE_SIZE_IN_BYTES => {}
+        _ => return None,
+    }
+    if footer[10..] != SUMMARY_FOOTER_MAGIC {
+        return None;
+    }
+    let min_size = footer[0] as usize;
+    let max_size = footer[1] as usize;
+    let mut records = [0u8; 4];
+    records.copy_from_slice(&footer[2..6]);
+    let mut block_size = [0u8; 4];
+    block_size.copy_from_slice(&footer[6..10]);
+    let mut summary = TableSummary {
+        records: u32::from_le_bytes(records) as usize,
+        key_range: None,
+    };
+    if min_size + max_size > 0 {
+        let keys_offset = offset.checked_sub(min_size + max_size)?;
+        let mut keys = vec![0u8; min_size + max_size];
+        match file.read_at(&mut keys, keys_offset as u64) {
+            Ok(read) if read == keys.len() => {}
+            _ => return None,
+        }
+        let max = keys.split_off(min_size);
+        summary.key_range = Some((keys, max));
+    }
+    Some((u32::from_le_bytes(block_size) as usize, summary))
+}
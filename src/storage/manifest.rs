@@ -0,0 +1,301 @@
+use crate::options::DharmaOpts;
+use crate::result::{Errors, Result};
+use crate::storage::backend::StorageFile;
+use crate::storage::encoding::TABLE_FOOTER_SIZE_IN_BYTES;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use xxhash_rust::xxh64::xxh64;
+
+/// Version of the on-disk format written by this build of the database.
+/// This must be incremented whenever the layout of persisted data changes.
+pub const FORMAT_VERSION: u32 = 3;
+
+/// Oldest version of the on-disk format of a database that can still be opened.
+/// Databases of format version 2 were written before blocks had headers.
+pub const MIN_FORMAT_VERSION: u32 = 2;
+
+/// Format version of SSTables from which blocks may start with a `BlockHeader`.
+/// Records of older tables are never read as a header.
+pub const BLOCK_HEADER_FORMAT_VERSION: u32 = 3;
+
+/// Oldest version of the on-disk format of SSTables that can still be read.
+/// Tables of format version 1 store values without a sequence number.
+pub const MIN_TABLE_FORMAT_VERSION: u32 = 1;
+
+/// Marks the part of the SSTable footer recording the format version of the table.
+/// It precedes the footer recording the `Encoding` of the table.
+const TABLE_VERSION_FOOTER_MAGIC: [u8; 4] = *b"DVER";
+
+/// Size in bytes of the part of the footer recording the format version. It is made
+/// up of `TABLE_VERSION_FOOTER_MAGIC` followed by the little endian version.
+pub const TABLE_VERSION_FOOTER_SIZE_IN_BYTES: usize = 8;
+
+const MANIFEST_NAME: &str = "MANIFEST";
+
+/// Empty file marking a sealed database next to the manifest. It lets `repair`
+/// keep the database sealed when the manifest recording the seal is lost.
+const SEALED_MARKER_NAME: &str = "SEALED";
+
+/// Marks manifests protected by a checksum. It is followed by the little endian
+/// xxHash of the serialized manifest and the serialized manifest itself.
+const MANIFEST_MAGIC: [u8; 4] = *b"DMAN";
+
+/// Size in bytes of `MANIFEST_MAGIC` followed by the checksum of the manifest.
+const MANIFEST_HEADER_SIZE_IN_BYTES: usize = 12;
+
+/// The manifest records metadata about the data persisted at the database path.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Manifest {
+    /// Version of the on-disk format of persisted data.
+    pub format_version: u32,
+    /// Sequence number of the latest write that was flushed. Compaction may drop
+    /// the records holding the latest sequence numbers from the SSTables so the
+    /// sequence number is recorded here to keep it from going backwards on restart.
+    pub last_sequence: u64,
+    /// Size in bytes of the blocks of the SSTables. Tables can only be read with the
+    /// block size they were written with, see `Dharma::rewrite_with_block_size`.
+    /// Zero for manifests written before the block size was recorded.
+    pub block_size_in_bytes: u64,
+    /// Whether the database was sealed by `Dharma::seal`, writes to a sealed
+    /// database fail with `DB_SEALED`.
+    pub sealed: bool,
+}
+
+/// Manifests written before databases could be sealed.
+#[derive(Deserialize)]
+struct ManifestWithoutSealed {
+    format_version: u32,
+    last_sequence: u64,
+    block_size_in_bytes: u64,
+}
+
+/// Manifests written before the block size was recorded.
+#[derive(Deserialize)]
+struct ManifestWithoutBlockSize {
+    format_version: u32,
+    last_sequence: u64,
+}
+
+/// Manifests written before the latest sequence number was recorded.
+#[derive(Deserialize)]
+struct ManifestWithoutSequence {
+    format_version: u32,
+}
+
+impl Manifest {
+    /// Create a manifest for data written in the current format.
+    pub fn new() -> Manifest {
+        Manifest {
+            format_version: FORMAT_VERSION,
+            last_sequence: 0,
+            block_size_in_bytes: 0,
+            sealed: false,
+        }
+    }
+
+    /// Create a manifest for data written in the current format with the block
+    /// size of the supplied database config.
+    pub fn with_options(options: &DharmaOpts) -> Manifest {
+        Manifest {
+            block_size_in_bytes: options.block_size_in_bytes as u64,
+            ..Manifest::new()
+        }
+    }
+
+    /// Get the block size of the SSTables, falling back to the block size of the
+    /// supplied config if the manifest doesn't record it.
+    pub fn block_size(&self, options: &DharmaOpts) -> usize {
+        match self.block_size_in_bytes {
+            0 => options.block_size_in_bytes,
+            block_size => block_size as usize,
+        }
+    }
+
+    /// Get the path of the manifest for the supplied database config.
+    pub fn path(options: &DharmaOpts) -> PathBuf {
+        PathBuf::from(format!("{0}/{1}", options.path, MANIFEST_NAME))
+    }
+
+    /// Read the manifest persisted at the database path.
+    ///
+    /// # Arguments
+    ///  - _options_ - The database config.
+    ///
+    /// # Returns
+    /// Result that resolves:
+    ///  - _Ok_ - Optional that contains the manifest if one exists.
+    ///  - _Err_ - `MANIFEST_CORRUPT` if the manifest doesn't match its checksum or
+    ///    can't be deserialized, or error that occurred while reading the manifest.
+    pub fn read(options: &DharmaOpts) -> Result<Option<Manifest>> {
+        let path = Manifest::path(options);
+        if !options.backend.exists(&path) {
+            return Ok(None);
+        }
+        let mut file = options
+            .backend
+            .open(&path)
+            .map_err(|_| Errors::MANIFEST_READ_FAILED)?;
+        let size = file.size().map_err(|_| Errors::MANIFEST_READ_FAILED)?;
+        let mut data = vec![0u8; size as usize];
+        file.read_at(&mut data, 0)
+            .map_err(|_| Errors::MANIFEST_READ_FAILED)?;
+        Manifest::decode(&data).map(Some)
+    }
+
+    // Manifests written before they were protected by a checksum are read as is.
+    fn decode(data: &[u8]) -> Result<Manifest> {
+        if data.starts_with(&MANIFEST_MAGIC) {
+            if data.len() < MANIFEST_HEADER_SIZE_IN_BYTES {
+                return Err(Errors::MANIFEST_CORRUPT);
+            }
+            let mut checksum = [0u8; 8];
+            checksum.copy_from_slice(&data[MANIFEST_MAGIC.len()..MANIFEST_HEADER_SIZE_IN_BYTES]);
+            let payload = &data[MANIFEST_HEADER_SIZE_IN_BYTES..];
+            if xxh64(payload, 0) != u64::from_le_bytes(checksum) {
+                return Err(Errors::MANIFEST_CORRUPT);
+            }
+            if let Ok(manifest) = bincode::deserialize::<Manifest>(payload) {
+                return Ok(manifest);
+            }
+            return bincode::deserialize::<ManifestWithoutSealed>(payload)
+                .map(Manifest::from)
+                .map_err(|_| Errors::MANIFEST_CORRUPT);
+        }
+        if let Ok(manifest) = bincode::deserialize::<Manifest>(data) {
+            return Ok(manifest);
+        }
+        if let Ok(manifest) = bincode::deserialize::<ManifestWithoutSealed>(data) {
+            return Ok(Manifest::from(manifest));
+        }
+        if let Ok(manifest) = bincode::deserialize::<ManifestWithoutBlockSize>(data) {
+            return Ok(Manifest {
+                format_version: manifest.format_version,
+                last_sequence: manifest.last_sequence,
+                ..Manifest::new()
+            });
+        }
+        bincode::deserialize::<ManifestWithoutSequence>(data)
+            .map(|manifest| Manifest {
+                format_version: manifest.format_version,
+                ..Manifest::new()
+            })
+            .map_err(|_| Errors::MANIFEST_CORRUPT)
+    }
+
+    /// Write the manifest to the database path replacing any existing manifest.
+    /// The manifest is written to a temporary file that is renamed over the existing
+    /// manifest so a crash never leaves a partially written manifest behind.
+    ///
+    /// # Arguments
+    ///  - _options_ - The database config.
+    ///
+    /// # Returns
+    /// Result that resolves:
+    ///  - _Ok_ - If the manifest was written successfully.
+    ///  - _Err_ - Error that occurred while writing the manifest.
+    pub fn write(&self, options: &DharmaOpts) -> Result<()> {
+        let payload = bincode::serialize(self).map_err(|_| Errors::MANIFEST_WRITE_FAILED)?;
+        let mut data = MANIFEST_MAGIC.to_vec();
+        data.extend_from_slice(&xxh64(&payload, 0).to_le_bytes());
+        data.extend_from_slice(&payload);
+        let path = Manifest::path(options);
+        let temp_path = path.with_extension("tmp");
+        let backend = options.backend.as_ref();
+        let mut file = backend
+            .create(&temp_path)
+            .map_err(|_| Errors::MANIFEST_WRITE_FAILED)?;
+        file.write(&data)
+            .and_then(|_| file.sync())
+            .and_then(|_| backend.rename(&temp_path, &path))
+            .and_then(|_| backend.sync_dir(&PathBuf::from(&options.path)))
+            .map_err(|_| Errors::MANIFEST_WRITE_FAILED)
+    }
+
+    /// Get the path of the marker of sealed databases for the supplied database config.
+    pub fn sealed_marker_path(options: &DharmaOpts) -> PathBuf {
+        PathBuf::from(format!("{0}/{1}", options.path, SEALED_MARKER_NAME))
+    }
+
+    /// Write the marker of sealed databases to the database path so the database
+    /// stays sealed even if the manifest has to be rebuilt, see `is_marked_sealed`.
+    ///
+    /// # Arguments
+    ///  - _options_ - The database config.
+    ///
+    /// # Returns
+    /// Result that resolves:
+    ///  - _Ok_ - If the marker was written successfully.
+    ///  - _Err_ - `MANIFEST_WRITE_FAILED` if the marker couldn't be written.
+    pub fn write_sealed_marker(options: &DharmaOpts) -> Result<()> {
+        let backend = options.backend.as_ref();
+        let mut file = backend
+            .create(&Manifest::sealed_marker_path(options))
+            .map_err(|_| Errors::MANIFEST_WRITE_FAILED)?;
+        file.sync()
+            .and_then(|_| backend.sync_dir(&PathBuf::from(&options.path)))
+            .map_err(|_| Errors::MANIFEST_WRITE_FAILED)
+    }
+
+    /// Check whether the database at the configured path was marked as sealed by
+    /// `write_sealed_marker`.
+    pub fn is_marked_sealed(options: &DharmaOpts) -> bool {
+        options
+            .backend
+            .exists(&Manifest::sealed_marker_path(options))
+    }
+
+    /// Check that the manifest describes data in a format supported by this build.
+    /// The manifest is written in the current format once the database is written to.
+    pub fn check_format_version(&self) -> Result<()> {
+        if self.format_version < MIN_FORMAT_VERSION || self.format_version > FORMAT_VERSION {
+            return Err(Errors::UNSUPPORTED_FORMAT_VERSION {
+                found: self.format_version,
+                expected: FORMAT_VERSION,
+            });
+        }
+        Ok(())
+    }
+}
+
+impl From<ManifestWithoutSealed> for Manifest {
+    fn from(manifest: ManifestWithoutSealed) -> Manifest {
+        Manifest {
+            format_version: manifest.format_version,
+            last_sequence: manifest.last_sequence,
+            block_size_in_bytes: manifest.block_size_in_bytes,
+            sealed: false,
+        }
+    }
+}
+
+/// Get the part of the SSTable footer recording the supplied format version.
+pub fn to_table_version_footer(version: u32) -> Vec<u8> {
+    let mut footer = TABLE_VERSION_FOOTER_MAGIC.to_vec();
+    footer.extend_from_slice(&version.to_le_bytes());
+    footer
+}
+
+/// Read the format version recorded in the footer of the SSTable.
+///
+/// # Arguments
+///  - _file_ - The SSTable.
+///  - _size_ - The size of the SSTable in bytes.
+///
+/// # Returns
+/// Optional that contains the format version unless the table was written before
+/// versions were recorded in the footer.
+pub fn table_version_from_footer(file: &mut dyn StorageFile, size: usize) -> Option<u32> {
+    let offset =
+        size.checked_sub(TABLE_VERSION_FOOTER_SIZE_IN_BYTES + TABLE_FOOTER_SIZE_IN_BYTES)?;
+    let mut footer = [0u8; TABLE_VERSION_FOOTER_SIZE_IN_BYTES];
+    match file.read_at(&mut footer, offset as u64) {
+        Ok(read) if read == TABLE_VERSION_FOOTER_SIZE_IN_BYTES => {}
+        _ => return None,
+    }
+    if footer[..TABLE_VERSION_FOOTER_MAGIC.len()] != TABLE_VERSION_FOOTER_MAGIC {
+        return None;
+    }
+    let mut version = [0u8; 4];
+    version.copy_from_slice(&footer[TABLE_VERSION_FOOTER_MAGIC.len()..]);
+    Some(u32::from_le_bytes(version))
+}
@@ -0,0 +1,111 @@
+use crate::options::DharmaOpts;
+use crate::result::{Errors, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use xxhash_rust::xxh64::xxh64;
+
+const METADATA_NAME: &str = "METADATA";
+
+/// Marks the metadata file. It is followed by the little endian xxHash of the
+/// serialized metadata and the serialized metadata itself.
+const METADATA_MAGIC: [u8; 4] = *b"DMET";
+
+/// Size in bytes of `METADATA_MAGIC` followed by the checksum of the metadata.
+const METADATA_HEADER_SIZE_IN_BYTES: usize = 12;
+
+/// Small amount of application metadata, like schema versions, stored alongside the
+/// data. It is kept in a file of its own at the database path so it never appears
+/// in reads or scans of the keyspace.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct Metadata {
+    entries: BTreeMap<String, Vec<u8>>,
+}
+
+impl Metadata {
+    /// Create metadata without any entries.
+    pub fn new() -> Metadata {
+        Metadata {
+            entries: BTreeMap::new(),
+        }
+    }
+
+    /// Get the path of the metadata file for the supplied database config.
+    pub fn path(options: &DharmaOpts) -> PathBuf {
+        PathBuf::from(format!("{0}/{1}", options.path, METADATA_NAME))
+    }
+
+    /// Get the value stored for the supplied metadata key.
+    pub fn get(&self, key: &str) -> Option<Vec<u8>> {
+        self.entries.get(key).cloned()
+    }
+
+    /// Store the value for the supplied metadata key replacing any existing value.
+    pub fn set(&mut self, key: &str, value: &[u8]) {
+        self.entries.insert(key.to_string(), value.to_vec());
+    }
+
+    /// Read the metadata persisted at the database path.
+    ///
+    /// # Arguments
+    ///  - _options_ - The database config.
+    ///
+    /// # Returns
+    /// Result that resolves:
+    ///  - _Ok_ - The metadata, without entries if none was written yet.
+    ///  - _Err_ - `METADATA_CORRUPT` if the metadata doesn't match its checksum or
+    ///    can't be deserialized, or `METADATA_READ_FAILED` if it couldn't be read.
+    pub fn read(options: &DharmaOpts) -> Result<Metadata> {
+        let path = Metadata::path(options);
+        if !options.backend.exists(&path) {
+            return Ok(Metadata::new());
+        }
+        let mut file = options
+            .backend
+            .open(&path)
+            .map_err(|_| Errors::METADATA_READ_FAILED)?;
+        let size = file.size().map_err(|_| Errors::METADATA_READ_FAILED)?;
+        let mut data = vec![0u8; size as usize];
+        file.read_at(&mut data, 0)
+            .map_err(|_| Errors::METADATA_READ_FAILED)?;
+        if !data.starts_with(&METADATA_MAGIC) || data.len() < METADATA_HEADER_SIZE_IN_BYTES {
+            return Err(Errors::METADATA_CORRUPT);
+        }
+        let mut checksum = [0u8; 8];
+        checksum.copy_from_slice(&data[METADATA_MAGIC.len()..METADATA_HEADER_SIZE_IN_BYTES]);
+        let payload = &data[METADATA_HEADER_SIZE_IN_BYTES..];
+        if xxh64(payload, 0) != u64::from_le_bytes(checksum) {
+            return Err(Errors::METADATA_CORRUPT);
+        }
+        bincode::deserialize::<Metadata>(payload).map_err(|_| Errors::METADATA_CORRUPT)
+    }
+
+    /// Write the metadata to the database path replacing any existing metadata.
+    /// Like the manifest, it is written to a temporary file that is renamed over
+    /// the existing metadata so a crash never leaves partially written metadata behind.
+    ///
+    /// # Arguments
+    ///  - _options_ - The database config.
+    ///
+    /// # Returns
+    /// Result that resolves:
+    ///  - _Ok_ - If the metadata was written successfully.
+    ///  - _Err_ - `METADATA_WRITE_FAILED` if the metadata couldn't be written.
+    pub fn write(&self, options: &DharmaOpts) -> Result<()> {
+        let payload = bincode::serialize(self).map_err(|_| Errors::METADATA_WRITE_FAILED)?;
+        let mut data = METADATA_MAGIC.to_vec();
+        data.extend_from_slice(&xxh64(&payload, 0).to_le_bytes());
+        data.extend_from_slice(&payload);
+        let path = Metadata::path(options);
+        let temp_path = path.with_extension("tmp");
+        let backend = options.backend.as_ref();
+        let mut file = backend
+            .create(&temp_path)
+            .map_err(|_| Errors::METADATA_WRITE_FAILED)?;
+        file.write(&data)
+            .and_then(|_| file.sync())
+            .and_then(|_| backend.rename(&temp_path, &path))
+            .and_then(|_| backend.sync_dir(&PathBuf::from(&options.path)))
+            .map_err(|_| Errors::METADATA_WRITE_FAILED)
+    }
+}
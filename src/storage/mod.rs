@@ -1,6 +1,20 @@
+pub mod backend;
 pub mod block;
+pub mod block_cache;
+pub mod checksum;
+pub mod codec;
 pub mod compaction;
+pub mod compression;
+pub mod debug;
+pub mod deletion_audit;
+pub mod encoding;
+pub mod footer;
+pub mod manifest;
+pub mod metadata;
 pub mod sorted_string_table_reader;
 pub mod sorted_string_table_writer;
 mod sstable_test;
+pub mod transform;
+pub mod value_cache;
+pub mod value_log;
 pub mod write_ahead_log;
@@ -1,22 +1,391 @@
 use crate::result::{Errors, Result};
-use crate::storage::block::{to_record_type, Record, RecordType, Value};
+use crate::options::DharmaOpts;
+#[cfg(feature = "std")]
+use crate::storage::backend::FsBackend;
+use crate::storage::backend::{StorageBackend, StorageFile};
+use crate::storage::block_cache::BlockCache;
+use crate::storage::block::{
+    decode_key_prefix, decode_prefix, decompress, is_compressed, is_prefix_compressed,
+    is_range_tombstone, is_separated, to_record_type, BlockHeader, RangeTombstone, Record,
+    RecordType, SeparatedValue, Value,
+};
+use crate::storage::checksum::{checksum_from_footer, compute_table_checksum};
+use crate::storage::codec::{
+    codec_from_footer, BincodeCodec, RecordCodec, RegisteredRecordCodec, BINCODE_CODEC_ID,
+};
+use crate::storage::encoding::Encoding;
+use crate::storage::footer::{block_size_from_footer, TableFooter, FOOTER_READ_SIZE_IN_BYTES};
+use crate::storage::manifest::{
+    table_version_from_footer, BLOCK_HEADER_FORMAT_VERSION, FORMAT_VERSION,
+    MIN_TABLE_FORMAT_VERSION,
+};
+use crate::storage::transform::ValueTransform;
+use crate::storage::value_log::{read_encoding, read_value, ValueLog, ValuePointer};
 use crate::traits::{ResourceKey, ResourceValue};
-use buffered_offset_reader::{BufOffsetReader, OffsetReadMut};
-use std::fs::{read_dir, File};
-use std::path::PathBuf;
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// Size in bytes of the length preceding each record written by `SSTableReader::stream_to`.
+const STREAMED_RECORD_LENGTH_SIZE_IN_BYTES: usize = 4;
+
+/// Layout of values in tables of format version 1, which predates sequence numbers.
+#[derive(Deserialize)]
+struct ValueV1<K, V> {
+    key: K,
+    value: V,
+}
 
 pub struct SSTableValue {
     // byte array representation of the data
     pub data: Vec<u8>,
     // offset at which this value occurs in the SSTable
     pub offset: usize,
+    // whether the value was stored compressed in the SSTable
+    pub compressed: bool,
+    // whether the data is a `SeparatedValue` pointing into the value log
+    pub separated: bool,
+    // value read from the value log for separated values
+    pub separated_value: Option<Vec<u8>>,
+    // whether the data is a `RangeTombstone` instead of a value
+    pub range_tombstone: bool,
+    // encoding the data was serialized with
+    pub encoding: Encoding,
+    // encoding the separated value was serialized with, recorded by the value log
+    pub separated_encoding: Encoding,
+    // identifier of the codec the record was serialized with
+    pub codec_id: u8,
+    // codec of the database used for records not serialized with the `BincodeCodec`
+    pub record_codec: Option<RegisteredRecordCodec>,
+    // format version of the table the record was read from
+    pub format_version: u32,
+    // whether a block of the record failed to load so that the record holds no data
+    pub read_failed: bool,
 }
 
 impl SSTableValue {
+    /// Deserialize the record with the `BincodeCodec` or with the codec of the
+    /// database for tables written with it. See `DharmaOpts::record_codec`.
     pub fn to_record<K: ResourceKey, V: ResourceValue>(&self) -> Result<Value<K, V>> {
-        let value_result = bincode::deserialize::<Value<K, V>>(self.data.as_slice());
-        return value_result.map_err(|err| Errors::RECORD_DESERIALIZATION_FAILED);
+        match &self.record_codec {
+            Some(codec) if codec.id() == self.codec_id => {
+                self.decode_record(codec.id(), |data| codec.decode(data))
+            }
+            _ => self.to_record_with(&BincodeCodec::new(self.encoding)),
+        }
+    }
+
+    /// Deserialize the record with the codec it was serialized with.
+    /// Values stored in the value log are always serialized with `bincode`.
+    ///
+    /// # Arguments
+    /// * _codec_ - The codec recorded in the footer of the table.
+    ///
+    /// # Returns
+    /// Result that resolves:
+    ///  - _Ok_ - The deserialized record.
+    ///  - _Err_ - Error if the data is not a value, was serialized with a
+    ///    different codec or could not be deserialized.
+    pub fn to_record_with<K: ResourceKey, V: ResourceValue, C: RecordCodec>(
+        &self,
+        codec: &C,
+    ) -> Result<Value<K, V>> {
+        self.decode_record(codec.id(), |data| codec.decode(data))
+    }
+
+    // deserialize the record with `decode` of the codec of the supplied identifier
+    fn decode_record<K, V, F>(&self, codec_id: u8, decode: F) -> Result<Value<K, V>>
+    where
+        K: ResourceKey,
+        V: ResourceValue,
+        F: FnOnce(&[u8]) -> Result<Value<K, V>>,
+    {
+        self.check_read()?;
+        if self.range_tombstone || self.codec_id != codec_id {
+            return Err(Errors::RECORD_DESERIALIZATION_FAILED);
+        }
+        if self.separated {
+            let value_data = self
+                .separated_value
+                .as_ref()
+                .ok_or(Errors::RECORD_DESERIALIZATION_FAILED)?;
+            let separated = self
+                .encoding
+                .deserialize::<SeparatedValue<K>>(self.data.as_slice())
+                .map_err(|_| Errors::RECORD_DESERIALIZATION_FAILED)?;
+            let value = self
+                .separated_encoding
+                .deserialize::<V>(value_data.as_slice())
+                .map_err(|_| Errors::RECORD_DESERIALIZATION_FAILED)?;
+            let mut record = Value::with_seq(separated.key, value, separated.seq);
+            record.pointer = Some(separated.pointer);
+            return Ok(record);
+        }
+        if self.format_version == 1 {
+            // values were written before sequence numbers so they sort as the oldest
+            let legacy = self
+                .encoding
+                .deserialize::<ValueV1<K, V>>(self.data.as_slice())
+                .map_err(|_| Errors::RECORD_DESERIALIZATION_FAILED)?;
+            return Ok(Value::with_seq(legacy.key, legacy.value, 0));
+        }
+        decode(self.data.as_slice())
+    }
+
+    /// Get the encoding the value of the record was serialized with, which is the
+    /// encoding of the value log for separated values. See `to_raw`.
+    pub fn value_encoding(&self) -> Encoding {
+        if self.separated {
+            return self.separated_encoding;
+        }
+        self.encoding
+    }
+
+    /// Split the data into the serialized key and the serialized value so that the
+    /// record can be inspected or copied without knowing the type of the value.
+    /// Serialized keys don't record their length so the key type is still needed
+    /// to find where the key ends. Sequence numbers of values stored inline don't
+    /// have a fixed size with the varint encoding so those values can't be split off.
+    ///
+    /// # Returns
+    /// Result that resolves:
+    ///  - _Ok_ - The serialized key and value of the record.
+    ///  - _Err_ - Error if the data is not a value or could not be split.
+    pub fn to_raw<K: ResourceKey>(&self) -> Result<(Vec<u8>, Vec<u8>)> {
+        self.check_read()?;
+        let (_, key_start, key_end) = self.decode_key::<K>()?;
+        let key_bytes = self.data[key_start..key_end].to_vec();
+        if self.separated {
+            let value_bytes = self
+                .separated_value
+                .clone()
+                .ok_or(Errors::RECORD_DESERIALIZATION_FAILED)?;
+            return Ok((key_bytes, value_bytes));
+        }
+        if self.format_version == 1 {
+            return Ok((key_bytes, self.data[key_end..].to_vec()));
+        }
+        if self.encoding != Encoding::Fixint {
+            return Err(Errors::RECORD_DESERIALIZATION_FAILED);
+        }
+        // the value is followed by the sequence number
+        let value_end = self
+            .data
+            .len()
+            .checked_sub(std::mem::size_of::<u64>())
+            .filter(|value_end| *value_end >= key_end)
+            .ok_or(Errors::RECORD_DESERIALIZATION_FAILED)?;
+        Ok((key_bytes, self.data[key_end..value_end].to_vec()))
+    }
+
+    /// Deserialize only the key of the record.
+    ///
+    /// # Returns
+    /// Result that resolves:
+    ///  - _Ok_ - The key of the record.
+    ///  - _Err_ - Error if the data is not a value or the key could not be read.
+    pub fn to_key<K: ResourceKey>(&self) -> Result<K> {
+        self.check_read()?;
+        match &self.record_codec {
+            // records of other codecs are deserialized whole to read their key
+            Some(codec)
+                if codec.id() == self.codec_id
+                    && !self.range_tombstone
+                    && !self.separated
+                    && self.format_version != 1 =>
+            {
+                codec.decode_key(self.data.as_slice())
+            }
+            _ => self.decode_key::<K>().map(|(key, _, _)| key),
+        }
+    }
+
+    /// Read the sequence number of the record without deserializing its value.
+    /// Sequence numbers of values stored inline follow the value and can only be
+    /// located without the value type when integers have a fixed width.
+    ///
+    /// # Returns
+    /// Result that resolves:
+    ///  - _Ok_ - Optional that contains the sequence number unless it can only be
+    ///    read along with the value, see `to_record`.
+    ///  - _Err_ - Error if the data is not a value or could not be read.
+    pub fn to_seq<K: ResourceKey>(&self) -> Result<Option<u64>> {
+        self.check_read()?;
+        if self.range_tombstone {
+            return Err(Errors::RECORD_DESERIALIZATION_FAILED);
+        }
+        if self.separated {
+            let separated = self
+                .encoding
+                .deserialize::<SeparatedValue<K>>(self.data.as_slice())
+                .map_err(|_| Errors::RECORD_DESERIALIZATION_FAILED)?;
+            return Ok(Some(separated.seq));
+        }
+        if self.format_version == 1 {
+            return Ok(Some(0));
+        }
+        if self.encoding != Encoding::Fixint || self.codec_id != BINCODE_CODEC_ID {
+            return Ok(None);
+        }
+        let seq_start = self
+            .data
+            .len()
+            .checked_sub(std::mem::size_of::<u64>())
+            .ok_or(Errors::RECORD_DESERIALIZATION_FAILED)?;
+        self.encoding
+            .deserialize::<u64>(&self.data[seq_start..])
+            .map(Some)
+            .map_err(|_| Errors::RECORD_DESERIALIZATION_FAILED)
+    }
+
+    /// Deserialize the key of the record along with the offsets in the data
+    /// at which the serialized key starts and ends.
+    fn decode_key<K: ResourceKey>(&self) -> Result<(K, usize, usize)> {
+        // pointers into the value log are always serialized with `bincode`
+        if self.range_tombstone || (self.codec_id != BINCODE_CODEC_ID && !self.separated) {
+            return Err(Errors::RECORD_DESERIALIZATION_FAILED);
+        }
+        // separated values start with the pointer into the value log
+        let key_start = if self.separated {
+            let pointer = self
+                .encoding
+                .deserialize::<ValuePointer>(self.data.as_slice())
+                .map_err(|_| Errors::RECORD_DESERIALIZATION_FAILED)?;
+            self.encoding
+                .serialized_size(&pointer)
+                .map_err(|_| Errors::RECORD_DESERIALIZATION_FAILED)? as usize
+        } else {
+            0
+        };
+        let key_data = self
+            .data
+            .get(key_start..)
+            .ok_or(Errors::RECORD_DESERIALIZATION_FAILED)?;
+        let key = self
+            .encoding
+            .deserialize::<K>(key_data)
+            .map_err(|_| Errors::RECORD_DESERIALIZATION_FAILED)?;
+        let key_end = key_start
+            + self
+                .encoding
+                .serialized_size(&key)
+                .map_err(|_| Errors::RECORD_DESERIALIZATION_FAILED)? as usize;
+        Ok((key, key_start, key_end))
+    }
+
+    // fail with `SSTABLE_READ_FAILED` if the record couldn't be read
+    fn check_read(&self) -> Result<()> {
+        if self.read_failed {
+            return Err(Errors::SSTABLE_READ_FAILED);
+        }
+        Ok(())
     }
+
+    pub fn to_range_tombstone<K: ResourceKey>(&self) -> Result<RangeTombstone<K>> {
+        self.check_read()?;
+        if !self.range_tombstone {
+            return Err(Errors::RECORD_DESERIALIZATION_FAILED);
+        }
+        self.encoding
+            .deserialize::<RangeTombstone<K>>(self.data.as_slice())
+            .map_err(|_| Errors::RECORD_DESERIALIZATION_FAILED)
+    }
+}
+
+/// Record of an SSTable as written by `SSTableReader::stream_to`. The data is
+/// stored as it is read from the table, with prefix compressed keys restored and
+/// the value of separated records read from the value log, so that the record
+/// can be deserialized without the table. See `to_sstable_value`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct StreamedRecord {
+    pub data: Vec<u8>,
+    pub separated: bool,
+    pub separated_value: Option<Vec<u8>>,
+    pub range_tombstone: bool,
+    /// Identifier of the encoding the data was serialized with, see `Encoding::id`.
+    pub encoding: u8,
+    /// Identifier of the encoding the separated value was serialized with.
+    pub separated_encoding: u8,
+    pub codec_id: u8,
+    pub format_version: u32,
+}
+
+impl StreamedRecord {
+    /// Get the value that the record was read as to deserialize it with `to_record`
+    /// or `to_range_tombstone`. Streamed records don't record their offset in the table.
+    ///
+    /// # Returns
+    /// Result that resolves:
+    ///  - _Ok_ - The value of the record.
+    ///  - _Err_ - `RECORD_DESERIALIZATION_FAILED` if the encoding is unknown.
+    pub fn to_sstable_value(&self) -> Result<SSTableValue> {
+        let encoding =
+            Encoding::from_id(self.encoding).ok_or(Errors::RECORD_DESERIALIZATION_FAILED)?;
+        let separated_encoding = Encoding::from_id(self.separated_encoding)
+            .ok_or(Errors::RECORD_DESERIALIZATION_FAILED)?;
+        Ok(SSTableValue {
+            data: self.data.clone(),
+            offset: 0,
+            compressed: false,
+            separated: self.separated,
+            separated_value: self.separated_value.clone(),
+            range_tombstone: self.range_tombstone,
+            encoding,
+            separated_encoding,
+            codec_id: self.codec_id,
+            record_codec: None,
+            format_version: self.format_version,
+            read_failed: false,
+        })
+    }
+}
+
+/// Report on a single block of an SSTable returned by `SSTableReader::validate_blocks`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BlockReport {
+    /// Position of the block in the table.
+    pub index: usize,
+    /// Number of bytes of the block that could be read.
+    pub size_in_bytes: usize,
+    /// Types of the records in the block in the order they first appear.
+    pub record_types: Vec<RecordType>,
+    /// Whether every record of the block parses and the block is padded exactly
+    /// to the block size.
+    pub valid: bool,
+}
+
+/// Read the next record written by `SSTableReader::stream_to`.
+///
+/// # Arguments
+///  - _input_ - The stream of records.
+///
+/// # Returns
+/// Result that resolves:
+///  - _Ok_ - Optional that contains the next record unless the stream has ended.
+///  - _Err_ - `SSTABLE_STREAM_FAILED` if the stream ends within a record or
+///    `RECORD_DESERIALIZATION_FAILED` if the record couldn't be deserialized.
+pub fn read_streamed_record<R: Read>(input: &mut R) -> Result<Option<StreamedRecord>> {
+    let mut length = [0u8; STREAMED_RECORD_LENGTH_SIZE_IN_BYTES];
+    let mut filled = 0;
+    while filled < length.len() {
+        match input.read(&mut length[filled..]) {
+            Ok(0) if filled == 0 => return Ok(None),
+            Ok(0) => return Err(Errors::SSTABLE_STREAM_FAILED),
+            Ok(read) => filled += read,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(_) => return Err(Errors::SSTABLE_STREAM_FAILED),
+        }
+    }
+    let mut data = vec![0u8; u32::from_be_bytes(length) as usize];
+    input
+        .read_exact(&mut data)
+        .map_err(|_| Errors::SSTABLE_STREAM_FAILED)?;
+    Encoding::Fixint
+        .deserialize(data.as_slice())
+        .map(Some)
+        .map_err(|_| Errors::RECORD_DESERIALIZATION_FAILED)
 }
 
 // Utility to read values one after another from an SSTable.
@@ -26,6 +395,11 @@ pub struct SSTableReader {
     offset: usize,
     // total size of the SSTable
     pub size: usize,
+    // offset past the last block that holds records, trailing blocks
+    // made up entirely of padding are never read
+    data_size: usize,
+    // number of blocks loaded by this reader
+    blocks_read: usize,
     // data buffered from the current block being read
     pub buffer: Vec<u8>,
     // offset within the current buffer
@@ -33,13 +407,48 @@ pub struct SSTableReader {
     // the size of blocks in this SSTable
     block_size: usize,
     // the reader to read data in blocks
-    reader: BufOffsetReader<File>,
+    reader: Box<dyn StorageFile>,
+    // path to the SSTable being read
+    path: PathBuf,
+    // cache consulted before reading blocks from storage
+    cache: Option<Arc<Mutex<BlockCache>>>,
+    // block swapped out while reading a value that spans blocks
+    preserved_buffer: Vec<u8>,
+    // number of blocks read from storage at once
+    readahead_blocks: usize,
+    // blocks read ahead of the current block
+    readahead: Vec<u8>,
+    // offset in the table of the first block read ahead
+    readahead_offset: usize,
+    // value log holding values stored separately from their keys
+    value_log: Option<Box<dyn StorageFile>>,
+    // whether a block could not be read completely, iteration ends at that block
+    read_failed: bool,
+    // offset of the block, position within the block and key reached by the last
+    // walk over prefix compressed keys so sequential reads don't walk from the start
+    prefix_cursor: Option<(usize, usize, Vec<u8>)>,
+    // encoding of the records in the table
+    encoding: Encoding,
+    // encoding of the values in the value log, see `ValueLog`
+    value_log_encoding: Encoding,
+    // identifier of the codec the records in the table were serialized with
+    codec_id: u8,
+    // format version of the table which selects how its records are parsed
+    format_version: u32,
+    // header of the current block if it starts with one, see `BlockHeader`
+    block_header: Option<BlockHeader>,
+    // transform reversed on the records holding values, see `DharmaOpts::value_transform`
+    value_transform: Option<Arc<dyn ValueTransform>>,
+    // codec of the database attached to the values read, see `DharmaOpts::record_codec`
+    record_codec: Option<RegisteredRecordCodec>,
 }
 
 impl SSTableReader {
     /// Create an SSTable reader by reading the table at the specified path
     /// with the supplied blocks size. Supplying an incorrect block size will
     /// result in reading malformed data and overflow errors.
+    /// The table is read from the local file system, use `with_backend` to
+    /// read tables from a different storage backend.
     /// TODO(sushrut) - Encode blocksize in table metadata instead of reading from parameter
     ///
     /// # Arguments
@@ -50,24 +459,380 @@ impl SSTableReader {
     /// Result that resolves:
     ///  - _Ok_ - The SSTableReader instance.
     ///  - _Err_ - Error that occured whlie creating reader.
+    #[cfg(feature = "std")]
     pub fn from(path: &PathBuf, block_size: usize) -> Result<SSTableReader> {
-        let file_result = File::open(path);
-        if file_result.is_ok() {
-            let file = file_result.unwrap();
-            let size = file.metadata().unwrap().len();
-            let mut reader = BufOffsetReader::new(file);
-            let mut buffer = vec![0u8; block_size as usize];
-            reader.read_at(&mut buffer, 0);
-            return Ok(SSTableReader {
-                block_size,
-                buffer,
-                buffer_offset: 0,
-                offset: 0,
-                size: size as usize,
-                reader,
+        SSTableReader::with_backend(&FsBackend::new(), path, block_size)
+    }
+
+    /// Create an SSTable reader by reading the table at the specified path
+    /// from the supplied storage backend. Values separated from the table can't
+    /// be read, use `with_value_log` for tables of databases with a value log.
+    ///
+    /// # Arguments
+    ///  - _backend_ - The storage backend from which the table is read.
+    ///  - _path_ - The path at which the SSTable exists.
+    ///  - _block_size_ - The sixe of blocks in the table. See block.rs.
+    ///
+    /// # Returns
+    /// Result that resolves:
+    ///  - _Ok_ - The SSTableReader instance.
+    ///  - _Err_ - Error that occured whlie creating reader.
+    pub fn with_backend(
+        backend: &dyn StorageBackend,
+        path: &PathBuf,
+        block_size: usize,
+    ) -> Result<SSTableReader> {
+        let reader = backend
+            .open(path)
+            .map_err(|_| Errors::SSTABLE_READ_FAILED)?;
+        SSTableReader::with_file(reader, path, block_size, None)
+    }
+
+    /// Create an SSTable reader by reading the table at the specified path from
+    /// the supplied storage backend along with the value log holding the values
+    /// separated from the table, see `ValueLog::path`.
+    ///
+    /// # Arguments
+    ///  - _backend_ - The storage backend from which the table is read.
+    ///  - _path_ - The path at which the SSTable exists.
+    ///  - _block_size_ - The sixe of blocks in the table. See block.rs.
+    ///  - _value_log_path_ - The path of the value log of the database, ignored
+    ///    when no value log exists.
+    ///
+    /// # Returns
+    /// Result that resolves:
+    ///  - _Ok_ - The SSTableReader instance.
+    ///  - _Err_ - Error that occured whlie creating reader.
+    pub fn with_value_log(
+        backend: &dyn StorageBackend,
+        path: &PathBuf,
+        block_size: usize,
+        value_log_path: &PathBuf,
+    ) -> Result<SSTableReader> {
+        let reader = backend
+            .open(path)
+            .map_err(|_| Errors::SSTABLE_READ_FAILED)?;
+        let value_log = if backend.exists(value_log_path) {
+            backend.open(value_log_path).ok()
+        } else {
+            None
+        };
+        SSTableReader::with_file(reader, path, block_size, value_log)
+    }
+
+    /// Create an SSTable reader by reading the table at the specified path with
+    /// the storage backend, block size, value transform and record codec of the
+    /// database config.
+    ///
+    /// # Arguments
+    ///  - _options_ - The database config.
+    ///  - _path_ - The path at which the SSTable exists.
+    ///
+    /// # Returns
+    /// Result that resolves:
+    ///  - _Ok_ - The SSTableReader instance.
+    ///  - _Err_ - Error that occured whlie creating reader.
+    pub fn with_options(options: &DharmaOpts, path: &PathBuf) -> Result<SSTableReader> {
+        let mut reader = SSTableReader::with_value_log(
+            options.backend.as_ref(),
+            path,
+            options.block_size_in_bytes,
+            &ValueLog::path(options),
+        )?;
+        reader.set_value_transform(options.value_transform.clone());
+        reader.set_record_codec(options.record_codec.clone());
+        Ok(reader)
+    }
+
+    /// Create an SSTable reader for the table held by the supplied file.
+    ///
+    /// # Arguments
+    ///  - _reader_ - The file holding the table.
+    ///  - _path_ - The path reported for the table.
+    ///  - _block_size_ - The sixe of blocks in the table. See block.rs. Ignored for
+    ///    tables recording their block size in their footer.
+    ///  - _value_log_ - The value log holding values separated from the table if any.
+    ///
+    /// # Returns
+    /// Result that resolves:
+    ///  - _Ok_ - The SSTableReader instance.
+    ///  - _Err_ - Error that occured whlie creating reader.
+    pub fn with_file(
+        mut reader: Box<dyn StorageFile>,
+        path: &PathBuf,
+        block_size: usize,
+        mut value_log: Option<Box<dyn StorageFile>>,
+    ) -> Result<SSTableReader> {
+        let size = reader.size().map_err(|_| Errors::SSTABLE_READ_FAILED)?;
+        // tables are read with the block size recorded in their footer so tables
+        // written with different block sizes can be read side by side
+        let block_size =
+            block_size_from_footer(reader.as_mut(), size as usize).unwrap_or(block_size);
+        let mut buffer = vec![0u8; block_size as usize];
+        let read = reader
+            .read_at(&mut buffer, 0)
+            .map_err(|_| Errors::SSTABLE_READ_FAILED)?;
+        if read < std::cmp::min(block_size, size as usize) {
+            return Err(Errors::SSTABLE_READ_FAILED);
+        }
+        let data_size = find_data_size(reader.as_mut(), size as usize, block_size);
+        // tables written before the encoding was configurable have no footer
+        let encoding = Encoding::from_footer(reader.as_mut(), size as usize).unwrap_or_default();
+        let codec_id = codec_from_footer(reader.as_mut(), size as usize);
+        // tables written before the format version was recorded have no footer and
+        // store values in the layout of the first format version
+        let format_version = table_version_from_footer(reader.as_mut(), size as usize)
+            .unwrap_or(MIN_TABLE_FORMAT_VERSION);
+        if format_version < MIN_TABLE_FORMAT_VERSION || format_version > FORMAT_VERSION {
+            return Err(Errors::UNSUPPORTED_FORMAT_VERSION {
+                found: format_version,
+                expected: FORMAT_VERSION,
             });
         }
-        return Err(Errors::SSTABLE_READ_FAILED);
+        let value_log_encoding = match value_log.as_mut() {
+            Some(value_log) => {
+                read_encoding(value_log.as_mut()).ok_or(Errors::SSTABLE_READ_FAILED)?
+            }
+            None => Encoding::default(),
+        };
+        let mut sstable_reader = SSTableReader {
+            block_size,
+            buffer,
+            buffer_offset: 0,
+            offset: 0,
+            size: size as usize,
+            data_size,
+            blocks_read: 1,
+            reader,
+            path: path.clone(),
+            cache: None,
+            preserved_buffer: Vec::new(),
+            readahead_blocks: 1,
+            readahead: Vec::new(),
+            readahead_offset: 0,
+            value_log,
+            read_failed: false,
+            prefix_cursor: None,
+            encoding,
+            value_log_encoding,
+            codec_id,
+            format_version,
+            block_header: None,
+            value_transform: None,
+            record_codec: None,
+        };
+        sstable_reader.read_block_header();
+        Ok(sstable_reader)
+    }
+
+    /// Read the footer of the SSTable at the specified path without opening the table.
+    /// Only the last bytes of the table holding the footer are read, so scanning the
+    /// footers of a directory of tables is fast.
+    ///
+    /// # Arguments
+    ///  - _path_ - The path at which the SSTable exists.
+    ///
+    /// # Returns
+    /// Result that resolves:
+    ///  - _Ok_ - The footer of the table.
+    ///  - _Err_ - `SSTABLE_READ_FAILED` if the table couldn't be read.
+    #[cfg(feature = "std")]
+    pub fn read_footer(path: &PathBuf) -> Result<TableFooter> {
+        SSTableReader::read_footer_with_backend(&FsBackend::new(), path)
+    }
+
+    /// Read the footer of the SSTable at the specified path from the supplied
+    /// storage backend. See `read_footer`.
+    pub fn read_footer_with_backend(
+        backend: &dyn StorageBackend,
+        path: &PathBuf,
+    ) -> Result<TableFooter> {
+        let mut file = backend
+            .open(path)
+            .map_err(|_| Errors::SSTABLE_READ_FAILED)?;
+        let size = file.size().map_err(|_| Errors::SSTABLE_READ_FAILED)? as usize;
+        let tail_size = std::cmp::min(size, FOOTER_READ_SIZE_IN_BYTES);
+        let mut tail = vec![0u8; tail_size];
+        let read = file
+            .read_at(&mut tail, (size - tail_size) as u64)
+            .map_err(|_| Errors::SSTABLE_READ_FAILED)?;
+        if read < tail_size {
+            return Err(Errors::SSTABLE_READ_FAILED);
+        }
+        Ok(TableFooter::read(&mut tail, tail_size))
+    }
+
+    /// Get the identifier of the codec the records in the table were serialized with.
+    /// Records of tables written with a codec other than the `BincodeCodec` are read
+    /// with `SSTableValue::to_record_with`.
+    pub fn codec_id(&self) -> u8 {
+        self.codec_id
+    }
+
+    /// Check the table against the checksum recorded in its footer when it was written.
+    /// This detects truncated tables and corruption anywhere in the table, including
+    /// padding between records that reads never inspect.
+    ///
+    /// # Returns
+    /// Result that resolves:
+    ///  - _Ok_ - Flag specifying whether the table matches its checksum. Tables without
+    ///    a checksum, including truncated tables that lost their footer, don't match.
+    ///  - _Err_ - Error that occurred while reading the table.
+    pub fn verify_table_checksum(&mut self) -> Result<bool> {
+        let expected = match checksum_from_footer(self.reader.as_mut(), self.size) {
+            Some(expected) => expected,
+            None => return Ok(false),
+        };
+        let actual = compute_table_checksum(self.reader.as_mut(), self.size)?;
+        Ok(actual == expected)
+    }
+
+    /// Walk every block of the table checking that its records parse, that it is
+    /// padded exactly to the block size and that it holds as many records as its
+    /// header records if it has one. Blocks are read directly from storage and
+    /// the position of the reader is left untouched. A malformed block doesn't stop
+    /// the walk so every block is reported.
+    ///
+    /// # Returns
+    /// Result that resolves:
+    ///  - _Ok_ - A report for each block of the table in order.
+    ///  - _Err_ - Error that occurred while reading the table.
+    pub fn validate_blocks(&mut self) -> Result<Vec<BlockReport>> {
+        let block_count = (self.size + self.block_size - 1) / self.block_size;
+        let mut reports = Vec::with_capacity(block_count);
+        let mut block = vec![0u8; self.block_size];
+        for index in 0..block_count {
+            let read = self
+                .reader
+                .read_at(&mut block, (index * self.block_size) as u64)
+                .unwrap_or(0);
+            let (record_types, parsed) = parse_block(&block[..read]);
+            // blocks of tables written before block headers are never read as having one
+            let header = match self.format_version >= BLOCK_HEADER_FORMAT_VERSION {
+                true => BlockHeader::read(&block[..read]),
+                false => None,
+            };
+            let records_match = match header {
+                Some((header, size)) => header.records == count_records(&block[size..read]),
+                None => true,
+            };
+            reports.push(BlockReport {
+                index,
+                size_in_bytes: read,
+                record_types,
+                valid: parsed && records_match && read == self.block_size,
+            });
+        }
+        Ok(reports)
+    }
+
+    /// Get the header of the block the reader is at, not set if the table was
+    /// written without block headers. See `DharmaOpts::block_headers`.
+    pub fn block_header(&self) -> Option<&BlockHeader> {
+        self.block_header.as_ref()
+    }
+
+    /// Get the version of the on-disk format the table was written in.
+    pub fn format_version(&self) -> u32 {
+        self.format_version
+    }
+
+    /// Use the supplied block cache for subsequent block reads. Blocks read
+    /// from storage are added to the cache.
+    ///
+    /// # Arguments
+    ///  - _cache_ - The block cache shared across readers.
+    pub fn set_block_cache(&mut self, cache: Arc<Mutex<BlockCache>>) {
+        self.cache = Some(cache);
+    }
+
+    /// Reverse the supplied transform on the records holding values that are read.
+    /// See `DharmaOpts::value_transform`.
+    ///
+    /// # Arguments
+    ///  - _transform_ - The transform the records were written with.
+    pub fn set_value_transform(&mut self, transform: Option<Arc<dyn ValueTransform>>) {
+        self.value_transform = transform;
+    }
+
+    /// Deserialize records of tables written with the supplied codec with it. Records
+    /// of other tables are deserialized with the `BincodeCodec`.
+    /// See `DharmaOpts::record_codec`.
+    ///
+    /// # Arguments
+    ///  - _codec_ - The codec of the database.
+    pub fn set_record_codec(&mut self, codec: Option<RegisteredRecordCodec>) {
+        self.record_codec = codec;
+    }
+
+    /// Decode records with the supplied encoding instead of the encoding recorded
+    /// in the footer of the table. This is needed to read the Write Ahead Log
+    /// which has no footer.
+    ///
+    /// # Arguments
+    ///  - _encoding_ - The encoding the records were written with.
+    pub fn set_encoding(&mut self, encoding: Encoding) {
+        self.encoding = encoding;
+    }
+
+    /// Decode records in the supplied format version instead of the version recorded
+    /// in the footer of the table. This is needed to read the Write Ahead Log which
+    /// has no footer and is always written in the current format version.
+    ///
+    /// # Arguments
+    ///  - _format_version_ - The format version the records were written in.
+    pub fn set_format_version(&mut self, format_version: u32) {
+        self.format_version = format_version;
+        // the header of the first block is only read once its version is known
+        if self.offset == 0 && self.buffer_offset == 0 {
+            self.read_block_header();
+        }
+    }
+
+    /// Read the supplied number of blocks from storage at once when a block is
+    /// loaded so that sequential scans need fewer reads. Subsequent blocks are
+    /// served from the blocks read ahead.
+    ///
+    /// # Arguments
+    ///  - _blocks_ - The number of blocks to read at once, 1 disables readahead.
+    pub fn set_readahead(&mut self, blocks: usize) {
+        self.readahead_blocks = std::cmp::max(blocks, 1);
+        self.readahead = Vec::new();
+    }
+
+    /// Load the blocks spanning the supplied byte offsets into the block cache.
+    /// This moves the reader so it should be used on a dedicated reader.
+    ///
+    /// # Arguments
+    ///  - _start_offset_ - Offset within the first block to load.
+    ///  - _end_offset_ - Offset within the last block to load.
+    ///
+    /// # Returns
+    /// The number of blocks loaded.
+    pub fn warm(&mut self, start_offset: usize, end_offset: usize) -> usize {
+        let mut block_offset = (start_offset / self.block_size) * self.block_size;
+        let mut warmed = 0;
+        while block_offset <= end_offset && block_offset < self.size {
+            if self.load_block_at(block_offset).is_err() {
+                break;
+            }
+            warmed += 1;
+            block_offset += self.block_size;
+        }
+        warmed
+    }
+
+    /// Check whether a block of the table could not be read completely or held a
+    /// record of an unknown type. Iteration stops at such a block so `has_next`
+    /// returning `false` does not imply all records were read if this is set.
+    pub fn read_failed(&self) -> bool {
+        self.read_failed
+    }
+
+    /// Get the number of blocks loaded by this reader including blocks
+    /// served from the block cache.
+    pub fn blocks_read(&self) -> usize {
+        self.blocks_read
     }
 
     /// Get the paths to valid SSTables within the supplied directory.
@@ -77,24 +842,79 @@ impl SSTableReader {
     ///
     /// # Returns
     /// Result that resolves:
-    ///  - _Ok_ - The list of paths to SSTables sorted lexically.
+    ///  - _Ok_ - The list of paths to SSTables sorted by table number.
     ///  - _Err_ - Error that occurred while reading directory.
+    #[cfg(feature = "std")]
     pub fn get_valid_table_paths(base_path: &String) -> Result<Vec<PathBuf>> {
-        let tables_path = format!("{0}/tables", base_path);
-        let read_dir_result = read_dir(tables_path);
+        SSTableReader::get_valid_table_paths_with_backend(&FsBackend::new(), base_path)
+    }
+
+    /// Get the paths to valid SSTables within the supplied directory of the storage backend.
+    /// Tables are expected in the default table directory with the default extension,
+    /// use `get_table_paths` for databases configured otherwise.
+    ///
+    /// # Arguments
+    ///  - _backend_ - The storage backend in which to look for SSTables.
+    ///  - _base_path_ - The directory in which to look for SSTables.
+    ///
+    /// # Returns
+    /// Result that resolves:
+    ///  - _Ok_ - The list of paths to SSTables sorted by table number.
+    ///  - _Err_ - Error that occurred while reading directory.
+    pub fn get_valid_table_paths_with_backend(
+        backend: &dyn StorageBackend,
+        base_path: &String,
+    ) -> Result<Vec<PathBuf>> {
+        let tables_path = PathBuf::from(format!("{0}/tables", base_path));
+        SSTableReader::list_table_paths(backend, &tables_path, |path| {
+            path.extension()
+                .map_or(false, |extension| extension == "db")
+        })
+    }
+
+    /// Get the paths to the SSTables of the database in the table directory and with
+    /// the table extension specified by the options.
+    ///
+    /// # Arguments
+    ///  - _options_ - Configuration options of the database.
+    ///  - _base_path_ - The directory in which the database is stored.
+    ///
+    /// # Returns
+    /// Result that resolves:
+    ///  - _Ok_ - The list of paths to SSTables sorted by table number.
+    ///  - _Err_ - Error that occurred while reading directory.
+    pub fn get_table_paths(options: &DharmaOpts, base_path: &String) -> Result<Vec<PathBuf>> {
+        SSTableReader::list_table_paths(
+            options.backend.as_ref(),
+            &options.tables_path_in(base_path),
+            |path| options.is_table_path(path),
+        )
+    }
+
+    fn list_table_paths<F>(
+        backend: &dyn StorageBackend,
+        tables_path: &Path,
+        is_table_path: F,
+    ) -> Result<Vec<PathBuf>>
+    where
+        F: Fn(&Path) -> bool,
+    {
+        let read_dir_result = backend.list_dir(tables_path);
         if read_dir_result.is_ok() {
-            let read_dir = read_dir_result.unwrap();
             let mut output = Vec::new();
-            for path_result in read_dir {
-                if let Ok(dir_entry) = path_result {
-                    let path = dir_entry.path();
-                    let extension = path.extension();
-                    if extension.is_some() && extension.unwrap().eq("db") {
-                        output.push(path);
-                    }
+            for path in read_dir_result.unwrap() {
+                if is_table_path(&path) {
+                    output.push(path);
                 }
             }
-            output.sort();
+            // tables are named by number so `10.db` is ordered after `9.db`
+            output.sort_by_key(|path| {
+                let table_number = path
+                    .file_stem()
+                    .and_then(|stem| stem.to_str())
+                    .and_then(|stem| stem.parse::<usize>().ok());
+                (table_number, path.clone())
+            });
             return Ok(output);
         }
         Err(Errors::SSTABLE_READ_FAILED)
@@ -107,46 +927,88 @@ impl SSTableReader {
     pub fn read(&mut self) -> SSTableValue {
         let mut previous_buffer_offset = self.buffer_offset;
         let previous_offset = self.offset;
-        let previous_buffer = self.buffer.clone();
+        // the current block is swapped out instead of copied if other blocks are loaded
+        let mut preserved = false;
         let mut temp_buffer = Vec::new();
+        // offset of the first chunk of a value that is split across blocks
+        let mut start_offset = None;
+        // key preceding the value if its key is prefix compressed
+        let mut previous_key = None;
         loop {
             match to_record_type(self.buffer[self.buffer_offset]) {
                 RecordType::PADDING => {
-                    self.load_next_block();
+                    if self.load_next_block_preserving(&mut preserved).is_err() {
+                        return self.failed_read(
+                            previous_offset,
+                            previous_buffer_offset,
+                            preserved,
+                        );
+                    }
                 }
                 RecordType::COMPLETE => {
+                    if is_prefix_compressed(self.buffer[self.buffer_offset]) {
+                        previous_key = Some(self.previous_key_before(self.buffer_offset));
+                    }
                     let buffer = &self.buffer;
+                    let compressed = is_compressed(buffer[self.buffer_offset]);
+                    let separated = is_separated(buffer[self.buffer_offset]);
+                    let range_tombstone = is_range_tombstone(buffer[self.buffer_offset]);
                     let upper_byte = buffer[self.buffer_offset + 1] as u16;
                     let lower_byte = buffer[self.buffer_offset + 2] as u16;
                     let size = (upper_byte << 8 | lower_byte) as usize;
+                    // offset of the record within the table
+                    let offset = self.offset + self.buffer_offset;
                     self.buffer_offset += 3;
                     let mut data_copy = vec![0u8; size];
                     data_copy.copy_from_slice(
                         &self.buffer[self.buffer_offset..(self.buffer_offset + size)],
                     );
-                    let offset = self.offset;
                     self.offset = previous_offset;
                     self.buffer_offset = previous_buffer_offset;
-                    self.buffer = previous_buffer;
-                    return SSTableValue {
+                    self.restore_block(preserved);
+                    if compressed {
+                        data_copy = decompress(data_copy);
+                    }
+                    return self.to_sstable_value(
                         offset,
-                        data: data_copy,
-                    };
+                        data_copy,
+                        compressed,
+                        separated,
+                        range_tombstone,
+                        previous_key,
+                    );
                 }
                 RecordType::START | RecordType::MIDDLE => {
+                    if start_offset.is_none()
+                        && is_prefix_compressed(self.buffer[self.buffer_offset])
+                    {
+                        previous_key = Some(self.previous_key_before(self.buffer_offset));
+                    }
                     let buffer = &self.buffer;
                     let upper_byte = buffer[self.buffer_offset + 1] as u16;
                     let lower_byte = buffer[self.buffer_offset + 2] as u16;
                     let size = (upper_byte << 8 | lower_byte) as usize;
+                    if start_offset.is_none() {
+                        start_offset = Some(self.offset + self.buffer_offset);
+                    }
                     self.buffer_offset += 3;
                     for i in self.buffer_offset..(self.buffer_offset + size) {
                         temp_buffer.push(self.buffer[i]);
                     }
                     // load the next block
-                    self.load_next_block();
+                    if self.load_next_block_preserving(&mut preserved).is_err() {
+                        return self.failed_read(
+                            previous_offset,
+                            previous_buffer_offset,
+                            preserved,
+                        );
+                    }
                 }
                 RecordType::END => {
                     let buffer = &self.buffer;
+                    let compressed = is_compressed(buffer[self.buffer_offset]);
+                    let separated = is_separated(buffer[self.buffer_offset]);
+                    let range_tombstone = is_range_tombstone(buffer[self.buffer_offset]);
                     let upper_byte = buffer[self.buffer_offset + 1] as u16;
                     let lower_byte = buffer[self.buffer_offset + 2] as u16;
                     let size = (upper_byte << 8 | lower_byte) as usize;
@@ -155,19 +1017,219 @@ impl SSTableReader {
                         temp_buffer.push(self.buffer[i]);
                     }
                     self.buffer_offset += size;
+                    // the value starts at the offset of its first chunk
+                    let offset = start_offset.unwrap_or(self.offset);
                     // reset buffer and offset to previous state
-                    let offset = self.offset;
                     self.offset = previous_offset;
                     self.buffer_offset = previous_buffer_offset;
-                    self.buffer = previous_buffer;
-                    return SSTableValue {
+                    self.restore_block(preserved);
+                    if compressed {
+                        temp_buffer = decompress(temp_buffer);
+                    }
+                    return self.to_sstable_value(
                         offset,
-                        data: temp_buffer,
-                    };
+                        temp_buffer,
+                        compressed,
+                        separated,
+                        range_tombstone,
+                        previous_key,
+                    );
                 }
-                _ => {}
+                // headers are skipped when their block is loaded so a header anywhere
+                // else is as malformed as an unknown record
+                RecordType::HEADER | RecordType::UNKNOWN => {
+                    // the size of an unknown record can't be trusted so iteration ends here
+                    self.read_failed = true;
+                    return self.failed_read(previous_offset, previous_buffer_offset, preserved);
+                }
+            }
+        }
+    }
+
+    /// Read the serialized key and value of the current record without
+    /// deserializing the value. See `SSTableValue::to_raw`.
+    ///
+    /// # Returns
+    /// Result that resolves:
+    ///  - _Ok_ - The serialized key and value of the record.
+    ///  - _Err_ - Error that occurred while reading the record.
+    pub fn read_raw<K: ResourceKey>(&mut self) -> Result<(Vec<u8>, Vec<u8>)> {
+        self.read().to_raw::<K>()
+    }
+
+    /// Copy the remaining records of the table to the writer without the padding of
+    /// their blocks. Each record is written as a `StreamedRecord` serialized with the
+    /// fixint encoding and preceded by its length as a big endian `u32`. Records can
+    /// be read back with `read_streamed_record`.
+    ///
+    /// # Arguments
+    ///  - _out_ - The writer receiving the records.
+    ///
+    /// # Returns
+    /// Result that resolves:
+    ///  - _Ok_ - The number of records written.
+    ///  - _Err_ - `SSTABLE_READ_FAILED` if the table couldn't be read completely or
+    ///    `SSTABLE_STREAM_FAILED` if a record couldn't be written.
+    pub fn stream_to<W: Write>(&mut self, mut out: W) -> Result<usize> {
+        let mut count = 0;
+        while self.has_next() {
+            let sstable_value = self.read();
+            let record = StreamedRecord {
+                data: sstable_value.data,
+                separated: sstable_value.separated,
+                separated_value: sstable_value.separated_value,
+                range_tombstone: sstable_value.range_tombstone,
+                encoding: sstable_value.encoding.id(),
+                separated_encoding: sstable_value.separated_encoding.id(),
+                codec_id: sstable_value.codec_id,
+                format_version: sstable_value.format_version,
+            };
+            let data = Encoding::Fixint
+                .serialize(&record)
+                .map_err(|_| Errors::RECORD_SERIALIZATION_FAILED)?;
+            let length = u32::try_from(data.len()).map_err(|_| Errors::SSTABLE_STREAM_FAILED)?;
+            out.write_all(&length.to_be_bytes())
+                .and_then(|_| out.write_all(&data))
+                .map_err(|_| Errors::SSTABLE_STREAM_FAILED)?;
+            count += 1;
+            self.next();
+        }
+        if self.read_failed() {
+            return Err(Errors::SSTABLE_READ_FAILED);
+        }
+        out.flush().map_err(|_| Errors::SSTABLE_STREAM_FAILED)?;
+        Ok(count)
+    }
+
+    /// Create the value for the data of a record, restoring its prefix compressed
+    /// key and reading the value from the value log if the record only holds a
+    /// pointer to it.
+    fn to_sstable_value(
+        &mut self,
+        offset: usize,
+        mut data: Vec<u8>,
+        compressed: bool,
+        separated: bool,
+        range_tombstone: bool,
+        previous_key: Option<Vec<u8>>,
+    ) -> SSTableValue {
+        if let Some(previous_key) = previous_key {
+            // malformed data is dropped so that it fails to deserialize
+            data = decode_prefix(&data, &previous_key).unwrap_or_default();
+        }
+        let transform = self.value_transform.as_ref();
+        if let (false, false, Some(transform)) = (separated, range_tombstone, transform) {
+            if !data.is_empty() {
+                data = transform.on_read(data).unwrap_or_default();
+            }
+        }
+        let mut separated_value = None;
+        if separated {
+            let pointer = self
+                .encoding
+                .deserialize::<ValuePointer>(data.as_slice())
+                .ok();
+            if let (Some(pointer), Some(value_log)) = (pointer, self.value_log.as_mut()) {
+                separated_value = read_value(value_log.as_mut(), &pointer);
+            }
+            if let Some(transform) = transform {
+                separated_value = separated_value.and_then(|value| transform.on_read(value).ok());
             }
         }
+        SSTableValue {
+            offset,
+            data,
+            compressed,
+            separated,
+            separated_value,
+            range_tombstone,
+            encoding: self.encoding,
+            separated_encoding: self.value_log_encoding,
+            codec_id: self.codec_id,
+            record_codec: self.record_codec.clone(),
+            format_version: self.format_version,
+            read_failed: false,
+        }
+    }
+
+    /// Iterate over the offset and stored length in bytes of each remaining record
+    /// in the SSTable without decoding them. The length of a record split across
+    /// blocks is the combined length of its chunks. The offsets can be used as
+    /// seek targets to build external indexes.
+    /// Iteration stops after the first error.
+    pub fn offsets(&mut self) -> impl Iterator<Item = Result<(usize, usize)>> + '_ {
+        let mut failed = false;
+        std::iter::from_fn(move || {
+            if failed || !self.has_next() {
+                return None;
+            }
+            let extent = self.read_extent();
+            failed = extent.is_err();
+            if !failed {
+                self.next();
+            }
+            Some(extent)
+        })
+    }
+
+    /// Get the offset and stored length of the current record without reading its data.
+    fn read_extent(&mut self) -> Result<(usize, usize)> {
+        let previous_buffer_offset = self.buffer_offset;
+        let previous_offset = self.offset;
+        // the current block is swapped out instead of copied if other blocks are loaded
+        let mut preserved = false;
+        let mut start_offset = None;
+        let mut length = 0;
+        let extent = loop {
+            if self.buffer_offset >= self.buffer.len() {
+                break Err(Errors::SSTABLE_READ_FAILED);
+            }
+            let record_type = to_record_type(self.buffer[self.buffer_offset]);
+            // padding at the end of a block may be too short to hold a size
+            let size = if self.buffer_offset + 3 <= self.buffer.len() {
+                let upper_byte = self.buffer[self.buffer_offset + 1] as u16;
+                let lower_byte = self.buffer[self.buffer_offset + 2] as u16;
+                (upper_byte << 8 | lower_byte) as usize
+            } else {
+                0
+            };
+            match record_type {
+                RecordType::PADDING => {
+                    if self.offset + self.block_size >= self.size {
+                        break Err(Errors::SSTABLE_READ_FAILED);
+                    }
+                    if let Err(err) = self.load_next_block_preserving(&mut preserved) {
+                        break Err(err);
+                    }
+                }
+                RecordType::COMPLETE => {
+                    break Ok((self.offset + self.buffer_offset, size));
+                }
+                RecordType::START | RecordType::MIDDLE => {
+                    if start_offset.is_none() {
+                        start_offset = Some(self.offset + self.buffer_offset);
+                    }
+                    length += size;
+                    if self.offset + self.block_size >= self.size {
+                        break Err(Errors::SSTABLE_READ_FAILED);
+                    }
+                    if let Err(err) = self.load_next_block_preserving(&mut preserved) {
+                        break Err(err);
+                    }
+                }
+                RecordType::END => {
+                    let offset = start_offset.unwrap_or(self.offset + self.buffer_offset);
+                    break Ok((offset, length + size));
+                }
+                RecordType::HEADER | RecordType::UNKNOWN => {
+                    break Err(Errors::SSTABLE_READ_FAILED);
+                }
+            }
+        };
+        self.offset = previous_offset;
+        self.buffer_offset = previous_buffer_offset;
+        self.restore_block(preserved);
+        extent
     }
 
     /// Seek the reader to the block containing the specified offset.
@@ -181,26 +1243,201 @@ impl SSTableReader {
             // get block that contains this offset
             let block_number: usize = (offset as f64 / self.block_size as f64).floor() as usize;
             let block_offset = block_number * self.block_size;
-            // load the block at this offset
-            self.load_block_at(block_offset);
-            self.buffer_offset = offset - block_offset;
+            // load the block at this offset, records never start within its header
+            self.load_block_at(block_offset)?;
+            self.buffer_offset = std::cmp::max(offset - block_offset, self.buffer_offset);
             return Ok(());
         }
         Err(Errors::SSTABLE_INVALID_READ_OFFSET)
     }
 
+    /// Seek the reader to the first record with a key greater than or equal to
+    /// the target key. Blocks are binary searched by their first key, read from the
+    /// block header of tables written with block headers or from the first record
+    /// starting in the block otherwise, and records are walked from the block found.
+    /// Use `seek_to_key_from` when the offset of a smaller key is known, such as the
+    /// address held by the sparse index.
+    ///
+    /// # Arguments
+    ///  - _key_ - The target key.
+    ///
+    /// # Returns
+    /// Result that resolves:
+    ///  - _Ok_ - Flag specifying whether a record with the target key exists.
+    ///    If every key is smaller than the target the reader is left past the
+    ///    last value, at the range tombstones of the table if it has any.
+    ///  - _Err_ - Error that occurred while reading records.
+    pub fn seek_to_key<K: ResourceKey>(&mut self, key: &K) -> Result<bool> {
+        if self.size == 0 {
+            return Ok(false);
+        }
+        let offset = self.search_blocks(key)?;
+        self.seek_to_key_from(key, offset)
+    }
+
+    // Find the offset of the first value starting in the last block whose first key
+    // is smaller than the key, 0 if there is no such block. Blocks in which no value
+    // starts are never picked.
+    fn search_blocks<K: ResourceKey>(&mut self, key: &K) -> Result<usize> {
+        let mut low = 0;
+        let mut high = (self.data_size + self.block_size - 1) / self.block_size;
+        while high - low > 1 {
+            let middle = (low + high) / 2;
+            match self.first_key_of_block::<K>(middle)? {
+                Some(first_key) if &first_key < key => low = middle,
+                _ => high = middle,
+            }
+        }
+        if low == 0 {
+            return Ok(0);
+        }
+        let offset = self.first_record_of_block(low)?;
+        Ok(offset.unwrap_or(0))
+    }
+
+    // Get the key of the first value starting in the block, from the block header
+    // if the block has one.
+    fn first_key_of_block<K: ResourceKey>(&mut self, block: usize) -> Result<Option<K>> {
+        if self.first_record_of_block(block)?.is_none() {
+            return Ok(None);
+        }
+        if let Some(header) = &self.block_header {
+            if header.first_key.is_empty() {
+                return Ok(None);
+            }
+            return Ok(self.encoding.deserialize::<K>(&header.first_key).ok());
+        }
+        let sstable_value = self.read();
+        if self.read_failed {
+            return Err(Errors::SSTABLE_READ_FAILED);
+        }
+        // range tombstones are stored after all values of the table
+        if sstable_value.range_tombstone {
+            return Ok(None);
+        }
+        sstable_value.to_key::<K>().map(Some)
+    }
+
+    // Load the block and move to the first record starting in it.
+    //
+    // # Returns
+    // The offset of the record, not set if no record starts in the block.
+    fn first_record_of_block(&mut self, block: usize) -> Result<Option<usize>> {
+        self.load_block_at(block * self.block_size)?;
+        // the block may start with the end of a record split across blocks
+        if let RecordType::END = to_record_type(self.buffer[self.buffer_offset]) {
+            let size = u16::from_be_bytes([
+                self.buffer[self.buffer_offset + 1],
+                self.buffer[self.buffer_offset + 2],
+            ]) as usize;
+            self.buffer_offset += Record::RECORD_BASE_SIZE_IN_BYTES + size;
+        }
+        if self.buffer_offset + Record::RECORD_BASE_SIZE_IN_BYTES > self.block_size {
+            return Ok(None);
+        }
+        match to_record_type(self.buffer[self.buffer_offset]) {
+            RecordType::COMPLETE | RecordType::START => Ok(Some(self.offset + self.buffer_offset)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Seek the reader to the first record with a key greater than or equal to
+    /// the target key by walking records from the supplied offset. The offset
+    /// should be that of a record with a key no larger than the target, such as
+    /// the address held by the sparse index for the nearest sampled key.
+    /// See `seek_to_key`.
+    ///
+    /// # Arguments
+    ///  - _key_ - The target key.
+    ///  - _offset_ - The offset of the record from which to start walking.
+    pub fn seek_to_key_from<K: ResourceKey>(&mut self, key: &K, offset: usize) -> Result<bool> {
+        // an empty table has no offsets to seek to
+        if self.size == 0 {
+            return Ok(false);
+        }
+        self.seek_closest(offset)?;
+        while self.has_next() {
+            let sstable_value = self.read();
+            if self.read_failed {
+                return Err(Errors::SSTABLE_READ_FAILED);
+            }
+            // range tombstones are stored after all values of the table
+            if sstable_value.range_tombstone {
+                return Ok(false);
+            }
+            let record_key = sstable_value.to_key::<K>()?;
+            if &record_key >= key {
+                return Ok(&record_key == key);
+            }
+            self.next();
+        }
+        Ok(false)
+    }
+
+    /// Iterate over the values of the table with keys in the range `[start, end]`.
+    /// The reader is moved to the first key greater than or equal to `start` with
+    /// `seek_to_key` and values are read until a key greater than `end` is reached.
+    /// All retained versions of a key in the range are returned.
+    ///
+    /// # Arguments
+    ///  - _start_ - The inclusive lower bound of the range.
+    ///  - _end_ - The inclusive upper bound of the range.
+    ///
+    /// # Returns
+    /// Result that resolves:
+    ///  - _Ok_ - Iterator over the values in the range sorted by key. The iterator
+    ///    ends after yielding the first error.
+    ///  - _Err_ - Error that occurred while seeking to the start of the range.
+    pub fn range_iter<K: ResourceKey, V: ResourceValue>(
+        &mut self,
+        start: &K,
+        end: &K,
+    ) -> Result<impl Iterator<Item = Result<Value<K, V>>> + '_> {
+        self.seek_to_key(start)?;
+        let end = end.clone();
+        let mut done = false;
+        Ok(std::iter::from_fn(move || {
+            if done || !self.has_next() {
+                if !done && self.read_failed {
+                    done = true;
+                    return Some(Err(Errors::SSTABLE_READ_FAILED));
+                }
+                return None;
+            }
+            let sstable_value = self.read();
+            // range tombstones are stored after all values of the table
+            if sstable_value.range_tombstone {
+                done = true;
+                return None;
+            }
+            let record = match sstable_value.to_record::<K, V>() {
+                Ok(record) => record,
+                Err(err) => {
+                    done = true;
+                    return Some(Err(err));
+                }
+            };
+            if record.key > end {
+                done = true;
+                return None;
+            }
+            self.next();
+            Some(Ok(record))
+        }))
+    }
+
     /// Check whether more values can be processed in the SSTable.
     ///
     /// # Returns
     /// Flag specifying whether more values can be read from the SSTable.
     pub fn has_next(&self) -> bool {
-        if self.offset >= self.size {
+        if self.read_failed || self.offset >= self.data_size {
             return false;
         }
         let record_type = to_record_type(self.buffer[self.buffer_offset]);
         return match record_type {
             RecordType::PADDING => {
-                return self.offset + self.block_size < self.size;
+                return self.offset + self.block_size < self.data_size;
             }
             _ => true,
         };
@@ -213,10 +1450,16 @@ impl SSTableReader {
             let buffer = &self.buffer;
             let record_type = to_record_type(self.buffer[self.buffer_offset]);
             match record_type {
-                // check if this is the last block in table before loading next block
+                // the current value is the first value in the next block
+                // so load the next block and skip past that value
                 RecordType::PADDING => {
-                    self.load_next_block();
-                    break;
+                    if self.offset + self.block_size >= self.data_size {
+                        self.offset = self.data_size;
+                        break;
+                    }
+                    if self.load_next_block().is_err() {
+                        break;
+                    }
                 }
                 RecordType::COMPLETE => {
                     let upper_byte = buffer[self.buffer_offset + 1] as u16;
@@ -225,12 +1468,15 @@ impl SSTableReader {
                     self.buffer_offset += 3;
                     self.buffer_offset += size;
                     if self.buffer_offset == self.block_size {
-                        self.load_next_block();
+                        // a failed load ends iteration through `read_failed`
+                        let _ = self.load_next_block();
                     }
                     break;
                 }
                 RecordType::START | RecordType::MIDDLE => {
-                    self.load_next_block();
+                    if self.load_next_block().is_err() {
+                        break;
+                    }
                 }
                 RecordType::END => {
                     let upper_byte = buffer[self.buffer_offset + 1] as u16;
@@ -239,24 +1485,239 @@ impl SSTableReader {
                     let size = (upper_byte << 8 | lower_byte) as usize;
                     self.buffer_offset += size;
                     if self.buffer_offset == self.block_size {
-                        self.load_next_block();
+                        // a failed load ends iteration through `read_failed`
+                        let _ = self.load_next_block();
                     }
                     break;
                 }
-                _ => {}
+                RecordType::HEADER | RecordType::UNKNOWN => {
+                    self.read_failed = true;
+                    break;
+                }
+            }
+        }
+    }
+
+    fn load_next_block(&mut self) -> Result<()> {
+        self.load_block_at(self.offset + self.block_size)
+    }
+
+    // Load the next block keeping the current block aside the first time so
+    // that it can be restored without being read again.
+    fn load_next_block_preserving(&mut self, preserved: &mut bool) -> Result<()> {
+        if !*preserved {
+            std::mem::swap(&mut self.buffer, &mut self.preserved_buffer);
+            *preserved = true;
+        }
+        // a record never continues past the end of the table, this happens
+        // when the final block of a split record was never written
+        if self.offset + self.block_size >= self.size {
+            self.read_failed = true;
+            return Err(Errors::SSTABLE_READ_FAILED);
+        }
+        self.load_next_block()
+    }
+
+    // Reset the reader to the value being read after a block failed to load.
+    // The returned value holds no data and fails to deserialize with `SSTABLE_READ_FAILED`.
+    fn failed_read(
+        &mut self,
+        previous_offset: usize,
+        previous_buffer_offset: usize,
+        preserved: bool,
+    ) -> SSTableValue {
+        self.offset = previous_offset;
+        self.buffer_offset = previous_buffer_offset;
+        self.restore_block(preserved);
+        let mut sstable_value = self.to_sstable_value(
+            previous_offset + previous_buffer_offset,
+            Vec::new(),
+            false,
+            false,
+            false,
+            None,
+        );
+        sstable_value.read_failed = true;
+        sstable_value
+    }
+
+    // Get the key of the last prefix compressed record completed in the current
+    // block before the supplied position. Keys are restored by walking the records
+    // of the block from its start or from where the previous walk stopped.
+    fn previous_key_before(&mut self, position: usize) -> Vec<u8> {
+        let (mut walked, mut key) = match self.prefix_cursor.take() {
+            Some((block_offset, walked, key))
+                if block_offset == self.offset && walked <= position =>
+            {
+                (walked, key)
+            }
+            _ => (0, Vec::new()),
+        };
+        while walked + Record::RECORD_BASE_SIZE_IN_BYTES <= position {
+            let type_byte = self.buffer[walked];
+            let upper_byte = self.buffer[walked + 1] as usize;
+            let lower_byte = self.buffer[walked + 2] as usize;
+            let data_start = walked + Record::RECORD_BASE_SIZE_IN_BYTES;
+            walked = data_start + (upper_byte << 8 | lower_byte);
+            // chunks of records started in previous blocks are skipped
+            let is_complete = matches!(to_record_type(type_byte), RecordType::COMPLETE);
+            if is_complete && is_prefix_compressed(type_byte) {
+                let decoded = self
+                    .buffer
+                    .get(data_start..walked)
+                    .and_then(|data| decode_key_prefix(data, &key));
+                if let Some(decoded) = decoded {
+                    key = decoded;
+                }
             }
         }
+        self.prefix_cursor = Some((self.offset, walked, key.clone()));
+        key
     }
 
-    fn load_next_block(&mut self) {
-        self.load_block_at(self.offset + self.block_size);
+    fn restore_block(&mut self, preserved: bool) {
+        if preserved {
+            std::mem::swap(&mut self.buffer, &mut self.preserved_buffer);
+        }
     }
 
-    fn load_block_at(&mut self, offset: usize) {
-        let mut buffer = vec![0u8; self.block_size];
+    // Blocks are read into the existing buffers so that scans don't allocate per block.
+    fn load_block_at(&mut self, offset: usize) -> Result<()> {
         self.offset = offset;
         self.buffer_offset = 0;
-        self.reader.read_at(&mut buffer, self.offset as u64);
-        self.buffer = buffer;
+        self.blocks_read += 1;
+        self.buffer.resize(self.block_size, 0);
+        // blocks past the end of the table are never cached
+        let cache = self.cache.clone().filter(|_| offset < self.size);
+        if let Some(cache) = &cache {
+            if cache
+                .lock()
+                .unwrap()
+                .read_into(&self.path, offset, &mut self.buffer)
+            {
+                self.read_block_header();
+                return Ok(());
+            }
+        }
+        if let Err(err) = self.read_block(offset) {
+            self.read_failed = true;
+            return Err(err);
+        }
+        if let Some(cache) = &cache {
+            cache
+                .lock()
+                .unwrap()
+                .insert(&self.path, offset, self.buffer.clone());
+        }
+        self.read_block_header();
+        Ok(())
+    }
+
+    // Read the header of the loaded block and move past it. Blocks of tables
+    // written before block headers are never read as having one.
+    fn read_block_header(&mut self) {
+        self.block_header = None;
+        if self.format_version < BLOCK_HEADER_FORMAT_VERSION || self.buffer_offset != 0 {
+            return;
+        }
+        if let Some((header, size)) = BlockHeader::read(&self.buffer) {
+            self.block_header = Some(header);
+            self.buffer_offset = size;
+        }
+    }
+
+    fn read_block(&mut self, offset: usize) -> Result<()> {
+        let readahead_size = self.block_size * self.readahead_blocks;
+        let readahead_end = self.readahead_offset + self.readahead.len();
+        if self.readahead.len() != readahead_size
+            || offset < self.readahead_offset
+            || offset + self.block_size > readahead_end
+        {
+            self.readahead.resize(readahead_size, 0);
+            // reads past the end of the table leave the buffer untouched
+            for byte in self.readahead.iter_mut() {
+                *byte = 0;
+            }
+            self.readahead_offset = offset;
+            let read = self
+                .reader
+                .read_at(&mut self.readahead, offset as u64)
+                .unwrap_or(0);
+            // only the last block of the table may be partial, a short read
+            // before the end of the table means the data is not available
+            let expected = std::cmp::min(readahead_size, self.size.saturating_sub(offset));
+            if read < expected {
+                self.readahead.clear();
+                return Err(Errors::SSTABLE_READ_FAILED);
+            }
+        }
+        let start = offset - self.readahead_offset;
+        self.buffer
+            .copy_from_slice(&self.readahead[start..start + self.block_size]);
+        Ok(())
+    }
+}
+
+/// Walk the records of the block collecting their types. Padding records and the
+/// zeroed bytes too few to hold a record are only allowed at the end of the block.
+///
+/// # Returns
+/// The types of the records in the order they first appear along with a flag
+/// specifying whether every record fits in the block and has a known type.
+fn parse_block(block: &[u8]) -> (Vec<RecordType>, bool) {
+    let mut record_types = Vec::new();
+    let mut offset = 0;
+    while offset < block.len() {
+        if block.len() - offset < Record::RECORD_BASE_SIZE_IN_BYTES {
+            let zeroed = block[offset..].iter().all(|byte| *byte == 0);
+            return (record_types, zeroed);
+        }
+        let record_type = to_record_type(block[offset]);
+        let data_size = u16::from_be_bytes([block[offset + 1], block[offset + 2]]) as usize;
+        if !record_types.contains(&record_type) {
+            record_types.push(record_type);
+        }
+        offset += Record::RECORD_BASE_SIZE_IN_BYTES + data_size;
+        match record_type {
+            RecordType::UNKNOWN => return (record_types, false),
+            RecordType::PADDING => return (record_types, offset == block.len()),
+            _ if offset > block.len() => return (record_types, false),
+            _ => {}
+        }
+    }
+    (record_types, true)
+}
+
+/// Count the records of the block following its header, leaving out padding.
+fn count_records(block: &[u8]) -> usize {
+    let mut records = 0;
+    let mut offset = 0;
+    while offset + Record::RECORD_BASE_SIZE_IN_BYTES <= block.len() {
+        if let RecordType::PADDING = to_record_type(block[offset]) {
+            break;
+        }
+        let data_size = u16::from_be_bytes([block[offset + 1], block[offset + 2]]) as usize;
+        offset += Record::RECORD_BASE_SIZE_IN_BYTES + data_size;
+        records += 1;
+    }
+    records
+}
+
+/// Find the offset past the last block of the table that holds records.
+/// Blocks are inspected from the end of the table and a block whose first
+/// record is padding holds no records, so only the first byte of each
+/// trailing block is read.
+fn find_data_size(reader: &mut dyn StorageFile, size: usize, block_size: usize) -> usize {
+    let mut data_size = ((size + block_size - 1) / block_size) * block_size;
+    let mut first_byte = [0u8; 1];
+    while data_size > 0 {
+        let block_offset = data_size - block_size;
+        let read = reader.read_at(&mut first_byte, block_offset as u64);
+        let is_padding = matches!(to_record_type(first_byte[0]), RecordType::PADDING);
+        if read.is_err() || !is_padding {
+            break;
+        }
+        data_size = block_offset;
     }
+    data_size
 }
@@ -1,63 +1,98 @@
 use crate::result::{Errors, Result};
 use crate::options::DharmaOpts;
-use crate::storage::block::{create_blocks, write_block_to_disk, Record, Value};
+use crate::storage::backend::StorageFile;
+use crate::storage::block::{
+    create_blocks_with_codec, create_blocks_with_tombstones, decompress, is_compressed,
+    write_block_to_disk, write_block_with_footer, Block, BlockBuilder, RangeTombstone, Record,
+    Value,
+};
+use crate::storage::checksum::{new_table_hasher, seal_table_checksum, to_checksum_footer};
+use crate::storage::codec::{
+    codec_from_footer, record_codec_id, to_codec_footer, BincodeCodec, RecordCodec,
+};
+use crate::storage::encoding::Encoding;
+use crate::storage::footer::{to_summary_footer, TableSummary};
+use crate::storage::manifest::{
+    table_version_from_footer, to_table_version_footer, FORMAT_VERSION, MIN_TABLE_FORMAT_VERSION,
+};
+use crate::storage::value_log::ValueLog;
 use crate::traits::{ResourceKey, ResourceValue};
-use buffered_offset_reader::{BufOffsetReader, OffsetReadMut};
-use log;
 use serde::de::DeserializeOwned;
-use std::fs::{create_dir_all, File};
+use serde::Serialize;
+use std::ops::Range;
 use std::path::{Path, PathBuf};
+use xxhash_rust::xxh64::Xxh64;
 
 /// Write the list of key value pairs, sorted by key to a series of SSTables on disk.
+/// No table is created if the list is empty.
 /// # Arguments
 /// * _option_  - Configurations options specified as `DharmaOpts`
 /// * _tuples_  - List of key value pairs sorted by key.
 ///
 /// # Returns
 /// A `Result` which is
-/// - `Ok`: - Empty value
+/// - `Ok`: - Optional that contains the path of the written SSTable
 /// - `Err`: - Error type as specified by `Errors` module
 pub fn write_sstable<K: ResourceKey, V: ResourceValue>(
     options: &DharmaOpts,
     tuples: &Vec<(K, V)>,
     table_number: usize,
-) -> Result<PathBuf> {
-    let values: Vec<Value<K, V>> = tuples
-        .iter()
-        .map(|tup| {
-            return Value::new(tup.0.clone(), tup.1.clone());
-        })
-        .collect();
-    // pack values into blocks
-    let mut blocks = Vec::new();
-    // pack the values into blocks of fixed size as specified by `options.block_size_in_bytes`
-    create_blocks(options, &values, &mut blocks);
-    // write this chunk to disk
-    let path_str = format!("{0}/tables/{1}.db", options.path, table_number);
-    let path = Path::new(&path_str);
-    if path.parent().is_some() && !path.parent().unwrap().exists() {
-        create_dir_all(path.parent().unwrap());
+) -> Result<Option<PathBuf>> {
+    write_values_sstable(options, &to_values(tuples), table_number)
+}
+
+/// Write the list of values sorted by key and by descending sequence number
+/// for values of the same key to an SSTable on disk.
+/// No table is created if the list is empty.
+/// # Arguments
+/// * _option_  - Configurations options specified as `DharmaOpts`
+/// * _values_  - List of values sorted by key.
+/// * _table_number_ - The number of the table used to name the SSTable.
+///
+/// # Returns
+/// A `Result` which is
+/// - `Ok`: - Optional that contains the path of the written SSTable
+/// - `Err`: - Error type as specified by `Errors` module
+pub fn write_values_sstable<K: ResourceKey, V: ResourceValue>(
+    options: &DharmaOpts,
+    values: &Vec<Value<K, V>>,
+    table_number: usize,
+) -> Result<Option<PathBuf>> {
+    write_table_sstable(options, values, &Vec::new(), table_number)
+}
+
+/// Write the list of values sorted by key followed by the range tombstones
+/// to an SSTable on disk. No table is created if both lists are empty.
+/// # Arguments
+/// * _option_  - Configurations options specified as `DharmaOpts`
+/// * _values_  - List of values sorted by key.
+/// * _tombstones_ - List of range tombstones stored after the values.
+/// * _table_number_ - The number of the table used to name the SSTable.
+///
+/// # Returns
+/// A `Result` which is
+/// - `Ok`: - Optional that contains the path of the written SSTable
+/// - `Err`: - Error type as specified by `Errors` module
+pub fn write_table_sstable<K: ResourceKey, V: ResourceValue>(
+    options: &DharmaOpts,
+    values: &[Value<K, V>],
+    tombstones: &[RangeTombstone<K>],
+    table_number: usize,
+) -> Result<Option<PathBuf>> {
+    if values.is_empty() && tombstones.is_empty() {
+        return Ok(None);
     }
-    // create file for SSTable
-    let file_result = File::create(&path);
-    if file_result.is_ok() {
-        let mut file = file_result.unwrap();
-        // write all blocks to SSTable file
-        for (block_counter, block) in blocks.iter().enumerate() {
-            let write_result = write_block_to_disk(options, &mut file, &block);
-            if write_result.is_err() {
-                log::error!(
-                    "Failed to write block from chunk {0} to disk",
-                    block_counter
-                );
-                return Err(Errors::SSTABLE_CREATION_FAILED);
-            }
+    let path = options.table_path(table_number);
+    if let Some(parent) = path.parent() {
+        if !options.backend.exists(parent) {
+            options
+                .backend
+                .create_dir_all(parent)
+                .map_err(|_| Errors::SSTABLE_CREATION_FAILED)?;
         }
-    } else {
-        log::error!("Failed to create SSTable from chunk from values");
-        return Err(Errors::SSTABLE_CREATION_FAILED);
     }
-    Ok(PathBuf::from(path_str))
+    write_table_at_path(options, values, tombstones, &path)?;
+    Ok(Some(path))
 }
 
 /// Write the list of key value pairs, sorted by key to a series of SSTables on disk.
@@ -74,36 +109,572 @@ pub fn write_sstable_at_path<K: ResourceKey, V: ResourceValue>(
     tuples: &Vec<(K, V)>,
     path: &PathBuf,
 ) -> Result<()> {
-    let values: Vec<Value<K, V>> = tuples
-        .iter()
-        .map(|tup| {
-            return Value::new(tup.0.clone(), tup.1.clone());
-        })
-        .collect();
+    write_values_at_path(options, &to_values(tuples), path)
+}
+
+/// Write the list of values sorted by key and by descending sequence number
+/// for values of the same key to an SSTable at the specified path.
+/// # Arguments
+/// * _option_  - Configurations options specified as `DharmaOpts`
+/// * _values_  - List of values sorted by key.
+/// * _path_ - The path at which to write the SSTable.
+///
+/// # Returns
+/// A `Result` which is
+/// - `Ok`: - Empty value
+/// - `Err`: - Error type as specified by `Errors` module
+pub fn write_values_at_path<K: ResourceKey, V: ResourceValue>(
+    options: &DharmaOpts,
+    values: &Vec<Value<K, V>>,
+    path: &PathBuf,
+) -> Result<()> {
+    write_table_at_path(options, values, &Vec::new(), path)
+}
+
+/// Write the list of values sorted by key followed by the range tombstones
+/// to an SSTable at the specified path.
+/// # Arguments
+/// * _option_  - Configurations options specified as `DharmaOpts`
+/// * _values_  - List of values sorted by key.
+/// * _tombstones_ - List of range tombstones stored after the values.
+/// * _path_ - The path at which to write the SSTable.
+///
+/// # Returns
+/// A `Result` which is
+/// - `Ok`: - Empty value
+/// - `Err`: - Error type as specified by `Errors` module
+pub fn write_table_at_path<K: ResourceKey, V: ResourceValue>(
+    options: &DharmaOpts,
+    values: &[Value<K, V>],
+    tombstones: &[RangeTombstone<K>],
+    path: &PathBuf,
+) -> Result<()> {
+    let table = pack_table(options, values, tombstones)?;
+    write_packed_table(options, &table, path)
+}
+
+/// Write the list of values sorted by key serialized with the supplied codec
+/// followed by the range tombstones to an SSTable at the specified path.
+/// The codec is recorded in the footer of the table, read the table back with
+/// `read_sstable_with_codec` or `SSTableValue::to_record_with`.
+/// # Arguments
+/// * _option_  - Configurations options specified as `DharmaOpts`
+/// * _codec_ - The codec used to serialize the values.
+/// * _values_  - List of values sorted by key.
+/// * _tombstones_ - List of range tombstones stored after the values.
+/// * _path_ - The path at which to write the SSTable.
+///
+/// # Returns
+/// A `Result` which is
+/// - `Ok`: - Empty value
+/// - `Err`: - Error type as specified by `Errors` module
+pub fn write_table_with_codec<K: ResourceKey, V: ResourceValue, C: RecordCodec>(
+    options: &DharmaOpts,
+    codec: &C,
+    values: &[Value<K, V>],
+    tombstones: &[RangeTombstone<K>],
+    path: &PathBuf,
+) -> Result<()> {
+    let table = pack_table_with_codec(options, codec, values, tombstones)?;
+    write_packed_table(options, &table, path)
+}
+
+/// Blocks of an SSTable packed by `pack_table` that are ready to be written.
+/// Packed tables hold no keys or values so they can be written on any thread.
+pub struct PackedTable {
+    blocks: Vec<Block>,
+    codec_id: u8,
+    summary: TableSummary,
+}
+
+/// Pack the list of values sorted by key followed by the range tombstones into the
+/// blocks of an SSTable without writing it, see `write_packed_table`. Values large
+/// enough to be separated are appended to the value log.
+/// # Arguments
+/// * _option_  - Configurations options specified as `DharmaOpts`
+/// * _values_  - List of values sorted by key.
+/// * _tombstones_ - List of range tombstones stored after the values.
+///
+/// # Returns
+/// A `Result` which is
+/// - `Ok`: - The packed table.
+/// - `Err`: - Error type as specified by `Errors` module
+pub fn pack_table<K: ResourceKey, V: ResourceValue>(
+    options: &DharmaOpts,
+    values: &[Value<K, V>],
+    tombstones: &[RangeTombstone<K>],
+) -> Result<PackedTable> {
+    pack_table_using(
+        options,
+        record_codec_id(options),
+        values,
+        tombstones,
+        |values, blocks| create_blocks_with_tombstones(options, values, tombstones, blocks),
+    )
+}
+
+/// Pack the values serialized with the supplied codec followed by the range
+/// tombstones into the blocks of an SSTable. See `pack_table`.
+pub fn pack_table_with_codec<K: ResourceKey, V: ResourceValue, C: RecordCodec>(
+    options: &DharmaOpts,
+    codec: &C,
+    values: &[Value<K, V>],
+    tombstones: &[RangeTombstone<K>],
+) -> Result<PackedTable> {
+    pack_table_using(options, codec.id(), values, tombstones, |values, blocks| {
+        create_blocks_with_codec(options, codec, values, tombstones, blocks)
+    })
+}
+
+// pack the values with `create_blocks` which serializes them with the codec of the
+// supplied identifier
+fn pack_table_using<K, V, F>(
+    options: &DharmaOpts,
+    codec_id: u8,
+    values: &[Value<K, V>],
+    tombstones: &[RangeTombstone<K>],
+    create_blocks: F,
+) -> Result<PackedTable>
+where
+    K: ResourceKey,
+    V: ResourceValue,
+    F: FnOnce(&[Value<K, V>], &mut Vec<Block>) -> Result<()>,
+{
+    // move large values to the value log so the table only holds pointers to them
+    let separated_values;
+    let values = match options.value_separation_min_bytes {
+        Some(min_bytes) => {
+            let mut value_log = ValueLog::open(options, min_bytes)?;
+            separated_values = values
+                .iter()
+                .map(|value| value_log.separate(value))
+                .collect::<Result<Vec<Value<K, V>>>>()?;
+            value_log.sync()?;
+            &separated_values
+        }
+        None => values,
+    };
     // pack values into blocks
     let mut blocks = Vec::new();
     // pack the values into blocks of fixed size as specified by `options.block_size_in_bytes`
-    create_blocks(options, &values, &mut blocks);
+    if create_blocks(values, &mut blocks).is_err() {
+        options.log_error(&format!(
+            "Failed to serialize values with codec {0}",
+            codec_id
+        ));
+        return Err(Errors::SSTABLE_CREATION_FAILED);
+    }
+    Ok(PackedTable {
+        blocks,
+        codec_id,
+        summary: TableSummary::of(options.encoding, values, tombstones),
+    })
+}
+
+/// Write the packed table to an SSTable at the specified path.
+/// # Arguments
+/// * _option_  - Configurations options specified as `DharmaOpts`
+/// * _table_ - The table packed by `pack_table`.
+/// * _path_ - The path at which to write the SSTable.
+///
+/// # Returns
+/// A `Result` which is
+/// - `Ok`: - Empty value
+/// - `Err`: - Error type as specified by `Errors` module
+pub fn write_packed_table(options: &DharmaOpts, table: &PackedTable, path: &PathBuf) -> Result<()> {
     // create file for SSTable
-    let file_result = File::create(path);
+    let file_result = options.backend.create(path);
     if file_result.is_ok() {
         let mut file = file_result.unwrap();
         // write all blocks to SSTable file
-        for (block_counter, block) in blocks.iter().enumerate() {
-            let write_result = write_block_to_disk(options, &mut file, &block);
+        write_blocks_with_footer(
+            options,
+            file.as_mut(),
+            &table.blocks,
+            table.codec_id,
+            &table.summary,
+            new_table_hasher(),
+            options.preallocate_tables,
+        )?;
+        if file.sync().is_err() {
+            options.log_error("Failed to sync SSTable to disk");
+            return Err(Errors::SSTABLE_CREATION_FAILED);
+        }
+    } else {
+        options.log_error("Failed to create SSTable from chunk from values");
+        return Err(Errors::SSTABLE_CREATION_FAILED);
+    }
+    Ok(())
+}
+
+/// Split values sorted by key into consecutive ranges that each fit in an SSTable
+/// of at most `max_table_size_in_bytes`. Tables are split on block boundaries and
+/// all versions of a key are kept in the same table so the tables hold disjoint
+/// key ranges. A range may exceed the size when the versions of a single key don't
+/// fit in a table by themselves. Sizes are estimated from values stored inline.
+///
+/// # Arguments
+/// * _option_  - Configurations options specified as `DharmaOpts`
+/// * _values_  - List of values sorted by key.
+/// * _max_table_size_in_bytes_ - The maximum size of each table.
+///
+/// # Returns
+/// A `Result` which is
+/// - `Ok`: - The ranges of values to write to each table in order. A single empty
+///   range is returned if there are no values.
+/// - `Err`: - `RECORD_SERIALIZATION_FAILED` or the error returned by the codec if a
+///   value couldn't be serialized.
+pub fn split_values_by_table_size<K: ResourceKey, V: ResourceValue>(
+    options: &DharmaOpts,
+    values: &[Value<K, V>],
+    max_table_size_in_bytes: usize,
+) -> Result<Vec<Range<usize>>> {
+    // one block is reserved for the last partially filled block and the footer
+    let max_full_blocks = (max_table_size_in_bytes / options.block_size_in_bytes).saturating_sub(1);
+    let mut ranges = Vec::new();
+    let mut start = 0;
+    let mut builder = BlockBuilder::new(options);
+    let mut blocks = Vec::new();
+    let mut key_start = 0;
+    while key_start < values.len() {
+        let mut key_end = key_start + 1;
+        while key_end < values.len() && values[key_end].key == values[key_start].key {
+            key_end += 1;
+        }
+        for value in &values[key_start..key_end] {
+            builder.add(options, value, &mut blocks)?;
+        }
+        // the footer needs a block of its own if it doesn't fit in the padding
+        let summary = TableSummary::of(options.encoding, &values[start..key_end], &[]);
+        let footer_size_in_bytes = table_footer(options, record_codec_id(options), &summary).len();
+        let footer_blocks = !builder.has_room_for_footer(footer_size_in_bytes) as usize;
+        if blocks.len() + footer_blocks > max_full_blocks && key_start > start {
+            // start the next table with the versions of this key
+            ranges.push(start..key_start);
+            start = key_start;
+            builder = BlockBuilder::new(options);
+            blocks = Vec::new();
+            for value in &values[key_start..key_end] {
+                builder.add(options, value, &mut blocks)?;
+            }
+        }
+        key_start = key_end;
+    }
+    ranges.push(start..values.len());
+    Ok(ranges)
+}
+
+/// Split values sorted by key into at most `partitions` consecutive ranges holding
+/// about as many values each. All versions of a key are kept in the same range so
+/// the tables written from the ranges hold disjoint key ranges.
+///
+/// # Arguments
+/// * _values_  - List of values sorted by key.
+/// * _partitions_ - The number of ranges to split the values into, zero is treated as one.
+///
+/// # Returns
+/// The ranges of values in order. A single empty range is returned if there are no values.
+pub fn split_values_into_partitions<K: ResourceKey, V: ResourceValue>(
+    values: &[Value<K, V>],
+    partitions: usize,
+) -> Vec<Range<usize>> {
+    let partitions = std::cmp::max(partitions, 1);
+    let mut ranges = Vec::new();
+    let mut start = 0;
+    for partition in 1..partitions {
+        let mut end = std::cmp::max(values.len() * partition / partitions, start);
+        // older versions of the last key stay in its partition
+        while end > 0 && end < values.len() && values[end].key == values[end - 1].key {
+            end += 1;
+        }
+        if end > start {
+            ranges.push(start..end);
+            start = end;
+        }
+    }
+    if start < values.len() || ranges.is_empty() {
+        ranges.push(start..values.len());
+    }
+    ranges
+}
+
+/// Writes values to an SSTable as they are supplied instead of collecting
+/// them first. Blocks are written to storage as soon as they are full so only
+/// a single block is held in memory. The resulting table is identical to one
+/// written with `write_values_at_path` for the same values.
+pub struct SSTableWriter {
+    options: DharmaOpts,
+    file: Box<dyn StorageFile>,
+    builder: BlockBuilder,
+    // number of blocks written to the table
+    blocks_written: usize,
+    // value log receiving large values when key value separation is enabled
+    value_log: Option<ValueLog>,
+    // hash of the blocks written so far, see `seal_table_checksum`
+    checksum: Xxh64,
+    // summary of the values appended so far recorded in the footer
+    summary: TableSummary,
+}
+
+impl SSTableWriter {
+    /// Create an empty SSTable at the specified path to stream values into.
+    ///
+    /// # Arguments
+    /// * _option_  - Configurations options specified as `DharmaOpts`
+    /// * _path_ - The path at which to write the SSTable.
+    ///
+    /// # Returns
+    /// A `Result` which is
+    /// - `Ok`: - The writer for the SSTable.
+    /// - `Err`: - Error type as specified by `Errors` module
+    pub fn create(options: &DharmaOpts, path: &PathBuf) -> Result<SSTableWriter> {
+        let file = options
+            .backend
+            .create(path)
+            .map_err(|_| Errors::SSTABLE_CREATION_FAILED)?;
+        let value_log = match options.value_separation_min_bytes {
+            Some(min_bytes) => Some(ValueLog::open(options, min_bytes)?),
+            None => None,
+        };
+        Ok(SSTableWriter {
+            options: options.clone(),
+            file,
+            builder: BlockBuilder::new(options),
+            blocks_written: 0,
+            value_log,
+            checksum: new_table_hasher(),
+            summary: TableSummary::default(),
+        })
+    }
+
+    /// Append the value to the SSTable. Values must be appended in sorted order.
+    pub fn append<K: ResourceKey, V: ResourceValue>(&mut self, value: &Value<K, V>) -> Result<()> {
+        self.summary.add(self.options.encoding, value);
+        let mut blocks = Vec::new();
+        match self.value_log.as_mut() {
+            Some(value_log) => {
+                let separated = value_log.separate(value)?;
+                self.builder.add(&self.options, &separated, &mut blocks)?;
+            }
+            None => self.builder.add(&self.options, value, &mut blocks)?,
+        }
+        self.write_blocks(blocks)
+    }
+
+    /// Get the number of blocks written to the SSTable so far. Appended values
+    /// are buffered until their block is full.
+    pub fn blocks_written(&self) -> usize {
+        self.blocks_written
+    }
+
+    /// Write the last partially filled block and sync the SSTable to storage.
+    ///
+    /// # Returns
+    /// A `Result` which is
+    /// - `Ok`: - The number of blocks in the SSTable.
+    /// - `Err`: - Error type as specified by `Errors` module
+    pub fn finish(mut self) -> Result<usize> {
+        let mut blocks = Vec::new();
+        let builder = std::mem::replace(&mut self.builder, BlockBuilder::new(&self.options));
+        builder.finish(&mut blocks);
+        let checksum = std::mem::replace(&mut self.checksum, new_table_hasher());
+        self.blocks_written += write_blocks_with_footer(
+            &self.options,
+            self.file.as_mut(),
+            &blocks,
+            record_codec_id(&self.options),
+            &self.summary,
+            checksum,
+            // the size of streamed tables isn't known up front
+            false,
+        )?;
+        // values must be durable before the pointers to them
+        if let Some(value_log) = self.value_log.as_mut() {
+            value_log.sync()?;
+        }
+        self.file
+            .sync()
+            .map_err(|_| Errors::SSTABLE_CREATION_FAILED)?;
+        Ok(self.blocks_written)
+    }
+
+    fn write_blocks(&mut self, blocks: Vec<Block>) -> Result<()> {
+        for block in blocks {
+            let write_result = write_hashed_block(
+                &self.options,
+                self.file.as_mut(),
+                &block,
+                &mut self.checksum,
+            );
             if write_result.is_err() {
-                log::error!(
-                    "Failed to write block from chunk {0} to disk",
-                    block_counter
-                );
+                self.options.log_error(&format!(
+                    "Failed to write block {0} to disk",
+                    self.blocks_written
+                ));
                 return Err(Errors::SSTABLE_CREATION_FAILED);
             }
+            self.blocks_written += 1;
         }
-    } else {
-        log::error!("Failed to create SSTable from chunk from values");
+        Ok(())
+    }
+}
+
+/// Write the block to the SSTable adding its bytes to the checksum of the table.
+fn write_hashed_block(
+    options: &DharmaOpts,
+    file: &mut dyn StorageFile,
+    block: &Block,
+    checksum: &mut Xxh64,
+) -> Result<()> {
+    let mut data: Vec<u8> = Vec::with_capacity(options.block_size_in_bytes);
+    write_block_to_disk(options, &mut data, block)?;
+    checksum.update(&data);
+    file.write(&data)
+        .map_err(|_| Errors::SSTABLE_CREATION_FAILED)
+}
+
+/// Write the last blocks of an SSTable followed by the footer recording the summary of
+/// the records, the codec, the checksum, the format version and the encoding of the
+/// table. The footer is stored in
+/// the padding of the last block and in a block of its own if the last block is full.
+/// When `preallocate` is set the empty file is first resized to the final size of the
+/// table and blocks are written at their offsets. Files that can't be resized are
+/// written by appending blocks instead.
+///
+/// # Returns
+/// A `Result` which is
+/// - `Ok`: - The number of blocks written.
+/// - `Err`: - Error type as specified by `Errors` module
+fn write_blocks_with_footer(
+    options: &DharmaOpts,
+    file: &mut dyn StorageFile,
+    blocks: &Vec<Block>,
+    codec_id: u8,
+    summary: &TableSummary,
+    mut checksum: Xxh64,
+    preallocate: bool,
+) -> Result<usize> {
+    let footer = table_footer(options, codec_id, summary);
+    // the last blocks are buffered so that the checksum covering them can be
+    // stored in their footer
+    let mut tail: Vec<u8> = Vec::new();
+    let (full_blocks, footer_written) = match blocks.split_last() {
+        Some((last_block, full_blocks)) => (
+            full_blocks,
+            write_block_with_footer(options, &mut tail, last_block, &footer)?,
+        ),
+        None => (&blocks[..], false),
+    };
+    let mut blocks_written = blocks.len();
+    if !footer_written {
+        write_block_with_footer(options, &mut tail, &Block::new(), &footer)?;
+        blocks_written += 1;
+    }
+    let table_size_in_bytes = full_blocks.len() * options.block_size_in_bytes + tail.len();
+    let mut positioned_file = PositionedFile { file, offset: None };
+    if preallocate
+        && positioned_file
+            .file
+            .set_len(table_size_in_bytes as u64)
+            .is_ok()
+    {
+        positioned_file.offset = Some(0);
+    }
+    for (block_counter, block) in full_blocks.iter().enumerate() {
+        if write_hashed_block(options, &mut positioned_file, block, &mut checksum).is_err() {
+            options.log_error(&format!(
+                "Failed to write block from chunk {0} to disk",
+                block_counter
+            ));
+            return Err(Errors::SSTABLE_CREATION_FAILED);
+        }
+    }
+    seal_table_checksum(checksum, &mut tail);
+    if positioned_file.write(&tail).is_err() {
+        options.log_error("Failed to write SSTable footer to disk");
         return Err(Errors::SSTABLE_CREATION_FAILED);
     }
-    Ok(())
+    Ok(blocks_written)
+}
+
+/// Writes to the wrapped file at increasing offsets once the file has been resized
+/// to its final size and appends to the file otherwise.
+struct PositionedFile<'a> {
+    file: &'a mut dyn StorageFile,
+    // offset of the next write when the file was preallocated
+    offset: Option<u64>,
+}
+
+impl<'a> StorageFile for PositionedFile<'a> {
+    fn read_at(&mut self, buffer: &mut [u8], offset: u64) -> std::io::Result<usize> {
+        self.file.read_at(buffer, offset)
+    }
+
+    fn write(&mut self, data: &[u8]) -> std::io::Result<()> {
+        match self.offset {
+            Some(offset) => {
+                self.file.write_at(data, offset)?;
+                self.offset = Some(offset + data.len() as u64);
+                Ok(())
+            }
+            None => self.file.write(data),
+        }
+    }
+
+    fn size(&self) -> std::io::Result<u64> {
+        self.file.size()
+    }
+
+    fn sync(&mut self) -> std::io::Result<()> {
+        self.file.sync()
+    }
+}
+
+/// Get the footer stored at the end of SSTables written with the codec. The
+/// checksum is left zeroed, see `seal_table_checksum`. The footer must fit in the
+/// padding of an empty block so the key range, and then the whole summary, are
+/// left out for blocks that are too small to hold them.
+fn table_footer(options: &DharmaOpts, codec_id: u8, summary: &TableSummary) -> Vec<u8> {
+    let mut trailer = to_codec_footer(codec_id);
+    trailer.extend(to_checksum_footer(0));
+    trailer.extend(to_table_version_footer(FORMAT_VERSION));
+    trailer.extend(options.encoding.to_footer());
+    let room = options
+        .block_size_in_bytes
+        .saturating_sub(Record::RECORD_BASE_SIZE_IN_BYTES);
+    for with_keys in [true, false] {
+        let mut footer = to_summary_footer(options.block_size_in_bytes, summary, with_keys);
+        if footer.len() + trailer.len() <= room {
+            footer.extend(trailer);
+            return footer;
+        }
+    }
+    trailer
+}
+
+fn to_values<K: ResourceKey, V: ResourceValue>(tuples: &Vec<(K, V)>) -> Vec<Value<K, V>> {
+    tuples
+        .iter()
+        .map(|tup| {
+            return Value::new(tup.0.clone(), tup.1.clone());
+        })
+        .collect()
+}
+
+/// Read the size of the record starting at the offset of the block.
+/// Records never extend past the end of the block they are written to so a record
+/// that does was read with a different block size than the table was written with.
+fn record_size_in_block(buffer: &[u8], offset: usize) -> Result<usize> {
+    if offset + Record::RECORD_BASE_SIZE_IN_BYTES > buffer.len() {
+        return Err(Errors::SSTABLE_BLOCK_SIZE_MISMATCH);
+    }
+    let upper_size_byte = buffer[offset + 1] as u16;
+    let lower_size_byte = buffer[offset + 2] as u16;
+    let size = (upper_size_byte << 8 | lower_size_byte) as usize;
+    if offset + Record::RECORD_BASE_SIZE_IN_BYTES + size > buffer.len() {
+        return Err(Errors::SSTABLE_BLOCK_SIZE_MISMATCH);
+    }
+    Ok(size)
 }
 
 /// Read the SSTable at the specified path and return the data persisted in it
@@ -117,35 +688,90 @@ pub fn write_sstable_at_path<K: ResourceKey, V: ResourceValue>(
 /// # Returns
 /// A `Result` that is
 ///  - `Ok`: The list of `Value<K, V>` persisted to the SSTable
-///  - `Err`: Error type as specified by `Errors` module
-pub fn read_sstable<K: DeserializeOwned, V: DeserializeOwned>(
+///  - `Err`: Error type as specified by `Errors` module. Tables that can't be parsed
+///    into whole records with the configured block size fail with
+///    `SSTABLE_BLOCK_SIZE_MISMATCH`.
+pub fn read_sstable<K: Serialize + DeserializeOwned, V: Serialize + DeserializeOwned>(
     options: &DharmaOpts,
     path: &Path,
 ) -> Result<Vec<Value<K, V>>> {
+    let mut reader = options
+        .backend
+        .open(path)
+        .map_err(|_| Errors::SSTABLE_READ_FAILED)?;
+    let total_size_in_bytes = reader.size().map_err(|_| Errors::SSTABLE_READ_FAILED)?;
+    let encoding =
+        Encoding::from_footer(reader.as_mut(), total_size_in_bytes as usize).unwrap_or_default();
+    read_sstable_with_codec(options, &BincodeCodec::new(encoding), path)
+}
+
+/// Read the SSTable at the specified path whose values were serialized with the
+/// supplied codec and return the data persisted in it as a `Vec` of `Value<K, V>`.
+///
+/// # Arguments
+/// * _option_ - Configuration options specified as `DharmaOpts`
+/// * _codec_ - The codec the values were serialized with.
+/// * _path_ - File System Path to SSTable
+///
+/// # Returns
+/// A `Result` that is
+///  - `Ok`: The list of `Value<K, V>` persisted to the SSTable
+///  - `Err`: Error type as specified by `Errors` module. Reading fails if the
+///    table was written with a different codec.
+pub fn read_sstable_with_codec<K, V, C>(
+    options: &DharmaOpts,
+    codec: &C,
+    path: &Path,
+) -> Result<Vec<Value<K, V>>>
+where
+    K: Serialize + DeserializeOwned,
+    V: Serialize + DeserializeOwned,
+    C: RecordCodec,
+{
     let mut output: Vec<Value<K, V>> = Vec::new();
-    let file_result = File::open(path);
+    let file_result = options.backend.open(path);
     if file_result.is_ok() {
-        let file = file_result.unwrap();
-        let metadata = file.metadata().unwrap();
-        let total_size_in_bytes = metadata.len();
-        // read number of blocks from metadata embedded in SStable rather than relying
-        // on options. options might change which might cause read error due to mismatch
-        // between written and supplied block size
-        let block_count =
-            (total_size_in_bytes as f64 / options.block_size_in_bytes as f64).ceil() as u64;
+        let mut reader = file_result.unwrap();
+        let total_size_in_bytes = reader.size().unwrap();
+        let codec_id = codec_from_footer(reader.as_mut(), total_size_in_bytes as usize);
+        if codec_id != codec.id() {
+            options.log_error(&format!(
+                "SSTable was written with codec {0} but read with codec {1}",
+                codec_id,
+                codec.id()
+            ));
+            return Err(Errors::SSTABLE_READ_FAILED);
+        }
+        // tables written before the format version was recorded have no footer
+        let format_version =
+            table_version_from_footer(reader.as_mut(), total_size_in_bytes as usize)
+                .unwrap_or(MIN_TABLE_FORMAT_VERSION);
+        // tables are always written as whole blocks with the footer stored in the
+        // padding of the last block so a table whose size isn't a multiple of the
+        // block size was written with a different block size
+        let block_size = options.block_size_in_bytes as usize;
+        if total_size_in_bytes % block_size as u64 != 0 {
+            options.log_error(&format!(
+                "SSTable of {0} bytes is not made up of blocks of {1} bytes",
+                total_size_in_bytes, block_size
+            ));
+            return Err(Errors::SSTABLE_BLOCK_SIZE_MISMATCH);
+        }
+        let block_count = total_size_in_bytes / block_size as u64;
         let mut i = 0;
-        let mut reader = BufOffsetReader::new(file);
         // buffer to accumulate data from records split across multiple blocks
         let mut record_byte_buffer = Vec::new();
         while i < block_count {
-            let mut buffer = vec![0u8; options.block_size_in_bytes as usize];
+            let mut buffer = vec![0u8; block_size];
             // read blocksize number of bytes
-            // TODO: handle read error
-            reader.read_at(&mut buffer, i * options.block_size_in_bytes as u64);
+            reader
+                .read_at(&mut buffer, i * block_size as u64)
+                .map_err(|_| Errors::SSTABLE_READ_FAILED)?;
             // unpack bytes array into records
             let mut r = 0;
             while r < buffer.len() {
-                let record_type = buffer[r];
+                let record_type = buffer[r] & !Record::RECORD_COMPRESSED_FLAG;
+                let compressed = is_compressed(buffer[r]);
                 match record_type {
                     // padding record
                     0 => {
@@ -153,9 +779,7 @@ pub fn read_sstable<K: DeserializeOwned, V: DeserializeOwned>(
                         if remaining <= Record::RECORD_BASE_SIZE_IN_BYTES {
                             r += remaining;
                         } else {
-                            let upper_size_byte = buffer[r + 1] as u16;
-                            let lower_size_byte = buffer[r + 2] as u16;
-                            let size = (upper_size_byte << 8 | lower_size_byte) as usize;
+                            let size = record_size_in_block(&buffer, r)?;
                             // skip record type byte(1) and size bytes(2)
                             r += 3;
                             // skip bytes specified by padding
@@ -164,53 +788,104 @@ pub fn read_sstable<K: DeserializeOwned, V: DeserializeOwned>(
                     }
                     // complete record
                     1 => {
-                        // read size
-                        let upper_size_byte = buffer[r + 1] as u16;
-                        let lower_size_byte = buffer[r + 2] as u16;
-                        let size = (upper_size_byte << 8 | lower_size_byte) as usize;
+                        // a complete record can't follow the start of a split record
+                        if !record_byte_buffer.is_empty() {
+                            return Err(Errors::SSTABLE_BLOCK_SIZE_MISMATCH);
+                        }
+                        let size = record_size_in_block(&buffer, r)?;
                         // skip record type byte(1) and size bytes(2)
                         r += 3;
                         // read size bytes
-                        let decoded: Value<K, V> =
-                            bincode::deserialize(&buffer[r..r + size]).unwrap();
+                        let mut data = buffer[r..r + size].to_vec();
+                        if compressed {
+                            data = decompress(data);
+                        }
+                        if let Some(transform) = &options.value_transform {
+                            data = transform.on_read(data)?;
+                        }
+                        let decoded = decode_record(codec, &data, format_version)?;
                         output.push(decoded);
                         r += size;
                     }
                     // start and middle records
                     2 | 3 => {
-                        let upper_size_byte = buffer[r + 1] as u16;
-                        let lower_size_byte = buffer[r + 2] as u16;
-                        let size = (upper_size_byte << 8 | lower_size_byte) as usize;
+                        // a split record has to start before its middle parts
+                        if (record_type == 2) != record_byte_buffer.is_empty() {
+                            return Err(Errors::SSTABLE_BLOCK_SIZE_MISMATCH);
+                        }
+                        let size = record_size_in_block(&buffer, r)?;
                         // skip record type byte(1) and size bytes(2)
                         r += 3;
-                        for i in 0..size {
-                            record_byte_buffer.push(buffer[r + i]);
-                        }
+                        record_byte_buffer.extend_from_slice(&buffer[r..r + size]);
                         r += size;
                     }
                     // end
                     4 => {
-                        let upper_size_byte = buffer[r + 1] as u16;
-                        let lower_size_byte = buffer[r + 2] as u16;
-                        let size = (upper_size_byte << 8 | lower_size_byte) as usize;
+                        if record_byte_buffer.is_empty() {
+                            return Err(Errors::SSTABLE_BLOCK_SIZE_MISMATCH);
+                        }
+                        let size = record_size_in_block(&buffer, r)?;
                         // skip record type byte(1) and size bytes(2)
                         r += 3;
-                        for i in 0..size {
-                            record_byte_buffer.push(buffer[r + i]);
+                        record_byte_buffer.extend_from_slice(&buffer[r..r + size]);
+                        if compressed {
+                            record_byte_buffer = decompress(record_byte_buffer);
                         }
-                        let decoded: Value<K, V> =
-                            bincode::deserialize(record_byte_buffer.as_slice()).unwrap();
+                        if let Some(transform) = &options.value_transform {
+                            record_byte_buffer = transform.on_read(record_byte_buffer)?;
+                        }
+                        let decoded =
+                            decode_record(codec, record_byte_buffer.as_slice(), format_version)?;
                         output.push(decoded);
                         r += size;
                         // last chunk in record processed so create a new buffer
                         record_byte_buffer = Vec::new();
                     }
-                    _ => {}
+                    // block header
+                    5 => {
+                        let size = record_size_in_block(&buffer, r)?;
+                        // skip record type byte(1) and size bytes(2)
+                        r += 3;
+                        r += size;
+                    }
+                    // unknown record types and flags can't be skipped safely
+                    _ => {
+                        options
+                            .log_error(&format!("Unknown record type {0} in SSTable", buffer[r]));
+                        return Err(Errors::SSTABLE_READ_FAILED);
+                    }
                 }
             }
             i += 1;
         }
+        // a record split across blocks that never ends was cut at the wrong offsets
+        if !record_byte_buffer.is_empty() {
+            return Err(Errors::SSTABLE_BLOCK_SIZE_MISMATCH);
+        }
         return Ok(output);
     }
     Err(Errors::SSTABLE_READ_FAILED)
 }
+
+/// Deserialize a record of a table of the supplied format version with the codec.
+/// Records of tables of format version 1 were serialized with `bincode` before
+/// sequence numbers were stored so they sort as the oldest values.
+fn decode_record<K, V, C>(codec: &C, data: &[u8], format_version: u32) -> Result<Value<K, V>>
+where
+    K: DeserializeOwned,
+    V: DeserializeOwned,
+    C: RecordCodec,
+{
+    if format_version > MIN_TABLE_FORMAT_VERSION {
+        return codec.decode(data);
+    }
+    let (key, value) = Encoding::Fixint
+        .deserialize::<(K, V)>(data)
+        .map_err(|_| Errors::RECORD_DESERIALIZATION_FAILED)?;
+    Ok(Value {
+        key,
+        value,
+        seq: 0,
+        pointer: None,
+    })
+}
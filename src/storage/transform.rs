@@ -0,0 +1,31 @@
+use crate::result::Result;
+
+/// Transform applied to the serialized records holding values before they are stored
+/// in SSTables and after they are read back, see `DharmaOpts::value_transform`.
+/// Implement this trait to encrypt values at rest or to wrap them in an envelope.
+/// Records holding values stored inline are transformed along with their key and
+/// sequence number while values stored in the value log are transformed on their own.
+/// Range tombstones are never transformed.
+pub trait ValueTransform: Send + Sync {
+    /// Transform the serialized record before it is written.
+    ///
+    /// # Arguments
+    /// * _bytes_ - The serialized record.
+    ///
+    /// # Returns
+    /// Result that resolves:
+    ///  - _Ok_ - The bytes to store.
+    ///  - _Err_ - Error specifying why the record couldn't be transformed.
+    fn on_write(&self, bytes: Vec<u8>) -> Result<Vec<u8>>;
+
+    /// Restore the serialized record from the bytes produced by `on_write`.
+    ///
+    /// # Arguments
+    /// * _bytes_ - The stored bytes.
+    ///
+    /// # Returns
+    /// Result that resolves:
+    ///  - _Ok_ - The serialized record.
+    ///  - _Err_ - Error specifying why the bytes couldn't be restored.
+    fn on_read(&self, bytes: Vec<u8>) -> Result<Vec<u8>>;
+}
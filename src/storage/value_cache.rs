@@ -0,0 +1,148 @@
+use std::collections::BTreeMap;
+
+/// Counters describing the effectiveness of the value cache.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ValueCacheStats {
+    /// Number of reads served from the cache.
+    pub hits: u64,
+    /// Number of reads that had to go to the SSTables.
+    pub misses: u64,
+    /// Number of values currently held in the cache.
+    pub entries: usize,
+}
+
+impl ValueCacheStats {
+    /// Get the fraction of reads served from the cache.
+    ///
+    /// # Returns
+    /// Optional that contains the ratio unless no values were read.
+    pub fn hit_ratio(&self) -> Option<f64> {
+        let reads = self.hits + self.misses;
+        if reads == 0 {
+            return None;
+        }
+        Some(self.hits as f64 / reads as f64)
+    }
+}
+
+/// Cache of decoded values read from SSTables keyed by the key they are associated with.
+/// Unlike the block cache, hits skip parsing blocks and deserializing records entirely.
+/// When the cache is full the least recently used value is evicted.
+pub struct ValueCache<K, V> {
+    capacity: usize,
+    // cached values along with the tick they were last used at
+    values: BTreeMap<K, (V, u64)>,
+    // keys by the tick they were last used at, least recently used first
+    usage: BTreeMap<u64, K>,
+    // incremented every time a value is used so ticks are unique
+    tick: u64,
+    hits: u64,
+    misses: u64,
+}
+
+impl<K, V> ValueCache<K, V>
+where
+    K: Clone + Ord,
+    V: Clone,
+{
+    /// Create a value cache that holds at most `capacity` values.
+    /// A cache with zero capacity never stores values.
+    pub fn new(capacity: usize) -> ValueCache<K, V> {
+        ValueCache {
+            capacity,
+            values: BTreeMap::new(),
+            usage: BTreeMap::new(),
+            tick: 0,
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    /// Check whether the cache stores values.
+    pub fn is_enabled(&self) -> bool {
+        self.capacity > 0
+    }
+
+    /// Get the value associated with the key if it is cached.
+    pub fn get(&mut self, key: &K) -> Option<V> {
+        let tick = self.next_tick();
+        match self.values.get_mut(key) {
+            Some((value, last_used)) => {
+                self.hits += 1;
+                self.usage.remove(last_used);
+                *last_used = tick;
+                self.usage.insert(tick, key.clone());
+                Some(value.clone())
+            }
+            None => {
+                self.misses += 1;
+                None
+            }
+        }
+    }
+
+    /// Add the value associated with the key to the cache.
+    pub fn insert(&mut self, key: K, value: V) {
+        if self.capacity == 0 {
+            return;
+        }
+        let tick = self.next_tick();
+        match self.values.insert(key.clone(), (value, tick)) {
+            Some((_, last_used)) => {
+                self.usage.remove(&last_used);
+            }
+            None => {
+                while self.values.len() > self.capacity {
+                    let oldest = self.usage.keys().next().copied();
+                    match oldest.and_then(|oldest| self.usage.remove(&oldest)) {
+                        Some(evicted) => self.values.remove(&evicted),
+                        None => break,
+                    };
+                }
+            }
+        }
+        self.usage.insert(tick, key);
+    }
+
+    /// Remove the value associated with the key.
+    pub fn invalidate(&mut self, key: &K) {
+        if let Some((_, last_used)) = self.values.remove(key) {
+            self.usage.remove(&last_used);
+        }
+    }
+
+    /// Remove the values whose keys match the predicate.
+    pub fn invalidate_where<F>(&mut self, predicate: F)
+    where
+        F: Fn(&K) -> bool,
+    {
+        let usage = &mut self.usage;
+        self.values.retain(|key, (_, last_used)| {
+            let invalidated = predicate(key);
+            if invalidated {
+                usage.remove(last_used);
+            }
+            !invalidated
+        });
+    }
+
+    /// Remove all cached values.
+    pub fn clear(&mut self) {
+        self.values.clear();
+        self.usage.clear();
+    }
+
+    /// Get the hit and miss counters of the cache.
+    pub fn stats(&self) -> ValueCacheStats {
+        ValueCacheStats {
+            hits: self.hits,
+            misses: self.misses,
+            entries: self.values.len(),
+        }
+    }
+
+    fn next_tick(&mut self) -> u64 {
+        self.tick += 1;
+        self.tick
+    }
+}
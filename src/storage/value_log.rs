@@ -0,0 +1,170 @@
+use crate::options::DharmaOpts;
+use crate::result::{Errors, Result};
+use crate::storage::backend::StorageFile;
+use crate::storage::block::Value;
+use crate::storage::encoding::Encoding;
+use crate::storage::transform::ValueTransform;
+use crate::traits::{ResourceKey, ResourceValue};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+const VALUE_LOG_NAME: &str = "values.log";
+
+/// Marks the header holding the encoding of the values in the value log.
+const VALUE_LOG_HEADER_MAGIC: [u8; 4] = *b"DVLG";
+
+/// Size in bytes of the header at the start of the value log. The header is made
+/// up of `VALUE_LOG_HEADER_MAGIC` followed by a byte specifying the `Encoding`.
+pub const VALUE_LOG_HEADER_SIZE_IN_BYTES: usize = 5;
+
+/// Location of a value stored in the value log.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq)]
+pub struct ValuePointer {
+    /// Offset of the serialized value within the value log.
+    pub offset: u64,
+    /// Length of the serialized value in bytes.
+    pub length: u32,
+}
+
+/// Append only log holding values that are stored separately from their keys.
+/// SSTables only hold pointers into the value log for these values so that
+/// compaction rewrites keys and pointers without copying the values.
+/// Values are never removed from the log. The log records the encoding of its
+/// values in a header so that they are decoded with it after the encoding of the
+/// database changed, values are always appended in the recorded encoding.
+pub struct ValueLog {
+    file: Box<dyn StorageFile>,
+    // serialized values smaller than this are stored inline in SSTables
+    min_bytes: usize,
+    // encoding of the values appended to the log
+    encoding: Encoding,
+    // transform applied to the values appended to the log
+    transform: Option<Arc<dyn ValueTransform>>,
+}
+
+impl ValueLog {
+    /// Get the path of the value log for the supplied database config.
+    pub fn path(options: &DharmaOpts) -> PathBuf {
+        PathBuf::from(format!("{0}/{1}", options.path, VALUE_LOG_NAME))
+    }
+
+    /// Open the value log of the database creating it if it doesn't exist.
+    ///
+    /// # Arguments
+    ///  - _options_ - The database config.
+    ///  - _min_bytes_ - Minimum serialized size of values moved to the log.
+    ///
+    /// # Returns
+    /// Result that resolves:
+    ///  - _Ok_ - The value log.
+    ///  - _Err_ - Error that occurred while opening the value log.
+    pub fn open(options: &DharmaOpts, min_bytes: usize) -> Result<ValueLog> {
+        let path = ValueLog::path(options);
+        let file_result = if options.backend.exists(&path) {
+            options.backend.open(&path)
+        } else {
+            options.backend.create(&path)
+        };
+        let mut file = file_result.map_err(|_| Errors::VALUE_LOG_WRITE_FAILED)?;
+        let size = file.size().map_err(|_| Errors::VALUE_LOG_WRITE_FAILED)?;
+        let encoding = if size == 0 {
+            let mut header = VALUE_LOG_HEADER_MAGIC.to_vec();
+            header.push(options.encoding.id());
+            file.write(&header)
+                .map_err(|_| Errors::VALUE_LOG_WRITE_FAILED)?;
+            options.encoding
+        } else {
+            read_encoding(file.as_mut()).ok_or(Errors::VALUE_LOG_WRITE_FAILED)?
+        };
+        Ok(ValueLog {
+            file,
+            min_bytes,
+            encoding,
+            transform: options.value_transform.clone(),
+        })
+    }
+
+    /// Move the value into the value log if it is large enough to be stored
+    /// separately. Values that already point into the log are not written again.
+    ///
+    /// # Returns
+    /// Result that resolves:
+    ///  - _Ok_ - The value with its pointer set if it is stored in the log.
+    ///  - _Err_ - Error that occurred while appending the value.
+    pub fn separate<K: ResourceKey, V: ResourceValue>(
+        &mut self,
+        value: &Value<K, V>,
+    ) -> Result<Value<K, V>> {
+        let mut output = value.clone();
+        if output.pointer.is_some() {
+            return Ok(output);
+        }
+        let encoded = self
+            .encoding
+            .serialize(&value.value)
+            .map_err(|_| Errors::RECORD_SERIALIZATION_FAILED)?;
+        if encoded.len() >= self.min_bytes {
+            let encoded = match &self.transform {
+                Some(transform) => transform.on_write(encoded)?,
+                None => encoded,
+            };
+            output.pointer = Some(self.append(&encoded)?);
+        }
+        Ok(output)
+    }
+
+    /// Flush values appended to the log to durable storage.
+    pub fn sync(&mut self) -> Result<()> {
+        self.file.sync().map_err(|_| Errors::VALUE_LOG_WRITE_FAILED)
+    }
+
+    fn append(&mut self, data: &[u8]) -> Result<ValuePointer> {
+        let offset = self
+            .file
+            .size()
+            .map_err(|_| Errors::VALUE_LOG_WRITE_FAILED)?;
+        self.file
+            .write(data)
+            .map_err(|_| Errors::VALUE_LOG_WRITE_FAILED)?;
+        Ok(ValuePointer {
+            offset,
+            length: data.len() as u32,
+        })
+    }
+}
+
+/// Read the encoding of the values in the value log from its header.
+/// Value logs written before the encoding was recorded have no header and hold
+/// values in the default encoding.
+///
+/// # Returns
+/// Optional that contains the encoding unless the header couldn't be read or
+/// records an unknown encoding.
+pub fn read_encoding(file: &mut dyn StorageFile) -> Option<Encoding> {
+    let size = file.size().ok()? as usize;
+    if size < VALUE_LOG_HEADER_SIZE_IN_BYTES {
+        return Some(Encoding::default());
+    }
+    let mut header = [0u8; VALUE_LOG_HEADER_SIZE_IN_BYTES];
+    match file.read_at(&mut header, 0) {
+        Ok(read) if read == header.len() => {}
+        _ => return None,
+    }
+    if header[..4] != VALUE_LOG_HEADER_MAGIC {
+        return Some(Encoding::default());
+    }
+    Encoding::from_id(header[4])
+}
+
+/// Read the serialized value referenced by the pointer from the value log.
+///
+/// # Returns
+/// Optional that contains the value bytes if they could be read.
+pub fn read_value(file: &mut dyn StorageFile, pointer: &ValuePointer) -> Option<Vec<u8>> {
+    let mut data = vec![0u8; pointer.length as usize];
+    match file.read_at(&mut data, pointer.offset) {
+        Ok(read) if read == data.len() => Some(data),
+        _ => None,
+    }
+}
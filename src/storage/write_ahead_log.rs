@@ -1,61 +1,541 @@
 use crate::result::{Errors, Result};
 use crate::options::DharmaOpts;
-use crate::storage::block::{create_blocks, write_block_to_disk, Block, Value};
+use crate::storage::backend::StorageFile;
+use crate::storage::block::{
+    create_blocks_with_tombstones, is_compressed, write_block_to_disk, Block, BlockBuilder,
+    RangeTombstone, Record, Value,
+};
 use crate::storage::sorted_string_table_reader::SSTableReader;
+use crate::storage::encoding::Encoding;
+use crate::storage::manifest::FORMAT_VERSION;
 use crate::traits::{ResourceKey, ResourceValue};
-use std::fs::{remove_file, File};
+use lz4_flex::{compress_prepend_size, decompress_size_prepended};
+use std::collections::BTreeMap;
 use std::path::{Path, PathBuf};
+use std::sync::{Condvar, Mutex};
+use xxhash_rust::xxh64::xxh64;
 
 const WRITE_AHEAD_LOG_NAME: &str = "wal.log";
 
+/// Name the log is renamed to while it is being recovered, see `begin_recovery`.
+const RECOVERING_LOG_NAME: &str = "wal.log.recovering";
+
+/// Operation types recorded by framed records, see `WalFormat::Framed`.
+const WAL_OP_PUT: u8 = 1;
+const WAL_OP_DELETE: u8 = 2;
+const WAL_OP_DELETE_RANGE: u8 = 3;
+
+/// Size in bytes of the operation type and the payload size preceding the payload
+/// of a framed record.
+const WAL_RECORD_HEADER_SIZE_IN_BYTES: usize = 5;
+
+/// Size in bytes of the checksum following the payload of a framed record.
+const WAL_RECORD_CHECKSUM_SIZE_IN_BYTES: usize = 8;
+
+/// Magic bytes at the start of the log. The magic is followed by a byte identifying
+/// the format of the records, see `WalFormat`, a byte identifying the `Encoding`
+/// the records were serialized with and the size of the blocks of the log as a
+/// little endian `u32`.
+const WAL_FORMAT_MAGIC: &[u8; 4] = b"DWAL";
+
+/// Format byte following the magic of logs in the `WalFormat::Blocks` format.
+const WAL_FORMAT_BLOCKS: u8 = 0;
+
+/// Format byte following the magic of logs in the `WalFormat::Framed` format.
+const WAL_FORMAT_FRAMED: u8 = 1;
+
+/// Size in bytes of the header at the start of the log, see `WAL_FORMAT_MAGIC`.
+const WAL_HEADER_SIZE_IN_BYTES: usize = 10;
+
+/// How records are laid out in the Write Ahead Log. Logs are read in the format,
+/// with the encoding and with the block size recorded in the header at their start,
+/// reading a log in a different format than the configured one fails with
+/// `WAL_FORMAT_MISMATCH`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WalFormat {
+    /// Records are packed into blocks like the records of SSTables. A single append
+    /// may fill several blocks and only whole blocks are read back. Logs written
+    /// before the header was recorded start with the first block.
+    Blocks,
+    /// Every append is written as one self delimiting record
+    ///
+    /// | op (1 byte) | size (4 bytes) | payload | checksum (8 bytes) |
+    ///
+    /// The op tells puts, deletes and range deletions apart and the checksum is the
+    /// little endian xxHash of the op, size and payload. A torn or corrupt record is
+    /// detected at the record itself and the records before it are recovered.
+    /// The most significant bit of the op is set when the payload is compressed,
+    /// see `DharmaOpts::wal_compression_min_bytes`.
+    Framed,
+}
+
+/// An operation recorded in the Write Ahead Log.
+pub enum WalOperation<K, V> {
+    /// The key was associated with the value.
+    Put(Value<K, V>),
+    /// The key was deleted by associating it with the nil value, see `Nil`.
+    Delete { key: K, seq: u64 },
+    /// The keys within the range were deleted.
+    DeleteRange(RangeTombstone<K>),
+}
+
+impl<K, V> WalOperation<K, V>
+where
+    K: ResourceKey,
+    V: ResourceValue,
+{
+    // blocks hold deletes as values associated with the nil value
+    fn from_value(value: Value<K, V>) -> WalOperation<K, V> {
+        if value.is_tombstone() {
+            return WalOperation::Delete {
+                key: value.key,
+                seq: value.seq,
+            };
+        }
+        WalOperation::Put(value)
+    }
+}
+
+/// Writes waiting to be committed to the log as part of a group commit.
+struct CommitQueue {
+    // encoded writes that will be committed in the next batch
+    pending: Vec<u8>,
+    // identifier of the batch that pending writes will be committed in
+    next_batch: u64,
+    // identifier of the last batch that was committed
+    committed_batch: u64,
+    // flag specifying whether a batch is being committed
+    committing: bool,
+    // whether a batch failed to commit after which the log can no longer be trusted
+    failed: bool,
+    // number of times the log was synced to disk
+    sync_count: u64,
+}
+
 pub struct WriteAheadLog {
     options: DharmaOpts,
-    writer: File,
+    // options records are packed into blocks with, see `wal_block_options`
+    block_options: DharmaOpts,
+    writer: Mutex<Box<dyn StorageFile>>,
+    queue: Mutex<CommitQueue>,
+    committed: Condvar,
 }
 
 impl WriteAheadLog {
     pub fn create(options: DharmaOpts) -> Result<WriteAheadLog> {
         let path = format!("{0}/{1}", options.path, WRITE_AHEAD_LOG_NAME);
         // check if WAL already exists
-        if !Path::new(&path).exists() {
-            let file_result = File::create(path);
+        if !options.backend.exists(Path::new(&path)) {
+            let file_result = options.backend.create(Path::new(&path));
             if file_result.is_ok() {
-                let writer: File = file_result.unwrap();
-                return Ok(WriteAheadLog {
-                    options: options.clone(),
-                    writer,
-                });
+                let mut writer = file_result.unwrap();
+                let header = log_header(&options);
+                if writer.write(&header).is_err() {
+                    return Err(Errors::WAL_LOG_CREATION_FAILED);
+                }
+                return Ok(WriteAheadLog::with_writer(options, writer));
             }
             return Err(Errors::WAL_LOG_CREATION_FAILED);
         }
         Err(Errors::DB_PATH_DIRTY)
     }
 
+    /// Replace the existing Write Ahead Log with a log of the supplied config holding
+    /// the supplied records and range tombstones. The new log is written next to the
+    /// log and renamed over it so the records are in one of the two logs at any point,
+    /// see `rewrite`.
+    ///
+    /// # Arguments
+    ///  - _options_ - The config of the new log.
+    ///  - _values_ - The records of the new log in the order they are replayed.
+    ///  - _tombstones_ - The range tombstones of the new log.
+    ///
+    /// # Returns
+    /// Result that resolves
+    ///  - _Ok_ - The new Write Ahead Log.
+    ///  - _Err_ - `WAL_WRITE_FAILED` if the new log couldn't be written, the
+    ///    existing log is left intact then.
+    pub fn replace<K: ResourceKey, V: ResourceValue>(
+        options: DharmaOpts,
+        values: &[Value<K, V>],
+        tombstones: &[RangeTombstone<K>],
+    ) -> Result<WriteAheadLog> {
+        // the writer is replaced by the handle of the new log once it is written
+        let placeholder: Box<dyn StorageFile> = Box::new(Vec::new());
+        let log = WriteAheadLog::with_writer(options, placeholder);
+        {
+            let mut writer = log.writer.lock().unwrap();
+            log.replace_entries(&mut writer, values, tombstones)?;
+        }
+        if log.options.sync_wal_on_reset {
+            log.options
+                .backend
+                .sync_dir(Path::new(&log.options.path))
+                .map_err(|_| Errors::WAL_WRITE_FAILED)?;
+        }
+        Ok(log)
+    }
+
+    fn with_writer(options: DharmaOpts, writer: Box<dyn StorageFile>) -> WriteAheadLog {
+        WriteAheadLog {
+            block_options: wal_block_options(&options),
+            options,
+            writer: Mutex::new(writer),
+            queue: Mutex::new(CommitQueue {
+                pending: Vec::new(),
+                next_batch: 1,
+                committed_batch: 0,
+                committing: false,
+                failed: false,
+                sync_count: 0,
+            }),
+            committed: Condvar::new(),
+        }
+    }
+
     /// Write the key and value to the Write Ahead Log.
+    /// When `group_commit` is enabled the write is synced to disk before returning.
+    /// Writers that arrive while a sync is in flight join the next batch which is
+    /// synced once on behalf of all writers in it.
+    ///
+    /// # Arguments
+    ///  - _key_: The resource key.
+    ///  - _value_: The resource value
+    ///
+    /// # Returns
+    /// Result that is:
+    ///  - _Ok_ - If the record was added to the log successfully.
+    ///  - _Err_ - The there was an error writing record to disk. Partial record may be written.
+    pub fn append<K: ResourceKey, V: ResourceValue>(&self, key: K, value: V) -> Result<()> {
+        self.append_versioned(key, value, 0)
+    }
+
+    /// Write the key and value along with the sequence number of the write
+    /// to the Write Ahead Log. See `append`.
     ///
     /// # Arguments
     ///  - _key_: The resource key.
     ///  - _value_: The resource value
+    ///  - _seq_: The sequence number of the write.
     ///
     /// # Returns
     /// Result that is:
     ///  - _Ok_ - If the record was added to the log successfully.
     ///  - _Err_ - The there was an error writing record to disk. Partial record may be written.
-    pub fn append<K: ResourceKey, V: ResourceValue>(&mut self, key: K, value: V) -> Result<()> {
-        let value = Value::new(key, value);
-        // break record into blocks
-        let mut blocks: Vec<Block> = Vec::new();
-        create_blocks(&self.options, &vec![value], &mut blocks);
+    pub fn append_versioned<K: ResourceKey, V: ResourceValue>(
+        &self,
+        key: K,
+        value: V,
+        seq: u64,
+    ) -> Result<()> {
+        self.append_values(&vec![Value::with_seq(key, value, seq)])
+    }
+
+    /// Write all key value pairs to the Write Ahead Log in a single pass.
+    /// The records are packed into blocks together so that bulk inserts need
+    /// fewer writes than appending records one at a time. Records are tagged with
+    /// consecutive sequence numbers in the order they are supplied.
+    ///
+    /// # Arguments
+    ///  - _records_: The key value pairs to write.
+    ///  - _first_seq_: The sequence number of the first record.
+    ///
+    /// # Returns
+    /// Result that is:
+    ///  - _Ok_ - If all records were added to the log successfully.
+    ///  - _Err_ - The there was an error writing records to disk. Partial records may be written.
+    pub fn append_batch<K: ResourceKey, V: ResourceValue>(
+        &self,
+        records: &[(K, V)],
+        first_seq: u64,
+    ) -> Result<()> {
+        let values = records
+            .iter()
+            .zip(first_seq..)
+            .map(|((key, value), seq)| Value::with_seq(key.clone(), value.clone(), seq))
+            .collect();
+        self.append_values(&values)
+    }
+
+    /// Write the range tombstone to the Write Ahead Log. See `append`.
+    ///
+    /// # Arguments
+    ///  - _tombstone_: The range tombstone recording a range deletion.
+    ///
+    /// # Returns
+    /// Result that is:
+    ///  - _Ok_ - If the tombstone was added to the log successfully.
+    ///  - _Err_ - The there was an error writing the tombstone to disk.
+    pub fn append_range_tombstone<K: ResourceKey>(
+        &self,
+        tombstone: &RangeTombstone<K>,
+    ) -> Result<()> {
+        let encoded = match self.options.wal_format {
+            WalFormat::Blocks => {
+                let mut blocks: Vec<Block> = Vec::new();
+                let mut builder = BlockBuilder::new(&self.block_options);
+                builder.add_range_tombstone(&self.block_options, tombstone, &mut blocks)?;
+                builder.finish(&mut blocks);
+                self.encode_blocks(blocks)?
+            }
+            WalFormat::Framed => self.encode_framed_tombstone(tombstone)?,
+        };
+        self.append_encoded(encoded)
+    }
+
+    fn append_values<K: ResourceKey, V: ResourceValue>(
+        &self,
+        values: &Vec<Value<K, V>>,
+    ) -> Result<()> {
+        let encoded = self.encode_entries(values, &[])?;
+        self.append_encoded(encoded)
+    }
+
+    fn append_encoded(&self, encoded: Vec<u8>) -> Result<()> {
+        if !self.options.group_commit {
+            let mut writer = self.writer.lock().unwrap();
+            return writer
+                .write(&encoded)
+                .map_err(|_| Errors::WAL_WRITE_FAILED);
+        }
+        self.group_commit(encoded)
+    }
+
+    // encode the values followed by the range tombstones in the format of the log
+    fn encode_entries<K: ResourceKey, V: ResourceValue>(
+        &self,
+        values: &[Value<K, V>],
+        tombstones: &[RangeTombstone<K>],
+    ) -> Result<Vec<u8>> {
+        if let WalFormat::Blocks = self.options.wal_format {
+            // break records into blocks
+            let mut blocks: Vec<Block> = Vec::new();
+            create_blocks_with_tombstones(&self.block_options, values, tombstones, &mut blocks)?;
+            return self.encode_blocks(blocks);
+        }
+        let encoding = self.options.encoding;
+        let mut encoded = Vec::new();
+        for value in values {
+            let record = if value.is_tombstone() {
+                let payload = encoding.serialize(&(&value.key, value.seq));
+                self.frame_record(WAL_OP_DELETE, payload)?
+            } else {
+                self.frame_record(WAL_OP_PUT, encoding.serialize(value))?
+            };
+            encoded.extend(record);
+        }
+        for tombstone in tombstones {
+            encoded.extend(self.encode_framed_tombstone(tombstone)?);
+        }
+        Ok(encoded)
+    }
+
+    fn encode_framed_tombstone<K: ResourceKey>(
+        &self,
+        tombstone: &RangeTombstone<K>,
+    ) -> Result<Vec<u8>> {
+        let payload = self.options.encoding.serialize(tombstone);
+        self.frame_record(WAL_OP_DELETE_RANGE, payload)
+    }
+
+    // frame the serialized payload of an operation as a record of `WalFormat::Framed`
+    fn frame_record(&self, op: u8, payload: bincode::Result<Vec<u8>>) -> Result<Vec<u8>> {
+        let mut payload = payload.map_err(|_| Errors::WAL_WRITE_FAILED)?;
+        let mut op = op;
+        if let Some(min_bytes) = self.block_options.value_compression_min_bytes {
+            if payload.len() >= min_bytes {
+                payload = compress_prepend_size(&payload);
+                op |= Record::RECORD_COMPRESSED_FLAG;
+            }
+        }
+        let mut record = Vec::with_capacity(
+            WAL_RECORD_HEADER_SIZE_IN_BYTES + payload.len() + WAL_RECORD_CHECKSUM_SIZE_IN_BYTES,
+        );
+        record.push(op);
+        record.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        record.extend(payload);
+        let checksum = xxh64(&record, 0);
+        record.extend_from_slice(&checksum.to_le_bytes());
+        Ok(record)
+    }
+
+    fn encode_blocks(&self, blocks: Vec<Block>) -> Result<Vec<u8>> {
+        let mut encoded: Vec<u8> = Vec::new();
         for block in blocks {
-            let write_result = write_block_to_disk(&self.options, &mut self.writer, &block);
+            let write_result = write_block_to_disk(&self.options, &mut encoded, &block);
             if write_result.is_err() {
                 return Err(Errors::WAL_WRITE_FAILED);
             }
         }
+        Ok(encoded)
+    }
+
+    /// Rewrite the Write Ahead Log so that it only holds the newest `versions_to_keep`
+    /// records of each key along with all range tombstones. Keys that are written
+    /// repeatedly otherwise bloat the log and the work done during recovery.
+    /// The compacted log is written next to the log and renamed over it so the
+    /// log is left intact if compaction fails. The directory holding the log is
+    /// synced after the rename so that appends following the compaction are never
+    /// lost with the compacted log. Appends wait for the compaction.
+    ///
+    /// # Returns
+    /// Result that resolves
+    ///  - _Ok_ - The number of records retained in the log.
+    ///  - _Err_ - Error that occurred while compacting the log.
+    pub fn compact<K: ResourceKey, V: ResourceValue>(&self) -> Result<usize> {
+        let mut writer = self.writer.lock().unwrap();
+        let (values, tombstones) = WriteAheadLog::read_entries::<K, V>(self.options.clone())?;
+        // later records win among records with the same sequence number
+        let mut versions_by_key: BTreeMap<K, Vec<Value<K, V>>> = BTreeMap::new();
+        for value in values.into_iter().rev() {
+            versions_by_key
+                .entry(value.key.clone())
+                .or_default()
+                .push(value);
+        }
+        let mut retained = Vec::new();
+        for (_, mut versions) in versions_by_key {
+            versions.sort_by(|a, b| b.seq.cmp(&a.seq));
+            versions.truncate(std::cmp::max(self.options.versions_to_keep, 1));
+            // records are replayed in order so older versions are written first
+            retained.extend(versions.into_iter().rev());
+        }
+        self.replace_entries(&mut writer, &retained, &tombstones)?;
+        self.options
+            .backend
+            .sync_dir(Path::new(&self.options.path))
+            .map_err(|_| Errors::WAL_WRITE_FAILED)?;
+        Ok(retained.len())
+    }
+
+    /// Replace the records of the Write Ahead Log with the supplied records and range
+    /// tombstones. Used in place of `reset` when some of the logged values are still
+    /// only held in memory after the others were made durable elsewhere. The new log
+    /// is written next to the log and renamed over it so the records are in one of
+    /// the two logs at any point.
+    ///
+    /// # Arguments
+    ///  - _values_ - The records to keep in the log in the order they are replayed.
+    ///  - _tombstones_ - The range tombstones to keep in the log.
+    ///
+    /// # Returns
+    /// Result that resolves
+    ///  - _Ok_ - If the log was replaced.
+    ///  - _Err_ - `WAL_WRITE_FAILED` if the new log couldn't be written, the log
+    ///    is left intact then.
+    pub fn rewrite<K: ResourceKey, V: ResourceValue>(
+        &self,
+        values: &[Value<K, V>],
+        tombstones: &[RangeTombstone<K>],
+    ) -> Result<()> {
+        let mut writer = self.writer.lock().unwrap();
+        self.replace_entries(&mut writer, values, tombstones)?;
+        if self.options.sync_wal_on_reset {
+            self.options
+                .backend
+                .sync_dir(Path::new(&self.options.path))
+                .map_err(|_| Errors::WAL_WRITE_FAILED)?;
+        }
+        Ok(())
+    }
+
+    // write the entries to a new log that is renamed over the log
+    fn replace_entries<K: ResourceKey, V: ResourceValue>(
+        &self,
+        writer: &mut Box<dyn StorageFile>,
+        values: &[Value<K, V>],
+        tombstones: &[RangeTombstone<K>],
+    ) -> Result<()> {
+        let mut encoded = log_header(&self.options);
+        encoded.extend(self.encode_entries(values, tombstones)?);
+        let backend = self.options.backend.as_ref();
+        let path = PathBuf::from(format!("{0}/{1}", self.options.path, WRITE_AHEAD_LOG_NAME));
+        let replacement_path = path.with_extension("log.tmp");
+        let mut replacement = backend
+            .create(&replacement_path)
+            .map_err(|_| Errors::WAL_WRITE_FAILED)?;
+        replacement
+            .write(&encoded)
+            .and_then(|_| replacement.sync())
+            .map_err(|_| Errors::WAL_WRITE_FAILED)?;
+        backend
+            .rename(&replacement_path, &path)
+            .map_err(|_| Errors::WAL_WRITE_FAILED)?;
+        // the open handle refers to the replaced log
+        *writer = backend.open(&path).map_err(|_| Errors::WAL_WRITE_FAILED)?;
         Ok(())
     }
 
-    /// Clear the Write Ahead Log of previously stored values.
+    /// Get the size of the log in bytes.
+    pub fn size(&self) -> Result<u64> {
+        self.writer
+            .lock()
+            .unwrap()
+            .size()
+            .map_err(|_| Errors::DB_STATS_FAILED)
+    }
+
+    /// Sync the records written to the log to disk.
+    ///
+    /// # Returns
+    /// Result that is:
+    ///  - _Ok_ - If the log was synced.
+    ///  - _Err_ - `WAL_WRITE_FAILED` if the log couldn't be synced.
+    pub fn sync(&self) -> Result<()> {
+        self.writer
+            .lock()
+            .unwrap()
+            .sync()
+            .map_err(|_| Errors::WAL_WRITE_FAILED)
+    }
+
+    /// Get the number of times the log was synced to disk by group commits.
+    pub fn sync_count(&self) -> u64 {
+        self.queue.lock().unwrap().sync_count
+    }
+
+    fn group_commit(&self, encoded: Vec<u8>) -> Result<()> {
+        let mut queue = self.queue.lock().unwrap();
+        if queue.failed {
+            return Err(Errors::WAL_WRITE_FAILED);
+        }
+        queue.pending.extend_from_slice(&encoded);
+        let batch = queue.next_batch;
+        loop {
+            if queue.failed {
+                return Err(Errors::WAL_WRITE_FAILED);
+            }
+            if queue.committed_batch >= batch {
+                return Ok(());
+            }
+            if !queue.committing {
+                // become the leader and commit all pending writes
+                queue.committing = true;
+                let pending = std::mem::replace(&mut queue.pending, Vec::new());
+                let leader_batch = queue.next_batch;
+                queue.next_batch += 1;
+                drop(queue);
+                let commit_result = {
+                    let mut writer = self.writer.lock().unwrap();
+                    writer.write(&pending).and_then(|_| writer.sync())
+                };
+                queue = self.queue.lock().unwrap();
+                queue.committing = false;
+                queue.committed_batch = leader_batch;
+                if commit_result.is_err() {
+                    queue.failed = true;
+                }
+                queue.sync_count += 1;
+                self.committed.notify_all();
+                continue;
+            }
+            queue = self.committed.wait(queue).unwrap();
+        }
+    }
+
+    /// Clear the Write Ahead Log of previously stored values. The deletion of the
+    /// log is made durable before the new log is created when `sync_wal_on_reset`
+    /// is set, values the log held must be durable elsewhere before it is reset.
     ///
     /// # Returns
     /// Result that resolves
@@ -63,10 +543,16 @@ impl WriteAheadLog {
     ///  - _Err_ - Error that occurred while resetting Write Ahead Log.
     pub fn reset(&mut self) -> Result<WriteAheadLog> {
         let delete_wal_result = self.cleanup();
-        if delete_wal_result.is_ok() {
-            return WriteAheadLog::create(self.options.clone());
+        if delete_wal_result.is_err() {
+            return Err(Errors::WAL_LOG_CREATION_FAILED);
         }
-        Err(Errors::WAL_LOG_CREATION_FAILED)
+        if self.options.sync_wal_on_reset {
+            self.options
+                .backend
+                .sync_dir(Path::new(&self.options.path))
+                .map_err(|_| Errors::WAL_CLEANUP_FAILED)?;
+        }
+        WriteAheadLog::create(self.options.clone())
     }
 
     /// Delete the Write Ahead Log.
@@ -77,29 +563,535 @@ impl WriteAheadLog {
     ///  - _Err_ -
     pub fn cleanup(&mut self) -> Result<()> {
         let path = format!("{0}/{1}", self.options.path, WRITE_AHEAD_LOG_NAME);
-        let delete_wal_result = remove_file(&path);
+        let delete_wal_result = self.options.backend.remove(Path::new(&path));
         if delete_wal_result.is_err() {
             return Err(Errors::WAL_CLEANUP_FAILED);
         }
         Ok(())
     }
 
+    /// Read the records appended to the log after the supplied offset without removing
+    /// them from the log. Followers tailing the log pass the returned offset to the
+    /// next call to only receive records appended since. See `read_entries_from`.
+    ///
+    /// # Arguments
+    ///  - _offset_ - Offset returned by a previous read or 0 to read the whole log.
+    ///
+    /// # Returns
+    /// Result that resolves
+    ///  - _Ok_ - The records in the order they were written along with the offset
+    ///    to read the following records from.
+    ///  - _Err_ - Error that occurred while reading the Write Ahead Log.
+    pub fn read_from<K: ResourceKey, V: ResourceValue>(
+        &self,
+        offset: usize,
+    ) -> Result<(Vec<(K, V)>, usize)> {
+        let (values, _, next_offset) = self.read_entries_from::<K, V>(offset)?;
+        let records = values
+            .into_iter()
+            .map(|record| (record.key, record.value))
+            .collect();
+        Ok((records, next_offset))
+    }
+
+    /// Read the records and range tombstones appended to the log after the supplied
+    /// offset without removing them from the log. Every append fills up whole blocks
+    /// so only complete blocks are read. A record whose blocks are not all written yet
+    /// is torn, reading stops at the block it starts in and the records before that
+    /// block are returned along with the offset of that block. Logs in the
+    /// `WalFormat::Framed` format are read up to the first torn record instead.
+    ///
+    /// # Arguments
+    ///  - _offset_ - Offset returned by a previous read or 0 to read the whole log.
+    ///
+    /// # Returns
+    /// Result that resolves
+    ///  - _Ok_ - The records and the range tombstones, each in the order they were
+    ///    written, along with the offset to read the following entries from.
+    ///  - _Err_ - Error that occurred while reading the Write Ahead Log.
+    pub fn read_entries_from<K: ResourceKey, V: ResourceValue>(
+        &self,
+        offset: usize,
+    ) -> Result<(Vec<Value<K, V>>, Vec<RangeTombstone<K>>, usize)> {
+        let path = log_path(&self.options);
+        let header = read_log_header(&self.options, &path)?;
+        let offset = std::cmp::max(offset, header.records_start);
+        if let WalFormat::Framed = self.options.wal_format {
+            let data = read_log_from(&self.options, &path, offset)?;
+            let (operations, read) = decode_framed_records(&data, header.encoding);
+            let (values, tombstones) = split_operations(operations);
+            return Ok((values, tombstones, offset + read));
+        }
+        let block_size = header.block_size;
+        if (offset - header.records_start) % block_size != 0 {
+            return Err(Errors::WAL_INVALID_READ_OFFSET);
+        }
+        let mut file = self
+            .options
+            .backend
+            .open(&path)
+            .map_err(|_| Errors::WAL_BOOTSTRAP_FAILED)?;
+        let size = file.size().map_err(|_| Errors::WAL_BOOTSTRAP_FAILED)? as usize;
+        if offset > size {
+            return Err(Errors::WAL_INVALID_READ_OFFSET);
+        }
+        let end = offset + (size - offset) / block_size * block_size;
+        if end == offset {
+            return Ok((Vec::new(), Vec::new(), offset));
+        }
+        let mut data = vec![0u8; end - offset];
+        match file.read_at(&mut data, offset as u64) {
+            Ok(read) if read == data.len() => {}
+            _ => return Err(Errors::WAL_BOOTSTRAP_FAILED),
+        }
+        let mut reader = SSTableReader::with_file(Box::new(data), &path, block_size, None)
+            .map_err(|_| Errors::WAL_BOOTSTRAP_FAILED)?;
+        reader.set_encoding(header.encoding);
+        reader.set_format_version(FORMAT_VERSION);
+        reader.set_value_transform(self.options.value_transform.clone());
+        // entries are tagged with their offset relative to `offset`
+        let mut values = Vec::new();
+        let mut tombstones = Vec::new();
+        let mut next_offset = end;
+        while reader.has_next() {
+            let value = reader.read();
+            let decoded = if value.range_tombstone {
+                value
+                    .to_range_tombstone::<K>()
+                    .map(|tombstone| tombstones.push((value.offset, tombstone)))
+            } else {
+                value
+                    .to_record::<K, V>()
+                    .map(|record| values.push((value.offset, record)))
+            };
+            if decoded.is_err() || reader.read_failed() {
+                next_offset = offset + value.offset / block_size * block_size;
+                break;
+            }
+            reader.next();
+        }
+        let before_next_offset = |entry_offset: &usize| offset + entry_offset < next_offset;
+        Ok((
+            values
+                .into_iter()
+                .filter(|(entry_offset, _)| before_next_offset(entry_offset))
+                .map(|(_, record)| record)
+                .collect(),
+            tombstones
+                .into_iter()
+                .filter(|(entry_offset, _)| before_next_offset(entry_offset))
+                .map(|(_, tombstone)| tombstone)
+                .collect(),
+            next_offset,
+        ))
+    }
+
     /// Attempt to recover data from existing WAL. This operation does not ensure
     /// database recovery and could lead to data loss. WAL is deleted after
     /// this operation.
     pub fn recover<K: ResourceKey, V: ResourceValue>(options: DharmaOpts) -> Result<Vec<(K, V)>> {
+        let values = WriteAheadLog::recover_values::<K, V>(options)?;
+        Ok(values
+            .into_iter()
+            .map(|record| (record.key, record.value))
+            .collect())
+    }
+
+    /// Attempt to recover the records along with their sequence numbers from
+    /// the existing WAL. See `recover`.
+    pub fn recover_values<K: ResourceKey, V: ResourceValue>(
+        options: DharmaOpts,
+    ) -> Result<Vec<Value<K, V>>> {
+        let (values, _) = WriteAheadLog::recover_entries::<K, V>(options)?;
+        Ok(values)
+    }
+
+    /// Attempt to recover the records and range tombstones from the existing WAL.
+    /// See `recover`.
+    pub fn recover_entries<K: ResourceKey, V: ResourceValue>(
+        options: DharmaOpts,
+    ) -> Result<(Vec<Value<K, V>>, Vec<RangeTombstone<K>>)> {
+        let operations = WriteAheadLog::recover_operations::<K, V>(options)?;
+        Ok(split_operations(operations))
+    }
+
+    /// Attempt to recover the operations from the existing WAL in the order they
+    /// were written. See `recover`.
+    pub fn recover_operations<K: ResourceKey, V: ResourceValue>(
+        options: DharmaOpts,
+    ) -> Result<Vec<WalOperation<K, V>>> {
         let path = format!("{0}/{1}", options.path, WRITE_AHEAD_LOG_NAME);
-        let mut reader =
-            SSTableReader::from(&PathBuf::from(&path), options.block_size_in_bytes).unwrap();
-        let mut data = Vec::new();
+        let data = WriteAheadLog::read_operations(options.clone())?;
+        return options
+            .backend
+            .remove(Path::new(&path))
+            .and_then(|_| Ok(data))
+            .map_err(|_| Errors::WAL_BOOTSTRAP_FAILED);
+    }
+
+    /// Read the records along with their sequence numbers from the existing WAL
+    /// in the order they were written. The WAL is left in place.
+    ///
+    /// # Returns
+    /// Result that resolves
+    ///  - _Ok_ - The records in the Write Ahead Log.
+    ///  - _Err_ - Error that occurred while reading the Write Ahead Log.
+    pub fn read_values<K: ResourceKey, V: ResourceValue>(
+        options: DharmaOpts,
+    ) -> Result<Vec<Value<K, V>>> {
+        let (values, _) = WriteAheadLog::read_entries::<K, V>(options)?;
+        Ok(values)
+    }
+
+    /// Read the records and the range tombstones from the existing WAL, each
+    /// in the order they were written. Deletes are read as records associated
+    /// with the nil value. The WAL is left in place.
+    ///
+    /// # Returns
+    /// Result that resolves
+    ///  - _Ok_ - The records and range tombstones in the Write Ahead Log.
+    ///  - _Err_ - Error that occurred while reading the Write Ahead Log.
+    pub fn read_entries<K: ResourceKey, V: ResourceValue>(
+        options: DharmaOpts,
+    ) -> Result<(Vec<Value<K, V>>, Vec<RangeTombstone<K>>)> {
+        let operations = WriteAheadLog::read_operations::<K, V>(options)?;
+        Ok(split_operations(operations))
+    }
+
+    /// Read the operations from the existing WAL in the order they were written.
+    /// Records of a log in the `WalFormat::Framed` format following a torn or
+    /// corrupt record are dropped. The WAL is left in place.
+    ///
+    /// # Returns
+    /// Result that resolves
+    ///  - _Ok_ - The operations in the Write Ahead Log.
+    ///  - _Err_ - `WAL_FORMAT_MISMATCH` if the log was written in a different format
+    ///    than `options.wal_format` or the error that occurred while reading the log.
+    pub fn read_operations<K: ResourceKey, V: ResourceValue>(
+        options: DharmaOpts,
+    ) -> Result<Vec<WalOperation<K, V>>> {
+        WriteAheadLog::read_operations_at(&options, &log_path(&options))
+    }
+
+    /// Set the existing WAL aside and read its operations in the order they were
+    /// written. The log is renamed so that a database can be opened with a new log
+    /// while the operations are replayed, `finish_recovery` deletes it once the
+    /// replayed operations are durable. A log set aside by a recovery that didn't
+    /// finish is read again, the log created by that recovery only holds operations
+    /// replayed from it and is deleted.
+    ///
+    /// # Returns
+    /// Result that resolves
+    ///  - _Ok_ - The operations of the log that was set aside.
+    ///  - _Err_ - Error that occurred while setting the log aside or reading it.
+    pub fn begin_recovery<K: ResourceKey, V: ResourceValue>(
+        options: DharmaOpts,
+    ) -> Result<Vec<WalOperation<K, V>>> {
+        let backend = options.backend.as_ref();
+        let path = log_path(&options);
+        let recovering_path = recovering_log_path(&options);
+        let set_aside = if backend.exists(&recovering_path) {
+            if backend.exists(&path) {
+                backend.remove(&path)
+            } else {
+                Ok(())
+            }
+        } else {
+            backend.rename(&path, &recovering_path)
+        };
+        set_aside
+            .and_then(|_| backend.sync_dir(Path::new(&options.path)))
+            .map_err(|_| Errors::WAL_BOOTSTRAP_FAILED)?;
+        WriteAheadLog::read_operations_at(&options, &recovering_path)
+    }
+
+    /// Check whether a log set aside by `begin_recovery` wasn't deleted yet because
+    /// the recovery was interrupted.
+    pub fn is_recovering(options: &DharmaOpts) -> bool {
+        options.backend.exists(&recovering_log_path(options))
+    }
+
+    /// Delete the log set aside by `begin_recovery`. The operations read from it
+    /// must be durable elsewhere before.
+    ///
+    /// # Returns
+    /// Result that resolves
+    ///  - _Ok_ - If the log was deleted.
+    ///  - _Err_ - `WAL_CLEANUP_FAILED` if the log couldn't be deleted.
+    pub fn finish_recovery(options: DharmaOpts) -> Result<()> {
+        let backend = options.backend.as_ref();
+        backend
+            .remove(&recovering_log_path(&options))
+            .and_then(|_| backend.sync_dir(Path::new(&options.path)))
+            .map_err(|_| Errors::WAL_CLEANUP_FAILED)
+    }
+
+    fn read_operations_at<K: ResourceKey, V: ResourceValue>(
+        options: &DharmaOpts,
+        path: &Path,
+    ) -> Result<Vec<WalOperation<K, V>>> {
+        let header = read_log_header(options, path)?;
+        if let WalFormat::Framed = options.wal_format {
+            let data = read_log_from(options, path, header.records_start)?;
+            let (operations, read) = decode_framed_records(&data, header.encoding);
+            if read < data.len() {
+                options.log_warn(&format!(
+                    "Dropping {} bytes of the Write Ahead Log following a torn record",
+                    data.len() - read
+                ));
+            }
+            return Ok(operations);
+        }
+        let file = options
+            .backend
+            .open(path)
+            .map_err(|_| Errors::WAL_BOOTSTRAP_FAILED)?;
+        let records = LogRecords {
+            file,
+            start: header.records_start as u64,
+        };
+        let mut reader = SSTableReader::with_file(
+            Box::new(records),
+            &path.to_path_buf(),
+            header.block_size,
+            None,
+        )
+        .map_err(|_| Errors::WAL_BOOTSTRAP_FAILED)?;
+        reader.set_readahead(options.wal_recovery_readahead_blocks);
+        reader.set_encoding(header.encoding);
+        reader.set_format_version(FORMAT_VERSION);
+        reader.set_value_transform(options.value_transform.clone());
+        let mut operations = Vec::new();
         while reader.has_next() {
             let value = reader.read();
-            let record: Value<K, V> = value.to_record::<K, V>().unwrap();
-            data.push((record.key, record.value));
+            if value.range_tombstone {
+                let tombstone = value
+                    .to_range_tombstone::<K>()
+                    .map_err(|_| Errors::WAL_BOOTSTRAP_FAILED)?;
+                operations.push(WalOperation::DeleteRange(tombstone));
+                reader.next();
+                continue;
+            }
+            let record: Value<K, V> = value
+                .to_record::<K, V>()
+                .map_err(|_| Errors::WAL_BOOTSTRAP_FAILED)?;
+            operations.push(WalOperation::from_value(record));
             reader.next();
         }
-        return remove_file(&path)
-            .and_then(|_| Ok(data))
-            .map_err(|_| Errors::WAL_BOOTSTRAP_FAILED);
+        Ok(operations)
+    }
+}
+
+/// Get the path of the log for the supplied database config.
+fn log_path(options: &DharmaOpts) -> PathBuf {
+    PathBuf::from(format!("{0}/{1}", options.path, WRITE_AHEAD_LOG_NAME))
+}
+
+/// Get the path the log is set aside at while it is recovered.
+fn recovering_log_path(options: &DharmaOpts) -> PathBuf {
+    PathBuf::from(format!("{0}/{1}", options.path, RECOVERING_LOG_NAME))
+}
+
+/// Get the header written at the start of new logs recording the configured
+/// format and encoding.
+fn log_header(options: &DharmaOpts) -> Vec<u8> {
+    let mut header = WAL_FORMAT_MAGIC.to_vec();
+    header.push(match options.wal_format {
+        WalFormat::Blocks => WAL_FORMAT_BLOCKS,
+        WalFormat::Framed => WAL_FORMAT_FRAMED,
+    });
+    header.push(options.encoding.id());
+    header.extend_from_slice(&(options.block_size_in_bytes as u32).to_le_bytes());
+    header
+}
+
+/// Read the header at the start of the log and check that the log was written in
+/// the configured format. Logs without a header were written in the
+/// `WalFormat::Blocks` format with the configured encoding before the header was
+/// recorded, a log whose header never made it to disk holds no records.
+///
+/// # Returns
+/// Result that resolves
+///  - _Ok_ - The header of the log.
+///  - _Err_ - `WAL_FORMAT_MISMATCH` if the log was written in a different format.
+fn read_log_header(options: &DharmaOpts, path: &Path) -> Result<LogHeader> {
+    let mut file = options
+        .backend
+        .open(path)
+        .map_err(|_| Errors::WAL_BOOTSTRAP_FAILED)?;
+    let size = file.size().map_err(|_| Errors::WAL_BOOTSTRAP_FAILED)? as usize;
+    let mut header = vec![0u8; std::cmp::min(size, WAL_HEADER_SIZE_IN_BYTES)];
+    match file.read_at(&mut header, 0) {
+        Ok(read) if read == header.len() => {}
+        _ => return Err(Errors::WAL_BOOTSTRAP_FAILED),
+    }
+    let torn_header = WAL_FORMAT_MAGIC.starts_with(&header) || header.starts_with(WAL_FORMAT_MAGIC);
+    let configured = |records_start| LogHeader {
+        records_start,
+        encoding: options.encoding,
+        block_size: options.block_size_in_bytes,
+    };
+    if size < WAL_HEADER_SIZE_IN_BYTES && torn_header {
+        return Ok(configured(size));
+    }
+    if !header.starts_with(WAL_FORMAT_MAGIC) {
+        return match options.wal_format {
+            WalFormat::Blocks => Ok(configured(0)),
+            WalFormat::Framed => Err(Errors::WAL_FORMAT_MISMATCH),
+        };
+    }
+    let format = match header[4] {
+        WAL_FORMAT_BLOCKS => WalFormat::Blocks,
+        WAL_FORMAT_FRAMED => WalFormat::Framed,
+        _ => return Err(Errors::WAL_FORMAT_MISMATCH),
+    };
+    if format != options.wal_format {
+        return Err(Errors::WAL_FORMAT_MISMATCH);
+    }
+    let encoding = Encoding::from_id(header[5]).ok_or(Errors::WAL_BOOTSTRAP_FAILED)?;
+    let mut block_size = [0u8; 4];
+    block_size.copy_from_slice(&header[6..WAL_HEADER_SIZE_IN_BYTES]);
+    Ok(LogHeader {
+        records_start: WAL_HEADER_SIZE_IN_BYTES,
+        encoding,
+        block_size: u32::from_le_bytes(block_size) as usize,
+    })
+}
+
+/// Metadata recorded in the header at the start of the log, see `read_log_header`.
+struct LogHeader {
+    // offset of the first record in the log
+    records_start: usize,
+    // encoding the records were serialized with
+    encoding: Encoding,
+    // size of the blocks of logs in the `WalFormat::Blocks` format
+    block_size: usize,
+}
+
+/// The records of a log following its header, read like an SSTable.
+struct LogRecords {
+    file: Box<dyn StorageFile>,
+    // offset of the first record in the log
+    start: u64,
+}
+
+impl StorageFile for LogRecords {
+    fn read_at(&mut self, buffer: &mut [u8], offset: u64) -> std::io::Result<usize> {
+        self.file.read_at(buffer, self.start + offset)
+    }
+
+    fn write(&mut self, data: &[u8]) -> std::io::Result<()> {
+        self.file.write(data)
+    }
+
+    fn size(&self) -> std::io::Result<u64> {
+        Ok(self.file.size()?.saturating_sub(self.start))
+    }
+
+    fn sync(&mut self) -> std::io::Result<()> {
+        self.file.sync()
+    }
+}
+
+/// Read the bytes of the log following the offset.
+fn read_log_from(options: &DharmaOpts, path: &Path, offset: usize) -> Result<Vec<u8>> {
+    let mut file = options
+        .backend
+        .open(path)
+        .map_err(|_| Errors::WAL_BOOTSTRAP_FAILED)?;
+    let size = file.size().map_err(|_| Errors::WAL_BOOTSTRAP_FAILED)? as usize;
+    if offset > size {
+        return Err(Errors::WAL_INVALID_READ_OFFSET);
+    }
+    let mut data = vec![0u8; size - offset];
+    match file.read_at(&mut data, offset as u64) {
+        Ok(read) if read == data.len() => Ok(data),
+        _ => Err(Errors::WAL_BOOTSTRAP_FAILED),
+    }
+}
+
+/// Decode the records of `WalFormat::Framed` at the start of the data. Decoding stops
+/// at the first record that is torn, fails its checksum or can't be deserialized.
+///
+/// # Returns
+/// The decoded operations in order along with the number of bytes they were read from.
+fn decode_framed_records<K: ResourceKey, V: ResourceValue>(
+    data: &[u8],
+    encoding: Encoding,
+) -> (Vec<WalOperation<K, V>>, usize) {
+    let mut operations = Vec::new();
+    let mut offset = 0;
+    while data.len() - offset >= WAL_RECORD_HEADER_SIZE_IN_BYTES + WAL_RECORD_CHECKSUM_SIZE_IN_BYTES
+    {
+        let mut size = [0u8; 4];
+        size.copy_from_slice(&data[offset + 1..offset + WAL_RECORD_HEADER_SIZE_IN_BYTES]);
+        let payload_end =
+            offset + WAL_RECORD_HEADER_SIZE_IN_BYTES + u32::from_be_bytes(size) as usize;
+        let record_end = payload_end + WAL_RECORD_CHECKSUM_SIZE_IN_BYTES;
+        if record_end > data.len() {
+            break;
+        }
+        let mut checksum = [0u8; WAL_RECORD_CHECKSUM_SIZE_IN_BYTES];
+        checksum.copy_from_slice(&data[payload_end..record_end]);
+        if xxh64(&data[offset..payload_end], 0) != u64::from_le_bytes(checksum) {
+            break;
+        }
+        let mut payload = data[offset + WAL_RECORD_HEADER_SIZE_IN_BYTES..payload_end].to_vec();
+        if is_compressed(data[offset]) {
+            match decompress_size_prepended(&payload) {
+                Ok(decompressed) => payload = decompressed,
+                Err(_) => break,
+            }
+        }
+        let operation = match data[offset] & !Record::RECORD_COMPRESSED_FLAG {
+            WAL_OP_PUT => encoding.deserialize(&payload).ok().map(WalOperation::Put),
+            WAL_OP_DELETE => encoding
+                .deserialize(&payload)
+                .ok()
+                .map(|(key, seq)| WalOperation::Delete { key, seq }),
+            WAL_OP_DELETE_RANGE => encoding
+                .deserialize(&payload)
+                .ok()
+                .map(WalOperation::DeleteRange),
+            _ => None,
+        };
+        match operation {
+            Some(operation) => operations.push(operation),
+            None => break,
+        }
+        offset = record_end;
+    }
+    (operations, offset)
+}
+
+/// Split the operations into the records, with deletes associated with the nil
+/// value, and the range tombstones, each in the order they were written.
+fn split_operations<K: ResourceKey, V: ResourceValue>(
+    operations: Vec<WalOperation<K, V>>,
+) -> (Vec<Value<K, V>>, Vec<RangeTombstone<K>>) {
+    let mut values = Vec::new();
+    let mut tombstones = Vec::new();
+    for operation in operations {
+        match operation {
+            WalOperation::Put(value) => values.push(value),
+            WalOperation::Delete { key, seq } => values.push(Value::with_seq(key, V::nil(), seq)),
+            WalOperation::DeleteRange(tombstone) => tombstones.push(tombstone),
+        }
+    }
+    (values, tombstones)
+}
+
+// Records in the log are compressed according to `wal_compression_min_bytes` when
+// it is set. The reader decompresses records by their flag so recovery is unaffected.
+fn wal_block_options(options: &DharmaOpts) -> DharmaOpts {
+    let mut block_options = options.clone();
+    // records are located by their offset in the log and never by block
+    block_options.block_headers = false;
+    // the log has no footer recording the codec so records are always serialized
+    // with `bincode`
+    block_options.record_codec = None;
+    if options.wal_compression_min_bytes.is_some() {
+        block_options.value_compression_min_bytes = options.wal_compression_min_bytes;
     }
+    block_options
 }
@@ -13,7 +13,14 @@ pub trait Nil {
 
 /// Trait to be implemented by a key used to persist
 /// values into the store.
-pub trait ResourceKey: Clone + Display + Ord + Serialize + DeserializeOwned {}
+/// Keys must be `'static` so that compaction filters and record codecs registered
+/// in the options can be looked up by the key type. Keys borrowing data are no
+/// longer supported.
+pub trait ResourceKey: Clone + Display + Ord + Serialize + DeserializeOwned + 'static {}
 
 /// Trait to be implemented by values to be persisted in the store.
-pub trait ResourceValue: Clone + Display + Serialize + DeserializeOwned + Nil + PartialEq {}
+/// Values must be `'static` for the same reason as keys.
+pub trait ResourceValue:
+    Clone + Display + Serialize + DeserializeOwned + Nil + PartialEq + 'static
+{
+}
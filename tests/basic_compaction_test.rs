@@ -2,11 +2,18 @@ use crate::common::test_key::TestKey;
 use crate::common::test_value::TestValue;
 use crate::common::{cleanup_paths, get_test_data, get_test_data_in_range};
 use dharmadb::options::DharmaOpts;
+use dharmadb::storage::backend::InMemoryBackend;
 use dharmadb::storage::block::Value;
 use dharmadb::storage::compaction::basic::{BasicCompaction, BasicCompactionOpts};
+use dharmadb::storage::compaction::{CompactionFilter, RegisteredCompactionFilter};
+use dharmadb::storage::encoding::Encoding;
 use dharmadb::storage::sorted_string_table_reader::SSTableReader;
-use dharmadb::storage::sorted_string_table_writer::write_sstable;
+use dharmadb::storage::sorted_string_table_writer::{write_sstable, write_values_at_path};
+use dharmadb::storage::value_log::ValueLog;
 use dharmadb::traits::Nil;
+use std::fs::metadata;
+use std::path::PathBuf;
+use std::sync::Arc;
 
 mod common;
 
@@ -169,3 +176,390 @@ fn test_basic_compaction_ignores_delete_markers() {
         i += 1;
     }
 }
+
+fn read_compacted_table(
+    path: &std::path::PathBuf,
+    options: &DharmaOpts,
+) -> Vec<(TestKey, TestValue)> {
+    let mut reader = SSTableReader::with_options(options, path).unwrap();
+    let mut output = Vec::new();
+    while reader.has_next() {
+        let record: Value<TestKey, TestValue> = reader.read().to_record().unwrap();
+        output.push((record.key, record.value));
+        reader.next();
+    }
+    output
+}
+
+#[test]
+fn test_basic_compaction_with_io_budget() {
+    let data_1 = get_test_data_in_range(0, 200);
+    let data_2 = get_test_data_in_range(80, 220);
+    let mut options = DharmaOpts::default();
+    options.block_size_in_bytes = 512;
+    cleanup_paths(&options);
+    assert!(write_sstable(&options, &data_1, 0).is_ok());
+    assert!(write_sstable(&options, &data_2, 1).is_ok());
+
+    let mut compaction_opts = BasicCompactionOpts::from(options.clone());
+    compaction_opts.threshold = 2;
+    let compaction = BasicCompaction::new(compaction_opts);
+    let compaction_path = compaction.compact::<TestKey, TestValue>().unwrap().unwrap();
+    assert_eq!(compaction.last_compaction_steps(), 1);
+    let expected = read_compacted_table(&compaction_path, &options);
+
+    let mut budgeted_options = options.clone();
+    budgeted_options.compaction_io_budget_bytes_per_op = Some(256);
+    let mut budgeted_compaction_opts = BasicCompactionOpts::from(budgeted_options.clone());
+    budgeted_compaction_opts.threshold = 2;
+    let budgeted_compaction = BasicCompaction::new(budgeted_compaction_opts);
+    let budgeted_path = budgeted_compaction
+        .compact::<TestKey, TestValue>()
+        .unwrap()
+        .unwrap();
+    // compaction is split into more steps but produces the same table
+    assert!(budgeted_compaction.last_compaction_steps() > 1);
+    assert_eq!(read_compacted_table(&budgeted_path, &options), expected);
+
+    // the written table counts against the budget as well as the read tables so
+    // keeping the overwritten versions of the same tables takes more steps
+    let mut versioned_options = budgeted_options.clone();
+    versioned_options.versions_to_keep = 2;
+    let mut versioned_compaction_opts = BasicCompactionOpts::from(versioned_options);
+    versioned_compaction_opts.threshold = 2;
+    let versioned_compaction = BasicCompaction::new(versioned_compaction_opts);
+    versioned_compaction
+        .compact::<TestKey, TestValue>()
+        .unwrap()
+        .unwrap();
+    assert!(
+        versioned_compaction.last_compaction_steps() > budgeted_compaction.last_compaction_steps()
+    );
+}
+
+#[test]
+fn test_basic_compaction_only_merges_overlapping_tables() {
+    let data_1 = get_test_data_in_range(0, 200);
+    let data_2 = get_test_data_in_range(500, 600);
+    let data_3 = get_test_data_in_range(150, 300);
+    let options = DharmaOpts::default();
+    cleanup_paths(&options);
+    let path_1 = write_sstable(&options, &data_1, 0).unwrap().unwrap();
+    let path_2 = write_sstable(&options, &data_2, 1).unwrap().unwrap();
+    let path_3 = write_sstable(&options, &data_3, 2).unwrap().unwrap();
+
+    let mut compaction_opts = BasicCompactionOpts::from(options.clone());
+    compaction_opts.threshold = 3;
+    compaction_opts.merge_overlapping_only = true;
+    let compaction = BasicCompaction::new(compaction_opts);
+    let compaction_path = compaction.compact::<TestKey, TestValue>().unwrap().unwrap();
+    // the disjoint table is left untouched
+    assert_eq!(compaction.last_merged_tables(), vec![path_1, path_3]);
+    assert_eq!(
+        read_compacted_table(&compaction_path, &options),
+        get_test_data_in_range(0, 300)
+    );
+    assert_eq!(read_compacted_table(&path_2, &options), data_2);
+}
+
+#[test]
+fn test_basic_compaction_with_byte_threshold() {
+    let options = DharmaOpts::default();
+    cleanup_paths(&options);
+    let small_path = write_sstable(&options, &get_test_data(10), 0)
+        .unwrap()
+        .unwrap();
+    let large_path = write_sstable(&options, &get_test_data(5000), 1)
+        .unwrap()
+        .unwrap();
+    let total_bytes = metadata(&small_path).unwrap().len() + metadata(&large_path).unwrap().len();
+
+    // two tables are below the count threshold but reach the byte threshold
+    let mut compaction_opts = BasicCompactionOpts::from(options.clone());
+    compaction_opts.threshold_bytes = Some(total_bytes);
+    let compaction = BasicCompaction::new(compaction_opts);
+    let compaction_path = compaction.compact::<TestKey, TestValue>().unwrap().unwrap();
+    assert_eq!(
+        compaction.last_merged_tables(),
+        vec![small_path, large_path]
+    );
+    assert_eq!(
+        read_compacted_table(&compaction_path, &options),
+        get_test_data(5000)
+    );
+
+    // the count threshold is ignored once a byte threshold is set
+    let mut compaction_opts = BasicCompactionOpts::from(options.clone());
+    compaction_opts.threshold = 2;
+    compaction_opts.threshold_bytes = Some(total_bytes + 1);
+    let compaction = BasicCompaction::new(compaction_opts);
+    assert!(compaction
+        .compact::<TestKey, TestValue>()
+        .unwrap()
+        .is_none());
+}
+
+#[test]
+fn test_basic_compaction_with_separated_values() {
+    let large_value = "v".repeat(1000);
+    let to_large_values = |data: Vec<(TestKey, TestValue)>| -> Vec<(TestKey, TestValue)> {
+        data.into_iter()
+            .map(|(key, _)| (key, TestValue::from(large_value.as_str())))
+            .collect()
+    };
+    let data_1 = to_large_values(get_test_data_in_range(0, 200));
+    let data_2 = to_large_values(get_test_data_in_range(100, 300));
+    let mut options = DharmaOpts::default();
+    options.block_size_in_bytes = 4096;
+    options.value_separation_min_bytes = Some(100);
+    cleanup_paths(&options);
+    write_sstable(&options, &data_1, 0).unwrap().unwrap();
+    write_sstable(&options, &data_2, 1).unwrap().unwrap();
+    let value_log_path = ValueLog::path(&options);
+    let value_log_size = metadata(&value_log_path).unwrap().len();
+    assert!(value_log_size >= 400 * 1000);
+
+    let mut compaction_opts = BasicCompactionOpts::from(options.clone());
+    compaction_opts.threshold = 2;
+    let compaction = BasicCompaction::new(compaction_opts);
+    let compaction_path = compaction.compact::<TestKey, TestValue>().unwrap().unwrap();
+    // values are resolved through the pointers in the compacted table
+    assert_eq!(
+        read_compacted_table(&compaction_path, &options),
+        to_large_values(get_test_data_in_range(0, 300))
+    );
+    // compaction only rewrote keys and pointers
+    assert_eq!(metadata(&value_log_path).unwrap().len(), value_log_size);
+    let table_size = metadata(&compaction_path).unwrap().len();
+    assert!(table_size * 10 < 300 * 1000);
+}
+
+#[test]
+fn test_separated_values_are_read_with_the_encoding_of_the_value_log() {
+    let large_value = "v".repeat(1000);
+    let to_large_values = |data: Vec<(TestKey, TestValue)>| -> Vec<(TestKey, TestValue)> {
+        data.into_iter()
+            .map(|(key, _)| (key, TestValue::from(large_value.as_str())))
+            .collect()
+    };
+    let data_1 = to_large_values(get_test_data_in_range(0, 200));
+    let data_2 = to_large_values(get_test_data_in_range(100, 300));
+    let mut options = DharmaOpts::default();
+    options.block_size_in_bytes = 4096;
+    options.value_separation_min_bytes = Some(100);
+    cleanup_paths(&options);
+    write_sstable(&options, &data_1, 0).unwrap().unwrap();
+    // the value log keeps the encoding it was created with after the encoding changed
+    options.encoding = Encoding::Varint;
+    write_sstable(&options, &data_2, 1).unwrap().unwrap();
+
+    let mut compaction_opts = BasicCompactionOpts::from(options.clone());
+    compaction_opts.threshold = 2;
+    let compaction = BasicCompaction::new(compaction_opts);
+    let compaction_path = compaction.compact::<TestKey, TestValue>().unwrap().unwrap();
+    assert_eq!(
+        read_compacted_table(&compaction_path, &options),
+        to_large_values(get_test_data_in_range(0, 300))
+    );
+}
+
+#[test]
+fn test_merge_iter_matches_compacted_table() {
+    let data_1 = get_test_data_in_range(0, 300);
+    let mut data_2 = get_test_data_in_range(200, 500);
+    // keys deleted in the newer table are dropped from the merged stream
+    data_2[0].1 = TestValue::nil();
+    data_2[50].1 = TestValue::nil();
+    let options = DharmaOpts::default();
+    cleanup_paths(&options);
+    assert!(write_sstable(&options, &data_1, 0).is_ok());
+    assert!(write_sstable(&options, &data_2, 1).is_ok());
+
+    let mut compaction_opts = BasicCompactionOpts::from(options.clone());
+    compaction_opts.threshold = 2;
+    let compaction = BasicCompaction::new(compaction_opts);
+    let merged: Vec<(TestKey, TestValue)> = compaction
+        .merge_iter::<TestKey, TestValue>()
+        .unwrap()
+        .map(Result::unwrap)
+        .collect();
+    assert_eq!(merged.len(), 498);
+    assert!(!merged.contains(&(TestKey::from(200), TestValue::nil())));
+    let compaction_path = compaction.compact::<TestKey, TestValue>().unwrap().unwrap();
+    assert_eq!(read_compacted_table(&compaction_path, &options), merged);
+}
+
+#[test]
+fn test_merge_orders_keys_and_breaks_ties_by_table() {
+    let mut options = DharmaOpts::default();
+    options.backend = Arc::new(InMemoryBackend::new());
+    let table_data = |table: usize, every: u32| -> Vec<Value<TestKey, TestValue>> {
+        (0..30)
+            .filter(|i| i % every == 0)
+            .map(|i| {
+                // the oldest table holds the newest version of key 5
+                let seq = if table == 0 && i == 5 { 10 } else { 1 };
+                let value = TestValue::from(format!("table {} key {}", table, i).as_str());
+                Value::with_seq(TestKey::from(i), value, seq)
+            })
+            .collect()
+    };
+    let paths: Vec<PathBuf> = [1, 2, 3]
+        .iter()
+        .enumerate()
+        .map(|(table, every)| {
+            let path = PathBuf::from(format!("{}/tables/{}.db", options.path, table));
+            write_values_at_path(&options, &table_data(table, *every), &path).unwrap();
+            path
+        })
+        .collect();
+
+    let compaction = BasicCompaction::new(BasicCompactionOpts::from(options.clone()));
+    let merged: Vec<(TestKey, TestValue)> = compaction
+        .merge_latest_iter::<TestKey, TestValue>(&paths)
+        .unwrap()
+        .map(Result::unwrap)
+        .collect();
+    let expected: Vec<(TestKey, TestValue)> = (0..30)
+        .map(|i| {
+            // values of the same sequence number come from the newest table holding the key
+            let table = match i {
+                5 => 0,
+                i if i % 3 == 0 => 2,
+                i if i % 2 == 0 => 1,
+                _ => 0,
+            };
+            let value = TestValue::from(format!("table {} key {}", table, i).as_str());
+            (TestKey::from(i), value)
+        })
+        .collect();
+    assert_eq!(merged, expected);
+}
+
+#[test]
+fn test_consolidate_drops_delete_markers() {
+    let mut options = DharmaOpts::default();
+    options.backend = Arc::new(InMemoryBackend::new());
+    let values: Vec<Value<TestKey, TestValue>> = get_test_data_in_range(0, 200)
+        .into_iter()
+        .map(|(key, value)| Value::with_seq(key, value, 1))
+        .collect();
+    let deletes: Vec<Value<TestKey, TestValue>> = (0..50)
+        .map(|i| Value::with_seq(TestKey::from(i), TestValue::nil(), 2))
+        .collect();
+    let paths: Vec<PathBuf> = [values, deletes]
+        .iter()
+        .enumerate()
+        .map(|(table, values)| {
+            let path = PathBuf::from(format!("{}/tables/{}.db", options.path, table));
+            write_values_at_path(&options, values, &path).unwrap();
+            path
+        })
+        .collect();
+    let compaction = BasicCompaction::new(BasicCompactionOpts::from(options.clone()));
+
+    let output_path = PathBuf::from(format!("{}/tables/consolidated.db", options.path));
+    let consolidated = compaction.consolidate::<TestKey, TestValue>(&paths, &output_path);
+    assert!(consolidated.unwrap());
+    let mut reader = SSTableReader::with_options(&options, &output_path).unwrap();
+    let mut output = Vec::new();
+    while reader.has_next() {
+        let record: Value<TestKey, TestValue> = reader.read().to_record().unwrap();
+        output.push((record.key, record.value));
+        reader.next();
+    }
+    assert_eq!(output, get_test_data_in_range(50, 200));
+
+    // no table is written when every key is deleted
+    let empty_path = PathBuf::from(format!("{}/tables/empty.db", options.path));
+    let consolidated =
+        compaction.consolidate::<TestKey, TestValue>(&paths[1..].to_vec(), &empty_path);
+    assert!(!consolidated.unwrap());
+    assert!(!options.backend.exists(&empty_path));
+}
+
+struct ExpiredFilter;
+
+impl CompactionFilter<TestKey, TestValue> for ExpiredFilter {
+    fn should_keep(&self, _key: &TestKey, value: &TestValue) -> bool {
+        !value.to_string().contains("expired")
+    }
+}
+
+#[test]
+fn test_basic_compaction_with_compaction_filter() {
+    let mut options = DharmaOpts::default();
+    options.backend = Arc::new(InMemoryBackend::new());
+    options.compaction_filter = Some(RegisteredCompactionFilter::new(ExpiredFilter));
+    // every tenth key of the first table and every fifth key of the second expired
+    let mark = |data: Vec<(TestKey, TestValue)>, every: u32| -> Vec<(TestKey, TestValue)> {
+        data.into_iter()
+            .enumerate()
+            .map(|(i, (key, value))| match i as u32 % every {
+                0 => (key, TestValue::from("expired")),
+                _ => (key, value),
+            })
+            .collect()
+    };
+    let data_1 = mark(get_test_data(200), 10);
+    let data_2 = mark(get_test_data_in_range(100, 300), 5);
+    assert!(write_sstable(&options, &data_1, 0).is_ok());
+    assert!(write_sstable(&options, &data_2, 1).is_ok());
+
+    let mut compaction_opts = BasicCompactionOpts::from(options.clone());
+    compaction_opts.threshold = 2;
+    let compaction = BasicCompaction::new(compaction_opts);
+    let compaction_path = compaction.compact::<TestKey, TestValue>().unwrap().unwrap();
+    let mut reader = SSTableReader::with_backend(
+        options.backend.as_ref(),
+        &compaction_path,
+        options.block_size_in_bytes,
+    )
+    .unwrap();
+    let mut output = Vec::new();
+    while reader.has_next() {
+        let record: Value<TestKey, TestValue> = reader.read().to_record().unwrap();
+        output.push((record.key, record.value));
+        reader.next();
+    }
+    // values from the second table overwrite values of the first table
+    let mut expected: Vec<(TestKey, TestValue)> = data_1
+        .into_iter()
+        .filter(|(key, _)| key < &TestKey::from(100))
+        .chain(data_2)
+        .filter(|(_, value)| value != &TestValue::from("expired"))
+        .collect();
+    expected.sort_by_key(|val| val.0.clone());
+    assert_eq!(output, expected);
+    assert_eq!(output.len(), 90 + 160);
+}
+
+#[test]
+fn test_basic_compaction_splits_output_by_record_count() {
+    let data_1 = get_test_data_in_range(0, 400);
+    let data_2 = get_test_data_in_range(200, 600);
+    let options = DharmaOpts::default();
+    cleanup_paths(&options);
+    let path_1 = write_sstable(&options, &data_1, 0).unwrap().unwrap();
+    let path_2 = write_sstable(&options, &data_2, 1).unwrap().unwrap();
+
+    let mut compaction_opts = BasicCompactionOpts::from(options.clone());
+    compaction_opts.threshold = 2;
+    compaction_opts.max_records_per_output_table = Some(100);
+    let compaction = BasicCompaction::new(compaction_opts);
+    let compacted = compaction
+        .compact_concurrently::<TestKey, TestValue>()
+        .unwrap();
+    assert_eq!(compacted.len(), 1);
+    let (compacted_paths, merged_paths) = &compacted[0];
+    assert_eq!(merged_paths, &vec![path_1, path_2]);
+    // 600 distinct keys are written to tables of 100 records each
+    assert_eq!(compacted_paths.len(), 6);
+    let mut output = Vec::new();
+    for compacted_path in compacted_paths {
+        let records = read_compacted_table(compacted_path, &options);
+        assert_eq!(records.len(), 100);
+        output.extend(records);
+    }
+    assert_eq!(output, get_test_data_in_range(0, 600));
+}
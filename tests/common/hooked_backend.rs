@@ -0,0 +1,179 @@
+use dharmadb::storage::backend::{InMemoryBackend, StorageBackend, StorageFile};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+type PathHook = Arc<dyn Fn(&Path) -> io::Result<()> + Send + Sync>;
+type RenameHook = Arc<dyn Fn(&Path, &Path) -> io::Result<()> + Send + Sync>;
+
+/// Backend delegating every operation to an `InMemoryBackend`. Tests inject
+/// failures and observe the database by registering a hook per operation. A hook
+/// runs before the operation is delegated and fails the operation with its error
+/// instead of delegating it. Files created or opened by the backend run the write
+/// and sync hooks with their path.
+#[derive(Clone)]
+pub struct HookedBackend {
+    inner: InMemoryBackend,
+    on_create: Option<PathHook>,
+    on_remove: Option<PathHook>,
+    on_rename: Option<RenameHook>,
+    on_sync_dir: Option<PathHook>,
+    on_write: Option<PathHook>,
+    on_sync: Option<PathHook>,
+}
+
+impl HookedBackend {
+    pub fn new(inner: InMemoryBackend) -> HookedBackend {
+        HookedBackend {
+            inner,
+            on_create: None,
+            on_remove: None,
+            on_rename: None,
+            on_sync_dir: None,
+            on_write: None,
+            on_sync: None,
+        }
+    }
+
+    pub fn on_create<F>(mut self, hook: F) -> HookedBackend
+    where
+        F: Fn(&Path) -> io::Result<()> + Send + Sync + 'static,
+    {
+        self.on_create = Some(Arc::new(hook));
+        self
+    }
+
+    pub fn on_remove<F>(mut self, hook: F) -> HookedBackend
+    where
+        F: Fn(&Path) -> io::Result<()> + Send + Sync + 'static,
+    {
+        self.on_remove = Some(Arc::new(hook));
+        self
+    }
+
+    pub fn on_rename<F>(mut self, hook: F) -> HookedBackend
+    where
+        F: Fn(&Path, &Path) -> io::Result<()> + Send + Sync + 'static,
+    {
+        self.on_rename = Some(Arc::new(hook));
+        self
+    }
+
+    pub fn on_sync_dir<F>(mut self, hook: F) -> HookedBackend
+    where
+        F: Fn(&Path) -> io::Result<()> + Send + Sync + 'static,
+    {
+        self.on_sync_dir = Some(Arc::new(hook));
+        self
+    }
+
+    pub fn on_write<F>(mut self, hook: F) -> HookedBackend
+    where
+        F: Fn(&Path) -> io::Result<()> + Send + Sync + 'static,
+    {
+        self.on_write = Some(Arc::new(hook));
+        self
+    }
+
+    pub fn on_sync<F>(mut self, hook: F) -> HookedBackend
+    where
+        F: Fn(&Path) -> io::Result<()> + Send + Sync + 'static,
+    {
+        self.on_sync = Some(Arc::new(hook));
+        self
+    }
+
+    fn wrap(&self, path: &Path, file: Box<dyn StorageFile>) -> Box<dyn StorageFile> {
+        Box::new(HookedFile {
+            inner: file,
+            path: path.to_path_buf(),
+            on_write: self.on_write.clone(),
+            on_sync: self.on_sync.clone(),
+        })
+    }
+}
+
+fn run(hook: &Option<PathHook>, path: &Path) -> io::Result<()> {
+    match hook {
+        Some(hook) => hook(path),
+        None => Ok(()),
+    }
+}
+
+impl StorageBackend for HookedBackend {
+    fn create(&self, path: &Path) -> io::Result<Box<dyn StorageFile>> {
+        run(&self.on_create, path)?;
+        let file = self.inner.create(path)?;
+        Ok(self.wrap(path, file))
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Box<dyn StorageFile>> {
+        let file = self.inner.open(path)?;
+        Ok(self.wrap(path, file))
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        self.inner.exists(path)
+    }
+
+    fn remove(&self, path: &Path) -> io::Result<()> {
+        run(&self.on_remove, path)?;
+        self.inner.remove(path)
+    }
+
+    fn list_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        self.inner.list_dir(path)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        if let Some(hook) = &self.on_rename {
+            hook(from, to)?;
+        }
+        self.inner.rename(from, to)
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        self.inner.create_dir_all(path)
+    }
+
+    fn sync_dir(&self, path: &Path) -> io::Result<()> {
+        run(&self.on_sync_dir, path)?;
+        self.inner.sync_dir(path)
+    }
+}
+
+struct HookedFile {
+    inner: Box<dyn StorageFile>,
+    path: PathBuf,
+    on_write: Option<PathHook>,
+    on_sync: Option<PathHook>,
+}
+
+impl StorageFile for HookedFile {
+    fn read_at(&mut self, buffer: &mut [u8], offset: u64) -> io::Result<usize> {
+        self.inner.read_at(buffer, offset)
+    }
+
+    fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        run(&self.on_write, &self.path)?;
+        self.inner.write(data)
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        self.inner.size()
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        run(&self.on_sync, &self.path)?;
+        self.inner.sync()
+    }
+
+    fn set_len(&mut self, size: u64) -> io::Result<()> {
+        self.inner.set_len(size)
+    }
+
+    fn write_at(&mut self, data: &[u8], offset: u64) -> io::Result<()> {
+        run(&self.on_write, &self.path)?;
+        self.inner.write_at(data, offset)
+    }
+}
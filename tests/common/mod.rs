@@ -3,6 +3,7 @@ use crate::common::test_value::TestValue;
 use dharmadb::options::DharmaOpts;
 use std::fs::{create_dir, remove_dir_all, remove_file};
 
+pub mod hooked_backend;
 pub mod test_key;
 pub mod test_value;
 
@@ -33,9 +34,17 @@ pub fn get_test_data_in_range(start: u32, end: u32) -> Vec<(TestKey, TestValue)>
 pub fn cleanup_paths(options: &DharmaOpts) {
     let sstable_dir = format!("{0}/tables", options.path);
     let wal_path = format!("{0}/wal.log", options.path);
+    let recovering_wal_path = format!("{0}/wal.log.recovering", options.path);
     let compaction_path = format!("{}/compaction", options.path);
+    let manifest_path = format!("{0}/MANIFEST", options.path);
+    let value_log_path = format!("{0}/values.log", options.path);
+    let sealed_marker_path = format!("{0}/SEALED", options.path);
     remove_dir_all(&sstable_dir);
     remove_dir_all(&compaction_path);
     create_dir(&sstable_dir);
     remove_file(&wal_path);
+    remove_file(&recovering_wal_path);
+    remove_file(&manifest_path);
+    remove_file(&value_log_path);
+    remove_file(&sealed_marker_path);
 }
@@ -1,10 +1,38 @@
+use crate::common::hooked_backend::HookedBackend;
 use crate::common::test_key::TestKey;
 use crate::common::test_value::TestValue;
 use crate::common::{cleanup_paths, get_test_data, get_test_data_in_range};
-use dharmadb::dharma::Dharma;
+use dharmadb::cursor::Cursor;
+use dharmadb::dharma::{Dharma, ReadOnlyDharma};
+use dharmadb::logger::DbLogger;
+use dharmadb::memtable::Memtable;
+use dharmadb::persistence::Activity;
 use dharmadb::result::{Errors, Result};
-use dharmadb::options::DharmaOpts;
-use dharmadb::storage::write_ahead_log::WriteAheadLog;
+use dharmadb::options::{DharmaOpts, PutOptions};
+use dharmadb::storage::backend::{FsBackend, InMemoryBackend, StorageBackend};
+use dharmadb::stats::SnapshotDescriptor;
+use dharmadb::storage::block::{create_blocks, write_block_to_disk, Value};
+use dharmadb::storage::checksum::compute_table_checksum;
+use dharmadb::storage::codec::{RecordCodec, RegisteredRecordCodec};
+use dharmadb::storage::compaction::{CompactionFilter, RegisteredCompactionFilter};
+use dharmadb::storage::compression::Compression;
+use dharmadb::storage::manifest::Manifest;
+use dharmadb::storage::sorted_string_table_reader::SSTableReader;
+use dharmadb::storage::sorted_string_table_writer::write_table_at_path;
+use dharmadb::storage::transform::ValueTransform;
+use dharmadb::storage::write_ahead_log::{WalFormat, WriteAheadLog};
+use dharmadb::traits::{Nil, ResourceValue};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::io;
+use std::mem::size_of;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+use subway::skiplist::SkipList;
 
 mod common;
 
@@ -183,3 +211,3088 @@ fn test_database_recovery_from_existing_wal() {
         assert_eq!(maybe_value.unwrap(), expected_value);
     }
 }
+
+#[test]
+fn test_estimate_count_in_range() {
+    let options = DharmaOpts::default();
+    cleanup_paths(&options);
+    let data = get_test_data(2000);
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options).unwrap();
+    for (key, value) in data {
+        db.put(key, value);
+    }
+    let flush_result = db.flush();
+    assert!(flush_result.is_ok());
+    let start = TestKey::from(250);
+    let end = TestKey::from(1250);
+    let estimate_result = db.estimate_count_in_range(&start, &end);
+    assert!(estimate_result.is_ok());
+    let estimate = estimate_result.unwrap() as i64;
+    let exact = 1000;
+    // estimate should be within five percent of the exact count
+    assert!((estimate - exact).abs() <= exact / 20);
+}
+
+#[test]
+fn test_stats() {
+    let options = DharmaOpts::default();
+    cleanup_paths(&options);
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options).unwrap();
+    for (key, value) in get_test_data_in_range(0, 500) {
+        db.put(key, value).unwrap();
+    }
+    db.flush().unwrap();
+    for (key, value) in get_test_data_in_range(500, 600) {
+        db.put(key, value).unwrap();
+    }
+    for i in 0..10 {
+        assert!(db.get(&TestKey::from(i)).unwrap().is_some());
+    }
+    let stats = db.stats().unwrap();
+    assert_eq!(stats.memtable_entries, 100);
+    assert_eq!(stats.memtable_size_in_bytes, db.in_memory_size());
+    assert_eq!(stats.sstables, 1);
+    assert!(stats.disk_size_in_bytes > 0);
+    assert!(stats.wal_size_in_bytes > 0);
+    assert!(stats.last_flush.is_some());
+    assert!(stats.last_compaction.is_none());
+    assert!(stats.block_cache_hit_ratio.is_some());
+}
+
+// Memtable backed by a BTreeMap instead of the default SkipList.
+struct BTreeMemtable {
+    data: BTreeMap<TestKey, TestValue>,
+}
+
+impl Memtable<TestKey, TestValue> for BTreeMemtable {
+    fn new() -> Self {
+        BTreeMemtable {
+            data: BTreeMap::new(),
+        }
+    }
+
+    fn insert(&mut self, key: TestKey, value: TestValue) {
+        self.data.insert(key, value);
+    }
+
+    fn get(&mut self, key: &TestKey) -> Option<TestValue> {
+        self.data.get(key).cloned()
+    }
+
+    fn delete(&mut self, key: &TestKey) {
+        self.data.remove(key);
+    }
+
+    fn range(&self, start: &TestKey, end: &TestKey) -> Vec<(TestKey, TestValue)> {
+        self.data
+            .range(start.clone()..end.clone())
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect()
+    }
+
+    fn collect(&self) -> Vec<(TestKey, TestValue)> {
+        self.data
+            .iter()
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect()
+    }
+
+    fn len(&self) -> usize {
+        self.data.len()
+    }
+}
+
+#[test]
+fn test_database_with_alternative_memtable() {
+    let options = DharmaOpts::default();
+    cleanup_paths(&options);
+    let mut db: Dharma<TestKey, TestValue, BTreeMemtable> =
+        Dharma::create_with_memtable(options).unwrap();
+    for (key, value) in get_test_data(100) {
+        assert!(db.put(key, value).is_ok());
+    }
+    assert!(db.delete(TestKey::from(10)).is_ok());
+    let estimate = db
+        .estimate_count_in_range(&TestKey::from(0), &TestKey::from(50))
+        .unwrap();
+    assert_eq!(estimate, 49);
+    assert!(db.flush().is_ok());
+    for (key, value) in get_test_data(100) {
+        let expected = if key == TestKey::from(10) {
+            None
+        } else {
+            Some(value)
+        };
+        assert_eq!(db.get(&key).unwrap(), expected);
+    }
+}
+
+#[test]
+fn test_skiplist_memtable_insert_replaces_value() {
+    let mut memtable: SkipList<TestKey, TestValue> = Memtable::new();
+    Memtable::insert(&mut memtable, TestKey::from(1), TestValue::from("first"));
+    Memtable::insert(&mut memtable, TestKey::from(1), TestValue::from("second"));
+    assert_eq!(Memtable::len(&memtable), 1);
+    assert_eq!(
+        Memtable::get(&mut memtable, &TestKey::from(1)),
+        Some(TestValue::from("second"))
+    );
+}
+
+#[test]
+fn test_retain_range() {
+    let options = DharmaOpts::default();
+    cleanup_paths(&options);
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options.clone()).unwrap();
+    for (start, end) in &[(0, 100), (200, 300)] {
+        for (key, value) in get_test_data_in_range(*start, *end) {
+            assert!(db.put(key, value).is_ok());
+        }
+        assert!(db.flush().is_ok());
+    }
+    for (key, value) in get_test_data_in_range(100, 150) {
+        assert!(db.put(key, value).is_ok());
+    }
+    assert!(db
+        .retain_range(&TestKey::from(50), &TestKey::from(120))
+        .is_ok());
+    // the table holding keys 200 to 300 is removed entirely
+    let paths = SSTableReader::get_valid_table_paths(&options.path).unwrap();
+    assert_eq!(paths.len(), 2);
+    let mut persisted = Vec::new();
+    for path in &paths {
+        let mut reader = SSTableReader::from(path, options.block_size_in_bytes).unwrap();
+        while reader.has_next() {
+            let record: Value<TestKey, TestValue> = reader.read().to_record().unwrap();
+            persisted.push((record.key, record.value));
+            reader.next();
+        }
+    }
+    assert_eq!(persisted, get_test_data_in_range(50, 121));
+    for (key, value) in get_test_data(300) {
+        let expected = if key >= TestKey::from(50) && key <= TestKey::from(120) {
+            Some(value)
+        } else {
+            None
+        };
+        assert_eq!(db.get(&key).unwrap(), expected);
+    }
+}
+
+#[test]
+fn test_flush_range() {
+    let options = DharmaOpts::default();
+    cleanup_paths(&options);
+    let data = get_test_data(500);
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options.clone()).unwrap();
+    for (key, value) in data {
+        db.put(key, value);
+    }
+    let size_before_flush = db.in_memory_size();
+    let flush_result = db.flush_range(&TestKey::from(100), &TestKey::from(200));
+    assert!(flush_result.is_ok());
+    assert_eq!(db.in_memory_size(), size_before_flush / 5 * 4);
+    // only keys in the flushed range are written to disk
+    let paths = SSTableReader::get_valid_table_paths(&options.path).unwrap();
+    assert_eq!(paths.len(), 1);
+    let mut reader = SSTableReader::from(&paths[0], options.block_size_in_bytes).unwrap();
+    let mut persisted = Vec::new();
+    while reader.has_next() {
+        let record: Value<TestKey, TestValue> = reader.read().to_record().unwrap();
+        persisted.push((record.key, record.value));
+        reader.next();
+    }
+    assert_eq!(persisted, get_test_data_in_range(100, 200));
+    // all keys remain readable
+    for (key, value) in get_test_data(500) {
+        let get_result = db.get(&key);
+        assert!(get_result.is_ok());
+        assert_eq!(get_result.unwrap(), Some(value));
+    }
+}
+
+#[test]
+fn test_failed_flush_range_keeps_values_in_memory() {
+    let fail_tables = Arc::new(AtomicBool::new(false));
+    let fail = fail_tables.clone();
+    let backend = HookedBackend::new(InMemoryBackend::new()).on_create(move |path: &Path| {
+        if fail.load(Ordering::SeqCst) && path.to_string_lossy().contains("tables") {
+            return Err(io::Error::new(io::ErrorKind::Other, "injected failure"));
+        }
+        Ok(())
+    });
+    let mut options = DharmaOpts::default();
+    options.backend = Arc::new(backend);
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options).unwrap();
+    for (key, value) in get_test_data(500) {
+        db.put(key, value).unwrap();
+    }
+    let size_before_flush = db.in_memory_size();
+    fail_tables.store(true, Ordering::SeqCst);
+    assert!(db
+        .flush_range(&TestKey::from(100), &TestKey::from(200))
+        .is_err());
+    assert_eq!(db.in_memory_size(), size_before_flush);
+    for (key, value) in get_test_data(500) {
+        assert_eq!(db.get(&key).unwrap(), Some(value));
+    }
+    // the range is flushed once tables can be created again
+    fail_tables.store(false, Ordering::SeqCst);
+    db.flush_range(&TestKey::from(100), &TestKey::from(200))
+        .unwrap();
+    assert_eq!(db.in_memory_size(), size_before_flush / 5 * 4);
+    for (key, value) in get_test_data(500) {
+        assert_eq!(db.get(&key).unwrap(), Some(value));
+    }
+}
+
+#[test]
+fn test_warm_range_populates_block_cache() {
+    let options = DharmaOpts::default();
+    cleanup_paths(&options);
+    let data = get_test_data(1000);
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options).unwrap();
+    for (key, value) in data {
+        db.put(key, value);
+    }
+    assert!(db.flush().is_ok());
+    let warm_result = db.warm_range(&TestKey::from(200), &TestKey::from(800));
+    assert!(warm_result.is_ok());
+    assert!(warm_result.unwrap() > 0);
+    let stats_after_warm = db.block_cache_stats();
+    for (key, value) in get_test_data_in_range(200, 800) {
+        assert_eq!(db.get(&key).unwrap(), Some(value));
+    }
+    // all reads within the warmed range are served from the cache
+    let stats_after_reads = db.block_cache_stats();
+    assert_eq!(stats_after_reads.misses, stats_after_warm.misses);
+    assert!(stats_after_reads.hits > stats_after_warm.hits);
+}
+
+#[test]
+fn test_value_cache_serves_repeated_reads() {
+    let mut options = DharmaOpts::default();
+    options.value_cache_entries = 16;
+    cleanup_paths(&options);
+    let data = get_test_data(100);
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options).unwrap();
+    for (key, value) in data {
+        db.put(key, value);
+    }
+    assert!(db.flush().is_ok());
+    let key = TestKey::from(42);
+    assert_eq!(db.get(&key).unwrap(), Some(TestValue::from("value is 42")));
+    let block_stats = db.block_cache_stats();
+    let value_stats = db.value_cache_stats();
+    assert_eq!(value_stats.entries, 1);
+    // the second read skips reading blocks and deserializing the record
+    assert_eq!(db.get(&key).unwrap(), Some(TestValue::from("value is 42")));
+    assert_eq!(db.block_cache_stats(), block_stats);
+    assert_eq!(db.value_cache_stats().hits, value_stats.hits + 1);
+    // writes to the key invalidate the cached value
+    assert!(db.put(key.clone(), TestValue::from("updated")).is_ok());
+    assert_eq!(db.value_cache_stats().entries, 0);
+    assert!(db.flush().is_ok());
+    assert_eq!(db.get(&key).unwrap(), Some(TestValue::from("updated")));
+    assert!(db.delete(key.clone()).is_ok());
+    assert!(db.flush().is_ok());
+    assert_eq!(db.get(&key).unwrap(), None);
+}
+
+#[test]
+fn test_value_cache_evicts_least_recently_used_value() {
+    let mut options = DharmaOpts::default();
+    options.backend = Arc::new(InMemoryBackend::new());
+    options.value_cache_entries = 2;
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options).unwrap();
+    for (key, value) in get_test_data(10) {
+        db.put(key, value).unwrap();
+    }
+    db.flush().unwrap();
+    db.get(&TestKey::from(1)).unwrap();
+    db.get(&TestKey::from(2)).unwrap();
+    // reading the first key again makes the second the least recently used
+    db.get(&TestKey::from(1)).unwrap();
+    db.get(&TestKey::from(3)).unwrap();
+    let stats = db.value_cache_stats();
+    assert_eq!(stats.entries, 2);
+    db.get(&TestKey::from(1)).unwrap();
+    assert_eq!(db.value_cache_stats().hits, stats.hits + 1);
+    db.get(&TestKey::from(2)).unwrap();
+    assert_eq!(db.value_cache_stats().misses, stats.misses + 1);
+}
+
+#[test]
+fn test_put_no_flush_rejects_writes_at_threshold() {
+    let mut options = DharmaOpts::default();
+    options.backend = Arc::new(InMemoryBackend::new());
+    let entry_size = size_of::<TestKey>() + size_of::<TestValue>();
+    options.memtable_size_in_bytes = entry_size * 10;
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options).unwrap();
+    let data = get_test_data(20);
+    for (key, value) in data[..9].iter().cloned() {
+        assert!(db.put_no_flush(key, value).is_ok());
+    }
+    let (key, value) = data[9].clone();
+    assert_eq!(
+        db.put_no_flush(key.clone(), value.clone()),
+        Err(Errors::DB_MEMTABLE_FULL)
+    );
+    // nothing was flushed and the rejected value was not written
+    assert_eq!(db.in_memory_size(), entry_size * 9);
+    assert!(db.table_layout().unwrap().is_empty());
+    assert_eq!(db.get(&key).unwrap(), None);
+    assert!(db.flush().is_ok());
+    assert_eq!(db.in_memory_size(), 0);
+    for (key, value) in data[9..].iter().cloned().take(9) {
+        assert!(db.put_no_flush(key, value).is_ok());
+    }
+    for (key, value) in data[..18].iter().cloned() {
+        assert_eq!(db.get(&key).unwrap(), Some(value));
+    }
+}
+
+#[test]
+fn test_scan_persisted_omits_unflushed_values() {
+    let mut options = DharmaOpts::default();
+    options.backend = Arc::new(InMemoryBackend::new());
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options).unwrap();
+    for (key, value) in get_test_data(100) {
+        assert!(db.put(key, value).is_ok());
+    }
+    assert!(db.delete(TestKey::from(10)).is_ok());
+    assert!(db.flush().is_ok());
+    for (key, value) in get_test_data_in_range(50, 150) {
+        assert!(db
+            .put(
+                key,
+                TestValue::from(format!("unflushed {}", value).as_str())
+            )
+            .is_ok());
+    }
+    assert!(db.delete(TestKey::from(20)).is_ok());
+    let scanned: Vec<(TestKey, TestValue)> = db
+        .scan_persisted()
+        .unwrap()
+        .map(|entry| entry.unwrap())
+        .collect();
+    let expected: Vec<(TestKey, TestValue)> = get_test_data(100)
+        .into_iter()
+        .filter(|(key, _)| key != &TestKey::from(10))
+        .collect();
+    assert_eq!(scanned, expected);
+}
+
+#[test]
+fn test_resample_index() {
+    let mut options = DharmaOpts::default();
+    options.backend = Arc::new(InMemoryBackend::new());
+    options.sparse_index_sampling_rate = 100;
+    let data = get_test_data(1000);
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options).unwrap();
+    for (key, value) in data.clone() {
+        assert!(db.put(key, value).is_ok());
+    }
+    assert!(db.flush().is_ok());
+    assert_eq!(db.stats().unwrap().index_entries, 10);
+    assert!(db.resample_index(10).is_ok());
+    assert_eq!(db.stats().unwrap().index_entries, 100);
+    assert_eq!(db.resample_index(0), Err(Errors::DB_INVALID_SAMPLING_RATE));
+    assert_eq!(db.stats().unwrap().index_entries, 100);
+    for (key, value) in data {
+        assert_eq!(db.get(&key).unwrap(), Some(value));
+    }
+}
+
+#[test]
+fn test_get_version() {
+    let mut options = DharmaOpts::default();
+    options.versions_to_keep = 3;
+    cleanup_paths(&options);
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options).unwrap();
+    let key = TestKey::from(7);
+    let mut sequences = Vec::new();
+    for version in &["first", "second", "third"] {
+        assert!(db.put(key.clone(), TestValue::from(version)).is_ok());
+        sequences.push(db.last_sequence());
+    }
+    let read_versions = |db: &mut Dharma<TestKey, TestValue>| {
+        for (seq, version) in sequences.iter().zip(&["first", "second", "third"]) {
+            let value = db.get_version(&key, *seq).unwrap();
+            assert_eq!(value, Some(TestValue::from(version)));
+        }
+        assert_eq!(db.get_version(&key, sequences[0] - 1).unwrap(), None);
+    };
+    read_versions(&mut db);
+    // versions are retained once persisted
+    assert!(db.flush().is_ok());
+    read_versions(&mut db);
+    assert_eq!(db.get(&key).unwrap(), Some(TestValue::from("third")));
+}
+
+#[test]
+fn test_last_sequence_survives_reopen() {
+    let mut options = DharmaOpts::default();
+    options.backend = Arc::new(InMemoryBackend::new());
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options.clone()).unwrap();
+    let mut previous = db.last_sequence();
+    for (key, value) in get_test_data(10) {
+        assert!(db.put(key.clone(), value).is_ok());
+        assert!(db.last_sequence() > previous);
+        previous = db.last_sequence();
+        assert!(db.delete(key).is_ok());
+        assert!(db.last_sequence() > previous);
+        previous = db.last_sequence();
+    }
+    assert!(db.put(TestKey::from(5), TestValue::from("value")).is_ok());
+    let latest = db.last_sequence();
+    // the tables no longer hold the latest writes once the other keys are discarded
+    assert!(db
+        .retain_range(&TestKey::from(0), &TestKey::from(3))
+        .is_ok());
+    assert!(db.close().is_ok());
+
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options).unwrap();
+    assert_eq!(db.last_sequence(), latest);
+    assert!(db.put(TestKey::from(1), TestValue::from("value")).is_ok());
+    assert_eq!(db.last_sequence(), latest + 1);
+}
+
+#[test]
+fn test_compaction_retains_configured_versions() {
+    let mut options = DharmaOpts::default();
+    options.versions_to_keep = 2;
+    cleanup_paths(&options);
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options).unwrap();
+    let key = TestKey::from(3);
+    let mut sequences = Vec::new();
+    // each flush writes a new table so that the tables are compacted
+    for version in &["first", "second", "third", "fourth"] {
+        assert!(db.put(key.clone(), TestValue::from(version)).is_ok());
+        sequences.push(db.last_sequence());
+        assert!(db.flush().is_ok());
+    }
+    let paths = SSTableReader::get_valid_table_paths(&DharmaOpts::default().path).unwrap();
+    assert_eq!(paths.len(), 1);
+    assert_eq!(db.get_version(&key, sequences[1]).unwrap(), None);
+    assert_eq!(
+        db.get_version(&key, sequences[2]).unwrap(),
+        Some(TestValue::from("third"))
+    );
+    assert_eq!(db.get(&key).unwrap(), Some(TestValue::from("fourth")));
+}
+
+// Backend that records the database activity whenever a table is created.
+fn observing_backend(
+    activity: Arc<Mutex<Option<Activity>>>,
+    observed: Arc<Mutex<Vec<(bool, bool)>>>,
+) -> HookedBackend {
+    HookedBackend::new(InMemoryBackend::new()).on_create(move |path: &Path| {
+        if let Some(activity) = activity.lock().unwrap().as_ref() {
+            // observe tables along with the compaction output written next to them
+            if path.parent().map_or(false, |dir| dir.ends_with("tables")) {
+                let observation = (activity.is_flushing(), activity.is_compacting());
+                observed.lock().unwrap().push(observation);
+            }
+        }
+        Ok(())
+    })
+}
+
+#[test]
+fn test_flush_and_compaction_activity() {
+    let activity = Arc::new(Mutex::new(None));
+    let observed = Arc::new(Mutex::new(Vec::new()));
+    let mut options = DharmaOpts::default();
+    options.backend = Arc::new(observing_backend(activity.clone(), observed.clone()));
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options).unwrap();
+    *activity.lock().unwrap() = Some(db.activity());
+    assert!(!db.is_flushing());
+    assert!(!db.is_compacting());
+    // four flushed tables trigger a compaction
+    for (key, value) in get_test_data(4) {
+        assert!(db.put(key, value).is_ok());
+        assert!(db.flush().is_ok());
+    }
+    assert!(!db.is_flushing());
+    assert!(!db.is_compacting());
+    // four tables are written by flushes and the last flush compacts them
+    let observed = observed.lock().unwrap().clone();
+    assert!(observed.len() > 4);
+    assert!(observed[..4].iter().all(|&state| state == (true, false)));
+    assert!(observed[4..].iter().all(|&state| state == (true, true)));
+}
+
+#[test]
+fn test_flush_of_empty_memtable_creates_no_table() {
+    let mut options = DharmaOpts::default();
+    options.backend = Arc::new(InMemoryBackend::new());
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options.clone()).unwrap();
+    assert!(db.flush().is_ok());
+    let paths =
+        SSTableReader::get_valid_table_paths_with_backend(options.backend.as_ref(), &options.path)
+            .unwrap();
+    assert!(paths.is_empty());
+    assert_eq!(db.get(&TestKey::from(0)).unwrap(), None);
+}
+
+#[test]
+fn test_close_flushes_once() {
+    let activity = Arc::new(Mutex::new(None));
+    let observed = Arc::new(Mutex::new(Vec::new()));
+    let mut options = DharmaOpts::default();
+    options.backend = Arc::new(observing_backend(activity.clone(), observed.clone()));
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options.clone()).unwrap();
+    *activity.lock().unwrap() = Some(db.activity());
+    for (key, value) in get_test_data(100) {
+        assert!(db.put(key, value).is_ok());
+    }
+    // the database is dropped by close without flushing again
+    assert!(db.close().is_ok());
+    assert_eq!(observed.lock().unwrap().len(), 1);
+    let paths =
+        SSTableReader::get_valid_table_paths_with_backend(options.backend.as_ref(), &options.path)
+            .unwrap();
+    assert_eq!(paths.len(), 1);
+}
+
+#[test]
+fn test_drop_without_flush() {
+    let activity = Arc::new(Mutex::new(None));
+    let observed = Arc::new(Mutex::new(Vec::new()));
+    let mut options = DharmaOpts::default();
+    options.flush_on_drop = false;
+    options.backend = Arc::new(observing_backend(activity.clone(), observed.clone()));
+    let data = get_test_data(100);
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options.clone()).unwrap();
+    *activity.lock().unwrap() = Some(db.activity());
+    for (key, value) in data.clone() {
+        assert!(db.put(key, value).is_ok());
+    }
+    drop(db);
+    assert!(observed.lock().unwrap().is_empty());
+    // values that were not flushed are recovered from the Write Ahead Log
+    let mut db = Dharma::<TestKey, TestValue>::recover::<TestKey, TestValue>(options).unwrap();
+    for (key, value) in data {
+        assert_eq!(db.get(&key).unwrap(), Some(value));
+    }
+}
+
+#[test]
+fn test_recovery_replays_deletes() {
+    for wal_format in &[WalFormat::Blocks, WalFormat::Framed] {
+        let mut options = DharmaOpts::default();
+        options.flush_on_drop = false;
+        options.wal_format = *wal_format;
+        options.backend = Arc::new(InMemoryBackend::new());
+        let mut db: Dharma<TestKey, TestValue> = Dharma::create(options.clone()).unwrap();
+        db.put(TestKey::from(1), TestValue::from("flushed"))
+            .unwrap();
+        db.flush().unwrap();
+        db.delete(TestKey::from(1)).unwrap();
+        db.put(TestKey::from(2), TestValue::from("logged")).unwrap();
+        db.delete(TestKey::from(2)).unwrap();
+        db.put(TestKey::from(3), TestValue::from("logged")).unwrap();
+        // crash before the deletes are flushed
+        drop(db);
+        let mut db = Dharma::<TestKey, TestValue>::recover::<TestKey, TestValue>(options).unwrap();
+        assert_eq!(db.get(&TestKey::from(1)).unwrap(), None);
+        assert_eq!(db.get(&TestKey::from(2)).unwrap(), None);
+        assert_eq!(
+            db.get(&TestKey::from(3)).unwrap(),
+            Some(TestValue::from("logged"))
+        );
+    }
+}
+
+#[test]
+fn test_auto_recover_on_open() {
+    let mut options = DharmaOpts::default();
+    options.flush_on_drop = false;
+    options.backend = Arc::new(InMemoryBackend::new());
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options.clone()).unwrap();
+    for (key, value) in get_test_data(20) {
+        db.put(key, value).unwrap();
+    }
+    db.delete(TestKey::from(5)).unwrap();
+    // crash leaving the values in the Write Ahead Log
+    drop(db);
+    assert!(matches!(
+        Dharma::<TestKey, TestValue>::create(options.clone()),
+        Err(Errors::DB_PATH_DIRTY)
+    ));
+
+    options.auto_recover = true;
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options).unwrap();
+    for (key, value) in get_test_data(20) {
+        let expected = if key == TestKey::from(5) {
+            None
+        } else {
+            Some(value)
+        };
+        assert_eq!(db.get(&key).unwrap(), expected);
+    }
+}
+
+#[test]
+fn test_auto_recover_resumes_interrupted_recovery() {
+    let mut options = DharmaOpts::default();
+    options.flush_on_drop = false;
+    let backend = InMemoryBackend::new();
+    options.backend = Arc::new(backend.clone());
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options.clone()).unwrap();
+    for (key, value) in get_test_data(20) {
+        db.put(key, value).unwrap();
+    }
+    drop(db);
+    // crash while replaying the log after it was set aside, the new log holds
+    // a part of the replayed operations
+    let operations = WriteAheadLog::begin_recovery::<TestKey, TestValue>(options.clone()).unwrap();
+    assert_eq!(operations.len(), 20);
+    let wal_path = PathBuf::from(format!("{}/wal.log", options.path));
+    assert!(!backend.exists(&wal_path));
+    let wal = WriteAheadLog::create(options.clone()).unwrap();
+    wal.append_versioned(TestKey::from(1), TestValue::from("1"), 1)
+        .unwrap();
+    drop(wal);
+
+    options.auto_recover = true;
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options.clone()).unwrap();
+    for (key, value) in get_test_data(20) {
+        assert_eq!(db.get(&key).unwrap(), Some(value));
+    }
+    let recovering_path = PathBuf::from(format!("{}/wal.log.recovering", options.path));
+    assert!(!backend.exists(&recovering_path));
+    // the replayed values are held by the new log
+    let logged = WriteAheadLog::read_values::<TestKey, TestValue>(options).unwrap();
+    assert_eq!(logged.len(), 20);
+}
+
+struct CapturingLogger {
+    messages: Mutex<Vec<(&'static str, String)>>,
+}
+
+impl DbLogger for CapturingLogger {
+    fn info(&self, message: &str) {
+        self.messages
+            .lock()
+            .unwrap()
+            .push(("info", message.to_string()));
+    }
+
+    fn warn(&self, message: &str) {
+        self.messages
+            .lock()
+            .unwrap()
+            .push(("warn", message.to_string()));
+    }
+
+    fn error(&self, message: &str) {
+        self.messages
+            .lock()
+            .unwrap()
+            .push(("error", message.to_string()));
+    }
+}
+
+#[test]
+fn test_logger_receives_lifecycle_events() {
+    let logger = Arc::new(CapturingLogger {
+        messages: Mutex::new(Vec::new()),
+    });
+    let mut options = DharmaOpts::default();
+    options.flush_on_drop = false;
+    options.auto_recover = true;
+    options.backend = Arc::new(InMemoryBackend::new());
+    options.logger = Some(logger.clone());
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options.clone()).unwrap();
+    // overlapping tables pass the threshold of four tables and are compacted
+    for (key, value) in get_test_data_in_range(1, 6) {
+        db.put(TestKey::from(0), value.clone()).unwrap();
+        db.put(key, value).unwrap();
+        db.flush().unwrap();
+    }
+    db.put(TestKey::from(10), TestValue::from("logged"))
+        .unwrap();
+    // crash leaving the value in the Write Ahead Log
+    drop(db);
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options).unwrap();
+    assert_eq!(
+        db.get(&TestKey::from(10)).unwrap(),
+        Some(TestValue::from("logged"))
+    );
+
+    let messages = logger.messages.lock().unwrap();
+    let logged = |level: &str, message: &str| {
+        messages.iter().any(|(logged_level, logged_message)| {
+            *logged_level == level && logged_message.starts_with(message)
+        })
+    };
+    assert!(logged("info", "Flushed 2 values to 1 tables"));
+    assert!(logged("info", "Compacted"));
+    assert!(logged("warn", "Write Ahead Log found"));
+    assert!(logged(
+        "info",
+        "Recovered 1 operations from the Write Ahead Log"
+    ));
+    assert!(!messages.iter().any(|(level, _)| *level == "error"));
+}
+
+#[test]
+fn test_compaction_reports_duplicate_values() {
+    let mut options = DharmaOpts::default();
+    options.backend = Arc::new(InMemoryBackend::new());
+    options.compaction_report_duplicates = true;
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options).unwrap();
+    db.pause_compaction();
+    let shared = TestValue::from("shared value");
+    // every flush rewrites the same values
+    for _ in 0..4 {
+        for (key, value) in get_test_data(50) {
+            db.put(key, value).unwrap();
+        }
+        for key in 100..110 {
+            db.put(TestKey::from(key), shared.clone()).unwrap();
+        }
+        db.flush().unwrap();
+    }
+    let size_before = db.stats().unwrap().disk_size_in_bytes;
+    assert!(db.last_deduplication_report().is_none());
+
+    db.resume_compaction().unwrap();
+    assert_eq!(db.table_layout().unwrap().len(), 1);
+    assert!(db.stats().unwrap().disk_size_in_bytes < size_before);
+    let report = db.last_deduplication_report().unwrap();
+    assert_eq!(report.duplicate_versions, 3 * 60);
+    assert!(report.bytes_reclaimed > 0);
+    assert_eq!(report.identical_value_runs, 1);
+    assert_eq!(report.keys_in_identical_runs, 10);
+    assert!(report.repeated_value_bytes > 0);
+    for (key, value) in get_test_data(50) {
+        assert_eq!(db.get(&key).unwrap(), Some(value));
+    }
+    for key in 100..110 {
+        assert_eq!(db.get(&TestKey::from(key)).unwrap(), Some(shared.clone()));
+    }
+}
+
+#[test]
+fn test_compaction_retains_deletion_audit() {
+    let mut options = DharmaOpts::default();
+    options.backend = Arc::new(InMemoryBackend::new());
+    options.retain_deletion_audit = true;
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options).unwrap();
+    db.pause_compaction();
+    let data = get_test_data(50);
+    for (key, value) in &data {
+        db.put(key.clone(), value.clone()).unwrap();
+    }
+    db.flush().unwrap();
+    let deleted_keys: Vec<TestKey> = data.iter().take(10).map(|(key, _)| key.clone()).collect();
+    let before = SystemTime::now();
+    for key in &deleted_keys {
+        db.delete(key.clone()).unwrap();
+    }
+    let after = SystemTime::now();
+    db.flush().unwrap();
+    // overlapping tables are compacted together once there are four of them
+    for (key, value) in get_test_data_in_range(40, 42) {
+        db.put(key, value).unwrap();
+        db.flush().unwrap();
+    }
+    assert_eq!(db.deletion_log().unwrap().len(), deleted_keys.len());
+
+    db.resume_compaction().unwrap();
+    assert_eq!(db.table_layout().unwrap().len(), 1);
+    // deletions are listed with the time they were made after compaction drops them
+    let deletion_log = db.deletion_log().unwrap();
+    assert_eq!(
+        deletion_log
+            .iter()
+            .map(|(key, _)| key.clone())
+            .collect::<Vec<TestKey>>(),
+        deleted_keys
+    );
+    for (_, deleted_at) in &deletion_log {
+        assert!(*deleted_at >= before && *deleted_at <= after);
+    }
+    for key in &deleted_keys {
+        assert_eq!(db.get(key).unwrap(), None);
+    }
+    for (key, value) in data.into_iter().skip(10) {
+        assert_eq!(db.get(&key).unwrap(), Some(value));
+    }
+}
+
+#[test]
+fn test_deletion_audit_lists_keys_of_deleted_ranges() {
+    let mut options = DharmaOpts::default();
+    options.backend = Arc::new(InMemoryBackend::new());
+    options.retain_deletion_audit = true;
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options).unwrap();
+    for (key, value) in get_test_data_in_range(0, 10) {
+        db.put(key, value).unwrap();
+    }
+    db.flush().unwrap();
+    db.delete(TestKey::from(4)).unwrap();
+    db.delete_range(&TestKey::from(2), &TestKey::from(6))
+        .unwrap();
+    let deleted_keys: Vec<TestKey> = db
+        .deletion_log()
+        .unwrap()
+        .into_iter()
+        .map(|(key, _)| key)
+        .collect();
+    // keys deleted before the range was deleted are not listed again
+    assert_eq!(
+        deleted_keys,
+        vec![
+            TestKey::from(4),
+            TestKey::from(2),
+            TestKey::from(3),
+            TestKey::from(5)
+        ]
+    );
+}
+
+#[test]
+fn test_deletion_audit_skips_deletions_that_failed() {
+    let fail_log_writes = Arc::new(AtomicBool::new(false));
+    let fail = fail_log_writes.clone();
+    let backend = HookedBackend::new(InMemoryBackend::new()).on_write(move |path: &Path| {
+        if fail.load(Ordering::SeqCst) && path.ends_with("wal.log") {
+            return Err(io::Error::new(io::ErrorKind::Other, "injected failure"));
+        }
+        Ok(())
+    });
+    let mut options = DharmaOpts::default();
+    options.backend = Arc::new(backend);
+    options.retain_deletion_audit = true;
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options).unwrap();
+    for (key, value) in get_test_data_in_range(0, 10) {
+        db.put(key, value).unwrap();
+    }
+    fail_log_writes.store(true, Ordering::SeqCst);
+    assert!(db.delete(TestKey::from(1)).is_err());
+    assert!(db
+        .delete_range(&TestKey::from(2), &TestKey::from(6))
+        .is_err());
+    assert!(db.deletion_log().unwrap().is_empty());
+    fail_log_writes.store(false, Ordering::SeqCst);
+    db.delete(TestKey::from(1)).unwrap();
+    let deleted_keys: Vec<TestKey> = db
+        .deletion_log()
+        .unwrap()
+        .into_iter()
+        .map(|(key, _)| key)
+        .collect();
+    assert_eq!(deleted_keys, vec![TestKey::from(1)]);
+}
+
+#[test]
+fn test_deletion_audit_appends_after_torn_record() {
+    let mut options = DharmaOpts::default();
+    options.flush_on_drop = false;
+    options.backend = Arc::new(InMemoryBackend::new());
+    options.retain_deletion_audit = true;
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options.clone()).unwrap();
+    db.delete(TestKey::from(1)).unwrap();
+    db.close().unwrap();
+    // crash while appending the next record
+    let audit_path = PathBuf::from(format!("{}/DELETIONS", options.path));
+    let mut file = options.backend.open(&audit_path).unwrap();
+    file.write(&[40, 0, 0, 0, 7, 7]).unwrap();
+
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options.clone()).unwrap();
+    db.delete(TestKey::from(2)).unwrap();
+    let deleted_keys: Vec<TestKey> = db
+        .deletion_log()
+        .unwrap()
+        .into_iter()
+        .map(|(key, _)| key)
+        .collect();
+    assert_eq!(deleted_keys, vec![TestKey::from(1), TestKey::from(2)]);
+}
+
+#[test]
+fn test_paused_compaction_catches_up_on_resume() {
+    let mut options = DharmaOpts::default();
+    options.backend = Arc::new(InMemoryBackend::new());
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options).unwrap();
+    db.pause_compaction();
+    assert!(db.is_compaction_paused());
+    // six overlapping tables pass the threshold of four tables
+    for (key, value) in get_test_data_in_range(1, 7) {
+        db.put(TestKey::from(0), value.clone()).unwrap();
+        db.put(key, value).unwrap();
+        db.flush().unwrap();
+    }
+    assert_eq!(db.table_layout().unwrap().len(), 6);
+    assert!(db.stats().unwrap().last_compaction.is_none());
+
+    db.resume_compaction().unwrap();
+    assert!(!db.is_compaction_paused());
+    assert_eq!(db.table_layout().unwrap().len(), 1);
+    assert!(db.stats().unwrap().last_compaction.is_some());
+    assert_eq!(
+        db.get(&TestKey::from(0)).unwrap(),
+        Some(TestValue::from("value is 6"))
+    );
+    for (key, value) in get_test_data_in_range(1, 7) {
+        assert_eq!(db.get(&key).unwrap(), Some(value));
+    }
+}
+
+#[test]
+fn test_compaction_inputs_are_retained_until_purged() {
+    let backend = InMemoryBackend::new();
+    let mut options = DharmaOpts::default();
+    options.compaction_retain_inputs = true;
+    options.backend = Arc::new(backend.clone());
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options.clone()).unwrap();
+    // four flushed tables with overlapping keys trigger a compaction
+    for (key, value) in get_test_data_in_range(1, 5) {
+        assert!(db.put(TestKey::from(0), value.clone()).is_ok());
+        assert!(db.put(key, value).is_ok());
+        assert!(db.flush().is_ok());
+    }
+    let trash_path = PathBuf::from(format!("{}/tables/trash", options.path));
+    assert_eq!(backend.list_dir(&trash_path).unwrap().len(), 4);
+    for (key, value) in get_test_data_in_range(1, 5) {
+        assert_eq!(db.get(&key).unwrap(), Some(value));
+    }
+    // recently trashed tables are kept
+    assert_eq!(db.purge_trash(Duration::from_secs(3600)).unwrap(), 0);
+    assert_eq!(backend.list_dir(&trash_path).unwrap().len(), 4);
+    assert_eq!(db.purge_trash(Duration::from_secs(0)).unwrap(), 4);
+    assert!(backend.list_dir(&trash_path).unwrap().is_empty());
+}
+
+// Overwrite the length of the value stored in the record of the key so that the
+// record no longer deserializes while the table remains readable.
+fn corrupt_record(backend: &InMemoryBackend, path: &PathBuf, options: &DharmaOpts, key: u32) {
+    let mut reader =
+        SSTableReader::with_backend(backend, path, options.block_size_in_bytes).unwrap();
+    let (offset, _) = reader.offsets().nth(key as usize).unwrap().unwrap();
+    let mut file = backend.open(path).unwrap();
+    let mut data = vec![0u8; file.size().unwrap() as usize];
+    file.read_at(&mut data, 0).unwrap();
+    // the record header is followed by the key and the length of the value
+    data[offset + 3 + size_of::<u32>() + size_of::<u64>() - 1] = 0xFF;
+    backend.create(path).unwrap().write(&data).unwrap();
+}
+
+fn database_with_corrupt_record(options: &mut DharmaOpts) -> Dharma<TestKey, TestValue> {
+    let backend = InMemoryBackend::new();
+    options.backend = Arc::new(backend.clone());
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options.clone()).unwrap();
+    for (key, value) in get_test_data(100) {
+        assert!(db.put(key, value).is_ok());
+    }
+    assert!(db.flush().is_ok());
+    let path = PathBuf::from(format!("{}/tables/0.db", options.path));
+    corrupt_record(&backend, &path, options, 5);
+    db
+}
+
+#[test]
+fn test_get_fails_on_corrupt_record() {
+    let mut options = DharmaOpts::default();
+    let mut db = database_with_corrupt_record(&mut options);
+    assert_eq!(
+        db.get(&TestKey::from(2)).unwrap(),
+        Some(TestValue::from("value is 2"))
+    );
+    // lookups that walk past the corrupt record fail
+    assert!(db.get(&TestKey::from(10)).err() == Some(Errors::RECORD_DESERIALIZATION_FAILED));
+}
+
+#[test]
+fn test_get_skips_corrupt_record() {
+    let mut options = DharmaOpts::default();
+    options.skip_corrupt_records = true;
+    let mut db = database_with_corrupt_record(&mut options);
+    assert_eq!(db.get(&TestKey::from(5)).unwrap(), None);
+    assert_eq!(
+        db.get(&TestKey::from(10)).unwrap(),
+        Some(TestValue::from("value is 10"))
+    );
+}
+
+#[test]
+fn test_table_layout() {
+    let mut options = DharmaOpts::default();
+    options.backend = Arc::new(InMemoryBackend::new());
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options.clone()).unwrap();
+    let ranges = [(0, 100), (50, 250), (400, 450)];
+    for (start, end) in ranges {
+        for (key, value) in get_test_data_in_range(start, end) {
+            assert!(db.put(key, value).is_ok());
+        }
+        assert!(db.flush().is_ok());
+    }
+    let layout = db.table_layout().unwrap();
+    assert_eq!(layout.len(), ranges.len());
+    for (table_number, (table, (start, end))) in layout.iter().zip(ranges).enumerate() {
+        let path = PathBuf::from(format!("{}/tables/{}.db", options.path, table_number));
+        assert_eq!(table.path, path);
+        let expected_range = (TestKey::from(start), TestKey::from(end - 1));
+        assert_eq!(table.key_range, Some(expected_range));
+        assert_eq!(table.records, (end - start) as usize);
+        assert!(table.size_in_bytes > 0);
+    }
+}
+
+#[test]
+fn test_get_missing_key() {
+    let options = DharmaOpts::default();
+    cleanup_paths(&options);
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options).unwrap();
+    assert!(db
+        .put(TestKey::from(1), TestValue::from("first value"))
+        .is_ok());
+    assert!(db.delete(TestKey::from(2)).is_ok());
+    // missing and deleted keys resolve to None by default
+    assert_eq!(db.get(&TestKey::from(2)).unwrap(), None);
+    assert_eq!(db.get(&TestKey::from(3)).unwrap(), None);
+    // strict reads treat missing and deleted keys as errors
+    assert_eq!(
+        db.get_strict(&TestKey::from(1)).unwrap(),
+        TestValue::from("first value")
+    );
+    assert!(db.get_strict(&TestKey::from(2)).err() == Some(Errors::DB_NO_SUCH_KEY));
+    assert!(db.get_strict(&TestKey::from(3)).err() == Some(Errors::DB_NO_SUCH_KEY));
+}
+
+#[test]
+fn test_absorb_wal() {
+    let options = DharmaOpts::default();
+    cleanup_paths(&options);
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options.clone()).unwrap();
+    db.put(TestKey::from(1), TestValue::from("in memory"))
+        .unwrap();
+    // append records to the log behind the database
+    let records = vec![
+        Value::with_seq(TestKey::from(0), TestValue::from("older"), 5),
+        Value::with_seq(TestKey::from(0), TestValue::from("newer"), 7),
+        Value::with_seq(TestKey::from(1), TestValue::from("stale"), 0),
+        Value::with_seq(TestKey::from(2), TestValue::from("logged"), 6),
+    ];
+    let wal_path = PathBuf::from(format!("{}/wal.log", options.path));
+    let mut wal = options.backend.open(&wal_path).unwrap();
+    let mut blocks = Vec::new();
+    create_blocks(&options, &records, &mut blocks).unwrap();
+    for block in &blocks {
+        write_block_to_disk(&options, wal.as_mut(), block).unwrap();
+    }
+
+    assert!(db.absorb_wal().is_ok());
+    assert_eq!(
+        db.get(&TestKey::from(0)).unwrap(),
+        Some(TestValue::from("newer"))
+    );
+    assert_eq!(
+        db.get(&TestKey::from(1)).unwrap(),
+        Some(TestValue::from("in memory"))
+    );
+    assert_eq!(
+        db.get(&TestKey::from(2)).unwrap(),
+        Some(TestValue::from("logged"))
+    );
+    assert_eq!(db.last_sequence(), 7);
+    // the log is retained
+    assert!(options.backend.exists(&wal_path));
+}
+
+#[test]
+fn test_delete_range() {
+    let options = DharmaOpts::default();
+    cleanup_paths(&options);
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options).unwrap();
+    for (key, value) in get_test_data(10) {
+        assert!(db.put(key, value).is_ok());
+    }
+    assert!(db
+        .delete_range(&TestKey::from(3), &TestKey::from(7))
+        .is_ok());
+    // a put after the range deletion overrides it
+    assert!(db.put(TestKey::from(5), TestValue::from("newer")).is_ok());
+
+    let assert_range_deleted = |db: &mut Dharma<TestKey, TestValue>| {
+        for (key, value) in get_test_data(10) {
+            let expected = if key == TestKey::from(5) {
+                Some(TestValue::from("newer"))
+            } else if key >= TestKey::from(3) && key < TestKey::from(7) {
+                None
+            } else {
+                Some(value)
+            };
+            assert_eq!(db.get(&key).unwrap(), expected);
+        }
+    };
+    assert_range_deleted(&mut db);
+    assert!(db.flush().is_ok());
+    assert_range_deleted(&mut db);
+}
+
+#[test]
+fn test_delete_range_of_persisted_values() {
+    let options = DharmaOpts::default();
+    cleanup_paths(&options);
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options).unwrap();
+    for (key, value) in get_test_data(10) {
+        assert!(db.put(key, value).is_ok());
+    }
+    assert!(db.flush().is_ok());
+    let before_delete = db.last_sequence();
+    assert!(db
+        .delete_range(&TestKey::from(2), &TestKey::from(8))
+        .is_ok());
+    assert_eq!(db.get(&TestKey::from(4)).unwrap(), None);
+    assert!(db.flush().is_ok());
+    assert_eq!(db.get(&TestKey::from(2)).unwrap(), None);
+    assert_eq!(db.get(&TestKey::from(7)).unwrap(), None);
+    assert_eq!(
+        db.get(&TestKey::from(8)).unwrap(),
+        Some(TestValue::from("value is 8"))
+    );
+    // reads before the range deletion are not affected
+    assert_eq!(
+        db.get_version(&TestKey::from(4), before_delete).unwrap(),
+        Some(TestValue::from("value is 4"))
+    );
+    assert!(db.put(TestKey::from(4), TestValue::from("newer")).is_ok());
+    assert_eq!(
+        db.get(&TestKey::from(4)).unwrap(),
+        Some(TestValue::from("newer"))
+    );
+}
+
+#[test]
+fn test_tables_outside_compaction_remain_readable() {
+    let mut options = DharmaOpts::default();
+    options.compaction_merge_overlapping_only = true;
+    cleanup_paths(&options);
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options.clone()).unwrap();
+    // the third table doesn't overlap the others so it is renumbered instead of merged
+    for (start, end) in &[(100, 110), (100, 110), (0, 10), (100, 110)] {
+        for (key, value) in get_test_data_in_range(*start, *end) {
+            assert!(db.put(key, value).is_ok());
+        }
+        assert!(db.flush().is_ok());
+    }
+    let paths = SSTableReader::get_valid_table_paths(&options.path).unwrap();
+    assert_eq!(paths.len(), 2);
+    for (key, value) in get_test_data_in_range(0, 10)
+        .into_iter()
+        .chain(get_test_data_in_range(100, 110))
+    {
+        assert_eq!(db.get(&key).unwrap(), Some(value));
+    }
+}
+
+#[test]
+fn test_concurrent_compaction_of_disjoint_ranges() {
+    let started = Arc::new(AtomicUsize::new(0));
+    let overlapped = Arc::new(AtomicUsize::new(0));
+    let mut options = DharmaOpts::default();
+    options.compaction_merge_overlapping_only = true;
+    options.max_concurrent_compactions = 2;
+    let (started_outputs, overlapped_outputs) = (started.clone(), overlapped.clone());
+    // hold back each compaction output until a second one is created
+    let backend = HookedBackend::new(InMemoryBackend::new()).on_create(move |path: &Path| {
+        let is_compaction_output = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .map_or(false, |name| name.starts_with("compaction"));
+        if is_compaction_output {
+            started_outputs.fetch_add(1, Ordering::SeqCst);
+            let deadline = Instant::now() + Duration::from_secs(2);
+            while started_outputs.load(Ordering::SeqCst) < 2 && Instant::now() < deadline {
+                thread::sleep(Duration::from_millis(1));
+            }
+            if started_outputs.load(Ordering::SeqCst) >= 2 {
+                overlapped_outputs.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+        Ok(())
+    });
+    options.backend = Arc::new(backend);
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options.clone()).unwrap();
+    // two pairs of overlapping tables with disjoint ranges trigger two compactions
+    for (start, end) in &[(0, 10), (100, 110), (5, 15), (105, 115)] {
+        for (key, _) in get_test_data_in_range(*start, *end) {
+            let value = TestValue::from(format!("written from {}", start).as_str());
+            assert!(db.put(key, value).is_ok());
+        }
+        assert!(db.flush().is_ok());
+    }
+    // both compactions were running when the other created its table
+    assert_eq!(started.load(Ordering::SeqCst), 2);
+    assert_eq!(overlapped.load(Ordering::SeqCst), 2);
+    let paths =
+        SSTableReader::get_valid_table_paths_with_backend(options.backend.as_ref(), &options.path)
+            .unwrap();
+    assert_eq!(paths.len(), 2);
+    for (start, end, written_from) in &[(0, 5, 0), (5, 15, 5), (100, 105, 100), (105, 115, 105)] {
+        let value = TestValue::from(format!("written from {}", written_from).as_str());
+        for (key, _) in get_test_data_in_range(*start, *end) {
+            assert_eq!(db.get(&key).unwrap(), Some(value.clone()));
+        }
+    }
+}
+
+#[test]
+fn test_flush_splits_large_memtable_into_tables() {
+    let backend = InMemoryBackend::new();
+    let mut options = DharmaOpts::default();
+    options.backend = Arc::new(backend.clone());
+    options.block_size_in_bytes = 1024;
+    options.max_flush_table_size_in_bytes = Some(4096);
+    // the disjoint tables of a flush are never merged again
+    options.compaction_merge_overlapping_only = true;
+    let data = get_test_data(1000);
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options.clone()).unwrap();
+    for (key, value) in data.clone() {
+        assert!(db.put(key, value).is_ok());
+    }
+    assert!(db.flush().is_ok());
+    // the values fill 35 blocks of a single table while each table holds at most 4
+    let layout = db.table_layout().unwrap();
+    assert_eq!(layout.len(), 9);
+    let mut records = 0;
+    for (idx, table) in layout.iter().enumerate() {
+        assert!(table.size_in_bytes <= 4096);
+        // tables hold consecutive disjoint key ranges
+        if idx > 0 {
+            let (_, previous_last) = layout[idx - 1].key_range.clone().unwrap();
+            let (first, _) = table.key_range.clone().unwrap();
+            assert!(previous_last < first);
+        }
+        records += table.records;
+    }
+    assert_eq!(records, data.len());
+    for (key, value) in data.clone() {
+        assert_eq!(db.get(&key).unwrap(), Some(value));
+    }
+    // the index is rebuilt from all tables when the database is opened again
+    drop(db);
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options).unwrap();
+    for (key, value) in data {
+        assert_eq!(db.get(&key).unwrap(), Some(value));
+    }
+}
+
+#[test]
+fn test_compaction_applies_range_tombstones() {
+    let options = DharmaOpts::default();
+    cleanup_paths(&options);
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options.clone()).unwrap();
+    for (key, value) in get_test_data(10) {
+        assert!(db.put(key, value).is_ok());
+    }
+    assert!(db.flush().is_ok());
+    assert!(db
+        .delete_range(&TestKey::from(2), &TestKey::from(8))
+        .is_ok());
+    assert!(db.flush().is_ok());
+    assert!(db.put(TestKey::from(5), TestValue::from("newer")).is_ok());
+    assert!(db.flush().is_ok());
+    assert!(db.put(TestKey::from(9), TestValue::from("newer")).is_ok());
+    // the fourth table triggers compaction of all tables
+    assert!(db.flush().is_ok());
+
+    let paths = SSTableReader::get_valid_table_paths(&options.path).unwrap();
+    assert_eq!(paths.len(), 1);
+    let mut reader = SSTableReader::from(&paths[0], options.block_size_in_bytes).unwrap();
+    let mut keys = Vec::new();
+    while reader.has_next() {
+        let sstable_value = reader.read();
+        // the tombstone is dropped once the values it deletes are dropped
+        assert!(!sstable_value.range_tombstone);
+        let record: Value<TestKey, TestValue> = sstable_value.to_record().unwrap();
+        keys.push(record.key);
+        reader.next();
+    }
+    let expected_keys: Vec<TestKey> = vec![0, 1, 5, 8, 9].into_iter().map(TestKey::from).collect();
+    assert_eq!(keys, expected_keys);
+    assert_eq!(db.get(&TestKey::from(3)).unwrap(), None);
+    assert_eq!(
+        db.get(&TestKey::from(5)).unwrap(),
+        Some(TestValue::from("newer"))
+    );
+}
+
+#[test]
+fn test_key_histogram_reflects_skew() {
+    let mut options = DharmaOpts::default();
+    options.sparse_index_sampling_rate = 10;
+    cleanup_paths(&options);
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options).unwrap();
+    // most keys are packed into a small part of the key space
+    let mut data = get_test_data(900);
+    for i in 1..=100 {
+        data.push((TestKey::from(i * 1000), TestValue::from("sparse")));
+    }
+    for (key, value) in data {
+        assert!(db.put(key, value).is_ok());
+    }
+    assert!(db.flush().is_ok());
+
+    let histogram = db.key_histogram(4).unwrap();
+    assert_eq!(histogram.len(), 4);
+    assert_eq!(histogram[0].0, TestKey::from(0));
+    let total: usize = histogram.iter().map(|(_, count)| count).sum();
+    assert_eq!(total, 1000);
+    // the dense keys hold most of the data so most buckets start within them
+    let dense_buckets = histogram
+        .iter()
+        .filter(|(key, _)| key < &TestKey::from(900))
+        .count();
+    assert_eq!(dense_buckets, 4);
+    assert!(db.key_histogram(0).unwrap().is_empty());
+}
+
+#[test]
+fn test_wal_compaction_interval() {
+    let mut options = DharmaOpts::default();
+    options.wal_compaction_interval_in_writes = Some(10);
+    cleanup_paths(&options);
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options.clone()).unwrap();
+    for i in 0..105 {
+        let value = TestValue::from(format!("version {}", i).as_str());
+        assert!(db.put(TestKey::from(1), value).is_ok());
+    }
+    // only the writes since the last compaction are left in the log
+    let logged = WriteAheadLog::read_values::<TestKey, TestValue>(options.clone()).unwrap();
+    assert_eq!(logged.len(), 6);
+    assert_eq!(logged[5].value, TestValue::from("version 104"));
+}
+
+#[test]
+fn test_repair_rebuilds_manifest() {
+    let options = DharmaOpts::default();
+    cleanup_paths(&options);
+    let data = get_test_data(300);
+    {
+        let mut db: Dharma<TestKey, TestValue> = Dharma::create(options.clone()).unwrap();
+        for (key, value) in data.clone() {
+            assert!(db.put(key, value).is_ok());
+        }
+        assert!(db.flush().is_ok());
+    }
+    // garble the recorded format version so that the database can't be opened
+    let manifest = Manifest::read(&options).unwrap().unwrap();
+    Manifest {
+        format_version: u32::MAX,
+        ..manifest
+    }
+    .write(&options)
+    .unwrap();
+    assert!(matches!(
+        Dharma::<TestKey, TestValue>::create(options.clone()).err(),
+        Some(Errors::UNSUPPORTED_FORMAT_VERSION { .. })
+    ));
+    assert!(Dharma::<TestKey, TestValue>::repair(options.clone()).is_ok());
+    assert_eq!(
+        Manifest::read(&options).unwrap().unwrap().format_version,
+        manifest.format_version
+    );
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options).unwrap();
+    for (key, value) in data {
+        assert_eq!(db.get(&key).unwrap(), Some(value));
+    }
+}
+
+#[test]
+fn test_max_sstables_rejects_flushes() {
+    let mut options = DharmaOpts::default();
+    options.max_sstables = Some(2);
+    cleanup_paths(&options);
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options.clone()).unwrap();
+    // tables with disjoint key ranges are never compacted
+    for (key, value) in get_test_data_in_range(0, 10) {
+        assert!(db.put(key, value).is_ok());
+    }
+    assert!(db.flush().is_ok());
+    for (key, value) in get_test_data_in_range(100, 110) {
+        assert!(db.put(key, value).is_ok());
+    }
+    assert!(db.flush().is_ok());
+    for (key, value) in get_test_data_in_range(200, 210) {
+        assert!(db.put(key, value).is_ok());
+    }
+    assert!(db.flush().err() == Some(Errors::DB_TOO_MANY_TABLES));
+    let paths = SSTableReader::get_valid_table_paths(&options.path).unwrap();
+    assert_eq!(paths.len(), 2);
+    // values that could not be flushed remain readable
+    assert_eq!(
+        db.get(&TestKey::from(205)).unwrap(),
+        Some(TestValue::from("value is 205"))
+    );
+}
+
+#[test]
+fn test_max_sstables_rejects_writes_that_flush() {
+    let mut options = DharmaOpts::default();
+    options.max_sstables = Some(1);
+    // ten entries fill the memtable
+    options.memtable_size_in_bytes = 10 * (size_of::<TestKey>() + size_of::<TestValue>());
+    cleanup_paths(&options);
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options).unwrap();
+    for (key, value) in get_test_data_in_range(0, 10) {
+        assert!(db.put(key, value).is_ok());
+    }
+    for (key, value) in get_test_data_in_range(100, 109) {
+        assert!(db.put(key, value).is_ok());
+    }
+    let put_result = db.put(TestKey::from(109), TestValue::from("value is 109"));
+    assert!(put_result.err() == Some(Errors::DB_TOO_MANY_TABLES));
+    assert_eq!(db.get(&TestKey::from(109)).unwrap(), None);
+}
+
+#[test]
+fn test_create_with_validation_callback() {
+    let options = DharmaOpts::default();
+    cleanup_paths(&options);
+    let sentinel = TestKey::from(0);
+    let check_sentinel = |db: &mut Dharma<TestKey, TestValue>| -> Result<()> {
+        db.get_strict(&TestKey::from(0)).map(|_| ())
+    };
+    let open_result = Dharma::create_with(options.clone(), check_sentinel);
+    assert!(open_result.err() == Some(Errors::DB_NO_SUCH_KEY));
+
+    // an aborted open leaves the path usable
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options.clone()).unwrap();
+    assert!(db.put(sentinel, TestValue::from("sentinel")).is_ok());
+    drop(db);
+    let open_result = Dharma::create_with(options, check_sentinel);
+    assert!(open_result.is_ok());
+}
+
+#[test]
+fn test_first_and_last_key_skip_deleted_keys() {
+    let mut options = DharmaOpts::default();
+    options.backend = Arc::new(InMemoryBackend::new());
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options).unwrap();
+    assert_eq!(db.first_key().unwrap(), None);
+    assert_eq!(db.last_key().unwrap(), None);
+    for (key, value) in get_test_data(100) {
+        assert!(db.put(key, value).is_ok());
+    }
+    assert!(db.flush().is_ok());
+    assert_eq!(db.first_key().unwrap(), Some(TestKey::from(0)));
+    assert_eq!(db.last_key().unwrap(), Some(TestKey::from(99)));
+    // deletions held in memory hide the boundary keys persisted on disk
+    assert!(db.delete(TestKey::from(0)).is_ok());
+    assert!(db.delete(TestKey::from(99)).is_ok());
+    assert_eq!(db.first_key().unwrap(), Some(TestKey::from(1)));
+    assert_eq!(db.last_key().unwrap(), Some(TestKey::from(98)));
+    assert!(db.flush().is_ok());
+    assert_eq!(db.first_key().unwrap(), Some(TestKey::from(1)));
+    assert_eq!(db.last_key().unwrap(), Some(TestKey::from(98)));
+    assert!(db
+        .delete_range(&TestKey::from(0), &TestKey::from(5))
+        .is_ok());
+    assert!(db.put(TestKey::from(150), TestValue::from("value")).is_ok());
+    assert_eq!(db.first_key().unwrap(), Some(TestKey::from(5)));
+    assert_eq!(db.last_key().unwrap(), Some(TestKey::from(150)));
+}
+
+#[test]
+fn test_last_key_skips_tables_whose_keys_are_deleted() {
+    let mut options = DharmaOpts::default();
+    options.backend = Arc::new(InMemoryBackend::new());
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options).unwrap();
+    for (start, end) in [(0, 50), (100, 150), (200, 210)] {
+        for (key, value) in get_test_data_in_range(start, end) {
+            assert!(db.put(key, value).is_ok());
+        }
+        assert!(db.flush().is_ok());
+    }
+    assert!(db
+        .delete_range(&TestKey::from(100), &TestKey::from(300))
+        .is_ok());
+    assert_eq!(db.last_key().unwrap(), Some(TestKey::from(49)));
+    assert!(db.flush().is_ok());
+    assert_eq!(db.last_key().unwrap(), Some(TestKey::from(49)));
+    assert!(db
+        .delete_range(&TestKey::from(0), &TestKey::from(300))
+        .is_ok());
+    assert_eq!(db.first_key().unwrap(), None);
+    assert_eq!(db.last_key().unwrap(), None);
+    assert!(db.put(TestKey::from(7), TestValue::from("value")).is_ok());
+    assert_eq!(db.first_key().unwrap(), Some(TestKey::from(7)));
+    assert_eq!(db.last_key().unwrap(), Some(TestKey::from(7)));
+}
+
+#[test]
+fn test_range_collect_spans_memory_and_disk() {
+    let mut options = DharmaOpts::default();
+    options.backend = Arc::new(InMemoryBackend::new());
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options).unwrap();
+    let mut expected: BTreeMap<TestKey, TestValue> = BTreeMap::new();
+    for (key, value) in get_test_data(100) {
+        assert!(db.put(key.clone(), value.clone()).is_ok());
+        expected.insert(key, value);
+    }
+    assert!(db.flush().is_ok());
+    // overwrite and delete some persisted keys in memory and add new keys
+    for i in (20..40).step_by(2) {
+        let value = TestValue::from("rewritten");
+        assert!(db.put(TestKey::from(i), value.clone()).is_ok());
+        expected.insert(TestKey::from(i), value);
+    }
+    for i in (21..40).step_by(4) {
+        assert!(db.delete(TestKey::from(i)).is_ok());
+        expected.remove(&TestKey::from(i));
+    }
+    for i in 100..110 {
+        let value = TestValue::from("new");
+        assert!(db.put(TestKey::from(i), value.clone()).is_ok());
+        expected.insert(TestKey::from(i), value);
+    }
+    assert!(db
+        .delete_range(&TestKey::from(50), &TestKey::from(60))
+        .is_ok());
+    expected.retain(|key, _| !(key >= &TestKey::from(50) && key < &TestKey::from(60)));
+
+    let start = TestKey::from(10);
+    let end = TestKey::from(105);
+    let expected_range: Vec<(TestKey, TestValue)> = expected
+        .into_iter()
+        .filter(|(key, _)| key >= &start && key < &end)
+        .collect();
+    assert_eq!(db.range_collect(&start, &end).unwrap(), expected_range);
+    assert!(db.range_collect(&end, &start).unwrap().is_empty());
+}
+
+#[test]
+fn test_range_collect_seeks_tables_to_the_start_key() {
+    let backend = Arc::new(InMemoryBackend::new());
+    let mut options = DharmaOpts::default();
+    options.backend = backend.clone();
+    options.block_size_in_bytes = 1024;
+    options.sparse_index_sampling_rate = 10;
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options.clone()).unwrap();
+    for (key, value) in get_test_data(2000) {
+        assert!(db.put(key, value).is_ok());
+    }
+    assert!(db.flush().is_ok());
+    // blocks before the start of the range are never read
+    let paths = SSTableReader::get_table_paths(&options, &options.path).unwrap();
+    assert_eq!(paths.len(), 1);
+    let mut file = backend.open(&paths[0]).unwrap();
+    assert!(file.write_at(&[0xFF; 512], 0).is_ok());
+
+    let range = db
+        .range_collect(&TestKey::from(1500), &TestKey::from(1510))
+        .unwrap();
+    assert_eq!(range, get_test_data_in_range(1500, 1510));
+}
+
+#[test]
+fn test_iter_returns_live_keys_in_order() {
+    let mut options = DharmaOpts::default();
+    options.backend = Arc::new(InMemoryBackend::new());
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options).unwrap();
+    assert_eq!(db.iter().unwrap().count(), 0);
+    let mut expected: BTreeMap<TestKey, TestValue> = BTreeMap::new();
+    // each table overwrites or deletes some keys of the previous tables
+    for round in 0..3 {
+        for i in (round * 20)..(round * 20 + 50) {
+            let value = TestValue::from(format!("value-{}-{}", i, round).as_str());
+            assert!(db.put(TestKey::from(i), value.clone()).is_ok());
+            expected.insert(TestKey::from(i), value);
+        }
+        for i in ((round * 20)..(round * 20 + 50)).step_by(7) {
+            assert!(db.delete(TestKey::from(i)).is_ok());
+            expected.remove(&TestKey::from(i));
+        }
+        assert!(db.flush().is_ok());
+    }
+    // keys held in memory overwrite and delete persisted keys
+    for i in (0..120).step_by(5) {
+        let value = TestValue::from("in-memory");
+        assert!(db.put(TestKey::from(i), value.clone()).is_ok());
+        expected.insert(TestKey::from(i), value);
+    }
+    for i in (1..120).step_by(11) {
+        assert!(db.delete(TestKey::from(i)).is_ok());
+        expected.remove(&TestKey::from(i));
+    }
+    assert!(db
+        .delete_range(&TestKey::from(90), &TestKey::from(95))
+        .is_ok());
+    expected.retain(|key, _| !(key >= &TestKey::from(90) && key < &TestKey::from(95)));
+
+    let output: Vec<(TestKey, TestValue)> =
+        db.iter().unwrap().map(|entry| entry.unwrap()).collect();
+    assert_eq!(output, expected.into_iter().collect::<Vec<_>>());
+}
+
+#[test]
+fn test_custom_table_directory_and_extension() {
+    let mut options = DharmaOpts::default();
+    let backend = InMemoryBackend::new();
+    options.backend = Arc::new(backend.clone());
+    options.table_directory = String::from("sstables");
+    options.table_extension = String::from("sst");
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options.clone()).unwrap();
+    // overlapping flushes cause the tables to be compacted and renumbered
+    for round in 0..6 {
+        for (key, value) in get_test_data(50) {
+            let value = TestValue::from(format!("{}-{}", value, round).as_str());
+            assert!(db.put(key, value).is_ok());
+        }
+        assert!(db.flush().is_ok());
+    }
+    assert!(db.close().is_ok());
+
+    let paths = SSTableReader::get_table_paths(&options, &options.path).unwrap();
+    assert!(!paths.is_empty());
+    for (table_number, path) in paths.iter().enumerate() {
+        assert_eq!(path, &options.table_path(table_number));
+        assert_eq!(path.parent().unwrap(), PathBuf::from("/tmp/sstables"));
+    }
+    // nothing is written to the default table directory
+    assert!(
+        SSTableReader::get_valid_table_paths_with_backend(&backend, &options.path)
+            .unwrap()
+            .is_empty()
+    );
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options).unwrap();
+    for (key, value) in get_test_data(50) {
+        let expected = TestValue::from(format!("{}-{}", value, 5).as_str());
+        assert_eq!(db.get(&key).unwrap(), Some(expected));
+    }
+}
+
+#[test]
+fn test_verify_integrity_reports_duplicate_keys() {
+    let mut options = DharmaOpts::default();
+    options.backend = Arc::new(InMemoryBackend::new());
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options.clone()).unwrap();
+    let mut first_seq = 0;
+    for (key, value) in get_test_data(10) {
+        let is_duplicated = key == TestKey::from(5);
+        assert!(db.put(key, value).is_ok());
+        if is_duplicated {
+            first_seq = db.last_sequence();
+        }
+    }
+    assert!(db.flush().is_ok());
+    assert!(db
+        .put(TestKey::from(5), TestValue::from("rewritten"))
+        .is_ok());
+    let second_seq = db.last_sequence();
+    assert!(db.put(TestKey::from(200), TestValue::from("value")).is_ok());
+    assert!(db.flush().is_ok());
+    for (key, value) in get_test_data_in_range(400, 410) {
+        assert!(db.put(key, value).is_ok());
+    }
+    assert!(db.flush().is_ok());
+    let report = db.verify_integrity().unwrap();
+    assert_eq!(report.tables_checked, 3);
+    assert_eq!(report.duplicate_keys.len(), 1);
+    let duplicate = &report.duplicate_keys[0];
+    assert_eq!(duplicate.key, TestKey::from(5));
+    assert_eq!(
+        duplicate.tables,
+        vec![
+            (
+                PathBuf::from(format!("{}/tables/0.db", options.path)),
+                first_seq
+            ),
+            (
+                PathBuf::from(format!("{}/tables/1.db", options.path)),
+                second_seq
+            ),
+        ]
+    );
+}
+
+#[test]
+fn test_orphaned_compaction_files_are_removed_on_open() {
+    let mut options = DharmaOpts::default();
+    options.backend = Arc::new(InMemoryBackend::new());
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options.clone()).unwrap();
+    for (key, value) in get_test_data(100) {
+        assert!(db.put(key, value).is_ok());
+    }
+    assert!(db.close().is_ok());
+    // outputs of a compaction that crashed before they were swapped in
+    let orphaned = [
+        PathBuf::from(format!("{}/tables/compaction.tmp", options.path)),
+        PathBuf::from(format!("{}/tables/compaction.tmp.1", options.path)),
+    ];
+    for path in &orphaned {
+        options
+            .backend
+            .create(path)
+            .unwrap()
+            .write(b"partial")
+            .unwrap();
+    }
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options.clone()).unwrap();
+    for path in &orphaned {
+        assert!(!options.backend.exists(path));
+    }
+    let table = PathBuf::from(format!("{}/tables/0.db", options.path));
+    assert!(options.backend.exists(&table));
+    assert_eq!(
+        db.get(&TestKey::from(42)).unwrap(),
+        Some(TestValue::from("value is 42"))
+    );
+    options.backend.create(&orphaned[0]).unwrap();
+    assert_eq!(db.gc_temp_files().unwrap(), 1);
+    assert_eq!(db.gc_temp_files().unwrap(), 0);
+}
+
+#[test]
+fn test_flush_interval_flushes_small_memtable() {
+    let mut options = DharmaOpts::default();
+    options.backend = Arc::new(InMemoryBackend::new());
+    options.flush_interval = Some(Duration::from_millis(50));
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options).unwrap();
+    for (key, value) in get_test_data(5) {
+        db.put(key, value).unwrap();
+    }
+    assert_eq!(db.stats().unwrap().sstables, 0);
+    assert!(!db.flush_if_due().unwrap());
+    thread::sleep(Duration::from_millis(100));
+    // the next write flushes the values held in memory for longer than the interval
+    db.put(TestKey::from(5), TestValue::from("5")).unwrap();
+    let stats = db.stats().unwrap();
+    assert_eq!(stats.sstables, 1);
+    assert_eq!(stats.memtable_entries, 0);
+    db.put(TestKey::from(6), TestValue::from("6")).unwrap();
+    thread::sleep(Duration::from_millis(100));
+    assert!(db.flush_if_due().unwrap());
+    assert_eq!(db.stats().unwrap().sstables, 2);
+    assert!(!db.flush_if_due().unwrap());
+    for (key, value) in get_test_data(5) {
+        assert_eq!(db.get(&key).unwrap(), Some(value));
+    }
+    assert_eq!(
+        db.get(&TestKey::from(6)).unwrap(),
+        Some(TestValue::from("6"))
+    );
+}
+
+#[test]
+fn test_rewrite_with_block_size() {
+    let mut options = DharmaOpts::default();
+    options.backend = Arc::new(InMemoryBackend::new());
+    options.block_size_in_bytes = 256;
+    let data = get_test_data(300);
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options.clone()).unwrap();
+    for (key, value) in data[..200].iter().cloned() {
+        db.put(key, value).unwrap();
+    }
+    db.flush().unwrap();
+    for (key, value) in data[200..].iter().cloned() {
+        db.put(key, value).unwrap();
+    }
+    assert_eq!(
+        db.rewrite_with_block_size(2).err(),
+        Some(Errors::DB_INVALID_BLOCK_SIZE)
+    );
+    assert!(db.rewrite_with_block_size(1024).is_ok());
+    for (key, value) in &data {
+        assert_eq!(db.get(key).unwrap(), Some(value.clone()));
+    }
+    let manifest = Manifest::read(&options).unwrap().unwrap();
+    assert_eq!(manifest.block_size_in_bytes, 1024);
+    db.close().unwrap();
+    // the tables are read with the new block size once the database is reopened
+    options.block_size_in_bytes = 1024;
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options.clone()).unwrap();
+    for (key, value) in &data {
+        assert_eq!(db.get(key).unwrap(), Some(value.clone()));
+    }
+    let paths = SSTableReader::get_table_paths(&options, &options.path).unwrap();
+    for path in paths {
+        let mut reader =
+            SSTableReader::with_backend(options.backend.as_ref(), &path, 1024).unwrap();
+        assert!(reader.verify_table_checksum().unwrap());
+    }
+}
+
+#[test]
+fn test_open_after_interrupted_rewrite_with_block_size() {
+    let mut options = DharmaOpts::default();
+    options.backend = Arc::new(InMemoryBackend::new());
+    options.block_size_in_bytes = 256;
+    options.flush_on_drop = false;
+    let data = get_test_data(300);
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options.clone()).unwrap();
+    for (key, value) in data[..200].iter().cloned() {
+        db.put(key, value).unwrap();
+    }
+    db.flush().unwrap();
+    for (key, value) in data[200..].iter().cloned() {
+        db.put(key, value).unwrap();
+    }
+    drop(db);
+    // the table and the log were rewritten but the manifest still records the old size
+    let mut rewritten = options.clone();
+    rewritten.block_size_in_bytes = 1024;
+    let paths = SSTableReader::get_table_paths(&options, &options.path).unwrap();
+    assert_eq!(paths.len(), 1);
+    let mut reader = SSTableReader::with_backend(options.backend.as_ref(), &paths[0], 256).unwrap();
+    let mut values: Vec<Value<TestKey, TestValue>> = Vec::new();
+    while reader.has_next() {
+        values.push(reader.read().to_record().unwrap());
+        reader.next();
+    }
+    write_table_at_path(&rewritten, &values, &Vec::new(), &paths[0]).unwrap();
+    let (values, tombstones) =
+        WriteAheadLog::read_entries::<TestKey, TestValue>(options.clone()).unwrap();
+    WriteAheadLog::replace(rewritten, &values, &tombstones).unwrap();
+
+    // tables and the log are read with the block size they record
+    options.auto_recover = true;
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options.clone()).unwrap();
+    for (key, value) in &data {
+        assert_eq!(db.get(key).unwrap(), Some(value.clone()));
+    }
+    let manifest = Manifest::read(&options).unwrap().unwrap();
+    assert_eq!(manifest.block_size_in_bytes, 256);
+}
+
+#[test]
+fn test_interleaved_sequential_and_random_puts() {
+    let mut options = DharmaOpts::default();
+    options.backend = Arc::new(InMemoryBackend::new());
+    options.versions_to_keep = 2;
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options).unwrap();
+    let mut expected = BTreeMap::new();
+    let mut versions = BTreeMap::new();
+    let mut seed = 11u32;
+    for i in 0..400u32 {
+        // alternate runs of appended keys with overwrites of earlier keys
+        let key = if i % 50 < 30 {
+            i
+        } else {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            (seed >> 16) % (i + 1)
+        };
+        let value = TestValue::from(format!("value {} of {}", i, key).as_str());
+        db.put(TestKey::from(key), value.clone()).unwrap();
+        if let Some(previous) = expected.insert(key, value) {
+            versions.insert(key, (db.last_sequence() - 1, previous));
+        }
+        if i == 250 {
+            db.flush().unwrap();
+            versions.clear();
+        }
+    }
+    for (key, value) in &expected {
+        assert_eq!(db.get(&TestKey::from(*key)).unwrap(), Some(value.clone()));
+    }
+    // overwritten keys keep their previous version in memory
+    for (key, (seq, value)) in &versions {
+        assert_eq!(
+            db.get_version(&TestKey::from(*key), *seq).unwrap(),
+            Some(value.clone())
+        );
+    }
+    let keys: Vec<TestKey> = db.iter().unwrap().map(|entry| entry.unwrap().0).collect();
+    let expected_keys: Vec<TestKey> = expected.keys().map(|key| TestKey::from(*key)).collect();
+    assert_eq!(keys, expected_keys);
+}
+
+#[test]
+fn test_merge_from_resolves_overlaps_by_sequence() {
+    let backend = Arc::new(InMemoryBackend::new());
+    let mut options = DharmaOpts::default();
+    options.backend = backend.clone();
+    options.path = String::from("/merge/a");
+    let mut other_options = options.clone();
+    other_options.path = String::from("/merge/b");
+    let value_of = |db: &str, key: u32| TestValue::from(format!("{} {}", db, key).as_str());
+
+    let mut other: Dharma<TestKey, TestValue> = Dharma::create(other_options.clone()).unwrap();
+    for key in 50..150 {
+        other.put(TestKey::from(key), value_of("b", key)).unwrap();
+    }
+    other.flush().unwrap();
+    // written after the key was last written to the first database
+    other.put(TestKey::from(60), value_of("b", 600)).unwrap();
+    other.delete(TestKey::from(120)).unwrap();
+    other.close().unwrap();
+
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options).unwrap();
+    for key in 0..100 {
+        db.put(TestKey::from(key), value_of("a", key)).unwrap();
+    }
+    db.flush().unwrap();
+    db.merge_from(Path::new(&other_options.path)).unwrap();
+    for key in 0..150 {
+        let expected = match key {
+            60 => Some(value_of("b", 600)),
+            120 => None,
+            0..=99 => Some(value_of("a", key)),
+            _ => Some(value_of("b", key)),
+        };
+        assert_eq!(db.get(&TestKey::from(key)).unwrap(), expected);
+    }
+    // the other database is left intact
+    let mut other: Dharma<TestKey, TestValue> = Dharma::create(other_options).unwrap();
+    assert_eq!(
+        other.get(&TestKey::from(60)).unwrap(),
+        Some(value_of("b", 600))
+    );
+}
+
+#[test]
+fn test_corrupt_manifest_is_repaired_on_open() {
+    let mut options = DharmaOpts::default();
+    options.backend = Arc::new(InMemoryBackend::new());
+    let data = get_test_data(300);
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options.clone()).unwrap();
+    for (key, value) in data.clone() {
+        assert!(db.put(key, value).is_ok());
+    }
+    db.close().unwrap();
+    let manifest_path = Manifest::path(&options);
+    let manifest = Manifest::read(&options).unwrap().unwrap();
+    let size = options
+        .backend
+        .open(&manifest_path)
+        .unwrap()
+        .size()
+        .unwrap() as usize;
+    let mut bytes = vec![0u8; size];
+    let mut file = options.backend.open(&manifest_path).unwrap();
+    file.read_at(&mut bytes, 0).unwrap();
+    // flip a bit of the serialized manifest so that it no longer matches its checksum
+    bytes[size - 1] ^= 1;
+    options
+        .backend
+        .create(&manifest_path)
+        .unwrap()
+        .write(&bytes)
+        .unwrap();
+    assert_eq!(
+        Manifest::read(&options).err(),
+        Some(Errors::MANIFEST_CORRUPT)
+    );
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options.clone()).unwrap();
+    for (key, value) in data {
+        assert_eq!(db.get(&key).unwrap(), Some(value));
+    }
+    assert_eq!(db.last_sequence(), manifest.last_sequence);
+    assert_eq!(Manifest::read(&options).unwrap(), Some(manifest));
+}
+
+#[test]
+fn test_get_debug_traces_the_read() {
+    let mut options = DharmaOpts::default();
+    options.backend = Arc::new(InMemoryBackend::new());
+    options.value_cache_entries = 0;
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options.clone()).unwrap();
+    for (key, value) in get_test_data_in_range(0, 10) {
+        db.put(key, value).unwrap();
+    }
+    db.flush().unwrap();
+    for (key, value) in get_test_data_in_range(20, 30) {
+        db.put(key, value).unwrap();
+    }
+    db.flush().unwrap();
+    let paths = SSTableReader::get_table_paths(&options, &options.path).unwrap();
+    assert_eq!(paths.len(), 2);
+    db.put(TestKey::from(5), TestValue::from("in memory"))
+        .unwrap();
+
+    let trace = db.get_debug(&TestKey::from(5)).unwrap();
+    assert!(trace.memtable_hit);
+    assert!(trace.tables_probed.is_empty());
+    assert_eq!(trace.value, Some(TestValue::from("in memory")));
+
+    // the first table is skipped as its range excludes the key
+    let trace = db.get_debug(&TestKey::from(25)).unwrap();
+    assert!(!trace.memtable_hit);
+    assert_eq!(trace.tables_probed, vec![paths[1].clone()]);
+    assert_eq!(trace.tables_skipped, vec![paths[0].clone()]);
+    assert_eq!(trace.value, Some(TestValue::from("value is 25")));
+
+    // the table of the nearest sampled key is read even though it can't hold the key
+    let trace = db.get_debug(&TestKey::from(15)).unwrap();
+    assert_eq!(trace.tables_probed, vec![paths[0].clone()]);
+    assert_eq!(trace.tables_skipped, vec![paths[1].clone()]);
+    assert_eq!(trace.value, None);
+}
+
+fn estimate_compression_of(value_for: impl Fn(u64) -> String) -> f64 {
+    let mut options = DharmaOpts::default();
+    options.backend = Arc::new(InMemoryBackend::new());
+    options.block_size_in_bytes = 4096;
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options).unwrap();
+    for i in 0..100 {
+        db.put(TestKey::from(i), TestValue::from(&value_for(i as u64)))
+            .unwrap();
+    }
+    db.flush().unwrap();
+    let layout = db.table_layout().unwrap();
+    let cache_stats = db.block_cache_stats();
+    let ratio = db.estimate_compression(Compression::Lz4, 8).unwrap();
+    // estimating leaves the tables and the block cache untouched
+    assert_eq!(db.table_layout().unwrap(), layout);
+    assert_eq!(db.block_cache_stats(), cache_stats);
+    ratio
+}
+
+#[test]
+fn test_estimate_compression_of_compressible_and_incompressible_data() {
+    let compressible = estimate_compression_of(|_| "compressible ".repeat(80));
+    assert!(compressible < 0.5);
+
+    let incompressible = estimate_compression_of(|i| {
+        // letters drawn from a linear congruential generator have no repeats to exploit
+        let mut state = i
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        (0..1000)
+            .map(|_| {
+                state = state
+                    .wrapping_mul(6364136223846793005)
+                    .wrapping_add(1442695040888963407);
+                (b'!' + (state >> 33) as u8 % 94) as char
+            })
+            .collect()
+    });
+    assert!(incompressible > 0.9);
+    assert!(incompressible < 1.1);
+}
+
+#[test]
+fn test_get_with_stats_reports_tables_and_blocks_read() {
+    let mut options = DharmaOpts::default();
+    options.backend = Arc::new(InMemoryBackend::new());
+    options.value_cache_entries = 0;
+    // only the first key of each table is sampled
+    options.sparse_index_sampling_rate = 1000;
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options.clone()).unwrap();
+    for (key, value) in get_test_data_in_range(0, 100) {
+        db.put(key, value).unwrap();
+    }
+    db.flush().unwrap();
+    db.put(TestKey::from(49), TestValue::from("updated 49"))
+        .unwrap();
+    db.put(TestKey::from(99), TestValue::from("updated 99"))
+        .unwrap();
+    db.flush().unwrap();
+    let paths = SSTableReader::get_table_paths(&options, &options.path).unwrap();
+    assert_eq!(paths.len(), 2);
+    db.put(TestKey::from(200), TestValue::from("in memory"))
+        .unwrap();
+
+    let (value, stats) = db.get_with_stats(&TestKey::from(200)).unwrap();
+    assert_eq!(value, Some(TestValue::from("in memory")));
+    assert_eq!(stats.tables_probed, 0);
+    assert_eq!(stats.blocks_read, 0);
+
+    // the key is found in the newest table
+    let (value, newest_stats) = db.get_with_stats(&TestKey::from(49)).unwrap();
+    assert_eq!(value, Some(TestValue::from("updated 49")));
+    assert_eq!(newest_stats.tables_probed, 1);
+    assert!(newest_stats.blocks_read >= 1);
+
+    // the newest table holds the nearest sampled key so the older table is read next
+    let (value, fallback_stats) = db.get_with_stats(&TestKey::from(50)).unwrap();
+    assert_eq!(value, Some(TestValue::from("value is 50")));
+    assert_eq!(fallback_stats.tables_probed, 2);
+    assert!(fallback_stats.blocks_read > newest_stats.blocks_read);
+}
+
+#[test]
+fn test_range_page_matches_full_scan() {
+    let mut options = DharmaOpts::default();
+    options.backend = Arc::new(InMemoryBackend::new());
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options).unwrap();
+    for (key, value) in get_test_data_in_range(0, 100) {
+        db.put(key, value).unwrap();
+    }
+    db.flush().unwrap();
+    for i in (0..100).step_by(3) {
+        db.delete(TestKey::from(i)).unwrap();
+    }
+    for (key, value) in get_test_data_in_range(100, 130) {
+        db.put(key, value).unwrap();
+    }
+    let full_scan: Vec<(TestKey, TestValue)> = db.iter().unwrap().map(|e| e.unwrap()).collect();
+
+    let mut pages = Vec::new();
+    let mut cursor = None;
+    loop {
+        let (page, next_cursor) = db.range_page(cursor, 7).unwrap();
+        assert!(page.len() <= 7);
+        pages.extend(page);
+        match next_cursor {
+            Some(next_cursor) => cursor = Some(next_cursor),
+            None => break,
+        }
+    }
+    assert_eq!(pages, full_scan);
+
+    // writes behind the cursor don't repeat keys in later pages
+    let (first_page, cursor) = db.range_page(None, 10).unwrap();
+    let cursor = cursor.unwrap();
+    assert_eq!(cursor, Cursor::new(first_page[9].0.clone()));
+    db.put(TestKey::from(0), TestValue::from("late")).unwrap();
+    let (second_page, _) = db.range_page(Some(cursor), 10).unwrap();
+    assert_eq!(second_page, full_scan[10..20].to_vec());
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_reads_with_direct_io() {
+    let base_path = "/tmp/dharma_direct_io";
+    let _ = std::fs::remove_dir_all(base_path);
+    let mut options = DharmaOpts::default();
+    options.path = String::from(base_path);
+    options.direct_io = true;
+    options.compaction_merge_overlapping_only = true;
+    // blocks smaller than the alignment of direct IO reads
+    options.block_size_in_bytes = 512;
+    options.memtable_size_in_bytes = 4096;
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options.clone()).unwrap();
+    let data = get_test_data(500);
+    for (key, value) in data.clone() {
+        db.put(key, value).unwrap();
+    }
+    db.flush().unwrap();
+    assert!(db.table_layout().unwrap().len() > 1);
+    for (key, value) in data.iter() {
+        assert_eq!(db.get(key).unwrap().as_ref(), Some(value));
+    }
+    let scanned: Vec<(TestKey, TestValue)> = db
+        .scan_persisted()
+        .unwrap()
+        .collect::<Result<Vec<(TestKey, TestValue)>>>()
+        .unwrap();
+    assert_eq!(scanned, data);
+    db.consolidate().unwrap();
+    assert_eq!(
+        db.range_collect(&TestKey::from(0), &TestKey::from(500))
+            .unwrap(),
+        data
+    );
+
+    // unaligned reads return the same bytes as reads without direct IO
+    let path = options.table_path(0);
+    let backend = FsBackend::new();
+    let mut direct = backend.open_direct(&path).unwrap();
+    let mut file = backend.open(&path).unwrap();
+    let size = file.size().unwrap();
+    assert_eq!(direct.size().unwrap(), size);
+    for offset in &[0, 1, 511, 4095, 4097, size - 10] {
+        let mut expected = vec![0u8; 700];
+        let mut read = vec![0u8; 700];
+        let expected_size = file.read_at(&mut expected, *offset).unwrap();
+        assert_eq!(direct.read_at(&mut read, *offset).unwrap(), expected_size);
+        assert_eq!(read, expected);
+    }
+    drop(db);
+    std::fs::remove_dir_all(base_path).unwrap();
+}
+
+#[test]
+fn test_contains_key_with_key_set_reads_no_blocks() {
+    let mut options = DharmaOpts::default();
+    options.backend = Arc::new(InMemoryBackend::new());
+    options.keep_key_set = true;
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options.clone()).unwrap();
+    for (key, value) in get_test_data(200) {
+        db.put(key, value).unwrap();
+    }
+    db.flush().unwrap();
+    db.delete(TestKey::from(5)).unwrap();
+    db.delete_range(&TestKey::from(10), &TestKey::from(20))
+        .unwrap();
+    db.close().unwrap();
+
+    // the key set is built from the tables when the database is opened
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options).unwrap();
+    db.put(TestKey::from(500), TestValue::from("added"))
+        .unwrap();
+    let stats = db.block_cache_stats();
+    assert!(db.contains_key(&TestKey::from(0)).unwrap());
+    assert!(db.contains_key(&TestKey::from(199)).unwrap());
+    assert!(db.contains_key(&TestKey::from(20)).unwrap());
+    assert!(db.contains_key(&TestKey::from(500)).unwrap());
+    assert!(!db.contains_key(&TestKey::from(5)).unwrap());
+    assert!(!db.contains_key(&TestKey::from(15)).unwrap());
+    assert!(!db.contains_key(&TestKey::from(300)).unwrap());
+    let stats_after_checks = db.block_cache_stats();
+    assert_eq!(stats_after_checks.hits, stats.hits);
+    assert_eq!(stats_after_checks.misses, stats.misses);
+}
+
+struct ExpiredFilter;
+
+impl CompactionFilter<TestKey, TestValue> for ExpiredFilter {
+    fn should_keep(&self, _key: &TestKey, value: &TestValue) -> bool {
+        !value.to_string().contains("expired")
+    }
+}
+
+#[test]
+fn test_key_set_drops_keys_filtered_by_compaction() {
+    let mut options = DharmaOpts::default();
+    options.backend = Arc::new(InMemoryBackend::new());
+    options.keep_key_set = true;
+    options.compaction_filter = Some(RegisteredCompactionFilter::new(ExpiredFilter));
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options).unwrap();
+    // every tenth key of each table expired, compaction runs on the fourth flush
+    for table in 0..4 {
+        for (i, (key, value)) in get_test_data_in_range(table * 100, table * 100 + 100)
+            .into_iter()
+            .enumerate()
+        {
+            let value = if i % 10 == 0 {
+                TestValue::from("expired")
+            } else {
+                value
+            };
+            db.put(key, value).unwrap();
+        }
+        db.flush().unwrap();
+    }
+    assert!(db.stats().unwrap().last_compaction.is_some());
+    let stats = db.block_cache_stats();
+    for key in 0..400 {
+        let expected = key % 10 != 0;
+        assert_eq!(db.contains_key(&TestKey::from(key)).unwrap(), expected);
+    }
+    // the key set was updated from the compacted tables instead of being rebuilt
+    let stats_after_checks = db.block_cache_stats();
+    assert_eq!(stats_after_checks.hits, stats.hits);
+    assert_eq!(stats_after_checks.misses, stats.misses);
+}
+
+struct OtherValueFilter;
+
+impl CompactionFilter<TestKey, String> for OtherValueFilter {
+    fn should_keep(&self, _key: &TestKey, _value: &String) -> bool {
+        false
+    }
+}
+
+#[test]
+fn test_create_rejects_compaction_filter_for_other_types() {
+    let mut options = DharmaOpts::default();
+    options.backend = Arc::new(InMemoryBackend::new());
+    options.compaction_filter = Some(RegisteredCompactionFilter::new(OtherValueFilter));
+    let db: Result<Dharma<TestKey, TestValue>> = Dharma::create(options);
+    assert!(matches!(db, Err(Errors::COMPACTION_FILTER_MISMATCH)));
+}
+
+static LOSE_MEMTABLE_WRITES: AtomicBool = AtomicBool::new(false);
+
+// Memtable that drops inserts while `LOSE_MEMTABLE_WRITES` is set so that it
+// diverges from the Write Ahead Log.
+struct LossyMemtable {
+    data: BTreeMemtable,
+}
+
+impl Memtable<TestKey, TestValue> for LossyMemtable {
+    fn new() -> Self {
+        LossyMemtable {
+            data: BTreeMemtable::new(),
+        }
+    }
+
+    fn insert(&mut self, key: TestKey, value: TestValue) {
+        if !LOSE_MEMTABLE_WRITES.load(Ordering::SeqCst) {
+            self.data.insert(key, value);
+        }
+    }
+
+    fn get(&mut self, key: &TestKey) -> Option<TestValue> {
+        self.data.get(key)
+    }
+
+    fn delete(&mut self, key: &TestKey) {
+        self.data.delete(key);
+    }
+
+    fn range(&self, start: &TestKey, end: &TestKey) -> Vec<(TestKey, TestValue)> {
+        self.data.range(start, end)
+    }
+
+    fn collect(&self) -> Vec<(TestKey, TestValue)> {
+        self.data.collect()
+    }
+
+    fn len(&self) -> usize {
+        self.data.len()
+    }
+}
+
+#[test]
+fn test_validate_wal_matches_memtable() {
+    let mut options = DharmaOpts::default();
+    options.backend = Arc::new(InMemoryBackend::new());
+    let mut db: Dharma<TestKey, TestValue, LossyMemtable> =
+        Dharma::create_with_memtable(options).unwrap();
+    for (key, value) in get_test_data(50) {
+        db.put(key, value).unwrap();
+    }
+    db.put(TestKey::from(10), TestValue::from("updated"))
+        .unwrap();
+    db.delete(TestKey::from(20)).unwrap();
+    assert!(db.validate_wal_matches_memtable().unwrap());
+
+    LOSE_MEMTABLE_WRITES.store(true, Ordering::SeqCst);
+    let result = db.put(TestKey::from(100), TestValue::from("lost"));
+    LOSE_MEMTABLE_WRITES.store(false, Ordering::SeqCst);
+    result.unwrap();
+    assert!(!db.validate_wal_matches_memtable().unwrap());
+}
+
+#[test]
+fn test_flush_partitions_write_a_table_per_partition() {
+    let mut options = DharmaOpts::default();
+    options.backend = Arc::new(InMemoryBackend::new());
+    options.compaction_threshold_bytes = Some(u64::MAX);
+    options.flush_partitions = 4;
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options).unwrap();
+    let data = get_test_data(400);
+    for (key, value) in data.clone() {
+        db.put(key, value).unwrap();
+    }
+    db.flush().unwrap();
+    let layout = db.table_layout().unwrap();
+    assert_eq!(layout.len(), 4);
+    for (partition, table) in layout.iter().enumerate() {
+        let first = partition as u32 * 100;
+        assert_eq!(
+            table.key_range,
+            Some((TestKey::from(first), TestKey::from(first + 99)))
+        );
+    }
+    // reads only probe the table of the partition holding the key
+    for (i, (key, value)) in data.iter().enumerate() {
+        let trace = db.get_debug(key).unwrap();
+        assert_eq!(trace.tables_probed, vec![layout[i / 100].path.clone()]);
+        assert_eq!(trace.value.as_ref(), Some(value));
+    }
+}
+
+#[test]
+fn test_parallel_flush_writes_every_partition() {
+    let mut options = DharmaOpts::default();
+    options.backend = Arc::new(InMemoryBackend::new());
+    options.compaction_threshold_bytes = Some(u64::MAX);
+    options.flush_partitions = 4;
+    options.flush_parallelism = 4;
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options).unwrap();
+    let data = get_test_data(400);
+    for (key, value) in data.clone() {
+        db.put(key, value).unwrap();
+    }
+    db.flush().unwrap();
+    let layout = db.table_layout().unwrap();
+    assert_eq!(layout.len(), 4);
+    for (partition, table) in layout.iter().enumerate() {
+        let first = partition as u32 * 100;
+        assert_eq!(
+            table.key_range,
+            Some((TestKey::from(first), TestKey::from(first + 99)))
+        );
+    }
+    let report = db.verify_integrity().unwrap();
+    assert_eq!(report.tables_checked, 4);
+    assert!(report.duplicate_keys.is_empty());
+    for (key, value) in data.iter() {
+        assert_eq!(db.get(key).unwrap().as_ref(), Some(value));
+    }
+}
+
+#[test]
+fn test_snapshot_descriptor_lists_the_tables() {
+    let backend = InMemoryBackend::new();
+    let mut options = DharmaOpts::default();
+    options.backend = Arc::new(backend.clone());
+    options.compaction_threshold_bytes = Some(u64::MAX);
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options).unwrap();
+    for (key, value) in get_test_data_in_range(0, 50) {
+        db.put(key, value).unwrap();
+    }
+    db.flush().unwrap();
+    // values in memory are flushed before the tables are described
+    for (key, value) in get_test_data_in_range(50, 100) {
+        db.put(key, value).unwrap();
+    }
+    let descriptor = db.snapshot_descriptor().unwrap();
+    assert_eq!(db.in_memory_size(), 0);
+    assert_eq!(descriptor.last_sequence, db.last_sequence());
+    let layout = db.table_layout().unwrap();
+    assert_eq!(descriptor.tables.len(), 2);
+    for (table, info) in descriptor.tables.iter().zip(layout.iter()) {
+        assert_eq!(table.path, info.path);
+        let mut file = backend.open(&table.path).unwrap();
+        let size = file.size().unwrap();
+        assert_eq!(table.size_in_bytes, size);
+        let checksum = compute_table_checksum(file.as_mut(), size as usize).unwrap();
+        assert_eq!(table.checksum, Some(checksum));
+    }
+    let serialized = serde_json::to_string(&descriptor).unwrap();
+    let deserialized: SnapshotDescriptor = serde_json::from_str(&serialized).unwrap();
+    assert_eq!(deserialized, descriptor);
+}
+
+#[test]
+fn test_pending_tombstone_count_drops_after_compaction() {
+    let mut options = DharmaOpts::default();
+    options.backend = Arc::new(InMemoryBackend::new());
+    options.compaction_threshold_bytes = Some(u64::MAX);
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options).unwrap();
+    for i in 0..100 {
+        db.put(TestKey::from(i), TestValue::from("value")).unwrap();
+    }
+    db.flush().unwrap();
+    assert_eq!(db.pending_tombstone_count().unwrap(), 0);
+    for i in 0..10 {
+        db.delete(TestKey::from(i)).unwrap();
+    }
+    // deletions in memory are not counted
+    assert_eq!(db.pending_tombstone_count().unwrap(), 0);
+    db.flush().unwrap();
+    assert_eq!(db.pending_tombstone_count().unwrap(), 10);
+    db.delete_range(&TestKey::from(50), &TestKey::from(60))
+        .unwrap();
+    db.flush().unwrap();
+    assert_eq!(db.pending_tombstone_count().unwrap(), 11);
+    db.consolidate().unwrap();
+    assert_eq!(db.pending_tombstone_count().unwrap(), 0);
+    assert_eq!(db.get(&TestKey::from(5)).unwrap(), None);
+    assert_eq!(db.get(&TestKey::from(55)).unwrap(), None);
+}
+
+#[test]
+fn test_consolidate_leaves_a_single_clean_table() {
+    let mut options = DharmaOpts::default();
+    options.backend = Arc::new(InMemoryBackend::new());
+    options.compaction_threshold_bytes = Some(u64::MAX);
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options).unwrap();
+    // an empty log only holds its header
+    let empty_wal_size = db.stats().unwrap().wal_size_in_bytes;
+    let mut expected: BTreeMap<TestKey, TestValue> = BTreeMap::new();
+    for round in 0..3 {
+        for i in (round * 30)..(round * 30 + 50) {
+            let value = TestValue::from(format!("value-{}-{}", i, round).as_str());
+            db.put(TestKey::from(i), value.clone()).unwrap();
+            expected.insert(TestKey::from(i), value);
+        }
+        for i in ((round * 30)..(round * 30 + 50)).step_by(9) {
+            db.delete(TestKey::from(i)).unwrap();
+            expected.remove(&TestKey::from(i));
+        }
+        db.flush().unwrap();
+    }
+    db.delete_range(&TestKey::from(40), &TestKey::from(45))
+        .unwrap();
+    expected.retain(|key, _| !(key >= &TestKey::from(40) && key < &TestKey::from(45)));
+    db.put(TestKey::from(200), TestValue::from("in memory"))
+        .unwrap();
+    expected.insert(TestKey::from(200), TestValue::from("in memory"));
+    assert!(db.table_layout().unwrap().len() > 1);
+
+    db.consolidate().unwrap();
+    let layout = db.table_layout().unwrap();
+    assert_eq!(layout.len(), 1);
+    // only live values remain, deletes and range tombstones are dropped
+    assert_eq!(layout[0].records, expected.len());
+    assert_eq!(db.stats().unwrap().wal_size_in_bytes, empty_wal_size);
+    let contents: BTreeMap<TestKey, TestValue> = db.iter().unwrap().map(|e| e.unwrap()).collect();
+    assert_eq!(contents, expected);
+    assert_eq!(db.get(&TestKey::from(42)).unwrap(), None);
+    assert_eq!(
+        db.get(&TestKey::from(200)).unwrap(),
+        Some(TestValue::from("in memory"))
+    );
+}
+
+#[test]
+fn test_into_read_only_flushes_and_removes_the_log() {
+    let backend = Arc::new(InMemoryBackend::new());
+    let mut options = DharmaOpts::default();
+    options.backend = backend.clone();
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options.clone()).unwrap();
+    for (key, value) in get_test_data_in_range(0, 50) {
+        db.put(key, value).unwrap();
+    }
+    db.delete(TestKey::from(10)).unwrap();
+    let wal_path = PathBuf::from(format!("{}/wal.log", options.path));
+    assert!(backend.exists(&wal_path));
+
+    // the read-only handle has no methods that write
+    let mut read_only: ReadOnlyDharma<TestKey, TestValue> = db.into_read_only().unwrap();
+    assert!(!backend.exists(&wal_path));
+    assert_eq!(read_only.table_layout().unwrap().len(), 1);
+    assert_eq!(
+        read_only.get(&TestKey::from(20)).unwrap(),
+        Some(TestValue::from("value is 20"))
+    );
+    assert_eq!(read_only.get(&TestKey::from(10)).unwrap(), None);
+    assert_eq!(read_only.iter().unwrap().count(), 49);
+    drop(read_only);
+    assert!(!backend.exists(&wal_path));
+
+    let mut reopened: Dharma<TestKey, TestValue> = Dharma::create(options).unwrap();
+    assert_eq!(
+        reopened.get(&TestKey::from(49)).unwrap(),
+        Some(TestValue::from("value is 49"))
+    );
+}
+
+#[test]
+fn test_sealed_database_can_be_read_but_not_written() {
+    let backend = Arc::new(InMemoryBackend::new());
+    let mut options = DharmaOpts::default();
+    options.backend = backend.clone();
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options.clone()).unwrap();
+    for (key, value) in get_test_data_in_range(0, 50) {
+        db.put(key, value).unwrap();
+    }
+    db.delete(TestKey::from(10)).unwrap();
+    assert!(!db.is_sealed());
+    db.seal().unwrap();
+    let wal_path = PathBuf::from(format!("{}/wal.log", options.path));
+    assert!(!backend.exists(&wal_path));
+    assert!(Manifest::read(&options).unwrap().unwrap().sealed);
+
+    for _ in 0..2 {
+        let mut sealed: Dharma<TestKey, TestValue> = Dharma::create(options.clone()).unwrap();
+        assert!(sealed.is_sealed());
+        assert_eq!(
+            sealed.get(&TestKey::from(20)).unwrap(),
+            Some(TestValue::from("value is 20"))
+        );
+        assert_eq!(sealed.get(&TestKey::from(10)).unwrap(), None);
+        assert_eq!(sealed.iter().unwrap().count(), 49);
+        assert!(matches!(
+            sealed.put(TestKey::from(60), TestValue::from("value is 60")),
+            Err(Errors::DB_SEALED)
+        ));
+        assert!(matches!(
+            sealed.delete(TestKey::from(20)),
+            Err(Errors::DB_SEALED)
+        ));
+        assert!(matches!(
+            sealed.delete_range(&TestKey::from(0), &TestKey::from(5)),
+            Err(Errors::DB_SEALED)
+        ));
+        assert!(matches!(
+            sealed.set_metadata("schema", b"v2"),
+            Err(Errors::DB_SEALED)
+        ));
+        assert!(matches!(sealed.flush(), Err(Errors::DB_SEALED)));
+        assert_eq!(sealed.get(&TestKey::from(60)).unwrap(), None);
+        assert_eq!(
+            sealed.get(&TestKey::from(20)).unwrap(),
+            Some(TestValue::from("value is 20"))
+        );
+        // the handle leaves nothing to recover behind so the database opens again
+        drop(sealed);
+        assert!(!backend.exists(&wal_path));
+    }
+}
+
+#[test]
+fn test_corrupt_manifest_of_sealed_database_is_repaired_sealed() {
+    let mut options = DharmaOpts::default();
+    options.backend = Arc::new(InMemoryBackend::new());
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options.clone()).unwrap();
+    for (key, value) in get_test_data_in_range(0, 50) {
+        db.put(key, value).unwrap();
+    }
+    db.seal().unwrap();
+    let manifest_path = Manifest::path(&options);
+    let size = options
+        .backend
+        .open(&manifest_path)
+        .unwrap()
+        .size()
+        .unwrap() as usize;
+    let mut bytes = vec![0u8; size];
+    let mut file = options.backend.open(&manifest_path).unwrap();
+    file.read_at(&mut bytes, 0).unwrap();
+    // flip the bit recording the seal so that the manifest no longer matches its checksum
+    bytes[size - 1] ^= 1;
+    options
+        .backend
+        .create(&manifest_path)
+        .unwrap()
+        .write(&bytes)
+        .unwrap();
+    assert_eq!(
+        Manifest::read(&options).err(),
+        Some(Errors::MANIFEST_CORRUPT)
+    );
+
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options.clone()).unwrap();
+    assert!(db.is_sealed());
+    assert_eq!(
+        db.get(&TestKey::from(20)).unwrap(),
+        Some(TestValue::from("value is 20"))
+    );
+    assert!(matches!(
+        db.put(TestKey::from(60), TestValue::from("value is 60")),
+        Err(Errors::DB_SEALED)
+    ));
+    drop(db);
+    assert!(Manifest::read(&options).unwrap().unwrap().sealed);
+    // an explicit repair keeps the seal as well
+    Dharma::<TestKey, TestValue>::repair(options.clone()).unwrap();
+    assert!(Manifest::read(&options).unwrap().unwrap().sealed);
+}
+
+#[test]
+fn test_metadata_survives_reopen_and_is_not_scanned() {
+    let mut options = DharmaOpts::default();
+    options.backend = Arc::new(InMemoryBackend::new());
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options.clone()).unwrap();
+    assert_eq!(db.get_metadata("schema_version"), None);
+    db.set_metadata("schema_version", &[3]).unwrap();
+    db.set_metadata("owner", b"billing").unwrap();
+    db.set_metadata("schema_version", &[4]).unwrap();
+    db.put(TestKey::from(1), TestValue::from("value")).unwrap();
+    db.close().unwrap();
+
+    let mut reopened: Dharma<TestKey, TestValue> = Dharma::create(options).unwrap();
+    assert_eq!(reopened.get_metadata("schema_version"), Some(vec![4]));
+    assert_eq!(reopened.get_metadata("owner"), Some(b"billing".to_vec()));
+    assert_eq!(reopened.get_metadata("missing"), None);
+    assert_eq!(reopened.iter().unwrap().count(), 1);
+}
+
+#[test]
+fn test_effective_options_use_the_block_size_of_the_tables() {
+    let mut options = DharmaOpts::default();
+    options.backend = Arc::new(InMemoryBackend::new());
+    options.block_size_in_bytes = 4096;
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options.clone()).unwrap();
+    let data = get_test_data_in_range(0, 200);
+    for (key, value) in &data {
+        db.put(key.clone(), value.clone()).unwrap();
+    }
+    db.flush().unwrap();
+    assert_eq!(db.effective_options().block_size_in_bytes, 4096);
+    drop(db);
+
+    // the tables are read with the block size they were written with
+    options.block_size_in_bytes = 32768;
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options).unwrap();
+    assert_eq!(db.effective_options().block_size_in_bytes, 4096);
+    for (key, value) in data {
+        assert_eq!(db.get(&key).unwrap(), Some(value));
+    }
+}
+
+static COUNTED_VALUE_DECODES: AtomicUsize = AtomicUsize::new(0);
+
+/// Value that counts how often it is deserialized.
+#[derive(Clone, Debug, PartialEq, serde::Serialize)]
+struct CountedValue(String);
+
+impl<'de> serde::Deserialize<'de> for CountedValue {
+    fn deserialize<D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> std::result::Result<Self, D::Error> {
+        COUNTED_VALUE_DECODES.fetch_add(1, Ordering::SeqCst);
+        String::deserialize(deserializer).map(CountedValue)
+    }
+}
+
+impl std::fmt::Display for CountedValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Nil for CountedValue {
+    fn nil() -> Self {
+        CountedValue(String::new())
+    }
+}
+
+impl ResourceValue for CountedValue {}
+
+#[test]
+fn test_range_lazy_only_decodes_values_that_are_read() {
+    let mut options = DharmaOpts::default();
+    options.backend = Arc::new(InMemoryBackend::new());
+    options.compaction_threshold_bytes = Some(u64::MAX);
+    let mut db: Dharma<TestKey, CountedValue> = Dharma::create(options).unwrap();
+    for i in 0..100 {
+        db.put(TestKey::from(i), CountedValue(format!("value {}", i)))
+            .unwrap();
+    }
+    db.flush().unwrap();
+    db.delete(TestKey::from(20)).unwrap();
+    db.put(TestKey::from(30), CountedValue(String::from("in memory")))
+        .unwrap();
+
+    let decodes = COUNTED_VALUE_DECODES.load(Ordering::SeqCst);
+    let entries = db
+        .range_lazy(&TestKey::from(10), &TestKey::from(60))
+        .unwrap();
+    assert_eq!(entries.len(), 49);
+    assert!(!entries.iter().any(|(key, _)| key == &TestKey::from(20)));
+    assert_eq!(COUNTED_VALUE_DECODES.load(Ordering::SeqCst), decodes);
+    // only the values that are read are deserialized
+    let mut read = 0;
+    for (key, value) in entries
+        .iter()
+        .filter(|(key, _)| key.to_string().ends_with('5'))
+    {
+        assert!(!value.is_decoded());
+        assert_eq!(value.get().unwrap(), CountedValue(format!("value {}", key)));
+        read += 1;
+    }
+    assert_eq!(read, 5);
+    assert_eq!(COUNTED_VALUE_DECODES.load(Ordering::SeqCst), decodes + read);
+    let (_, in_memory) = entries
+        .iter()
+        .find(|(key, _)| key == &TestKey::from(30))
+        .unwrap();
+    assert_eq!(
+        in_memory.get().unwrap(),
+        CountedValue(String::from("in memory"))
+    );
+}
+
+#[test]
+fn test_estimate_flush_matches_flushed_table() {
+    let mut options = DharmaOpts::default();
+    options.backend = Arc::new(InMemoryBackend::new());
+    options.block_size_in_bytes = 256;
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options.clone()).unwrap();
+    assert_eq!(db.estimate_flush().unwrap().blocks, 0);
+    // every value is larger than a block so each one is split
+    for i in 0..20 {
+        db.put(TestKey::from(i), TestValue::from(&"v".repeat(300)))
+            .unwrap();
+    }
+    let estimate = db.estimate_flush().unwrap();
+    assert_eq!(estimate.split_records, 20);
+    assert_eq!(estimate.total_bytes, estimate.blocks * 256);
+    assert!(estimate.padding_bytes > 0);
+    assert!(estimate.padding_bytes < estimate.total_bytes);
+    // estimating doesn't flush anything
+    assert!(db.table_layout().unwrap().is_empty());
+
+    db.flush().unwrap();
+    let layout = db.table_layout().unwrap();
+    assert_eq!(layout.len(), 1);
+    assert_eq!(layout[0].records, 20);
+    // the footer is stored in the padding of the last block or in a block of its own
+    let size = layout[0].size_in_bytes as usize;
+    assert!(size == estimate.total_bytes || size == estimate.total_bytes + 256);
+}
+
+#[test]
+fn test_put_with_options() {
+    let mut options = DharmaOpts::default();
+    options.flush_on_drop = false;
+    options.auto_recover = true;
+    options.backend = Arc::new(InMemoryBackend::new());
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options.clone()).unwrap();
+    let synced = PutOptions {
+        sync: true,
+        ..PutOptions::default()
+    };
+    db.put_with_options(TestKey::from(1), TestValue::from("synced"), synced)
+        .unwrap();
+    let unlogged = PutOptions {
+        skip_wal: true,
+        ..PutOptions::default()
+    };
+    db.put_with_options(TestKey::from(2), TestValue::from("unlogged"), unlogged)
+        .unwrap();
+    let expiring = PutOptions {
+        ttl: Some(Duration::from_millis(50)),
+        ..PutOptions::default()
+    };
+    db.put_with_options(TestKey::from(3), TestValue::from("expiring"), expiring)
+        .unwrap();
+    db.put_with_options(TestKey::from(4), TestValue::from("expiring"), expiring)
+        .unwrap();
+    // overwriting the key drops its TTL
+    db.put(TestKey::from(4), TestValue::from("kept")).unwrap();
+    assert_eq!(
+        db.get(&TestKey::from(2)).unwrap(),
+        Some(TestValue::from("unlogged"))
+    );
+    assert_eq!(
+        db.get(&TestKey::from(3)).unwrap(),
+        Some(TestValue::from("expiring"))
+    );
+
+    thread::sleep(Duration::from_millis(100));
+    assert_eq!(db.get(&TestKey::from(3)).unwrap(), None);
+    assert_eq!(
+        db.get(&TestKey::from(4)).unwrap(),
+        Some(TestValue::from("kept"))
+    );
+    assert_eq!(
+        db.range_collect(&TestKey::from(3), &TestKey::from(4))
+            .unwrap(),
+        vec![]
+    );
+    // the next write logs the delete marker of the expired key
+    db.put(TestKey::from(5), TestValue::from("written"))
+        .unwrap();
+
+    // crash leaving the values in the Write Ahead Log
+    drop(db);
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options).unwrap();
+    assert_eq!(
+        db.get(&TestKey::from(1)).unwrap(),
+        Some(TestValue::from("synced"))
+    );
+    // the write that skipped the log is lost
+    assert_eq!(db.get(&TestKey::from(2)).unwrap(), None);
+    assert_eq!(db.get(&TestKey::from(3)).unwrap(), None);
+}
+
+#[test]
+fn test_reads_hide_expired_keys_without_writing() {
+    let mut options = DharmaOpts::default();
+    options.flush_on_drop = false;
+    options.auto_recover = true;
+    options.backend = Arc::new(InMemoryBackend::new());
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options.clone()).unwrap();
+    let expiring = PutOptions {
+        ttl: Some(Duration::from_millis(50)),
+        ..PutOptions::default()
+    };
+    db.put_with_options(TestKey::from(1), TestValue::from("expiring"), expiring)
+        .unwrap();
+    db.put(TestKey::from(2), TestValue::from("kept")).unwrap();
+    thread::sleep(Duration::from_millis(100));
+    let wal_size = db.stats().unwrap().wal_size_in_bytes;
+    let sequence = db.last_sequence();
+    assert_eq!(db.get(&TestKey::from(1)).unwrap(), None);
+    assert_eq!(db.contains_key(&TestKey::from(1)).unwrap(), false);
+    assert_eq!(db.get_debug(&TestKey::from(1)).unwrap().value, None);
+    assert_eq!(db.first_key().unwrap(), Some(TestKey::from(2)));
+    assert_eq!(db.count().unwrap(), 1);
+    // reads never log delete markers
+    assert_eq!(db.stats().unwrap().wal_size_in_bytes, wal_size);
+    assert_eq!(db.last_sequence(), sequence);
+
+    // the next write logs the delete marker of the expired key
+    db.put(TestKey::from(3), TestValue::from("written"))
+        .unwrap();
+    assert_eq!(db.last_sequence(), sequence + 2);
+    drop(db);
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options).unwrap();
+    assert_eq!(db.get(&TestKey::from(1)).unwrap(), None);
+    assert_eq!(
+        db.get(&TestKey::from(3)).unwrap(),
+        Some(TestValue::from("written"))
+    );
+}
+
+/// Transform encrypting records by XORing them with a repeating key.
+struct XorTransform {
+    key: Vec<u8>,
+}
+
+impl XorTransform {
+    fn apply(&self, bytes: Vec<u8>) -> Vec<u8> {
+        bytes
+            .iter()
+            .zip(self.key.iter().cycle())
+            .map(|(byte, key)| byte ^ key)
+            .collect()
+    }
+}
+
+impl ValueTransform for XorTransform {
+    fn on_write(&self, bytes: Vec<u8>) -> Result<Vec<u8>> {
+        Ok(self.apply(bytes))
+    }
+
+    fn on_read(&self, bytes: Vec<u8>) -> Result<Vec<u8>> {
+        Ok(self.apply(bytes))
+    }
+}
+
+fn read_file(backend: &InMemoryBackend, path: &Path) -> Vec<u8> {
+    let mut file = backend.open(path).unwrap();
+    let mut data = vec![0u8; file.size().unwrap() as usize];
+    file.read_at(&mut data, 0).unwrap();
+    data
+}
+
+fn contains_bytes(data: &[u8], needle: &[u8]) -> bool {
+    data.windows(needle.len()).any(|window| window == needle)
+}
+
+#[test]
+fn test_value_transform_encrypts_values_at_rest() {
+    let backend = InMemoryBackend::new();
+    let mut options = DharmaOpts::default();
+    options.backend = Arc::new(backend.clone());
+    options.value_transform = Some(Arc::new(XorTransform {
+        key: b"secret".to_vec(),
+    }));
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options.clone()).unwrap();
+    let plaintext = |i: u32| format!("plaintext value {}", i);
+    for i in 0..100 {
+        db.put(TestKey::from(i), TestValue::from(&plaintext(i)))
+            .unwrap();
+    }
+    let wal_path = PathBuf::from(format!("{}/wal.log", options.path));
+    assert!(!contains_bytes(
+        &read_file(&backend, &wal_path),
+        b"plaintext value"
+    ));
+    // flush several tables so that they are compacted
+    for round in 0..4 {
+        db.flush().unwrap();
+        db.put(TestKey::from(round), TestValue::from(&plaintext(round)))
+            .unwrap();
+    }
+    db.flush().unwrap();
+    let paths = SSTableReader::get_table_paths(&options, &options.path).unwrap();
+    assert!(!paths.is_empty());
+    for path in &paths {
+        assert!(!contains_bytes(
+            &read_file(&backend, path),
+            b"plaintext value"
+        ));
+    }
+    for i in 0..100 {
+        assert_eq!(
+            db.get(&TestKey::from(i)).unwrap(),
+            Some(TestValue::from(&plaintext(i)))
+        );
+    }
+    let range = db
+        .range_collect(&TestKey::from(10), &TestKey::from(20))
+        .unwrap();
+    assert_eq!(range.len(), 10);
+    assert_eq!(range[0].1, TestValue::from(&plaintext(10)));
+
+    // the tables can't be read without the transform
+    drop(db);
+    options.value_transform = None;
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options).unwrap();
+    let read = db.get(&TestKey::from(50));
+    assert!(read.map_or(true, |value| value != Some(TestValue::from(&plaintext(50)))));
+}
+
+struct JsonCodec;
+
+impl RecordCodec for JsonCodec {
+    fn id(&self) -> u8 {
+        1
+    }
+
+    fn encode<K: Serialize, V: Serialize>(&self, value: &Value<K, V>) -> Result<Vec<u8>> {
+        serde_json::to_vec(value).map_err(|_| Errors::RECORD_SERIALIZATION_FAILED)
+    }
+
+    fn decode<K: DeserializeOwned, V: DeserializeOwned>(&self, data: &[u8]) -> Result<Value<K, V>> {
+        serde_json::from_slice(data).map_err(|_| Errors::RECORD_DESERIALIZATION_FAILED)
+    }
+}
+
+#[test]
+fn test_record_codec_serializes_flushed_and_compacted_tables() {
+    let backend = InMemoryBackend::new();
+    let mut options = DharmaOpts::default();
+    options.backend = Arc::new(backend.clone());
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options.clone()).unwrap();
+    // tables written before the codec was set are still read with bincode
+    for (key, value) in get_test_data_in_range(0, 100) {
+        db.put(key, value).unwrap();
+    }
+    db.flush().unwrap();
+    drop(db);
+    options.record_codec = Some(RegisteredRecordCodec::new::<TestKey, TestValue, _>(
+        JsonCodec,
+    ));
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options.clone()).unwrap();
+    for round in 1..4 {
+        for (key, value) in get_test_data_in_range(round * 100, round * 100 + 100) {
+            db.put(key, value).unwrap();
+        }
+        db.delete(TestKey::from(round)).unwrap();
+        db.flush().unwrap();
+    }
+    let paths = SSTableReader::get_table_paths(&options, &options.path).unwrap();
+    assert!(!paths.is_empty());
+    for path in &paths {
+        let reader = SSTableReader::with_options(&options, path).unwrap();
+        assert_eq!(reader.codec_id(), 1);
+    }
+    for (key, value) in get_test_data_in_range(4, 400) {
+        assert_eq!(db.get(&key).unwrap(), Some(value));
+    }
+    for i in 1..4 {
+        assert_eq!(db.get(&TestKey::from(i)).unwrap(), None);
+    }
+    let range = db
+        .range_collect(&TestKey::from(95), &TestKey::from(105))
+        .unwrap();
+    assert_eq!(range, get_test_data_in_range(95, 105));
+
+    // the tables can only be reopened with the codec
+    drop(db);
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options.clone()).unwrap();
+    assert_eq!(
+        db.get(&TestKey::from(250)).unwrap(),
+        Some(get_test_data_in_range(250, 251)[0].1.clone())
+    );
+    drop(db);
+    options.record_codec = None;
+    assert!(Dharma::<TestKey, TestValue>::create(options).is_err());
+}
+
+#[test]
+fn test_record_codec_registered_for_other_types_fails_writes() {
+    let mut options = DharmaOpts::default();
+    options.backend = Arc::new(InMemoryBackend::new());
+    options.record_codec = Some(RegisteredRecordCodec::new::<TestKey, CountedValue, _>(
+        JsonCodec,
+    ));
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options).unwrap();
+    db.put(TestKey::from(1), TestValue::from("value")).unwrap();
+    assert!(db.flush().is_err());
+}
+
+#[test]
+fn test_count_live_keys() {
+    let mut options = DharmaOpts::default();
+    options.backend = Arc::new(InMemoryBackend::new());
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options).unwrap();
+    assert_eq!(db.count().unwrap(), 0);
+    for (key, value) in get_test_data_in_range(0, 200) {
+        db.put(key, value).unwrap();
+    }
+    db.flush().unwrap();
+    // overwrites of persisted keys are counted once
+    for (key, value) in get_test_data_in_range(100, 300) {
+        db.put(key, value).unwrap();
+    }
+    db.delete(TestKey::from(5)).unwrap();
+    db.delete(TestKey::from(250)).unwrap();
+    // deleting a missing key doesn't change the count
+    db.delete(TestKey::from(1000)).unwrap();
+    db.delete_range(&TestKey::from(50), &TestKey::from(60))
+        .unwrap();
+    assert_eq!(db.count().unwrap(), 300 - 2 - 10);
+    assert_eq!(db.count().unwrap(), db.iter().unwrap().count());
+
+    db.flush().unwrap();
+    assert_eq!(db.count().unwrap(), 288);
+}
@@ -0,0 +1,57 @@
+use crate::common::get_test_data;
+use crate::common::test_key::TestKey;
+use crate::common::test_value::TestValue;
+use dharmadb::dharma::Dharma;
+use dharmadb::options::DharmaOpts;
+use dharmadb::storage::backend::{InMemoryBackend, StorageBackend};
+use dharmadb::storage::sorted_string_table_reader::SSTableReader;
+use std::path::Path;
+use std::sync::Arc;
+
+mod common;
+
+// Built and run without the `std` feature as well, see the build workflow.
+#[test]
+fn test_in_memory_store_does_not_touch_file_system() {
+    let backend = InMemoryBackend::new();
+    let mut options = DharmaOpts::default();
+    options.path = String::from("/tmp/dharma_in_memory_test");
+    options.memtable_size_in_bytes = 4096;
+    options.backend = Arc::new(backend.clone());
+    assert!(!Path::new(&options.path).exists());
+    let data = get_test_data(1000);
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options.clone()).unwrap();
+    for (key, value) in data.clone() {
+        assert!(db.put(key, value).is_ok());
+    }
+    assert!(db.flush().is_ok());
+    for (key, value) in data {
+        assert_eq!(db.get(&key).unwrap(), Some(value));
+    }
+    // tables are written to the backend while nothing is written to disk
+    let paths = SSTableReader::get_valid_table_paths_with_backend(&backend, &options.path);
+    assert!(!paths.unwrap().is_empty());
+    assert!(backend.exists(Path::new(&format!("{}/wal.log", options.path))));
+    assert!(!Path::new(&options.path).exists());
+}
+
+// work spread across threads runs on the calling thread without the `std` feature
+#[test]
+fn test_parallel_reads_flushes_and_compactions_complete() {
+    let mut options = DharmaOpts::default();
+    options.memtable_size_in_bytes = 4096;
+    options.get_parallelism = 4;
+    options.flush_parallelism = 4;
+    options.max_concurrent_compactions = 4;
+    options.compaction_merge_overlapping_only = true;
+    options.backend = Arc::new(InMemoryBackend::new());
+    let data = get_test_data(1000);
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options).unwrap();
+    for (key, value) in data.clone() {
+        assert!(db.put(key, value).is_ok());
+    }
+    assert!(db.flush().is_ok());
+    for (key, value) in data {
+        assert_eq!(db.get(&key).unwrap(), Some(value));
+    }
+}
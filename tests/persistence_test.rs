@@ -1,11 +1,22 @@
+use crate::common::hooked_backend::HookedBackend;
 use crate::common::test_key::TestKey;
 use crate::common::test_value::TestValue;
 use crate::common::{cleanup_paths, get_test_data, get_test_data_in_range};
+use dharmadb::dharma::Dharma;
 use dharmadb::options::DharmaOpts;
 use dharmadb::persistence::Persistence;
-use dharmadb::result::Result;
+use dharmadb::result::{Errors, Result};
+use dharmadb::storage::backend::{InMemoryBackend, StorageBackend};
+use dharmadb::storage::block::{RangeTombstone, Value};
+use dharmadb::storage::manifest::{Manifest, FORMAT_VERSION};
 use dharmadb::storage::sorted_string_table_reader::SSTableReader;
-use dharmadb::traits::Nil;
+use dharmadb::storage::sorted_string_table_writer::write_table_at_path;
+use dharmadb::storage::write_ahead_log::WriteAheadLog;
+use dharmadb::traits::{Nil, ResourceKey};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
 
 mod common;
 
@@ -165,3 +176,837 @@ fn test_tables_are_compacted_after_threshold_is_met() {
     assert!(sstable_paths.is_ok());
     assert_eq!(sstable_paths.unwrap().len(), 1);
 }
+
+#[test]
+fn test_disjoint_tables_are_not_merged_when_merging_overlapping_only() {
+    let mut options = DharmaOpts::default();
+    options.compaction_merge_overlapping_only = true;
+    cleanup_paths(&options);
+    let data_1 = get_test_data_in_range(0, 100);
+    let data_2 = get_test_data_in_range(80, 300);
+    let data_3 = get_test_data_in_range(280, 400);
+    let data_4 = get_test_data_in_range(400, 500);
+    let mut persistence = Persistence::create::<TestValue>(options.clone()).unwrap();
+
+    assert!(persistence.flush(&data_1).is_ok());
+    assert!(persistence.flush(&data_2).is_ok());
+    assert!(persistence.flush(&data_3).is_ok());
+    assert!(persistence.flush(&data_4).is_ok());
+
+    // the last table is left next to the table merged from the overlapping ones
+    let sstable_paths = SSTableReader::get_valid_table_paths(&options.path);
+    assert!(sstable_paths.is_ok());
+    assert_eq!(sstable_paths.unwrap().len(), 2);
+    for (key, value) in get_test_data_in_range(0, 500) {
+        let get_result: Result<Option<TestValue>> = persistence.get(&key);
+        assert_eq!(get_result.unwrap(), Some(value));
+    }
+}
+
+#[test]
+fn test_persistence_get_with_values_split_across_blocks() {
+    let mut data = Vec::new();
+    for i in 0..300 {
+        let value = TestValue::from("x".repeat((i % 40) as usize).as_str());
+        data.push((TestKey::from(i), value));
+    }
+    let mut options = DharmaOpts::default();
+    options.block_size_in_bytes = 64;
+    options.sparse_index_sampling_rate = 3;
+    cleanup_paths(&options);
+    let mut persistence = Persistence::create::<TestValue>(options).unwrap();
+    assert!(persistence.flush(&data).is_ok());
+    for (key, value) in data {
+        let get_result: Result<Option<TestValue>> = persistence.get(&key);
+        assert!(get_result.is_ok());
+        assert_eq!(get_result.unwrap(), Some(value));
+    }
+}
+
+#[test]
+fn test_create_fails_on_unsupported_format_version() {
+    let options = DharmaOpts::default();
+    cleanup_paths(&options);
+    let manifest = Manifest {
+        format_version: FORMAT_VERSION + 1,
+        ..Manifest::new()
+    };
+    assert!(manifest.write(&options).is_ok());
+    let persistence_result: Result<Persistence<TestKey>> =
+        Persistence::create::<TestValue>(options.clone());
+    assert!(persistence_result.is_err());
+    assert!(
+        persistence_result.err()
+            == Some(Errors::UNSUPPORTED_FORMAT_VERSION {
+                found: FORMAT_VERSION + 1,
+                expected: FORMAT_VERSION,
+            })
+    );
+    cleanup_paths(&options);
+}
+
+#[test]
+fn test_scan_table_visits_all_records() {
+    let data = get_test_data(500);
+    let options = DharmaOpts::default();
+    cleanup_paths(&options);
+    let mut persistence = Persistence::create::<TestValue>(options.clone()).unwrap();
+    assert!(persistence.flush(&data).is_ok());
+    let paths = SSTableReader::get_valid_table_paths(&options.path).unwrap();
+    let mut visited = Vec::new();
+    let scan_result = persistence.scan_table::<TestValue, _>(&paths[0], |key, _, offset| {
+        visited.push((key.clone(), offset));
+    });
+    assert!(scan_result.is_ok());
+    assert_eq!(visited.len(), data.len());
+    // offsets of visited records are valid seek targets
+    for (key, offset) in visited {
+        let mut reader = SSTableReader::from(&paths[0], options.block_size_in_bytes).unwrap();
+        assert!(reader.seek_closest(offset).is_ok());
+        let record: Value<TestKey, TestValue> = reader.read().to_record().unwrap();
+        assert_eq!(record.key, key);
+    }
+}
+
+#[test]
+fn test_persistence_get_with_sampling_rate_larger_than_table() {
+    let mut options = DharmaOpts::default();
+    options.sparse_index_sampling_rate = 1000;
+    cleanup_paths(&options);
+    let mut persistence = Persistence::create::<TestValue>(options).unwrap();
+    let older = get_test_data_in_range(0, 20);
+    let newer = get_test_data_in_range(10, 12);
+    assert!(persistence.flush(&older).is_ok());
+    assert!(persistence.flush(&newer).is_ok());
+    // the nearest sampled key of keys past 12 belongs to the newer table
+    // which doesn't hold them so they are read from the older table
+    for (key, value) in older {
+        let get_result = persistence.get::<TestValue>(&key);
+        assert!(get_result.is_ok());
+        assert_eq!(get_result.unwrap(), Some(value));
+    }
+}
+
+#[test]
+fn test_flush_passes_verification() {
+    let mut options = DharmaOpts::default();
+    options.verify_after_flush = true;
+    options.sparse_index_sampling_rate = 7;
+    cleanup_paths(&options);
+    let mut persistence = Persistence::create::<TestValue>(options).unwrap();
+    let data = get_test_data(500);
+    assert!(persistence.flush(&data).is_ok());
+    for (key, value) in data {
+        assert_eq!(persistence.get::<TestValue>(&key).unwrap(), Some(value));
+    }
+}
+
+#[test]
+fn test_failed_compaction_swap_leaves_tables_intact() {
+    let inner = InMemoryBackend::new();
+    let mut options = DharmaOpts::default();
+    // the compacted table can't be moved into place
+    let backend = HookedBackend::new(inner.clone()).on_rename(|from: &Path, _: &Path| {
+        if from.ends_with("compaction.tmp") {
+            return Err(io::Error::new(io::ErrorKind::Other, "injected failure"));
+        }
+        Ok(())
+    });
+    options.backend = Arc::new(backend);
+    let data = vec![
+        get_test_data_in_range(0, 100),
+        get_test_data_in_range(80, 300),
+        get_test_data_in_range(280, 400),
+        get_test_data_in_range(380, 500),
+    ];
+    let mut persistence = Persistence::<TestKey>::create::<TestValue>(options.clone()).unwrap();
+    for tuples in &data {
+        assert!(persistence.flush(tuples).is_ok());
+    }
+
+    // the merged tables remain and the compacted table is discarded
+    let sstable_paths =
+        SSTableReader::get_valid_table_paths_with_backend(&inner, &options.path).unwrap();
+    assert_eq!(sstable_paths.len(), 4);
+    let tables_path = PathBuf::from(format!("{}/tables", options.path));
+    assert_eq!(inner.list_dir(&tables_path).unwrap().len(), 4);
+    for (key, value) in get_test_data(500) {
+        assert_eq!(persistence.get::<TestValue>(&key).unwrap(), Some(value));
+    }
+}
+
+#[test]
+fn test_failed_removal_of_merged_table_fails_compaction() {
+    let inner = InMemoryBackend::new();
+    let mut options = DharmaOpts::default();
+    // the second table can't be removed
+    let backend = HookedBackend::new(inner.clone()).on_remove(|path: &Path| {
+        if path.ends_with("tables/1.db") {
+            return Err(io::Error::new(io::ErrorKind::Other, "injected failure"));
+        }
+        Ok(())
+    });
+    options.backend = Arc::new(backend);
+    let data = get_test_data(100);
+    let mut persistence = Persistence::<TestKey>::create::<TestValue>(options.clone()).unwrap();
+    for _ in 0..3 {
+        assert!(persistence.flush(&data).is_ok());
+    }
+    // the compacted table replaced the first table but the second one is left behind
+    assert!(persistence.flush(&data).is_err());
+    let sstable_paths =
+        SSTableReader::get_valid_table_paths_with_backend(&inner, &options.path).unwrap();
+    assert!(sstable_paths.len() > 1);
+    // the index was rebuilt from the tables on disk
+    for (key, value) in data {
+        assert_eq!(persistence.get::<TestValue>(&key).unwrap(), Some(value));
+    }
+}
+
+/// Check whether the first table is either missing or a complete table.
+fn is_first_table_intact(inner: &InMemoryBackend) -> bool {
+    let first_table = PathBuf::from("/tmp/tables/0.db");
+    if !inner.exists(&first_table) {
+        return true;
+    }
+    SSTableReader::with_backend(inner, &first_table, 32768)
+        .and_then(|mut reader| reader.verify_table_checksum())
+        .unwrap_or(false)
+}
+
+#[test]
+fn test_compacted_table_is_never_observed_incomplete() {
+    let inner = InMemoryBackend::new();
+    let observing = Arc::new(Mutex::new(false));
+    let incomplete_observations = Arc::new(Mutex::new(0));
+    // once observing every change checks the first table
+    let observe: Arc<dyn Fn() + Send + Sync> = {
+        let (inner, observing) = (inner.clone(), observing.clone());
+        let incomplete_observations = incomplete_observations.clone();
+        Arc::new(move || {
+            if *observing.lock().unwrap() && !is_first_table_intact(&inner) {
+                *incomplete_observations.lock().unwrap() += 1;
+            }
+        })
+    };
+    let observer = |observe: &Arc<dyn Fn() + Send + Sync>| {
+        let observe = observe.clone();
+        move |_: &Path| {
+            observe();
+            Ok(())
+        }
+    };
+    let rename_observer = observe.clone();
+    // compacted tables live on another file system so they can't be renamed into place
+    let backend = HookedBackend::new(inner.clone())
+        .on_create(observer(&observe))
+        .on_write(observer(&observe))
+        .on_remove(observer(&observe))
+        .on_rename(move |from: &Path, _: &Path| {
+            if from.ends_with("compaction.tmp") {
+                return Err(io::Error::from(io::ErrorKind::CrossesDevices));
+            }
+            rename_observer();
+            Ok(())
+        });
+    let mut options = DharmaOpts::default();
+    options.backend = Arc::new(backend);
+    let data = vec![
+        get_test_data_in_range(0, 100),
+        get_test_data_in_range(80, 300),
+        get_test_data_in_range(280, 400),
+        get_test_data_in_range(380, 500),
+    ];
+    let mut persistence = Persistence::<TestKey>::create::<TestValue>(options.clone()).unwrap();
+    assert!(persistence.flush(&data[0]).is_ok());
+    // the first table is complete once it was flushed
+    *observing.lock().unwrap() = true;
+    for tuples in &data[1..] {
+        assert!(persistence.flush(tuples).is_ok());
+    }
+
+    // the compacted table was copied across file systems and replaced the merged tables
+    observe();
+    assert_eq!(*incomplete_observations.lock().unwrap(), 0);
+    let sstable_paths =
+        SSTableReader::get_valid_table_paths_with_backend(&inner, &options.path).unwrap();
+    assert_eq!(sstable_paths.len(), 1);
+    let tables_path = PathBuf::from(format!("{}/tables", options.path));
+    assert_eq!(inner.list_dir(&tables_path).unwrap().len(), 1);
+    for (key, value) in get_test_data(500) {
+        assert_eq!(persistence.get::<TestValue>(&key).unwrap(), Some(value));
+    }
+}
+
+/// Backend that records syncs of files and directories along with renamed and removed files.
+/// Removing the Write Ahead Log fails when `crash_before_log_reset` is set as if
+/// the process crashed right before the log was reset.
+fn recording_backend(
+    inner: InMemoryBackend,
+    events: Arc<Mutex<Vec<String>>>,
+    crash_before_log_reset: bool,
+) -> HookedBackend {
+    let recorder = |operation: &'static str| {
+        let events = events.clone();
+        move |path: &Path| {
+            let event = format!("{} {}", operation, path.display());
+            events.lock().unwrap().push(event);
+            Ok(())
+        }
+    };
+    let record_removal = recorder("remove");
+    HookedBackend::new(inner)
+        .on_sync(recorder("sync"))
+        .on_sync_dir(recorder("sync_dir"))
+        .on_remove(move |path: &Path| {
+            if crash_before_log_reset && path.ends_with("wal.log") {
+                return Err(io::Error::new(io::ErrorKind::Other, "injected crash"));
+            }
+            record_removal(path)
+        })
+        .on_rename(move |from: &Path, to: &Path| {
+            let event = format!("rename {} {}", from.display(), to.display());
+            events.lock().unwrap().push(event);
+            Ok(())
+        })
+}
+
+#[test]
+fn test_flushed_table_is_synced_before_log_reset() {
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let mut options = DharmaOpts::default();
+    options.backend = Arc::new(recording_backend(
+        InMemoryBackend::new(),
+        events.clone(),
+        false,
+    ));
+    let mut persistence = Persistence::<TestKey>::create::<TestValue>(options.clone()).unwrap();
+    events.lock().unwrap().clear();
+    assert!(persistence.flush(&get_test_data(100)).is_ok());
+    let events = events.lock().unwrap().clone();
+    let position = |event: String| events.iter().position(|e| e == &event).unwrap();
+    let table_sync = position(format!("sync {}/tables/0.db", options.path));
+    let dir_sync = position(format!("sync_dir {}/tables", options.path));
+    let log_reset = position(format!("remove {}/wal.log", options.path));
+    // a crash before the log is reset must find the complete table
+    assert!(table_sync < dir_sync);
+    assert!(dir_sync < log_reset);
+}
+
+#[test]
+fn test_compacted_table_swap_is_synced_before_merged_tables_are_removed() {
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let mut options = DharmaOpts::default();
+    options.backend = Arc::new(recording_backend(
+        InMemoryBackend::new(),
+        events.clone(),
+        false,
+    ));
+    let mut persistence = Persistence::<TestKey>::create::<TestValue>(options.clone()).unwrap();
+    for _ in 0..3 {
+        assert!(persistence.flush(&get_test_data(100)).is_ok());
+    }
+    events.lock().unwrap().clear();
+    // the fourth table triggers a compaction
+    assert!(persistence.flush(&get_test_data(100)).is_ok());
+    let events = events.lock().unwrap().clone();
+    let position = |event: String| events.iter().position(|e| e == &event).unwrap();
+    let tables_path = options.tables_path();
+    let swap = position(format!(
+        "rename {}/compaction.tmp {}/0.db",
+        tables_path.display(),
+        tables_path.display()
+    ));
+    let merged_removal = position(format!("remove {}/1.db", tables_path.display()));
+    let dir_sync = events[swap..merged_removal]
+        .iter()
+        .position(|e| e == &format!("sync_dir {}", tables_path.display()));
+    assert!(dir_sync.is_some());
+}
+
+#[test]
+fn test_wal_compaction_is_synced_after_rename() {
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let mut options = DharmaOpts::default();
+    options.backend = Arc::new(recording_backend(
+        InMemoryBackend::new(),
+        events.clone(),
+        false,
+    ));
+    let wal = WriteAheadLog::create(options.clone()).unwrap();
+    for (key, value) in get_test_data(10) {
+        assert!(wal.append(key, value).is_ok());
+    }
+    events.lock().unwrap().clear();
+    assert_eq!(wal.compact::<TestKey, TestValue>().unwrap(), 10);
+    let events = events.lock().unwrap().clone();
+    let position = |event: String| events.iter().position(|e| e == &event).unwrap();
+    let rename = position(format!("rename {0}/wal.log.tmp {0}/wal.log", options.path));
+    let dir_sync = position(format!("sync_dir {}", options.path));
+    assert!(rename < dir_sync);
+}
+
+#[test]
+fn test_crash_before_log_reset_recovers_flushed_values() {
+    let inner = InMemoryBackend::new();
+    let mut options = DharmaOpts::default();
+    options.backend = Arc::new(recording_backend(
+        inner.clone(),
+        Arc::new(Mutex::new(Vec::new())),
+        true,
+    ));
+    let data = get_test_data(200);
+    let mut persistence = Persistence::<TestKey>::create::<TestValue>(options.clone()).unwrap();
+    for (key, value) in data.clone() {
+        assert!(persistence.insert(key, value).is_ok());
+    }
+    assert!(persistence.flush(&data).is_err());
+    drop(persistence);
+
+    // the flushed table and the log both survive the crash
+    let sstable_paths =
+        SSTableReader::get_valid_table_paths_with_backend(&inner, &options.path).unwrap();
+    assert_eq!(sstable_paths.len(), 1);
+    options.backend = Arc::new(inner);
+    let mut db = Dharma::<TestKey, TestValue>::recover::<TestKey, TestValue>(options).unwrap();
+    for (key, value) in data {
+        assert_eq!(db.get(&key).unwrap(), Some(value));
+    }
+}
+
+#[test]
+fn test_flush_retaining_replaces_log_without_removing_it() {
+    let inner = InMemoryBackend::new();
+    let mut options = DharmaOpts::default();
+    // the log is never removed so retained values are logged at every point
+    options.backend = Arc::new(recording_backend(
+        inner.clone(),
+        Arc::new(Mutex::new(Vec::new())),
+        true,
+    ));
+    let data = get_test_data(200);
+    let mut persistence = Persistence::<TestKey>::create::<TestValue>(options.clone()).unwrap();
+    for (key, value) in data.clone() {
+        assert!(persistence.insert(key, value).is_ok());
+    }
+    let to_values = |data: &[(TestKey, TestValue)]| -> Vec<Value<TestKey, TestValue>> {
+        data.iter()
+            .map(|(key, value)| Value::new(key.clone(), value.clone()))
+            .collect()
+    };
+    let flushed = to_values(&data[..100]);
+    let retained = to_values(&data[100..]);
+    let tombstones = vec![RangeTombstone::new(
+        TestKey::from(300),
+        TestKey::from(400),
+        1,
+    )];
+    assert!(persistence
+        .flush_values_retaining(&flushed, &Vec::new(), &retained, &tombstones)
+        .is_ok());
+    let (logged, logged_tombstones) =
+        WriteAheadLog::read_entries::<TestKey, TestValue>(options.clone()).unwrap();
+    let logged: Vec<(TestKey, TestValue)> = logged
+        .into_iter()
+        .map(|value| (value.key, value.value))
+        .collect();
+    assert_eq!(logged, data[100..].to_vec());
+    assert_eq!(logged_tombstones.len(), 1);
+}
+
+/// Paths and contents of removed files whose directory wasn't synced yet.
+type RemovedFiles = Arc<Mutex<Vec<(PathBuf, Vec<u8>)>>>;
+
+/// Backend that keeps the contents of removed files until their directory is synced.
+/// The files are restored by `restore_unsynced_removals`, as if the process crashed
+/// before the directory reached the disk.
+fn unsynced_removal_backend(inner: InMemoryBackend, removed: RemovedFiles) -> HookedBackend {
+    let (removal_inner, synced) = (inner.clone(), removed.clone());
+    HookedBackend::new(inner)
+        .on_remove(move |path: &Path| {
+            let mut file = removal_inner.open(path)?;
+            let mut data = vec![0u8; file.size()? as usize];
+            file.read_at(&mut data, 0)?;
+            removed.lock().unwrap().push((path.to_path_buf(), data));
+            Ok(())
+        })
+        .on_sync_dir(move |path: &Path| {
+            synced
+                .lock()
+                .unwrap()
+                .retain(|(removed_path, _)| removed_path.parent() != Some(path));
+            Ok(())
+        })
+}
+
+fn restore_unsynced_removals(inner: &InMemoryBackend, removed: &RemovedFiles) {
+    for (path, data) in removed.lock().unwrap().drain(..) {
+        inner.create(&path).unwrap().write(&data).unwrap();
+    }
+}
+
+/// Crash right after flushing values and count the keys stored in more than
+/// one table once the database is recovered and flushed again.
+fn duplicate_keys_after_crash_during_flush(sync_wal_on_reset: bool) -> usize {
+    let inner = InMemoryBackend::new();
+    let removed = Arc::new(Mutex::new(Vec::new()));
+    let mut options = DharmaOpts::default();
+    options.backend = Arc::new(unsynced_removal_backend(inner.clone(), removed.clone()));
+    options.sync_wal_on_reset = sync_wal_on_reset;
+    let data = get_test_data(100);
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options.clone()).unwrap();
+    for (key, value) in data.clone() {
+        db.put(key, value).unwrap();
+    }
+    db.flush().unwrap();
+    std::mem::forget(db);
+    restore_unsynced_removals(&inner, &removed);
+
+    let mut db = Dharma::<TestKey, TestValue>::recover::<TestKey, TestValue>(options).unwrap();
+    db.flush().unwrap();
+    for (key, value) in data {
+        assert_eq!(db.get(&key).unwrap(), Some(value));
+    }
+    db.verify_integrity().unwrap().duplicate_keys.len()
+}
+
+#[test]
+fn test_synced_wal_reset_survives_crash_during_flush() {
+    // the deleted log comes back after the crash and its records are flushed again
+    assert_eq!(duplicate_keys_after_crash_during_flush(false), 100);
+    assert_eq!(duplicate_keys_after_crash_during_flush(true), 0);
+}
+
+#[test]
+fn test_tables_containing_key() {
+    let mut options = DharmaOpts::default();
+    options.backend = Arc::new(InMemoryBackend::new());
+    let mut persistence: Persistence<TestKey> =
+        Persistence::create::<TestValue>(options.clone()).unwrap();
+    let mut gap = get_test_data_in_range(0, 5);
+    gap.extend(get_test_data_in_range(6, 10));
+    assert!(persistence.flush(&get_test_data_in_range(0, 10)).is_ok());
+    assert!(persistence.flush(&gap).is_ok());
+    assert!(persistence.flush(&get_test_data_in_range(5, 20)).is_ok());
+    let paths = SSTableReader::get_table_paths(&options, &options.path).unwrap();
+    assert_eq!(paths.len(), 3);
+    // the second table covers the key without holding it
+    let tables = persistence
+        .tables_containing::<TestValue>(&TestKey::from(5))
+        .unwrap();
+    assert_eq!(tables, vec![paths[2].clone(), paths[0].clone()]);
+    let tables = persistence
+        .tables_containing::<TestValue>(&TestKey::from(15))
+        .unwrap();
+    assert_eq!(tables, vec![paths[2].clone()]);
+    assert!(persistence
+        .tables_containing::<TestValue>(&TestKey::from(20))
+        .unwrap()
+        .is_empty());
+}
+
+#[test]
+fn test_create_from_tables_only_serves_the_mounted_tables() {
+    let mut options = DharmaOpts::default();
+    options.backend = Arc::new(InMemoryBackend::new());
+    options.compaction_threshold_bytes = Some(u64::MAX);
+    let mut persistence: Persistence<TestKey> =
+        Persistence::create::<TestValue>(options.clone()).unwrap();
+    assert!(persistence.flush(&get_test_data_in_range(0, 10)).is_ok());
+    assert!(persistence.flush(&get_test_data_in_range(10, 20)).is_ok());
+    drop(persistence);
+    let paths = SSTableReader::get_table_paths(&options, &options.path).unwrap();
+    assert_eq!(paths.len(), 2);
+
+    let mut mounted: Persistence<TestKey> =
+        Persistence::create_from_tables::<TestValue>(options.clone(), vec![paths[1].clone()])
+            .unwrap();
+    assert!(mounted
+        .get::<TestValue>(&TestKey::from(5))
+        .unwrap()
+        .is_none());
+    assert_eq!(
+        mounted.get::<TestValue>(&TestKey::from(15)).unwrap(),
+        Some(TestValue::from("value is 15"))
+    );
+    let scanned: Vec<(TestKey, TestValue)> = mounted
+        .scan_persisted::<TestValue>()
+        .unwrap()
+        .collect::<Result<Vec<(TestKey, TestValue)>>>()
+        .unwrap();
+    assert_eq!(scanned, get_test_data_in_range(10, 20));
+    // tables of a partially mounted database are never rewritten
+    let insert_result = mounted.insert(TestKey::from(30), TestValue::from("value"));
+    assert!(insert_result.err() == Some(Errors::DB_PARTIALLY_MOUNTED));
+    assert_eq!(
+        SSTableReader::get_table_paths(&options, &options.path)
+            .unwrap()
+            .len(),
+        2
+    );
+}
+
+#[test]
+fn test_create_from_tables_leaves_the_log_untouched() {
+    let mut options = DharmaOpts::default();
+    options.backend = Arc::new(InMemoryBackend::new());
+    let mut persistence: Persistence<TestKey> =
+        Persistence::create::<TestValue>(options.clone()).unwrap();
+    assert!(persistence.flush(&get_test_data_in_range(0, 10)).is_ok());
+    persistence
+        .insert(TestKey::from(20), TestValue::from("value is 20"))
+        .unwrap();
+    persistence.retain_log();
+    drop(persistence);
+    let wal_path = PathBuf::from(format!("{}/wal.log", options.path));
+    let wal_size = options.backend.open(&wal_path).unwrap().size().unwrap();
+    let paths = SSTableReader::get_table_paths(&options, &options.path).unwrap();
+
+    let mounted: Persistence<TestKey> =
+        Persistence::create_from_tables::<TestValue>(options.clone(), paths).unwrap();
+    assert_eq!(mounted.wal_size().unwrap(), 0);
+    drop(mounted);
+    // the log of the database still holds the unflushed write
+    assert_eq!(
+        options.backend.open(&wal_path).unwrap().size().unwrap(),
+        wal_size
+    );
+    let recovered: Vec<(TestKey, TestValue)> =
+        Persistence::<TestKey>::recover(options.clone()).unwrap();
+    assert_eq!(
+        recovered,
+        vec![(TestKey::from(20), TestValue::from("value is 20"))]
+    );
+}
+
+#[test]
+fn test_max_index_entries_coarsens_the_index() {
+    let mut options = DharmaOpts::default();
+    options.backend = Arc::new(InMemoryBackend::new());
+    options.sparse_index_sampling_rate = 4;
+    options.max_index_entries = Some(16);
+    let mut persistence: Persistence<TestKey> =
+        Persistence::create::<TestValue>(options.clone()).unwrap();
+    let data = get_test_data_in_range(0, 5000);
+    assert!(persistence.flush(&data).is_ok());
+    assert!(persistence.index_entries() <= 16);
+    assert!(persistence.sampling_stride() > 4);
+    for (key, value) in data.iter() {
+        let found = persistence.get::<TestValue>(key).unwrap();
+        assert_eq!(found, Some(value.clone()));
+    }
+    assert!(persistence
+        .get::<TestValue>(&TestKey::from(5000))
+        .unwrap()
+        .is_none());
+}
+
+#[test]
+fn test_parallel_get_returns_value_from_old_table() {
+    let mut options = DharmaOpts::default();
+    options.backend = Arc::new(InMemoryBackend::new());
+    options.get_parallelism = 4;
+    // keep the tables apart so the key has to be looked for across all of them
+    options.compaction_threshold_bytes = Some(u64::MAX);
+    let mut persistence: Persistence<TestKey> =
+        Persistence::create::<TestValue>(options.clone()).unwrap();
+    assert!(persistence.flush(&get_test_data_in_range(0, 100)).is_ok());
+    // newer tables cover the key without holding it
+    for start in 1..10 {
+        let mut data = get_test_data_in_range(start * 200, start * 200 + 1);
+        data.extend(get_test_data_in_range(0, 1));
+        data.sort_by(|a, b| a.0.cmp(&b.0));
+        assert!(persistence.flush(&data).is_ok());
+    }
+    let newest = vec![
+        (TestKey::from(0), TestValue::from("newest")),
+        (TestKey::from(5000), TestValue::from("newest")),
+    ];
+    assert!(persistence.flush(&newest).is_ok());
+    let paths = SSTableReader::get_table_paths(&options, &options.path).unwrap();
+    assert_eq!(paths.len(), 11);
+    let found = persistence.get::<TestValue>(&TestKey::from(0)).unwrap();
+    assert_eq!(found, Some(TestValue::from("newest")));
+    for key in 1..100 {
+        let found = persistence.get::<TestValue>(&TestKey::from(key)).unwrap();
+        let expected = TestValue::from(format!("value is {}", key).as_str());
+        assert_eq!(found, Some(expected));
+    }
+    assert!(persistence
+        .get::<TestValue>(&TestKey::from(150))
+        .unwrap()
+        .is_none());
+}
+
+#[test]
+fn test_create_if_missing_creates_the_directory_tree() {
+    let base_path = "/tmp/dharma_create_if_missing";
+    let _ = std::fs::remove_dir_all(base_path);
+    let mut options = DharmaOpts::default();
+    options.path = format!("{}/nested/db", base_path);
+    let persistence_result: Result<Persistence<TestKey>> =
+        Persistence::create::<TestValue>(options.clone());
+    assert!(persistence_result.is_ok());
+    assert!(Path::new(&options.path).is_dir());
+    assert!(options.tables_path().is_dir());
+    drop(persistence_result);
+    std::fs::remove_dir_all(base_path).unwrap();
+}
+
+#[test]
+fn test_missing_database_is_not_created_when_disabled() {
+    let base_path = "/tmp/dharma_not_created";
+    let _ = std::fs::remove_dir_all(base_path);
+    let mut options = DharmaOpts::default();
+    options.path = format!("{}/db", base_path);
+    options.create_if_missing = false;
+    let persistence_result: Result<Persistence<TestKey>> =
+        Persistence::create::<TestValue>(options.clone());
+    assert!(persistence_result.err().unwrap() == Errors::DB_NOT_FOUND);
+    assert!(!Path::new(base_path).exists());
+
+    // databases that already exist are opened
+    options.create_if_missing = true;
+    drop(Persistence::<TestKey>::create::<TestValue>(options.clone()).unwrap());
+    options.create_if_missing = false;
+    assert!(Persistence::<TestKey>::create::<TestValue>(options).is_ok());
+    std::fs::remove_dir_all(base_path).unwrap();
+}
+
+#[test]
+fn test_validate_tables_on_open_rejects_unsorted_table() {
+    let mut options = DharmaOpts::default();
+    options.backend = Arc::new(InMemoryBackend::new());
+    drop(Persistence::<TestKey>::create::<TestValue>(options.clone()).unwrap());
+    // a table whose records are out of order as a buggy writer could leave behind
+    let values: Vec<Value<TestKey, TestValue>> = vec![3, 1, 2]
+        .into_iter()
+        .map(|key| Value::new(TestKey::from(key), TestValue::from("value")))
+        .collect();
+    let path = options.table_path(0);
+    write_table_at_path(&options, &values, &[], &path).unwrap();
+
+    assert!(Persistence::<TestKey>::create::<TestValue>(options.clone()).is_ok());
+    options.validate_tables_on_open = true;
+    let result = Persistence::<TestKey>::create::<TestValue>(options);
+    assert!(result.err().unwrap() == Errors::UNSORTED_TABLE { path });
+}
+
+static COUNTED_KEY_CLONES: AtomicUsize = AtomicUsize::new(0);
+
+/// Key that counts how often it is cloned.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, serde::Serialize, serde::Deserialize)]
+struct CountedKey(u32);
+
+impl Clone for CountedKey {
+    fn clone(&self) -> Self {
+        COUNTED_KEY_CLONES.fetch_add(1, Ordering::SeqCst);
+        CountedKey(self.0)
+    }
+}
+
+impl std::fmt::Display for CountedKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl ResourceKey for CountedKey {}
+
+#[test]
+fn test_index_build_only_clones_sampled_keys() {
+    let mut options = DharmaOpts::default();
+    options.backend = Arc::new(InMemoryBackend::new());
+    options.sparse_index_sampling_rate = 100;
+    options.compaction_threshold_bytes = Some(u64::MAX);
+    let records: u32 = 2000;
+    let tables = 2;
+    {
+        let mut persistence: Persistence<CountedKey> =
+            Persistence::create::<TestValue>(options.clone()).unwrap();
+        for table in 0..tables {
+            let data: Vec<(CountedKey, TestValue)> = (0..records / tables)
+                .map(|i| {
+                    let key = table * records / tables + i;
+                    (CountedKey(key), TestValue::from("value"))
+                })
+                .collect();
+            assert!(persistence.flush(&data).is_ok());
+        }
+    }
+
+    let clones_before_open = COUNTED_KEY_CLONES.load(Ordering::SeqCst);
+    let mut persistence: Persistence<CountedKey> =
+        Persistence::create::<TestValue>(options).unwrap();
+    let clones = COUNTED_KEY_CLONES.load(Ordering::SeqCst) - clones_before_open;
+    // keys are cloned for the sampled keys and the first key of each table, not per record
+    let sampled_keys = records / 100;
+    assert!(clones <= (sampled_keys + tables) as usize);
+    assert_eq!(
+        persistence
+            .get::<TestValue>(&CountedKey(records - 1))
+            .unwrap(),
+        Some(TestValue::from("value"))
+    );
+}
+
+#[test]
+fn test_global_scan_with_source_attributes_keys_to_newest_table() {
+    let mut options = DharmaOpts::default();
+    options.backend = Arc::new(InMemoryBackend::new());
+    let mut persistence: Persistence<TestKey> =
+        Persistence::create::<TestValue>(options.clone()).unwrap();
+    assert!(persistence.flush(&get_test_data_in_range(0, 20)).is_ok());
+    let newer: Vec<(TestKey, TestValue)> = get_test_data_in_range(10, 30)
+        .into_iter()
+        .map(|(key, _)| (key, TestValue::from("newer")))
+        .collect();
+    assert!(persistence.flush(&newer).is_ok());
+    let tables = SSTableReader::get_table_paths(&options, &options.path).unwrap();
+    assert_eq!(tables.len(), 2);
+
+    let scanned = persistence
+        .global_scan_with_source::<TestValue>()
+        .unwrap()
+        .collect::<Result<Vec<(TestKey, TestValue, PathBuf)>>>()
+        .unwrap();
+    assert_eq!(scanned.len(), 30);
+    for (i, (key, value, source)) in scanned.into_iter().enumerate() {
+        assert_eq!(key, TestKey::from(i as u32));
+        if i < 10 {
+            assert_eq!(value, TestValue::from(format!("value is {}", i).as_str()));
+            assert_eq!(source, tables[0]);
+        } else {
+            assert_eq!(value, TestValue::from("newer"));
+            assert_eq!(source, tables[1]);
+        }
+    }
+}
+
+#[test]
+fn test_scan_range_seeks_tables_to_the_start_key() {
+    let mut options = DharmaOpts::default();
+    options.backend = Arc::new(InMemoryBackend::new());
+    options.block_size_in_bytes = 1024;
+    options.sparse_index_sampling_rate = 10;
+    let mut persistence: Persistence<TestKey> =
+        Persistence::create::<TestValue>(options.clone()).unwrap();
+    assert!(persistence.flush(&get_test_data_in_range(0, 2000)).is_ok());
+
+    let mut scan = persistence
+        .scan_range::<TestValue>(Some(&TestKey::from(1500)), Some(&TestKey::from(1510)))
+        .unwrap();
+    let mut scanned = Vec::new();
+    for entry in scan.by_ref() {
+        let (key, _, value) = entry.unwrap();
+        scanned.push((key, value.get().unwrap()));
+    }
+    assert_eq!(scanned, get_test_data_in_range(1500, 1510));
+    let seeked_blocks = scan.blocks_read();
+
+    let mut full_scan = persistence
+        .scan_range::<TestValue>(None, Some(&TestKey::from(1510)))
+        .unwrap();
+    assert_eq!(full_scan.by_ref().count(), 1510);
+    assert!(seeked_blocks * 10 < full_scan.blocks_read());
+}
@@ -1,11 +1,33 @@
-use crate::common::get_test_data;
+use crate::common::{get_test_data, get_test_data_in_range};
 use crate::common::test_key::TestKey;
 use crate::common::test_value::TestValue;
+use dharmadb::dharma::Dharma;
 use dharmadb::options::DharmaOpts;
-use dharmadb::storage::block::Value;
-use dharmadb::storage::sorted_string_table_reader::SSTableReader;
-use dharmadb::storage::sorted_string_table_writer::write_sstable;
-use std::fs::File;
+use dharmadb::storage::backend::{FsBackend, InMemoryBackend, StorageBackend, StorageFile};
+use dharmadb::result::{Errors, Result};
+use dharmadb::storage::block::{
+    create_blocks, create_blocks_with_codec, write_block_to_disk, BlockHeader, RangeTombstone,
+    Record, RecordType, Value,
+};
+use dharmadb::storage::checksum::compute_table_checksum;
+use dharmadb::storage::codec::{RecordCodec, BINCODE_CODEC_ID};
+use dharmadb::storage::debug::{dump_table, DebugFormat};
+use dharmadb::storage::encoding::Encoding;
+use dharmadb::storage::manifest::{to_table_version_footer, FORMAT_VERSION};
+use dharmadb::storage::sorted_string_table_reader::{read_streamed_record, SSTableReader};
+use dharmadb::storage::sorted_string_table_writer::{
+    read_sstable, read_sstable_with_codec, write_sstable, write_sstable_at_path,
+    write_table_at_path, write_table_with_codec, write_values_at_path, SSTableWriter,
+};
+use dharmadb::storage::value_log::ValueLog;
+use serde::de::DeserializeOwned;
+use dharmadb::traits::{Nil, ResourceKey};
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Arc;
 
 mod common;
 
@@ -16,7 +38,7 @@ fn test_sstables_io() {
     let write_result = write_sstable(&options, &values, 0);
     assert!(write_result.is_ok());
     // read SSTable back
-    let written_path = write_result.unwrap();
+    let written_path = write_result.unwrap().unwrap();
     let reader_result = SSTableReader::from(&written_path, options.block_size_in_bytes);
     assert!(reader_result.is_ok());
     let mut reader = reader_result.unwrap();
@@ -40,10 +62,1454 @@ fn test_sstable_size_is_multiple_of_block_size() {
     let options = DharmaOpts::default();
     let write_result = write_sstable(&options, &values, 0);
     assert!(write_result.is_ok());
-    let file_path = write_result.unwrap();
+    let file_path = write_result.unwrap().unwrap();
     let file_handle_result = File::open(&file_path);
     assert!(file_handle_result.is_ok());
     let file_handle = file_handle_result.unwrap();
     let file_size_in_bytes = file_handle.metadata().unwrap().len();
     assert_eq!(file_size_in_bytes % options.block_size_in_bytes as u64, 0);
 }
+
+#[test]
+fn test_sstables_io_with_in_memory_backend() {
+    let values = get_test_data(700);
+    let mut options = DharmaOpts::default();
+    options.backend = Arc::new(InMemoryBackend::new());
+    let write_result = write_sstable(&options, &values, 0);
+    assert!(write_result.is_ok());
+    // read SSTable back from the in-memory backend
+    let written_path = write_result.unwrap().unwrap();
+    let reader_result = SSTableReader::with_backend(
+        options.backend.as_ref(),
+        &written_path,
+        options.block_size_in_bytes,
+    );
+    assert!(reader_result.is_ok());
+    let mut reader = reader_result.unwrap();
+    let mut result: Vec<(TestKey, TestValue)> = Vec::new();
+    while reader.has_next() {
+        let value = reader.read();
+        let record: Value<TestKey, TestValue> = value.to_record().unwrap();
+        result.push((record.key, record.value));
+        reader.next();
+    }
+    assert_eq!(values, result);
+}
+
+#[test]
+fn test_empty_data_creates_no_table() {
+    let mut options = DharmaOpts::default();
+    options.backend = Arc::new(InMemoryBackend::new());
+    let values: Vec<(TestKey, TestValue)> = Vec::new();
+    let write_result = write_sstable(&options, &values, 0);
+    assert!(write_result.is_ok());
+    assert!(write_result.unwrap().is_none());
+    let path = PathBuf::from(format!("{}/tables/0.db", options.path));
+    assert!(!options.backend.exists(&path));
+}
+
+#[test]
+fn test_zero_size_table_has_no_records() {
+    let options = DharmaOpts::default();
+    let backend = InMemoryBackend::new();
+    let path = PathBuf::from(format!("{}/tables/0.db", options.path));
+    backend.create(&path).unwrap();
+    let mut reader = reader_for(&backend, &path, &options);
+    assert_eq!(reader.size, 0);
+    assert!(!reader.has_next());
+    assert_eq!(reader.offsets().count(), 0);
+    assert!(!reader.seek_to_key(&TestKey::from(0)).unwrap());
+    assert!(reader.seek_closest(0).is_err());
+}
+
+#[test]
+fn test_only_large_values_are_compressed() {
+    let mut values = Vec::new();
+    for i in 0..200 {
+        let value = if i % 2 == 0 {
+            TestValue::from("small")
+        } else {
+            TestValue::from("large value ".repeat(50).as_str())
+        };
+        values.push((TestKey::from(i), value));
+    }
+    let mut options = DharmaOpts::default();
+    options.value_compression_min_bytes = Some(100);
+    let write_result = write_sstable(&options, &values, 0);
+    assert!(write_result.is_ok());
+    let written_path = write_result.unwrap().unwrap();
+    let mut reader = SSTableReader::from(&written_path, options.block_size_in_bytes).unwrap();
+    let mut result: Vec<(TestKey, TestValue)> = Vec::new();
+    let mut i = 0;
+    while reader.has_next() {
+        let value = reader.read();
+        // only the large values are stored compressed
+        assert_eq!(value.compressed, i % 2 == 1);
+        let record: Value<TestKey, TestValue> = value.to_record().unwrap();
+        result.push((record.key, record.value));
+        i += 1;
+        reader.next();
+    }
+    assert_eq!(values, result);
+}
+
+fn get_test_data_with_split_values(count: u32) -> Vec<(TestKey, TestValue)> {
+    let mut vector = Vec::new();
+    for i in 0..count {
+        // values of varying length so that several records are split across blocks
+        let value = TestValue::from("x".repeat((i % 40) as usize).as_str());
+        vector.push((TestKey::from(i), value));
+    }
+    vector
+}
+
+#[test]
+fn test_scan_across_block_boundaries_is_ordered() {
+    let values = get_test_data_with_split_values(300);
+    let mut options = DharmaOpts::default();
+    options.block_size_in_bytes = 64;
+    let write_result = write_sstable(&options, &values, 0);
+    assert!(write_result.is_ok());
+    let written_path = write_result.unwrap().unwrap();
+    let mut reader = SSTableReader::from(&written_path, options.block_size_in_bytes).unwrap();
+    let mut result: Vec<(TestKey, TestValue)> = Vec::new();
+    while reader.has_next() {
+        let record: Value<TestKey, TestValue> = reader.read().to_record().unwrap();
+        if let Some((previous_key, _)) = result.last() {
+            assert!(previous_key < &record.key);
+        }
+        result.push((record.key, record.value));
+        reader.next();
+    }
+    assert_eq!(values, result);
+}
+
+#[test]
+fn test_value_offsets_are_valid_seek_targets() {
+    let values = get_test_data_with_split_values(300);
+    let mut options = DharmaOpts::default();
+    options.block_size_in_bytes = 64;
+    let written_path = write_sstable(&options, &values, 0).unwrap().unwrap();
+    let mut reader = SSTableReader::from(&written_path, options.block_size_in_bytes).unwrap();
+    let mut offsets = Vec::new();
+    while reader.has_next() {
+        let value = reader.read();
+        let record: Value<TestKey, TestValue> = value.to_record().unwrap();
+        offsets.push((record.key, value.offset));
+        reader.next();
+    }
+    // seeking to the offset of a value reads back the same value
+    for (key, offset) in offsets {
+        let mut seek_reader =
+            SSTableReader::from(&written_path, options.block_size_in_bytes).unwrap();
+        assert!(seek_reader.seek_closest(offset).is_ok());
+        let record: Value<TestKey, TestValue> = seek_reader.read().to_record().unwrap();
+        assert_eq!(record.key, key);
+    }
+}
+
+#[test]
+fn test_iteration_skips_trailing_padding_blocks() {
+    let values = get_test_data(300);
+    let options = DharmaOpts::default();
+    let written_path = write_sstable(&options, &values, 0).unwrap().unwrap();
+    let data_blocks = File::open(&written_path).unwrap().metadata().unwrap().len() as usize
+        / options.block_size_in_bytes;
+    // append blocks made up entirely of padding to the end of the table
+    let mut file = OpenOptions::new().append(true).open(&written_path).unwrap();
+    let padding = vec![0u8; options.block_size_in_bytes * 4];
+    file.write_all(&padding).unwrap();
+    file.sync_all().unwrap();
+
+    let mut reader = SSTableReader::from(&written_path, options.block_size_in_bytes).unwrap();
+    let mut count = 0;
+    while reader.has_next() {
+        let value = reader.read();
+        assert!(value.to_record::<TestKey, TestValue>().is_ok());
+        count += 1;
+        reader.next();
+    }
+    assert_eq!(count, values.len());
+    assert!(reader.blocks_read() <= data_blocks);
+}
+
+#[test]
+fn test_offsets_are_valid_seek_targets() {
+    let values = get_test_data_with_split_values(200);
+    let mut options = DharmaOpts::default();
+    options.block_size_in_bytes = 64;
+    let written_path = write_sstable(&options, &values, 0).unwrap().unwrap();
+    let mut reader = SSTableReader::from(&written_path, options.block_size_in_bytes).unwrap();
+    let offsets: Vec<(usize, usize)> = reader.offsets().map(Result::unwrap).collect();
+    assert_eq!(offsets.len(), values.len());
+    for ((offset, length), (key, value)) in offsets.into_iter().zip(values) {
+        let mut reader = SSTableReader::from(&written_path, options.block_size_in_bytes).unwrap();
+        assert!(reader.seek_closest(offset).is_ok());
+        let sstable_value = reader.read();
+        assert_eq!(sstable_value.data.len(), length);
+        let record: Value<TestKey, TestValue> = sstable_value.to_record().unwrap();
+        assert_eq!((record.key, record.value), (key, value));
+    }
+}
+
+#[test]
+fn test_seek_to_key() {
+    // only even keys are written so that odd keys are absent
+    let values: Vec<(TestKey, TestValue)> = get_test_data_with_split_values(400)
+        .into_iter()
+        .step_by(2)
+        .collect();
+    let mut options = DharmaOpts::default();
+    options.block_size_in_bytes = 64;
+    let written_path = write_sstable(&options, &values, 0).unwrap().unwrap();
+    let mut reader = SSTableReader::from(&written_path, options.block_size_in_bytes).unwrap();
+    for target in [0, 1, 2, 57, 100, 255, 398] {
+        let found = reader.seek_to_key(&TestKey::from(target)).unwrap();
+        assert_eq!(found, target % 2 == 0);
+        let expected = if target % 2 == 0 { target } else { target + 1 };
+        let record: Value<TestKey, TestValue> = reader.read().to_record().unwrap();
+        assert_eq!(record.key, TestKey::from(expected));
+    }
+    // keys past the last key leave nothing to read
+    assert!(!reader.seek_to_key(&TestKey::from(399)).unwrap());
+    assert!(!reader.has_next());
+    // walking from the offset of a smaller key finds the same record
+    reader.seek_closest(0).unwrap();
+    let offsets: Vec<(usize, usize)> = reader.offsets().map(Result::unwrap).collect();
+    let (offset, _) = offsets[50];
+    assert!(!reader
+        .seek_to_key_from(&TestKey::from(151), offset)
+        .unwrap());
+    let record: Value<TestKey, TestValue> = reader.read().to_record().unwrap();
+    assert_eq!(record.key, TestKey::from(152));
+}
+
+#[test]
+fn test_streaming_writer_fills_blocks_like_batch_writer() {
+    let values: Vec<Value<TestKey, TestValue>> = get_test_data(2000)
+        .into_iter()
+        .map(|(key, value)| Value::new(key, value))
+        .collect();
+    let mut options = DharmaOpts::default();
+    options.block_size_in_bytes = 256;
+    let mut batch_blocks = Vec::new();
+    create_blocks(&options, &values, &mut batch_blocks).unwrap();
+
+    let path = PathBuf::from(format!("{}/tables/streamed.db", options.path));
+    let mut writer = SSTableWriter::create(&options, &path).unwrap();
+    for value in &values {
+        assert!(writer.append(value).is_ok());
+    }
+    let blocks_written = writer.finish().unwrap();
+    assert_eq!(blocks_written, batch_blocks.len());
+    let file_size = File::open(&path).unwrap().metadata().unwrap().len() as usize;
+    assert_eq!(file_size, batch_blocks.len() * options.block_size_in_bytes);
+    // streamed values are read back in order
+    let mut reader = SSTableReader::from(&path, options.block_size_in_bytes).unwrap();
+    let mut count = 0;
+    while reader.has_next() {
+        let record: Value<TestKey, TestValue> = reader.read().to_record().unwrap();
+        assert_eq!(record.key, values[count].key);
+        count += 1;
+        reader.next();
+    }
+    assert_eq!(count, values.len());
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_reads_final_partial_block_of_unpadded_table() {
+    let values = get_test_data(100);
+    let mut options = DharmaOpts::default();
+    options.block_size_in_bytes = 256;
+    let backend = InMemoryBackend::new();
+    let mut blocks = Vec::new();
+    let records: Vec<Value<TestKey, TestValue>> = values
+        .iter()
+        .map(|(key, value)| Value::new(key.clone(), value.clone()))
+        .collect();
+    // tables without a footer were written in format version 1
+    create_blocks_with_codec(&options, &FormatVersion1Codec, &records, &[], &mut blocks).unwrap();
+    let mut data: Vec<u8> = Vec::new();
+    for block in &blocks {
+        write_block_to_disk(&options, &mut data, block).unwrap();
+    }
+    // drop the padding that fills up the last block
+    let last_block_size: usize = blocks
+        .last()
+        .unwrap()
+        .records
+        .iter()
+        .map(|record| Record::RECORD_BASE_SIZE_IN_BYTES + record.data.len())
+        .sum();
+    data.truncate((blocks.len() - 1) * options.block_size_in_bytes + last_block_size);
+    assert_ne!(data.len() % options.block_size_in_bytes, 0);
+    let path = PathBuf::from("/unpadded/tables/0.db");
+    backend.create(&path).unwrap().write(&data).unwrap();
+
+    let mut reader =
+        SSTableReader::with_backend(&backend, &path, options.block_size_in_bytes).unwrap();
+    let mut output = Vec::new();
+    while reader.has_next() {
+        let record: Value<TestKey, TestValue> = reader.read().to_record().unwrap();
+        output.push((record.key, record.value));
+        reader.next();
+    }
+    assert!(!reader.read_failed());
+    assert_eq!(output, values);
+}
+
+#[test]
+fn test_short_read_before_end_of_table_fails() {
+    let values = get_test_data(100);
+    let mut options = DharmaOpts::default();
+    options.block_size_in_bytes = 256;
+    let backend = InMemoryBackend::new();
+    options.backend = Arc::new(backend.clone());
+    let path = PathBuf::from("/short/tables/0.db");
+    write_sstable_at_path(&options, &values, &path).unwrap();
+    let mut reader =
+        SSTableReader::with_backend(&backend, &path, options.block_size_in_bytes).unwrap();
+    // shrink the table to its first block after the reader has been opened
+    let mut first_block = vec![0u8; options.block_size_in_bytes];
+    backend
+        .open(&path)
+        .unwrap()
+        .read_at(&mut first_block, 0)
+        .unwrap();
+    backend.create(&path).unwrap().write(&first_block).unwrap();
+
+    let mut count = 0;
+    while reader.has_next() {
+        reader.next();
+        count += 1;
+    }
+    assert!(reader.read_failed());
+    assert!(count < values.len());
+    assert!(reader
+        .seek_closest(options.block_size_in_bytes * 2)
+        .is_err());
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct StringKey(String);
+
+impl Display for StringKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl ResourceKey for StringKey {}
+
+#[test]
+fn test_prefix_compressed_keys() {
+    let values: Vec<Value<StringKey, TestValue>> = (0..500)
+        .map(|i| {
+            let key = StringKey(format!("user:profile:settings:{:06}", i));
+            Value::new(key, TestValue::from(format!("value is {}", i).as_str()))
+        })
+        .collect();
+    let mut options = DharmaOpts::default();
+    options.block_size_in_bytes = 256;
+    let backend = InMemoryBackend::new();
+    options.backend = Arc::new(backend.clone());
+    let plain_path = PathBuf::from("/prefix/tables/0.db");
+    write_values_at_path(&options, &values, &plain_path).unwrap();
+    options.prefix_compress_keys = true;
+    let compressed_path = PathBuf::from("/prefix/tables/1.db");
+    write_values_at_path(&options, &values, &compressed_path).unwrap();
+    let plain_size = backend.open(&plain_path).unwrap().size().unwrap();
+    let compressed_size = backend.open(&compressed_path).unwrap().size().unwrap();
+    assert!(compressed_size * 4 < plain_size * 3);
+
+    let mut reader =
+        SSTableReader::with_backend(&backend, &compressed_path, options.block_size_in_bytes)
+            .unwrap();
+    let mut output = Vec::new();
+    while reader.has_next() {
+        let record: Value<StringKey, TestValue> = reader.read().to_record().unwrap();
+        output.push((record.key, record.value));
+        reader.next();
+    }
+    let expected: Vec<(StringKey, TestValue)> = values
+        .iter()
+        .map(|value| (value.key.clone(), value.value.clone()))
+        .collect();
+    assert_eq!(output, expected);
+    // keys are restored when reading from the middle of a block
+    let offsets: Vec<(usize, usize)> = reader_for(&backend, &compressed_path, &options)
+        .offsets()
+        .map(Result::unwrap)
+        .collect();
+    for ((offset, _), (key, value)) in offsets.into_iter().zip(expected).rev() {
+        let mut reader = reader_for(&backend, &compressed_path, &options);
+        reader.seek_closest(offset).unwrap();
+        let record: Value<StringKey, TestValue> = reader.read().to_record().unwrap();
+        assert_eq!((record.key, record.value), (key, value));
+    }
+}
+
+fn reader_for(backend: &InMemoryBackend, path: &PathBuf, options: &DharmaOpts) -> SSTableReader {
+    let value_log_path = ValueLog::path(options);
+    SSTableReader::with_value_log(backend, path, options.block_size_in_bytes, &value_log_path)
+        .unwrap()
+}
+
+#[test]
+fn test_unknown_record_type_fails_read() {
+    let values = get_test_data(100);
+    let mut options = DharmaOpts::default();
+    options.block_size_in_bytes = 256;
+    let backend = InMemoryBackend::new();
+    options.backend = Arc::new(backend.clone());
+    let path = PathBuf::from("/unknown/tables/0.db");
+    write_sstable_at_path(&options, &values, &path).unwrap();
+    let mut reader =
+        SSTableReader::with_backend(&backend, &path, options.block_size_in_bytes).unwrap();
+    let offsets: Vec<usize> = reader.offsets().map(|offset| offset.unwrap().0).collect();
+    // replace the type of the second record with a reserved record type
+    let mut file = backend.open(&path).unwrap();
+    let mut data = vec![0u8; file.size().unwrap() as usize];
+    file.read_at(&mut data, 0).unwrap();
+    data[offsets[1]] = Record::RECORD_TYPE_MASK;
+    backend.create(&path).unwrap().write(&data).unwrap();
+
+    let mut reader =
+        SSTableReader::with_backend(&backend, &path, options.block_size_in_bytes).unwrap();
+    assert!(reader.read().to_record::<TestKey, TestValue>().is_ok());
+    reader.next();
+    assert!(reader.has_next());
+    assert!(reader.read().to_record::<TestKey, TestValue>().is_err());
+    assert!(reader.read_failed());
+    reader.next();
+    assert!(!reader.has_next());
+}
+
+#[test]
+fn test_read_sstable_with_wrong_block_size_fails() {
+    let values = get_test_data(500);
+    let mut options = DharmaOpts::default();
+    options.block_size_in_bytes = 256;
+    options.backend = Arc::new(InMemoryBackend::new());
+    let path = PathBuf::from("/block_size_mismatch/tables/0.db");
+    write_sstable_at_path(&options, &values, &path).unwrap();
+    assert_eq!(
+        read_sstable::<TestKey, TestValue>(&options, &path)
+            .unwrap()
+            .len(),
+        values.len()
+    );
+    // the table is not made up of whole blocks of this size
+    options.block_size_in_bytes = 300;
+    let read_result = read_sstable::<TestKey, TestValue>(&options, &path);
+    assert!(read_result.err() == Some(Errors::SSTABLE_BLOCK_SIZE_MISMATCH));
+    // records are cut at offsets that don't match the blocks they were written to
+    options.block_size_in_bytes = 128;
+    let read_result = read_sstable::<TestKey, TestValue>(&options, &path);
+    assert!(read_result.err() == Some(Errors::SSTABLE_BLOCK_SIZE_MISMATCH));
+}
+
+#[test]
+fn test_unknown_record_type_does_not_hang() {
+    let values = get_test_data(100);
+    let mut options = DharmaOpts::default();
+    options.block_size_in_bytes = 256;
+    let backend = InMemoryBackend::new();
+    options.backend = Arc::new(backend.clone());
+    let path = PathBuf::from("/unknown_type/tables/0.db");
+    write_sstable_at_path(&options, &values, &path).unwrap();
+    // a record type that is not written by this version of the database
+    let mut file = backend.open(&path).unwrap();
+    let mut data = vec![0u8; file.size().unwrap() as usize];
+    file.read_at(&mut data, 0).unwrap();
+    data[0] = 6;
+    backend.create(&path).unwrap().write(&data).unwrap();
+
+    let read_result = read_sstable::<TestKey, TestValue>(&options, &path);
+    assert!(read_result.is_err());
+    let mut reader =
+        SSTableReader::with_backend(&backend, &path, options.block_size_in_bytes).unwrap();
+    let mut count = 0;
+    while reader.has_next() && count <= values.len() {
+        reader.next();
+        count += 1;
+    }
+    assert_eq!(count, 1);
+    assert!(reader.read_failed());
+}
+
+#[test]
+fn test_value_accessors_and_tombstones() {
+    let mut values = get_test_data(100);
+    values[10].1 = TestValue::nil();
+    values[20].1 = TestValue::nil();
+    let options = DharmaOpts::default();
+    let written_path = write_sstable(&options, &values, 0).unwrap().unwrap();
+    let mut reader = SSTableReader::from(&written_path, options.block_size_in_bytes).unwrap();
+    let mut tombstones = Vec::new();
+    let mut result = Vec::new();
+    while reader.has_next() {
+        let record: Value<TestKey, TestValue> = reader.read().to_record().unwrap();
+        if record.is_tombstone() {
+            tombstones.push(record.key().clone());
+        }
+        assert_eq!(record.value(), &record.value);
+        result.push(record.into_tuple());
+        reader.next();
+    }
+    assert_eq!(tombstones, vec![TestKey::from(10), TestKey::from(20)]);
+    assert_eq!(result, values);
+}
+
+#[test]
+fn test_read_raw_round_trips() {
+    let values = get_test_data(300);
+    for separation in vec![None, Some(0)] {
+        let mut options = DharmaOpts::default();
+        options.block_size_in_bytes = 256;
+        options.value_separation_min_bytes = separation;
+        options.path = String::from("/raw");
+        let backend = InMemoryBackend::new();
+        options.backend = Arc::new(backend.clone());
+        let path = PathBuf::from("/raw/tables/0.db");
+        write_sstable_at_path(&options, &values, &path).unwrap();
+        let mut reader = reader_for(&backend, &path, &options);
+        let mut read_values = Vec::new();
+        while reader.has_next() {
+            let (key_bytes, value_bytes) = reader.read_raw::<TestKey>().unwrap();
+            let key: TestKey = bincode::deserialize(&key_bytes).unwrap();
+            let value: TestValue = bincode::deserialize(&value_bytes).unwrap();
+            read_values.push((key, value));
+            reader.next();
+        }
+        assert_eq!(read_values, values);
+    }
+}
+
+#[test]
+fn test_to_key_matches_decoded_record() {
+    let values = get_test_data(300);
+    for encoding in &[Encoding::Fixint, Encoding::Varint] {
+        for separation in vec![None, Some(0)] {
+            let mut options = DharmaOpts::default();
+            options.block_size_in_bytes = 256;
+            options.value_separation_min_bytes = separation;
+            options.encoding = *encoding;
+            options.path = String::from("/keys");
+            let backend = InMemoryBackend::new();
+            options.backend = Arc::new(backend.clone());
+            let path = PathBuf::from("/keys/tables/0.db");
+            write_sstable_at_path(&options, &values, &path).unwrap();
+            let mut reader = reader_for(&backend, &path, &options);
+            let mut records = 0;
+            while reader.has_next() {
+                let sstable_value = reader.read();
+                let record: Value<TestKey, TestValue> = sstable_value.to_record().unwrap();
+                assert_eq!(sstable_value.to_key::<TestKey>().unwrap(), record.key);
+                // inline sequence numbers can't be located without the value as varints
+                let seq = sstable_value.to_seq::<TestKey>().unwrap();
+                if *encoding == Encoding::Varint && separation.is_none() {
+                    assert_eq!(seq, None);
+                } else {
+                    assert_eq!(seq, Some(record.seq));
+                }
+                records += 1;
+                reader.next();
+            }
+            assert_eq!(records, values.len());
+        }
+    }
+}
+
+#[test]
+fn test_sstables_io_with_each_encoding() {
+    let values = get_test_data(2000);
+    let backend = InMemoryBackend::new();
+    let mut sizes = Vec::new();
+    for encoding in &[Encoding::Fixint, Encoding::Varint] {
+        let mut options = DharmaOpts::default();
+        options.block_size_in_bytes = 256;
+        options.backend = Arc::new(backend.clone());
+        options.encoding = *encoding;
+        let path = PathBuf::from(format!("/encoding/tables/{:?}.db", encoding));
+        write_sstable_at_path(&options, &values, &path).unwrap();
+        sizes.push(backend.open(&path).unwrap().size().unwrap());
+        // the encoding is read from the table instead of the options
+        let mut reader =
+            SSTableReader::with_backend(&backend, &path, options.block_size_in_bytes).unwrap();
+        let mut output = Vec::new();
+        while reader.has_next() {
+            let record: Value<TestKey, TestValue> = reader.read().to_record().unwrap();
+            output.push((record.key, record.value));
+            reader.next();
+        }
+        assert_eq!(output, values);
+        let read_values: Vec<Value<TestKey, TestValue>> = read_sstable(&options, &path).unwrap();
+        assert_eq!(read_values.len(), values.len());
+    }
+    // integer keys, string lengths and sequence numbers take fewer bytes as varints
+    assert!(sizes[1] < sizes[0]);
+}
+
+struct JsonCodec;
+
+impl RecordCodec for JsonCodec {
+    fn id(&self) -> u8 {
+        1
+    }
+
+    fn encode<K: Serialize, V: Serialize>(&self, value: &Value<K, V>) -> Result<Vec<u8>> {
+        serde_json::to_vec(value).map_err(|_| Errors::RECORD_SERIALIZATION_FAILED)
+    }
+
+    fn decode<K: DeserializeOwned, V: DeserializeOwned>(&self, data: &[u8]) -> Result<Value<K, V>> {
+        serde_json::from_slice(data).map_err(|_| Errors::RECORD_DESERIALIZATION_FAILED)
+    }
+}
+
+#[test]
+fn test_sstables_io_with_custom_codec() {
+    let values: Vec<Value<TestKey, TestValue>> = get_test_data(700)
+        .into_iter()
+        .map(|(key, value)| Value::new(key, value))
+        .collect();
+    let backend = InMemoryBackend::new();
+    let mut options = DharmaOpts::default();
+    options.backend = Arc::new(backend.clone());
+    let path = PathBuf::from("/codec/tables/0.db");
+    write_table_with_codec(&options, &JsonCodec, &values, &[], &path).unwrap();
+    // records are stored as json
+    let mut reader =
+        SSTableReader::with_backend(&backend, &path, options.block_size_in_bytes).unwrap();
+    assert_eq!(reader.codec_id(), 1);
+    let first = reader.read();
+    assert!(serde_json::from_slice::<serde_json::Value>(&first.data).is_ok());
+    assert!(first.to_record::<TestKey, TestValue>().is_err());
+    let mut output = Vec::new();
+    while reader.has_next() {
+        let record: Value<TestKey, TestValue> = reader.read().to_record_with(&JsonCodec).unwrap();
+        output.push(record.into_tuple());
+        reader.next();
+    }
+    assert_eq!(output, get_test_data(700));
+    let read_values: Vec<Value<TestKey, TestValue>> =
+        read_sstable_with_codec(&options, &JsonCodec, &path).unwrap();
+    let read_tuples: Vec<(TestKey, TestValue)> = read_values
+        .into_iter()
+        .map(|value| value.into_tuple())
+        .collect();
+    assert_eq!(read_tuples, get_test_data(700));
+    // the codec recorded in the footer must match the codec reading the table
+    assert!(read_sstable::<TestKey, TestValue>(&options, &path).is_err());
+}
+
+#[test]
+fn test_default_codec_is_recorded_as_bincode() {
+    let backend = InMemoryBackend::new();
+    let mut options = DharmaOpts::default();
+    options.backend = Arc::new(backend.clone());
+    let path = PathBuf::from("/codec/tables/1.db");
+    write_sstable_at_path(&options, &get_test_data(100), &path).unwrap();
+    let reader = SSTableReader::with_backend(&backend, &path, options.block_size_in_bytes).unwrap();
+    assert_eq!(reader.codec_id(), BINCODE_CODEC_ID);
+}
+
+// values of format version 1 tables don't have a sequence number
+#[derive(Serialize)]
+struct ValueV1 {
+    key: TestKey,
+    value: TestValue,
+}
+
+/// Serializes records in the layout of format version 1 tables.
+struct FormatVersion1Codec;
+
+impl RecordCodec for FormatVersion1Codec {
+    fn id(&self) -> u8 {
+        BINCODE_CODEC_ID
+    }
+
+    fn encode<K: Serialize, V: Serialize>(&self, value: &Value<K, V>) -> Result<Vec<u8>> {
+        bincode::serialize(&(&value.key, &value.value))
+            .map_err(|_| Errors::RECORD_SERIALIZATION_FAILED)
+    }
+
+    fn decode<K: DeserializeOwned, V: DeserializeOwned>(
+        &self,
+        _data: &[u8],
+    ) -> Result<Value<K, V>> {
+        Err(Errors::RECORD_DESERIALIZATION_FAILED)
+    }
+}
+
+/// Write the values to a table at the path the way tables were written before
+/// footers existed, as padded blocks of format version 1 records and nothing else.
+fn write_format_version_1_table(
+    options: &DharmaOpts,
+    values: &Vec<(TestKey, TestValue)>,
+    path: &PathBuf,
+) {
+    let records: Vec<Value<TestKey, TestValue>> = values
+        .iter()
+        .map(|(key, value)| Value::new(key.clone(), value.clone()))
+        .collect();
+    let mut blocks = Vec::new();
+    create_blocks_with_codec(options, &FormatVersion1Codec, &records, &[], &mut blocks).unwrap();
+    let mut file = options.backend.create(path).unwrap();
+    for block in &blocks {
+        write_block_to_disk(options, file.as_mut(), block).unwrap();
+    }
+}
+
+#[test]
+fn test_read_format_version_1_table() {
+    let values = get_test_data(50);
+    let backend = InMemoryBackend::new();
+    let mut options = DharmaOpts::default();
+    options.backend = Arc::new(backend.clone());
+    // pack complete records into a single block followed by padding and the footer
+    let mut data = Vec::new();
+    for (key, value) in &values {
+        let record = bincode::serialize(&ValueV1 {
+            key: key.clone(),
+            value: value.clone(),
+        })
+        .unwrap();
+        data.push(1u8);
+        data.extend_from_slice(&(record.len() as u16).to_be_bytes());
+        data.extend(record);
+    }
+    let mut footer = to_table_version_footer(1);
+    footer.extend(Encoding::Fixint.to_footer());
+    let padding_size = options.block_size_in_bytes - data.len() - Record::RECORD_BASE_SIZE_IN_BYTES;
+    data.push(0u8);
+    data.extend_from_slice(&(padding_size as u16).to_be_bytes());
+    data.resize(options.block_size_in_bytes - footer.len(), 0);
+    data.extend(footer);
+    let path = PathBuf::from("/legacy/tables/0.db");
+    backend.create(&path).unwrap().write(&data).unwrap();
+
+    let mut reader =
+        SSTableReader::with_backend(&backend, &path, options.block_size_in_bytes).unwrap();
+    assert_eq!(reader.format_version(), 1);
+    let mut output = Vec::new();
+    while reader.has_next() {
+        let sstable_value = reader.read();
+        assert_eq!(sstable_value.to_seq::<TestKey>().unwrap(), Some(0));
+        let record: Value<TestKey, TestValue> = sstable_value.to_record().unwrap();
+        assert_eq!(record.seq, 0);
+        output.push(record.into_tuple());
+        reader.next();
+    }
+    assert_eq!(output, values);
+
+    // tables written now record the current format version
+    let current_path = PathBuf::from("/legacy/tables/1.db");
+    write_sstable_at_path(&options, &values, &current_path).unwrap();
+    let reader =
+        SSTableReader::with_backend(&backend, &current_path, options.block_size_in_bytes).unwrap();
+    assert_eq!(reader.format_version(), FORMAT_VERSION);
+}
+
+#[test]
+fn test_read_table_without_footer_as_format_version_1() {
+    let mut values = get_test_data(200);
+    // a large value is split across blocks
+    values[100].1 = TestValue::from(&"x".repeat(600));
+    let mut options = DharmaOpts::default();
+    options.block_size_in_bytes = 256;
+    options.backend = Arc::new(InMemoryBackend::new());
+    let path = PathBuf::from(format!("{}/tables/0.db", options.path));
+    write_format_version_1_table(&options, &values, &path);
+
+    let mut reader =
+        SSTableReader::with_backend(options.backend.as_ref(), &path, options.block_size_in_bytes)
+            .unwrap();
+    assert_eq!(reader.format_version(), 1);
+    let footer = SSTableReader::read_footer_with_backend(options.backend.as_ref(), &path).unwrap();
+    assert_eq!(footer.format_version, 1);
+    assert_eq!(footer.records, None);
+    let mut output = Vec::new();
+    while reader.has_next() {
+        let record: Value<TestKey, TestValue> = reader.read().to_record().unwrap();
+        assert_eq!(record.seq, 0);
+        output.push(record.into_tuple());
+        reader.next();
+    }
+    assert!(!reader.read_failed());
+    assert_eq!(output, values);
+    let records = read_sstable::<TestKey, TestValue>(&options, &path).unwrap();
+    assert_eq!(
+        records
+            .into_iter()
+            .map(|record| record.into_tuple())
+            .collect::<Vec<(TestKey, TestValue)>>(),
+        values
+    );
+
+    // the database reads the table and newer writes win over its values
+    let mut db: Dharma<TestKey, TestValue> = Dharma::create(options).unwrap();
+    for (key, value) in &values {
+        assert_eq!(db.get(key).unwrap(), Some(value.clone()));
+    }
+    db.put(TestKey::from(5), TestValue::from("updated"))
+        .unwrap();
+    db.flush().unwrap();
+    assert_eq!(
+        db.get(&TestKey::from(5)).unwrap(),
+        Some(TestValue::from("updated"))
+    );
+}
+
+#[test]
+fn test_verify_table_checksum() {
+    let values = get_test_data(100);
+    let mut options = DharmaOpts::default();
+    options.block_size_in_bytes = 256;
+    let backend = InMemoryBackend::new();
+    options.backend = Arc::new(backend.clone());
+    let path = PathBuf::from("/checksum/tables/0.db");
+    write_sstable_at_path(&options, &values, &path).unwrap();
+    let mut reader = reader_for(&backend, &path, &options);
+    assert!(reader.verify_table_checksum().unwrap());
+
+    let size = backend.open(&path).unwrap().size().unwrap() as usize;
+    let mut data = vec![0u8; size];
+    backend.open(&path).unwrap().read_at(&mut data, 0).unwrap();
+    // drop a block from the middle of the table keeping the footer intact
+    let block_size = options.block_size_in_bytes;
+    let mut truncated = data[..block_size].to_vec();
+    truncated.extend_from_slice(&data[block_size * 2..]);
+    backend.create(&path).unwrap().write(&truncated).unwrap();
+    let mut reader = reader_for(&backend, &path, &options);
+    assert!(!reader.verify_table_checksum().unwrap());
+
+    // drop the end of the table along with the footer
+    backend
+        .create(&path)
+        .unwrap()
+        .write(&data[..size - block_size / 2])
+        .unwrap();
+    let mut reader = reader_for(&backend, &path, &options);
+    assert!(!reader.verify_table_checksum().unwrap());
+}
+
+#[test]
+fn test_preallocated_tables_match_appended_tables() {
+    let values = get_test_data(500);
+    let mut options = DharmaOpts::default();
+    options.block_size_in_bytes = 256;
+    let backend = InMemoryBackend::new();
+    options.backend = Arc::new(backend.clone());
+    let appended_path = PathBuf::from("/preallocate/tables/0.db");
+    write_sstable_at_path(&options, &values, &appended_path).unwrap();
+    options.preallocate_tables = true;
+    let preallocated_path = PathBuf::from("/preallocate/tables/1.db");
+    write_sstable_at_path(&options, &values, &preallocated_path).unwrap();
+
+    let read_all = |path: &PathBuf| {
+        let mut file = backend.open(path).unwrap();
+        let mut data = vec![0u8; file.size().unwrap() as usize];
+        file.read_at(&mut data, 0).unwrap();
+        data
+    };
+    let preallocated = read_all(&preallocated_path);
+    assert_eq!(preallocated, read_all(&appended_path));
+    assert_eq!(preallocated.len() % options.block_size_in_bytes, 0);
+    let mut reader = reader_for(&backend, &preallocated_path, &options);
+    assert!(reader.verify_table_checksum().unwrap());
+}
+
+/// File that runs out of space after the supplied number of bytes. The write
+/// exceeding the limit only stores the bytes that still fit.
+struct ShortWriteFile {
+    data: Vec<u8>,
+    capacity: usize,
+}
+
+impl StorageFile for ShortWriteFile {
+    fn read_at(&mut self, buffer: &mut [u8], offset: u64) -> std::io::Result<usize> {
+        self.data.read_at(buffer, offset)
+    }
+
+    fn write(&mut self, data: &[u8]) -> std::io::Result<()> {
+        let available = self.capacity - self.data.len();
+        if data.len() > available {
+            self.data.extend_from_slice(&data[..available]);
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::WriteZero,
+                "no space left",
+            ));
+        }
+        self.data.extend_from_slice(data);
+        Ok(())
+    }
+
+    fn size(&self) -> std::io::Result<u64> {
+        Ok(self.data.len() as u64)
+    }
+
+    fn sync(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_short_writes_fail_block_write() {
+    let mut options = DharmaOpts::default();
+    options.block_size_in_bytes = 256;
+    let values = get_test_data(5)
+        .into_iter()
+        .map(|(key, value)| Value::new(key, value))
+        .collect::<Vec<Value<TestKey, TestValue>>>();
+    let mut blocks = Vec::new();
+    create_blocks(&options, &values, &mut blocks).unwrap();
+    let block = &blocks[0];
+    for capacity in 0..=options.block_size_in_bytes {
+        let mut file = ShortWriteFile {
+            data: Vec::new(),
+            capacity,
+        };
+        match write_block_to_disk(&options, &mut file, block) {
+            Ok(()) => assert_eq!(file.data.len(), options.block_size_in_bytes),
+            Err(error) => {
+                assert!(capacity < options.block_size_in_bytes);
+                assert!(error == Errors::BLOCK_WRITE_FAILED);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_dump_table_formats() {
+    let mut options = DharmaOpts::default();
+    options.backend = Arc::new(InMemoryBackend::new());
+    let path = PathBuf::from(format!("{}/dump.db", options.path));
+    let values = vec![
+        Value::with_seq(TestKey::from(1), TestValue::from("ab"), 3),
+        Value::with_seq(TestKey::from(2), TestValue::from("cd"), 4),
+    ];
+    let tombstones = vec![RangeTombstone::new(TestKey::from(1), TestKey::from(5), 7)];
+    assert!(write_table_at_path(&options, &values, &tombstones, &path).is_ok());
+    let dump = |format| dump_table::<TestKey, TestValue>(&options, &path, format).unwrap();
+
+    let lines = dump(DebugFormat::Display);
+    assert_eq!(lines.len(), 3);
+    assert_eq!(lines[0], "0: 1 => ab @3");
+    assert!(lines[1].ends_with(": 2 => cd @4"));
+    assert!(lines[2].ends_with(": [1, 5) deleted @7"));
+
+    let lines = dump(DebugFormat::Hex);
+    assert_eq!(lines[0], "0: 01000000 => 02000000000000006162 @3");
+    assert!(lines[2].ends_with(": [01000000, 05000000) deleted @7"));
+
+    let lines = dump(DebugFormat::Base64);
+    assert_eq!(lines[0], "0: AQAAAA== => AgAAAAAAAABhYg== @3");
+    assert!(lines[2].ends_with(": [AQAAAA==, BQAAAA==) deleted @7"));
+}
+
+#[test]
+fn test_split_value_whose_end_chunk_fills_the_block() {
+    let mut options = DharmaOpts::default();
+    options.backend = Arc::new(InMemoryBackend::new());
+    options.block_size_in_bytes = 64;
+    let chunk_size = options.block_size_in_bytes - Record::RECORD_BASE_SIZE_IN_BYTES;
+    // the START chunk fills the first block and the END chunk the last byte of the second
+    let split_value = (0..200)
+        .map(|length| {
+            Value::new(
+                TestKey::from(0),
+                TestValue::from("x".repeat(length).as_str()),
+            )
+        })
+        .find(|value| Encoding::default().serialize(value).unwrap().len() == 2 * chunk_size)
+        .unwrap();
+    let values = vec![
+        split_value,
+        Value::new(TestKey::from(1), TestValue::from("after")),
+    ];
+    let mut blocks = Vec::new();
+    create_blocks(&options, &values, &mut blocks).unwrap();
+    assert_eq!(blocks.len(), 3);
+    assert_eq!(blocks[1].records.len(), 1);
+    assert_eq!(blocks[1].records[0].data_size_in_bytes as usize, chunk_size);
+    let path = PathBuf::from("/split/blocks");
+    let mut file = options.backend.create(&path).unwrap();
+    for (count, block) in blocks.iter().enumerate() {
+        write_block_to_disk(&options, file.as_mut(), block).unwrap();
+        let expected_size = (count + 1) * options.block_size_in_bytes;
+        assert_eq!(file.size().unwrap(), expected_size as u64);
+    }
+
+    let tuples: Vec<(TestKey, TestValue)> = values
+        .iter()
+        .map(|value| (value.key.clone(), value.value.clone()))
+        .collect();
+    let written_path = write_sstable(&options, &tuples, 0).unwrap().unwrap();
+    let size = options.backend.open(&written_path).unwrap().size().unwrap();
+    assert_eq!(size % options.block_size_in_bytes as u64, 0);
+    let mut reader = SSTableReader::with_backend(
+        options.backend.as_ref(),
+        &written_path,
+        options.block_size_in_bytes,
+    )
+    .unwrap();
+    let mut result: Vec<(TestKey, TestValue)> = Vec::new();
+    while reader.has_next() {
+        let record: Value<TestKey, TestValue> = reader.read().to_record().unwrap();
+        result.push((record.key, record.value));
+        reader.next();
+    }
+    assert!(!reader.read_failed());
+    assert_eq!(result, tuples);
+}
+
+#[test]
+fn test_stream_table_records_to_writer() {
+    let mut options = DharmaOpts::default();
+    options.backend = Arc::new(InMemoryBackend::new());
+    options.block_size_in_bytes = 512;
+    let path = PathBuf::from(format!("{}/stream.db", options.path));
+    let values: Vec<Value<TestKey, TestValue>> = get_test_data(300)
+        .into_iter()
+        .enumerate()
+        .map(|(seq, (key, value))| Value::with_seq(key, value, seq as u64))
+        .collect();
+    let tombstones = vec![RangeTombstone::new(
+        TestKey::from(10),
+        TestKey::from(20),
+        500,
+    )];
+    assert!(write_table_at_path(&options, &values, &tombstones, &path).is_ok());
+    let mut reader =
+        SSTableReader::with_backend(options.backend.as_ref(), &path, options.block_size_in_bytes)
+            .unwrap();
+    let mut output = Vec::new();
+    assert_eq!(reader.stream_to(&mut output).unwrap(), 301);
+
+    let mut input = output.as_slice();
+    let mut streamed_values = Vec::new();
+    let mut streamed_tombstones = Vec::new();
+    while let Some(record) = read_streamed_record(&mut input).unwrap() {
+        let sstable_value = record.to_sstable_value().unwrap();
+        if sstable_value.range_tombstone {
+            streamed_tombstones.push(sstable_value.to_range_tombstone::<TestKey>().unwrap());
+        } else {
+            streamed_values.push(sstable_value.to_record::<TestKey, TestValue>().unwrap());
+        }
+    }
+    let to_entries = |values: &Vec<Value<TestKey, TestValue>>| -> Vec<(TestKey, TestValue, u64)> {
+        values
+            .iter()
+            .map(|value| (value.key.clone(), value.value.clone(), value.seq))
+            .collect()
+    };
+    assert_eq!(to_entries(&streamed_values), to_entries(&values));
+    assert_eq!(streamed_tombstones, tombstones);
+}
+
+#[test]
+fn test_range_iter_yields_keys_within_bounds() {
+    let mut options = DharmaOpts::default();
+    options.backend = Arc::new(InMemoryBackend::new());
+    options.block_size_in_bytes = 256;
+    let path = PathBuf::from(format!("{}/range.db", options.path));
+    let values: Vec<Value<TestKey, TestValue>> = get_test_data(500)
+        .into_iter()
+        .map(|(key, value)| Value::new(key, value))
+        .collect();
+    let tombstones = vec![RangeTombstone::new(TestKey::from(0), TestKey::from(500), 1)];
+    assert!(write_table_at_path(&options, &values, &tombstones, &path).is_ok());
+    let open = || {
+        SSTableReader::with_backend(options.backend.as_ref(), &path, options.block_size_in_bytes)
+            .unwrap()
+    };
+
+    let mut reader = open();
+    let keys: Vec<TestKey> = reader
+        .range_iter::<TestKey, TestValue>(&TestKey::from(120), &TestKey::from(340))
+        .unwrap()
+        .map(|record| record.unwrap().key)
+        .collect();
+    let expected: Vec<TestKey> = (120..=340).map(TestKey::from).collect();
+    assert_eq!(keys, expected);
+
+    // the range tombstones after the values are never yielded
+    let mut reader = open();
+    let records = reader
+        .range_iter::<TestKey, TestValue>(&TestKey::from(450), &TestKey::from(1000))
+        .unwrap()
+        .count();
+    assert_eq!(records, 50);
+    let mut reader = open();
+    let records = reader
+        .range_iter::<TestKey, TestValue>(&TestKey::from(600), &TestKey::from(700))
+        .unwrap()
+        .count();
+    assert_eq!(records, 0);
+}
+
+#[test]
+fn test_values_around_chunk_capacity_use_minimal_blocks() {
+    let mut options = DharmaOpts::default();
+    options.block_size_in_bytes = 64;
+    let chunk_size = options.block_size_in_bytes - Record::RECORD_BASE_SIZE_IN_BYTES;
+    let value_of_size = |key: u32, size: usize| {
+        (0..200)
+            .map(|length| {
+                Value::new(
+                    TestKey::from(key),
+                    TestValue::from("x".repeat(length).as_str()),
+                )
+            })
+            .find(|value| Encoding::default().serialize(value).unwrap().len() == size)
+            .unwrap()
+    };
+    let is_type =
+        |record: &Record, record_type: RecordType| record.record_type as u8 == record_type as u8;
+
+    // a value just under the capacity of a block is stored whole
+    let mut blocks = Vec::new();
+    create_blocks(
+        &options,
+        &vec![value_of_size(0, chunk_size - 2)],
+        &mut blocks,
+    )
+    .unwrap();
+    assert_eq!(blocks.len(), 1);
+    assert!(is_type(&blocks[0].records[0], RecordType::COMPLETE));
+
+    // a value just over the capacity of a block is split across two blocks
+    let mut blocks = Vec::new();
+    create_blocks(
+        &options,
+        &vec![value_of_size(0, chunk_size + 2)],
+        &mut blocks,
+    )
+    .unwrap();
+    assert_eq!(blocks.len(), 2);
+    assert!(is_type(&blocks[0].records[0], RecordType::START));
+    assert_eq!(blocks[0].records[0].data_size_in_bytes as usize, chunk_size);
+    assert!(is_type(&blocks[1].records[0], RecordType::END));
+    assert_eq!(blocks[1].records[0].data_size_in_bytes, 2);
+
+    // the split starts in the next block when the leftover space can't hold a
+    // record header and no block is left empty
+    let values = vec![
+        value_of_size(0, chunk_size - 2),
+        value_of_size(1, chunk_size + 2),
+        value_of_size(2, chunk_size - 2),
+    ];
+    let mut blocks = Vec::new();
+    create_blocks(&options, &values, &mut blocks).unwrap();
+    assert_eq!(blocks.len(), 4);
+    assert!(blocks.iter().all(|block| !block.records.is_empty()));
+    assert!(is_type(&blocks[1].records[0], RecordType::START));
+    assert_eq!(blocks[1].records[0].data_size_in_bytes as usize, chunk_size);
+    // the last value fills the space left after the split value
+    assert!(is_type(&blocks[2].records[0], RecordType::END));
+    assert!(is_type(&blocks[2].records[1], RecordType::START));
+    assert!(is_type(&blocks[3].records[0], RecordType::END));
+}
+
+#[test]
+fn test_block_headers_count_the_records_of_their_block() {
+    let mut values = get_test_data(100);
+    // a large value is split across blocks
+    values[50].1 = TestValue::from(&"x".repeat(600));
+    let mut options = DharmaOpts::default();
+    options.block_size_in_bytes = 256;
+    options.block_headers = true;
+    let backend = InMemoryBackend::new();
+    options.backend = Arc::new(backend.clone());
+    let path = PathBuf::from("/block_headers/tables/0.db");
+    write_sstable_at_path(&options, &values, &path).unwrap();
+
+    // offset of every value read back through the headers
+    let mut reader =
+        SSTableReader::with_backend(&backend, &path, options.block_size_in_bytes).unwrap();
+    assert!(reader.block_header().is_some());
+    let mut offsets = Vec::new();
+    let mut output = Vec::new();
+    while reader.has_next() {
+        let sstable_value = reader.read();
+        let record: Value<TestKey, TestValue> = sstable_value.to_record().unwrap();
+        offsets.push((sstable_value.offset, record.key.clone()));
+        output.push(record.into_tuple());
+        reader.next();
+    }
+    assert!(!reader.read_failed());
+    assert_eq!(output, values);
+
+    let mut file = backend.open(&path).unwrap();
+    let mut data = vec![0u8; file.size().unwrap() as usize];
+    file.read_at(&mut data, 0).unwrap();
+    let mut blocks_with_headers = 0;
+    for (block_number, block) in data.chunks(options.block_size_in_bytes).enumerate() {
+        // the footer may be written to a block of its own
+        if block[0] == 0 {
+            continue;
+        }
+        let (header, header_size) = BlockHeader::read(block).unwrap();
+        blocks_with_headers += 1;
+        let mut parsed = 0;
+        let mut r = header_size;
+        while r + Record::RECORD_BASE_SIZE_IN_BYTES <= block.len() && block[r] != 0 {
+            let size = u16::from_be_bytes([block[r + 1], block[r + 2]]) as usize;
+            r += Record::RECORD_BASE_SIZE_IN_BYTES + size;
+            parsed += 1;
+        }
+        assert_eq!(header.records, parsed);
+        let block_start = block_number * options.block_size_in_bytes;
+        let first_key = offsets
+            .iter()
+            .find(|(offset, _)| {
+                *offset >= block_start && *offset < block_start + options.block_size_in_bytes
+            })
+            .map(|(_, key)| options.encoding.serialize(key).unwrap())
+            .unwrap_or_default();
+        assert_eq!(header.first_key, first_key);
+    }
+    assert!(blocks_with_headers > 1);
+
+    // tables written without headers are read as before
+    options.block_headers = false;
+    let plain_path = PathBuf::from("/block_headers/tables/1.db");
+    write_sstable_at_path(&options, &values, &plain_path).unwrap();
+    let reader =
+        SSTableReader::with_backend(&backend, &plain_path, options.block_size_in_bytes).unwrap();
+    assert!(reader.block_header().is_none());
+    let output = read_sstable::<TestKey, TestValue>(&options, &plain_path).unwrap();
+    assert_eq!(
+        output
+            .into_iter()
+            .map(|value| value.into_tuple())
+            .collect::<Vec<_>>(),
+        values
+    );
+    let output = read_sstable::<TestKey, TestValue>(&options, &path).unwrap();
+    assert_eq!(output.len(), values.len());
+}
+
+#[test]
+fn test_seek_to_key_searches_block_headers() {
+    // only even keys are written so that odd keys are absent
+    let values: Vec<(TestKey, TestValue)> = get_test_data_with_split_values(2000)
+        .into_iter()
+        .step_by(2)
+        .collect();
+    let mut options = DharmaOpts::default();
+    options.block_size_in_bytes = 256;
+    options.block_headers = true;
+    let backend = InMemoryBackend::new();
+    options.backend = Arc::new(backend.clone());
+    let path = PathBuf::from("/seek_block_headers/tables/0.db");
+    write_sstable_at_path(&options, &values, &path).unwrap();
+    options.block_headers = false;
+    let plain_path = PathBuf::from("/seek_block_headers/tables/1.db");
+    write_sstable_at_path(&options, &values, &plain_path).unwrap();
+    let blocks = backend.open(&plain_path).unwrap().size().unwrap() as usize / 256;
+
+    for target in [0, 1, 2, 57, 100, 255, 998, 1001, 1998] {
+        let expected = if target % 2 == 0 { target } else { target + 1 };
+        let mut reader = SSTableReader::with_backend(&backend, &path, 256).unwrap();
+        assert_eq!(
+            reader.seek_to_key(&TestKey::from(target)).unwrap(),
+            target % 2 == 0
+        );
+        let record: Value<TestKey, TestValue> = reader.read().to_record().unwrap();
+        assert_eq!(record.key, TestKey::from(expected));
+        let mut plain_reader = SSTableReader::with_backend(&backend, &plain_path, 256).unwrap();
+        assert_eq!(
+            plain_reader.seek_to_key(&TestKey::from(target)).unwrap(),
+            target % 2 == 0
+        );
+        let record: Value<TestKey, TestValue> = plain_reader.read().to_record().unwrap();
+        assert_eq!(record.key, TestKey::from(expected));
+        // blocks are skipped by their first key instead of walking their records,
+        // blocks without a header are searched by their first record
+        if target >= 998 {
+            assert!(reader.blocks_read() * 4 < blocks);
+            assert!(plain_reader.blocks_read() * 4 < blocks);
+        }
+    }
+    let mut reader = SSTableReader::with_backend(&backend, &path, 256).unwrap();
+    assert!(!reader.seek_to_key(&TestKey::from(1999)).unwrap());
+    assert!(!reader.has_next());
+}
+
+#[test]
+fn test_validate_blocks_checks_the_record_count_of_block_headers() {
+    let values = get_test_data(100);
+    let mut options = DharmaOpts::default();
+    options.block_size_in_bytes = 256;
+    options.block_headers = true;
+    let backend = InMemoryBackend::new();
+    options.backend = Arc::new(backend.clone());
+    let path = PathBuf::from("/validate_block_headers/tables/0.db");
+    write_sstable_at_path(&options, &values, &path).unwrap();
+    let mut reader = SSTableReader::with_backend(&backend, &path, 256).unwrap();
+    assert!(reader
+        .validate_blocks()
+        .unwrap()
+        .iter()
+        .all(|report| report.valid));
+
+    // the header of the second block claims one more record than it holds
+    let mut file = backend.open(&path).unwrap();
+    let mut data = vec![0u8; file.size().unwrap() as usize];
+    file.read_at(&mut data, 0).unwrap();
+    let header_data = 256 + Record::RECORD_BASE_SIZE_IN_BYTES;
+    let records = u16::from_be_bytes([data[header_data], data[header_data + 1]]);
+    data[header_data..header_data + 2].copy_from_slice(&(records + 1).to_be_bytes());
+    backend.create(&path).unwrap().write(&data).unwrap();
+    let mut reader = SSTableReader::with_backend(&backend, &path, 256).unwrap();
+    for report in reader.validate_blocks().unwrap() {
+        assert_eq!(report.valid, report.index != 1);
+    }
+}
+
+#[test]
+fn test_validate_blocks_flags_only_the_corrupted_block() {
+    let values = get_test_data(100);
+    let mut options = DharmaOpts::default();
+    options.block_size_in_bytes = 256;
+    let backend = InMemoryBackend::new();
+    options.backend = Arc::new(backend.clone());
+    let path = PathBuf::from("/validate_blocks/tables/0.db");
+    write_sstable_at_path(&options, &values, &path).unwrap();
+
+    let mut reader = SSTableReader::with_backend(&backend, &path, 256).unwrap();
+    let reports = reader.validate_blocks().unwrap();
+    assert!(reports.len() > 3);
+    for (index, report) in reports.iter().enumerate() {
+        assert_eq!(report.index, index);
+        assert_eq!(report.size_in_bytes, 256);
+        assert!(report.valid);
+    }
+    assert_eq!(reports[0].record_types[0], RecordType::COMPLETE);
+    // the footer is stored in the padding of the last block
+    let last = reports.last().unwrap();
+    assert_eq!(last.record_types.last(), Some(&RecordType::PADDING));
+    // validating doesn't move the reader
+    let record = reader.read().to_record::<TestKey, TestValue>().unwrap();
+    assert_eq!(record.key, values[0].0);
+
+    // the size of the first record of the third block overflows the block
+    let mut file = backend.open(&path).unwrap();
+    let mut data = vec![0u8; file.size().unwrap() as usize];
+    file.read_at(&mut data, 0).unwrap();
+    data[2 * 256 + 1] = 0xFF;
+    data[2 * 256 + 2] = 0xFF;
+    backend.create(&path).unwrap().write(&data).unwrap();
+
+    let mut reader = SSTableReader::with_backend(&backend, &path, 256).unwrap();
+    let corrupted = reader.validate_blocks().unwrap();
+    assert_eq!(corrupted.len(), reports.len());
+    for report in &corrupted {
+        assert_eq!(report.valid, report.index != 2);
+    }
+}
+
+#[test]
+fn test_read_footer_without_opening_the_table() {
+    let values = get_test_data(700);
+    let mut options = DharmaOpts::default();
+    options.block_size_in_bytes = 512;
+    let path = write_sstable(&options, &values, 0).unwrap().unwrap();
+    let footer = SSTableReader::read_footer(&path).unwrap();
+    assert_eq!(footer.block_size, Some(512));
+    assert_eq!(footer.records, Some(700));
+    assert_eq!(
+        footer.decode_key_range::<TestKey>(),
+        Some((TestKey::from(0), TestKey::from(699)))
+    );
+    let mut file = FsBackend::new().open(&path).unwrap();
+    let size = file.size().unwrap() as usize;
+    assert_eq!(
+        footer.checksum,
+        Some(compute_table_checksum(file.as_mut(), size).unwrap())
+    );
+    assert_eq!(footer.format_version, FORMAT_VERSION);
+    assert_eq!(footer.encoding, Encoding::Fixint);
+    assert_eq!(footer.codec_id, BINCODE_CODEC_ID);
+    std::fs::remove_file(&path).unwrap();
+
+    // range tombstones widen the key range
+    let backend = InMemoryBackend::new();
+    options.backend = Arc::new(backend.clone());
+    let path = PathBuf::from("/read_footer/tables/0.db");
+    let values: Vec<Value<TestKey, TestValue>> = get_test_data_in_range(10, 20)
+        .into_iter()
+        .map(|(key, value)| Value::new(key, value))
+        .collect();
+    let tombstones = vec![RangeTombstone::new(TestKey::from(15), TestKey::from(30), 1)];
+    write_table_at_path(&options, &values, &tombstones, &path).unwrap();
+    let footer = SSTableReader::read_footer_with_backend(&backend, &path).unwrap();
+    assert_eq!(footer.records, Some(11));
+    assert_eq!(
+        footer.decode_key_range::<TestKey>(),
+        Some((TestKey::from(10), TestKey::from(30)))
+    );
+}
+
+#[test]
+fn test_separated_values_are_read_from_the_value_log_of_the_database_path() {
+    let values = get_test_data(50);
+    let mut options = DharmaOpts::default();
+    options.value_separation_min_bytes = Some(0);
+    options.path = String::from("/db");
+    options.backend = Arc::new(InMemoryBackend::new());
+    // the table isn't stored below the database path
+    let path = PathBuf::from("/exported/0.db");
+    write_sstable_at_path(&options, &values, &path).unwrap();
+    let mut reader = SSTableReader::with_options(&options, &path).unwrap();
+    let mut read_values = Vec::new();
+    while reader.has_next() {
+        let record: Value<TestKey, TestValue> = reader.read().to_record().unwrap();
+        read_values.push(record.into_tuple());
+        reader.next();
+    }
+    assert_eq!(read_values, values);
+}
+
+#[test]
+fn test_read_of_missing_block_fails() {
+    let values = get_test_data(100);
+    let mut options = DharmaOpts::default();
+    options.block_size_in_bytes = 256;
+    let backend = InMemoryBackend::new();
+    options.backend = Arc::new(backend.clone());
+    let path = PathBuf::from("/missing_block/tables/0.db");
+    write_sstable_at_path(&options, &values, &path).unwrap();
+    let mut reader =
+        SSTableReader::with_backend(&backend, &path, options.block_size_in_bytes).unwrap();
+    // shrink the table to its first block after the reader has been opened
+    let mut first_block = vec![0u8; options.block_size_in_bytes];
+    backend
+        .open(&path)
+        .unwrap()
+        .read_at(&mut first_block, 0)
+        .unwrap();
+    backend.create(&path).unwrap().write(&first_block).unwrap();
+
+    let mut records = 0;
+    let mut error = None;
+    while reader.has_next() {
+        let sstable_value = reader.read();
+        match sstable_value.to_record::<TestKey, TestValue>() {
+            Ok(_) => records += 1,
+            Err(err) => {
+                assert!(sstable_value.read_failed);
+                assert!(sstable_value.to_key::<TestKey>().is_err());
+                error = Some(err);
+                break;
+            }
+        }
+        reader.next();
+    }
+    // the record following the first block is never returned as an empty value
+    assert!(records < values.len());
+    assert!(error == Some(Errors::SSTABLE_READ_FAILED));
+}
@@ -0,0 +1,375 @@
+use crate::common::test_key::TestKey;
+use crate::common::test_value::TestValue;
+use crate::common::{cleanup_paths, get_test_data};
+use dharmadb::options::DharmaOpts;
+use dharmadb::result::Errors;
+use dharmadb::storage::backend::{InMemoryBackend, StorageBackend};
+use dharmadb::storage::encoding::Encoding;
+use dharmadb::storage::block::RangeTombstone;
+use dharmadb::storage::write_ahead_log::{WalFormat, WalOperation, WriteAheadLog};
+use dharmadb::traits::Nil;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::thread;
+use xxhash_rust::xxh64::xxh64;
+
+mod common;
+
+#[test]
+fn test_group_commit_batches_concurrent_appends() {
+    let mut options = DharmaOpts::default();
+    options.group_commit = true;
+    cleanup_paths(&options);
+    let wal = Arc::new(WriteAheadLog::create(options.clone()).unwrap());
+    let thread_count = 8;
+    let writes_per_thread = 50;
+    let mut handles = Vec::new();
+    for t in 0..thread_count {
+        let wal = wal.clone();
+        handles.push(thread::spawn(move || {
+            for i in 0..writes_per_thread {
+                let key = TestKey::from(t * writes_per_thread + i);
+                let value = TestValue::from(format!("value is {}", key).as_str());
+                assert!(wal.append(key, value).is_ok());
+            }
+        }));
+    }
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    let write_count = (thread_count * writes_per_thread) as u64;
+    assert!(wal.sync_count() < write_count);
+    // all committed writes can be recovered from the log
+    let mut recovered = WriteAheadLog::recover::<TestKey, TestValue>(options).unwrap();
+    recovered.sort_by_key(|val| val.0.clone());
+    assert_eq!(recovered, get_test_data(write_count as u32));
+}
+
+#[test]
+fn test_recovery_with_readahead() {
+    let mut options = DharmaOpts::default();
+    // small blocks so values are split across blocks read ahead together
+    options.block_size_in_bytes = 64;
+    options.wal_recovery_readahead_blocks = 3;
+    cleanup_paths(&options);
+    let wal = WriteAheadLog::create(options.clone()).unwrap();
+    let data = get_test_data(200);
+    for (key, value) in data.clone() {
+        assert!(wal.append(key, value).is_ok());
+    }
+    let recovered = WriteAheadLog::recover::<TestKey, TestValue>(options).unwrap();
+    assert_eq!(recovered, data);
+}
+
+#[test]
+fn test_append_batch() {
+    let options = DharmaOpts::default();
+    cleanup_paths(&options);
+    let wal = WriteAheadLog::create(options.clone()).unwrap();
+    let data = get_test_data(500);
+    assert!(wal.append_batch(&data[..250], 1).is_ok());
+    assert!(wal.append_batch(&data[250..], 251).is_ok());
+    // each record keeps the sequence number of its position in the batches
+    let values = WriteAheadLog::read_values::<TestKey, TestValue>(options.clone()).unwrap();
+    let seqs: Vec<u64> = values.iter().map(|value| value.seq).collect();
+    assert_eq!(seqs, (1..=500).collect::<Vec<u64>>());
+    let recovered = WriteAheadLog::recover::<TestKey, TestValue>(options).unwrap();
+    assert_eq!(recovered, data);
+}
+
+#[test]
+fn test_compact_keeps_latest_value_per_key() {
+    let options = DharmaOpts::default();
+    cleanup_paths(&options);
+    let wal = WriteAheadLog::create(options.clone()).unwrap();
+    for i in 1..=100 {
+        let value = TestValue::from(format!("version {}", i).as_str());
+        assert!(wal.append_versioned(TestKey::from(1), value, i).is_ok());
+    }
+    assert_eq!(wal.compact::<TestKey, TestValue>().unwrap(), 1);
+    // appends after compaction go to the compacted log
+    assert!(wal
+        .append(TestKey::from(2), TestValue::from("appended"))
+        .is_ok());
+    let recovered = WriteAheadLog::recover::<TestKey, TestValue>(options).unwrap();
+    assert_eq!(
+        recovered,
+        vec![
+            (TestKey::from(1), TestValue::from("version 100")),
+            (TestKey::from(2), TestValue::from("appended")),
+        ]
+    );
+}
+
+#[test]
+fn test_recovery_with_varint_encoding() {
+    let mut options = DharmaOpts::default();
+    options.encoding = Encoding::Varint;
+    cleanup_paths(&options);
+    let wal = WriteAheadLog::create(options.clone()).unwrap();
+    for (key, value) in get_test_data(300) {
+        assert!(wal.append(key, value).is_ok());
+    }
+    // the log records the encoding it was written with in its header
+    let recovered = WriteAheadLog::recover::<TestKey, TestValue>(options).unwrap();
+    assert_eq!(recovered, get_test_data(300));
+}
+
+#[test]
+fn test_recovery_after_encoding_changed() {
+    for wal_format in &[WalFormat::Blocks, WalFormat::Framed] {
+        let mut options = DharmaOpts::default();
+        options.wal_format = *wal_format;
+        options.backend = Arc::new(InMemoryBackend::new());
+        let wal = WriteAheadLog::create(options.clone()).unwrap();
+        for (key, value) in get_test_data(300) {
+            assert!(wal.append(key, value).is_ok());
+        }
+        // the log is read with the encoding recorded in its header
+        options.encoding = Encoding::Varint;
+        let recovered = WriteAheadLog::recover::<TestKey, TestValue>(options).unwrap();
+        assert_eq!(recovered, get_test_data(300));
+    }
+}
+
+#[test]
+fn test_read_from_tails_the_log() {
+    let mut options = DharmaOpts::default();
+    // small blocks so values are split across blocks
+    options.block_size_in_bytes = 64;
+    let backend = InMemoryBackend::new();
+    options.backend = Arc::new(backend.clone());
+    let wal = WriteAheadLog::create(options.clone()).unwrap();
+    let data = get_test_data(100);
+    for (key, value) in data[..50].iter().cloned() {
+        assert!(wal.append(key, value).is_ok());
+    }
+    let (records, offset) = wal.read_from::<TestKey, TestValue>(0).unwrap();
+    assert_eq!(records, data[..50].to_vec());
+    assert_eq!(offset as u64, wal.size().unwrap());
+    for (key, value) in data[50..].iter().cloned() {
+        assert!(wal.append(key, value).is_ok());
+    }
+    let (records, next_offset) = wal.read_from::<TestKey, TestValue>(offset).unwrap();
+    assert_eq!(records, data[50..].to_vec());
+    // nothing new was appended
+    let (records, offset) = wal.read_from::<TestKey, TestValue>(next_offset).unwrap();
+    assert!(records.is_empty());
+    assert_eq!(offset, next_offset);
+
+    // append a value spanning several blocks and tear off its last blocks
+    let path = PathBuf::from(format!("{}/wal.log", options.path));
+    let large_value = TestValue::from("a".repeat(500).as_str());
+    assert!(wal.append(TestKey::from(100), large_value.clone()).is_ok());
+    let size = wal.size().unwrap() as usize;
+    let mut log = vec![0u8; size];
+    backend.open(&path).unwrap().read_at(&mut log, 0).unwrap();
+    let torn_size = offset + 2 * options.block_size_in_bytes + 10;
+    backend
+        .create(&path)
+        .unwrap()
+        .write(&log[..torn_size])
+        .unwrap();
+    let (records, torn_offset) = wal.read_from::<TestKey, TestValue>(offset).unwrap();
+    assert!(records.is_empty());
+    assert_eq!(torn_offset, offset);
+
+    // the record is read once its blocks are complete
+    backend.create(&path).unwrap().write(&log).unwrap();
+    let (records, offset) = wal.read_from::<TestKey, TestValue>(torn_offset).unwrap();
+    assert_eq!(records, vec![(TestKey::from(100), large_value)]);
+    assert_eq!(offset, size);
+    assert!(wal.read_from::<TestKey, TestValue>(offset + 1).is_err());
+}
+
+#[test]
+fn test_compressed_wal_is_smaller_and_recovers() {
+    let create_wal = |compression_min_bytes: Option<usize>| {
+        let mut options = DharmaOpts::default();
+        options.block_size_in_bytes = 256;
+        options.wal_compression_min_bytes = compression_min_bytes;
+        let backend = InMemoryBackend::new();
+        options.backend = Arc::new(backend.clone());
+        (
+            WriteAheadLog::create(options.clone()).unwrap(),
+            options,
+            backend,
+        )
+    };
+    let data: Vec<(TestKey, TestValue)> = (0..50)
+        .map(|i| {
+            let value = format!("value is {} ", i).repeat(100);
+            (TestKey::from(i), TestValue::from(value.as_str()))
+        })
+        .collect();
+    let (wal, _, _) = create_wal(None);
+    assert!(wal.append_batch(&data, 1).is_ok());
+    let (compressed_wal, options, backend) = create_wal(Some(64));
+    assert!(compressed_wal.append_batch(&data, 1).is_ok());
+    assert!(compressed_wal.size().unwrap() < wal.size().unwrap());
+    let (records, offset) = compressed_wal.read_from::<TestKey, TestValue>(0).unwrap();
+    assert_eq!(records, data);
+
+    // a compressed record spanning blocks is torn when its last block is missing
+    let mut seed = 7u32;
+    let pattern: String = (0..300)
+        .map(|_| {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            (b'a' + (seed >> 16) as u8 % 26) as char
+        })
+        .collect();
+    let large_value = TestValue::from(pattern.repeat(4).as_str());
+    assert!(compressed_wal
+        .append(TestKey::from(50), large_value.clone())
+        .is_ok());
+    let size = compressed_wal.size().unwrap() as usize;
+    assert!(size > offset + options.block_size_in_bytes);
+    let path = PathBuf::from(format!("{}/wal.log", options.path));
+    let mut log = vec![0u8; size];
+    backend.open(&path).unwrap().read_at(&mut log, 0).unwrap();
+    let torn_size = offset + options.block_size_in_bytes + 10;
+    backend
+        .create(&path)
+        .unwrap()
+        .write(&log[..torn_size])
+        .unwrap();
+    let (records, torn_offset) = compressed_wal
+        .read_from::<TestKey, TestValue>(offset)
+        .unwrap();
+    assert!(records.is_empty());
+    assert_eq!(torn_offset, offset);
+    backend.create(&path).unwrap().write(&log).unwrap();
+    let (records, _) = compressed_wal
+        .read_from::<TestKey, TestValue>(offset)
+        .unwrap();
+    assert_eq!(records, vec![(TestKey::from(50), large_value.clone())]);
+    let mut expected = data;
+    expected.push((TestKey::from(50), large_value));
+    let recovered = WriteAheadLog::recover::<TestKey, TestValue>(options).unwrap();
+    assert_eq!(recovered, expected);
+}
+
+#[test]
+fn test_framed_wal_recovers_ordered_operations() {
+    let mut options = DharmaOpts::default();
+    options.wal_format = WalFormat::Framed;
+    let backend = InMemoryBackend::new();
+    options.backend = Arc::new(backend.clone());
+    let wal = WriteAheadLog::create(options.clone()).unwrap();
+    assert!(wal
+        .append_versioned(TestKey::from(1), TestValue::from("one"), 1)
+        .is_ok());
+    assert!(wal
+        .append_versioned(TestKey::from(1), TestValue::nil(), 2)
+        .is_ok());
+    let tombstone = RangeTombstone::new(TestKey::from(5), TestKey::from(9), 3);
+    assert!(wal.append_range_tombstone(&tombstone).is_ok());
+    assert!(wal
+        .append_versioned(TestKey::from(2), TestValue::from("two"), 4)
+        .is_ok());
+    let describe = |operation: WalOperation<TestKey, TestValue>| match operation {
+        WalOperation::Put(value) => format!("put {} {} @{}", value.key, value.value, value.seq),
+        WalOperation::Delete { key, seq } => format!("delete {} @{}", key, seq),
+        WalOperation::DeleteRange(tombstone) => format!(
+            "delete [{}, {}) @{}",
+            tombstone.start, tombstone.end, tombstone.seq
+        ),
+    };
+    let expected = vec![
+        "put 1 one @1",
+        "delete 1 @2",
+        "delete [5, 9) @3",
+        "put 2 two @4",
+    ];
+    let operations = WriteAheadLog::read_operations::<TestKey, TestValue>(options.clone()).unwrap();
+    let described: Vec<String> = operations.into_iter().map(describe).collect();
+    assert_eq!(described, expected);
+
+    // every append is a single record so a torn append only drops that append
+    let path = PathBuf::from(format!("{}/wal.log", options.path));
+    let size = wal.size().unwrap() as usize;
+    let mut log = vec![0u8; size];
+    backend.open(&path).unwrap().read_at(&mut log, 0).unwrap();
+    backend
+        .create(&path)
+        .unwrap()
+        .write(&log[..size - 3])
+        .unwrap();
+    let (records, offset) = wal.read_from::<TestKey, TestValue>(0).unwrap();
+    assert_eq!(
+        records,
+        vec![
+            (TestKey::from(1), TestValue::from("one")),
+            (TestKey::from(1), TestValue::nil()),
+        ]
+    );
+    let (records, torn_offset) = wal.read_from::<TestKey, TestValue>(offset).unwrap();
+    assert!(records.is_empty());
+    assert_eq!(torn_offset, offset);
+    let operations = WriteAheadLog::read_operations::<TestKey, TestValue>(options.clone()).unwrap();
+    let described: Vec<String> = operations.into_iter().map(describe).collect();
+    assert_eq!(described, expected[..3].to_vec());
+
+    // the record is read once it is complete
+    backend.create(&path).unwrap().write(&log).unwrap();
+    let (records, offset) = wal.read_from::<TestKey, TestValue>(torn_offset).unwrap();
+    assert_eq!(records, vec![(TestKey::from(2), TestValue::from("two"))]);
+    assert_eq!(offset, size);
+    let operations = WriteAheadLog::recover_operations::<TestKey, TestValue>(options).unwrap();
+    let described: Vec<String> = operations.into_iter().map(describe).collect();
+    assert_eq!(described, expected);
+}
+
+#[test]
+fn test_wal_read_in_different_format_fails_and_is_kept() {
+    for (written, configured) in &[
+        (WalFormat::Framed, WalFormat::Blocks),
+        (WalFormat::Blocks, WalFormat::Framed),
+    ] {
+        let mut options = DharmaOpts::default();
+        options.wal_format = *written;
+        let backend = InMemoryBackend::new();
+        options.backend = Arc::new(backend.clone());
+        let wal = WriteAheadLog::create(options.clone()).unwrap();
+        assert!(wal.append(TestKey::from(1), TestValue::from("one")).is_ok());
+        options.wal_format = *configured;
+        let recovered = WriteAheadLog::recover_operations::<TestKey, TestValue>(options.clone());
+        assert!(recovered.is_err());
+        assert_eq!(recovered.err().unwrap(), Errors::WAL_FORMAT_MISMATCH);
+        // the log isn't deleted so it can be recovered with the format it was written in
+        let path = PathBuf::from(format!("{}/wal.log", options.path));
+        assert!(backend.exists(&path));
+        options.wal_format = *written;
+        let recovered = WriteAheadLog::recover::<TestKey, TestValue>(options).unwrap();
+        assert_eq!(recovered, vec![(TestKey::from(1), TestValue::from("one"))]);
+    }
+}
+
+#[test]
+fn test_framed_wal_stops_at_record_that_fails_to_decompress() {
+    let mut options = DharmaOpts::default();
+    options.wal_format = WalFormat::Framed;
+    options.wal_compression_min_bytes = Some(64);
+    let backend = InMemoryBackend::new();
+    options.backend = Arc::new(backend.clone());
+    let wal = WriteAheadLog::create(options.clone()).unwrap();
+    assert!(wal.append(TestKey::from(1), TestValue::from("one")).is_ok());
+    let offset = wal.size().unwrap() as usize;
+    let large_value = TestValue::from("a".repeat(512).as_str());
+    assert!(wal.append(TestKey::from(2), large_value).is_ok());
+    // corrupt the compressed payload and fix up the checksum of the record
+    let path = PathBuf::from(format!("{}/wal.log", options.path));
+    let size = wal.size().unwrap() as usize;
+    let mut log = vec![0u8; size];
+    backend.open(&path).unwrap().read_at(&mut log, 0).unwrap();
+    let payload_end = size - 8;
+    for byte in log[offset + 9..payload_end].iter_mut() {
+        *byte = 0xFF;
+    }
+    let checksum = xxh64(&log[offset..payload_end], 0);
+    log[payload_end..].copy_from_slice(&checksum.to_le_bytes());
+    backend.create(&path).unwrap().write(&log).unwrap();
+    let (records, next_offset) = wal.read_from::<TestKey, TestValue>(0).unwrap();
+    assert_eq!(records, vec![(TestKey::from(1), TestValue::from("one"))]);
+    assert_eq!(next_offset, offset);
+}